@@ -0,0 +1,24 @@
+//! With the `header` feature, regenerates the checked-in `include/prism.h`
+//! from the `extern "C"` functions:
+//! `cargo build -p prism-capi --features header`.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "header")]
+    header();
+}
+
+#[cfg(feature = "header")]
+fn header() {
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_else(|_| ".".to_string());
+    let generated = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir))
+        .and_then(|config| cbindgen::generate_with_config(&dir, config).map_err(|err| err.to_string()));
+    match generated {
+        Ok(bindings) => {
+            bindings.write_to_file(format!("{}/include/prism.h", dir));
+        }
+        Err(err) => println!("cargo:warning=include/prism.h was not regenerated: {}", err),
+    }
+}
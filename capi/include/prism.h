@@ -0,0 +1,73 @@
+#ifndef PRISM_H
+#define PRISM_H
+
+/* Generated by cbindgen from capi/src/lib.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// An interpreter and the runtime its evaluations run on.
+typedef struct PrismInterpreter PrismInterpreter;
+
+// A host function: gets the call's arguments as a JSON array and returns
+// the result as JSON, or NULL to fail the call. The returned string is
+// handed back to the [`PrismRelease`] given with the callback.
+typedef char *(*PrismCallback)(void *user_data, const char *args_json);
+
+// Frees a string a [`PrismCallback`] returned.
+typedef void (*PrismRelease)(void *user_data, char *result);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Creates an interpreter with the standard library and prelude, or returns
+// NULL if its runtime cannot start. Free it with `prism_interpreter_free`.
+struct PrismInterpreter *prism_interpreter_new(void);
+
+// # Safety
+// `prism` must come from `prism_interpreter_new` and not be used again.
+void prism_interpreter_free(struct PrismInterpreter *prism);
+
+// Grants a capability (`"env"`, `"stdin"`, `"fs"` or `"net"`) to scripts.
+// Returns false for an unknown name.
+//
+// # Safety
+// `prism` must be a live interpreter and `capability` a C string.
+bool prism_grant(struct PrismInterpreter *prism, const char *capability);
+
+// Evaluates `source` and returns `{"value": ..., "confidence": ...}`, or
+// `{"error": {"kind": ..., "message": ...}}` if it fails or its value has
+// no JSON form.
+//
+// # Safety
+// `prism` must be a live interpreter and `source` a C string.
+char *prism_eval(struct PrismInterpreter *prism, const char *source);
+
+// Defines a global function `name` that calls `callback` with
+// `user_data`. Returns false if `name` is not a C string.
+//
+// # Safety
+// `prism` must be a live interpreter and `name` a C string. `callback` and
+// `release` must stay callable, and `user_data` valid, for as long as the
+// interpreter lives; they are called on the thread running `prism_eval`.
+bool prism_register_function(struct PrismInterpreter *prism,
+                             const char *name,
+                             uintptr_t arity,
+                             PrismCallback callback,
+                             PrismRelease release,
+                             void *user_data);
+
+// Frees a string returned by the library.
+//
+// # Safety
+// `string` must come from this library and not be used again.
+void prism_string_free(char *string);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* PRISM_H */
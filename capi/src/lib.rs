@@ -0,0 +1,238 @@
+//! C API for embedding the Prism runtime in C, C++, Go (through cgo),
+//! Swift and other hosts; `include/prism.h` is generated from this file by
+//! cbindgen when the crate builds.
+//!
+//! ```c
+//! #include "prism.h"
+//!
+//! char *shout(void *user_data, const char *args_json) { ... }
+//!
+//! PrismInterpreter *prism = prism_interpreter_new();
+//! prism_register_function(prism, "shout", 1, shout, release, NULL);
+//! char *result = prism_eval(prism, "shout(\"triage\") ~> 0.9;");
+//! // {"value":"TRIAGE","confidence":0.9}
+//! prism_string_free(result);
+//! prism_interpreter_free(prism);
+//! ```
+//!
+//! Strings crossing the boundary are NUL-terminated UTF-8. Values travel as
+//! JSON. Strings the library returns belong to the caller, who releases
+//! them with `prism_string_free`. Scripts get no capabilities until the
+//! host grants them.
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+use prism::capabilities::Capability;
+use prism::error::PrismError;
+use prism::interpreter::Interpreter;
+use prism::value::{Value, ValueKind};
+
+/// An interpreter and the runtime its evaluations run on.
+pub struct PrismInterpreter {
+    interpreter: Option<Interpreter>,
+    runtime: tokio::runtime::Runtime,
+}
+
+/// A host function: gets the call's arguments as a JSON array and returns
+/// the result as JSON, or NULL to fail the call. The returned string is
+/// handed back to the [`PrismRelease`] given with the callback.
+pub type PrismCallback = Option<unsafe extern "C" fn(user_data: *mut c_void, args_json: *const c_char) -> *mut c_char>;
+
+/// Frees a string a [`PrismCallback`] returned.
+pub type PrismRelease = Option<unsafe extern "C" fn(user_data: *mut c_void, result: *mut c_char)>;
+
+/// Creates an interpreter with the standard library and prelude, or returns
+/// NULL if its runtime cannot start. Free it with `prism_interpreter_free`.
+#[no_mangle]
+pub extern "C" fn prism_interpreter_new() -> *mut PrismInterpreter {
+    let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    Box::into_raw(Box::new(PrismInterpreter { interpreter: Some(Interpreter::new()), runtime }))
+}
+
+/// # Safety
+/// `prism` must come from `prism_interpreter_new` and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn prism_interpreter_free(prism: *mut PrismInterpreter) {
+    if !prism.is_null() {
+        drop(Box::from_raw(prism));
+    }
+}
+
+/// Grants a capability (`"env"`, `"stdin"`, `"fs"` or `"net"`) to scripts.
+/// Returns false for an unknown name.
+///
+/// # Safety
+/// `prism` must be a live interpreter and `capability` a C string.
+#[no_mangle]
+pub unsafe extern "C" fn prism_grant(prism: *mut PrismInterpreter, capability: *const c_char) -> bool {
+    let (Some(prism), Some(name)) = (prism.as_mut(), text(capability)) else {
+        return false;
+    };
+    let Ok(capability) = name.parse::<Capability>() else {
+        return false;
+    };
+    let interpreter = prism.interpreter.take().expect("the interpreter is only taken here");
+    let capabilities = interpreter.capabilities().clone().grant(capability);
+    prism.interpreter = Some(interpreter.with_capabilities(capabilities));
+    true
+}
+
+/// Evaluates `source` and returns `{"value": ..., "confidence": ...}`, or
+/// `{"error": {"kind": ..., "message": ...}}` if it fails or its value has
+/// no JSON form.
+///
+/// # Safety
+/// `prism` must be a live interpreter and `source` a C string.
+#[no_mangle]
+pub unsafe extern "C" fn prism_eval(prism: *mut PrismInterpreter, source: *const c_char) -> *mut c_char {
+    let (Some(prism), Some(source)) = (prism.as_mut(), text(source)) else {
+        return json_string(error_json(&PrismError::InvalidArgument("prism_eval needs an interpreter and UTF-8 source".to_string())));
+    };
+    let PrismInterpreter { interpreter, runtime } = prism;
+    let interpreter = interpreter.as_mut().expect("the interpreter is only taken in prism_grant");
+    let result = catch_unwind(AssertUnwindSafe(|| runtime.block_on(interpreter.evaluate(source.to_string()))));
+    let json = match result {
+        Ok(Ok(value)) => match value.to_json() {
+            Ok(json) => serde_json::json!({ "value": json, "confidence": value.confidence }),
+            Err(err) => error_json(&err),
+        },
+        Ok(Err(err)) => error_json(&err),
+        Err(_) => error_json(&PrismError::RuntimeError("the interpreter panicked".to_string())),
+    };
+    json_string(json)
+}
+
+/// Defines a global function `name` that calls `callback` with
+/// `user_data`. Returns false if `name` is not a C string.
+///
+/// # Safety
+/// `prism` must be a live interpreter and `name` a C string. `callback` and
+/// `release` must stay callable, and `user_data` valid, for as long as the
+/// interpreter lives; they are called on the thread running `prism_eval`.
+#[no_mangle]
+pub unsafe extern "C" fn prism_register_function(
+    prism: *mut PrismInterpreter,
+    name: *const c_char,
+    arity: usize,
+    callback: PrismCallback,
+    release: PrismRelease,
+    user_data: *mut c_void,
+) -> bool {
+    let (Some(prism), Some(name), Some(callback)) = (prism.as_mut(), text(name), callback) else {
+        return false;
+    };
+    let user_data = UserData(user_data);
+    let function_name = name.to_string();
+    let handler = Arc::new(move |_: &Interpreter, args: Vec<Value>| {
+        let args = args.iter().map(Value::to_json).collect::<prism::error::Result<Vec<_>>>()?;
+        let args = CString::new(serde_json::Value::Array(args).to_string())
+            .map_err(|_| PrismError::InvalidArgument(format!("arguments of {} contain NUL", function_name)))?;
+        let result = callback(user_data.pointer(), args.as_ptr());
+        if result.is_null() {
+            return Err(PrismError::RuntimeError(format!("{} failed", function_name)));
+        }
+        let parsed = CStr::from_ptr(result).to_str().ok().and_then(|json| serde_json::from_str(json).ok());
+        if let Some(release) = release {
+            release(user_data.pointer(), result);
+        }
+        let json: serde_json::Value =
+            parsed.ok_or_else(|| PrismError::InvalidOperation(format!("{} returned invalid JSON", function_name)))?;
+        Ok(Value::from_json(&json))
+    });
+    let function = Value::new(ValueKind::NativeFunction { name: name.to_string(), arity, handler });
+    let interpreter = prism.interpreter.as_mut().expect("the interpreter is only taken in prism_grant");
+    interpreter.define_global(name, function).is_ok()
+}
+
+/// Frees a string returned by the library.
+///
+/// # Safety
+/// `string` must come from this library and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn prism_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// The host's pointer, which the host promises may be used from the
+/// thread running evaluations.
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+impl UserData {
+    fn pointer(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+unsafe fn text<'a>(string: *const c_char) -> Option<&'a str> {
+    if string.is_null() {
+        return None;
+    }
+    CStr::from_ptr(string).to_str().ok()
+}
+
+fn error_json(err: &PrismError) -> serde_json::Value {
+    serde_json::json!({ "error": { "kind": err.kind(), "message": err.to_string() } })
+}
+
+fn json_string(json: serde_json::Value) -> *mut c_char {
+    // JSON escapes control characters, so there is no NUL to reject
+    CString::new(json.to_string()).expect("JSON has no NUL").into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe extern "C" fn double(_: *mut c_void, args: *const c_char) -> *mut c_char {
+        let args: Vec<f64> = serde_json::from_str(CStr::from_ptr(args).to_str().unwrap()).unwrap();
+        CString::new((args[0] * 2.0).to_string()).unwrap().into_raw()
+    }
+
+    unsafe extern "C" fn release(user_data: *mut c_void, result: *mut c_char) {
+        *(user_data as *mut usize) += 1;
+        drop(CString::from_raw(result));
+    }
+
+    unsafe fn eval(prism: *mut PrismInterpreter, source: &str) -> serde_json::Value {
+        let source = CString::new(source).unwrap();
+        let result = prism_eval(prism, source.as_ptr());
+        let json = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+        prism_string_free(result);
+        json
+    }
+
+    #[test]
+    fn test_eval_and_callbacks() {
+        unsafe {
+            let prism = prism_interpreter_new();
+            let mut released = 0usize;
+            let name = CString::new("double").unwrap();
+            let user_data = &mut released as *mut usize as *mut c_void;
+            assert!(prism_register_function(prism, name.as_ptr(), 1, Some(double), Some(release), user_data));
+
+            let json = eval(prism, "let x = double(21); let result = { answer: x } ~> 0.9; result;");
+            assert_eq!(json, serde_json::json!({ "value": { "answer": 42 }, "confidence": 0.9 }));
+            assert_eq!(released, 1);
+
+            let json = eval(prism, "undefined_name;");
+            assert_eq!(json["error"]["kind"], "resolve_error");
+
+            // Nothing is granted until the host says so
+            let json = eval(prism, "env.get(\"HOME\");");
+            assert_eq!(json["error"]["kind"], "permission_denied");
+            let capability = CString::new("env").unwrap();
+            assert!(prism_grant(prism, capability.as_ptr()));
+            assert!(eval(prism, "env.get(\"HOME\");").get("value").is_some());
+            prism_interpreter_free(prism);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Interpreter micro-benchmarks: `cargo bench --bench interpreter`.
+//!
+//! Each workload is parsed and evaluated from scratch on every iteration and
+//! the mean wall-clock time per iteration is printed. `empty_program` is
+//! the cost of setting up an interpreter that every workload includes, so
+//! short ones such as `arithmetic` are best read against it.
+//! `parse_large_file` only lexes and parses a generated file of a few
+//! hundred kilobytes.
+//! `import_modules` imports eight generated file modules of 300 functions
+//! each, and `import_waiting_modules` eight small ones that each wait 10ms
+//! while loading, as a module fetching data would.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+use prism::{Capabilities, Interpreter};
+
+const WORKLOADS: &[(&str, &str)] = &[
+    ("empty_program", "nil;"),
+    ("arithmetic", r#"
+        let a = 2 + 2;
+        let b = a * 3 - 1;
+        let c = (a + b) / 2;
+        let d = c > 3 and b != a;
+        let e = [a, b, c, { total: a + b + c }];
+        e;
+    "#),
+    ("fib_recursive", r#"
+        fn fib(n) {
+            if (n < 2) { return n; }
+            return fib(n - 1) + fib(n - 2);
+        }
+        fib(15);
+    "#),
+    ("tail_recursive_sum", r#"
+        fn sum(n, acc) {
+            if (n == 0) { return acc; }
+            return sum(n - 1, acc + n);
+        }
+        sum(2000, 0);
+    "#),
+    ("match_and_uncertain", r#"
+        fn classify(x) {
+            let label = match x { 0 => "zero", n if n < 0 => "negative", _ => "positive" };
+            let result = "";
+            uncertain if (x ~> 0.7) { result = label; } medium { result = "maybe"; } low { result = "unknown"; }
+            return result;
+        }
+        fn run(n) {
+            if (n == 0) { return 0; }
+            classify(n);
+            return run(n - 1);
+        }
+        run(500);
+    "#),
+];
+
+fn bench(runtime: &tokio::runtime::Runtime, source: &str) -> Duration {
+    time(|| {
+        runtime.block_on(async {
+            let mut interpreter = Interpreter::new();
+            interpreter.evaluate(source.to_string()).await.expect("benchmark program failed");
+        })
+    })
+}
+
+/// Writes `count` modules to `dir` and returns a program importing them all.
+fn write_modules(dir: &Path, count: usize, functions: usize, wait: &str) -> String {
+    std::fs::create_dir_all(dir).expect("failed to create the module directory");
+    let mut program = String::new();
+    for m in 0..count {
+        let mut module = format!("let _loaded = {wait};\nlet value = {m};\n");
+        for f in 0..functions {
+            module.push_str(&format!("fn f{f}(a, b) {{ let x = a + b * {m}; if (x > 10) {{ return \"big\"; }} return x; }}\n"));
+        }
+        std::fs::write(dir.join(format!("m{m}.prism")), module).expect("failed to write a module");
+        program.push_str(&format!("import {{ value as v{m} }} from \"./m{m}.prism\";\n"));
+    }
+    program
+}
+
+fn bench_imports(runtime: &tokio::runtime::Runtime, dir: &Path, source: &str) -> Duration {
+    time(|| {
+        runtime.block_on(async {
+            // A fresh interpreter loads every module again
+            let mut interpreter = Interpreter::new().with_capabilities(Capabilities::all()).with_module_dir(dir);
+            interpreter.evaluate(source.to_string()).await.expect("benchmark program failed");
+        })
+    })
+}
+
+fn time(run: impl Fn()) -> Duration {
+    // Warm up, then scale the iteration count to roughly one second.
+    let start = Instant::now();
+    run();
+    let once = start.elapsed().max(Duration::from_micros(1));
+    let iterations = (Duration::from_secs(1).as_nanos() / once.as_nanos()).clamp(3, 100_000) as u32;
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        run();
+    }
+    start.elapsed() / iterations
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let filter = std::env::args().nth(1).filter(|arg| !arg.starts_with('-'));
+    for (name, source) in WORKLOADS {
+        if filter.as_deref().is_some_and(|filter| !name.contains(filter)) {
+            continue;
+        }
+        println!("{:<24} {:>12.3?}/iter", name, bench(&runtime, source));
+    }
+
+    if filter.as_deref().is_none_or(|filter| "parse_large_file".contains(filter)) {
+        let source: String = (0..4000)
+            .map(|i| format!("fn f{i}(a, b) {{ let x = a + b * 2; if (x > 10) {{ return \"big {i}\"; }} return x ~> 0.9; }}\n"))
+            .collect();
+        let elapsed = time(|| {
+            prism::parser::parse(&source).expect("generated source parses");
+        });
+        println!("{:<24} {:>12.3?}/iter", "parse_large_file", elapsed);
+    }
+
+    let root = std::env::temp_dir().join(format!("prism-bench-modules-{}", std::process::id()));
+    for (name, functions, wait) in [("import_modules", 300, "nil"), ("import_waiting_modules", 1, "utils.sleep(0.01)")] {
+        if filter.as_deref().is_some_and(|filter| !name.contains(filter)) {
+            continue;
+        }
+        let dir = root.join(name);
+        let source = write_modules(&dir, 8, functions, wait);
+        println!("{:<24} {:>12.3?}/iter", name, bench_imports(&runtime, &dir, &source));
+    }
+    let _ = std::fs::remove_dir_all(&root);
+}
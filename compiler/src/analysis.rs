@@ -0,0 +1,222 @@
+//! Parsing and static analysis without an interpreter, for linters, editor
+//! plugins and codemods.
+//!
+//! [`parse_program`] turns source into a [`Program`], and [`analyze`] runs
+//! the resolver and checker over it and reports what the program declares:
+//! every variable, function and parameter with its static type and the
+//! confidence its value can have, and the modules it imports.
+//!
+//! ```
+//! let program = prism::parse_program("let dose = 400 ~> 0.8; fn half(x: number) { return x / 2; }").unwrap();
+//! let report = prism::analyze(&program);
+//! assert!(report.diagnostics.is_empty());
+//! let dose = report.symbol("dose").unwrap();
+//! assert_eq!((dose.ty.to_string(), dose.confidence.low), ("number".to_string(), 0.8));
+//! ```
+
+use std::sync::OnceLock;
+use crate::ast::{walk_stmt, Stmt, Type, Visitor};
+use crate::diagnostics::Diagnostic;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+
+/// A parsed program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    statements: Vec<Stmt>,
+}
+
+impl Program {
+    pub fn statements(&self) -> &[Stmt] {
+        &self.statements
+    }
+
+    pub fn into_statements(self) -> Vec<Stmt> {
+        self.statements
+    }
+}
+
+/// Parses `source`; a syntax error is returned as an error diagnostic.
+pub fn parse_program(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    let tokens = Lexer::new(source)
+        .scan_tokens()
+        .map_err(|err| vec![Diagnostic::error(err.to_string())])?;
+    let mut parser = Parser::new(tokens);
+    match parser.parse() {
+        Ok(statements) => Ok(Program { statements }),
+        Err(err) => Err(vec![Diagnostic::error(err.to_string()).at_line(Some(parser.line()))]),
+    }
+}
+
+/// The range a value's confidence falls in; `[0, 1]` when nothing is known.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfidenceBounds {
+    pub low: f64,
+    pub high: f64,
+}
+
+impl ConfidenceBounds {
+    pub const UNKNOWN: ConfidenceBounds = ConfidenceBounds { low: 0.0, high: 1.0 };
+
+    pub fn exactly(confidence: f64) -> Self {
+        Self { low: confidence, high: confidence }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Variable,
+    Function,
+    Parameter,
+    /// A name an `import` binds.
+    Import,
+}
+
+/// A name the program declares.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The annotated type, or the one inferred from the initializer.
+    pub ty: Type,
+    pub confidence: ConfidenceBounds,
+    pub line: Option<usize>,
+    /// How many scopes in the declaration is; 0 is the top level.
+    pub depth: usize,
+}
+
+/// An `import` statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    /// The module, a standard library name or a file path.
+    pub module: String,
+    /// The names taken from it, each with its alias.
+    pub names: Vec<(String, Option<String>)>,
+}
+
+/// What [`analyze`] finds.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AnalysisReport {
+    /// Resolver and checker errors and warnings.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Declarations in source order.
+    pub symbols: Vec<Symbol>,
+    pub imports: Vec<Import>,
+}
+
+impl AnalysisReport {
+    /// The first top-level symbol called `name`.
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.depth == 0 && symbol.name == name)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+}
+
+/// Analyzes `program` as the interpreter would run it, with the standard
+/// library modules and the prelude defined.
+pub fn analyze(program: &Program) -> AnalysisReport {
+    static BUILTINS: OnceLock<Vec<String>> = OnceLock::new();
+    let builtins = BUILTINS.get_or_init(|| {
+        let modules = crate::stdlib::init_stdlib().expect("standard library modules build without errors");
+        let modules = modules.into_iter().map(|(name, _)| name);
+        modules.chain(crate::stdlib::PRELUDE.iter().copied()).map(str::to_string).collect()
+    });
+    analyze_with_globals(program, builtins)
+}
+
+/// [`analyze`] with `globals` as the names defined before the program runs,
+/// e.g. an embedder's own globals besides the standard library.
+pub fn analyze_with_globals(program: &Program, globals: &[String]) -> AnalysisReport {
+    let mut statements = program.statements.clone();
+    let mut diagnostics = crate::resolver::resolve(&mut statements, globals.iter().cloned());
+    let (checked, mut symbols) = crate::checker::check_with_symbols(&statements);
+    diagnostics.extend(checked);
+
+    let mut imports = Imports(Vec::new());
+    imports.visit_statements(&statements);
+    let imports = imports.0;
+    for import in &imports {
+        symbols.extend(import.names.iter().map(|(name, alias)| Symbol {
+            name: alias.clone().unwrap_or_else(|| name.clone()),
+            kind: SymbolKind::Import,
+            ty: Type::Any,
+            confidence: ConfidenceBounds::UNKNOWN,
+            line: None,
+            depth: 0,
+        }));
+    }
+    AnalysisReport { diagnostics, symbols, imports }
+}
+
+struct Imports(Vec<Import>);
+
+impl Visitor for Imports {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Import { module, imports, .. } = stmt {
+            self.0.push(Import { module: module.clone(), names: imports.clone() });
+        }
+        walk_stmt(self, stmt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_symbols_types_and_confidence() {
+        let source = r#"
+            import { mean as average } from "utils";
+            let limit: number? = nil;
+            let dose = 400 ~> 0.8;
+            let scaled = -dose;
+            fn triage(temp: number, note) ~> 0.9 {
+                let urgent = temp > 39;
+                return urgent;
+            }
+        "#;
+        let report = analyze(&parse_program(source).unwrap());
+        assert!(!report.has_errors(), "{:?}", report.diagnostics);
+
+        let shown: Vec<(&str, SymbolKind, String, usize)> = report
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.kind, symbol.ty.to_string(), symbol.depth))
+            .collect();
+        assert_eq!(shown, [
+            ("limit", SymbolKind::Variable, "number | nil".to_string(), 0),
+            ("dose", SymbolKind::Variable, "number".to_string(), 0),
+            ("scaled", SymbolKind::Variable, "number".to_string(), 0),
+            ("triage", SymbolKind::Function, "function".to_string(), 0),
+            ("temp", SymbolKind::Parameter, "number".to_string(), 1),
+            ("note", SymbolKind::Parameter, "any".to_string(), 1),
+            ("urgent", SymbolKind::Variable, "bool".to_string(), 2),
+            ("average", SymbolKind::Import, "any".to_string(), 0),
+        ]);
+        assert_eq!(report.symbol("scaled").unwrap().confidence, ConfidenceBounds::exactly(0.8));
+        assert_eq!(report.symbol("triage").unwrap().confidence, ConfidenceBounds::exactly(0.9));
+        assert_eq!(report.symbols[4].confidence, ConfidenceBounds::UNKNOWN);
+        assert_eq!(report.imports, [Import {
+            module: "utils".to_string(),
+            names: vec![("mean".to_string(), Some("average".to_string()))],
+        }]);
+    }
+
+    #[test]
+    fn test_reports_problems_without_running() {
+        let errors = parse_program("let x = ;").unwrap_err();
+        assert_eq!((errors.len(), errors[0].line), (1, Some(1)));
+
+        let report = analyze(&parse_program("let x: number = \"ten\";\nprint(missing);").unwrap());
+        let messages: Vec<&str> = report.diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert!(messages.iter().any(|m| m.contains("missing")), "{:?}", messages);
+        assert!(messages.iter().any(|m| m.contains("Cannot initialize `x`")), "{:?}", messages);
+
+        let host = vec!["missing".to_string(), "print".to_string()];
+        let report = analyze_with_globals(&parse_program("print(missing);").unwrap(), &host);
+        assert!(!report.has_errors());
+    }
+}
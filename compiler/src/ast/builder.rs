@@ -0,0 +1,283 @@
+//! Constructors for building programs in code rather than from source text,
+//! e.g. from a visual editor or a plan an LLM produced.
+//!
+//! ```
+//! use prism::ast::{BinaryOp, ExprBuilder, StmtBuilder};
+//!
+//! let e = ExprBuilder::at_line(3);
+//! let program = vec![
+//!     StmtBuilder::let_("dose", e.binary(e.var("weight"), BinaryOp::Multiply, e.number(0.5))),
+//!     StmtBuilder::expr(e.call(e.var("print"), [e.var("dose")])),
+//! ];
+//! assert_eq!(prism::ast::to_source(&program), "let dose = weight * 0.5;\nprint(dose);\n");
+//! ```
+//!
+//! Trees can be run with [`Interpreter::evaluate_program`](crate::interpreter::Interpreter::evaluate_program)
+//! or printed with [`to_source`](super::to_source). Operands are not
+//! reordered: `binary(a, Multiply, binary(b, Add, c))` means `a * (b + c)`.
+
+use crate::token::{Token, TokenKind};
+use crate::value::{Value, ValueKind};
+use super::{BinaryOp, Expr, MatchArm, Stmt, Type, UnaryOp};
+
+/// Builds expressions. Every node records the builder's line, so runtime
+/// errors in generated code point back at the step that produced it; use
+/// [`at_line`](Self::at_line) to move on to the next one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExprBuilder {
+    line: usize,
+}
+
+impl ExprBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn at_line(line: usize) -> Self {
+        ExprBuilder { line }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn nil(&self) -> Expr {
+        self.value(ValueKind::Nil)
+    }
+
+    pub fn boolean(&self, b: bool) -> Expr {
+        self.value(ValueKind::Boolean(b))
+    }
+
+    pub fn number(&self, n: f64) -> Expr {
+        self.value(ValueKind::Number(n))
+    }
+
+    pub fn string(&self, s: impl Into<String>) -> Expr {
+        self.value(ValueKind::String(s.into()))
+    }
+
+    pub fn var(&self, name: impl Into<String>) -> Expr {
+        Expr::Variable { name: name.into(), line: self.line, slot: None }
+    }
+
+    pub fn assign(&self, name: impl Into<String>, value: Expr) -> Expr {
+        Expr::Assign { name: name.into(), value: Box::new(value), slot: None }
+    }
+
+    /// `and` and `or` become logical expressions, which short-circuit.
+    pub fn binary(&self, left: Expr, op: BinaryOp, right: Expr) -> Expr {
+        let (kind, lexeme) = match op {
+            BinaryOp::Add => (TokenKind::Plus, "+"),
+            BinaryOp::Subtract => (TokenKind::Minus, "-"),
+            BinaryOp::Multiply => (TokenKind::Star, "*"),
+            BinaryOp::Divide => (TokenKind::Slash, "/"),
+            BinaryOp::Equal => (TokenKind::EqualEqual, "=="),
+            BinaryOp::NotEqual => (TokenKind::BangEqual, "!="),
+            BinaryOp::Less => (TokenKind::Less, "<"),
+            BinaryOp::LessEqual => (TokenKind::LessEqual, "<="),
+            BinaryOp::Greater => (TokenKind::Greater, ">"),
+            BinaryOp::GreaterEqual => (TokenKind::GreaterEqual, ">="),
+            BinaryOp::And => (TokenKind::And, "and"),
+            BinaryOp::Or => (TokenKind::Or, "or"),
+        };
+        let (left, operator, right) = (Box::new(left), self.token(kind, lexeme), Box::new(right));
+        match op {
+            BinaryOp::And | BinaryOp::Or => Expr::Logical { left, operator, right },
+            _ => Expr::Binary { left, operator, right },
+        }
+    }
+
+    pub fn unary(&self, op: UnaryOp, right: Expr) -> Expr {
+        let operator = match op {
+            UnaryOp::Not => self.token(TokenKind::Bang, "!"),
+            UnaryOp::Minus => self.token(TokenKind::Minus, "-"),
+        };
+        Expr::Unary { operator, right: Box::new(right) }
+    }
+
+    pub fn call(&self, callee: Expr, arguments: impl IntoIterator<Item = Expr>) -> Expr {
+        Expr::Call { callee: Box::new(callee), arguments: arguments.into_iter().collect(), tail: false }
+    }
+
+    /// `object.name`, e.g. a field or a module member.
+    pub fn get(&self, object: Expr, name: impl Into<String>) -> Expr {
+        Expr::Get { object: Box::new(object), name: name.into(), optional: false }
+    }
+
+    /// `object?.name`, which is nil when `object` is nil or lacks the field.
+    pub fn optional_get(&self, object: Expr, name: impl Into<String>) -> Expr {
+        Expr::Get { object: Box::new(object), name: name.into(), optional: true }
+    }
+
+    /// Calls `module.name(arguments)`, e.g. `llm.chat_completion`.
+    pub fn call_member(&self, module: &str, name: &str, arguments: impl IntoIterator<Item = Expr>) -> Expr {
+        self.call(self.get(self.var(module), name), arguments)
+    }
+
+    /// `expr ~> confidence`.
+    pub fn confidence(&self, expr: Expr, confidence: f64) -> Expr {
+        Expr::Confidence { expr: Box::new(expr), confidence }
+    }
+
+    pub fn list(&self, items: impl IntoIterator<Item = Expr>) -> Expr {
+        Expr::List(items.into_iter().collect())
+    }
+
+    pub fn map<K: Into<String>>(&self, entries: impl IntoIterator<Item = (K, Expr)>) -> Expr {
+        Expr::Map(entries.into_iter().map(|(key, value)| (self.string(key), value)).collect())
+    }
+
+    pub fn match_(&self, subject: Expr, arms: impl IntoIterator<Item = MatchArm>) -> Expr {
+        Expr::Match { subject: Box::new(subject), arms: arms.into_iter().collect() }
+    }
+
+    fn value(&self, kind: ValueKind) -> Expr {
+        Expr::Literal(Value::new(kind))
+    }
+
+    fn token(&self, kind: TokenKind, lexeme: &str) -> Token {
+        Token::new(kind, lexeme, self.line)
+    }
+}
+
+/// Builds statements. Bodies are given as statement lists and wrapped in
+/// blocks; names with a trailing `_` are Prism keywords.
+pub struct StmtBuilder;
+
+impl StmtBuilder {
+    pub fn expr(expr: Expr) -> Stmt {
+        Stmt::Expression(Box::new(expr))
+    }
+
+    pub fn let_(name: impl Into<String>, initializer: Expr) -> Stmt {
+        Stmt::Let { name: name.into(), type_annotation: None, initializer: Some(Box::new(initializer)) }
+    }
+
+    pub fn let_typed(name: impl Into<String>, ty: Type, initializer: Option<Expr>) -> Stmt {
+        Stmt::Let { name: name.into(), type_annotation: Some(ty), initializer: initializer.map(Box::new) }
+    }
+
+    pub fn block(body: impl IntoIterator<Item = Stmt>) -> Stmt {
+        Stmt::Block(body.into_iter().collect())
+    }
+
+    pub fn if_(condition: Expr, then_branch: impl IntoIterator<Item = Stmt>) -> Stmt {
+        Stmt::If { condition: Box::new(condition), then_branch: Box::new(Self::block(then_branch)), else_branch: None }
+    }
+
+    /// `if` with an `else`; pass another `if_` as the only statement of
+    /// `else_branch` for an `else if` chain.
+    pub fn if_else(
+        condition: Expr,
+        then_branch: impl IntoIterator<Item = Stmt>,
+        else_branch: impl IntoIterator<Item = Stmt>,
+    ) -> Stmt {
+        let mut else_branch: Vec<Stmt> = else_branch.into_iter().collect();
+        let else_branch = match else_branch.as_slice() {
+            [Stmt::If { .. }] => else_branch.remove(0),
+            _ => Stmt::Block(else_branch),
+        };
+        Stmt::If {
+            condition: Box::new(condition),
+            then_branch: Box::new(Self::block(then_branch)),
+            else_branch: Some(Box::new(else_branch)),
+        }
+    }
+
+    pub fn while_(condition: Expr, body: impl IntoIterator<Item = Stmt>) -> Stmt {
+        Stmt::While { condition: Box::new(condition), body: Box::new(Self::block(body)) }
+    }
+
+    pub fn for_(name: impl Into<String>, iterable: Expr, body: impl IntoIterator<Item = Stmt>) -> Stmt {
+        Stmt::For { name: name.into(), iterable: Box::new(iterable), body: Box::new(Self::block(body)) }
+    }
+
+    /// An untyped function; set the other fields of the returned
+    /// [`Stmt::Function`] for annotations or a declared confidence.
+    pub fn function<P: Into<String>>(
+        name: impl Into<String>,
+        params: impl IntoIterator<Item = P>,
+        body: impl IntoIterator<Item = Stmt>,
+    ) -> Stmt {
+        let params: Vec<String> = params.into_iter().map(Into::into).collect();
+        Stmt::Function {
+            name: name.into(),
+            param_types: vec![None; params.len()],
+            params,
+            return_type: None,
+            body: Box::new(Self::block(body)),
+            is_async: false,
+            confidence: None,
+            captures: None,
+        }
+    }
+
+    pub fn return_(value: Option<Expr>) -> Stmt {
+        Stmt::Return(value.map(Box::new))
+    }
+
+    pub fn yield_(value: Expr) -> Stmt {
+        Stmt::Yield(Box::new(value))
+    }
+
+    pub fn break_() -> Stmt {
+        Stmt::Break
+    }
+
+    pub fn continue_() -> Stmt {
+        Stmt::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{to_source, Pattern};
+    use crate::interpreter::Interpreter;
+
+    #[tokio::test]
+    async fn test_built_programs_run_and_print() {
+        let e = ExprBuilder::at_line(1);
+        let classify = StmtBuilder::function("classify", ["temp"], [
+            StmtBuilder::if_else(
+                e.binary(e.var("temp"), BinaryOp::Greater, e.number(39.0)),
+                [StmtBuilder::return_(Some(e.confidence(e.string("urgent"), 0.9)))],
+                [StmtBuilder::return_(Some(e.match_(e.var("temp"), [MatchArm {
+                    pattern: Pattern::Wildcard,
+                    guard: None,
+                    body: e.string("routine"),
+                }])))],
+            ),
+        ]);
+        let e = ExprBuilder::at_line(2);
+        let program = vec![
+            classify,
+            StmtBuilder::let_("temps", e.list([e.number(40.0), e.number(37.0)])),
+            StmtBuilder::let_("labels", e.string("")),
+            StmtBuilder::for_("t", e.var("temps"), [StmtBuilder::expr(e.assign(
+                "labels",
+                e.binary(e.var("labels"), BinaryOp::Add, e.call(e.var("classify"), [e.var("t")])),
+            ))]),
+            StmtBuilder::expr(e.map([
+                ("labels", e.var("labels")),
+                ("words", e.call_member("text", "word_count", [e.string("two words")])),
+            ])),
+        ];
+
+        let source = to_source(&program);
+        assert!(source.contains("if (temp > 39) {\n        return \"urgent\" ~> 0.9;\n    } else {"));
+        assert!(source.ends_with("({ labels: labels, words: text.word_count(\"two words\") });\n"));
+
+        let result = Interpreter::new().evaluate_program(program).await.unwrap();
+        assert_eq!(result.to_string(), "{labels: urgentroutine, words: 2}");
+    }
+
+    #[tokio::test]
+    async fn test_built_nodes_carry_their_line() {
+        let e = ExprBuilder::at_line(7);
+        let program = vec![StmtBuilder::expr(e.binary(e.number(1.0), BinaryOp::Add, e.var("missing")))];
+        let err = Interpreter::new().evaluate_program(program).await.unwrap_err();
+        assert!(err.to_string().contains("line 7"), "{}", err);
+    }
+}
@@ -0,0 +1,414 @@
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use crate::interpreter::Interpreter;
+use crate::token::Token;
+use crate::value::{Value, ValueKind};
+
+mod builder;
+mod printer;
+mod visit;
+
+pub use builder::{ExprBuilder, StmtBuilder};
+pub use printer::{expr_to_source, to_source, to_source_with_map};
+pub use visit::{walk_expr, walk_expr_mut, walk_match_arm, walk_match_arm_mut, walk_stmt, walk_stmt_mut, Visitor, VisitorMut};
+pub(crate) use printer::function_header;
+
+pub type AsyncResult<T> = Pin<Box<dyn Future<Output = Result<T, Box<dyn Error + Send + Sync>>> + Send + Sync>>;
+/// Confidence at or above which `uncertain if` takes its first branch.
+pub const DEFAULT_HIGH_CONFIDENCE: f64 = 0.8;
+/// Confidence at or above which `uncertain if` takes its `medium` branch.
+pub const DEFAULT_MEDIUM_CONFIDENCE: f64 = 0.5;
+
+pub type AsyncFn = Arc<dyn Fn(&Interpreter, Vec<Value>) -> AsyncResult<Value> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Variable {
+        name: String,
+        line: usize,
+        slot: Option<Slot>, // filled in by the resolver; `None` means a global
+    },
+    Assign {
+        name: String,
+        value: Box<Expr>,
+        slot: Option<Slot>,
+    },
+    Binary {
+        left: Box<Expr>,
+        operator: Token,
+        right: Box<Expr>,
+    },
+    Unary {
+        operator: Token,
+        right: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        arguments: Vec<Expr>,
+        tail: bool, // set by the resolver for `return f(...)` inside a function
+    },
+    Get {
+        object: Box<Expr>,
+        name: String,
+        optional: bool, // `?.` yields nil instead of failing on nil or missing fields
+    },
+    Logical {
+        left: Box<Expr>,
+        operator: Token,
+        right: Box<Expr>,
+    },
+    Confidence {
+        expr: Box<Expr>,
+        confidence: f64,
+    },
+    ConfidenceCombine {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    InContext {
+        context: String,
+        body: Box<Expr>,
+    },
+    Grouping(Box<Expr>),
+    List(Vec<Expr>),
+    Map(Vec<(Expr, Expr)>),
+    ModuleAccess {
+        module: String,
+        name: String,
+    },
+    Match {
+        subject: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
+}
+
+impl Expr {
+    /// The source line of the expression, when one of its tokens records it.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Expr::Variable { line, .. } if *line > 0 => Some(*line),
+            Expr::Binary { operator, .. }
+            | Expr::Unary { operator, .. }
+            | Expr::Logical { operator, .. } => Some(operator.line),
+            Expr::Get { object, .. } => object.line(),
+            Expr::Call { callee, .. } => callee.line(),
+            Expr::Grouping(inner) => inner.line(),
+            Expr::Assign { value, .. } => value.line(),
+            Expr::Confidence { expr, .. } => expr.line(),
+            Expr::Match { subject, .. } => subject.line(),
+            _ => None,
+        }
+    }
+}
+
+impl Stmt {
+    /// The line of the statement's leading expression, when it has one;
+    /// declarations and blocks have none of their own.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Stmt::Expression(expr) | Stmt::Yield(expr) | Stmt::Return(Some(expr)) => expr.line(),
+            Stmt::Let { initializer, .. } => initializer.as_ref()?.line(),
+            Stmt::If { condition, .. } | Stmt::UncertainIf { condition, .. } | Stmt::While { condition, .. } => {
+                condition.line()
+            }
+            Stmt::For { iterable, .. } => iterable.line(),
+            Stmt::Cfg { body, .. } | Stmt::Export(_, body) => body.line(),
+            _ => None,
+        }
+    }
+}
+
+/// Where a local variable lives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    /// At `index` in the frame of the running call (or top-level code).
+    Local(usize),
+    /// A local of an enclosing function that the running one closes over:
+    /// `depth` scopes out from where the function was defined, at position
+    /// `index` in that scope.
+    Captured { depth: usize, index: usize },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Literal(Value),
+    Wildcard,
+    Binding(String),
+    /// `~> 0.8` matches values whose confidence is at least the threshold.
+    Confidence(f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Expression(Box<Expr>),
+    Let {
+        name: String,
+        type_annotation: Option<Type>,
+        initializer: Option<Box<Expr>>,
+    },
+    Block(Vec<Stmt>),
+    If {
+        condition: Box<Expr>,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    UncertainIf {
+        condition: Box<Expr>,
+        high_threshold: f64,
+        medium_threshold: f64,
+        then_branch: Box<Stmt>,
+        medium_branch: Option<Box<Stmt>>,
+        low_branch: Option<Box<Stmt>>,
+    },
+    While {
+        condition: Box<Expr>,
+        body: Box<Stmt>,
+    },
+    /// `for (name in iterable) body`; see [`crate::iterator`].
+    For {
+        name: String,
+        iterable: Box<Expr>,
+        body: Box<Stmt>,
+    },
+    Function {
+        name: String,
+        params: Vec<String>,
+        param_types: Vec<Option<Type>>,
+        return_type: Option<Type>,
+        body: Box<Stmt>,
+        is_async: bool,
+        confidence: Option<f64>,
+        /// Filled in by the resolver: `None` when the function uses no
+        /// locals of enclosing functions, otherwise the locals of the
+        /// defining frame that it, or a function nested in it, closes over.
+        captures: Option<Vec<usize>>,
+    },
+    Return(Option<Box<Expr>>),
+    /// `break;` leaves the innermost loop.
+    Break,
+    /// `continue;` starts the next iteration of the innermost loop.
+    Continue,
+    /// `yield value;` — makes the enclosing function a generator.
+    Yield(Box<Expr>),
+    Context {
+        name: String,
+        body: Box<Stmt>,
+    },
+    Import {
+        module: String,
+        imports: Vec<(String, Option<String>)>, // (name, alias)
+        confidence: Option<f64>,
+    },
+    Export(String, Box<Stmt>), // name and the statement being exported
+    Module {
+        name: String,
+        body: Vec<Stmt>,
+        confidence: Option<f64>,
+    },
+    ModuleAccess {
+        module_name: String,
+        name: String,
+    },
+    /// `@cfg(predicate) stmt`: the statement exists only when the
+    /// interpreter's flags satisfy the predicate; see [`crate::cfg`].
+    Cfg {
+        predicate: CfgPredicate,
+        body: Box<Stmt>,
+    },
+}
+
+/// The condition of a `@cfg` annotation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CfgPredicate {
+    /// `"wasm"`
+    Flag(String),
+    /// `feature = "medical"`
+    Value(String, String),
+    Not(Box<CfgPredicate>),
+    All(Vec<CfgPredicate>),
+    Any(Vec<CfgPredicate>),
+}
+
+impl fmt::Display for CfgPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |f: &mut fmt::Formatter<'_>, name: &str, items: &[CfgPredicate]| {
+            let items: Vec<String> = items.iter().map(ToString::to_string).collect();
+            write!(f, "{}({})", name, items.join(", "))
+        };
+        match self {
+            CfgPredicate::Flag(flag) => write!(f, "{:?}", flag),
+            CfgPredicate::Value(key, value) => write!(f, "{} = {:?}", key, value),
+            CfgPredicate::Not(inner) => write!(f, "not({})", inner),
+            CfgPredicate::All(items) => list(f, "all", items),
+            CfgPredicate::Any(items) => list(f, "any", items),
+        }
+    }
+}
+
+/// Static type annotation, e.g. `number`, `string?` or `number | nil`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Any,
+    Nil,
+    Boolean,
+    Number,
+    String,
+    List,
+    Map,
+    Function,
+    Named(String),
+    Union(Vec<Type>),
+}
+
+impl Type {
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "any" => Type::Any,
+            "nil" => Type::Nil,
+            "bool" | "boolean" => Type::Boolean,
+            "number" => Type::Number,
+            "string" => Type::String,
+            "list" => Type::List,
+            "map" => Type::Map,
+            "function" => Type::Function,
+            _ => Type::Named(name.to_string()),
+        }
+    }
+
+    /// `T?` is shorthand for `T | nil`.
+    pub fn optional(inner: Type) -> Self {
+        Type::union(vec![inner, Type::Nil])
+    }
+
+    /// Builds a union, flattening nested unions and dropping duplicates.
+    pub fn union(members: Vec<Type>) -> Self {
+        let mut flat: Vec<Type> = Vec::new();
+        for member in members {
+            let parts = match member {
+                Type::Union(parts) => parts,
+                other => vec![other],
+            };
+            for part in parts {
+                if !flat.contains(&part) {
+                    flat.push(part);
+                }
+            }
+        }
+        if flat.len() == 1 {
+            flat.remove(0)
+        } else {
+            Type::Union(flat)
+        }
+    }
+
+    pub fn is_nullable(&self) -> bool {
+        match self {
+            Type::Nil => true,
+            Type::Union(members) => members.contains(&Type::Nil),
+            _ => false,
+        }
+    }
+
+    /// The type left after a successful nil test.
+    pub fn without_nil(&self) -> Self {
+        match self {
+            Type::Union(members) => Type::union(
+                members.iter().filter(|m| **m != Type::Nil).cloned().collect()
+            ),
+            other => other.clone(),
+        }
+    }
+
+    pub fn is_assignable_to(&self, target: &Type) -> bool {
+        match (self, target) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Union(members), _) => members.iter().all(|m| m.is_assignable_to(target)),
+            (_, Type::Union(members)) => members.iter().any(|m| self.is_assignable_to(m)),
+            // User-defined type names are not checked yet.
+            (Type::Named(_), _) | (_, Type::Named(_)) => true,
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Nil => write!(f, "nil"),
+            Type::Boolean => write!(f, "bool"),
+            Type::Number => write!(f, "number"),
+            Type::String => write!(f, "string"),
+            Type::List => write!(f, "list"),
+            Type::Map => write!(f, "map"),
+            Type::Function => write!(f, "function"),
+            Type::Named(name) => write!(f, "{}", name),
+            Type::Union(members) => {
+                for (i, member) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " | ")?;
+                    }
+                    write!(f, "{}", member)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnaryOp {
+    Not,
+    Minus,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+impl From<&Stmt> for Expr {
+    fn from(stmt: &Stmt) -> Self {
+        match stmt {
+            Stmt::Expression(expr) => *expr.clone(),
+            Stmt::Let { name, initializer: Some(expr), .. } => Expr::Assign {
+                name: name.clone(),
+                value: expr.clone(),
+                slot: None,
+            },
+            Stmt::Let { name, initializer: None, .. } => Expr::Variable {
+                name: name.clone(),
+                line: 0,
+                slot: None,
+            },
+            Stmt::Block(stmts) => Expr::Grouping(Box::new(
+                stmts.last()
+                    .map(Self::from)
+                    .unwrap_or(Expr::Literal(Value::new(ValueKind::Nil)))
+            )),
+            _ => Expr::Literal(Value::new(ValueKind::Nil)),
+        }
+    }
+}
@@ -0,0 +1,514 @@
+//! Renders syntax trees back to Prism source.
+//!
+//! The output is canonical: the same tree always prints the same way, with
+//! four-space indentation, one statement per line and parentheses only
+//! where precedence needs them. Parsing it gives the same tree again, apart
+//! from line numbers. Comments are not part of the tree and are lost.
+
+use crate::lexer::keyword;
+use crate::source_map::{Origin, SourceMap};
+use crate::token::TokenKind;
+use crate::value::{Value, ValueKind};
+use super::{Expr, MatchArm, Pattern, Stmt, DEFAULT_HIGH_CONFIDENCE, DEFAULT_MEDIUM_CONFIDENCE};
+
+const INDENT: &str = "    ";
+
+// Binding strength of each expression form, loosest first, following the
+// parser's descent
+const ASSIGNMENT: u8 = 1;
+const CONFIDENCE: u8 = 2;
+const OR: u8 = 3;
+const AND: u8 = 4;
+const EQUALITY: u8 = 5;
+const COMPARISON: u8 = 6;
+const TERM: u8 = 7;
+const FACTOR: u8 = 8;
+const UNARY: u8 = 9;
+const POSTFIX: u8 = 10;
+const PRIMARY: u8 = 11;
+
+/// Renders a program as Prism source.
+///
+/// Nodes the parser does not produce yet are written in the syntax the
+/// specification gives them, and values without a literal form (functions,
+/// modules, strings containing `"`) as they display.
+pub fn to_source(program: &[Stmt]) -> String {
+    to_source_with_map(program).0
+}
+
+/// Renders a program like [`to_source`], along with a map from each printed
+/// line to the line its statement had in the tree, for running the printed
+/// source while reporting errors against the original.
+pub fn to_source_with_map(program: &[Stmt]) -> (String, SourceMap) {
+    let mut printer = Printer::default();
+    for stmt in program {
+        printer.stmt(stmt);
+    }
+    (printer.out, printer.map)
+}
+
+/// Renders a single expression on one line, without a trailing `;`.
+pub fn expr_to_source(expr: &Expr) -> String {
+    match expr {
+        Expr::Literal(value) => literal(value),
+        Expr::Variable { name, .. } => name.clone(),
+        Expr::Assign { name, value, .. } => format!("{} = {}", name, operand(value, ASSIGNMENT)),
+        Expr::Binary { left, operator, right } | Expr::Logical { left, operator, right } => {
+            let precedence = precedence(expr);
+            format!(
+                "{} {} {}",
+                operand(left, precedence),
+                operator_text(&operator.kind, operator.lexeme()),
+                operand(right, precedence + 1),
+            )
+        }
+        Expr::Unary { operator, right } => {
+            format!("{}{}", operator_text(&operator.kind, operator.lexeme()), operand(right, UNARY))
+        }
+        Expr::Call { callee, arguments, .. } => format!("{}({})", operand(callee, POSTFIX), list(arguments)),
+        Expr::Get { object, name, optional } => {
+            format!("{}{}{}", operand(object, POSTFIX), if *optional { "?." } else { "." }, name)
+        }
+        Expr::Confidence { expr, confidence } => format!("{} ~> {}", operand(expr, OR), number(*confidence)),
+        Expr::ConfidenceCombine { left, right } => {
+            format!("confidence.combine([{}, {}])", expr_to_source(left), expr_to_source(right))
+        }
+        Expr::InContext { context, body } => format!("in context {} {{ {} }}", context, expr_to_source(body)),
+        Expr::Grouping(inner) => format!("({})", expr_to_source(inner)),
+        Expr::List(items) => format!("[{}]", list(items)),
+        Expr::Map(entries) if entries.is_empty() => "{}".to_string(),
+        Expr::Map(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        Expr::Literal(Value { kind: ValueKind::String(name), .. }) => map_key(name),
+                        other => expr_to_source(other),
+                    };
+                    format!("{}: {}", key, expr_to_source(value))
+                })
+                .collect();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        Expr::ModuleAccess { module, name } => format!("{}.{}", module, name),
+        Expr::Match { subject, arms } => {
+            let arms: Vec<String> = arms.iter().map(match_arm).collect();
+            format!("match {} {{ {} }}", expr_to_source(subject), arms.join(", "))
+        }
+    }
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    depth: usize,
+    /// Written in front of the next line, for `export`.
+    prefix: String,
+    lines: usize,
+    /// The tree's line for the statement being printed, if it has one.
+    origin: Option<usize>,
+    map: SourceMap,
+}
+
+impl Printer {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(&std::mem::take(&mut self.prefix));
+        self.out.push_str(text);
+        self.out.push('\n');
+        self.lines += 1;
+        if let Some(line) = self.origin {
+            self.map.add(self.lines, Origin::line(line));
+        }
+    }
+
+    fn open(&mut self, header: &str) {
+        self.line(&format!("{} {{", header));
+        self.depth += 1;
+    }
+
+    /// Closes a branch and opens the next one, as in `} else {`.
+    fn reopen(&mut self, header: &str) {
+        self.depth -= 1;
+        self.line(&format!("}} {} {{", header));
+        self.depth += 1;
+    }
+
+    fn close(&mut self) {
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    /// The statements of a branch; a single statement is written as if it
+    /// were a block of one.
+    fn body(&mut self, body: &Stmt) {
+        match body {
+            Stmt::Block(stmts) => {
+                for stmt in stmts {
+                    self.stmt(stmt);
+                }
+            }
+            other => self.stmt(other),
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        let outer = self.origin;
+        self.origin = stmt.line().or(outer);
+        self.print(stmt);
+        self.origin = outer;
+    }
+
+    fn print(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) => {
+                let text = expr_to_source(expr);
+                // A leading `{` would start a block
+                if text.starts_with('{') {
+                    self.line(&format!("({});", text));
+                } else {
+                    self.line(&format!("{};", text));
+                }
+            }
+            Stmt::Let { name, type_annotation, initializer } => {
+                let mut text = format!("let {}", name);
+                if let Some(ty) = type_annotation {
+                    text.push_str(&format!(": {}", ty));
+                }
+                if let Some(initializer) = initializer {
+                    text.push_str(&format!(" = {}", expr_to_source(initializer)));
+                }
+                self.line(&format!("{};", text));
+            }
+            Stmt::Block(_) => {
+                self.line("{");
+                self.depth += 1;
+                self.body(stmt);
+                self.close();
+            }
+            Stmt::If { condition, then_branch, else_branch } => {
+                self.open(&format!("if ({})", expr_to_source(condition)));
+                self.body(then_branch);
+                let mut next = else_branch.as_deref();
+                while let Some(branch) = next {
+                    match branch {
+                        Stmt::If { condition, then_branch, else_branch } => {
+                            self.reopen(&format!("else if ({})", expr_to_source(condition)));
+                            self.body(then_branch);
+                            next = else_branch.as_deref();
+                        }
+                        other => {
+                            self.reopen("else");
+                            self.body(other);
+                            next = None;
+                        }
+                    }
+                }
+                self.close();
+            }
+            Stmt::UncertainIf {
+                condition,
+                high_threshold,
+                medium_threshold,
+                then_branch,
+                medium_branch,
+                low_branch,
+            } => {
+                // A condition that carries its own `~>` needs the threshold
+                // spelled out, or it would be read as the threshold
+                let is_confidence = matches!(**condition, Expr::Confidence { .. });
+                if *high_threshold == DEFAULT_HIGH_CONFIDENCE && !is_confidence {
+                    self.open(&format!("uncertain if ({})", expr_to_source(condition)));
+                } else {
+                    self.open(&format!(
+                        "uncertain if ({} ~> {})",
+                        operand(condition, OR),
+                        number(*high_threshold),
+                    ));
+                }
+                self.body(then_branch);
+                if let Some(medium) = medium_branch {
+                    if *medium_threshold == DEFAULT_MEDIUM_CONFIDENCE {
+                        self.reopen("medium");
+                    } else {
+                        self.reopen(&format!("medium (~> {})", number(*medium_threshold)));
+                    }
+                    self.body(medium);
+                }
+                if let Some(low) = low_branch {
+                    self.reopen("low");
+                    self.body(low);
+                }
+                self.close();
+            }
+            Stmt::While { condition, body } => {
+                self.open(&format!("while ({})", expr_to_source(condition)));
+                self.body(body);
+                self.close();
+            }
+            Stmt::For { name, iterable, body } => {
+                self.open(&format!("for ({} in {})", name, expr_to_source(iterable)));
+                self.body(body);
+                self.close();
+            }
+            Stmt::Function { body, .. } => {
+                self.open(&function_header(stmt).expect("a function"));
+                self.body(body);
+                self.close();
+            }
+            Stmt::Return(None) => self.line("return;"),
+            Stmt::Break => self.line("break;"),
+            Stmt::Continue => self.line("continue;"),
+            Stmt::Return(Some(value)) => self.line(&format!("return {};", expr_to_source(value))),
+            Stmt::Yield(value) => self.line(&format!("yield {};", expr_to_source(value))),
+            Stmt::Context { name, body } => {
+                let is_identifier = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+                    && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+                if is_identifier {
+                    self.open(&format!("in context {}", name));
+                } else {
+                    self.open(&format!("in context \"{}\"", name));
+                }
+                self.body(body);
+                self.close();
+            }
+            Stmt::Import { module, imports, confidence } => {
+                let names: Vec<String> = imports
+                    .iter()
+                    .map(|(name, alias)| match alias {
+                        Some(alias) => format!("{} as {}", name, alias),
+                        None => name.clone(),
+                    })
+                    .collect();
+                let names = match names.as_slice() {
+                    [single] => single.clone(),
+                    _ => format!("{{ {} }}", names.join(", ")),
+                };
+                let confidence = confidence.map(|c| format!(" ~> {}", number(c))).unwrap_or_default();
+                self.line(&format!("import {} from \"{}\"{};", names, module, confidence));
+            }
+            Stmt::Export(_, stmt) => {
+                self.prefix = "export ".to_string();
+                self.stmt(stmt);
+            }
+            Stmt::Module { name, body, confidence } => {
+                let confidence = confidence.map(|c| format!(" ~> {}", number(c))).unwrap_or_default();
+                self.open(&format!("module {}{}", name, confidence));
+                for stmt in body {
+                    self.stmt(stmt);
+                }
+                self.close();
+            }
+            Stmt::ModuleAccess { module_name, name } => self.line(&format!("{}.{};", module_name, name)),
+            Stmt::Cfg { predicate, body } => {
+                self.line(&format!("@cfg({})", predicate));
+                self.stmt(body);
+            }
+        }
+    }
+}
+
+/// `fn name(params) -> type async ~> confidence`, the line that opens a
+/// function declaration.
+pub(crate) fn function_header(stmt: &Stmt) -> Option<String> {
+    let Stmt::Function { name, params, param_types, return_type, is_async, confidence, .. } = stmt else {
+        return None;
+    };
+    let params: Vec<String> = params
+        .iter()
+        .enumerate()
+        .map(|(i, param)| match param_types.get(i) {
+            Some(Some(ty)) => format!("{}: {}", param, ty),
+            _ => param.clone(),
+        })
+        .collect();
+    let mut header = format!("fn {}({})", name, params.join(", "));
+    if let Some(ty) = return_type {
+        header.push_str(&format!(" -> {}", ty));
+    }
+    if *is_async {
+        header.push_str(" async");
+    }
+    if let Some(confidence) = confidence {
+        header.push_str(&format!(" ~> {}", number(*confidence)));
+    }
+    Some(header)
+}
+
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Assign { .. } => ASSIGNMENT,
+        Expr::Confidence { .. } => CONFIDENCE,
+        Expr::Literal(value) if value.confidence != 1.0 => CONFIDENCE,
+        Expr::Literal(Value { kind: ValueKind::Number(n), .. }) if n.is_sign_negative() => UNARY,
+        Expr::Logical { operator, .. } | Expr::Binary { operator, .. } => match operator.kind {
+            TokenKind::Or => OR,
+            TokenKind::And => AND,
+            TokenKind::EqualEqual | TokenKind::BangEqual => EQUALITY,
+            TokenKind::Greater | TokenKind::GreaterEqual | TokenKind::Less | TokenKind::LessEqual => COMPARISON,
+            TokenKind::Star | TokenKind::Slash => FACTOR,
+            _ => TERM,
+        },
+        Expr::Unary { .. } => UNARY,
+        Expr::Call { .. } | Expr::Get { .. } | Expr::ModuleAccess { .. } | Expr::ConfidenceCombine { .. } => POSTFIX,
+        _ => PRIMARY,
+    }
+}
+
+/// `expr`, parenthesized when it binds more loosely than `min`.
+fn operand(expr: &Expr, min: u8) -> String {
+    if precedence(expr) < min {
+        format!("({})", expr_to_source(expr))
+    } else {
+        expr_to_source(expr)
+    }
+}
+
+fn operator_text<'a>(kind: &TokenKind, lexeme: &'a str) -> &'a str {
+    match kind {
+        TokenKind::Plus => "+",
+        TokenKind::Minus => "-",
+        TokenKind::Star => "*",
+        TokenKind::Slash => "/",
+        TokenKind::Bang => "!",
+        TokenKind::BangEqual => "!=",
+        TokenKind::EqualEqual => "==",
+        TokenKind::Greater => ">",
+        TokenKind::GreaterEqual => ">=",
+        TokenKind::Less => "<",
+        TokenKind::LessEqual => "<=",
+        TokenKind::And => "and",
+        TokenKind::Or => "or",
+        _ => lexeme,
+    }
+}
+
+fn list(items: &[Expr]) -> String {
+    items.iter().map(expr_to_source).collect::<Vec<_>>().join(", ")
+}
+
+fn match_arm(arm: &MatchArm) -> String {
+    let pattern = match &arm.pattern {
+        Pattern::Literal(value) => literal(value),
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Binding(name) => name.clone(),
+        Pattern::Confidence(threshold) => format!("~> {}", number(*threshold)),
+    };
+    match &arm.guard {
+        Some(guard) => format!("{} if {} => {}", pattern, expr_to_source(guard), expr_to_source(&arm.body)),
+        None => format!("{} => {}", pattern, expr_to_source(&arm.body)),
+    }
+}
+
+fn literal(value: &Value) -> String {
+    let text = match &value.kind {
+        ValueKind::Nil => "nil".to_string(),
+        ValueKind::Boolean(b) => b.to_string(),
+        ValueKind::Number(n) => number(*n),
+        ValueKind::String(s) => format!("\"{}\"", s),
+        ValueKind::List(items) => format!("[{}]", items.iter().map(literal).collect::<Vec<_>>().join(", ")),
+        ValueKind::Map(entries) if entries.is_empty() => "{}".to_string(),
+        ValueKind::Map(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| format!("{}: {}", map_key(&key.to_string()), literal(value)))
+                .collect();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        _ => value.to_string(),
+    };
+    if value.confidence != 1.0 {
+        format!("{} ~> {}", text, number(value.confidence))
+    } else {
+        text
+    }
+}
+
+/// Numbers exactly as the lexer reads them back: plain digits, never an
+/// exponent.
+fn number(n: f64) -> String {
+    n.to_string()
+}
+
+fn map_key(name: &str) -> String {
+    let is_identifier = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_identifier && keyword(name).is_none() {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::token::Token;
+
+    #[test]
+    fn test_programs_print_canonically() {
+        let source = r#"
+            import { fuzzy, llm as model } from "std";
+            let dose: number | nil = 2*(weight+1) ~> 0.9;
+            fn triage(case: map, urgent) -> string ~> 0.8 {
+                if (case.temp>39 and !urgent) { return "urgent"; }
+                else if (case?.temp == nil) { return nil; } else { yield -case.temp; }
+            }
+            uncertain if (diagnosis ~> 0.9) { print({ "if": 1, name: "x" }); } medium (~> 0.6) { notify(); } low { }
+            for (n in range(3)) { while (n < 2) { n = n - -1; continue; } break; }
+            let label = match score { ~> 0.8 => "sure", 1 => "one", s if s > 2 => "big", _ => "none" };
+            in context Triage { in context "final diagnosis" { decide(); } }
+        "#;
+        let printed = to_source(&parse(source).unwrap());
+        assert_eq!(printed, r#"import { fuzzy, llm as model } from "std";
+let dose: number | nil = 2 * (weight + 1) ~> 0.9;
+fn triage(case: map, urgent) -> string ~> 0.8 {
+    if (case.temp > 39 and !urgent) {
+        return "urgent";
+    } else if (case?.temp == nil) {
+        return nil;
+    } else {
+        yield -case.temp;
+    }
+}
+uncertain if (diagnosis ~> 0.9) {
+    print({ "if": 1, name: "x" });
+} medium (~> 0.6) {
+    notify();
+} low {
+}
+for (n in range(3)) {
+    while (n < 2) {
+        n = n - -1;
+        continue;
+    }
+    break;
+}
+let label = match score { ~> 0.8 => "sure", 1 => "one", s if s > 2 => "big", _ => "none" };
+in context Triage {
+    in context "final diagnosis" {
+        decide();
+    }
+}
+"#);
+        assert_eq!(to_source(&parse(&printed).unwrap()), printed);
+    }
+
+    #[test]
+    fn test_built_trees_get_the_parentheses_they_need() {
+        let op = |kind, lexeme: &str| Token::new(kind, lexeme, 0);
+        let number = |n| Box::new(Expr::Literal(Value::new(ValueKind::Number(n))));
+        let sum = Expr::Binary { left: number(1.0), operator: op(TokenKind::Plus, "+"), right: number(2.0) };
+        let difference = Expr::Binary { left: number(5.0), operator: op(TokenKind::Minus, "-"), right: Box::new(sum.clone()) };
+        let product = Expr::Binary { left: Box::new(sum), operator: op(TokenKind::Star, "*"), right: number(-3.0) };
+        let map = Expr::Map(vec![(Expr::Literal(Value::new(ValueKind::String("a".to_string()))), product)]);
+        let program = vec![
+            Stmt::Expression(Box::new(difference)),
+            Stmt::Expression(Box::new(map)),
+        ];
+        assert_eq!(to_source(&program), "5 - (1 + 2);\n({ a: (1 + 2) * -3 });\n");
+    }
+}
@@ -0,0 +1,322 @@
+//! Traversal of syntax trees for lints, codemods and analysis passes.
+//!
+//! Implement [`Visitor`] (or [`VisitorMut`] to change the tree in place) and
+//! override the methods for the nodes you care about; the defaults visit
+//! every child. An override decides whether to descend further by calling
+//! the matching `walk_*` function.
+//!
+//! ```
+//! use prism::ast::{Expr, Visitor, walk_expr};
+//!
+//! #[derive(Default)]
+//! struct Calls(Vec<String>);
+//!
+//! impl Visitor for Calls {
+//!     fn visit_expr(&mut self, expr: &Expr) {
+//!         if let Expr::Call { callee, .. } = expr {
+//!             if let Expr::Variable { name, .. } = callee.as_ref() {
+//!                 self.0.push(name.clone());
+//!             }
+//!         }
+//!         walk_expr(self, expr);
+//!     }
+//! }
+//!
+//! let program = prism::parser::parse("fn f(x) { return g(h(x)); }").unwrap();
+//! let mut calls = Calls::default();
+//! calls.visit_statements(&program);
+//! assert_eq!(calls.0, ["g", "h"]);
+//! ```
+
+use super::{Expr, MatchArm, Stmt};
+
+/// Visits a tree by reference.
+pub trait Visitor {
+    fn visit_statements(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            self.visit_stmt(stmt);
+        }
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+
+    fn visit_match_arm(&mut self, arm: &MatchArm) {
+        walk_match_arm(self, arm);
+    }
+}
+
+/// Visits the statements and expressions directly inside `stmt`.
+pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::Expression(expr) | Stmt::Yield(expr) | Stmt::Return(Some(expr)) => visitor.visit_expr(expr),
+        Stmt::Let { initializer, .. } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_expr(initializer);
+            }
+        }
+        Stmt::Block(statements) | Stmt::Module { body: statements, .. } => visitor.visit_statements(statements),
+        Stmt::If { condition, then_branch, else_branch } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt(else_branch);
+            }
+        }
+        Stmt::UncertainIf { condition, then_branch, medium_branch, low_branch, .. } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(then_branch);
+            for branch in [medium_branch, low_branch].into_iter().flatten() {
+                visitor.visit_stmt(branch);
+            }
+        }
+        Stmt::While { condition, body } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(body);
+        }
+        Stmt::For { iterable, body, .. } => {
+            visitor.visit_expr(iterable);
+            visitor.visit_stmt(body);
+        }
+        Stmt::Function { body, .. }
+        | Stmt::Context { body, .. }
+        | Stmt::Export(_, body)
+        | Stmt::Cfg { body, .. } => visitor.visit_stmt(body),
+        Stmt::Return(None) | Stmt::Break | Stmt::Continue | Stmt::Import { .. } | Stmt::ModuleAccess { .. } => {}
+    }
+}
+
+/// Visits the expressions and match arms directly inside `expr`.
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Literal(_) | Expr::Variable { .. } | Expr::ModuleAccess { .. } => {}
+        Expr::Assign { value, .. } => visitor.visit_expr(value),
+        Expr::Binary { left, right, .. }
+        | Expr::Logical { left, right, .. }
+        | Expr::ConfidenceCombine { left, right } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        Expr::Unary { right: inner, .. }
+        | Expr::Get { object: inner, .. }
+        | Expr::Confidence { expr: inner, .. }
+        | Expr::InContext { body: inner, .. }
+        | Expr::Grouping(inner) => visitor.visit_expr(inner),
+        Expr::Call { callee, arguments, .. } => {
+            visitor.visit_expr(callee);
+            for argument in arguments {
+                visitor.visit_expr(argument);
+            }
+        }
+        Expr::List(items) => {
+            for item in items {
+                visitor.visit_expr(item);
+            }
+        }
+        Expr::Map(entries) => {
+            for (key, value) in entries {
+                visitor.visit_expr(key);
+                visitor.visit_expr(value);
+            }
+        }
+        Expr::Match { subject, arms } => {
+            visitor.visit_expr(subject);
+            for arm in arms {
+                visitor.visit_match_arm(arm);
+            }
+        }
+    }
+}
+
+/// Visits the guard and then the body of `arm`.
+pub fn walk_match_arm<V: Visitor + ?Sized>(visitor: &mut V, arm: &MatchArm) {
+    if let Some(guard) = &arm.guard {
+        visitor.visit_expr(guard);
+    }
+    visitor.visit_expr(&arm.body);
+}
+
+/// Visits a tree by mutable reference, in the same order as [`Visitor`].
+pub trait VisitorMut {
+    fn visit_statements_mut(&mut self, statements: &mut [Stmt]) {
+        for stmt in statements {
+            self.visit_stmt_mut(stmt);
+        }
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_match_arm_mut(&mut self, arm: &mut MatchArm) {
+        walk_match_arm_mut(self, arm);
+    }
+}
+
+/// [`walk_stmt`] for [`VisitorMut`].
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(visitor: &mut V, stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Expression(expr) | Stmt::Yield(expr) | Stmt::Return(Some(expr)) => visitor.visit_expr_mut(expr),
+        Stmt::Let { initializer, .. } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_expr_mut(initializer);
+            }
+        }
+        Stmt::Block(statements) | Stmt::Module { body: statements, .. } => visitor.visit_statements_mut(statements),
+        Stmt::If { condition, then_branch, else_branch } => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_stmt_mut(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt_mut(else_branch);
+            }
+        }
+        Stmt::UncertainIf { condition, then_branch, medium_branch, low_branch, .. } => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_stmt_mut(then_branch);
+            for branch in [medium_branch, low_branch].into_iter().flatten() {
+                visitor.visit_stmt_mut(branch);
+            }
+        }
+        Stmt::While { condition, body } => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_stmt_mut(body);
+        }
+        Stmt::For { iterable, body, .. } => {
+            visitor.visit_expr_mut(iterable);
+            visitor.visit_stmt_mut(body);
+        }
+        Stmt::Function { body, .. }
+        | Stmt::Context { body, .. }
+        | Stmt::Export(_, body)
+        | Stmt::Cfg { body, .. } => visitor.visit_stmt_mut(body),
+        Stmt::Return(None) | Stmt::Break | Stmt::Continue | Stmt::Import { .. } | Stmt::ModuleAccess { .. } => {}
+    }
+}
+
+/// [`walk_expr`] for [`VisitorMut`].
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Literal(_) | Expr::Variable { .. } | Expr::ModuleAccess { .. } => {}
+        Expr::Assign { value, .. } => visitor.visit_expr_mut(value),
+        Expr::Binary { left, right, .. }
+        | Expr::Logical { left, right, .. }
+        | Expr::ConfidenceCombine { left, right } => {
+            visitor.visit_expr_mut(left);
+            visitor.visit_expr_mut(right);
+        }
+        Expr::Unary { right: inner, .. }
+        | Expr::Get { object: inner, .. }
+        | Expr::Confidence { expr: inner, .. }
+        | Expr::InContext { body: inner, .. }
+        | Expr::Grouping(inner) => visitor.visit_expr_mut(inner),
+        Expr::Call { callee, arguments, .. } => {
+            visitor.visit_expr_mut(callee);
+            for argument in arguments {
+                visitor.visit_expr_mut(argument);
+            }
+        }
+        Expr::List(items) => {
+            for item in items {
+                visitor.visit_expr_mut(item);
+            }
+        }
+        Expr::Map(entries) => {
+            for (key, value) in entries {
+                visitor.visit_expr_mut(key);
+                visitor.visit_expr_mut(value);
+            }
+        }
+        Expr::Match { subject, arms } => {
+            visitor.visit_expr_mut(subject);
+            for arm in arms {
+                visitor.visit_match_arm_mut(arm);
+            }
+        }
+    }
+}
+
+/// [`walk_match_arm`] for [`VisitorMut`].
+pub fn walk_match_arm_mut<V: VisitorMut + ?Sized>(visitor: &mut V, arm: &mut MatchArm) {
+    if let Some(guard) = &mut arm.guard {
+        visitor.visit_expr_mut(guard);
+    }
+    visitor.visit_expr_mut(&mut arm.body);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::to_source;
+    use crate::parser::parse;
+
+    const PROGRAM: &str = r#"
+        fn plan(dose) {
+            let doses = [dose, dose * 2];
+            for (d in doses) {
+                if (d > limit) { print(d); } else { log({"dose": d}); }
+            }
+            return match dose { 0 => nil, n if n > limit => warn(n), _ => dose ~> 0.9 };
+        }
+    "#;
+
+    #[derive(Default)]
+    struct Variables(Vec<String>);
+
+    impl Visitor for Variables {
+        fn visit_expr(&mut self, expr: &Expr) {
+            if let Expr::Variable { name, .. } = expr {
+                self.0.push(name.clone());
+            }
+            walk_expr(self, expr);
+        }
+    }
+
+    #[test]
+    fn test_visitor_reaches_every_expression() {
+        let mut variables = Variables::default();
+        variables.visit_statements(&parse(PROGRAM).unwrap());
+        assert_eq!(variables.0, [
+            "dose", "dose", "doses", "d", "limit", "print", "d", "log", "d",
+            "dose", "n", "limit", "warn", "n", "dose",
+        ]);
+    }
+
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            if let Expr::Variable { name, .. } = expr {
+                if name == "limit" {
+                    *name = "max_dose".to_string();
+                }
+            }
+            walk_expr_mut(self, expr);
+        }
+
+        // Nested functions keep their own names.
+        fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+            if !matches!(stmt, Stmt::Function { name, .. } if name == "inner") {
+                walk_stmt_mut(self, stmt);
+            }
+        }
+    }
+
+    #[test]
+    fn test_visitor_mut_rewrites_in_place() {
+        let mut program = parse("let x = limit + 1; fn inner() { return limit; } print(x > limit);").unwrap();
+        Rename.visit_statements_mut(&mut program);
+        let source = to_source(&program);
+        assert!(source.contains("let x = max_dose + 1;"), "{}", source);
+        assert!(source.contains("return limit;"), "{}", source);
+        assert!(source.contains("print(x > max_dose);"), "{}", source);
+    }
+}
@@ -0,0 +1,113 @@
+use std::time::SystemTime;
+use serde::Serialize;
+use crate::secrets::Secrets;
+
+/// Something an evaluation did that an embedder may need to account for.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A native function was called; natives are where I/O and LLM requests happen.
+    NativeCall { name: String },
+    /// The evaluation was stopped through its cancellation token.
+    Cancelled { reason: String },
+    /// A prompt template from `prompts.load` was rendered.
+    Prompt {
+        name: String,
+        version: Option<String>,
+        model: Option<String>,
+        temperature: Option<f64>,
+    },
+    /// A request to an LLM provider, with its token usage.
+    LlmRequest {
+        model: String,
+        input_tokens: usize,
+        output_tokens: usize,
+    },
+    /// An `llm.conversation` session was exported or imported.
+    Conversation {
+        action: String,
+        messages: usize,
+        tokens: usize,
+    },
+}
+
+impl AuditEvent {
+    /// The event with registered secrets masked in its text.
+    pub fn redacted(self, secrets: &Secrets) -> Self {
+        match self {
+            AuditEvent::NativeCall { name } => AuditEvent::NativeCall {
+                name: secrets.redact(&name).into_owned(),
+            },
+            AuditEvent::Cancelled { reason } => AuditEvent::Cancelled {
+                reason: secrets.redact(&reason).into_owned(),
+            },
+            AuditEvent::Prompt { name, version, model, temperature } => AuditEvent::Prompt {
+                name: secrets.redact(&name).into_owned(),
+                version,
+                model,
+                temperature,
+            },
+            event @ (AuditEvent::LlmRequest { .. } | AuditEvent::Conversation { .. }) => event,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditEntry {
+    pub timestamp: SystemTime,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+/// Ordered record of the side effects of one evaluation.
+///
+/// It is kept when an evaluation fails or is cancelled, so the host can see
+/// how far a script got.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AuditLog {
+    entries: Vec<AuditEntry>,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, event: AuditEvent) {
+        self.entries.push(AuditEntry {
+            timestamp: SystemTime::now(),
+            event,
+        });
+    }
+
+    pub fn entries(&self) -> &[AuditEntry] {
+        &self.entries
+    }
+
+    /// Appends the entries of `other`, e.g. those of a concurrent task.
+    pub fn append(&mut self, other: &mut AuditLog) {
+        self.entries.append(&mut other.entries);
+    }
+
+    /// The LLM requests, in order.
+    pub fn llm_requests(&self) -> impl Iterator<Item = (&str, usize, usize)> {
+        self.entries.iter().filter_map(|entry| match &entry.event {
+            AuditEvent::LlmRequest { model, input_tokens, output_tokens } => {
+                Some((model.as_str(), *input_tokens, *output_tokens))
+            }
+            _ => None,
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
@@ -0,0 +1,305 @@
+//! `prism batch jobs.yaml`: runs a list of scripts headlessly, e.g. as a
+//! nightly prompt-regression check in CI, and reports on all of them.
+//!
+//! ```yaml
+//! defaults:
+//!   model: gpt-4o-mini
+//! jobs:
+//!   - name: triage
+//!     script: scripts/triage.prism
+//!     env: { REGION: eu }
+//!     model: { model: gpt-4o, temperature: 0.2 }
+//!     budget: { max_cost: 0.50, max_tokens: 20000, timeout_secs: 120 }
+//!     replay: fixtures/triage.jsonl
+//! ```
+//!
+//! Paths are relative to the jobs file. A job without a name is named after
+//! its script, and `defaults` fill in what a job leaves out. Scripts get
+//! the configured capabilities, as with `prism run`. A job fails when its script fails
+//! or it goes over its budget; its [`RunReport`] is kept either way.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::cancellation::CancellationToken;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::llm::replay::{Recorder, Replay};
+use crate::llm::ModelConfig;
+use crate::run_report::RunReport;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchFile {
+    #[serde(default)]
+    pub defaults: JobDefaults,
+    pub jobs: Vec<Job>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobDefaults {
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub model: Option<ModelSpec>,
+    pub budget: Option<Budget>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    pub name: Option<String>,
+    pub script: PathBuf,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub model: Option<ModelSpec>,
+    pub budget: Option<Budget>,
+    /// Answers LLM requests from this recording instead of the provider.
+    pub replay: Option<PathBuf>,
+    /// Records the job's LLM exchanges to this file.
+    pub record: Option<PathBuf>,
+}
+
+/// A model name, or the parameters to change from the defaults.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ModelSpec {
+    Name(String),
+    Config { model: Option<String>, temperature: Option<f32>, max_tokens: Option<usize> },
+}
+
+impl ModelSpec {
+    fn apply(&self, mut config: ModelConfig) -> ModelConfig {
+        match self {
+            ModelSpec::Name(model) => config.model = model.clone(),
+            ModelSpec::Config { model, temperature, max_tokens } => {
+                if let Some(model) = model {
+                    config.model = model.clone();
+                }
+                if let Some(temperature) = temperature {
+                    config.temperature = *temperature;
+                }
+                if let Some(max_tokens) = max_tokens {
+                    config.max_tokens = *max_tokens;
+                }
+            }
+        }
+        config
+    }
+}
+
+/// Limits a job must stay within to pass.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Budget {
+    /// Estimated USD cost of the job's LLM calls.
+    pub max_cost: Option<f64>,
+    /// Input and output tokens of the job's LLM calls together.
+    pub max_tokens: Option<usize>,
+    /// The script is cancelled when it runs longer.
+    pub timeout_secs: Option<f64>,
+}
+
+/// What happened to one job.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JobResult {
+    pub name: String,
+    pub script: String,
+    pub passed: bool,
+    pub failure: Option<String>,
+    pub report: RunReport,
+}
+
+/// The combined report of a batch.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatchReport {
+    pub passed: usize,
+    pub failed: usize,
+    pub duration_ms: f64,
+    pub jobs: Vec<JobResult>,
+}
+
+impl BatchFile {
+    pub fn parse(yaml: &str) -> Result<Self> {
+        serde_yaml::from_str(yaml).map_err(|err| PrismError::InvalidArgument(format!("Invalid jobs file: {}", err)))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// Runs the jobs of `batch` one after another, resolving paths against
+/// `dir`.
+pub async fn run(batch: &BatchFile, dir: &Path) -> BatchReport {
+    let started = Instant::now();
+    let mut jobs = Vec::new();
+    for job in &batch.jobs {
+        jobs.push(run_job(job, &batch.defaults, dir).await);
+    }
+    let passed = jobs.iter().filter(|job| job.passed).count();
+    BatchReport { passed, failed: jobs.len() - passed, duration_ms: started.elapsed().as_secs_f64() * 1000.0, jobs }
+}
+
+async fn run_job(job: &Job, defaults: &JobDefaults, dir: &Path) -> JobResult {
+    let script = dir.join(&job.script);
+    let name = job
+        .name
+        .clone()
+        .unwrap_or_else(|| job.script.file_stem().unwrap_or_default().to_string_lossy().into_owned());
+    let budget = job.budget.clone().or_else(|| defaults.budget.clone()).unwrap_or_default();
+    let started = Instant::now();
+
+    let mut interpreter = Interpreter::new();
+    let result = match prepare(job, defaults, dir, &script) {
+        Ok(prepared) => {
+            interpreter = prepared;
+            let token = match budget.timeout_secs {
+                Some(secs) => CancellationToken::with_timeout(Duration::from_secs_f64(secs)),
+                None => CancellationToken::new(),
+            };
+            match std::fs::read_to_string(&script) {
+                Ok(source) => interpreter.evaluate_cancellable(source, token).await,
+                Err(err) => Err(err.into()),
+            }
+        }
+        Err(err) => Err(err),
+    };
+    let report = RunReport::new(&interpreter, &result, started.elapsed());
+
+    let tokens = report.llm.input_tokens + report.llm.output_tokens;
+    let failure = match &report.error {
+        Some(error) => Some(error.clone()),
+        None => match budget {
+            Budget { max_cost: Some(max), .. } if report.llm.cost > max => {
+                Some(format!("Over budget: cost ${:.4} exceeds ${:.4}", report.llm.cost, max))
+            }
+            Budget { max_tokens: Some(max), .. } if tokens > max => {
+                Some(format!("Over budget: {} tokens exceed {}", tokens, max))
+            }
+            _ => None,
+        },
+    };
+    JobResult { name, script: script.display().to_string(), passed: failure.is_none(), failure, report }
+}
+
+fn prepare(job: &Job, defaults: &JobDefaults, dir: &Path, script: &Path) -> Result<Interpreter> {
+    let mut env = defaults.env.clone();
+    env.extend(job.env.clone());
+    let mut model = crate::config::global().model_config();
+    for spec in [&defaults.model, &job.model].into_iter().flatten() {
+        model = spec.apply(model);
+    }
+    let mut interpreter = Interpreter::new()
+        .with_capabilities(crate::config::global().granted())
+        .with_module_dir(script.parent().unwrap_or(Path::new(".")))
+        .with_env_vars(env)
+        .with_model_config(model);
+    if let Some(path) = &job.record {
+        interpreter = interpreter.with_recorder(Arc::new(Recorder::create(dir.join(path))?));
+    }
+    if let Some(path) = &job.replay {
+        interpreter = interpreter.with_replay(Arc::new(Replay::open(dir.join(path))?));
+    }
+    // What scripts print would interleave with the batch's own output
+    interpreter.capture_output();
+    Ok(interpreter)
+}
+
+impl BatchReport {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// The report in the JUnit XML format CI systems display.
+    pub fn to_junit(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuite name=\"prism\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+            self.jobs.len(),
+            self.failed,
+            self.duration_ms / 1000.0
+        ));
+        for job in &self.jobs {
+            xml.push_str(&format!(
+                "  <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                escape(&job.name),
+                escape(&job.script),
+                job.report.duration_ms / 1000.0
+            ));
+            match &job.failure {
+                Some(failure) => xml.push_str(&format!(
+                    ">\n    <failure message=\"{}\">{}</failure>\n  </testcase>\n",
+                    escape(failure.lines().next().unwrap_or_default()),
+                    escape(failure)
+                )),
+                None => xml.push_str("/>\n"),
+            }
+        }
+        xml.push_str("</testsuite>\n");
+        xml
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_jobs_run_with_their_settings() {
+        let dir = std::env::temp_dir().join(format!("prism-batch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("greet.prism"), r#"print("hi"); env.get("REGION");"#).unwrap();
+        std::fs::write(dir.join("ask.prism"), r#"llm.chat_completion("Is it flu?");"#).unwrap();
+        let server = crate::llm::testing::EchoServer::start();
+        let batch = BatchFile::parse(&format!(
+            r#"
+defaults:
+  env: {{ REGION: us, OPENAI_API_KEY: sk-test, OPENAI_BASE_URL: "{}" }}
+jobs:
+  - script: greet.prism
+    env: {{ REGION: eu }}
+  - name: cheap
+    script: ask.prism
+    model: gpt-4o-mini
+    budget: {{ max_cost: 1.0 }}
+  - name: strict
+    script: ask.prism
+    budget: {{ max_tokens: 1 }}
+  - name: broken
+    script: missing.prism
+"#,
+            server.url()
+        ))
+        .unwrap();
+        let report = run(&batch, &dir).await;
+
+        let outcomes: Vec<(&str, bool)> = report.jobs.iter().map(|job| (job.name.as_str(), job.passed)).collect();
+        assert_eq!(outcomes, [("greet", true), ("cheap", true), ("strict", false), ("broken", false)]);
+        assert_eq!(report.jobs[0].report.value, Some(serde_json::json!("eu")));
+        assert_eq!(report.jobs[1].report.llm.calls[0].model, "gpt-4o-mini");
+        assert!(report.jobs[2].failure.as_deref().unwrap().starts_with("Over budget"));
+        assert_eq!((report.passed, report.failed), (2, 2));
+
+        let junit = report.to_junit();
+        assert!(junit.contains("<testsuite name=\"prism\" tests=\"4\" failures=\"2\""));
+        assert!(junit.contains("<testcase name=\"greet\""));
+        assert!(junit.contains("<failure message=\"Over budget"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_settings_are_rejected() {
+        let err = BatchFile::parse("jobs:\n  - script: a.prism\n    budgt: {}\n").unwrap_err();
+        assert!(err.to_string().contains("budgt"));
+    }
+}
@@ -0,0 +1,46 @@
+//! `prism-kernel <connection_file>`: the Jupyter kernel, which Jupyter
+//! starts once `prism-kernel install [kernels_dir]` has registered it; see
+//! [`prism::jupyter`].
+
+use std::path::{Path, PathBuf};
+use prism::interpreter::Interpreter;
+use prism::jupyter::{self, ConnectionInfo, Kernel};
+
+#[tokio::main]
+async fn main() {
+    prism::init();
+    if std::env::var("PRISM_DEBUG").unwrap_or_default() == "true" {
+        env_logger::init();
+    }
+
+    let args: Vec<String> = std::env::args().collect();
+    let result = match args.as_slice() {
+        [_, command, rest @ ..] if command == "install" && rest.len() <= 1 => install(rest.first().map(PathBuf::from)),
+        [_, file] => serve(Path::new(file)).await,
+        _ => {
+            eprintln!("Usage: prism-kernel <connection_file>");
+            eprintln!("       prism-kernel install [kernels_dir]");
+            std::process::exit(1);
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn install(kernels_dir: Option<PathBuf>) -> prism::error::Result<()> {
+    let Some(kernels_dir) = kernels_dir.or_else(jupyter::user_kernels_dir) else {
+        eprintln!("Error: cannot find the Jupyter data directory; pass the kernels directory");
+        std::process::exit(1);
+    };
+    let dir = jupyter::install(&kernels_dir, &std::env::current_exe()?)?;
+    println!("Installed the Prism kernel in {}", dir.display());
+    Ok(())
+}
+
+async fn serve(connection_file: &Path) -> prism::error::Result<()> {
+    let info = ConnectionInfo::load(connection_file)?;
+    let interpreter = Interpreter::new().with_capabilities(prism::config::global().granted());
+    Kernel::bind(info, interpreter).await?.run().await
+}
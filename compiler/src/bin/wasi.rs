@@ -0,0 +1,27 @@
+//! `prism-wasi <source_file>`: runs a script, or a precompiled `.prismc`,
+//! in a WASI runtime; see [`prism::wasi`].
+
+use std::path::Path;
+use prism::wasi::{block_on, interpreter};
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let [_, file] = args.as_slice() else {
+        eprintln!("Usage: prism-wasi <source_file>");
+        std::process::exit(1);
+    };
+    let path = Path::new(file);
+    let program = prism::precompile::load(path).unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    });
+
+    let mut interpreter = interpreter().with_module_dir(path.parent().unwrap_or(Path::new(".")));
+    match block_on(interpreter.evaluate_program(program)) {
+        Ok(result) => println!("{:?}", result),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
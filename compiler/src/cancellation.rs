@@ -0,0 +1,96 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Cooperative cancellation for a running evaluation.
+///
+/// Clones share one flag, so an embedder keeps a clone and calls
+/// [`cancel`](Self::cancel) from any thread. The interpreter checks the
+/// token at loop back-edges and before every call, including natives that
+/// make LLM requests; code between those points runs to completion.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token that also counts as cancelled once `timeout` has elapsed.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            deadline: Some(Instant::now() + timeout),
+        }
+    }
+
+    /// A token sharing this one's flag whose deadline is at most `timeout`
+    /// away, for limiting part of an evaluation.
+    pub fn limited_to(&self, timeout: Duration) -> Self {
+        let deadline = Instant::now() + timeout;
+        Self {
+            cancelled: Arc::clone(&self.cancelled),
+            deadline: Some(self.deadline.map_or(deadline, |current| current.min(deadline))),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.is_expired()
+    }
+
+    fn is_expired(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Why the token is cancelled, if it is.
+    pub fn reason(&self) -> Option<&'static str> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            Some("cancelled by host")
+        } else if self.is_expired() {
+            Some("timed out")
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clones_share_cancellation() {
+        let token = CancellationToken::new();
+        let handle = token.clone();
+        assert!(!token.is_cancelled());
+        handle.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(token.reason(), Some("cancelled by host"));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let token = CancellationToken::with_timeout(Duration::ZERO);
+        assert!(token.is_cancelled());
+        assert_eq!(token.reason(), Some("timed out"));
+    }
+
+    #[test]
+    fn test_limited_token_shares_flag_and_keeps_earlier_deadline() {
+        let token = CancellationToken::with_timeout(Duration::ZERO);
+        assert!(token.limited_to(Duration::from_secs(60)).is_cancelled());
+
+        let token = CancellationToken::new();
+        let limited = token.limited_to(Duration::from_secs(60));
+        assert!(!limited.is_cancelled());
+        token.cancel();
+        assert_eq!(limited.reason(), Some("cancelled by host"));
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use crate::error::{PrismError, Result};
+
+/// Access to the outside world that a host must grant before scripts get it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// Reading process environment variables, including values from `.env`.
+    Env,
+    /// Reading standard input, interactively or piped.
+    Stdin,
+    /// Reading and writing files, e.g. through `store.open`.
+    Fs,
+    /// Opening network connections, e.g. through `ws.connect`.
+    Net,
+}
+
+impl Capability {
+    pub const ALL: &'static [Capability] = &[Capability::Env, Capability::Stdin, Capability::Fs, Capability::Net];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::Env => "env",
+            Capability::Stdin => "stdin",
+            Capability::Fs => "fs",
+            Capability::Net => "net",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Capability {
+    type Err = PrismError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Capability::ALL
+            .iter()
+            .copied()
+            .find(|capability| capability.name() == name)
+            .ok_or_else(|| PrismError::InvalidArgument(format!("Unknown capability '{}'", name)))
+    }
+}
+
+/// The set of capabilities granted to an interpreter. Nothing is granted by
+/// default; the CLI and REPL grant everything to the user's own scripts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    granted: HashSet<Capability>,
+}
+
+impl Capabilities {
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn all() -> Self {
+        Self {
+            granted: Capability::ALL.iter().copied().collect(),
+        }
+    }
+
+    pub fn grant(mut self, capability: Capability) -> Self {
+        self.granted.insert(capability);
+        self
+    }
+
+    pub fn revoke(mut self, capability: Capability) -> Self {
+        self.granted.remove(&capability);
+        self
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        self.granted.contains(&capability)
+    }
+
+    /// Fails with [`PrismError::PermissionDenied`] unless `capability` is granted.
+    pub fn require(&self, capability: Capability, operation: &str) -> Result<()> {
+        if self.allows(capability) {
+            Ok(())
+        } else {
+            Err(PrismError::PermissionDenied(format!(
+                "{} requires the '{}' capability",
+                operation, capability
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grant_and_require() {
+        let capabilities = Capabilities::none();
+        assert!(matches!(
+            capabilities.require(Capability::Env, "env.get"),
+            Err(PrismError::PermissionDenied(_))
+        ));
+
+        let capabilities = capabilities.grant(Capability::Env);
+        assert!(capabilities.require(Capability::Env, "env.get").is_ok());
+        assert!(!capabilities.revoke(Capability::Env).allows(Capability::Env));
+    }
+
+    #[test]
+    fn test_parse_capability() {
+        assert_eq!("env".parse::<Capability>().unwrap(), Capability::Env);
+        assert!("network".parse::<Capability>().is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Conditional compilation: `@cfg(...)` annotations keep a statement only
+//! when the interpreter's flags satisfy them, so one script can target both
+//! native and browser hosts.
+//!
+//! ```text
+//! @cfg("wasm")
+//! fn save(report) { print(report); }
+//! @cfg(not("wasm"))
+//! fn save(report) { store.set("report", report); }
+//! @cfg(feature = "medical") let triage = medical.triage;
+//! ```
+//!
+//! Annotations are removed before the program is resolved and checked, so
+//! alternatives may declare the same names.
+
+use std::collections::BTreeSet;
+use crate::ast::{CfgPredicate, Stmt};
+
+/// The flags `@cfg` predicates are tested against: bare flags such as
+/// `"wasm"` and key-value pairs such as `feature = "medical"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CfgFlags {
+    flags: BTreeSet<String>,
+    values: BTreeSet<(String, String)>,
+}
+
+impl Default for CfgFlags {
+    /// `"native"` or `"wasm"`, depending on the build, and `"wasi"` too
+    /// for WASI builds.
+    fn default() -> Self {
+        let flags = Self::none();
+        let flags = if cfg!(feature = "native") { flags.with_flag("native") } else { flags };
+        let flags = if cfg!(target_os = "wasi") { flags.with_flag("wasi") } else { flags };
+        if cfg!(target_arch = "wasm32") { flags.with_flag("wasm") } else { flags }
+    }
+}
+
+impl CfgFlags {
+    pub fn none() -> Self {
+        CfgFlags { flags: BTreeSet::new(), values: BTreeSet::new() }
+    }
+
+    pub fn with_flag(mut self, flag: &str) -> Self {
+        self.flags.insert(flag.to_string());
+        self
+    }
+
+    pub fn with_value(mut self, key: &str, value: &str) -> Self {
+        self.values.insert((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Shorthand for `with_value("feature", name)`.
+    pub fn with_feature(self, name: &str) -> Self {
+        self.with_value("feature", name)
+    }
+
+    pub fn matches(&self, predicate: &CfgPredicate) -> bool {
+        match predicate {
+            CfgPredicate::Flag(flag) => self.flags.contains(flag),
+            CfgPredicate::Value(key, value) => self.values.contains(&(key.clone(), value.clone())),
+            CfgPredicate::Not(inner) => !self.matches(inner),
+            CfgPredicate::All(items) => items.iter().all(|item| self.matches(item)),
+            CfgPredicate::Any(items) => items.iter().any(|item| self.matches(item)),
+        }
+    }
+}
+
+/// Removes the statements whose `@cfg` does not match `flags` and unwraps
+/// the others, at every level of nesting.
+pub fn apply(statements: &mut Vec<Stmt>, flags: &CfgFlags) {
+    statements.retain_mut(|stmt| match stmt {
+        Stmt::Cfg { predicate, .. } if !flags.matches(predicate) => false,
+        _ => {
+            apply_stmt(stmt, flags);
+            true
+        }
+    });
+}
+
+fn apply_stmt(stmt: &mut Stmt, flags: &CfgFlags) {
+    match stmt {
+        Stmt::Cfg { predicate, body } => {
+            let body = if flags.matches(predicate) {
+                std::mem::replace(body.as_mut(), Stmt::Block(Vec::new()))
+            } else {
+                Stmt::Block(Vec::new())
+            };
+            *stmt = body;
+            apply_stmt(stmt, flags);
+        }
+        Stmt::Block(statements) | Stmt::Module { body: statements, .. } => apply(statements, flags),
+        Stmt::If { then_branch, else_branch, .. } => {
+            apply_stmt(then_branch, flags);
+            if let Some(else_branch) = else_branch {
+                apply_stmt(else_branch, flags);
+            }
+        }
+        Stmt::UncertainIf { then_branch, medium_branch, low_branch, .. } => {
+            apply_stmt(then_branch, flags);
+            for branch in [medium_branch, low_branch].into_iter().flatten() {
+                apply_stmt(branch, flags);
+            }
+        }
+        Stmt::While { body, .. }
+        | Stmt::For { body, .. }
+        | Stmt::Function { body, .. }
+        | Stmt::Context { body, .. }
+        | Stmt::Export(_, body) => apply_stmt(body, flags),
+        Stmt::Expression(_)
+        | Stmt::Let { .. }
+        | Stmt::Return(_)
+        | Stmt::Break
+        | Stmt::Continue
+        | Stmt::Yield(_)
+        | Stmt::Import { .. }
+        | Stmt::ModuleAccess { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::to_source;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_apply_keeps_matching_statements() {
+        let source = r#"
+            @cfg("wasm") fn save(r) { return "download"; }
+            @cfg(not("wasm")) fn save(r) { return "store"; }
+            fn triage(temp) {
+                @cfg(all("native", feature = "medical")) let limit = 39;
+                @cfg(any("wasm", feature = "research")) { let limit = 40; }
+                return temp;
+            }
+        "#;
+        let mut program = parse(source).unwrap();
+        apply(&mut program, &CfgFlags::none().with_flag("native").with_feature("medical"));
+        assert_eq!(
+            to_source(&program),
+            "fn save(r) {\n    return \"store\";\n}\nfn triage(temp) {\n    let limit = 39;\n    return temp;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_annotations_print_and_reject_unknown_predicates() {
+        let program = parse(r#"@cfg(any("wasm", feature = "medical")) let x = 1;"#).unwrap();
+        assert_eq!(to_source(&program), "@cfg(any(\"wasm\", feature = \"medical\"))\nlet x = 1;\n");
+
+        let err = parse(r#"@cfg(maybe("wasm")) let x = 1;"#).unwrap_err();
+        assert!(err.to_string().contains("Unknown cfg predicate 'maybe'"), "{}", err);
+    }
+}
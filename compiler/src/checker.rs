@@ -0,0 +1,845 @@
+use std::collections::HashMap;
+use crate::analysis::{ConfidenceBounds, Symbol, SymbolKind};
+use crate::ast::{Expr, MatchArm, Pattern, Stmt, Type};
+use crate::diagnostics::Diagnostic;
+use crate::token::TokenKind;
+use crate::value::ValueKind;
+
+/// Runs the static checks over a parsed program and returns everything found.
+///
+/// Types come from annotations (`let x: number? = ...`) and from literals;
+/// anything else is `any` and is not checked. Values whose type includes
+/// `nil` must pass a nil test (`if (x != nil)`, `x != nil and ...`, or an
+/// early `return`) before they are used as operands, called, or accessed
+/// with `.` — `?.` is always allowed and itself produces a possibly-nil value.
+///
+/// `match` must be exhaustive, and the bands of an `uncertain if` should
+/// cover every confidence in [0, 1]; arms that can never run are reported
+/// as warnings.
+pub fn check(statements: &[Stmt]) -> Vec<Diagnostic> {
+    let mut checker = Checker::new();
+    checker.check_statements(statements);
+    checker.diagnostics
+}
+
+/// [`check`], also returning every declaration the checker saw, in source
+/// order; see [`crate::analysis`].
+pub fn check_with_symbols(statements: &[Stmt]) -> (Vec<Diagnostic>, Vec<Symbol>) {
+    let mut checker = Checker::new();
+    checker.check_statements(statements);
+    (checker.diagnostics, checker.symbols)
+}
+
+#[derive(Debug, Clone)]
+struct Signature {
+    params: Vec<Option<Type>>,
+    return_type: Option<Type>,
+}
+
+#[derive(Debug, Clone)]
+struct Binding {
+    declared: Type,
+    /// Whether `declared` was only inferred from a value, so assigning a
+    /// value of another type widens it instead of being an error.
+    inferred: bool,
+    narrowed: bool,
+    signature: Option<Signature>,
+    confidence: ConfidenceBounds,
+}
+
+impl Binding {
+    fn new(declared: Type) -> Self {
+        Self {
+            declared,
+            inferred: false,
+            narrowed: false,
+            signature: None,
+            confidence: ConfidenceBounds::UNKNOWN,
+        }
+    }
+
+    fn current(&self) -> Type {
+        if self.narrowed {
+            self.declared.without_nil()
+        } else {
+            self.declared.clone()
+        }
+    }
+}
+
+struct Checker {
+    scopes: Vec<HashMap<String, Binding>>,
+    return_types: Vec<Option<Type>>,
+    diagnostics: Vec<Diagnostic>,
+    symbols: Vec<Symbol>,
+}
+
+impl Checker {
+    fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            return_types: Vec::new(),
+            diagnostics: Vec::new(),
+            symbols: Vec::new(),
+        }
+    }
+
+    fn check_statements(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            self.check_stmt(stmt);
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) => {
+                self.expr_type(expr);
+            }
+            Stmt::Let { name, type_annotation, initializer } => {
+                let init_ty = initializer.as_ref().map(|init| self.expr_type(init));
+                let declared = match (type_annotation, init_ty) {
+                    (Some(annotation), Some(init_ty)) => {
+                        if !init_ty.is_assignable_to(annotation) {
+                            let line = initializer.as_deref().and_then(Expr::line);
+                            self.error(format!(
+                                "Cannot initialize `{}` of type {} with a value of type {}",
+                                name, annotation, init_ty
+                            ), line);
+                        }
+                        annotation.clone()
+                    }
+                    (Some(annotation), None) => {
+                        if !annotation.is_nullable() && *annotation != Type::Any {
+                            self.error(format!(
+                                "`{}` has non-optional type {} and must be initialized",
+                                name, annotation
+                            ), None);
+                        }
+                        annotation.clone()
+                    }
+                    // An untyped `let x = nil;` says nothing about later values.
+                    (None, Some(Type::Nil)) | (None, None) => Type::Any,
+                    (None, Some(init_ty)) => init_ty,
+                };
+                let confidence = initializer
+                    .as_deref()
+                    .map_or(ConfidenceBounds::exactly(1.0), |init| self.confidence(init));
+                let binding = Binding { confidence, inferred: type_annotation.is_none(), ..Binding::new(declared) };
+                self.record(name, SymbolKind::Variable, binding, stmt.line());
+            }
+            Stmt::Block(statements) => {
+                self.begin_scope();
+                self.check_statements(statements);
+                self.end_scope();
+            }
+            Stmt::If { condition, then_branch, else_branch } => {
+                self.expr_type(condition);
+                self.check_narrowed(condition, true, then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.check_narrowed(condition, false, else_branch);
+                } else if always_returns(then_branch) {
+                    // `if (x == nil) { return ...; }` guards the rest of the function.
+                    for name in narrowed_names(condition, false) {
+                        self.narrow(&name);
+                    }
+                }
+            }
+            Stmt::UncertainIf {
+                condition,
+                high_threshold,
+                medium_threshold,
+                then_branch,
+                medium_branch,
+                low_branch,
+            } => {
+                self.expr_type(condition);
+                self.check_confidence_bands(
+                    *high_threshold,
+                    medium_branch.as_ref().map(|_| *medium_threshold),
+                    low_branch.is_some(),
+                    condition.line(),
+                );
+                self.check_stmt(then_branch);
+                for branch in [medium_branch, low_branch].into_iter().flatten() {
+                    self.check_stmt(branch);
+                }
+            }
+            Stmt::While { condition, body } => {
+                self.expr_type(condition);
+                self.check_narrowed(condition, true, body);
+            }
+            Stmt::For { name, iterable, body } => {
+                self.expr_type(iterable);
+                self.begin_scope();
+                self.record(name, SymbolKind::Variable, Binding::new(Type::Any), iterable.line());
+                self.check_stmt(body);
+                self.end_scope();
+            }
+            Stmt::Function { name, params, param_types, return_type, body, confidence, .. } => {
+                self.record(name, SymbolKind::Function, Binding {
+                    declared: Type::Function,
+                    inferred: false,
+                    narrowed: false,
+                    signature: Some(Signature {
+                        params: param_types.clone(),
+                        return_type: return_type.clone(),
+                    }),
+                    confidence: ConfidenceBounds::exactly(confidence.unwrap_or(1.0)),
+                }, None);
+
+                self.begin_scope();
+                for (param, ty) in params.iter().zip(param_types) {
+                    self.record(param, SymbolKind::Parameter, Binding::new(ty.clone().unwrap_or(Type::Any)), None);
+                }
+                self.return_types.push(return_type.clone());
+                self.check_stmt(body);
+                self.return_types.pop();
+                self.end_scope();
+            }
+            Stmt::Return(value) => {
+                let ty = match value {
+                    Some(value) => self.expr_type(value),
+                    None => Type::Nil,
+                };
+                if let Some(Some(expected)) = self.return_types.last().cloned() {
+                    if !ty.is_assignable_to(&expected) {
+                        let line = value.as_deref().and_then(Expr::line);
+                        self.error(format!(
+                            "Function declared to return {} but returns a value of type {}",
+                            expected, ty
+                        ), line);
+                    }
+                }
+            }
+            Stmt::Yield(value) => {
+                self.expr_type(value);
+            }
+            Stmt::Context { body, .. } => self.check_stmt(body),
+            Stmt::Export(_, stmt) | Stmt::Cfg { body: stmt, .. } => self.check_stmt(stmt),
+            Stmt::Module { body, .. } => {
+                self.begin_scope();
+                self.check_statements(body);
+                self.end_scope();
+            }
+            Stmt::Break | Stmt::Continue | Stmt::Import { .. } | Stmt::ModuleAccess { .. } => {}
+        }
+    }
+
+    fn expr_type(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Literal(value) => literal_type(&value.kind),
+            Expr::Variable { name, .. } => self
+                .lookup(name)
+                .map(Binding::current)
+                .unwrap_or(Type::Any),
+            Expr::Assign { name, value, .. } => {
+                let value_ty = self.expr_type(value);
+                let mismatch = match self.lookup_mut(name) {
+                    Some(binding) => {
+                        if value_ty.is_nullable() {
+                            binding.narrowed = false;
+                        }
+                        if value_ty.is_assignable_to(&binding.declared) {
+                            None
+                        } else if binding.inferred {
+                            // `let x = 1; x = "a";` makes `x` a number or a string from here on
+                            binding.declared = Type::union(vec![binding.declared.clone(), value_ty.clone()]);
+                            None
+                        } else {
+                            Some(binding.declared.clone())
+                        }
+                    }
+                    None => None,
+                };
+                if let Some(declared) = mismatch {
+                    self.error(format!(
+                        "Cannot assign a value of type {} to `{}` of type {}",
+                        value_ty, name, declared
+                    ), value.line());
+                }
+                value_ty
+            }
+            Expr::Binary { left, operator, right } => {
+                let left_ty = self.expr_type(left);
+                let right_ty = self.expr_type(right);
+                match operator.kind {
+                    // Comparing against nil is exactly how values get tested.
+                    TokenKind::EqualEqual | TokenKind::BangEqual => Type::Boolean,
+                    _ => {
+                        self.require_non_nil(left, &left_ty, "used as an operand");
+                        self.require_non_nil(right, &right_ty, "used as an operand");
+                        match operator.kind {
+                            TokenKind::Greater
+                            | TokenKind::GreaterEqual
+                            | TokenKind::Less
+                            | TokenKind::LessEqual => Type::Boolean,
+                            TokenKind::Plus if left_ty == Type::String || right_ty == Type::String => Type::String,
+                            _ if left_ty == Type::Number && right_ty == Type::Number => Type::Number,
+                            _ => Type::Any,
+                        }
+                    }
+                }
+            }
+            Expr::Unary { operator, right } => {
+                let ty = self.expr_type(right);
+                if operator.kind == TokenKind::Minus {
+                    self.require_non_nil(right, &ty, "negated");
+                    Type::Number
+                } else {
+                    Type::Boolean
+                }
+            }
+            Expr::Logical { left, operator, right } => {
+                self.expr_type(left);
+                // The right operand only runs once the left one has decided nothing.
+                let names = narrowed_names(left, operator.kind == TokenKind::And);
+                self.begin_scope();
+                for name in names {
+                    self.narrow(&name);
+                }
+                self.expr_type(right);
+                self.end_scope();
+                Type::Boolean
+            }
+            Expr::Call { callee, arguments, .. } => {
+                let callee_ty = self.expr_type(callee);
+                self.require_non_nil(callee, &callee_ty, "called");
+                let arg_types: Vec<Type> = arguments.iter().map(|arg| self.expr_type(arg)).collect();
+
+                let signature = match callee.as_ref() {
+                    Expr::Variable { name, .. } => self
+                        .lookup(name)
+                        .and_then(|binding| binding.signature.clone())
+                        .map(|signature| (name.clone(), signature)),
+                    _ => None,
+                };
+
+                match signature {
+                    Some((name, signature)) => {
+                        for (i, (arg_ty, param_ty)) in arg_types.iter().zip(&signature.params).enumerate() {
+                            if let Some(param_ty) = param_ty {
+                                if !arg_ty.is_assignable_to(param_ty) {
+                                    self.error(format!(
+                                        "Argument {} of `{}` expects {} but got {}",
+                                        i + 1, name, param_ty, arg_ty
+                                    ), arguments[i].line());
+                                }
+                            }
+                        }
+                        signature.return_type.unwrap_or(Type::Any)
+                    }
+                    None => Type::Any,
+                }
+            }
+            Expr::Get { object, optional, .. } => {
+                let object_ty = self.expr_type(object);
+                if *optional {
+                    Type::optional(Type::Any)
+                } else {
+                    self.require_non_nil(object, &object_ty, "accessed with '.' (use '?.')");
+                    Type::Any
+                }
+            }
+            Expr::Grouping(inner) => self.expr_type(inner),
+            Expr::List(items) => {
+                for item in items {
+                    self.expr_type(item);
+                }
+                Type::List
+            }
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr_type(key);
+                    self.expr_type(value);
+                }
+                Type::Map
+            }
+            Expr::Confidence { expr, .. } => self.expr_type(expr),
+            Expr::ConfidenceCombine { left, right } => {
+                self.expr_type(left);
+                self.expr_type(right);
+                Type::Any
+            }
+            Expr::InContext { body, .. } => self.expr_type(body),
+            Expr::ModuleAccess { .. } => Type::Any,
+            Expr::Match { subject, arms } => {
+                let subject_ty = self.expr_type(subject);
+                let mut nil_handled = false;
+                let mut arm_types = Vec::new();
+                for arm in arms {
+                    self.begin_scope();
+                    if let Pattern::Binding(name) = &arm.pattern {
+                        let ty = if nil_handled { subject_ty.without_nil() } else { subject_ty.clone() };
+                        let confidence = self.confidence(subject);
+                        let binding = Binding { confidence, inferred: true, ..Binding::new(ty) };
+                        self.record(name, SymbolKind::Variable, binding, subject.line());
+                    }
+                    if let Some(guard) = &arm.guard {
+                        self.expr_type(guard);
+                    }
+                    arm_types.push(self.expr_type(&arm.body));
+                    self.end_scope();
+
+                    if arm.guard.is_none() && is_nil_pattern(&arm.pattern) {
+                        nil_handled = true;
+                    }
+                }
+                self.check_match_arms(arms, subject.line());
+                if arm_types.is_empty() {
+                    Type::Nil
+                } else {
+                    Type::union(arm_types)
+                }
+            }
+        }
+    }
+
+    /// High covers [high, 1], medium [medium, high) and low everything below.
+    fn check_confidence_bands(&mut self, high: f64, medium: Option<f64>, has_low: bool, line: Option<usize>) {
+        for threshold in std::iter::once(high).chain(medium) {
+            if !(0.0..=1.0).contains(&threshold) {
+                self.error(format!("Confidence threshold {} is outside [0, 1]", threshold), line);
+                return;
+            }
+        }
+
+        let floor = match medium {
+            Some(medium) if medium >= high => {
+                self.warning(format!(
+                    "The `medium` arm is unreachable: its threshold {} is not below the high threshold {}",
+                    medium, high
+                ), line);
+                high
+            }
+            Some(medium) => medium,
+            None => high,
+        };
+
+        if has_low && floor == 0.0 {
+            self.warning("The `low` arm is unreachable: every confidence is at least 0".to_string(), line);
+        } else if !has_low && floor > 0.0 {
+            self.warning(format!(
+                "`uncertain if` does not handle confidence below {}; add a `low` arm",
+                floor
+            ), line);
+        }
+    }
+
+    fn check_match_arms(&mut self, arms: &[MatchArm], line: Option<usize>) {
+        let mut exhaustive = false;
+        let mut literals: Vec<&crate::value::Value> = Vec::new();
+        let mut confidence_floor: Option<f64> = None;
+
+        for (i, arm) in arms.iter().enumerate() {
+            let position = i + 1;
+            if exhaustive {
+                self.warning(format!(
+                    "Match arm {} is unreachable: earlier arms already match every value",
+                    position
+                ), line);
+                continue;
+            }
+
+            let guarded = arm.guard.is_some();
+            match &arm.pattern {
+                Pattern::Wildcard | Pattern::Binding(_) => exhaustive = !guarded,
+                Pattern::Literal(value) => {
+                    if literals.iter().any(|seen| seen.kind == value.kind) {
+                        self.warning(format!(
+                            "Match arm {} is unreachable: {} is already matched",
+                            position, value
+                        ), line);
+                    } else if !guarded {
+                        literals.push(value);
+                    }
+                }
+                Pattern::Confidence(threshold) => {
+                    if let Some(floor) = confidence_floor.filter(|floor| threshold >= floor) {
+                        self.warning(format!(
+                            "Match arm {} is unreachable: confidence ~> {} is already matched by ~> {}",
+                            position, threshold, floor
+                        ), line);
+                    } else if !guarded {
+                        confidence_floor = Some(*threshold);
+                        exhaustive = *threshold == 0.0;
+                    }
+                }
+            }
+
+            let covers_bool = |b: bool| literals.iter().any(|seen| seen.kind == ValueKind::Boolean(b));
+            if covers_bool(true) && covers_bool(false) {
+                exhaustive = true;
+            }
+        }
+
+        if !exhaustive {
+            let detail = match confidence_floor {
+                Some(floor) => format!(" (confidence below {} is not matched)", floor),
+                None => String::new(),
+            };
+            self.error(format!("Non-exhaustive match{}; add a `_` arm", detail), line);
+        }
+    }
+
+    fn require_non_nil(&mut self, expr: &Expr, ty: &Type, usage: &str) {
+        if ty.is_nullable() {
+            let subject = path_of(expr)
+                .map(|path| format!("`{}`", path))
+                .unwrap_or_else(|| "Expression".to_string());
+            self.error(format!(
+                "{} may be nil ({}) and is {} without a nil test",
+                subject, ty, usage
+            ), expr.line());
+        }
+    }
+
+    fn check_narrowed(&mut self, condition: &Expr, when: bool, stmt: &Stmt) {
+        self.begin_scope();
+        for name in narrowed_names(condition, when) {
+            self.narrow(&name);
+        }
+        self.check_stmt(stmt);
+        self.end_scope();
+    }
+
+    /// Shadows `name` in the innermost scope with its nil-free type.
+    fn narrow(&mut self, name: &str) {
+        if let Some(binding) = self.lookup(name).cloned() {
+            self.declare(name, Binding { narrowed: true, ..binding });
+        }
+    }
+
+    fn error(&mut self, message: String, line: Option<usize>) {
+        self.diagnostics.push(Diagnostic::error(message).at_line(line));
+    }
+
+    fn warning(&mut self, message: String, line: Option<usize>) {
+        self.diagnostics.push(Diagnostic::warning(message).at_line(line));
+    }
+
+    /// The confidence `expr` can evaluate to, as far as it is known statically.
+    fn confidence(&self, expr: &Expr) -> ConfidenceBounds {
+        match expr {
+            Expr::Literal(value) => ConfidenceBounds::exactly(value.confidence),
+            Expr::Confidence { confidence, .. } => ConfidenceBounds::exactly(*confidence),
+            Expr::Variable { name, .. } => self
+                .lookup(name)
+                .map_or(ConfidenceBounds::UNKNOWN, |binding| binding.confidence),
+            Expr::Grouping(inner) | Expr::Unary { right: inner, .. } => self.confidence(inner),
+            // Arithmetic and collection literals produce fresh, certain values.
+            Expr::Binary { .. } | Expr::List(_) | Expr::Map(_) => ConfidenceBounds::exactly(1.0),
+            Expr::Logical { left, right, .. } => {
+                let (left, right) = (self.confidence(left), self.confidence(right));
+                ConfidenceBounds { low: left.low.min(right.low), high: left.high.max(right.high) }
+            }
+            _ => ConfidenceBounds::UNKNOWN,
+        }
+    }
+
+    /// Declares a name the program introduces and records it as a symbol.
+    fn record(&mut self, name: &str, kind: SymbolKind, binding: Binding, line: Option<usize>) {
+        self.symbols.push(Symbol {
+            name: name.to_string(),
+            kind,
+            ty: binding.declared.clone(),
+            confidence: binding.confidence,
+            line,
+            depth: self.scopes.len() - 1,
+        });
+        self.declare(name, binding);
+    }
+
+    fn declare(&mut self, name: &str, binding: Binding) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), binding);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn lookup_mut(&mut self, name: &str) -> Option<&mut Binding> {
+        self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name))
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+}
+
+fn literal_type(kind: &ValueKind) -> Type {
+    match kind {
+        ValueKind::Nil => Type::Nil,
+        ValueKind::Boolean(_) => Type::Boolean,
+        ValueKind::Number(_) => Type::Number,
+        ValueKind::String(_) => Type::String,
+        ValueKind::List(_) => Type::List,
+        ValueKind::Map(_) => Type::Map,
+        ValueKind::Function { .. }
+        | ValueKind::NativeFunction { .. }
+        | ValueKind::AsyncNativeFunction { .. } => Type::Function,
+        ValueKind::Module(_) | ValueKind::Range { .. } | ValueKind::DateTime(_) | ValueKind::Duration(_) => Type::Any,
+        #[cfg(feature = "decimal")]
+        ValueKind::Decimal(_) => Type::Any,
+        #[cfg(feature = "tensor")]
+        ValueKind::Tensor(_) => Type::Any,
+    }
+}
+
+/// Names proven non-nil when `condition` evaluates to `when`.
+fn narrowed_names(condition: &Expr, when: bool) -> Vec<String> {
+    match condition {
+        Expr::Grouping(inner) => narrowed_names(inner, when),
+        Expr::Unary { operator, right } if operator.kind == TokenKind::Bang => {
+            narrowed_names(right, !when)
+        }
+        Expr::Binary { left, operator, right } => {
+            let proves_non_nil = match operator.kind {
+                TokenKind::BangEqual => when,
+                TokenKind::EqualEqual => !when,
+                _ => false,
+            };
+            if !proves_non_nil {
+                return Vec::new();
+            }
+            match (left.as_ref(), right.as_ref()) {
+                (Expr::Variable { name, .. }, other) | (other, Expr::Variable { name, .. })
+                    if is_nil_literal(other) => vec![name.clone()],
+                _ => Vec::new(),
+            }
+        }
+        Expr::Logical { left, operator, right } => match (&operator.kind, when) {
+            (TokenKind::And, true) | (TokenKind::Or, false) => {
+                let mut names = narrowed_names(left, when);
+                names.extend(narrowed_names(right, when));
+                names
+            }
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+fn is_nil_pattern(pattern: &Pattern) -> bool {
+    matches!(pattern, Pattern::Literal(value) if value.kind == ValueKind::Nil)
+}
+
+fn is_nil_literal(expr: &Expr) -> bool {
+    matches!(expr, Expr::Literal(value) if value.kind == ValueKind::Nil)
+}
+
+fn always_returns(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Return(_) => true,
+        Stmt::Block(statements) => statements.last().is_some_and(always_returns),
+        Stmt::If { then_branch, else_branch: Some(else_branch), .. } => {
+            always_returns(then_branch) && always_returns(else_branch)
+        }
+        _ => false,
+    }
+}
+
+fn path_of(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Variable { name, .. } => Some(name.clone()),
+        Expr::Get { object, name, optional } => path_of(object).map(|object| {
+            format!("{}{}{}", object, if *optional { "?." } else { "." }, name)
+        }),
+        Expr::Grouping(inner) => path_of(inner),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn errors(source: &str) -> Vec<Diagnostic> {
+        check(&parse(source).unwrap())
+            .into_iter()
+            .filter(Diagnostic::is_error)
+            .collect()
+    }
+
+    fn warnings(source: &str) -> Vec<Diagnostic> {
+        check(&parse(source).unwrap())
+            .into_iter()
+            .filter(|d| !d.is_error())
+            .collect()
+    }
+
+    #[test]
+    fn test_optional_annotations_parse() {
+        let program = parse("let a: string? = nil; let b: number | nil = 1;").unwrap();
+        match &program[0] {
+            Stmt::Let { type_annotation, .. } => {
+                assert_eq!(type_annotation, &Some(Type::optional(Type::String)));
+            }
+            other => panic!("unexpected statement {:?}", other),
+        }
+        match &program[1] {
+            Stmt::Let { type_annotation: Some(ty), .. } => {
+                assert!(ty.is_nullable());
+                assert_eq!(ty.to_string(), "number | nil");
+            }
+            other => panic!("unexpected statement {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_possibly_nil_use_is_flagged() {
+        let found = errors("let score: number? = nil;\nlet total = score + 1;");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].line, Some(2));
+        assert!(found[0].message.contains("`score` may be nil"));
+    }
+
+    #[test]
+    fn test_nil_test_narrows() {
+        assert!(errors(r#"
+            let score: number? = nil;
+            if (score != nil) { score + 1; }
+            if (score == nil) { 0; } else { score * 2; }
+            score != nil and score > 0.5;
+        "#).is_empty());
+    }
+
+    #[test]
+    fn test_early_return_narrows() {
+        assert!(errors(r#"
+            fn boost(score: number?) -> number {
+                if (score == nil) { return 0; }
+                return score + 0.1;
+            }
+        "#).is_empty());
+    }
+
+    #[test]
+    fn test_optional_chaining_result_is_nullable() {
+        let found = errors(r#"
+            let response = { diagnosis: "flu" };
+            let severity = response?.severity;
+            severity * 2;
+        "#);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message.contains("`severity` may be nil"));
+
+        assert!(errors(r#"
+            let response = { diagnosis: "flu" };
+            let severity = response?.severity;
+            if (severity != nil) { severity * 2; }
+        "#).is_empty());
+    }
+
+    #[test]
+    fn test_dot_access_on_optional_is_flagged() {
+        let found = errors("let patient: map? = nil; patient.name;");
+        assert_eq!(found.len(), 1);
+        assert!(errors("let patient: map? = nil; patient?.name;").is_empty());
+    }
+
+    #[test]
+    fn test_nil_assignment_to_non_optional() {
+        assert_eq!(errors("let x: number = nil;").len(), 1);
+        assert_eq!(errors("let x: number = 1; x = nil;").len(), 1);
+        assert_eq!(errors("let x: string;").len(), 1);
+        assert!(errors("let x: string?; x = nil;").is_empty());
+    }
+
+    #[test]
+    fn test_untyped_variables_take_any_value() {
+        assert!(errors(r#"let x = 1; x = "a";"#).is_empty());
+        assert!(errors("let y = []; y = nil;").is_empty());
+
+        // The variable may now hold the new value, so it is checked as such
+        let found = errors("let z = 1; z = nil; z + 1;");
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message.contains("`z` may be nil"), "{}", found[0].message);
+    }
+
+    #[test]
+    fn test_argument_and_return_types() {
+        let found = errors(r#"
+            fn label(name: string) -> string { return name; }
+            let maybe: string? = nil;
+            label(maybe);
+        "#);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message.contains("Argument 1 of `label`"));
+
+        assert_eq!(errors("fn f() -> number { return nil; }").len(), 1);
+        assert!(errors("fn f() -> number? { return nil; }").is_empty());
+    }
+
+    #[test]
+    fn test_uncertain_if_band_coverage() {
+        let complete = "let x = 1 ~> 0.7; uncertain if (x) { 1; } medium { 2; } low { 3; }";
+        assert!(check(&parse(complete).unwrap()).is_empty());
+
+        let found = warnings("let x = 1; uncertain if (x ~> 0.9) { 1; } medium (~> 0.6) { 2; }");
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message.contains("below 0.6"));
+
+        let found = warnings("let x = 1; uncertain if (x) { 1; } low { 2; }");
+        assert!(found.is_empty());
+
+        let spec_form = "let x = 1; uncertain if (x ~> 0.8) { 1; } medium (x ~> 0.5) { 2; } low { 3; }";
+        assert!(check(&parse(spec_form).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_uncertain_if_unreachable_arms() {
+        let found = warnings("let x = 1; uncertain if (x ~> 0.5) { 1; } medium (~> 0.7) { 2; } low { 3; }");
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message.contains("`medium` arm is unreachable"));
+
+        let found = warnings("let x = 1; uncertain if (x ~> 0.8) { 1; } medium (~> 0) { 2; } low { 3; }");
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message.contains("`low` arm is unreachable"));
+    }
+
+    #[test]
+    fn test_match_exhaustiveness() {
+        let found = errors(r#"let x = "flu"; match x { "flu" => 1, "cold" => 2 };"#);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message.contains("Non-exhaustive match"));
+
+        assert!(errors(r#"let x = "flu"; match x { "flu" => 1, _ => 2 };"#).is_empty());
+        assert!(errors("let b = true; match b { true => 1, false => 0 };").is_empty());
+        assert!(errors("let x = 1; match x { n if n > 3 => 1, n => 0 };").is_empty());
+        assert_eq!(errors("let x = 1; match x { n if n > 3 => 1 };").len(), 1);
+    }
+
+    #[test]
+    fn test_match_confidence_bands() {
+        let found = errors("let x = 1; match x { ~> 0.8 => 1, ~> 0.5 => 2 };");
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message.contains("confidence below 0.5"));
+
+        assert!(errors("let x = 1; match x { ~> 0.8 => 1, ~> 0.5 => 2, ~> 0 => 3 };").is_empty());
+    }
+
+    #[test]
+    fn test_match_unreachable_arms() {
+        let found = warnings("let x = 1; match x { _ => 0, 1 => 1 };");
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message.contains("Match arm 2 is unreachable"));
+
+        assert_eq!(warnings("let x = 1; match x { 1 => 1, 1 => 2, _ => 3 };").len(), 1);
+        assert_eq!(warnings("let x = 1; match x { ~> 0.5 => 1, ~> 0.8 => 2, _ => 3 };").len(), 1);
+    }
+
+    #[test]
+    fn test_match_nil_arm_narrows_binding() {
+        let source = "let score: number? = nil; match score { nil => 0, s => s + 1 };";
+        assert!(errors(source).is_empty());
+        let source = "let score: number? = nil; match score { s => s + 1 };";
+        assert_eq!(errors(source).len(), 1);
+    }
+}
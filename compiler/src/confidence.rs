@@ -1,4 +1,51 @@
 use std::collections::HashMap;
+use std::str::FromStr;
+use serde::Deserialize;
+use crate::error::PrismError;
+
+/// How the confidences of values used together combine, e.g. the results
+/// of `async.all`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CombineStrategy {
+    /// As if the values were independent: the product.
+    #[default]
+    Product,
+    /// As confident as the weakest value.
+    Min,
+    Mean,
+}
+
+impl CombineStrategy {
+    /// Combines `confidences`; nothing combines to 1.
+    pub fn combine(self, confidences: impl IntoIterator<Item = f64>) -> f64 {
+        let confidences: Vec<f64> = confidences.into_iter().collect();
+        if confidences.is_empty() {
+            return 1.0;
+        }
+        match self {
+            CombineStrategy::Product => confidences.iter().product(),
+            CombineStrategy::Min => confidences.iter().copied().fold(1.0, f64::min),
+            CombineStrategy::Mean => confidences.iter().sum::<f64>() / confidences.len() as f64,
+        }
+    }
+}
+
+impl FromStr for CombineStrategy {
+    type Err = PrismError;
+
+    fn from_str(name: &str) -> Result<Self, PrismError> {
+        match name {
+            "product" => Ok(CombineStrategy::Product),
+            "min" => Ok(CombineStrategy::Min),
+            "mean" => Ok(CombineStrategy::Mean),
+            _ => Err(PrismError::InvalidArgument(format!(
+                "Unknown confidence strategy '{}'; expected product, min or mean",
+                name
+            ))),
+        }
+    }
+}
 
 pub struct ConfidenceEngine {
     decay_rate: f64,
@@ -21,7 +68,7 @@ impl ConfidenceEngine {
     }
 
     pub fn set(&mut self, key: &str, value: f64) {
-        if value >= 0.0 && value <= 1.0 {
+        if (0.0..=1.0).contains(&value) {
             self.current_values.insert(key.to_string(), value);
         }
     }
@@ -95,6 +142,16 @@ impl ConfidenceEngine {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_combine_strategies() {
+        let confidences = [0.5, 0.8];
+        assert_eq!(CombineStrategy::Product.combine(confidences), 0.4);
+        assert_eq!(CombineStrategy::Min.combine(confidences), 0.5);
+        assert_eq!(CombineStrategy::Mean.combine(confidences), 0.65);
+        assert_eq!(CombineStrategy::Min.combine([]), 1.0);
+        assert_eq!("mean".parse::<CombineStrategy>().unwrap(), CombineStrategy::Mean);
+    }
+
     #[test]
     fn test_confidence_set_get() {
         let mut engine = ConfidenceEngine::new(0.1);
@@ -0,0 +1,315 @@
+//! User and project configuration.
+//!
+//! [`prism::init`](crate::init) reads `~/.config/prism/config.toml` (or
+//! `$XDG_CONFIG_HOME/prism/config.toml`) and then the nearest `.prismrc`
+//! above the working directory, whose settings take precedence. Both are
+//! TOML:
+//!
+//! ```toml
+//! model = "gpt-4o-mini"
+//! provider = "openai"
+//! temperature = 0.2
+//! critic = "gpt-4o"               # verifies answers in `llm.complete_checked`
+//! confidence = "min"              # how confidences combine: product, min or mean
+//! capabilities = ["env", "fs"]    # what the CLI grants scripts; all by default
+//! module_paths = ["lib"]          # where imports are also looked up
+//!
+//! [profiles.cheap]                # chosen with `prism run --profile cheap`
+//! model = "gpt-4o-mini"           # or `llm.use_profile("cheap")`
+//! budget = 0.50                   # most USD a run may spend on LLM calls
+//!
+//! [profiles.precise]
+//! temperature = 0.0
+//! top_p = 0.9
+//! stop = ["\n\n"]
+//!
+//! [profiles.offline]
+//! provider = "local"
+//! model = "llama3"
+//!
+//! [providers.openai]              # see `llm::queue`
+//! max_in_flight = 4
+//! requests_per_minute = 60
+//! ```
+//!
+//! A profile changes only the settings it lists. Relative module paths are
+//! relative to the file that lists them.
+//! Interpreters created afterwards start from these settings, and CLI flags
+//! override them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use serde::Deserialize;
+use crate::capabilities::{Capabilities, Capability};
+use crate::confidence::CombineStrategy;
+use crate::error::{PrismError, Result};
+use crate::llm::queue::ProviderLimits;
+use crate::llm::ModelConfig;
+
+/// The name of project configuration files.
+pub const PROJECT_FILE: &str = ".prismrc";
+
+static GLOBAL: OnceLock<Config> = OnceLock::new();
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub model: Option<String>,
+    pub provider: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<usize>,
+    pub critic: Option<String>,
+    pub confidence: Option<CombineStrategy>,
+    pub capabilities: Option<Vec<String>>,
+    pub module_paths: Vec<PathBuf>,
+    pub profiles: BTreeMap<String, Profile>,
+    /// Request limits by provider.
+    pub providers: BTreeMap<String, ProviderLimits>,
+}
+
+/// A named set of model settings on top of the configured ones.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub model: Option<String>,
+    pub provider: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<usize>,
+    /// Most USD the LLM calls of a run may cost while it is in use.
+    pub budget: Option<f64>,
+    /// The model that verifies answers in `llm.complete_checked`.
+    pub critic: Option<String>,
+    pub top_p: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    /// Sequences that end a completion.
+    pub stop: Option<Vec<String>>,
+}
+
+impl Profile {
+    fn overridden_by(self, other: Profile) -> Profile {
+        Profile {
+            model: other.model.or(self.model),
+            provider: other.provider.or(self.provider),
+            temperature: other.temperature.or(self.temperature),
+            max_tokens: other.max_tokens.or(self.max_tokens),
+            budget: other.budget.or(self.budget),
+            critic: other.critic.or(self.critic),
+            top_p: other.top_p.or(self.top_p),
+            presence_penalty: other.presence_penalty.or(self.presence_penalty),
+            frequency_penalty: other.frequency_penalty.or(self.frequency_penalty),
+            stop: other.stop.or(self.stop),
+        }
+    }
+
+    /// `config` with the settings this profile lists.
+    pub fn apply(&self, mut config: ModelConfig) -> ModelConfig {
+        if let Some(model) = &self.model {
+            config.model = model.clone();
+        }
+        if let Some(provider) = &self.provider {
+            config.provider = provider.clone();
+        }
+        if let Some(temperature) = self.temperature {
+            config.temperature = temperature;
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            config.max_tokens = max_tokens;
+        }
+        if self.budget.is_some() {
+            config.budget = self.budget;
+        }
+        if let Some(critic) = &self.critic {
+            config.critic = Some(critic.clone());
+        }
+        if self.top_p.is_some() {
+            config.top_p = self.top_p;
+        }
+        if self.presence_penalty.is_some() {
+            config.presence_penalty = self.presence_penalty;
+        }
+        if self.frequency_penalty.is_some() {
+            config.frequency_penalty = self.frequency_penalty;
+        }
+        if let Some(stop) = &self.stop {
+            config.stop = stop.clone();
+        }
+        config
+    }
+}
+
+impl Config {
+    /// Parses the configuration in `text`, read from a file in `dir`.
+    pub fn parse(text: &str, dir: &Path) -> Result<Self> {
+        let mut config: Config = toml::from_str(text).map_err(|err| PrismError::InvalidArgument(err.to_string()))?;
+        for name in config.capabilities.iter().flatten() {
+            name.parse::<Capability>()?;
+        }
+        let home = std::env::var_os("HOME").map(PathBuf::from);
+        config.module_paths = config
+            .module_paths
+            .into_iter()
+            .map(|path| match (path.strip_prefix("~"), &home) {
+                (Ok(rest), Some(home)) => home.join(rest),
+                _ => dir.join(path),
+            })
+            .collect();
+        Ok(config)
+    }
+
+    /// Reads the file at `path`, or returns `None` when there is none.
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        Self::parse(&text, dir)
+            .map(Some)
+            .map_err(|err| err.map_message(|msg| format!("{}: {}", path.display(), msg)))
+    }
+
+    /// The user's configuration file, whether or not it exists.
+    pub fn user_path() -> Option<PathBuf> {
+        let dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(dir.join("prism").join("config.toml"))
+    }
+
+    /// The nearest `.prismrc` in `dir` or above it.
+    pub fn project_path(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors().map(|dir| dir.join(PROJECT_FILE)).find(|path| path.is_file())
+    }
+
+    /// The user's configuration overridden by the project's, for the
+    /// working directory.
+    pub fn load() -> Result<Self> {
+        let mut config = Config::default();
+        if let Some(user) = Self::user_path() {
+            config = config.overridden_by(Self::read(&user)?.unwrap_or_default());
+        }
+        let cwd = std::env::current_dir()?;
+        if let Some(project) = Self::project_path(&cwd) {
+            config = config.overridden_by(Self::read(&project)?.unwrap_or_default());
+        }
+        Ok(config)
+    }
+
+    /// These settings with those `other` sets taking precedence; module
+    /// paths of `other` are searched first, and profiles of the same name
+    /// are merged.
+    pub fn overridden_by(self, other: Config) -> Config {
+        let mut module_paths = other.module_paths;
+        module_paths.extend(self.module_paths);
+        let mut profiles = self.profiles;
+        for (name, profile) in other.profiles {
+            let merged = profiles.remove(&name).unwrap_or_default().overridden_by(profile);
+            profiles.insert(name, merged);
+        }
+        let mut providers = self.providers;
+        providers.extend(other.providers);
+        Config {
+            model: other.model.or(self.model),
+            provider: other.provider.or(self.provider),
+            temperature: other.temperature.or(self.temperature),
+            max_tokens: other.max_tokens.or(self.max_tokens),
+            critic: other.critic.or(self.critic),
+            confidence: other.confidence.or(self.confidence),
+            capabilities: other.capabilities.or(self.capabilities),
+            module_paths,
+            profiles,
+            providers,
+        }
+    }
+
+    pub fn model_config(&self) -> ModelConfig {
+        let settings = Profile {
+            model: self.model.clone(),
+            provider: self.provider.clone(),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            budget: None,
+            critic: self.critic.clone(),
+            ..Profile::default()
+        };
+        settings.apply(ModelConfig::default())
+    }
+
+    /// The model configuration of each profile.
+    pub fn profiles(&self) -> HashMap<String, ModelConfig> {
+        let base = self.model_config();
+        self.profiles.iter().map(|(name, profile)| (name.clone(), profile.apply(base.clone()))).collect()
+    }
+
+    /// What the CLI grants scripts: the configured capabilities, or all.
+    pub fn granted(&self) -> Capabilities {
+        match &self.capabilities {
+            None => Capabilities::all(),
+            Some(names) => names
+                .iter()
+                .filter_map(|name| name.parse().ok())
+                .fold(Capabilities::none(), Capabilities::grant),
+        }
+    }
+}
+
+/// The configuration [`prism::init`](crate::init) loaded, or the defaults.
+pub fn global() -> &'static Config {
+    GLOBAL.get_or_init(Config::default)
+}
+
+/// Makes `config` the one new interpreters start from. Only the first call
+/// takes effect, before any interpreter has read it.
+pub fn set_global(config: Config) -> bool {
+    GLOBAL.set(config).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_settings_override_the_user_s() {
+        let user = Config::parse(
+            "model = \"gpt-4\"\ntemperature = 0.3\nconfidence = \"min\"\nmodule_paths = [\"shared\"]\n",
+            Path::new("/home/me/.config/prism"),
+        )
+        .unwrap();
+        let project = Config::parse("model = \"gpt-4o-mini\"\ncapabilities = [\"env\"]\nmodule_paths = [\"lib\"]\n", Path::new("/work"))
+            .unwrap();
+        let config = user.overridden_by(project);
+
+        let model = config.model_config();
+        assert_eq!((model.model.as_str(), model.temperature), ("gpt-4o-mini", 0.3));
+        assert_eq!(config.confidence, Some(CombineStrategy::Min));
+        assert_eq!(config.module_paths, [PathBuf::from("/work/lib"), PathBuf::from("/home/me/.config/prism/shared")]);
+        assert!(config.granted().allows(Capability::Env));
+        assert!(!config.granted().allows(Capability::Net));
+        assert_eq!(Config::default().granted(), Capabilities::all());
+    }
+
+    #[test]
+    fn test_profiles_change_only_what_they_list() {
+        let user = Config::parse("temperature = 0.3\n[profiles.cheap]\nmodel = \"gpt-4o-mini\"\n", Path::new(".")).unwrap();
+        let project = Config::parse("[profiles.cheap]\nbudget = 0.5\n[profiles.offline]\nprovider = \"local\"\n", Path::new("."))
+            .unwrap();
+        let profiles = user.overridden_by(project).profiles();
+
+        let cheap = &profiles["cheap"];
+        assert_eq!((cheap.model.as_str(), cheap.temperature, cheap.budget), ("gpt-4o-mini", 0.3, Some(0.5)));
+        let offline = &profiles["offline"];
+        assert_eq!((offline.provider.as_str(), offline.model.as_str(), offline.budget), ("local", "gpt-4", None));
+
+        let limits = Config::parse("[providers.openai]\nmax_in_flight = 4\n", Path::new(".")).unwrap();
+        assert_eq!(limits.providers["openai"], ProviderLimits { max_in_flight: Some(4), requests_per_minute: None });
+    }
+
+    #[test]
+    fn test_invalid_settings_are_reported() {
+        assert!(Config::parse("modle = \"gpt-4\"", Path::new(".")).unwrap_err().to_string().contains("modle"));
+        assert!(Config::parse("capabilities = [\"root\"]", Path::new(".")).unwrap_err().to_string().contains("root"));
+        assert!(Config::parse("confidence = \"max\"", Path::new(".")).is_err());
+    }
+}
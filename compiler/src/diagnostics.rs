@@ -0,0 +1,88 @@
+use std::fmt;
+
+use crate::error::PrismError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found by a static pass before the program runs, or a runtime
+/// error as it is reported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub line: Option<usize>,
+    /// The file the line is in, when it is not the program being checked;
+    /// see [`crate::source_map`].
+    pub file: Option<String>,
+    /// Lines shown under the message, e.g. the frames a runtime error
+    /// passed through.
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            line: None,
+            file: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.into(),
+            line: None,
+            file: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn at_line(mut self, line: Option<usize>) -> Self {
+        self.line = line;
+        self
+    }
+
+    pub fn in_file(mut self, file: Option<String>) -> Self {
+        self.file = file;
+        self
+    }
+
+    pub fn with_notes(mut self, notes: Vec<String>) -> Self {
+        self.notes = notes;
+        self
+    }
+
+    /// A runtime error, with the frames it was traced through as notes.
+    pub fn from_error(err: &PrismError) -> Self {
+        match err {
+            PrismError::Traced(trace) => Self::error(trace.error.to_string()).with_notes(trace.lines()),
+            err => Self::error(err.to_string()),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match (self.line, &self.file) {
+            (Some(line), Some(file)) => write!(f, "{} at line {} of {}: {}", label, line, file, self.message),
+            (Some(line), None) => write!(f, "{} at line {}: {}", label, line, self.message),
+            (None, _) => write!(f, "{}: {}", label, self.message),
+        }?;
+        self.notes.iter().try_for_each(|note| write!(f, "\n  {}", note))
+    }
+}
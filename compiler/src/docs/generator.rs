@@ -0,0 +1,218 @@
+//! `prism doc`: API documentation for Prism source files.
+//!
+//! A file's public API is its top-level functions, except those whose name
+//! starts with `_`. `//!` comments at the start of a file describe the
+//! module and `///` comments directly above a function describe the
+//! function; both are markdown. Signatures keep their type annotations,
+//! `async` and declared confidence, as written, under their `@cfg`
+//! annotations.
+
+use crate::ast::{function_header, Stmt};
+use crate::error::Result;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::token::TokenKind;
+
+/// The documentation of one source file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleDoc {
+    pub name: String,
+    pub summary: String,
+    pub functions: Vec<FunctionEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionEntry {
+    pub name: String,
+    /// The declaration as written, e.g. `fn triage(case: map) -> string ~> 0.8`.
+    pub signature: String,
+    pub doc: String,
+    pub line: usize,
+}
+
+/// Collects the documentation of `source`, whose module is called `name`.
+pub fn document(name: &str, source: &str) -> Result<ModuleDoc> {
+    let tokens = Lexer::new(source).scan_tokens()?;
+    // Lines of the `fn` keywords outside any braces, in source order
+    let mut depth = 0usize;
+    let mut lines = Vec::new();
+    for token in &tokens {
+        match token.kind {
+            TokenKind::LeftBrace => depth += 1,
+            TokenKind::RightBrace => depth = depth.saturating_sub(1),
+            TokenKind::Fun if depth == 0 => lines.push(token.line),
+            _ => {}
+        }
+    }
+    let program = Parser::new(tokens).parse()?;
+
+    let source_lines: Vec<&str> = source.lines().collect();
+    let functions = program
+        .iter()
+        .map(|stmt| annotated(stmt, Vec::new()))
+        .filter(|(stmt, _)| matches!(stmt, Stmt::Function { .. }))
+        .zip(lines)
+        .filter_map(|((stmt, annotations), line)| {
+            let Stmt::Function { name, .. } = stmt else { return None };
+            let mut signature = annotations.join("\n");
+            if !signature.is_empty() {
+                signature.push('\n');
+            }
+            signature.push_str(&function_header(stmt).expect("a function"));
+            (!name.starts_with('_')).then(|| FunctionEntry {
+                name: name.clone(),
+                signature,
+                doc: doc_comment_above(&source_lines, line),
+                line,
+            })
+        })
+        .collect();
+
+    let summary = comment_text(source_lines.iter().map(|line| line.trim()).take_while(|line| line.starts_with("//!")), "//!");
+    Ok(ModuleDoc { name: name.to_string(), summary, functions })
+}
+
+/// The statement under any `@cfg` annotations, and the annotations.
+fn annotated(stmt: &Stmt, mut annotations: Vec<String>) -> (&Stmt, Vec<String>) {
+    match stmt {
+        Stmt::Cfg { predicate, body } => {
+            annotations.push(format!("@cfg({})", predicate));
+            annotated(body, annotations)
+        }
+        stmt => (stmt, annotations),
+    }
+}
+
+/// The `///` lines immediately above the 1-based `line`, skipping the
+/// annotations between them and the function.
+fn doc_comment_above(lines: &[&str], line: usize) -> String {
+    let mut above = &lines[..line.saturating_sub(1).min(lines.len())];
+    while above.last().is_some_and(|line| line.trim().starts_with('@')) {
+        above = &above[..above.len() - 1];
+    }
+    let start = above.iter().rposition(|line| !line.trim().starts_with("///")).map_or(0, |i| i + 1);
+    comment_text(above[start..].iter().map(|line| line.trim()), "///")
+}
+
+fn comment_text<'a>(lines: impl Iterator<Item = &'a str>, marker: &str) -> String {
+    let lines: Vec<&str> = lines
+        .map(|line| {
+            let text = &line[marker.len()..];
+            text.strip_prefix(' ').unwrap_or(text)
+        })
+        .collect();
+    lines.join("\n").trim().to_string()
+}
+
+/// One markdown document with a section per module.
+pub fn markdown(modules: &[ModuleDoc]) -> String {
+    let mut out = String::new();
+    for module in modules {
+        out.push_str(&format!("# {}\n\n", module.name));
+        if !module.summary.is_empty() {
+            out.push_str(&format!("{}\n\n", module.summary));
+        }
+        for function in &module.functions {
+            out.push_str(&format!("## {}\n\n```prism\n{}\n```\n\n", function.name, function.signature));
+            if !function.doc.is_empty() {
+                out.push_str(&format!("{}\n\n", function.doc));
+            }
+        }
+    }
+    out
+}
+
+/// A standalone HTML page with a table of contents. Doc comments are shown
+/// as preformatted text rather than rendered as markdown.
+pub fn html(modules: &[ModuleDoc]) -> String {
+    let mut contents = String::new();
+    let mut body = String::new();
+    for module in modules {
+        let id = escape(&module.name);
+        contents.push_str(&format!("<li><a href=\"#{}\">{}</a><ul>\n", id, id));
+        body.push_str(&format!("<section id=\"{}\">\n<h1>{}</h1>\n", id, id));
+        if !module.summary.is_empty() {
+            body.push_str(&format!("<pre class=\"doc\">{}</pre>\n", escape(&module.summary)));
+        }
+        for function in &module.functions {
+            let anchor = format!("{}.{}", id, escape(&function.name));
+            contents.push_str(&format!("<li><a href=\"#{}\">{}</a></li>\n", anchor, escape(&function.name)));
+            body.push_str(&format!(
+                "<h2 id=\"{}\">{}</h2>\n<pre><code>{}</code></pre>\n",
+                anchor,
+                escape(&function.name),
+                escape(&function.signature)
+            ));
+            if !function.doc.is_empty() {
+                body.push_str(&format!("<pre class=\"doc\">{}</pre>\n", escape(&function.doc)));
+            }
+        }
+        contents.push_str("</ul></li>\n");
+        body.push_str("</section>\n");
+    }
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>API documentation</title>\n\
+         <style>body {{ font-family: sans-serif; max-width: 50em; margin: auto; }} \
+         pre.doc {{ white-space: pre-wrap; font-family: inherit; }}</style>\n</head>\n<body>\n\
+         <nav><ul>\n{}</ul></nav>\n{}</body>\n</html>\n",
+        contents, body
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"//! Triage helpers.
+//! Scores are `0..1`.
+
+/// Classifies a case by temperature.
+///
+/// Returns `"urgent"` above 39 degrees.
+fn triage(case: map, temp: number) -> string ~> 0.8 {
+    fn inner() { return 1; }
+    if (temp > 39) { return "urgent"; }
+    return "routine";
+}
+
+// An ordinary comment is not documentation
+fn score(case) { return { value: 0.5 }; }
+
+fn _helper() { return nil; }
+
+/// Saves to the browser.
+@cfg("wasm")
+fn save(report) { return nil; }
+"#;
+
+    #[test]
+    fn test_document_collects_public_functions() {
+        let module = document("triage", SOURCE).unwrap();
+        assert_eq!(module.summary, "Triage helpers.\nScores are `0..1`.");
+        let names: Vec<&str> = module.functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["triage", "score", "save"]);
+        let triage = &module.functions[0];
+        assert_eq!(triage.signature, "fn triage(case: map, temp: number) -> string ~> 0.8");
+        assert_eq!(triage.doc, "Classifies a case by temperature.\n\nReturns `\"urgent\"` above 39 degrees.");
+        assert_eq!(triage.line, 7);
+        assert_eq!(module.functions[1].doc, "");
+        assert_eq!(module.functions[2].signature, "@cfg(\"wasm\")\nfn save(report)");
+        assert_eq!(module.functions[2].doc, "Saves to the browser.");
+    }
+
+    #[test]
+    fn test_markdown_and_html_output() {
+        let modules = [document("triage", SOURCE).unwrap()];
+        let markdown = markdown(&modules);
+        assert!(markdown.starts_with("# triage\n\nTriage helpers.\nScores are `0..1`.\n\n## triage\n\n```prism\n"));
+        assert!(markdown.contains("## score\n\n```prism\nfn score(case)\n```\n\n"));
+
+        let html = html(&modules);
+        assert!(html.contains("<li><a href=\"#triage.score\">score</a></li>"));
+        assert!(html.contains("<pre class=\"doc\">Classifies a case by temperature.\n\nReturns `&quot;urgent&quot;` above 39 degrees.</pre>"));
+    }
+}
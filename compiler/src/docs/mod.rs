@@ -0,0 +1,166 @@
+//! Documentation of functions, recorded when modules export them (see
+//! [`Module::export_documented`](crate::module::Module::export_documented))
+//! and read back by the `doc` and `signature` builtins and the REPL's
+//! `:doc` command. Documentation of Prism source files is generated by
+//! [`generator`].
+
+pub mod generator;
+
+use std::sync::Arc;
+use crate::value::{Value, ValueKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDoc {
+    /// Parameter names in order; a trailing `...` marks a variadic one.
+    pub params: Vec<String>,
+    pub summary: String,
+    /// Descriptions of the parameters that need one, by name.
+    pub param_docs: Vec<(String, String)>,
+    /// A short Prism snippet showing a typical call.
+    pub example: Option<String>,
+}
+
+impl FunctionDoc {
+    pub fn new(params: &[&str], summary: &str) -> Self {
+        FunctionDoc {
+            params: params.iter().map(|param| param.to_string()).collect(),
+            summary: summary.to_string(),
+            param_docs: Vec::new(),
+            example: None,
+        }
+    }
+
+    pub fn with_param(mut self, name: &str, description: &str) -> Self {
+        debug_assert!(self.params.iter().any(|param| param.trim_end_matches("...") == name), "no parameter {}", name);
+        self.param_docs.push((name.to_string(), description.to_string()));
+        self
+    }
+
+    pub fn with_example(mut self, source: &str) -> Self {
+        self.example = Some(source.to_string());
+        self
+    }
+}
+
+/// A function as found among the globals: the name to call it by and its
+/// documentation, if it has any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Documented {
+    pub name: String,
+    pub doc: Option<FunctionDoc>,
+}
+
+/// Finds `function` among `globals`, either directly (`print`) or exported
+/// by a module (`utils.timeout`). Functions are matched by identity, so two
+/// natives that are both called `run` are told apart.
+pub fn find(globals: &[(String, Value)], function: &Value) -> Option<Documented> {
+    let mut direct = None;
+    let mut exported = None;
+    for (name, value) in globals {
+        match &value.kind {
+            ValueKind::Module(module) => {
+                let module = module.read();
+                if exported.is_none() {
+                    exported = module
+                        .exports()
+                        .find(|(_, export)| same_function(export, function))
+                        .map(|(export, _)| (format!("{}.{}", name, export), module.doc(export).cloned()));
+                }
+            }
+            _ if direct.is_none() && same_function(value, function) => direct = Some(name.clone()),
+            _ => {}
+        }
+    }
+    match (direct, exported) {
+        // Prelude functions are documented by the module they come from
+        (Some(name), Some((_, doc))) => Some(Documented { name, doc }),
+        (Some(name), None) => Some(Documented { name, doc: None }),
+        (None, Some((name, doc))) => Some(Documented { name, doc }),
+        (None, None) => None,
+    }
+}
+
+/// `name(params)` for a function value; natives without documentation show
+/// a `_` for each parameter.
+pub fn signature(globals: &[(String, Value)], function: &Value) -> Option<String> {
+    let found = find(globals, function);
+    let (name, params) = match &function.kind {
+        ValueKind::Function { name, params, .. } => (name.clone(), params.clone()),
+        ValueKind::NativeFunction { name, arity, .. } | ValueKind::AsyncNativeFunction { name, arity, .. } => {
+            match found {
+                Some(Documented { name, doc: Some(doc) }) => (name, doc.params),
+                Some(Documented { name, doc: None }) => (name, vec!["_".to_string(); *arity]),
+                None => (name.clone(), vec!["_".to_string(); *arity]),
+            }
+        }
+        _ => return None,
+    };
+    Some(format!("{}({})", name, params.join(", ")))
+}
+
+/// The signature followed by the indented summary, parameters and example,
+/// as `:doc` shows it.
+pub fn describe(globals: &[(String, Value)], function: &Value) -> Option<String> {
+    let mut description = signature(globals, function)?;
+    let Some(doc) = find(globals, function).and_then(|found| found.doc) else {
+        return Some(description);
+    };
+    description.push_str(&format!("\n    {}", doc.summary));
+    for (name, param) in &doc.param_docs {
+        description.push_str(&format!("\n    {}: {}", name, param));
+    }
+    if let Some(example) = &doc.example {
+        description.push_str("\n\n    Example:");
+        for line in example.lines() {
+            description.push_str(&format!("\n        {}", line));
+        }
+    }
+    Some(description)
+}
+
+fn same_function(a: &Value, b: &Value) -> bool {
+    match (&a.kind, &b.kind) {
+        (ValueKind::Function { body: a, .. }, ValueKind::Function { body: b, .. }) => Arc::ptr_eq(a, b),
+        (ValueKind::NativeFunction { handler: a, .. }, ValueKind::NativeFunction { handler: b, .. }) => {
+            Arc::ptr_eq(a, b)
+        }
+        (ValueKind::AsyncNativeFunction { handler: a, .. }, ValueKind::AsyncNativeFunction { handler: b, .. }) => {
+            Arc::ptr_eq(a, b)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn test_documented_examples_parse() {
+        for (name, module) in crate::stdlib::init_stdlib().unwrap() {
+            let ValueKind::Module(module) = &module.kind else { continue };
+            let module = module.read();
+            for (export, _) in module.exports() {
+                if let Some(example) = module.doc(export).and_then(|doc| doc.example.as_deref()) {
+                    if let Err(err) = crate::parser::parse(example) {
+                        panic!("example of {}.{} does not parse: {}", name, export, err);
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_describe_shows_parameters_and_example() {
+        let mut interpreter = Interpreter::new();
+        let interval = interpreter.evaluate("utils.interval;".to_string()).await.unwrap();
+        let description = describe(&interpreter.global_values(), &interval).unwrap();
+        assert_eq!(
+            description,
+            "utils.interval(fn, secs, times)\n    Calls fn every secs seconds and returns how often it ran.\n    \
+             fn: returning false stops the interval\n    times: the most runs, or nil for no limit\n\n    \
+             Example:\n        utils.interval(poll, 60, 10);"
+        );
+    }
+}
@@ -1,58 +1,477 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
 use parking_lot::RwLock;
 use crate::error::{PrismError, Result};
-use crate::value::Value;
+use crate::value::{Value, ValueKind};
 
+/// The environment a function closes over. A function stored in the
+/// environment it closes over, as every named function is, holds it weakly
+/// so the two do not keep each other alive; reading it back out makes the
+/// reference strong again.
+#[derive(Debug, Clone)]
+pub enum Closure {
+    Strong(Arc<Environment>),
+    Weak(Weak<Environment>),
+}
+
+impl Closure {
+    pub fn new(environment: &Arc<Environment>) -> Self {
+        Closure::Strong(Arc::clone(environment))
+    }
+
+    /// The environment, unless it is gone.
+    pub fn environment(&self) -> Option<Arc<Environment>> {
+        match self {
+            Closure::Strong(environment) => Some(Arc::clone(environment)),
+            Closure::Weak(environment) => environment.upgrade(),
+        }
+    }
+}
+
+/// A scope shared between tasks: the globals, or the scope of a call or
+/// block whose locals functions close over.
+///
+/// Other locals live in the [`Frame`] of the call that defines them and are
+/// read without locks; only those a closure captures move here, since the
+/// closure may run on another task. A scope's parent never changes, so
+/// reaching the scope a captured local lives in takes no locks. Each read
+/// or write then locks only that one scope, briefly and never while holding
+/// another, so a native that re-enters the interpreter cannot deadlock on a
+/// variable.
 #[derive(Debug)]
 pub struct Environment {
-    values: HashMap<String, Value>,
-    enclosing: Option<Arc<RwLock<Environment>>>,
+    /// Variables by name: the globals, which the REPL, imports and natives
+    /// add at runtime.
+    values: RwLock<HashMap<String, Value>>,
+    /// Captured locals, at their position in the scope that declared them.
+    slots: RwLock<Vec<Value>>,
+    enclosing: Option<Arc<Environment>>,
+    /// Globals programs may not redefine or assign; see [`freeze`](Self::freeze).
+    frozen: HashSet<String>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Environment {
     pub fn new() -> Self {
+        Self::with_values(Vec::new())
+    }
+
+    /// A global environment holding `values`.
+    pub fn with_values(values: impl IntoIterator<Item = (String, Value)>) -> Self {
         Self {
-            values: HashMap::new(),
+            values: RwLock::new(values.into_iter().collect()),
+            slots: RwLock::new(Vec::new()),
             enclosing: None,
+            frozen: HashSet::new(),
         }
     }
 
-    pub fn with_enclosing(enclosing: Arc<RwLock<Environment>>) -> Self {
-        Self {
-            values: HashMap::new(),
+    /// A scope inside `enclosing`, starting with `slots` as its locals.
+    pub fn with_enclosing(enclosing: Arc<Environment>, slots: Vec<Value>) -> Self {
+        let environment = Self {
+            values: RwLock::new(HashMap::new()),
+            slots: RwLock::new(Vec::with_capacity(slots.len())),
             enclosing: Some(enclosing),
+            frozen: HashSet::new(),
+        };
+        for value in slots {
+            environment.define_slot(value);
+        }
+        environment
+    }
+
+    /// Makes the variables defined so far read-only, e.g. the standard
+    /// library and prelude an embedder does not want scripts to replace.
+    pub fn freeze(mut self) -> Self {
+        self.frozen = self.values.get_mut().keys().cloned().collect();
+        self
+    }
+
+    pub fn is_frozen(&self, name: &str) -> bool {
+        self.frozen.contains(name)
+    }
+
+    fn check_frozen(&self, name: &str) -> Result<()> {
+        if self.is_frozen(name) {
+            return Err(PrismError::InvalidOperation(format!("{} is built in and cannot be redefined", name)));
+        }
+        Ok(())
+    }
+
+    pub fn get_enclosing(&self) -> Option<&Arc<Environment>> {
+        self.enclosing.as_ref()
+    }
+
+    /// The outermost scope, which holds the globals.
+    pub fn root(self: &Arc<Self>) -> Arc<Environment> {
+        let mut env = self;
+        while let Some(enclosing) = &env.enclosing {
+            env = enclosing;
+        }
+        Arc::clone(env)
+    }
+
+    /// The globals, without taking another reference to them.
+    pub fn globals(&self) -> &Environment {
+        let mut env = self;
+        while let Some(enclosing) = &env.enclosing {
+            env = enclosing;
+        }
+        env
+    }
+
+    pub fn define(&self, name: String, value: Value) -> Result<()> {
+        self.check_frozen(&name)?;
+        let value = self.store(value);
+        self.values.write().insert(name, value);
+        Ok(())
+    }
+
+    /// Drops every value, and with them the references closures hold.
+    pub fn clear(&self) {
+        // Taken out first, so values dropping cannot find the scope locked
+        let values = std::mem::take(&mut *self.values.write());
+        let slots = std::mem::take(&mut *self.slots.write());
+
+        // The scopes the dropped functions closed over may be left alive
+        // only by their own locals; see `release`
+        let mut closures = Vec::new();
+        values.values().chain(&slots).for_each(|value| closures_in(value, &mut closures));
+        let mut seen = HashSet::new();
+        let mut scopes = Vec::new();
+        for closure in closures {
+            let mut environment = closure.environment();
+            while let Some(current) = environment {
+                if current.is_global() || !seen.insert(Arc::as_ptr(&current)) {
+                    break;
+                }
+                scopes.push(Arc::downgrade(&current));
+                environment = current.enclosing.clone();
+            }
+        }
+        drop((values, slots));
+        for scope in scopes {
+            if let Some(scope) = scope.upgrade() {
+                Environment::release(scope);
+            }
         }
     }
 
-    pub fn get_enclosing(&self) -> Option<Arc<RwLock<Environment>>> {
-        self.enclosing.clone()
+    pub fn is_global(&self) -> bool {
+        self.enclosing.is_none()
+    }
+
+    /// Names defined directly in this environment.
+    pub fn names(&self) -> Vec<String> {
+        self.values.read().keys().cloned().collect()
+    }
+
+    /// The variables defined directly in this environment, with their values.
+    pub fn entries(&self) -> Vec<(String, Value)> {
+        self.values.read().iter().map(|(name, value)| (name.clone(), load(value))).collect()
     }
 
-    pub fn define(&mut self, name: String, value: Value) -> Result<()> {
-        self.values.insert(name, value);
+    /// Defines the next local slot; locals are declared in resolver order.
+    pub fn define_slot(&self, value: Value) {
+        let value = self.store(value);
+        self.slots.write().push(value);
+    }
+
+    /// Puts a local captured from a frame at its position in the scope.
+    fn capture_slot(&self, index: usize, value: Value) {
+        let value = self.store(value);
+        let mut slots = self.slots.write();
+        if slots.len() <= index {
+            slots.resize_with(index + 1, || Value::new(ValueKind::Nil));
+        }
+        slots[index] = value;
+    }
+
+    pub fn get_at(&self, depth: usize, index: usize) -> Result<Value> {
+        let slots = self.ancestor(depth)?.slots.read();
+        slots.get(index).map(load).ok_or_else(|| Self::bad_slot(depth, index))
+    }
+
+    pub fn assign_at(&self, depth: usize, index: usize, value: Value) -> Result<()> {
+        let env = self.ancestor(depth)?;
+        let value = env.store(value);
+        let mut slots = env.slots.write();
+        let slot = slots.get_mut(index).ok_or_else(|| Self::bad_slot(depth, index))?;
+        // The old value is dropped after the lock is released
+        let _old = std::mem::replace(slot, value);
+        drop(slots);
         Ok(())
     }
 
+    fn ancestor(&self, depth: usize) -> Result<&Environment> {
+        let mut env = self;
+        for _ in 0..depth {
+            env = env.enclosing.as_deref().ok_or_else(|| Self::bad_slot(depth, 0))?;
+        }
+        Ok(env)
+    }
+
+    /// `value` as this environment keeps it: functions in it that close
+    /// over this very environment only refer to it weakly.
+    fn store(&self, mut value: Value) -> Value {
+        weaken(&mut value, self);
+        value
+    }
+
+    fn bad_slot(depth: usize, index: usize) -> PrismError {
+        PrismError::RuntimeError(format!("No local variable at depth {} slot {}", depth, index))
+    }
+
     pub fn get(&self, name: &str) -> Result<Value> {
-        if let Some(value) = self.values.get(name) {
-            Ok(value.clone())
-        } else if let Some(enclosing) = &self.enclosing {
-            enclosing.read().get(name)
-        } else {
-            Err(PrismError::UndefinedVariable(name.to_string()))
+        let mut env = Some(self);
+        while let Some(scope) = env {
+            if let Some(value) = scope.values.read().get(name) {
+                return Ok(load(value));
+            }
+            env = scope.enclosing.as_deref();
+        }
+        Err(PrismError::UndefinedVariable(name.to_string()))
+    }
+
+    /// Frees a scope that has been left, along with the scopes inside it,
+    /// if nothing but the values stored in them keeps them alive.
+    ///
+    /// That happens when a function defined in a nested loop or block is
+    /// kept in a local of the scope: it closes over the inner block, which
+    /// [`weaken`] leaves strong so the function can outlive that block, and
+    /// the block refers back to the scope. `scope` must be the caller's
+    /// only handle on it.
+    pub fn release(scope: Arc<Environment>) {
+        if Arc::strong_count(&scope) == 1 {
+            return;
+        }
+        // The scope and those inside it that stored functions close over,
+        // with the references to each found among them
+        let mut scopes = vec![scope];
+        let mut found: HashMap<*const Environment, usize> = HashMap::new();
+        found.insert(Arc::as_ptr(&scopes[0]), 0);
+        let mut references = Vec::new();
+        let mut next = 0;
+        while next < scopes.len() {
+            let scope = Arc::clone(&scopes[next]);
+            next += 1;
+            if let Some(enclosing) = &scope.enclosing {
+                references.push(Arc::as_ptr(enclosing));
+            }
+            let mut closures = Vec::new();
+            for value in scope.slots.read().iter().chain(scope.values.read().values()) {
+                closures_in(value, &mut closures);
+            }
+            for closure in closures {
+                if let Closure::Strong(environment) = &closure {
+                    references.push(Arc::as_ptr(environment));
+                }
+                // Everything between the environment and a scope found is inside
+                let mut inside = Vec::new();
+                let mut environment = closure.environment();
+                while let Some(current) = environment {
+                    if found.contains_key(&Arc::as_ptr(&current)) {
+                        for scope in inside.drain(..) {
+                            found.insert(Arc::as_ptr(&scope), scopes.len());
+                            scopes.push(scope);
+                        }
+                        break;
+                    }
+                    environment = current.enclosing.clone();
+                    inside.push(current);
+                }
+            }
+        }
+
+        let mut counts = vec![0; scopes.len()];
+        for reference in references {
+            if let Some(&index) = found.get(&reference) {
+                counts[index] += 1;
+            }
+        }
+        // Each scope is also held once by `scopes`
+        let unreachable = scopes.iter().zip(&counts).all(|(scope, count)| Arc::strong_count(scope) == count + 1);
+        if unreachable {
+            scopes.iter().for_each(|scope| scope.clear());
         }
     }
 
-    pub fn assign(&mut self, name: &str, value: Value) -> Result<()> {
-        if self.values.contains_key(name) {
-            self.values.insert(name.to_string(), value);
-            Ok(())
-        } else if let Some(enclosing) = &self.enclosing {
-            enclosing.write().assign(name, value)
-        } else {
-            Err(PrismError::UndefinedVariable(name.to_string()))
+    pub fn assign(&self, name: &str, value: Value) -> Result<()> {
+        let mut env = Some(self);
+        while let Some(scope) = env {
+            let mut values = scope.values.write();
+            if let Some(slot) = values.get_mut(name) {
+                scope.check_frozen(name)?;
+                let _old = std::mem::replace(slot, scope.store(value));
+                drop(values);
+                return Ok(());
+            }
+            drop(values);
+            env = scope.enclosing.as_deref();
         }
+        Err(PrismError::UndefinedVariable(name.to_string()))
+    }
+}
+
+/// The locals of a running call, or of top-level code, by the slots the
+/// resolver hands out. The frame belongs to the call, so reading and writing
+/// them takes no lock; a local a function closes over is moved into an
+/// [`Environment`] for its scope when the function is defined.
+#[derive(Debug, Default)]
+pub struct Frame {
+    locals: Vec<Local>,
+    scopes: Vec<FrameScope>,
+}
+
+#[derive(Debug)]
+enum Local {
+    Value(Value),
+    /// Captured: at this position in the scope's environment.
+    Boxed(Arc<Environment>, usize),
+}
+
+#[derive(Debug)]
+struct FrameScope {
+    /// Where the scope's locals start in the frame.
+    start: usize,
+    /// Holds the locals captured from the scope, once there are any.
+    boxed: Option<Arc<Environment>>,
+}
+
+impl Frame {
+    /// The frame of a call, with the arguments as the locals of its first scope.
+    pub fn new(args: Vec<Value>) -> Self {
+        Self {
+            locals: args.into_iter().map(Local::Value).collect(),
+            scopes: vec![FrameScope { start: 0, boxed: None }],
+        }
+    }
+
+    /// Whether there is a scope to define locals in; top-level code outside
+    /// any block defines globals instead.
+    pub fn in_scope(&self) -> bool {
+        !self.scopes.is_empty()
+    }
+
+    pub fn enter(&mut self) {
+        self.scopes.push(FrameScope { start: self.locals.len(), boxed: None });
+    }
+
+    /// Drops the locals of the innermost scope and releases what the
+    /// functions closing over it shared; see [`Environment::release`].
+    pub fn leave(&mut self) {
+        let Some(scope) = self.scopes.pop() else { return };
+        self.locals.truncate(scope.start);
+        if let Some(boxed) = scope.boxed {
+            Environment::release(boxed);
+        }
+    }
+
+    /// Leaves every scope, as when the call returns.
+    pub fn leave_all(&mut self) {
+        while !self.scopes.is_empty() {
+            self.leave();
+        }
+    }
+
+    /// Defines the next local, returning its slot; locals are declared in
+    /// resolver order.
+    pub fn define(&mut self, value: Value) -> usize {
+        self.locals.push(Local::Value(value));
+        self.locals.len() - 1
+    }
+
+    pub fn get(&self, index: usize) -> Result<Value> {
+        match self.locals.get(index) {
+            Some(Local::Value(value)) => Ok(value.clone()),
+            Some(Local::Boxed(environment, index)) => environment.get_at(0, *index),
+            None => Err(Environment::bad_slot(0, index)),
+        }
+    }
+
+    pub fn set(&mut self, index: usize, value: Value) -> Result<()> {
+        match self.locals.get_mut(index) {
+            Some(Local::Value(local)) => {
+                *local = value;
+                Ok(())
+            },
+            Some(Local::Boxed(environment, index)) => environment.assign_at(0, *index, value),
+            None => Err(Environment::bad_slot(0, index)),
+        }
+    }
+
+    /// The environment for a function defined here that closes over the
+    /// locals at `indices`: they move into environments for their scopes,
+    /// one for each scope of the frame, chained from `enclosing`, the
+    /// environment of the running function.
+    pub fn capture(&mut self, indices: &[usize], enclosing: &Arc<Environment>) -> Arc<Environment> {
+        let mut environment = Arc::clone(enclosing);
+        for position in 0..self.scopes.len() {
+            let start = self.scopes[position].start;
+            let end = self.scopes.get(position + 1).map_or(self.locals.len(), |scope| scope.start);
+            let scope = &mut self.scopes[position];
+            let boxed = scope.boxed.get_or_insert_with(|| Arc::new(Environment::with_enclosing(environment, Vec::new())));
+            environment = Arc::clone(boxed);
+            for index in indices.iter().copied().filter(|index| (start..end).contains(index)) {
+                let local = &mut self.locals[index];
+                if let Local::Value(value) = local {
+                    environment.capture_slot(index - start, std::mem::replace(value, Value::new(ValueKind::Nil)));
+                    *local = Local::Boxed(Arc::clone(&environment), index - start);
+                }
+            }
+        }
+        environment
+    }
+}
+
+fn weaken(value: &mut Value, environment: &Environment) {
+    match &mut value.kind {
+        ValueKind::Function { closure, .. } => {
+            if let Closure::Strong(strong) = closure {
+                if std::ptr::eq(Arc::as_ptr(strong), environment) {
+                    *closure = Closure::Weak(Arc::downgrade(strong));
+                }
+            }
+        }
+        ValueKind::List(items) => items.iter_mut().for_each(|item| weaken(item, environment)),
+        ValueKind::Map(entries) => entries.iter_mut().for_each(|(_, value)| weaken(value, environment)),
+        _ => {}
+    }
+}
+
+/// Collects the closures of the functions in `value`.
+fn closures_in(value: &Value, closures: &mut Vec<Closure>) {
+    match &value.kind {
+        ValueKind::Function { closure, .. } => closures.push(closure.clone()),
+        ValueKind::List(items) => items.iter().for_each(|item| closures_in(item, closures)),
+        ValueKind::Map(entries) => entries.iter().for_each(|(_, value)| closures_in(value, closures)),
+        _ => {}
+    }
+}
+
+/// A stored value as the program sees it, with every closure strong: the
+/// environment it was read from is alive, and the copy may outlive it.
+fn load(value: &Value) -> Value {
+    let mut value = value.clone();
+    strengthen(&mut value);
+    value
+}
+
+fn strengthen(value: &mut Value) {
+    match &mut value.kind {
+        ValueKind::Function { closure, .. } => {
+            if let Some(environment) = closure.environment() {
+                *closure = Closure::Strong(environment);
+            }
+        }
+        ValueKind::List(items) => items.iter_mut().for_each(strengthen),
+        ValueKind::Map(entries) => entries.iter_mut().for_each(|(_, value)| strengthen(value)),
+        _ => {}
     }
 }
 
@@ -63,7 +482,7 @@ mod tests {
 
     #[test]
     fn test_environment() {
-        let mut env = Environment::new();
+        let env = Environment::new();
         env.define("x".to_string(), Value::new(ValueKind::Number(42.0)))
             .unwrap();
         assert_eq!(
@@ -74,13 +493,13 @@ mod tests {
 
     #[test]
     fn test_environment_with_enclosing() {
-        let mut global = Environment::new();
+        let global = Environment::new();
         global
             .define("x".to_string(), Value::new(ValueKind::Number(42.0)))
             .unwrap();
-        let global = Arc::new(RwLock::new(global));
+        let global = Arc::new(global);
 
-        let mut local = Environment::with_enclosing(global);
+        let local = Environment::with_enclosing(global, Vec::new());
         local
             .define("y".to_string(), Value::new(ValueKind::Number(24.0)))
             .unwrap();
@@ -97,7 +516,7 @@ mod tests {
 
     #[test]
     fn test_environment_assign() {
-        let mut env = Environment::new();
+        let env = Environment::new();
         env.define("x".to_string(), Value::new(ValueKind::Number(42.0)))
             .unwrap();
         env.assign("x", Value::new(ValueKind::Number(24.0)))
@@ -110,20 +529,109 @@ mod tests {
 
     #[test]
     fn test_environment_assign_enclosing() {
-        let mut global = Environment::new();
+        let global = Environment::new();
         global
             .define("x".to_string(), Value::new(ValueKind::Number(42.0)))
             .unwrap();
-        let global = Arc::new(RwLock::new(global));
+        let global = Arc::new(global);
 
-        let mut local = Environment::with_enclosing(global.clone());
+        let local = Environment::with_enclosing(global.clone(), Vec::new());
         local
             .assign("x", Value::new(ValueKind::Number(24.0)))
             .unwrap();
 
         assert_eq!(
-            global.read().get("x").unwrap().kind,
+            global.get("x").unwrap().kind,
             ValueKind::Number(24.0)
         );
     }
+
+    #[test]
+    fn test_frozen_globals_cannot_change() {
+        let global = Arc::new(Environment::with_values([("len".to_string(), Value::new(ValueKind::Nil))]).freeze());
+        let local = Environment::with_enclosing(global.clone(), Vec::new());
+        assert!(global.define("len".to_string(), Value::new(ValueKind::Number(1.0))).is_err());
+        assert!(local.assign("len", Value::new(ValueKind::Number(1.0))).is_err());
+        global.define("mine".to_string(), Value::new(ValueKind::Number(1.0))).unwrap();
+        local.assign("mine", Value::new(ValueKind::Number(2.0))).unwrap();
+        assert_eq!(global.get("mine").unwrap().kind, ValueKind::Number(2.0));
+        assert_eq!(global.get("len").unwrap().kind, ValueKind::Nil);
+    }
+
+    #[test]
+    fn test_environment_slots() {
+        let global = Arc::new(Environment::new());
+        let outer = Environment::with_enclosing(global, vec![Value::new(ValueKind::Number(1.0))]);
+        outer.define_slot(Value::new(ValueKind::Number(2.0)));
+        let outer = Arc::new(outer);
+
+        let inner = Environment::with_enclosing(outer.clone(), Vec::new());
+        inner.define_slot(Value::new(ValueKind::Number(3.0)));
+        inner.assign_at(1, 1, Value::new(ValueKind::Number(20.0))).unwrap();
+
+        assert_eq!(inner.get_at(0, 0).unwrap().kind, ValueKind::Number(3.0));
+        assert_eq!(outer.get_at(0, 1).unwrap().kind, ValueKind::Number(20.0));
+        assert!(inner.get_at(2, 0).is_err());
+        assert!(inner.get_at(3, 0).is_err());
+    }
+
+    #[test]
+    fn test_reads_hold_no_lock_on_outer_scopes() {
+        let global = Arc::new(Environment::new());
+        global.define("x".to_string(), Value::new(ValueKind::Number(1.0))).unwrap();
+        let frame = Arc::new(Environment::with_enclosing(global.clone(), vec![Value::new(ValueKind::Nil)]));
+
+        // A writer holding the globals does not block reads of the frame
+        let _writer = global.values.write();
+        assert_eq!(frame.get_at(0, 0).unwrap().kind, ValueKind::Nil);
+        assert!(Arc::ptr_eq(&frame.root(), &global));
+    }
+
+    #[test]
+    fn test_frames_share_only_captured_locals() {
+        let global = Arc::new(Environment::new());
+        let number = |n: f64| Value::new(ValueKind::Number(n));
+        let mut frame = Frame::new(vec![number(1.0)]);
+        frame.enter();
+        assert_eq!(frame.define(number(2.0)), 1);
+
+        let closure = frame.capture(&[0], &global);
+        assert!(Arc::ptr_eq(&closure.root(), &global));
+        frame.set(0, number(10.0)).unwrap();
+        frame.set(1, number(20.0)).unwrap();
+        // The captured local is shared with the closure, one scope out
+        assert_eq!(closure.get_at(1, 0).unwrap().kind, ValueKind::Number(10.0));
+        assert_eq!(frame.get(1).unwrap().kind, ValueKind::Number(20.0));
+
+        frame.leave_all();
+        assert!(frame.get(0).is_err());
+        assert_eq!(closure.get_at(1, 0).unwrap().kind, ValueKind::Number(10.0));
+    }
+
+    #[test]
+    fn test_functions_stored_in_their_closure_do_not_keep_it_alive() {
+        let function = |closure: &Arc<Environment>| {
+            Value::new(ValueKind::Function {
+                name: "f".to_string(),
+                params: Vec::new(),
+                body: Arc::new(crate::ast::Stmt::Block(Vec::new())),
+                sync: Arc::default(),
+                generator: false,
+                closure: Closure::new(closure),
+            })
+        };
+        let env = Arc::new(Environment::new());
+        let stored = function(&env);
+        env.define("f".to_string(), stored.clone()).unwrap();
+        env.define("fs".to_string(), Value::new(ValueKind::List(vec![stored]))).unwrap();
+
+        // Read back out, the function keeps the environment alive again
+        let read = env.get("f").unwrap();
+        let ValueKind::Function { closure: Closure::Strong(_), .. } = read.kind else { panic!("{:?}", read) };
+        let weak = Arc::downgrade(&env);
+        drop(env);
+        assert!(weak.upgrade().is_some());
+        drop(read);
+        assert!(weak.upgrade().is_none());
+    }
 }
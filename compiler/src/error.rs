@@ -8,13 +8,69 @@ pub enum PrismError {
     IO(io::Error),
     ParseError(String),
     TypeError(String),
+    ResolveError(String),
     RuntimeError(String),
+    Cancelled(String),
     Serialization(serde_json::Error),
     ModuleNotFound(String),
     ModuleAlreadyExists(String),
     UndefinedVariable(String),
     InvalidOperation(String),
     InvalidArgument(String),
+    PermissionDenied(String),
+    /// A native function panicked; the panic was contained to the call.
+    NativePanic { function: String, message: String },
+    /// An error with the evaluation context it passed through on the way up.
+    Traced(Box<Trace>),
+}
+
+/// Distinct frames kept from each end of a [`Trace`]; deep recursion
+/// through several functions would otherwise bury the message.
+const KEPT_FRAMES: usize = 8;
+
+/// The "while ..." frames of a runtime error, innermost first, with runs of
+/// the same frame (as in recursion) stored once with their count. Past
+/// `2 * KEPT_FRAMES` runs the middle ones are dropped and counted, so both
+/// where the error happened and where the program started are kept.
+#[derive(Debug)]
+pub struct Trace {
+    pub error: PrismError,
+    frames: Vec<(String, usize)>,
+    omitted: usize,
+}
+
+impl Trace {
+    /// Each distinct frame with how many times in a row it was passed.
+    pub fn frames(&self) -> &[(String, usize)] {
+        &self.frames
+    }
+
+    fn push(&mut self, frame: String) {
+        match self.frames.last_mut() {
+            Some((last, count)) if *last == frame => *count += 1,
+            _ => {
+                if self.frames.len() == 2 * KEPT_FRAMES {
+                    self.omitted += self.frames.remove(KEPT_FRAMES).1;
+                }
+                self.frames.push((frame, 1));
+            },
+        }
+    }
+
+    /// The frames as the lines of a diagnostic.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (index, (frame, count)) in self.frames.iter().enumerate() {
+            if index == KEPT_FRAMES && self.omitted > 0 {
+                lines.push(format!("… {} more frames", self.omitted));
+            }
+            lines.push(format!("while {}", frame));
+            if *count > 1 {
+                lines.push(format!("… {} more times", count - 1));
+            }
+        }
+        lines
+    }
 }
 
 impl From<io::Error> for PrismError {
@@ -35,13 +91,91 @@ impl std::fmt::Display for PrismError {
             PrismError::IO(err) => write!(f, "IO error: {}", err),
             PrismError::ParseError(msg) => write!(f, "Parse error: {}", msg),
             PrismError::TypeError(msg) => write!(f, "Type error: {}", msg),
+            PrismError::ResolveError(msg) => write!(f, "Resolve error: {}", msg),
             PrismError::RuntimeError(msg) => write!(f, "Runtime error: {}", msg),
+            PrismError::Cancelled(reason) => write!(f, "Evaluation cancelled: {}", reason),
             PrismError::Serialization(err) => write!(f, "Serialization error: {}", err),
             PrismError::ModuleNotFound(name) => write!(f, "Module not found: {}", name),
             PrismError::ModuleAlreadyExists(name) => write!(f, "Module already exists: {}", name),
             PrismError::UndefinedVariable(name) => write!(f, "Undefined variable: {}", name),
             PrismError::InvalidOperation(msg) => write!(f, "Invalid operation: {}", msg),
             PrismError::InvalidArgument(msg) => write!(f, "Invalid argument: {}", msg),
+            PrismError::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
+            PrismError::NativePanic { function, message } => {
+                write!(f, "Native function `{}` panicked: {}", function, message)
+            },
+            PrismError::Traced(trace) => {
+                write!(f, "{}", trace.error)?;
+                trace.lines().iter().try_for_each(|line| write!(f, "\n  {}", line))
+            },
+        }
+    }
+}
+
+impl PrismError {
+    /// The variant as a snake_case label, e.g. for metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PrismError::IO(_) => "io",
+            PrismError::ParseError(_) => "parse_error",
+            PrismError::TypeError(_) => "type_error",
+            PrismError::ResolveError(_) => "resolve_error",
+            PrismError::RuntimeError(_) => "runtime_error",
+            PrismError::Cancelled(_) => "cancelled",
+            PrismError::Serialization(_) => "serialization",
+            PrismError::ModuleNotFound(_) => "module_not_found",
+            PrismError::ModuleAlreadyExists(_) => "module_already_exists",
+            PrismError::UndefinedVariable(_) => "undefined_variable",
+            PrismError::InvalidOperation(_) => "invalid_operation",
+            PrismError::InvalidArgument(_) => "invalid_argument",
+            PrismError::PermissionDenied(_) => "permission_denied",
+            PrismError::NativePanic { .. } => "native_panic",
+            PrismError::Traced(trace) => trace.error.kind(),
+        }
+    }
+
+    /// The error without the context it was traced through.
+    pub fn root(&self) -> &PrismError {
+        match self {
+            PrismError::Traced(trace) => &trace.error,
+            err => err,
+        }
+    }
+
+    /// Records that the error passed through `frame` on its way up.
+    /// Cancellation is not a failure of what was being evaluated and is left
+    /// as it is.
+    pub fn in_frame(self, frame: impl FnOnce() -> String) -> Self {
+        let mut trace = match self {
+            err @ PrismError::Cancelled(_) => return err,
+            PrismError::Traced(trace) => trace,
+            error => Box::new(Trace { error, frames: Vec::new(), omitted: 0 }),
+        };
+        trace.push(frame());
+        PrismError::Traced(trace)
+    }
+
+    /// Rewrites the message of errors that carry one, e.g. to redact secrets.
+    /// Wrapped I/O and serialization errors are left untouched.
+    pub fn map_message(self, f: impl FnOnce(String) -> String) -> Self {
+        match self {
+            PrismError::ParseError(msg) => PrismError::ParseError(f(msg)),
+            PrismError::TypeError(msg) => PrismError::TypeError(f(msg)),
+            PrismError::ResolveError(msg) => PrismError::ResolveError(f(msg)),
+            PrismError::RuntimeError(msg) => PrismError::RuntimeError(f(msg)),
+            PrismError::Cancelled(reason) => PrismError::Cancelled(f(reason)),
+            PrismError::ModuleNotFound(name) => PrismError::ModuleNotFound(f(name)),
+            PrismError::ModuleAlreadyExists(name) => PrismError::ModuleAlreadyExists(f(name)),
+            PrismError::UndefinedVariable(name) => PrismError::UndefinedVariable(f(name)),
+            PrismError::InvalidOperation(msg) => PrismError::InvalidOperation(f(msg)),
+            PrismError::InvalidArgument(msg) => PrismError::InvalidArgument(f(msg)),
+            PrismError::PermissionDenied(msg) => PrismError::PermissionDenied(f(msg)),
+            PrismError::NativePanic { function, message } => PrismError::NativePanic { function, message: f(message) },
+            PrismError::Traced(mut trace) => {
+                trace.error = trace.error.map_message(f);
+                PrismError::Traced(trace)
+            },
+            err @ (PrismError::IO(_) | PrismError::Serialization(_)) => err,
         }
     }
 }
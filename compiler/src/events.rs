@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use parking_lot::RwLock;
+use crate::value::Value;
+
+/// Host callback for events emitted by scripts.
+pub type EventListener = Arc<dyn Fn(&Value) + Send + Sync>;
+
+/// Named events connecting scripts and their host.
+///
+/// Scripts subscribe functions with `events.on(name, handler)` and the host
+/// subscribes closures with [`Interpreter::on`](crate::Interpreter::on);
+/// an event emitted by either side reaches both. Clones share subscriptions,
+/// so concurrent tasks of one evaluation see the same handlers.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    handlers: Arc<RwLock<HashMap<String, Vec<Value>>>>,
+    listeners: Arc<RwLock<HashMap<String, Vec<EventListener>>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribes a script function.
+    pub fn subscribe(&self, name: impl Into<String>, handler: Value) {
+        self.handlers.write().entry(name.into()).or_default().push(handler);
+    }
+
+    /// Subscribes a host closure.
+    pub fn listen(&self, name: impl Into<String>, listener: EventListener) {
+        self.listeners.write().entry(name.into()).or_default().push(listener);
+    }
+
+    /// Drops every subscription to `name`.
+    pub fn clear(&self, name: &str) {
+        self.handlers.write().remove(name);
+        self.listeners.write().remove(name);
+    }
+
+    /// Script functions subscribed to `name`, in subscription order.
+    pub fn handlers(&self, name: &str) -> Vec<Value> {
+        self.handlers.read().get(name).cloned().unwrap_or_default()
+    }
+
+    /// Calls the host closures subscribed to `name`.
+    pub fn notify_listeners(&self, name: &str, payload: &Value) {
+        let listeners = self.listeners.read().get(name).cloned().unwrap_or_default();
+        for listener in listeners {
+            listener(payload);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Generator functions: calling a function whose body contains `yield`
+//! returns an iterator instead of running the body.
+//!
+//! The body runs in a [fork](Interpreter::fork) as a future that the
+//! iterator's `next()` polls. `yield` stores its value in the generator's
+//! [`Slot`] and suspends the body once, so `next()` sees a pending body with
+//! a filled slot and returns that value; the following `next()` resumes the
+//! body where it left off. Waits inside the body (sleeps, LLM calls) keep
+//! their wakers, so a generator can stream results as they arrive.
+
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use parking_lot::Mutex;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::iterator;
+use crate::value::{Value, ValueKind};
+
+type Body = Pin<Box<dyn Future<Output = (Interpreter, Result<()>)> + Send>>;
+
+/// Where a suspended generator body leaves its yielded value.
+#[derive(Debug, Default)]
+pub struct Slot(Mutex<Option<Value>>);
+
+impl Slot {
+    /// Hands `value` to the consumer and suspends the body until the next
+    /// `next()` call polls it again.
+    pub async fn yield_value(&self, value: Value) {
+        *self.0.lock() = Some(value);
+        let mut suspended = false;
+        poll_fn(|_: &mut Context<'_>| {
+            if suspended {
+                Poll::Ready(())
+            } else {
+                suspended = true;
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+enum State {
+    Suspended(Body),
+    Running,
+    Finished,
+}
+
+/// The iterator returned by calling a generator function; `body` is the
+/// function body running in a fork whose yields go to `slot`.
+pub fn generator(name: &str, slot: Arc<Slot>, body: Body) -> Value {
+    let state = Arc::new(Mutex::new(State::Suspended(body)));
+    let name = name.to_string();
+    let next = Value::new(ValueKind::AsyncNativeFunction {
+        name: "next".to_string(),
+        arity: 0,
+        handler: Arc::new(move |interpreter, _| {
+            let state = Arc::clone(&state);
+            let slot = Arc::clone(&slot);
+            let name = name.clone();
+            Box::pin(async move {
+                let mut body = match std::mem::replace(&mut *state.lock(), State::Running) {
+                    State::Suspended(body) => body,
+                    State::Running => {
+                        return Err(PrismError::RuntimeError(format!("generator {} is already running", name)))
+                    }
+                    State::Finished => {
+                        *state.lock() = State::Finished;
+                        return Ok(iterator::step(None));
+                    }
+                };
+                let finished = poll_fn(|cx| match body.as_mut().poll(cx) {
+                    Poll::Ready(outcome) => Poll::Ready(Some(outcome)),
+                    Poll::Pending if slot.0.lock().is_some() => Poll::Ready(None),
+                    Poll::Pending => Poll::Pending,
+                })
+                .await;
+                match finished {
+                    None => {
+                        *state.lock() = State::Suspended(body);
+                        Ok(iterator::step(slot.0.lock().take()))
+                    }
+                    Some((fork, result)) => {
+                        *state.lock() = State::Finished;
+                        interpreter.join(fork);
+                        result.map(|()| iterator::step(None))
+                    }
+                }
+            })
+        }),
+    });
+    Value::new(ValueKind::Map(vec![(Value::new(ValueKind::String("next".to_string())), next)]))
+}
@@ -0,0 +1,187 @@
+//! Token classes for syntax highlighting, shared by the REPL, editor
+//! integrations and the web playground so none of them re-lexes Prism.
+//!
+//! ```
+//! use prism::highlight::{highlight, TokenClass};
+//!
+//! let source = "let dose = 400 ~> 0.8;";
+//! let classes: Vec<(&str, TokenClass)> = highlight(source)
+//!     .into_iter()
+//!     .map(|(span, class)| (&source[span], class))
+//!     .collect();
+//! assert_eq!(classes, [
+//!     ("let", TokenClass::Keyword),
+//!     ("=", TokenClass::Operator),
+//!     ("400", TokenClass::Number),
+//!     ("~>", TokenClass::Confidence),
+//!     ("0.8", TokenClass::Number),
+//! ]);
+//! ```
+
+use std::ops::Range;
+use crate::lexer::Lexer;
+use crate::token::{Token, TokenKind};
+
+/// A byte range of the highlighted source.
+pub type Span = Range<usize>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenClass {
+    /// Reserved words, `true`, `false` and `nil`, and the words that are
+    /// only keywords where they appear: `uncertain`, `medium`, `low` and `cfg`.
+    Keyword,
+    /// The `~>` operator.
+    Confidence,
+    /// The name after `in context`.
+    Context,
+    String,
+    Number,
+    Operator,
+    Comment,
+    /// Text that is not a token, such as an unterminated string.
+    Invalid,
+}
+
+impl TokenClass {
+    /// A lowercase name for the class, e.g. for CSS classes or LSP token types.
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenClass::Keyword => "keyword",
+            TokenClass::Confidence => "confidence",
+            TokenClass::Context => "context",
+            TokenClass::String => "string",
+            TokenClass::Number => "number",
+            TokenClass::Operator => "operator",
+            TokenClass::Comment => "comment",
+            TokenClass::Invalid => "invalid",
+        }
+    }
+}
+
+/// Classifies the tokens and comments of `source`, in order. Identifiers and
+/// punctuation are left out. Source that does not lex, e.g. while it is
+/// being typed, is still classified up to and after the problem.
+pub fn highlight(source: &str) -> Vec<(Span, TokenClass)> {
+    let (tokens, skipped) = Lexer::new(source).scan_tokens_lossy();
+    let mut classes: Vec<(Span, TokenClass)> = tokens
+        .iter()
+        .enumerate()
+        .filter_map(|(i, token)| {
+            let previous = i.checked_sub(1).map(|i| &tokens[i].kind);
+            let next = tokens.get(i + 1).map(|token| &token.kind);
+            classify(token, previous, next).map(|class| (token.span(), class))
+        })
+        .collect();
+
+    for span in skipped {
+        let class = if source[span.clone()].starts_with('"') {
+            TokenClass::String
+        } else {
+            TokenClass::Invalid
+        };
+        classes.push((span, class));
+    }
+    classes.extend(comments(source, &tokens).map(|span| (span, TokenClass::Comment)));
+    classes.sort_by_key(|(span, _)| span.start);
+    classes
+}
+
+fn classify(token: &Token, previous: Option<&TokenKind>, next: Option<&TokenKind>) -> Option<TokenClass> {
+    let class = match &token.kind {
+        TokenKind::Confidence => TokenClass::Confidence,
+        TokenKind::String(_) | TokenKind::Identifier(_) if previous == Some(&TokenKind::Context) => TokenClass::Context,
+        TokenKind::String(_) => TokenClass::String,
+        TokenKind::Number(_) | TokenKind::Decimal(_) => TokenClass::Number,
+        TokenKind::Identifier(name) => match (name.as_str(), previous, next) {
+            ("uncertain", _, Some(TokenKind::If)) | ("cfg", Some(TokenKind::At), _) => TokenClass::Keyword,
+            ("medium" | "low", Some(TokenKind::RightBrace), Some(TokenKind::LeftBrace | TokenKind::LeftParen)) => {
+                TokenClass::Keyword
+            }
+            _ => return None,
+        },
+        TokenKind::Minus
+        | TokenKind::Plus
+        | TokenKind::Slash
+        | TokenKind::Star
+        | TokenKind::Bang
+        | TokenKind::BangEqual
+        | TokenKind::Equal
+        | TokenKind::EqualEqual
+        | TokenKind::Greater
+        | TokenKind::GreaterEqual
+        | TokenKind::Less
+        | TokenKind::LessEqual
+        | TokenKind::Arrow
+        | TokenKind::ThinArrow
+        | TokenKind::QuestionDot
+        | TokenKind::Pipe => TokenClass::Operator,
+        TokenKind::LeftParen
+        | TokenKind::RightParen
+        | TokenKind::LeftBrace
+        | TokenKind::RightBrace
+        | TokenKind::LeftBracket
+        | TokenKind::RightBracket
+        | TokenKind::Comma
+        | TokenKind::Dot
+        | TokenKind::Semicolon
+        | TokenKind::Colon
+        | TokenKind::Question
+        | TokenKind::At
+        | TokenKind::EOF => return None,
+        _ => TokenClass::Keyword,
+    };
+    Some(class)
+}
+
+/// The `//` comments in the text between tokens, which the lexer drops.
+fn comments<'a>(source: &'a str, tokens: &'a [Token]) -> impl Iterator<Item = Span> + 'a {
+    let gaps = std::iter::once(0)
+        .chain(tokens.iter().map(|token| token.span().end))
+        .zip(tokens.iter().map(|token| token.span().start));
+    gaps.flat_map(move |(start, end)| {
+        let mut spans = Vec::new();
+        let mut offset = start;
+        while let Some(found) = source.get(offset..end).and_then(|gap| gap.find("//")) {
+            let from = offset + found;
+            let to = source[from..end].find('\n').map_or(end, |newline| from + newline);
+            spans.push(from..to);
+            offset = to;
+        }
+        spans
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classes(source: &str) -> Vec<(&str, &'static str)> {
+        highlight(source)
+            .into_iter()
+            .map(|(span, class)| (&source[span], class.name()))
+            .collect()
+    }
+
+    #[test]
+    fn test_contextual_keywords_and_context_names() {
+        let source = "in context Triage {\n  uncertain if (x ~> 0.9) { a; } medium { b; } low { c; }\n}\nlet low = medium;";
+        assert_eq!(classes(source), [
+            ("in", "keyword"), ("context", "keyword"), ("Triage", "context"),
+            ("uncertain", "keyword"), ("if", "keyword"), ("~>", "confidence"), ("0.9", "number"),
+            ("medium", "keyword"), ("low", "keyword"),
+            ("let", "keyword"), ("=", "operator"),
+        ]);
+    }
+
+    #[test]
+    fn test_comments_and_unfinished_source() {
+        let source = "// dose in mg\nlet d = 19.99d; // rounded\n# oops\nprint(\"unterminated";
+        assert_eq!(classes(source), [
+            ("// dose in mg", "comment"),
+            ("let", "keyword"), ("=", "operator"), ("19.99d", "number"),
+            ("// rounded", "comment"),
+            ("#", "invalid"),
+            ("\"unterminated", "string"),
+        ]);
+    }
+}
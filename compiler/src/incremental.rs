@@ -0,0 +1,385 @@
+//! Incremental re-parsing for editors.
+//!
+//! A [`Document`] keeps its source as a list of chunks, one per top-level
+//! statement, each with its parse result. An edit re-lexes and re-parses
+//! only the chunks it touches (growing the region while it leaves a brace
+//! or string open) and moves the line numbers of the statements after it
+//! instead of parsing them again. A syntax error stays within its chunk, so
+//! the rest of the file still has a tree to check.
+
+use std::ops::Range;
+use crate::ast::{Expr, Stmt};
+use crate::diagnostics::Diagnostic;
+use crate::error::PrismError;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::token::{Token, TokenKind};
+
+#[derive(Debug)]
+struct Chunk {
+    /// The statement's text and the whitespace and comments after it.
+    text: String,
+    /// The line the chunk starts on.
+    line: usize,
+    parsed: Result<Vec<Stmt>, PrismError>,
+}
+
+impl Chunk {
+    fn newlines(&self) -> usize {
+        self.text.matches('\n').count()
+    }
+}
+
+/// A source file being edited.
+#[derive(Debug)]
+pub struct Document {
+    chunks: Vec<Chunk>,
+}
+
+impl Document {
+    pub fn new(source: &str) -> Self {
+        Document { chunks: split(source, 1).chunks }
+    }
+
+    pub fn source(&self) -> String {
+        self.chunks.iter().map(|chunk| chunk.text.as_str()).collect()
+    }
+
+    /// Replaces `lines` (1-based, end exclusive) with `text`, which should
+    /// end with a newline unless it is the end of the file. Returns how many
+    /// statements were parsed again.
+    pub fn edit(&mut self, lines: Range<usize>, text: &str) -> usize {
+        if self.chunks.is_empty() {
+            *self = Document::new(text);
+            return self.chunks.len();
+        }
+        let start = self.offset_of_line(lines.start);
+        let end = self.offset_of_line(lines.end.max(lines.start));
+
+        // The chunks holding the edit, and the one before in case the edit
+        // continues it, e.g. by adding an `else`
+        let mut offset = 0;
+        let mut first = None;
+        let mut last = self.chunks.len() - 1;
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            let chunk_end = offset + chunk.text.len();
+            if first.is_none() && start < chunk_end {
+                first = Some(index);
+            }
+            if end < chunk_end || (end == chunk_end && end > start) {
+                last = index;
+                break;
+            }
+            offset = chunk_end;
+        }
+        let first = first.unwrap_or(self.chunks.len() - 1).saturating_sub(1).min(last);
+
+        let region_start: usize = self.chunks[..first].iter().map(|chunk| chunk.text.len()).sum();
+        let old: String = self.chunks[first..=last].iter().map(|chunk| chunk.text.as_str()).collect();
+        let mut region = format!("{}{}{}", &old[..start - region_start], text, &old[end - region_start..]);
+        let line = self.chunks[first].line;
+
+        // Grow the region until it ends between statements
+        let mut parts = split(&region, line);
+        while !parts.complete && last + 1 < self.chunks.len() {
+            last += 1;
+            region.push_str(&self.chunks[last].text);
+            parts = split(&region, line);
+        }
+
+        let old_newlines: usize = self.chunks[first..=last].iter().map(Chunk::newlines).sum();
+        let new_newlines = region.matches('\n').count();
+        let delta = new_newlines as isize - old_newlines as isize;
+        let reparsed = parts.chunks.len();
+        self.chunks.splice(first..=last, parts.chunks);
+        if delta != 0 {
+            for chunk in &mut self.chunks[first + reparsed..] {
+                chunk.line = chunk.line.saturating_add_signed(delta);
+                if let Ok(statements) = &mut chunk.parsed {
+                    statements.iter_mut().for_each(|stmt| shift_stmt(stmt, delta));
+                }
+            }
+        }
+        reparsed
+    }
+
+    /// The statements that parsed, in order.
+    pub fn program(&self) -> Vec<Stmt> {
+        self.chunks
+            .iter()
+            .filter_map(|chunk| chunk.parsed.as_ref().ok())
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Syntax errors, then what the resolver and checker find in the
+    /// statements that parsed; `globals` are the names defined outside the
+    /// file.
+    pub fn diagnostics(&self, globals: &[String]) -> Vec<Diagnostic> {
+        let mut diagnostics: Vec<Diagnostic> = self
+            .chunks
+            .iter()
+            .filter_map(|chunk| match &chunk.parsed {
+                Err(err) => Some(Diagnostic::error(err.to_string()).at_line(Some(chunk.line))),
+                Ok(_) => None,
+            })
+            .collect();
+        let mut program = self.program();
+        diagnostics.extend(crate::resolver::resolve(&mut program, globals.iter().cloned()));
+        diagnostics.extend(crate::checker::check(&program));
+        diagnostics
+    }
+
+    /// The byte offset where `line` starts, or the end of the source.
+    fn offset_of_line(&self, line: usize) -> usize {
+        let mut offset = 0;
+        for chunk in &self.chunks {
+            let newlines = chunk.newlines();
+            if line <= chunk.line + newlines {
+                let skip = line.saturating_sub(chunk.line);
+                let within = match skip {
+                    0 => 0,
+                    _ => chunk.text.match_indices('\n').nth(skip - 1).map_or(chunk.text.len(), |(i, _)| i + 1),
+                };
+                return offset + within;
+            }
+            offset += chunk.text.len();
+        }
+        offset
+    }
+}
+
+struct Split {
+    chunks: Vec<Chunk>,
+    /// Whether the text ended between statements rather than inside one.
+    complete: bool,
+}
+
+/// Splits `source`, which starts on `line`, into one chunk per top-level
+/// statement and parses each.
+fn split(source: &str, line: usize) -> Split {
+    let tokens = match Lexer::new(source).starting_at_line(line).scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            let chunk = Chunk { text: source.to_string(), line, parsed: Err(err) };
+            return Split { chunks: vec![chunk], complete: false };
+        }
+    };
+
+    // A statement ends at a `;` outside any brackets, or at a `}` that the
+    // next token does not continue, as `else` or the `;` after a map does
+    let mut starts = vec![0];
+    let mut depth = 0usize;
+    let mut ended = true;
+    for (index, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::EOF => break,
+            TokenKind::LeftParen | TokenKind::LeftBrace | TokenKind::LeftBracket => depth += 1,
+            TokenKind::RightParen | TokenKind::RightBrace | TokenKind::RightBracket => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        ended = depth == 0 && matches!(token.kind, TokenKind::Semicolon | TokenKind::RightBrace);
+        let next = &tokens[index + 1];
+        let continued = token.kind == TokenKind::RightBrace && !starts_statement(next);
+        if ended && next.kind != TokenKind::EOF && !continued {
+            starts.push(index + 1);
+        }
+    }
+    let complete = depth == 0 && (ended || tokens.len() == 1);
+
+    let eof = tokens.len() - 1;
+    let mut chunks = Vec::with_capacity(starts.len());
+    for (i, &from) in starts.iter().enumerate() {
+        let to = starts.get(i + 1).copied().unwrap_or(eof);
+        let text_start = if i == 0 { 0 } else { tokens[from].span().start };
+        let text_end = if to == eof { source.len() } else { tokens[to].span().start };
+        // A token's line is where it ends, which is not where a statement
+        // starting with a multi-line string starts
+        let chunk_line = line + source[..text_start].matches('\n').count();
+        let mut chunk_tokens: Vec<Token> = tokens[from..to].to_vec();
+        chunk_tokens.push(tokens[eof].clone());
+        chunks.push(Chunk {
+            text: source[text_start..text_end].to_string(),
+            line: chunk_line,
+            parsed: Parser::new(chunk_tokens).parse(),
+        });
+    }
+    Split { chunks, complete }
+}
+
+fn starts_statement(token: &Token) -> bool {
+    match &token.kind {
+        TokenKind::Identifier(name) => name != "medium" && name != "low",
+        TokenKind::Let
+        | TokenKind::Fun
+        | TokenKind::Async
+        | TokenKind::If
+        | TokenKind::While
+        | TokenKind::For
+        | TokenKind::Return
+        | TokenKind::Yield
+        | TokenKind::Break
+        | TokenKind::Continue
+        | TokenKind::Import
+        | TokenKind::Export
+        | TokenKind::Module
+        | TokenKind::Context
+        | TokenKind::At
+        | TokenKind::LeftBrace => true,
+        _ => false,
+    }
+}
+
+fn shift_stmt(stmt: &mut Stmt, delta: isize) {
+    match stmt {
+        Stmt::Expression(expr) | Stmt::Yield(expr) | Stmt::Return(Some(expr)) => shift_expr(expr, delta),
+        Stmt::Let { initializer, .. } => {
+            if let Some(expr) = initializer {
+                shift_expr(expr, delta);
+            }
+        }
+        Stmt::Block(statements) | Stmt::Module { body: statements, .. } => {
+            statements.iter_mut().for_each(|stmt| shift_stmt(stmt, delta));
+        }
+        Stmt::If { condition, then_branch, else_branch } => {
+            shift_expr(condition, delta);
+            shift_stmt(then_branch, delta);
+            if let Some(branch) = else_branch {
+                shift_stmt(branch, delta);
+            }
+        }
+        Stmt::UncertainIf { condition, then_branch, medium_branch, low_branch, .. } => {
+            shift_expr(condition, delta);
+            shift_stmt(then_branch, delta);
+            for branch in [medium_branch, low_branch].into_iter().flatten() {
+                shift_stmt(branch, delta);
+            }
+        }
+        Stmt::While { condition: expr, body } | Stmt::For { iterable: expr, body, .. } => {
+            shift_expr(expr, delta);
+            shift_stmt(body, delta);
+        }
+        Stmt::Function { body, .. }
+        | Stmt::Context { body, .. }
+        | Stmt::Export(_, body)
+        | Stmt::Cfg { body, .. } => shift_stmt(body, delta),
+        Stmt::Return(None) | Stmt::Break | Stmt::Continue | Stmt::Import { .. } | Stmt::ModuleAccess { .. } => {}
+    }
+}
+
+fn shift_expr(expr: &mut Expr, delta: isize) {
+    match expr {
+        Expr::Variable { line, .. } => *line = line.saturating_add_signed(delta),
+        Expr::Binary { left, operator, right } | Expr::Logical { left, operator, right } => {
+            operator.line = operator.line.saturating_add_signed(delta);
+            shift_expr(left, delta);
+            shift_expr(right, delta);
+        }
+        Expr::Unary { operator, right } => {
+            operator.line = operator.line.saturating_add_signed(delta);
+            shift_expr(right, delta);
+        }
+        Expr::Call { callee, arguments, .. } => {
+            shift_expr(callee, delta);
+            arguments.iter_mut().for_each(|arg| shift_expr(arg, delta));
+        }
+        Expr::Assign { value: inner, .. }
+        | Expr::Get { object: inner, .. }
+        | Expr::Confidence { expr: inner, .. }
+        | Expr::InContext { body: inner, .. }
+        | Expr::Grouping(inner) => shift_expr(inner, delta),
+        Expr::ConfidenceCombine { left, right } => {
+            shift_expr(left, delta);
+            shift_expr(right, delta);
+        }
+        Expr::List(items) => items.iter_mut().for_each(|item| shift_expr(item, delta)),
+        Expr::Map(entries) => {
+            for (key, value) in entries {
+                shift_expr(key, delta);
+                shift_expr(value, delta);
+            }
+        }
+        Expr::Match { subject, arms } => {
+            shift_expr(subject, delta);
+            for arm in arms {
+                if let Some(guard) = &mut arm.guard {
+                    shift_expr(guard, delta);
+                }
+                shift_expr(&mut arm.body, delta);
+            }
+        }
+        Expr::Literal(_) | Expr::ModuleAccess { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn source(functions: usize) -> String {
+        (0..functions)
+            .map(|i| format!("fn f{i}(a) {{\n    if (a > {i}) {{ return a * 2; }} else {{ return a; }}\n}}\nlet x{i} = f{i}(1);\n"))
+            .collect()
+    }
+
+    #[test]
+    fn test_edits_reparse_only_what_they_touch() {
+        let mut document = Document::new(&source(50));
+        assert_eq!(document.program(), parse(&source(50)).unwrap());
+
+        // Line 42 is the body of f10; the statements after it move down
+        let reparsed = document.edit(42..43, "    let b = a + 1;\n    return b;\n");
+        assert!(reparsed <= 2, "reparsed {}", reparsed);
+        assert_eq!(document.program(), parse(&document.source()).unwrap());
+        assert!(document.source().contains("fn f10(a) {\n    let b = a + 1;\n    return b;\n}"));
+
+        // Deleting lines moves them up again
+        document.edit(1..5, "");
+        assert_eq!(document.program(), parse(&document.source()).unwrap());
+        assert!(document.source().starts_with("fn f1(a)"));
+    }
+
+    #[test]
+    fn test_unclosed_code_grows_the_region() {
+        let mut document = Document::new(&source(5));
+        // Opening a brace swallows the following statements until it is closed
+        document.edit(4..5, "let x0 = {\n");
+        assert!(!document.diagnostics(&[]).is_empty());
+        document.edit(4..5, "let x0 = { a: 1 };\n");
+        assert_eq!(document.program(), parse(&document.source()).unwrap());
+        assert!(document.diagnostics(&[]).is_empty(), "{:?}", document.diagnostics(&[]));
+
+        // An unterminated string reaches the end of the file
+        document.edit(8..9, "let x1 = \"oops;\n");
+        let diagnostics = document.diagnostics(&[]);
+        assert!(diagnostics.iter().any(|d| d.message.contains("Unterminated string")), "{:?}", diagnostics);
+    }
+
+    #[test]
+    fn test_syntax_errors_stay_in_their_statement() {
+        let mut document = Document::new("let a = 1;\nlet b = ;\nlet c = a + 1;\n");
+        let diagnostics = document.diagnostics(&[]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, Some(2));
+        assert_eq!(document.program().len(), 2);
+
+        document.edit(2..3, "let b = 2;\n");
+        assert!(document.diagnostics(&[]).is_empty());
+        assert_eq!(document.program().len(), 3);
+    }
+
+    #[test]
+    fn test_statements_starting_with_multi_line_strings_keep_their_line() {
+        let mut document = Document::new("let a = 1;\n\"p\nq\nr\n\";\nlet b = 2;\n");
+        document.edit(4..4, "X\n");
+        assert_eq!(document.source(), "let a = 1;\n\"p\nq\nX\nr\n\";\nlet b = 2;\n");
+        assert_eq!(document.program(), parse(&document.source()).unwrap());
+
+        // Lines after the string are found where they are
+        document.edit(7..8, "let b = 3;\n");
+        assert_eq!(document.source(), "let a = 1;\n\"p\nq\nX\nr\n\";\nlet b = 3;\n");
+        assert_eq!(document.program(), parse(&document.source()).unwrap());
+    }
+}
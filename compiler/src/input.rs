@@ -0,0 +1,71 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read};
+use std::sync::Arc;
+use parking_lot::Mutex;
+
+/// Where `io.input` and `io.read_stdin` read from.
+///
+/// Native builds default to [`Stdin`]; browser builds install a sink backed
+/// by a host callback, and tests script the input with [`ScriptedInput`].
+pub trait InputSource: Send + Sync {
+    /// The next line without its line ending, or `None` at end of input.
+    fn read_line(&self) -> io::Result<Option<String>>;
+
+    /// Everything that is left, e.g. data piped into the script.
+    fn read_to_end(&self) -> io::Result<String>;
+}
+
+/// Reads the process's standard input.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stdin;
+
+impl InputSource for Stdin {
+    fn read_line(&self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let trimmed = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(trimmed);
+        Ok(Some(line))
+    }
+
+    fn read_to_end(&self) -> io::Result<String> {
+        let mut text = String::new();
+        io::stdin().lock().read_to_string(&mut text)?;
+        Ok(text)
+    }
+}
+
+/// Input given up front, one line at a time. Clones share the queue.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedInput {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl ScriptedInput {
+    pub fn new<I, S>(lines: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            lines: Arc::new(Mutex::new(lines.into_iter().map(Into::into).collect())),
+        }
+    }
+
+    pub fn push(&self, line: impl Into<String>) {
+        self.lines.lock().push_back(line.into());
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn read_line(&self) -> io::Result<Option<String>> {
+        Ok(self.lines.lock().pop_front())
+    }
+
+    fn read_to_end(&self) -> io::Result<String> {
+        let lines: Vec<String> = self.lines.lock().drain(..).collect();
+        Ok(lines.join("\n"))
+    }
+}
@@ -1,216 +1,3552 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use parking_lot::RwLock;
-use crate::ast::{Expr, Stmt};
-use crate::environment::Environment;
+use std::time::Duration;
+use crate::ast::{Expr, MatchArm, Pattern, Slot, Stmt};
+use crate::audit::{AuditEvent, AuditLog};
+use crate::cancellation::CancellationToken;
+use crate::capabilities::{Capabilities, Capability};
+use crate::cfg::CfgFlags;
+use crate::confidence::CombineStrategy;
+use crate::config::Profile;
+use crate::diagnostics::Diagnostic;
+use crate::environment::{Closure, Environment, Frame};
+use crate::events::{EventBus, EventListener};
 use crate::error::{PrismError, Result};
-use crate::value::{Value, ValueKind};
-use crate::token::TokenKind;
+use crate::value::{NativeFuture, Value, ValueKind};
+use crate::input::{InputSource, Stdin};
+use crate::generator;
+use crate::iterator::Iteration;
+use crate::llm::cache::{Embedder, ResponseCache};
+use crate::llm::calibration::Calibration;
+use crate::llm::queue::RequestQueue;
+use crate::llm::replay::{Recorder, Replay};
+use crate::llm::rules::RuleSet;
+use crate::llm::{LLMClient, LLMProvider, ModelConfig};
+use crate::metrics::{Metrics, MetricsSnapshot};
+use crate::module::Module;
+use crate::output::{CapturedOutput, OutputSink, Stdout};
+use crate::progress::{ProgressSink, ProgressTracker};
+use crate::purity::{self, SyncNodes};
+use crate::secrets::Secrets;
+use crate::snapshot::Snapshots;
+#[cfg(feature = "decimal")]
+use crate::stdlib::decimal;
+use crate::stdlib::tasks::{drive, Task};
+use crate::stdlib::time;
+use crate::source_map::SourceMap;
+use crate::telemetry;
+use crate::token::{Token, TokenKind};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::any::Any;
 use std::future::Future;
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
+use std::task::{Context, Poll};
 
+/// What an evaluation starts from.
+enum Program {
+    Source(String),
+    Statements(Vec<Stmt>),
+}
+
+/// Default nesting limit for calls that are not in tail position.
+pub const MAX_CALL_DEPTH: usize = 200;
+
+/// Default number of loop iterations and calls between yields to the async
+/// runtime.
+pub const YIELD_INTERVAL: usize = 1000;
+
+/// Runs Prism programs against its own global environment.
+///
+/// Instances share nothing mutable, so independent scripts can run
+/// concurrently in separate interpreters. `Interpreter` and the futures
+/// returned by its `evaluate*` methods are `Send`, so an evaluation can be
+/// spawned onto a multi-threaded runtime; see
+/// [`InterpreterPool`](crate::pool::InterpreterPool) for creating many.
 pub struct Interpreter {
-    environment: Arc<RwLock<Environment>>,
+    /// What the running function closes over: the globals, and the scopes
+    /// holding the captured locals of the functions it is nested in.
+    environment: Arc<Environment>,
+    /// The locals of the running call, or of the top-level code.
+    frame: Frame,
+    diagnostics: Vec<Diagnostic>,
+    call_depth: usize,
+    max_call_depth: usize,
+    max_loop_iterations: Option<usize>,
+    yield_interval: usize,
+    /// Loop iterations and calls since the last yield.
+    ticks: usize,
+    cancellation: CancellationToken,
+    audit: AuditLog,
+    capabilities: Capabilities,
+    cfg: CfgFlags,
+    secrets: Secrets,
+    output: Arc<dyn OutputSink>,
+    input: Arc<dyn InputSource>,
+    events: EventBus,
+    progress: ProgressTracker,
+    metrics: Metrics,
+    recorder: Option<Arc<Recorder>>,
+    replay: Option<Arc<Replay>>,
+    /// Where LLM requests wait for their provider's limits.
+    request_queue: Arc<RequestQueue>,
+    /// Maps model confidences to observed accuracy; shared with forks.
+    calibration: Arc<Calibration>,
+    /// The id of the latest completion, for `llm.feedback`.
+    last_response_id: Option<String>,
+    /// Answers to LLM requests already made, shared with forks.
+    llm_cache: Option<Arc<ResponseCache>>,
+    /// The clients sending LLM requests, by provider; shared with forks.
+    llm_clients: Arc<RwLock<HashMap<String, Arc<LLMClient>>>>,
+    /// What `llm.cache` compares prompts with in semantic mode.
+    embedder: Option<Arc<dyn Embedder>>,
+    rules: Arc<RwLock<RuleSet>>,
+    /// Whether LLM calls are only estimated; see [`with_dry_run`](Self::with_dry_run).
+    dry_run: bool,
+    snapshots: Option<Arc<Snapshots>>,
+    source_map: Option<Arc<SourceMap>>,
+    /// Where imports of file modules are looked up from.
+    module_dir: PathBuf,
+    /// Where imports not found beside the importer are looked up.
+    module_paths: Arc<Vec<PathBuf>>,
+    combine: CombineStrategy,
+    /// Environment variables set for scripts on top of the process's.
+    env_vars: Arc<HashMap<String, String>>,
+    /// Command-line arguments for `env.args()` and `main`.
+    args: Arc<Vec<String>>,
+    model_config: ModelConfig,
+    /// Named model configurations `llm.use_profile` switches between.
+    profiles: Arc<HashMap<String, ModelConfig>>,
+    /// The `in context` blocks being run, innermost last.
+    contexts: Vec<String>,
+    /// Model settings `llm.bind_context` bound to contexts, shared with forks.
+    context_models: Arc<RwLock<HashMap<String, Profile>>>,
+    /// File modules evaluated so far, shared with forks.
+    file_modules: Arc<RwLock<HashMap<PathBuf, Value>>>,
+    /// Set while running the body of a generator; see [`generator`].
+    generator: Option<Arc<generator::Slot>>,
+    /// Which nodes of the program or function body being run are sync.
+    sync: Arc<SyncNodes>,
+    /// Whether dropping the interpreter releases its globals; forks share
+    /// them. A closure kept in a global refers back to the globals through
+    /// the frames it closes over, so they would otherwise never be freed.
+    owns_globals: bool,
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        if self.owns_globals {
+            self.globals().clear();
+        }
+    }
+}
+
+/// How a statement finished.
+enum Flow {
+    Normal(Value),
+    Return(Value),
+    /// `return f(args);` — the caller's frame performs the call.
+    TailCall(Value, Vec<Value>),
+    Break,
+    Continue,
+}
+
+impl Flow {
+    /// The flow without the value of a statement that finished normally,
+    /// which loops and calls throw away; it may hold functions closing over
+    /// the scope about to be left and so keep [`Environment::release`] from
+    /// freeing it.
+    fn discard_value(self) -> Self {
+        match self {
+            Flow::Normal(_) => Flow::Normal(Value::new(ValueKind::Nil)),
+            flow => flow,
+        }
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
+    /// An interpreter with the standard library modules and the prelude
+    /// (`print`, `len`, `range`, ...) as globals.
     pub fn new() -> Self {
+        Self::with_stdlib(true)
+    }
+
+    /// An interpreter with the standard library modules but no prelude, for
+    /// embedders that want a minimal global surface.
+    pub fn without_prelude() -> Self {
+        Self::with_stdlib(false)
+    }
+
+    fn with_stdlib(prelude: bool) -> Self {
+        let mut globals = crate::stdlib::init_stdlib()
+            .expect("standard library modules build without errors");
+        if prelude {
+            let prelude = crate::stdlib::prelude_of(&globals).expect("the prelude builds without errors");
+            globals.extend(prelude);
+        }
+        let globals: Vec<(String, Value)> = globals
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        Self::with_globals(&globals)
+    }
+
+    /// An interpreter whose global environment starts with `globals`, and
+    /// whose model, confidence strategy and module paths come from the
+    /// [configuration](crate::config).
+    pub fn with_globals(globals: &[(String, Value)]) -> Self {
+        Self::with_global_environment(Environment::with_values(globals.iter().cloned()))
+    }
+
+    /// An interpreter of its own in `globals`, e.g. one made read-only with
+    /// [`Environment::freeze`].
+    pub fn with_global_environment(globals: Environment) -> Self {
+        Self::with_environment(Arc::new(globals), true)
+    }
+
+    /// An interpreter running in `globals`, which other interpreters may
+    /// share: what one defines, the others see. Unlike with
+    /// [`with_globals`](Self::with_globals), dropping it leaves them as
+    /// they are.
+    pub fn with_shared_globals(globals: Arc<Environment>) -> Self {
+        Self::with_environment(globals, false)
+    }
+
+    fn with_environment(environment: Arc<Environment>, owns_globals: bool) -> Self {
+        let config = crate::config::global();
         Self {
-            environment: Arc::new(RwLock::new(Environment::new())),
+            environment,
+            frame: Frame::default(),
+            diagnostics: Vec::new(),
+            call_depth: 0,
+            max_call_depth: MAX_CALL_DEPTH,
+            max_loop_iterations: None,
+            yield_interval: YIELD_INTERVAL,
+            ticks: 0,
+            cancellation: CancellationToken::new(),
+            audit: AuditLog::new(),
+            capabilities: Capabilities::none(),
+            cfg: CfgFlags::default(),
+            secrets: Secrets::new(),
+            output: Arc::new(Stdout),
+            input: Arc::new(Stdin),
+            events: EventBus::new(),
+            progress: ProgressTracker::default(),
+            metrics: Metrics::new(),
+            recorder: None,
+            replay: None,
+            request_queue: crate::llm::queue::global(),
+            calibration: Arc::new(Calibration::new()),
+            last_response_id: None,
+            llm_cache: None,
+            llm_clients: Arc::default(),
+            embedder: None,
+            rules: Arc::new(RwLock::new(RuleSet::new())),
+            dry_run: false,
+            snapshots: None,
+            source_map: None,
+            module_dir: PathBuf::from("."),
+            module_paths: Arc::new(config.module_paths.clone()),
+            combine: config.confidence.unwrap_or_default(),
+            env_vars: Arc::new(HashMap::new()),
+            args: Arc::new(Vec::new()),
+            model_config: config.model_config(),
+            profiles: Arc::new(config.profiles()),
+            contexts: Vec::new(),
+            context_models: Arc::new(RwLock::new(HashMap::new())),
+            file_modules: Arc::new(RwLock::new(HashMap::new())),
+            generator: None,
+            sync: Arc::default(),
+            owns_globals,
+        }
+    }
+
+    /// Limits how deeply non-tail calls may nest; tail calls do not count.
+    pub fn with_max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = depth;
+        self
+    }
+
+    /// Stops any single loop that runs more than `limit` iterations with a
+    /// runtime error, e.g. to guard against scripts that never terminate;
+    /// unlimited by default.
+    pub fn with_max_loop_iterations(mut self, limit: usize) -> Self {
+        self.max_loop_iterations = Some(limit);
+        self
+    }
+
+    /// Yields to the async runtime every `interval` loop iterations and
+    /// calls, so that a busy script does not starve the tasks sharing its
+    /// thread, such as concurrent LLM requests or timers. 0 never yields.
+    pub fn with_yield_interval(mut self, interval: usize) -> Self {
+        self.yield_interval = interval;
+        self
+    }
+
+    /// The flags `@cfg` annotations are tested against; by default
+    /// `"native"` or `"wasm"` depending on the build.
+    pub fn with_cfg(mut self, cfg: CfgFlags) -> Self {
+        self.cfg = cfg;
+        self
+    }
+
+    pub fn cfg(&self) -> &CfgFlags {
+        &self.cfg
+    }
+
+    /// Reports lines in errors and diagnostics where `map` says they came
+    /// from, for programs that were generated or transformed before running.
+    pub fn with_source_map(mut self, map: SourceMap) -> Self {
+        self.source_map = Some(Arc::new(map));
+        self
+    }
+
+    /// Resolves `import ... from "./file.prism"` relative to `dir` rather
+    /// than the working directory, e.g. the directory of the script.
+    pub fn with_module_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.module_dir = dir.into();
+        self
+    }
+
+    /// Sets environment variables for `env.get` and `env.has` without
+    /// changing the process environment; they take precedence over it.
+    pub fn with_env_vars(mut self, vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        Arc::make_mut(&mut self.env_vars).extend(vars);
+        self
+    }
+
+    /// The environment variable `name` as scripts see it.
+    pub fn env_var(&self, name: &str) -> Option<String> {
+        self.env_vars.get(name).cloned().or_else(|| std::env::var(name).ok())
+    }
+
+    /// The script's command-line arguments, e.g. those after `--` in
+    /// `prism run triage.prism -- --symptoms fever`.
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = Arc::new(args);
+        self
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Calls the program's `main` function, if it defined one, with the
+    /// arguments as a list of strings when it takes a parameter.
+    pub async fn call_main(&mut self) -> Result<Option<Value>> {
+        let main = self.global_values().into_iter().find(|(name, _)| name == "main").map(|(_, value)| value);
+        let Some(main) = main else {
+            return Ok(None);
+        };
+        let ValueKind::Function { params, .. } = &main.kind else {
+            return Ok(None);
+        };
+        let args = match params.len() {
+            0 => Vec::new(),
+            _ => vec![Value::new(ValueKind::List(
+                self.args.iter().map(|arg| Value::new(ValueKind::String(arg.clone()))).collect(),
+            ))],
+        };
+        self.call_function(main, args).await.map(Some)
+    }
+
+    /// The model and parameters LLM calls use.
+    pub fn with_model_config(mut self, config: ModelConfig) -> Self {
+        self.model_config = config;
+        self
+    }
+
+    pub fn model_config(&self) -> &ModelConfig {
+        &self.model_config
+    }
+
+    /// Makes later LLM calls use `config`, once it is valid for its provider.
+    pub fn set_model_config(&mut self, config: ModelConfig) -> Result<()> {
+        config.validate()?;
+        self.model_config = config;
+        Ok(())
+    }
+
+    /// Named model configurations scripts can switch to with
+    /// `llm.use_profile`, replacing those from the configuration file.
+    pub fn with_profiles(mut self, profiles: HashMap<String, ModelConfig>) -> Self {
+        self.profiles = Arc::new(profiles);
+        self
+    }
+
+    /// Makes LLM calls inside `in context <name>` use `settings` on top of
+    /// the interpreter's model configuration.
+    pub fn bind_context(&self, name: &str, settings: Profile) {
+        self.context_models.write().insert(name.to_string(), settings);
+    }
+
+    /// The `in context` blocks being run, innermost last.
+    pub fn contexts(&self) -> &[String] {
+        &self.contexts
+    }
+
+    /// The model configuration of LLM calls made here: the interpreter's,
+    /// with the settings bound to the innermost bound context.
+    pub fn active_model_config(&self) -> ModelConfig {
+        let bindings = self.context_models.read();
+        match self.contexts.iter().rev().find_map(|name| bindings.get(name)) {
+            Some(settings) => settings.apply(self.model_config.clone()),
+            None => self.model_config.clone(),
+        }
+    }
+
+    /// Makes LLM calls use the profile `name` from now on.
+    pub fn use_profile(&mut self, name: &str) -> Result<()> {
+        match self.profiles.get(name) {
+            Some(config) => {
+                self.model_config = config.clone();
+                Ok(())
+            }
+            None => {
+                let mut names: Vec<&String> = self.profiles.keys().collect();
+                names.sort();
+                let names: Vec<&str> = names.into_iter().map(String::as_str).collect();
+                Err(PrismError::InvalidArgument(format!(
+                    "Unknown profile '{}' (profiles: {})",
+                    name,
+                    if names.is_empty() { "none configured".to_string() } else { names.join(", ") }
+                )))
+            }
+        }
+    }
+
+    /// Looks up imports that are not beside the importing file in `paths`,
+    /// in order.
+    pub fn with_module_paths(mut self, paths: Vec<PathBuf>) -> Self {
+        self.module_paths = Arc::new(paths);
+        self
+    }
+
+    /// How confidences combine when values are used together.
+    pub fn with_combine_strategy(mut self, strategy: CombineStrategy) -> Self {
+        self.combine = strategy;
+        self
+    }
+
+    pub fn combine_strategy(&self) -> CombineStrategy {
+        self.combine
+    }
+
+    /// Grants access to the outside world; scripts get none by default.
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Values masked in printed output, error messages, logged warnings and
+    /// the audit log. Register more with [`Secrets::register`].
+    pub fn secrets(&self) -> &Secrets {
+        &self.secrets
+    }
+
+    /// Sends everything scripts print to `output` instead of stdout.
+    pub fn with_output(mut self, output: Arc<dyn OutputSink>) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Collects what scripts print from now on instead of writing it out;
+    /// the returned handle reads it, e.g. to assert on it in tests.
+    pub fn capture_output(&mut self) -> CapturedOutput {
+        let output = CapturedOutput::new();
+        self.output = Arc::new(output.clone());
+        output
+    }
+
+    /// Reads script input from `input` instead of stdin.
+    pub fn with_input(mut self, input: Arc<dyn InputSource>) -> Self {
+        self.input = input;
+        self
+    }
+
+    /// Shows `progress.*` bars on `sink`; without one they only emit
+    /// `progress` events.
+    pub fn with_progress(mut self, sink: Arc<dyn ProgressSink>) -> Self {
+        self.progress = ProgressTracker::new(Some(sink));
+        self
+    }
+
+    pub fn progress(&self) -> &ProgressTracker {
+        &self.progress
+    }
+
+    /// Records every LLM exchange, e.g. to replay the run later.
+    pub fn with_recorder(mut self, recorder: Arc<Recorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Answers LLM requests from a recording instead of the provider.
+    pub fn with_replay(mut self, replay: Arc<Replay>) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    /// Queues LLM requests in `queue` instead of the process-wide one.
+    pub fn with_request_queue(mut self, queue: Arc<RequestQueue>) -> Self {
+        self.request_queue = queue;
+        self
+    }
+
+    pub fn request_queue(&self) -> &Arc<RequestQueue> {
+        &self.request_queue
+    }
+
+    /// Sends the LLM requests for `client`'s provider through it, e.g. one
+    /// with several keys or a proxy's base URL.
+    pub fn with_llm_client(self, client: LLMClient) -> Self {
+        self.llm_clients.write().insert(client.get_provider().name().to_string(), Arc::new(client));
+        self
+    }
+
+    /// The client for `provider`. Unless one was given, it is made the
+    /// first time from the provider's key in the environment, e.g.
+    /// `OPENAI_API_KEY`, which is kept out of output from then on.
+    pub fn llm_client(&self, provider: &str) -> Result<Arc<LLMClient>> {
+        if let Some(client) = self.llm_clients.read().get(provider) {
+            return Ok(Arc::clone(client));
+        }
+        let (variable, new): (&str, fn(String) -> LLMProvider) = match provider {
+            "openai" => ("OPENAI", LLMProvider::OpenAI),
+            "google" => ("GOOGLE", LLMProvider::Google),
+            _ => return Err(PrismError::InvalidArgument(format!("Unknown LLM provider '{}'", provider))),
+        };
+        let key = self.env_var(&format!("{}_API_KEY", variable)).ok_or_else(|| {
+            PrismError::RuntimeError(format!("No API key for {}: set {}_API_KEY", provider, variable))
+        })?;
+        self.secrets.register(key.clone());
+        let mut client = LLMClient::new(new(key));
+        if let Some(base_url) = self.env_var(&format!("{}_BASE_URL", variable)) {
+            client = client.with_base_url(base_url);
+        }
+        Ok(Arc::clone(self.llm_clients.write().entry(provider.to_string()).or_insert(Arc::new(client))))
+    }
+
+    /// Calibrates completion confidences with `calibration`, e.g. one
+    /// persisted with [`Calibration::with_store`].
+    pub fn with_calibration(mut self, calibration: Arc<Calibration>) -> Self {
+        self.calibration = calibration;
+        self
+    }
+
+    pub fn set_calibration(&mut self, calibration: Arc<Calibration>) {
+        self.calibration = calibration;
+    }
+
+    pub fn calibration(&self) -> &Arc<Calibration> {
+        &self.calibration
+    }
+
+    pub fn last_response_id(&self) -> Option<&str> {
+        self.last_response_id.as_deref()
+    }
+
+    pub(crate) fn set_last_response_id(&mut self, id: String) {
+        self.last_response_id = Some(id);
+    }
+
+    /// Answers repeated, or in semantic mode similar, LLM requests from
+    /// `cache` instead of the provider.
+    pub fn with_llm_cache(mut self, cache: Arc<ResponseCache>) -> Self {
+        self.llm_cache = Some(cache);
+        self
+    }
+
+    pub fn set_llm_cache(&mut self, cache: Option<Arc<ResponseCache>>) {
+        self.llm_cache = cache;
+    }
+
+    pub fn llm_cache(&self) -> Option<&ResponseCache> {
+        self.llm_cache.as_deref()
+    }
+
+    /// Lets scripts turn on semantic caching with `llm.cache({ semantic: true })`,
+    /// comparing prompts by `embedder`'s embeddings.
+    pub fn with_embedder(mut self, embedder: Arc<dyn Embedder>) -> Self {
+        self.embedder = Some(embedder);
+        self
+    }
+
+    pub fn embedder(&self) -> Option<&Arc<dyn Embedder>> {
+        self.embedder.as_ref()
+    }
+
+    /// The rules that answer LLM requests to the `rules` provider.
+    pub fn with_rules(self, rules: RuleSet) -> Self {
+        *self.rules.write() = rules;
+        self
+    }
+
+    pub fn rules(&self) -> &RwLock<RuleSet> {
+        &self.rules
+    }
+
+    /// Answers LLM requests with low-confidence placeholders instead of
+    /// calling the provider, auditing each as if it used the model's
+    /// `max_tokens`, so a run's report estimates its worst-case cost.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Enables `snapshot(name, value)` with snapshots stored as configured.
+    pub fn with_snapshots(mut self, snapshots: Snapshots) -> Self {
+        self.snapshots = Some(Arc::new(snapshots));
+        self
+    }
+
+    pub fn snapshots(&self) -> Option<&Snapshots> {
+        self.snapshots.as_deref()
+    }
+
+    pub fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_deref()
+    }
+
+    pub fn replay(&self) -> Option<&Replay> {
+        self.replay.as_deref()
+    }
+
+    pub fn input(&self) -> &dyn InputSource {
+        &*self.input
+    }
+
+    /// Writes script output to the sink with secrets redacted; natives that
+    /// print go through here.
+    pub fn write_output(&self, text: &str) {
+        self.output.write(&self.secrets.redact(text));
+    }
+
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Calls `listener` with the payload of every `name` event a script emits.
+    pub fn on(&self, name: impl Into<String>, listener: impl Fn(&Value) + Send + Sync + 'static) {
+        let listener: EventListener = Arc::new(listener);
+        self.events.listen(name, listener);
+    }
+
+    /// Delivers an event to the host listeners and then runs the script
+    /// handlers subscribed with `events.on`, in order. Returns how many
+    /// script handlers ran; the first failing handler stops delivery.
+    pub async fn emit(&mut self, name: &str, payload: Value) -> Result<usize> {
+        self.events.notify_listeners(name, &payload);
+        let handlers = self.events.handlers(name);
+        for handler in &handlers {
+            self.call(handler.clone(), vec![payload.clone()]).await?;
         }
+        Ok(handlers.len())
+    }
+
+    /// Diagnostics produced by the static checks of the last evaluation.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Runtime counters shared with forks; natives record into these.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Statements, calls, LLM latency, cache hits and errors so far.
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Side effects of the last evaluation, kept when it failed or was cancelled.
+    pub fn audit_log(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    /// Defines a global visible to every program this interpreter runs.
+    pub fn define_global(&mut self, name: impl Into<String>, value: Value) -> Result<()> {
+        self.globals().define(name.into(), value)
+    }
+
+    /// Every global with its value, sorted by name: the standard library
+    /// modules, the prelude and what programs defined at the top level.
+    pub fn global_values(&self) -> Vec<(String, Value)> {
+        let mut values = self.globals().entries();
+        values.sort_by(|(a, _), (b, _)| a.cmp(b));
+        values
+    }
+
+    fn globals(&self) -> Arc<Environment> {
+        self.environment.root()
     }
 
     pub async fn evaluate(&mut self, source: String) -> Result<Value> {
-        let statements = crate::parser::parse(&source)?;
-        let mut result = Value::new(ValueKind::Nil);
-        for stmt in statements {
-            result = self.execute_statement(&stmt).await?;
+        self.evaluate_cancellable(source, CancellationToken::new()).await
+    }
+
+    /// Like [`evaluate`](Self::evaluate), but gives up with
+    /// [`PrismError::Cancelled`] once `timeout` has passed.
+    pub async fn evaluate_with_timeout(&mut self, source: String, timeout: Duration) -> Result<Value> {
+        self.evaluate_cancellable(source, CancellationToken::with_timeout(timeout)).await
+    }
+
+    /// Evaluates `source` until it finishes or `token` is cancelled, in which
+    /// case it fails with [`PrismError::Cancelled`]. Either way
+    /// [`audit_log`](Self::audit_log) holds what the script did.
+    pub async fn evaluate_cancellable(&mut self, source: String, token: CancellationToken) -> Result<Value> {
+        self.evaluate_with(Program::Source(source), token).await
+    }
+
+    /// Evaluates a program built in code, e.g. with
+    /// [`ExprBuilder`](crate::ast::ExprBuilder), without printing and
+    /// re-parsing it. It is resolved and checked like parsed source.
+    pub async fn evaluate_program(&mut self, statements: Vec<Stmt>) -> Result<Value> {
+        self.evaluate_with(Program::Statements(statements), CancellationToken::new()).await
+    }
+
+    async fn evaluate_with(&mut self, program: Program, token: CancellationToken) -> Result<Value> {
+        self.cancellation = token;
+        self.audit.clear();
+        let span = telemetry::span("prism.evaluate");
+        if let Program::Source(source) = &program {
+            span.set("prism.source.bytes", source.len() as i64);
+        }
+        let result = span.run(self.run(program)).await;
+        match &result {
+            Ok(value) => span.set("prism.confidence", value.confidence),
+            Err(err) => span.fail(err),
         }
-        Ok(result)
+        // The token only governs this evaluation, not later host calls like `emit`
+        self.cancellation = CancellationToken::new();
+        if let Err(err) = &result {
+            self.metrics.record_error(err);
+        }
+        self.metrics.publish();
+        let secrets = self.secrets.clone();
+        result.map_err(|err| err.map_message(|msg| secrets.redact(&msg).into_owned()))
     }
 
-    fn execute_statement<'a>(&'a mut self, stmt: &'a Stmt) -> Pin<Box<dyn Future<Output = Result<Value>> + Send + 'a>> {
-        Box::pin(async move {
-            match stmt {
-                Stmt::Expression(expr) => {
-                    println!("Executing expression: {:?}", expr);
-                    self.evaluate_expression(expr).await
-                },
-                Stmt::Let(name, initializer) => {
-                    println!("Declaring variable: {} with initializer: {:?}", name, initializer);
-                    let value = if let Some(init) = initializer {
-                        let val = self.evaluate_expression(init).await?;
-                        println!("Initialized {} with value: {:?}", name, val);
-                        val
-                    } else {
-                        Value::new(ValueKind::Nil)
-                    };
-                    self.environment.write().define(name.clone(), value.clone())?;
-                    Ok(value)
-                },
-                Stmt::If { condition, then_branch, else_branch } => {
-                    println!("Executing if statement with condition: {:?}", condition);
-                    let cond_value = self.evaluate_expression(condition).await?;
-                    
-                    match cond_value.kind {
-                        ValueKind::Boolean(true) => {
-                            println!("Condition is true, executing then branch");
-                            self.execute_statement(then_branch).await
-                        },
-                        ValueKind::Boolean(false) => {
-                            if let Some(else_stmt) = else_branch {
-                                println!("Condition is false, executing else branch");
-                                self.execute_statement(else_stmt).await
-                            } else {
-                                println!("Condition is false, no else branch");
-                                Ok(Value::new(ValueKind::Nil))
-                            }
-                        },
-                        _ => Err(PrismError::RuntimeError(format!("Condition must be a boolean, got {:?}", cond_value.kind))),
-                    }
-                },
-                Stmt::Block(statements) => {
-                    println!("Executing block with {} statements", statements.len());
-                    // Create a new environment for this block
-                    let previous = Arc::clone(&self.environment);
-                    self.environment = Arc::new(RwLock::new(Environment::with_enclosing(previous)));
-                    
-                    let mut result = Value::new(ValueKind::Nil);
-                    for stmt in statements {
-                        result = self.execute_statement(stmt).await?;
-                    }
-                    
-                    // Restore the previous environment
-                    let enclosing = {
-                        let env = self.environment.read();
-                        env.get_enclosing()
-                    };
-                    
-                    if let Some(parent_env) = enclosing {
-                        self.environment = parent_env;
-                    }
-                    Ok(result)
-                },
-                Stmt::Function { name, params, body: _, is_async: _, confidence } => {
-                    let closure = Arc::clone(&self.environment);
-                    let params = params.clone();
-                    let mut function = Value::new(ValueKind::Function {
-                        name: name.clone(),
-                        params: params.clone(),
-                        body: Arc::new(move |args| {
-                            let mut env = Environment::with_enclosing(Arc::clone(&closure));
-                            for (param, arg) in params.iter().zip(args) {
-                                env.define(param.clone(), arg)?;
-                            }
-                            Ok(Value::new(ValueKind::Nil)) // Placeholder
-                        }),
-                    });
-                    if let Some(conf) = confidence {
-                        function.set_confidence(*conf);
-                    }
-                    self.environment.write().define(name.clone(), function.clone())?;
-                    Ok(function)
-                },
-                _ => Ok(Value::new(ValueKind::Nil)), // Handle other statement types
+    async fn run(&mut self, program: Program) -> Result<Value> {
+        let mut statements = match program {
+            Program::Source(source) => {
+                let parsing = telemetry::span("prism.parse");
+                let statements = crate::parser::parse(&source).inspect_err(|err| parsing.fail(err))?;
+                parsing.end();
+                statements
             }
-        })
+            Program::Statements(statements) => statements,
+        };
+        crate::cfg::apply(&mut statements, &self.cfg);
+
+        let checking = telemetry::span("prism.check");
+        let globals = self.environment.names();
+        self.diagnostics = self.remap(crate::resolver::resolve(&mut statements, globals));
+        if let Some(errors) = Self::errors(&self.diagnostics) {
+            let err = PrismError::ResolveError(errors);
+            checking.fail(&err);
+            return Err(err);
+        }
+
+        self.diagnostics = self.remap(crate::checker::check(&statements));
+        if let Some(errors) = Self::errors(&self.diagnostics) {
+            let err = PrismError::TypeError(errors);
+            checking.fail(&err);
+            return Err(err);
+        }
+        for warning in self.diagnostics.iter().filter(|d| !d.is_error()) {
+            log::warn!("{}", self.secrets.redact(&warning.to_string()));
+        }
+        checking.end();
+
+        self.load_file_modules(&statements).await?;
+        let executing = telemetry::span("prism.execute");
+        let result = executing.run(self.execute_program(&statements)).await;
+        if let Err(err) = &result {
+            executing.fail(err);
+        }
+        result
     }
 
-    fn evaluate_expression<'a>(&'a self, expr: &'a Expr) -> Pin<Box<dyn Future<Output = Result<Value>> + Send + 'a>> {
-        Box::pin(async move {
-            match expr {
-                Expr::Literal(value) => {
-                    println!("Evaluating literal: {:?}", value);
-                    Ok(value.clone())
-                },
-                Expr::Variable(name) => {
-                    println!("Looking up variable: {}", name);
-                    let val = self.environment.read().get(name)?;
-                    println!("Found value: {:?}", val);
-                    Ok(val)
-                },
-                Expr::Grouping(expr) => {
-                    println!("Evaluating grouped expression: {:?}", expr);
-                    self.evaluate_expression(expr).await
-                },
-                Expr::Binary { left, operator, right } => {
-                    println!("Evaluating binary expression: {:?} {:?} {:?}", left, operator, right);
-                    let left = self.evaluate_expression(left).await?;
-                    let right = self.evaluate_expression(right).await?;
-                    println!("Binary operands: {:?} {:?}", left, right);
-                    
-                    match (&left.kind, &right.kind) {
-                        // Numeric operations
-                        (ValueKind::Number(l), ValueKind::Number(r)) => {
-                            let result = match operator.kind {
-                                TokenKind::Plus => Value::new(ValueKind::Number(l + r)),
-                                TokenKind::Minus => Value::new(ValueKind::Number(l - r)),
-                                TokenKind::Star => Value::new(ValueKind::Number(l * r)),
-                                TokenKind::Slash => Value::new(ValueKind::Number(l / r)),
-                                // Comparison operators
-                                TokenKind::Greater => Value::new(ValueKind::Boolean(l > r)),
-                                TokenKind::GreaterEqual => Value::new(ValueKind::Boolean(l >= r)),
-                                TokenKind::Less => Value::new(ValueKind::Boolean(l < r)),
-                                TokenKind::LessEqual => Value::new(ValueKind::Boolean(l <= r)),
-                                TokenKind::EqualEqual => Value::new(ValueKind::Boolean(l == r)),
-                                TokenKind::BangEqual => Value::new(ValueKind::Boolean(l != r)),
-                                _ => return Err(PrismError::RuntimeError("Invalid operator for numbers".to_string())),
-                            };
-                            println!("Binary result: {:?}", result);
-                            Ok(result)
-                        },
-                        // Boolean operations
-                        (ValueKind::Boolean(l), ValueKind::Boolean(r)) => {
-                            let result = match operator.kind {
-                                TokenKind::And => Value::new(ValueKind::Boolean(*l && *r)),
-                                TokenKind::Or => Value::new(ValueKind::Boolean(*l || *r)),
-                                TokenKind::EqualEqual => Value::new(ValueKind::Boolean(l == r)),
-                                TokenKind::BangEqual => Value::new(ValueKind::Boolean(l != r)),
-                                _ => return Err(PrismError::RuntimeError("Invalid operator for booleans".to_string())),
-                            };
-                            println!("Binary result: {:?}", result);
-                            Ok(result)
-                        },
-                        // String operations
-                        (ValueKind::String(l), ValueKind::String(r)) => {
-                            let result = match operator.kind {
-                                TokenKind::Plus => Value::new(ValueKind::String(format!("{}{}", l, r))),
-                                TokenKind::EqualEqual => Value::new(ValueKind::Boolean(l == r)),
-                                TokenKind::BangEqual => Value::new(ValueKind::Boolean(l != r)),
-                                _ => return Err(PrismError::RuntimeError("Invalid operator for strings".to_string())),
-                            };
-                            println!("Binary result: {:?}", result);
-                            Ok(result)
-                        },
-                        // Equality for any type
-                        _ => match operator.kind {
-                            TokenKind::EqualEqual => Ok(Value::new(ValueKind::Boolean(left.kind == right.kind))),
-                            TokenKind::BangEqual => Ok(Value::new(ValueKind::Boolean(left.kind != right.kind))),
-                            _ => Err(PrismError::RuntimeError(format!(
-                                "Invalid operation between {:?} and {:?}",
-                                left.kind, right.kind
-                            ))),
-                        },
-                    }
-                },
-                Expr::Assign { name, value } => {
-                    let value = self.evaluate_expression(value).await?;
-                    self.environment.write().assign(name, value.clone())?;
-                    Ok(value)
-                },
-                Expr::Call { callee, arguments } => {
-                    let callee = self.evaluate_expression(callee).await?;
-                    let mut args = Vec::new();
-                    for arg in arguments {
-                        args.push(self.evaluate_expression(arg).await?);
+    /// Evaluates the file modules `statements` import that are not loaded
+    /// yet; see [`loader`](crate::loader).
+    async fn load_file_modules(&mut self, statements: &[Stmt]) -> Result<()> {
+        let globals = self.globals().names();
+        if crate::loader::file_imports(statements, &self.module_dir, &self.module_paths, &globals).is_empty() {
+            return Ok(());
+        }
+        self.capabilities.require(Capability::Fs, "import")?;
+        let loaded: HashSet<PathBuf> = self.file_modules.read().keys().cloned().collect();
+        let modules = crate::loader::discover(statements, &self.module_dir, &self.module_paths, &loaded, &globals, &self.cfg)?;
+
+        for wave in crate::loader::waves(modules, &loaded)? {
+            let paths: Vec<PathBuf> = wave.iter().map(|module| module.path.clone()).collect();
+            let globals = self.global_values();
+            let tasks: Vec<Task> = wave
+                .into_iter()
+                .map(|module| {
+                    let mut fork = self.fork();
+                    fork.environment = Arc::new(Environment::with_values(globals.iter().cloned()));
+                    fork.module_dir = module.path.parent().map(Path::to_path_buf).unwrap_or_default();
+                    let task: Task = Box::pin(async move {
+                        let result = fork.evaluate_file_module(&module.path, &module.statements).await;
+                        (fork, result)
+                    });
+                    task
+                })
+                .collect();
+
+            let mut error = None;
+            drive(tasks, paths.len(), |index, fork, result| {
+                self.join(fork);
+                match result {
+                    Ok(module) => {
+                        self.file_modules.write().insert(paths[index].clone(), module);
+                        false
                     }
-                    match callee.kind {
-                        ValueKind::Function { ref body, .. } => body(args),
-                        ValueKind::NativeFunction { ref handler, .. } => handler(args),
-                        _ => Err(PrismError::RuntimeError("Not a callable value".to_string())),
+                    Err(err) => {
+                        error = Some(err);
+                        true
                     }
                 }
-                _ => Ok(Value::new(ValueKind::Nil)), // Handle other expression types
+            })
+            .await;
+            if let Some(err) = error {
+                return Err(err);
             }
-        })
+        }
+        Ok(())
+    }
+
+    /// Runs a file module in this (fresh) environment and collects its exports.
+    async fn evaluate_file_module(&mut self, path: &Path, statements: &[Stmt]) -> Result<Value> {
+        self.execute_program(statements)
+            .await
+            .map_err(|err| err.map_message(|msg| format!("{}: {}", path.display(), msg)))?;
+        let name = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        let mut module = Module::new(name);
+        for name in crate::loader::exported_names(statements) {
+            module.export(name.clone(), self.environment.get(&name)?)?;
+        }
+        Ok(Value::new(ValueKind::Module(Arc::new(RwLock::new(module)))))
+    }
+
+    /// Binds the names an `import` statement lists.
+    fn import(&mut self, spec: &str, imports: &[(String, Option<String>)]) -> Result<Flow> {
+        let globals = self.globals();
+        let path = crate::loader::module_path(spec, &self.module_dir, &self.module_paths, |name| globals.get(name).is_ok());
+        let source = match path {
+            Some(path) => self.file_modules.read().get(&path).cloned(),
+            None => globals.get(spec).ok(),
+        };
+        let Some(Value { kind: ValueKind::Module(module), .. }) = source else {
+            return Err(PrismError::ModuleNotFound(spec.to_string()));
+        };
+        for (name, alias) in imports {
+            let value = module
+                .read()
+                .get_export(name)
+                .map_err(|_| PrismError::UndefinedVariable(format!("{} (not exported by '{}')", name, spec)))?;
+            self.define_variable(alias.as_ref().unwrap_or(name), value)?;
+        }
+        Ok(Flow::Normal(Value::new(ValueKind::Nil)))
+    }
+
+    async fn execute_program(&mut self, statements: &[Stmt]) -> Result<Value> {
+        let previous = std::mem::replace(&mut self.sync, Arc::new(SyncNodes::of(statements)));
+        let mut result = Ok(Value::new(ValueKind::Nil));
+        for stmt in statements {
+            match self.exec(stmt).await {
+                Ok(Flow::Normal(value)) => result = Ok(value),
+                Ok(_) => {}
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        self.sync = previous;
+        result
+    }
+
+    /// An interpreter for running a function concurrently with this one. It
+    /// shares the environment, cancellation, capabilities, secrets and I/O;
+    /// hand it back to [`join`](Self::join) to keep its audit entries.
+    pub fn fork(&self) -> Interpreter {
+        Interpreter {
+            environment: Arc::clone(&self.environment),
+            frame: Frame::default(),
+            diagnostics: Vec::new(),
+            call_depth: self.call_depth,
+            max_call_depth: self.max_call_depth,
+            max_loop_iterations: self.max_loop_iterations,
+            yield_interval: self.yield_interval,
+            ticks: 0,
+            cancellation: self.cancellation.clone(),
+            audit: AuditLog::new(),
+            capabilities: self.capabilities.clone(),
+            cfg: self.cfg.clone(),
+            secrets: self.secrets.clone(),
+            output: Arc::clone(&self.output),
+            input: Arc::clone(&self.input),
+            events: self.events.clone(),
+            progress: self.progress.clone(),
+            metrics: self.metrics.clone(),
+            recorder: self.recorder.clone(),
+            replay: self.replay.clone(),
+            request_queue: Arc::clone(&self.request_queue),
+            calibration: Arc::clone(&self.calibration),
+            last_response_id: None,
+            llm_cache: self.llm_cache.clone(),
+            llm_clients: Arc::clone(&self.llm_clients),
+            embedder: self.embedder.clone(),
+            rules: Arc::clone(&self.rules),
+            dry_run: self.dry_run,
+            snapshots: self.snapshots.clone(),
+            source_map: self.source_map.clone(),
+            module_dir: self.module_dir.clone(),
+            module_paths: Arc::clone(&self.module_paths),
+            combine: self.combine,
+            env_vars: Arc::clone(&self.env_vars),
+            args: Arc::clone(&self.args),
+            model_config: self.model_config.clone(),
+            profiles: Arc::clone(&self.profiles),
+            contexts: self.contexts.clone(),
+            context_models: Arc::clone(&self.context_models),
+            file_modules: Arc::clone(&self.file_modules),
+            generator: None,
+            sync: Arc::default(),
+            owns_globals: false,
+        }
+    }
+
+    /// Takes over what a [`fork`](Self::fork) recorded.
+    pub fn join(&mut self, mut fork: Interpreter) {
+        self.audit.append(&mut fork.audit);
+    }
+
+    /// Calls a script or native function value; async natives use this to
+    /// call the functions scripts pass them.
+    pub async fn call(&mut self, callee: Value, args: Vec<Value>) -> Result<Value> {
+        self.call_function(callee, args).await
+    }
+
+    /// Calls the global function `name` with `input` converted to a Prism
+    /// value through its JSON form, and converts the result back into `R`
+    /// alongside the result's confidence. A result that does not have `R`'s
+    /// shape is a type error.
+    pub async fn call_typed<T, R>(&mut self, name: &str, input: &T) -> Result<(R, f64)>
+    where
+        T: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        let callee = self
+            .global_values()
+            .into_iter()
+            .find(|(global, _)| global == name)
+            .map(|(_, value)| value)
+            .ok_or_else(|| PrismError::UndefinedVariable(name.to_string()))?;
+        let input = Value::from_json(&serde_json::to_value(input)?);
+        let result = self.call_function(callee, vec![input]).await?;
+        let typed = serde_json::from_value(result.to_json()?).map_err(|err| {
+            PrismError::TypeError(format!("The result of {} does not match the expected type: {}", name, err))
+        })?;
+        Ok((typed, result.confidence))
+    }
+
+    /// Runs `f` with the evaluation's deadline brought forward to at most
+    /// `timeout` from now. `Ok(None)` means the limit was hit; cancellation by
+    /// the host or the evaluation's own deadline is still an error.
+    pub async fn with_time_limit<'a, F>(&'a mut self, timeout: Duration, f: F) -> Result<Option<Value>>
+    where
+        F: for<'i> FnOnce(&'i mut Interpreter) -> NativeFuture<'i>,
+    {
+        let outer = self.cancellation.clone();
+        self.cancellation = outer.limited_to(timeout);
+        let result = f(self).await;
+        self.cancellation = outer;
+        match result {
+            Err(PrismError::Cancelled(_)) if self.cancellation.reason().is_none() => Ok(None),
+            result => result.map(Some),
+        }
+    }
+
+    /// Waits for `duration` without blocking other tasks on the runtime,
+    /// waking up regularly to honour cancellation. Builds without tokio, such
+    /// as wasm, have no timer to await and block in short slices instead.
+    pub async fn sleep(&mut self, duration: Duration) -> Result<()> {
+        const SLICE: Duration = Duration::from_millis(10);
+        let deadline = std::time::Instant::now() + duration;
+        loop {
+            self.check_cancelled("sleep")?;
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            if remaining.is_zero() {
+                return Ok(());
+            }
+            #[cfg(feature = "native")]
+            tokio::time::sleep(remaining.min(SLICE)).await;
+            #[cfg(not(feature = "native"))]
+            std::thread::sleep(remaining.min(SLICE));
+        }
+    }
+
+    /// The token governing the running evaluation, for natives that start
+    /// evaluations of their own.
+    pub(crate) fn cancellation(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// Cancellation point; `at` names where the script was stopped.
+    pub(crate) fn check_cancelled(&mut self, at: &str) -> Result<()> {
+        match self.cancellation.reason() {
+            Some(reason) => {
+                let reason = format!("{} at {}", reason, at);
+                self.record(AuditEvent::Cancelled { reason: reason.clone() });
+                Err(PrismError::Cancelled(reason))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Checks for cancellation at a loop back-edge or call, and every
+    /// [yield interval](Self::with_yield_interval) lets other tasks run.
+    /// Builds without tokio run one evaluation at a time and only check.
+    /// Fails once a loop runs more iterations than
+    /// [allowed](Self::with_max_loop_iterations).
+    fn check_iterations(&self, iterations: usize) -> Result<()> {
+        match self.max_loop_iterations {
+            Some(limit) if iterations > limit => Err(PrismError::RuntimeError(format!(
+                "Loop exceeded the limit of {} iterations",
+                limit
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    async fn checkpoint(&mut self, at: &str) -> Result<()> {
+        self.check_cancelled(at)?;
+        self.ticks += 1;
+        if self.yield_interval > 0 && self.ticks >= self.yield_interval {
+            self.ticks = 0;
+            #[cfg(feature = "native")]
+            tokio::task::yield_now().await;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn record(&mut self, event: AuditEvent) {
+        self.audit.record(event.redacted(&self.secrets));
+    }
+
+    /// Where `expr` is, for an error frame: its line, or where the source
+    /// map says that line came from.
+    fn at_line(&self, expr: &Expr) -> String {
+        let Some(line) = expr.line() else { return String::new() };
+        match self.source_map.as_ref().and_then(|map| map.original(line)) {
+            Some(origin) => format!(" at {}", origin),
+            None => format!(" at line {}", line),
+        }
+    }
+
+    fn remap(&self, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        match &self.source_map {
+            Some(map) => diagnostics.into_iter().map(|d| map.remap(d)).collect(),
+            None => diagnostics,
+        }
+    }
+
+    fn errors(diagnostics: &[Diagnostic]) -> Option<String> {
+        let errors: Vec<String> = diagnostics
+            .iter()
+            .filter(|d| d.is_error())
+            .map(|d| d.to_string())
+            .collect();
+        (!errors.is_empty()).then(|| errors.join("\n"))
+    }
+
+    /// Globals are looked up by name; locals live in the slots the resolver assigned.
+    fn define_variable(&mut self, name: &str, value: Value) -> Result<()> {
+        if self.frame.in_scope() {
+            self.frame.define(value);
+            Ok(())
+        } else {
+            self.environment.define(name.to_string(), value)
+        }
+    }
+
+    /// Takes the slot of a local `let` before its initializer runs, as the
+    /// resolver numbers it before any locals the initializer binds, such as
+    /// a match arm's.
+    fn reserve_local(&mut self) -> Option<usize> {
+        self.frame.in_scope().then(|| self.frame.define(Value::new(ValueKind::Nil)))
+    }
+
+    /// Gives a `let` its value, in the `slot` [`reserve_local`](Self::reserve_local) took for a local.
+    fn initialize_variable(&mut self, name: &str, slot: Option<usize>, value: Value) -> Result<()> {
+        match slot {
+            Some(slot) => self.frame.set(slot, value),
+            None => self.environment.define(name.to_string(), value),
+        }
+    }
+
+    /// Runs `stmt`, staying off the async machinery when nothing in it can suspend.
+    async fn exec(&mut self, stmt: &Stmt) -> Result<Flow> {
+        if self.sync.stmt(stmt).unwrap_or_else(|| purity::is_sync_stmt(stmt)) {
+            self.execute_sync(stmt)
+        } else {
+            self.metrics.record_statement();
+            self.execute_statement(stmt).await
+        }
+    }
+
+    /// Evaluates `expr`, staying off the async machinery when nothing in it can suspend.
+    async fn eval(&mut self, expr: &Expr) -> Result<Value> {
+        if self.sync.expr(expr).unwrap_or_else(|| purity::is_sync_expr(expr)) {
+            self.evaluate_sync(expr)
+        } else {
+            self.evaluate_expression(expr).await
+        }
+    }
+
+    fn execute_statement<'a>(&'a mut self, stmt: &'a Stmt) -> Pin<Box<dyn Future<Output = Result<Flow>> + Send + 'a>> {
+        Box::pin(async move {
+            match stmt {
+                Stmt::Expression(expr) => Ok(Flow::Normal(self.eval(expr).await?)),
+                Stmt::Let { name, initializer, .. } => {
+                    let slot = self.reserve_local();
+                    let value = match initializer {
+                        Some(init) => self.eval(init).await?,
+                        None => Value::new(ValueKind::Nil),
+                    };
+                    self.initialize_variable(name, slot, value.clone())?;
+                    Ok(Flow::Normal(value))
+                },
+                Stmt::If { condition, then_branch, else_branch } => {
+                    let condition = self.eval(condition).await?;
+                    match if_branch(&condition, then_branch, else_branch)? {
+                        Some(branch) => self.exec(branch).await,
+                        None => Ok(Flow::Normal(Value::new(ValueKind::Nil))),
+                    }
+                },
+                Stmt::UncertainIf { condition, high_threshold, medium_threshold, then_branch, medium_branch, low_branch } => {
+                    let condition = self.eval(condition).await?;
+                    let branch = uncertain_branch(
+                        &condition,
+                        (*high_threshold, then_branch),
+                        (*medium_threshold, medium_branch),
+                        low_branch,
+                    );
+                    match branch {
+                        Some(branch) => self.exec(branch).await,
+                        None => Ok(Flow::Normal(Value::new(ValueKind::Nil))),
+                    }
+                },
+                Stmt::Block(statements) => {
+                    self.frame.enter();
+                    let result = self.execute_block(statements).await;
+                    // Leave the scope also when the block failed
+                    self.frame.leave();
+                    result
+                },
+                Stmt::While { condition, body } => {
+                    let mut iterations = 0;
+                    loop {
+                        let condition = self.eval(condition).await?;
+                        if !condition_holds(&condition)? {
+                            return Ok(Flow::Normal(Value::new(ValueKind::Nil)));
+                        }
+                        iterations += 1;
+                        self.check_iterations(iterations)?;
+                        match self.exec(body).await? {
+                            Flow::Normal(_) | Flow::Continue => {}
+                            Flow::Break => return Ok(Flow::Normal(Value::new(ValueKind::Nil))),
+                            flow => return Ok(flow),
+                        }
+                        self.checkpoint("loop back-edge").await?;
+                    }
+                },
+                Stmt::For { iterable, body, .. } => {
+                    let iterable = self.eval(iterable).await?;
+                    let mut iteration = Iteration::of(&iterable)?;
+                    let mut iterations = 0;
+                    while let Some(item) = iteration.next(self).await? {
+                        iterations += 1;
+                        self.check_iterations(iterations)?;
+                        self.frame.enter();
+                        self.frame.define(item);
+                        let flow = self.exec(body).await.map(Flow::discard_value);
+                        // Leave the scope also when the body failed
+                        self.frame.leave();
+                        match flow? {
+                            Flow::Normal(_) | Flow::Continue => {}
+                            Flow::Break => break,
+                            flow => return Ok(flow),
+                        }
+                        self.checkpoint("loop back-edge").await?;
+                    }
+                    Ok(Flow::Normal(Value::new(ValueKind::Nil)))
+                },
+                Stmt::Function { .. } => self.execute_sync(stmt),
+                Stmt::Yield(value) => {
+                    let value = self.eval(value).await?;
+                    let Some(slot) = self.generator.clone() else {
+                        return Err(PrismError::RuntimeError("yield outside of a generator".to_string()));
+                    };
+                    slot.yield_value(value).await;
+                    self.check_cancelled("yield")?;
+                    Ok(Flow::Normal(Value::new(ValueKind::Nil)))
+                },
+                Stmt::Return(value) => match value.as_deref() {
+                    Some(Expr::Call { callee: callee_expr, arguments, tail: true }) => {
+                        let callee = self.eval(callee_expr).await?;
+                        let args = self.evaluate_arguments(callee_expr, arguments).await?;
+                        Ok(Flow::TailCall(callee, args))
+                    },
+                    Some(value) => Ok(Flow::Return(self.eval(value).await?)),
+                    None => Ok(Flow::Return(Value::new(ValueKind::Nil))),
+                },
+                Stmt::Import { module, imports, .. } => self.import(module, imports),
+                Stmt::Context { name, body } => {
+                    self.contexts.push(name.clone());
+                    let flow = self.exec(body).await;
+                    self.contexts.pop();
+                    flow
+                },
+                _ => Ok(Flow::Normal(Value::new(ValueKind::Nil))), // Handle other statement types
+            }
+        })
+    }
+
+    async fn execute_block(&mut self, statements: &[Stmt]) -> Result<Flow> {
+        let mut result = Value::new(ValueKind::Nil);
+        for stmt in statements {
+            match self.exec(stmt).await? {
+                Flow::Normal(value) => result = value,
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal(result))
+    }
+
+    async fn evaluate_arguments(&mut self, callee: &Expr, arguments: &[Expr]) -> Result<Vec<Value>> {
+        let mut args = Vec::with_capacity(arguments.len());
+        for (index, arg) in arguments.iter().enumerate() {
+            let value = self.eval(arg).await.map_err(|err| {
+                let place = if arg.line().is_some() { arg } else { callee };
+                err.in_frame(|| format!("evaluating argument {} of `{}`{}", index + 1, callee_name(callee), self.at_line(place)))
+            })?;
+            args.push(value);
+        }
+        Ok(args)
+    }
+
+    /// Calls `callee`, looping instead of recursing when the body ends in a
+    /// tail call so that tail-recursive functions run in constant depth.
+    async fn call_function(&mut self, mut callee: Value, mut args: Vec<Value>) -> Result<Value> {
+        loop {
+            self.metrics.record_call();
+            let (name, params, body, sync, generator, closure) = match &callee.kind {
+                ValueKind::Function { name, params, body, sync, generator, closure } => {
+                    let closure = closure.environment().ok_or_else(|| {
+                        PrismError::RuntimeError(format!("The environment {} closes over is gone", name))
+                    })?;
+                    (name.clone(), params.len(), Arc::clone(body), Arc::clone(sync), *generator, closure)
+                },
+                ValueKind::NativeFunction { name, handler, .. } => {
+                    self.check_cancelled(&format!("call to {}", name))?;
+                    self.record(AuditEvent::NativeCall { name: name.clone() });
+                    return isolate(name, || handler(&*self, args));
+                },
+                ValueKind::AsyncNativeFunction { name, handler, .. } => {
+                    self.check_cancelled(&format!("call to {}", name))?;
+                    self.record(AuditEvent::NativeCall { name: name.clone() });
+                    let future = isolate(name, || Ok(handler(self, args)))?;
+                    return Isolated { name, future }.await;
+                },
+                _ => return Err(PrismError::RuntimeError("Not a callable value".to_string())),
+            };
+            if args.len() != params {
+                return Err(PrismError::RuntimeError(format!(
+                    "{} expects {} arguments but got {}",
+                    name, params, args.len()
+                )));
+            }
+            if generator {
+                return Ok(self.start_generator(&name, body, sync, closure, args));
+            }
+            self.checkpoint(&format!("call to {}", name)).await?;
+            if self.call_depth >= self.max_call_depth {
+                return Err(PrismError::RuntimeError(format!(
+                    "Maximum call depth of {} exceeded in {}",
+                    self.max_call_depth, name
+                )));
+            }
+
+            let previous = self.enter_frame(closure, args);
+            let previous_sync = std::mem::replace(&mut self.sync, sync);
+            self.call_depth += 1;
+            let flow = self.exec(&body).await.map(Flow::discard_value);
+            self.call_depth -= 1;
+            self.sync = previous_sync;
+            self.leave_frame(previous);
+
+            match flow? {
+                // The resolver keeps `break` and `continue` inside loops.
+                Flow::Normal(_) | Flow::Break | Flow::Continue => return Ok(Value::new(ValueKind::Nil)),
+                Flow::Return(value) => return Ok(value),
+                Flow::TailCall(next, next_args) => {
+                    callee = next;
+                    args = next_args;
+                },
+            }
+        }
+    }
+
+    /// The iterator for a call of a generator function; its body runs in a
+    /// fork as the iterator is consumed.
+    fn start_generator(&self, name: &str, body: Arc<Stmt>, sync: Arc<SyncNodes>, closure: Arc<Environment>, args: Vec<Value>) -> Value {
+        let slot = Arc::new(generator::Slot::default());
+        let mut fork = self.fork();
+        fork.generator = Some(Arc::clone(&slot));
+        fork.sync = sync;
+        fork.environment = closure;
+        fork.frame = Frame::new(args);
+        fork.call_depth += 1;
+        let run = Box::pin(async move {
+            let result = match fork.exec(&body).await {
+                // `return f(x);` in a generator still has to run `f`
+                Ok(Flow::TailCall(callee, args)) => fork.call_function(callee, args).await.map(drop),
+                Ok(_) => Ok(()),
+                Err(err) => Err(err),
+            };
+            fork.frame.leave_all();
+            (fork, result)
+        });
+        generator::generator(name, slot, run)
+    }
+
+    fn evaluate_expression<'a>(&'a mut self, expr: &'a Expr) -> Pin<Box<dyn Future<Output = Result<Value>> + Send + 'a>> {
+        Box::pin(async move {
+            match expr {
+                Expr::Grouping(expr) => self.eval(expr).await,
+                Expr::Binary { left, operator, right } => {
+                    let left = self.eval(left).await?;
+                    let right = self.eval(right).await?;
+                    binary(operator, left, right)
+                },
+                Expr::Unary { operator, right } => {
+                    let right = self.eval(right).await?;
+                    unary(operator, right)
+                },
+                Expr::Assign { name, value, slot } => {
+                    let value = self.eval(value).await?;
+                    self.assign_variable(name, *slot, value.clone())?;
+                    Ok(value)
+                },
+                Expr::Call { callee: callee_expr, arguments, .. } => {
+                    let callee = self.eval(callee_expr).await?;
+                    let args = self.evaluate_arguments(callee_expr, arguments).await?;
+                    self.call_function(callee, args)
+                        .await
+                        .map_err(|err| err.in_frame(|| format!("calling `{}`{}", callee_name(callee_expr), self.at_line(callee_expr))))
+                }
+                Expr::Logical { left, operator, right } => {
+                    let left = self.eval(left).await?;
+                    if short_circuits(operator, &left)? {
+                        return Ok(left);
+                    }
+                    let right = self.eval(right).await?;
+                    logical_operand(&right)?;
+                    Ok(right)
+                }
+                Expr::Get { object, name, optional } => {
+                    let object = self.eval(object).await?;
+                    get_property(&object, name, *optional)
+                }
+                Expr::List(items) => {
+                    let mut values = Vec::with_capacity(items.len());
+                    for item in items {
+                        values.push(self.eval(item).await?);
+                    }
+                    Ok(Value::new(ValueKind::List(values)))
+                }
+                Expr::Map(entries) => {
+                    let mut values = Vec::with_capacity(entries.len());
+                    for (key, value) in entries {
+                        let key = self.eval(key).await?;
+                        let value = self.eval(value).await?;
+                        values.push((key, value));
+                    }
+                    Ok(Value::new(ValueKind::Map(values)))
+                }
+                Expr::Confidence { expr, confidence } => {
+                    let mut value = self.eval(expr).await?;
+                    value.set_confidence(*confidence);
+                    Ok(value)
+                }
+                Expr::Match { subject, arms } => {
+                    let subject = self.eval(subject).await?;
+                    for arm in arms.iter().filter(|arm| pattern_matches(&arm.pattern, &subject)) {
+                        let scoped = self.enter_arm(arm, &subject);
+                        let result = self.evaluate_guarded_arm(arm).await;
+                        self.leave_arm(scoped);
+                        if let Some(value) = result? {
+                            return Ok(value);
+                        }
+                    }
+                    Err(no_match(&subject))
+                }
+                _ => self.evaluate_sync(expr),
+            }
+        })
+    }
+
+    async fn evaluate_guarded_arm(&mut self, arm: &MatchArm) -> Result<Option<Value>> {
+        if let Some(guard) = &arm.guard {
+            let guard = self.eval(guard).await?;
+            if !guard_passes(&guard)? {
+                return Ok(None);
+            }
+        }
+        self.eval(&arm.body).await.map(Some)
+    }
+
+    /// Synchronous twin of [`Self::execute_statement`] for statements that
+    /// [`purity::is_sync_stmt`] accepts.
+    fn execute_sync(&mut self, stmt: &Stmt) -> Result<Flow> {
+        self.metrics.record_statement();
+        match stmt {
+            Stmt::Expression(expr) => Ok(Flow::Normal(self.evaluate_sync(expr)?)),
+            Stmt::Let { name, initializer, .. } => {
+                let slot = self.reserve_local();
+                let value = match initializer {
+                    Some(init) => self.evaluate_sync(init)?,
+                    None => Value::new(ValueKind::Nil),
+                };
+                self.initialize_variable(name, slot, value.clone())?;
+                Ok(Flow::Normal(value))
+            },
+            Stmt::If { condition, then_branch, else_branch } => {
+                let condition = self.evaluate_sync(condition)?;
+                match if_branch(&condition, then_branch, else_branch)? {
+                    Some(branch) => self.execute_sync(branch),
+                    None => Ok(Flow::Normal(Value::new(ValueKind::Nil))),
+                }
+            },
+            Stmt::UncertainIf { condition, high_threshold, medium_threshold, then_branch, medium_branch, low_branch } => {
+                let condition = self.evaluate_sync(condition)?;
+                let branch = uncertain_branch(
+                    &condition,
+                    (*high_threshold, then_branch),
+                    (*medium_threshold, medium_branch),
+                    low_branch,
+                );
+                match branch {
+                    Some(branch) => self.execute_sync(branch),
+                    None => Ok(Flow::Normal(Value::new(ValueKind::Nil))),
+                }
+            },
+            Stmt::Block(statements) => {
+                self.frame.enter();
+                let result = statements.iter().try_fold(Flow::Normal(Value::new(ValueKind::Nil)), |flow, stmt| {
+                    match flow {
+                        Flow::Normal(_) => self.execute_sync(stmt),
+                        done => Ok(done),
+                    }
+                });
+                self.frame.leave();
+                result
+            },
+            Stmt::Function { name, params, body, confidence, captures, .. } => {
+                // The function's own slot comes first, so it can close over it
+                let slot = self.frame.in_scope().then(|| self.frame.define(Value::new(ValueKind::Nil)));
+                let closure = match captures {
+                    Some(captures) => self.frame.capture(captures, &self.environment),
+                    None => self.globals(),
+                };
+                let body = Arc::new((**body).clone());
+                let mut function = Value::new(ValueKind::Function {
+                    name: name.clone(),
+                    params: params.clone(),
+                    sync: Arc::new(SyncNodes::of(std::slice::from_ref(&*body))),
+                    generator: purity::yields(&body),
+                    body,
+                    closure: Closure::new(&closure),
+                });
+                if let Some(conf) = confidence {
+                    function.set_confidence(*conf);
+                }
+                match slot {
+                    Some(slot) => self.frame.set(slot, function.clone())?,
+                    None => self.environment.define(name.clone(), function.clone())?,
+                }
+                Ok(Flow::Normal(function))
+            },
+            Stmt::Return(value) => match value {
+                Some(value) => Ok(Flow::Return(self.evaluate_sync(value)?)),
+                None => Ok(Flow::Return(Value::new(ValueKind::Nil))),
+            },
+            Stmt::Break => Ok(Flow::Break),
+            Stmt::Continue => Ok(Flow::Continue),
+            _ => Err(PrismError::RuntimeError("Statement cannot run synchronously".to_string())),
+        }
+    }
+
+    /// Synchronous twin of [`Self::evaluate_expression`] for expressions that
+    /// [`purity::is_sync_expr`] accepts.
+    fn evaluate_sync(&mut self, expr: &Expr) -> Result<Value> {
+        match expr {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Variable { name, slot, .. } => self.read_variable(name, *slot),
+            Expr::Grouping(expr) => self.evaluate_sync(expr),
+            Expr::Binary { left, operator, right } => {
+                let left = self.evaluate_sync(left)?;
+                let right = self.evaluate_sync(right)?;
+                binary(operator, left, right)
+            },
+            Expr::Unary { operator, right } => {
+                let right = self.evaluate_sync(right)?;
+                unary(operator, right)
+            },
+            Expr::Assign { name, value, slot } => {
+                let value = self.evaluate_sync(value)?;
+                self.assign_variable(name, *slot, value.clone())?;
+                Ok(value)
+            },
+            Expr::Call { .. } => Err(PrismError::RuntimeError("Calls cannot run synchronously".to_string())),
+            Expr::Logical { left, operator, right } => {
+                let left = self.evaluate_sync(left)?;
+                if short_circuits(operator, &left)? {
+                    return Ok(left);
+                }
+                let right = self.evaluate_sync(right)?;
+                logical_operand(&right)?;
+                Ok(right)
+            }
+            Expr::Get { object, name, optional } => {
+                let object = self.evaluate_sync(object)?;
+                get_property(&object, name, *optional)
+            }
+            Expr::List(items) => {
+                let values = items.iter().map(|item| self.evaluate_sync(item)).collect::<Result<_>>()?;
+                Ok(Value::new(ValueKind::List(values)))
+            }
+            Expr::Map(entries) => {
+                let values = entries
+                    .iter()
+                    .map(|(key, value)| Ok((self.evaluate_sync(key)?, self.evaluate_sync(value)?)))
+                    .collect::<Result<_>>()?;
+                Ok(Value::new(ValueKind::Map(values)))
+            }
+            Expr::Confidence { expr, confidence } => {
+                let mut value = self.evaluate_sync(expr)?;
+                value.set_confidence(*confidence);
+                Ok(value)
+            }
+            Expr::Match { subject, arms } => {
+                let subject = self.evaluate_sync(subject)?;
+                for arm in arms.iter().filter(|arm| pattern_matches(&arm.pattern, &subject)) {
+                    let scoped = self.enter_arm(arm, &subject);
+                    let result = self.evaluate_guarded_arm_sync(arm);
+                    self.leave_arm(scoped);
+                    if let Some(value) = result? {
+                        return Ok(value);
+                    }
+                }
+                Err(no_match(&subject))
+            }
+            _ => Ok(Value::new(ValueKind::Nil)), // Handle other expression types
+        }
+    }
+
+    fn evaluate_guarded_arm_sync(&mut self, arm: &MatchArm) -> Result<Option<Value>> {
+        if let Some(guard) = &arm.guard {
+            let guard = self.evaluate_sync(guard)?;
+            if !guard_passes(&guard)? {
+                return Ok(None);
+            }
+        }
+        self.evaluate_sync(&arm.body).map(Some)
+    }
+
+    fn read_variable(&self, name: &str, slot: Option<Slot>) -> Result<Value> {
+        match slot {
+            Some(Slot::Local(index)) => self.frame.get(index),
+            Some(Slot::Captured { depth, index }) => self.environment.get_at(depth, index),
+            None => self.environment.globals().get(name),
+        }
+    }
+
+    fn assign_variable(&mut self, name: &str, slot: Option<Slot>, value: Value) -> Result<()> {
+        match slot {
+            Some(Slot::Local(index)) => self.frame.set(index, value),
+            Some(Slot::Captured { depth, index }) => self.environment.assign_at(depth, index, value),
+            None => self.environment.globals().assign(name, value),
+        }
+    }
+
+    /// Switches to a new frame for a call of a function closing over
+    /// `closure`, returning what to restore afterwards.
+    fn enter_frame(&mut self, closure: Arc<Environment>, args: Vec<Value>) -> (Arc<Environment>, Frame) {
+        let environment = std::mem::replace(&mut self.environment, closure);
+        (environment, std::mem::replace(&mut self.frame, Frame::new(args)))
+    }
+
+    /// Restores the caller's frame after leaving every scope of the call's,
+    /// so scopes kept alive only by functions stored in them are freed.
+    fn leave_frame(&mut self, (environment, frame): (Arc<Environment>, Frame)) {
+        self.environment = environment;
+        std::mem::replace(&mut self.frame, frame).leave_all();
+    }
+
+    /// Binding arms get their own scope holding the subject; see the
+    /// resolver. Returns whether the arm entered one.
+    fn enter_arm(&mut self, arm: &MatchArm, subject: &Value) -> bool {
+        let Pattern::Binding(_) = &arm.pattern else { return false };
+        self.frame.enter();
+        self.frame.define(subject.clone());
+        true
+    }
+
+    fn leave_arm(&mut self, scoped: bool) {
+        if scoped {
+            self.frame.leave();
+        }
+    }
+}
+
+fn binary(operator: &Token, left: Value, right: Value) -> Result<Value> {
+    match (&left.kind, &right.kind) {
+        // Numeric operations
+        (ValueKind::Number(l), ValueKind::Number(r)) => {
+            let result = match operator.kind {
+                TokenKind::Plus => ValueKind::Number(l + r),
+                TokenKind::Minus => ValueKind::Number(l - r),
+                TokenKind::Star => ValueKind::Number(l * r),
+                TokenKind::Slash => ValueKind::Number(l / r),
+                // Comparison operators
+                TokenKind::Greater => ValueKind::Boolean(l > r),
+                TokenKind::GreaterEqual => ValueKind::Boolean(l >= r),
+                TokenKind::Less => ValueKind::Boolean(l < r),
+                TokenKind::LessEqual => ValueKind::Boolean(l <= r),
+                TokenKind::EqualEqual => ValueKind::Boolean(l == r),
+                TokenKind::BangEqual => ValueKind::Boolean(l != r),
+                _ => return Err(PrismError::RuntimeError("Invalid operator for numbers".to_string())),
+            };
+            Ok(Value::new(result))
+        },
+        // Boolean operations
+        (ValueKind::Boolean(l), ValueKind::Boolean(r)) => {
+            let result = match operator.kind {
+                TokenKind::And => *l && *r,
+                TokenKind::Or => *l || *r,
+                TokenKind::EqualEqual => l == r,
+                TokenKind::BangEqual => l != r,
+                _ => return Err(PrismError::RuntimeError("Invalid operator for booleans".to_string())),
+            };
+            Ok(Value::new(ValueKind::Boolean(result)))
+        },
+        // String operations
+        (ValueKind::String(l), ValueKind::String(r)) => {
+            let result = match operator.kind {
+                TokenKind::Plus => ValueKind::String(format!("{}{}", l, r)),
+                TokenKind::EqualEqual => ValueKind::Boolean(l == r),
+                TokenKind::BangEqual => ValueKind::Boolean(l != r),
+                _ => return Err(PrismError::RuntimeError("Invalid operator for strings".to_string())),
+            };
+            Ok(Value::new(result))
+        },
+        // Datetime and duration arithmetic and comparisons
+        (ValueKind::DateTime(_) | ValueKind::Duration(_), _) | (_, ValueKind::DateTime(_) | ValueKind::Duration(_)) => {
+            Ok(Value::new(time::binary(&operator.kind, &left.kind, &right.kind)?))
+        },
+        // Elementwise tensor operations and scaling
+        #[cfg(feature = "tensor")]
+        (ValueKind::Tensor(_), _) | (_, ValueKind::Tensor(_)) => {
+            Ok(Value::new(crate::stdlib::tensor::binary(&operator.kind, &left.kind, &right.kind)?))
+        },
+        // Decimal operations; a number mixed in becomes a decimal
+        #[cfg(feature = "decimal")]
+        (ValueKind::Decimal(l), ValueKind::Decimal(r)) => Ok(Value::new(decimal::binary(&operator.kind, l, r)?)),
+        #[cfg(feature = "decimal")]
+        (ValueKind::Decimal(l), ValueKind::Number(r)) => {
+            Ok(Value::new(decimal::binary(&operator.kind, l, &decimal::from_number(*r)?)?))
+        },
+        #[cfg(feature = "decimal")]
+        (ValueKind::Number(l), ValueKind::Decimal(r)) => {
+            Ok(Value::new(decimal::binary(&operator.kind, &decimal::from_number(*l)?, r)?))
+        },
+        // Equality for any type
+        _ => match operator.kind {
+            TokenKind::EqualEqual => Ok(Value::new(ValueKind::Boolean(left.kind == right.kind))),
+            TokenKind::BangEqual => Ok(Value::new(ValueKind::Boolean(left.kind != right.kind))),
+            _ => Err(PrismError::RuntimeError(format!(
+                "Invalid operation between {:?} and {:?}",
+                left.kind, right.kind
+            ))),
+        },
+    }
+}
+
+fn unary(operator: &Token, right: Value) -> Result<Value> {
+    match (&operator.kind, &right.kind) {
+        (TokenKind::Minus, ValueKind::Number(n)) => Ok(Value::with_confidence(ValueKind::Number(-n), right.confidence)),
+        (TokenKind::Minus, ValueKind::Duration(span)) => Ok(Value::with_confidence(ValueKind::Duration(-*span), right.confidence)),
+        #[cfg(feature = "tensor")]
+        (TokenKind::Minus, ValueKind::Tensor(t)) => {
+            Ok(Value::with_confidence(ValueKind::Tensor(std::sync::Arc::new(-t.as_ref())), right.confidence))
+        },
+        #[cfg(feature = "decimal")]
+        (TokenKind::Minus, ValueKind::Decimal(d)) => Ok(Value::with_confidence(ValueKind::Decimal(-d), right.confidence)),
+        (TokenKind::Bang, ValueKind::Boolean(b)) => Ok(Value::with_confidence(ValueKind::Boolean(!b), right.confidence)),
+        _ => Err(PrismError::RuntimeError(format!(
+            "Invalid operand for unary {:?}: {:?}",
+            operator.kind, right.kind
+        ))),
+    }
+}
+
+fn logical_operand(value: &Value) -> Result<bool> {
+    match value.kind {
+        ValueKind::Boolean(b) => Ok(b),
+        _ => Err(PrismError::RuntimeError(format!("Logical operands must be booleans, got {:?}", value.kind))),
+    }
+}
+
+/// Whether `left` alone decides an `and`/`or`.
+fn short_circuits(operator: &Token, left: &Value) -> Result<bool> {
+    let left = logical_operand(left)?;
+    Ok(match operator.kind {
+        TokenKind::Or => left,
+        _ => !left,
+    })
+}
+
+fn get_property(object: &Value, name: &str, optional: bool) -> Result<Value> {
+    match &object.kind {
+        ValueKind::Nil if optional => Ok(Value::new(ValueKind::Nil)),
+        ValueKind::Map(entries) => {
+            let field = entries.iter().find(|(key, _)| {
+                matches!(&key.kind, ValueKind::String(key) if key == name)
+            });
+            match field {
+                Some((_, value)) => Ok(value.clone()),
+                None if optional => Ok(Value::new(ValueKind::Nil)),
+                None => Err(PrismError::RuntimeError(format!("Undefined property '{}'", name))),
+            }
+        },
+        ValueKind::Module(module) => module.read().get_export(name),
+        _ => Err(PrismError::RuntimeError(format!(
+            "Cannot access property '{}' on {:?}",
+            name, object.kind
+        ))),
+    }
+}
+
+fn condition_holds(condition: &Value) -> Result<bool> {
+    match condition.kind {
+        ValueKind::Boolean(b) => Ok(b),
+        _ => Err(PrismError::RuntimeError(format!("Condition must be a boolean, got {:?}", condition.kind))),
+    }
+}
+
+/// How a call's callee reads in an error frame, e.g. `llm.chat_completion`.
+fn callee_name(callee: &Expr) -> String {
+    match callee {
+        Expr::Variable { name, .. } => name.clone(),
+        Expr::Get { object, name, .. } => format!("{}.{}", callee_name(object), name),
+        Expr::ModuleAccess { module, name } => format!("{}.{}", module, name),
+        Expr::Grouping(inner) => callee_name(inner),
+        _ => "function".to_string(),
+    }
+}
+
+/// Runs the synchronous part of a native call, turning a panic into
+/// [`PrismError::NativePanic`] instead of unwinding through the evaluator.
+/// Panics inside nested interpreter calls are caught at their own native
+/// boundary, so the interpreter is never left halfway through a call.
+fn isolate<T>(name: &str, call: impl FnOnce() -> Result<T>) -> Result<T> {
+    std::panic::catch_unwind(AssertUnwindSafe(call)).unwrap_or_else(|payload| Err(native_panic(name, payload)))
+}
+
+/// Polls an async native's future under the same isolation as [`isolate`].
+struct Isolated<'a> {
+    name: &'a str,
+    future: NativeFuture<'a>,
+}
+
+impl Future for Isolated<'_> {
+    type Output = Result<Value>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        match std::panic::catch_unwind(AssertUnwindSafe(|| this.future.as_mut().poll(cx))) {
+            Ok(poll) => poll,
+            Err(payload) => Poll::Ready(Err(native_panic(this.name, payload))),
+        }
+    }
+}
+
+fn native_panic(name: &str, payload: Box<dyn Any + Send>) -> PrismError {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    PrismError::NativePanic { function: name.to_string(), message }
+}
+
+fn if_branch<'s>(condition: &Value, then_branch: &'s Stmt, else_branch: &'s Option<Box<Stmt>>) -> Result<Option<&'s Stmt>> {
+    if condition_holds(condition)? {
+        Ok(Some(then_branch))
+    } else {
+        Ok(else_branch.as_deref())
+    }
+}
+
+/// Picks the `uncertain if` branch for the condition's confidence; `false`
+/// and `nil` count as no confidence at all.
+fn uncertain_branch<'s>(
+    condition: &Value,
+    (high_threshold, then_branch): (f64, &'s Stmt),
+    (medium_threshold, medium_branch): (f64, &'s Option<Box<Stmt>>),
+    low_branch: &'s Option<Box<Stmt>>,
+) -> Option<&'s Stmt> {
+    let confidence = match condition.kind {
+        ValueKind::Boolean(false) | ValueKind::Nil => 0.0,
+        _ => condition.confidence,
+    };
+
+    if confidence >= high_threshold {
+        Some(then_branch)
+    } else if medium_branch.is_some() && confidence >= medium_threshold {
+        medium_branch.as_deref()
+    } else {
+        low_branch.as_deref()
+    }
+}
+
+fn pattern_matches(pattern: &Pattern, subject: &Value) -> bool {
+    match pattern {
+        Pattern::Wildcard | Pattern::Binding(_) => true,
+        Pattern::Literal(value) => value.kind == subject.kind,
+        Pattern::Confidence(threshold) => subject.confidence >= *threshold,
+    }
+}
+
+fn guard_passes(guard: &Value) -> Result<bool> {
+    match guard.kind {
+        ValueKind::Boolean(b) => Ok(b),
+        _ => Err(PrismError::RuntimeError(format!("Match guard must be a boolean, got {:?}", guard.kind))),
+    }
+}
+
+fn no_match(subject: &Value) -> PrismError {
+    PrismError::RuntimeError(format!("No match arm matched {}", subject))
+}
+
+// Hosts rely on this to run evaluations on multi-threaded runtimes.
+const _: fn(&mut Interpreter) = |interpreter| {
+    fn is_send<T: Send>(_: &T) {}
+    is_send(interpreter);
+    is_send(&interpreter.evaluate(String::new()));
+    is_send(&interpreter.evaluate_cancellable(String::new(), CancellationToken::new()));
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::ScriptedInput;
+    use crate::llm::testing::{echo_client, EchoServer};
+
+    #[tokio::test]
+    async fn test_optional_chaining() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let response = { diagnosis: "flu", details: { severity: 0.4 } };
+            let missing = response?.treatment;
+            let nested = response.details?.severity;
+            missing == nil and nested == 0.4;
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::Boolean(true));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_nil_safety_errors_stop_evaluation() {
+        let mut interpreter = Interpreter::new();
+        let result = interpreter
+            .evaluate("let score: number? = nil; score + 1;".to_string())
+            .await;
+        assert!(matches!(result, Err(PrismError::TypeError(_))));
+        assert_eq!(interpreter.diagnostics().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_uncertain_if_selects_band() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let result = "none";
+            let diagnosis = "flu" ~> 0.6;
+            uncertain if (diagnosis) {
+                result = "high";
+            } medium {
+                result = "medium";
+            } low {
+                result = "low";
+            }
+            result;
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::String("medium".to_string()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_match_expression() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let symptom = "fever" ~> 0.9;
+            let label = match symptom { "cough" => "cold", s if s == "fever" => "flu", _ => "unknown" };
+            let band = match symptom { ~> 0.8 => "high", ~> 0 => "low" };
+            label + "/" + band;
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::String("flu/high".to_string()));
+
+        // An arm's binding is a local opened inside the `let` it initializes
+        let source = r#"
+            fn classify(x) {
+                let label = match x { 0 => "zero", n if n < 0 => "negative", _ => "positive" };
+                let sign = label;
+                return sign;
+            }
+            [classify(-2), classify(0), classify(3)];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[negative, zero, positive]");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_non_exhaustive_match_is_rejected() {
+        let mut interpreter = Interpreter::new();
+        let result = interpreter
+            .evaluate(r#"let x = "flu"; match x { "flu" => 1 };"#.to_string())
+            .await;
+        assert!(matches!(result, Err(PrismError::TypeError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_resolved_shadowing() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let a = "global";
+            let seen = "";
+            {
+                let a = "outer";
+                {
+                    let a = "inner";
+                    seen = seen + a;
+                }
+                seen = seen + a;
+                a = "changed";
+                seen = seen + a;
+            }
+            seen + a;
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::String("innerouterchangedglobal".to_string()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_undefined_variable_fails_before_running() {
+        let mut interpreter = Interpreter::new();
+        let result = interpreter
+            .evaluate("let ran = 1; ran = 2; undefined_name;".to_string())
+            .await;
+        assert!(matches!(result, Err(PrismError::ResolveError(_))));
+        // Nothing ran, so `ran` was never defined.
+        let result = interpreter.evaluate("ran;".to_string()).await;
+        assert!(matches!(result, Err(PrismError::ResolveError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_repl_globals_persist_between_evaluations() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        interpreter.evaluate("let x = 41;".to_string()).await?;
+        let result = interpreter.evaluate("x + 1;".to_string()).await?;
+        assert_eq!(result.kind, ValueKind::Number(42.0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_function_calls_and_closures() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            fn make_adder(n) {
+                fn add(x) { return x + n; }
+                return add;
+            }
+            let add2 = make_adder(2);
+            add2(40);
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::Number(42.0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_return_exits_nested_blocks_and_loops() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            fn triage(score) {
+                let steps = 0;
+                while (true) {
+                    steps = steps + 1;
+                    uncertain if (score) {
+                        return "urgent" ~> 0.9;
+                    } medium {
+                        if (steps > 2) { return steps; }
+                    } low {
+                        return nil;
+                    }
+                }
+                print("unreachable");
+            }
+            fn log_only(x) { let y = x; }
+            [triage(true ~> 0.95), triage(true ~> 0.6), triage(true ~> 0.1), log_only(1)];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[urgent, 3, nil, nil]");
+        let ValueKind::List(values) = &result.kind else { panic!("expected a list") };
+        assert_eq!(values[0].confidence, 0.9);
+        assert!(interpreter.evaluate("steps;".to_string()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_closures_do_not_leak_environments() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            fn counter() {
+                let count = 0;
+                fn next() { count = count + 1; return count; }
+                let steps = [next, next];
+                return next;
+            }
+            fn work(n) {
+                fn helper(x) { if (x == 0) { return 0; } return helper(x - 1); }
+                return helper(n);
+            }
+            let tick = counter();
+            tick();
+            tick();
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "2");
+
+        // Frames whose functions refer to them go away after each call
+        let globals = interpreter.globals();
+        let held = Arc::strong_count(&globals);
+        interpreter.evaluate("for (i in range(0, 50)) { work(3); counter(); }".to_string()).await?;
+        assert_eq!(Arc::strong_count(&globals), held);
+
+        // Also when the functions close over a loop or block inside the frame
+        let source = r#"
+            fn looped() {
+                let hs = [];
+                for (x in range(0, 3)) { fn h() { return x; } hs = [h, hs]; }
+                return 0;
+            }
+            fn blocked() {
+                let kept = nil;
+                if (true) { let y = 1; fn g() { return y; } kept = { g: g }; }
+                return 0;
+            }
+            for (i in range(0, 50)) { looped(); blocked(); }
+        "#;
+        interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(Arc::strong_count(&globals), held);
+
+        // Or are kept in a parameter or a loop variable
+        let source = r#"
+            fn param(x) { fn g() { return x; } x = g; return 0; }
+            fn looping(x) { while (true) { fn g() { return x; } x = g; break; } return 0; }
+            fn loop_var() { for (v in range(0, 2)) { fn g() { return v; } v = g; } return 0; }
+            fn unreturned(x) { fn g() { return x; } x = g; }
+            for (i in range(0, 20)) { param(i); looping(i); loop_var(); unreturned(i); }
+        "#;
+        interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(Arc::strong_count(&globals), held);
+
+        // Those that escape still work
+        let source = r#"
+            fn make() {
+                let kept = nil;
+                for (x in range(0, 3)) { fn h() { return x * 10; } kept = { h: h, rest: kept }; }
+                return kept;
+            }
+            let made = make();
+            let h = made.rest.h;
+            h();
+        "#;
+        assert_eq!(interpreter.evaluate(source.to_string()).await?.to_string(), "10");
+
+        // Neither top-level functions nor the closure kept in `tick` keep the
+        // globals alive once the interpreter is gone
+        let weak = Arc::downgrade(&globals);
+        drop(globals);
+        drop(interpreter);
+        assert!(weak.upgrade().is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deep_mutual_tail_recursion() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            fn is_even(n) {
+                if (n == 0) { return true; }
+                return is_odd(n - 1);
+            }
+            fn is_odd(n) {
+                if (n == 0) { return false; }
+                return is_even(n - 1);
+            }
+            is_even(5001);
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::Boolean(false));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tail_recursive_accumulator() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            fn propagate(steps, confidence) {
+                if (steps == 0) { return confidence; }
+                return propagate(steps - 1, confidence * 0.999);
+            }
+            propagate(3000, 1) < 0.05;
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::Boolean(true));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_non_tail_recursion_hits_call_depth_limit() {
+        let mut interpreter = Interpreter::new().with_max_call_depth(8);
+        let source = r#"
+            fn depth(n) {
+                if (n == 0) { return 0; }
+                return 1 + depth(n - 1);
+            }
+            depth(9);
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await;
+        assert!(matches!(result.as_ref().map_err(PrismError::root), Err(PrismError::RuntimeError(ref msg)) if msg.contains("call depth")));
+
+        // The interpreter is usable again afterwards.
+        let result = interpreter.evaluate("depth(7);".to_string()).await.unwrap();
+        assert_eq!(result.kind, ValueKind::Number(7.0));
+    }
+
+    #[tokio::test]
+    async fn test_while_loop() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let i = 0;
+            let total = 0;
+            while (i < 5) {
+                i = i + 1;
+                total = total + i;
+            }
+            total;
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::Number(15.0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_break_and_continue() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let i = 0;
+            let odd = 0;
+            while (true) {
+                i = i + 1;
+                if (i > 9) { break; }
+                if (i == 2 or i == 4 or i == 6 or i == 8) { continue; }
+                odd = odd + i;
+            }
+            fn first_over(items, limit) {
+                for (item in items) {
+                    if (item > limit) { return item; }
+                }
+                return nil;
+            }
+            let pairs = 0;
+            for (a in range(0, 4)) {
+                for (b in range(0, 4)) {
+                    if (b > a) { break; }
+                    pairs = pairs + 1;
+                }
+            }
+            [i, odd, first_over([3, 8, 12], 5), pairs];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[10, 25, 8, 10]");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_loop_iteration_limit() -> Result<()> {
+        let mut interpreter = Interpreter::new().with_max_loop_iterations(100);
+        let err = interpreter.evaluate("let n = 0; while (true) { n = n + 1; }".to_string()).await.unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: Loop exceeded the limit of 100 iterations");
+        assert_eq!(interpreter.evaluate("n;".to_string()).await?.kind, ValueKind::Number(100.0));
+
+        // The limit is per loop, so nested loops may run more in total.
+        let source = "let total = 0; for (a in range(0, 100)) { for (b in range(0, 100)) { total = total + 1; } } total;";
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::Number(10000.0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_timeout_stops_runaway_loop() {
+        let mut interpreter = Interpreter::new();
+        let result = interpreter
+            .evaluate_with_timeout("while (true) { }".to_string(), Duration::from_millis(20))
+            .await;
+        assert!(matches!(result, Err(PrismError::Cancelled(ref reason)) if reason.contains("loop back-edge")));
+
+        // The environment was restored, so the interpreter keeps working.
+        let result = interpreter.evaluate("1 + 1;".to_string()).await.unwrap();
+        assert_eq!(result.kind, ValueKind::Number(2.0));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_from_another_thread_keeps_partial_audit_log() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .define_global("ping", Value::new(ValueKind::NativeFunction {
+                name: "ping".to_string(),
+                arity: 0,
+                handler: Arc::new(|_, _| Ok(Value::new(ValueKind::Nil))),
+            }))
+            .unwrap();
+
+        let token = CancellationToken::new();
+        let handle = token.clone();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            handle.cancel();
+        });
+
+        let source = "ping(); fn spin() { return spin(); } spin();";
+        let result = interpreter.evaluate_cancellable(source.to_string(), token).await;
+        canceller.join().unwrap();
+
+        assert!(matches!(result, Err(PrismError::Cancelled(ref reason)) if reason.contains("call to spin")));
+        let events: Vec<_> = interpreter.audit_log().entries().iter().map(|e| e.event.clone()).collect();
+        assert_eq!(events[0], AuditEvent::NativeCall { name: "ping".to_string() });
+        assert!(matches!(events.last(), Some(AuditEvent::Cancelled { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_native_panics_become_errors() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .define_global("first", Value::new(ValueKind::NativeFunction {
+                name: "first".to_string(),
+                arity: 1,
+                handler: Arc::new(|_, args| {
+                    let items: Vec<Value> = Vec::new();
+                    Ok(items[args.len()].clone())
+                }),
+            }))
+            .unwrap();
+        interpreter
+            .define_global("later", Value::new(ValueKind::AsyncNativeFunction {
+                name: "later".to_string(),
+                arity: 0,
+                handler: Arc::new(|interpreter, _| Box::pin(async move {
+                    interpreter.sleep(Duration::from_millis(1)).await?;
+                    panic!("gave up");
+                })),
+            }))
+            .unwrap();
+
+        let err = interpreter.evaluate("first(1);".to_string()).await.unwrap_err();
+        assert!(matches!(err.root(), PrismError::NativePanic { ref function, ref message }
+            if function == "first" && message.contains("index out of bounds")));
+        let err = interpreter.evaluate("later();".to_string()).await.unwrap_err();
+        assert!(matches!(err.root(), PrismError::NativePanic { ref function, ref message }
+            if function == "later" && message.starts_with("gave up")));
+
+        // The panic stayed inside the call, so the interpreter keeps working.
+        let result = interpreter.evaluate("1 + 1;".to_string()).await.unwrap();
+        assert_eq!(result.kind, ValueKind::Number(2.0));
+    }
+
+    #[tokio::test]
+    async fn test_env_access_requires_capability() {
+        std::env::set_var("PRISM_TEST_ENV_PLAIN", "visible");
+        let source = "env.get(\"PRISM_TEST_ENV_PLAIN\");".to_string();
+
+        let result = Interpreter::new().evaluate(source.clone()).await;
+        assert!(matches!(result.as_ref().map_err(PrismError::root), Err(PrismError::PermissionDenied(_))));
+
+        let mut interpreter = Interpreter::new().with_capabilities(Capabilities::all());
+        let value = interpreter.evaluate(source).await.unwrap();
+        assert!(matches!(value.kind, ValueKind::String(ref s) if s == "visible"));
+        let missing = interpreter.evaluate("env.get(\"PRISM_TEST_ENV_UNSET\");".to_string()).await.unwrap();
+        assert!(matches!(missing.kind, ValueKind::Nil));
+    }
+
+    #[tokio::test]
+    async fn test_secrets_are_redacted_from_errors_and_audit_log() {
+        std::env::set_var("PRISM_TEST_API_KEY", "sk-test-0123456789");
+        let mut interpreter = Interpreter::new().with_capabilities(Capabilities::all());
+        let source = "let key = env.get(\"PRISM_TEST_API_KEY\"); core.assert(false, key);";
+        let err = interpreter.evaluate(source.to_string()).await.unwrap_err().to_string();
+        assert!(!err.contains("sk-test-0123456789"));
+        assert!(err.contains(crate::secrets::REDACTED));
+
+        interpreter.secrets().register("ping");
+        interpreter
+            .define_global("ping", Value::new(ValueKind::NativeFunction {
+                name: "ping".to_string(),
+                arity: 0,
+                handler: Arc::new(|_, _| Ok(Value::new(ValueKind::Nil))),
+            }))
+            .unwrap();
+        interpreter.evaluate("ping();".to_string()).await.unwrap();
+        let events: Vec<_> = interpreter.audit_log().entries().iter().map(|e| e.event.clone()).collect();
+        assert!(events.contains(&AuditEvent::NativeCall { name: crate::secrets::REDACTED.to_string() }));
+    }
+
+    #[tokio::test]
+    async fn test_prelude_is_available_without_imports() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = "let total = 0; let xs = range(1, 4); total = len(xs) + num(\"0.5\"); str(total);";
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::String("3.5".to_string()));
+
+        let result = interpreter.evaluate("conf_of(\"maybe\" ~> 0.7);".to_string()).await?;
+        assert_eq!(result.kind, ValueKind::Number(0.7));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prelude_can_be_disabled() {
+        let mut interpreter = Interpreter::without_prelude();
+        let result = interpreter.evaluate("len([1, 2]);".to_string()).await;
+        assert!(matches!(result, Err(PrismError::ResolveError(_))));
+        assert!(interpreter.evaluate("core.len([1, 2]);".to_string()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_main_gets_the_arguments() -> Result<()> {
+        let args = vec!["--symptoms".to_string(), "fever,cough".to_string()];
+        let mut interpreter = Interpreter::new().with_args(args);
+        interpreter.evaluate("fn main(args) { return len(args) + len(env.args()); }".to_string()).await?;
+        let result = interpreter.call_main().await?.expect("main is defined");
+        assert_eq!(result.kind, ValueKind::Number(4.0));
+
+        let mut interpreter = Interpreter::new();
+        interpreter.evaluate("let main = 1;".to_string()).await?;
+        assert!(interpreter.call_main().await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_imports_load_file_modules_once() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("prism-interpreter-modules-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib"))?;
+        std::fs::write(dir.join("lib/units.prism"), "print(\"units\");\nlet _scale = 2;\nfn double(x) { return x * _scale; }\n")?;
+        std::fs::write(dir.join("lib/triage.prism"), "import { double } from \"units\";\nfn triage(t) { return double(t) > 70; }\n")?;
+        std::fs::write(dir.join("scores.prism"), "import { double as twice } from \"./lib/units.prism\";\nlet base = twice(5);\n")?;
+
+        let mut interpreter = Interpreter::new().with_capabilities(Capabilities::all()).with_module_dir(&dir);
+        let output = interpreter.capture_output();
+        let source = r#"
+            import { triage } from "lib/triage";
+            import { base } from "./scores.prism";
+            import { len } from "core";
+            str(triage(36)) + " " + str(base + len([1]));
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::String("true 11".to_string()));
+        assert_eq!(output.contents(), "units\n");
+
+        // Loaded modules are reused by later evaluations
+        interpreter.evaluate("import { double } from \"lib/units\"; double(1);".to_string()).await?;
+        assert_eq!(output.contents(), "units\n");
+
+        let err = interpreter.evaluate("import { _scale } from \"lib/units\";".to_string()).await.unwrap_err();
+        assert!(err.to_string().contains("_scale (not exported by 'lib/units')"), "{}", err);
+
+        std::fs::write(dir.join("a.prism"), "import { b } from \"b\";\nlet a = 1;\n")?;
+        std::fs::write(dir.join("b.prism"), "import { a } from \"a\";\nlet b = 1;\n")?;
+        let err = interpreter.evaluate("import { a } from \"a\";".to_string()).await.unwrap_err();
+        assert!(err.to_string().contains("Import cycle between"), "{}", err);
+
+        let mut sandboxed = Interpreter::new().with_module_dir(&dir);
+        let err = sandboxed.evaluate("import { base } from \"scores\";".to_string()).await.unwrap_err();
+        assert!(matches!(err, PrismError::PermissionDenied(_)), "{}", err);
+
+        // Modules not beside the importer are looked up in the search paths
+        let mut searching = Interpreter::new()
+            .with_capabilities(Capabilities::all())
+            .with_module_dir(dir.join("app"))
+            .with_module_paths(vec![dir.join("lib")]);
+        let result = searching.evaluate("import { double } from \"units\"; double(4);".to_string()).await?;
+        assert_eq!(result.kind, ValueKind::Number(8.0));
+        let err = searching.evaluate("import { double } from \"./units\";".to_string()).await.unwrap_err();
+        assert!(!err.to_string().contains("not exported"), "{}", err);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lenient_conversion_feeds_uncertain_if() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let label = "unset";
+            uncertain if (bool(num("n/a", 0), false)) {
+                label = "high";
+            } medium {
+                label = "medium";
+            } low {
+                label = "low";
+            }
+            label;
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::String("low".to_string()));
+        assert!(interpreter.evaluate("num(\"n/a\");".to_string()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_print_writes_display_forms_to_output_sink() -> Result<()> {
+        let output = CapturedOutput::new();
+        let mut interpreter = Interpreter::new().with_output(Arc::new(output.clone()));
+        let source = r#"
+            print("hello", [1, "two"], nil);
+            printf("score: {} ({:.1}%)", 0.8, 80);
+            println("");
+        "#;
+        interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(output.take(), "hello [1, two] nil\nscore: 0.8 (80.0%)\n");
+
+        interpreter.secrets().register("s3cret");
+        interpreter.evaluate("print(\"token=s3cret\");".to_string()).await?;
+        assert_eq!(output.contents(), "token=[REDACTED]\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_errors_carry_evaluation_frames() {
+        let source = r#"
+            fn dose(weight) {
+                return weight * "mg";
+            }
+            fn plan(patient) {
+                let d = dose(patient.weight);
+                return d;
+            }
+            print("start", plan({ weight: 70 }));
+        "#;
+        let err = Interpreter::new().evaluate(source.to_string()).await.unwrap_err();
+        assert!(matches!(err.root(), PrismError::RuntimeError(_)));
+        let message = err.to_string();
+        let frames: Vec<&str> = message.lines().skip(1).collect();
+        assert_eq!(frames, [
+            "  while calling `dose` at line 6",
+            "  while calling `plan` at line 9",
+            "  while evaluating argument 2 of `print` at line 9",
+        ]);
+        let diagnostic = Diagnostic::from_error(&err);
+        assert_eq!(diagnostic.message, "Runtime error: Invalid operation between Number(70) and String(mg)");
+        assert_eq!(diagnostic.notes.len(), 3);
+        assert_eq!(diagnostic.to_string().lines().nth(1), Some("  while calling `dose` at line 6"));
+
+        // Recursion collapses into one frame
+        let err = Interpreter::new()
+            .with_max_call_depth(20)
+            .evaluate("fn f(n) { return 1 + f(n); }\nprint(1, f(1));".to_string())
+            .await
+            .unwrap_err();
+        let message = err.to_string();
+        let frames: Vec<&str> = message.lines().skip(1).collect();
+        assert_eq!(frames, [
+            "  while calling `f` at line 1",
+            "  … 19 more times",
+            "  while calling `f` at line 2",
+            "  while evaluating argument 2 of `print` at line 2",
+        ]);
+
+        // Past 16 distinct frames the middle ones are dropped, not the outermost
+        let mut source: String = (0..20).map(|i| format!("fn f{}() {{ return 1 + f{}(); }}\n", i, i + 1)).collect();
+        source.push_str("fn f20() { return 1 + \"x\"; }\nprint(f0());");
+        let err = Interpreter::new().evaluate(source).await.unwrap_err();
+        let message = err.to_string();
+        let frames: Vec<&str> = message.lines().skip(1).collect();
+        assert_eq!(frames.len(), 17);
+        assert_eq!(frames[0], "  while calling `f20` at line 20");
+        assert_eq!(frames[8], "  … 6 more frames");
+        assert_eq!(frames[15], "  while calling `f0` at line 22");
+        assert_eq!(frames[16], "  while evaluating argument 1 of `print` at line 22");
+    }
+
+    #[tokio::test]
+    async fn test_eval_sandbox_isolates_generated_code() {
+        let mut interpreter = Interpreter::new();
+        let output = interpreter.capture_output();
+        let source = r#"
+            let hidden = 1;
+            let good = eval.sandbox("let x = 6 * 7; print(x); x ~> 0.9;");
+            let leak = eval.sandbox("hidden + 1;");
+            let slow = eval.sandbox("while (true) { }", { timeout: 0.05 });
+            [good.ok, good.value, conf_of(good.value), good.output, leak.ok, len(leak.diagnostics), slow.error];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await.unwrap();
+        let ValueKind::List(items) = &result.kind else { panic!("expected a list, got {}", result) };
+        let shown: Vec<String> = items.iter().map(Value::to_string).collect();
+        assert_eq!(shown[..6], ["true", "42", "0.9", "42\n", "false", "1"]);
+        assert!(shown[6].contains("timed out"), "{}", shown[6]);
+        // The sandbox's output was returned, not printed
+        assert_eq!(output.contents(), "");
+
+        // The child cannot be given more than the caller has
+        let err = interpreter
+            .evaluate(r#"eval.sandbox("1;", { capabilities: ["env"] });"#.to_string())
+            .await
+            .unwrap_err();
+        assert!(matches!(err.root(), PrismError::PermissionDenied(_)));
+    }
+
+    #[tokio::test]
+    async fn test_source_maps_point_errors_at_the_original_lines() {
+        use crate::ast::{to_source_with_map, BinaryOp, ExprBuilder, StmtBuilder};
+        use crate::source_map::{Origin, SourceMap};
+
+        let e = ExprBuilder::at_line(3);
+        let dose = StmtBuilder::function("dose", ["w"], [
+            StmtBuilder::return_(Some(e.binary(e.var("w"), BinaryOp::Multiply, e.string("mg")))),
+        ]);
+        let e = ExprBuilder::at_line(7);
+        let (source, map) = to_source_with_map(&[dose, StmtBuilder::expr(e.call(e.var("dose"), [e.number(2.0)]))]);
+        assert_eq!(source.lines().nth(3), Some("dose(2);"));
+
+        let err = Interpreter::new().with_source_map(map).evaluate(source).await.unwrap_err();
+        assert!(err.to_string().ends_with("while calling `dose` at line 7"), "{}", err);
+
+        // Diagnostics are moved too, e.g. for a file spliced into a larger program
+        let source = "let a = 1;\nlet b = a + weight;".to_string();
+        let map = SourceMap::new().with_mapping(1, Origin::in_file("triage.prism", 20));
+        let err = Interpreter::new().with_source_map(map).evaluate(source).await.unwrap_err();
+        assert!(err.to_string().contains("error at line 21 of triage.prism: "), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_cfg_selects_statements_for_the_host() {
+        let source = r#"
+            @cfg("wasm") fn target() { return "browser"; }
+            @cfg(not("wasm")) fn target() { return "native"; }
+            let dose = 1;
+            @cfg(feature = "pediatric") { dose = 0.5; }
+            target() + " " + str(dose);
+        "#;
+        let result = Interpreter::new().evaluate(source.to_string()).await.unwrap();
+        assert_eq!(result.to_string(), "native 1");
+
+        let cfg = CfgFlags::none().with_flag("wasm").with_feature("pediatric");
+        let result = Interpreter::new().with_cfg(cfg).evaluate(source.to_string()).await.unwrap();
+        assert_eq!(result.to_string(), "browser 0.5");
+    }
+
+    #[tokio::test]
+    async fn test_introspection_builtins() {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            fn triage(temp, pulse) { return temp > 39; }
+            [signature(print), signature(core.len), doc(len), signature(triage), doc(triage),
+             len(members(core)), len(globals()) > 10];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await.unwrap();
+        let ValueKind::List(items) = &result.kind else { panic!("expected a list, got {}", result) };
+        let shown: Vec<String> = items.iter().map(Value::to_string).collect();
+        assert_eq!(shown[0], "print(values...)");
+        assert_eq!(shown[1], "len(value)");
+        assert!(shown[2].starts_with("The number of items"), "{}", shown[2]);
+        assert_eq!(shown[3..], ["triage(temp, pulse)", "nil", "24", "true"]);
+
+        let globals = interpreter.global_values();
+        let triage = &globals.iter().find(|(name, _)| name == "triage").unwrap().1;
+        assert_eq!(crate::docs::describe(&globals, triage).unwrap(), "triage(temp, pulse)");
+
+        let err = interpreter.evaluate("doc(1);".to_string()).await.unwrap_err();
+        assert!(err.to_string().contains("doc expects a function"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_capture_output_covers_forks() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let output = interpreter.capture_output();
+        let source = r#"
+            fn report(n) { print("task", n); return n; }
+            fn first() { return report(1); }
+            fn second() { return report(2); }
+            async.all([first, second]);
+            print("done");
+        "#;
+        interpreter.evaluate(source.to_string()).await?;
+        let printed = output.contents();
+        assert!(printed.contains("task 1\n") && printed.contains("task 2\n"));
+        assert!(printed.ends_with("done\n"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_input_reads_from_host_source() -> Result<()> {
+        let output = CapturedOutput::new();
+        let input = ScriptedInput::new(["Ada", "fever", "cough"]);
+        let source = r#"
+            let name = io.input("Patient name: ");
+            let rest = io.read_stdin();
+            core.format("{}: {} / {}", name, rest, io.input());
+        "#;
+
+        let denied = Interpreter::new()
+            .with_input(Arc::new(input.clone()))
+            .evaluate(source.to_string())
+            .await;
+        assert!(matches!(denied.as_ref().map_err(PrismError::root), Err(PrismError::PermissionDenied(_))));
+
+        let mut interpreter = Interpreter::new()
+            .with_capabilities(Capabilities::none().grant(crate::capabilities::Capability::Stdin))
+            .with_input(Arc::new(input))
+            .with_output(Arc::new(output.clone()));
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::String("Ada: fever\ncough / nil".to_string()));
+        assert_eq!(output.contents(), "Patient name: ");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_and_timeout_utilities() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let calls = 0;
+            fn flaky() {
+                calls = calls + 1;
+                if (calls < 3) { assert(false, "flaky"); }
+                return calls;
+            }
+            utils.retry(flaky, { attempts: 5, backoff: 0.001 });
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::Number(3.0));
+
+        let err = interpreter.evaluate("calls = 0; utils.retry(flaky, { attempts: 2 });".to_string()).await;
+        assert!(matches!(err.as_ref().map_err(PrismError::root), Err(PrismError::RuntimeError(ref msg)) if msg.contains("gave up after 2 attempts")));
+
+        let source = "fn spin() { return spin(); } let late = utils.timeout(spin, 0.02); late == nil;";
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::Boolean(true));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_interval_and_throttle_utilities() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let ticks = 0;
+            fn tick() { ticks = ticks + 1; return ticks < 3; }
+            let runs = utils.interval(tick, 0.001);
+            let hits = 0;
+            fn hit() { hits = hits + 1; return hits; }
+            let throttled = utils.throttle(hit, 60);
+            throttled(); throttled(); throttled();
+            let once = hits;
+            let quick = utils.throttle(hit, 0.02);
+            quick(); utils.sleep(0.03); quick();
+            [runs, once, hits];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[3, 1, 3]");
+
+        let forever = "fn busy() { return true; } utils.interval(busy, 0.001);";
+        let result = interpreter.evaluate_with_timeout(forever.to_string(), Duration::from_millis(30)).await;
+        assert!(matches!(result, Err(PrismError::Cancelled(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_memo_caches_by_arguments() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let calls = 0;
+            fn slow_square(x) { calls = calls + 1; return x * x; }
+            let square = utils.memo(slow_square);
+            square.call(3); square.call(3); square.call(4);
+            square.invalidate(3);
+            [square.call(3), calls, square.stats()];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[9, 3, {hits: 1, misses: 3, size: 2}]");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_metrics_snapshot() -> Result<()> {
+        let mut interpreter = Interpreter::new().with_llm_client(echo_client());
+        let source = r#"
+            fn double(x) { return x * 2; }
+            let cached = utils.memo(double);
+            cached.call(1); cached.call(1);
+            llm.chat_completion("hi");
+        "#;
+        interpreter.evaluate(source.to_string()).await?;
+        assert!(interpreter.evaluate("missing();".to_string()).await.is_err());
+
+        let snapshot = interpreter.metrics_snapshot();
+        assert_eq!(snapshot.statements, 7);
+        assert_eq!((snapshot.cache_hits, snapshot.cache_misses), (1, 1));
+        assert_eq!(snapshot.cache_hit_rate(), 0.5);
+        assert_eq!(snapshot.llm_requests.count, 1);
+        assert_eq!(snapshot.errors.get("resolve_error"), Some(&1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_serves_recorded_responses() -> Result<()> {
+        let recording = r#"{"model":"gpt-4","prompt":"Diagnose: fever","response":"influenza","confidence":0.82}"#;
+        let replay = Arc::new(crate::llm::replay::Replay::from_jsonl(recording)?);
+        let mut interpreter = Interpreter::new().with_replay(replay);
+        let source = r#"let answer = llm.chat_completion("Diagnose: fever"); [answer, conf_of(answer)];"#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[influenza, 0.82]");
+
+        // The recording is used up, and the provider is never consulted
+        assert!(interpreter.evaluate(source.to_string()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recordings_and_caches_store_no_secrets() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("prism-interpreter-recording-{}.jsonl", std::process::id()));
+        let recorder = Arc::new(crate::llm::replay::Recorder::create(&path)?);
+        let mut interpreter = Interpreter::new().with_recorder(recorder).with_llm_client(echo_client());
+        interpreter.secrets().register("sk-live-0123456789");
+        // Only the echo server's answer says this
+        interpreter.secrets().register("response to");
+        let source = r#"
+            llm.cache(true);
+            let first = llm.chat_completion("Is sk-live-0123456789 valid?");
+            let second = llm.chat_completion("Is sk-live-0123456789 valid?");
+            [first, second];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[LLM [REDACTED]: Is [REDACTED] valid?, LLM [REDACTED]: Is [REDACTED] valid?]");
+
+        // The second answer came from the cache, so only one was recorded
+        let recording = std::fs::read_to_string(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(recording.lines().count(), 1);
+        assert!(!recording.contains("sk-live") && !recording.contains("response to"), "{}", recording);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_profiles_switch_the_model_and_budget() -> Result<()> {
+        let cheap = ModelConfig { model: "gpt-4o-mini".to_string(), ..ModelConfig::default() };
+        let tight = ModelConfig { budget: Some(0.0001), ..ModelConfig::default() };
+        let profiles = HashMap::from([("cheap".to_string(), cheap), ("tight".to_string(), tight)]);
+        let mut interpreter = Interpreter::new().with_profiles(profiles).with_llm_client(echo_client());
+        let source = r#"
+            llm.chat_completion("Is it flu?");
+            llm.use_profile("cheap");
+            llm.chat_completion("Is it flu?");
+        "#;
+        interpreter.evaluate(source.to_string()).await?;
+        let models: Vec<&str> = interpreter.audit_log().llm_requests().map(|(model, _, _)| model).collect();
+        assert_eq!(models, ["gpt-4", "gpt-4o-mini"]);
+
+        // The first gpt-4 call already costs more than the budget
+        let source = r#"llm.use_profile("tight"); llm.chat_completion("Is it flu?"); llm.chat_completion("Is it flu?");"#;
+        let err = interpreter.evaluate(source.to_string()).await.unwrap_err();
+        assert!(err.to_string().contains("Over budget"), "{}", err);
+
+        let err = interpreter.evaluate(r#"llm.use_profile("accurate");"#.to_string()).await.unwrap_err();
+        assert!(err.to_string().contains("Unknown profile 'accurate' (profiles: cheap, tight)"), "{}", err);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_contexts_choose_their_bound_model() -> Result<()> {
+        let mut interpreter = Interpreter::new().with_llm_client(echo_client());
+        let source = r#"
+            llm.bind_context("triage", { model: "gpt-4o-mini" });
+            llm.bind_context("final diagnosis", { model: "gpt-4o", temperature: 0.0 });
+            let first = nil;
+            in context triage {
+                first = llm.chat_completion("Is it urgent?");
+                in context "final diagnosis" { llm.chat_completion("What is it?"); }
+                in context notes { llm.chat_completion("Summarize"); }
+            }
+            llm.chat_completion("Thanks");
+            first;
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.context.as_deref(), Some("triage"));
+        let models: Vec<&str> = interpreter.audit_log().llm_requests().map(|(model, _, _)| model).collect();
+        assert_eq!(models, ["gpt-4o-mini", "gpt-4o", "gpt-4o-mini", "gpt-4"]);
+        assert!(interpreter.contexts().is_empty());
+
+        let err = interpreter.evaluate(r#"llm.bind_context("triage", { modle: "x" });"#.to_string()).await.unwrap_err();
+        assert!(err.to_string().contains("modle"), "{}", err);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_conversations_summarize_to_stay_in_their_window() -> Result<()> {
+        // Dry-run answers are short, so summaries shrink the history
+        let mut interpreter = Interpreter::new().with_dry_run(true);
+        let source = r#"
+            let chat = llm.conversation({ window: 40, keep: 1 });
+            chat.say("The patient has had a fever of 39 degrees for three days");
+            chat.say("They also have a dry cough and a sore throat");
+            let answer = chat.say("What could it be?");
+            assert(chat.tokens() <= 40);
+            chat.history();
+        "#;
+        let history = interpreter.evaluate(source.to_string()).await?;
+        let ValueKind::List(turns) = &history.kind else { panic!("{}", history) };
+        let roles: Vec<serde_json::Value> = turns.iter().map(|turn| turn.to_json().unwrap()["role"].clone()).collect();
+        assert_eq!(roles, ["summary", "user", "assistant"]);
+        // The summary of placeholder answers is less sure than they are
+        assert!(turns[0].confidence <= 0.1 * crate::stdlib::llm::conversation::SUMMARY_CONFIDENCE);
+        assert_eq!(turns[2].confidence, turns[0].confidence);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_conversations_export_and_resume() -> Result<()> {
+        let mut interpreter = Interpreter::new().with_llm_client(echo_client());
+        let source = r#"
+            let chat = llm.conversation({ system: "You are a triage nurse" });
+            chat.say("Fever of 39" ~> 0.9);
+            let saved = chat.export();
+            let resumed = llm.conversation();
+            resumed.import(saved);
+            resumed.say("And a cough");
+            [saved, len(resumed.history())];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        let json = result.to_json()?;
+        let saved = &json[0];
+        assert_eq!(saved["format"], crate::stdlib::llm::conversation::EXPORT_FORMAT);
+        assert_eq!(saved["system"], "You are a triage nurse");
+        assert_eq!(saved["models"], serde_json::json!(["gpt-4"]));
+        let messages = saved["messages"].as_array().unwrap();
+        assert_eq!((messages[0]["role"].as_str(), messages[0]["confidence"].as_f64()), (Some("user"), Some(0.9)));
+        assert_eq!((messages[1]["role"].as_str(), messages[1]["model"].as_str()), (Some("assistant"), Some("gpt-4")));
+        assert!(messages[1]["tokens"].as_u64().unwrap() > 0 && saved["tokens"].as_u64().unwrap() > 0);
+        assert_eq!(json[1], 4);
+
+        let actions: Vec<&str> = interpreter
+            .audit_log()
+            .entries()
+            .iter()
+            .filter_map(|entry| match &entry.event {
+                AuditEvent::Conversation { action, .. } => Some(action.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(actions, ["export", "import"]);
+        let err = interpreter
+            .evaluate(r#"llm.conversation().import({ format: "chat/9", keep: 2, summarizer: "", messages: [] });"#.to_string())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Not an exported conversation"), "{}", err);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_semantic_cache_answers_near_duplicates() -> Result<()> {
+        let source = "llm.cache({ semantic: true });";
+        let err = Interpreter::new().evaluate(source.to_string()).await.unwrap_err();
+        assert!(err.to_string().contains("needs an embedder"), "{}", err);
+
+        let embedder = Arc::new(crate::llm::cache::HashingEmbedder::default());
+        let mut interpreter = Interpreter::new().with_embedder(embedder).with_llm_client(echo_client());
+        let source = r#"
+            llm.cache({ semantic: true, threshold: 0.8 });
+            llm.chat_completion("Is a 500 mg dose of amoxicillin valid for adults?");
+            llm.chat_completion("is a 500 mg dose of amoxicillin valid for adults");
+            llm.chat_completion("Summarize the discharge notes");
+            llm.cache(false);
+            llm.chat_completion("Summarize the discharge notes");
+        "#;
+        interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(interpreter.audit_log().llm_requests().count(), 3);
+        let snapshot = interpreter.metrics_snapshot();
+        assert_eq!((snapshot.cache_hits, snapshot.cache_misses), (1, 2));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_feedback_calibrates_later_completions() -> Result<()> {
+        let recording = r#"{"model":"gpt-4","prompt":"Is it flu?","response":"yes","confidence":0.9}"#;
+        let replay = Arc::new(crate::llm::replay::Replay::from_jsonl(&[recording; 3].join("\n"))?);
+        let mut interpreter = Interpreter::new().with_replay(replay);
+        let source = r#"
+            let first = llm.chat_completion("Is it flu?");
+            llm.feedback(llm.last_response_id(), false);
+            let second = llm.chat_completion("Is it flu?");
+            llm.feedback(llm.last_response_id(), true);
+            let third = llm.chat_completion("Is it flu?");
+            [conf_of(first), conf_of(second), conf_of(third)];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[0.9, 0, 0.2]");
+        let err = interpreter.evaluate(r#"llm.feedback("response-1", true);"#.to_string()).await.unwrap_err();
+        assert!(err.to_string().contains("No completion awaits feedback"), "{}", err);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_checked_completions_combine_both_models() -> Result<()> {
+        use crate::stdlib::llm::checked::critique_prompt;
+        let exchange = |model: &str, prompt: &str, response: &str, confidence: f64| {
+            serde_json::json!({ "model": model, "prompt": prompt, "response": response, "confidence": confidence })
+                .to_string()
+        };
+        let recordings = [
+            exchange("gpt-4", "Max adult dose of paracetamol?", "4 g a day", 0.8),
+            exchange("gpt-4o", &critique_prompt("Max adult dose of paracetamol?", "4 g a day"), "Yes.", 0.9),
+        ];
+        let replay = Arc::new(crate::llm::replay::Replay::from_jsonl(&recordings.join("\n"))?);
+        let mut interpreter = Interpreter::new().with_replay(replay).with_combine_strategy(CombineStrategy::Min);
+        let source = r#"
+            let checked = llm.complete_checked("Max adult dose of paracetamol?", { critic: "gpt-4o" });
+            [checked.answer, checked.verdict, checked.provenance.model, checked.provenance.critic, conf_of(checked)];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[4 g a day, Yes., gpt-4, gpt-4o, 0.8]");
+        let models: Vec<&str> = interpreter.audit_log().llm_requests().map(|(model, _, _)| model).collect();
+        assert_eq!(models, ["gpt-4", "gpt-4o"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batches_report_failed_prompts_alongside_answers() -> Result<()> {
+        let recordings = [
+            r#"{"model":"gpt-4","prompt":"Triage: chest pain","response":"urgent","confidence":0.9}"#,
+            r#"{"model":"gpt-4","prompt":"Triage: sore throat","response":"routine","confidence":0.8}"#,
+        ];
+        let replay = Arc::new(crate::llm::replay::Replay::from_jsonl(&recordings.join("\n"))?);
+        let mut interpreter = Interpreter::new().with_replay(replay);
+        let source = r#"
+            let batch = llm.complete_batch(
+                ["Triage: chest pain", "Triage: rash", 42, "Triage: sore throat"],
+                { concurrency: 2 }
+            );
+            [batch.responses, conf_of(batch.responses), len(batch.failures)];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[[urgent, nil, nil, routine], 1, 2]");
+        assert_eq!(interpreter.audit_log().llm_requests().count(), 2);
+
+        let result = interpreter.evaluate("llm.complete_batch([\"Triage: rash\"]).failures;".to_string()).await?;
+        assert!(result.to_string().contains("index: 0"), "{}", result);
+        assert!(result.to_string().contains("no recorded response"), "{}", result);
+        assert!(interpreter.evaluate("llm.complete_batch(\"Triage: rash\");".to_string()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chat_completions_take_roles_and_system_prompts() -> Result<()> {
+        let server = EchoServer::start();
+        let mut interpreter = Interpreter::new().with_llm_client(server.client());
+        let source = r#"
+            llm.chat_completion(
+                [{ role: "user", content: "Fever?" }, { role: "assistant", content: "How high?" }, { role: "user", content: "39" }],
+                { system: "You are a triage nurse" }
+            );
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(
+            result.to_string(),
+            "LLM response to: system: You are a triage nurse\nuser: Fever?\nassistant: How high?\nuser: 39\n"
+        );
+        let sent = &server.received()[0].body["messages"];
+        let roles: Vec<&str> = sent.as_array().unwrap().iter().map(|message| message["role"].as_str().unwrap()).collect();
+        assert_eq!(roles, ["system", "user", "assistant", "user"]);
+        let result = interpreter.evaluate(r#"llm.chat_completion("Fever?");"#.to_string()).await?;
+        assert_eq!(result.to_string(), "LLM response to: Fever?");
+        let err = interpreter
+            .evaluate(r#"llm.chat_completion([{ role: "doctor", content: "Rest" }]);"#.to_string())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Unknown message role 'doctor'"), "{}", err);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scripts_configure_sampling_parameters() -> Result<()> {
+        let server = EchoServer::start();
+        let mut interpreter = Interpreter::new().with_llm_client(server.client());
+        let source = r#"llm.configure({ temperature: 0.2, top_p: 0.9, presence_penalty: 0.5, stop: ["END"] });"#;
+        interpreter.evaluate(source.to_string()).await?;
+        let config = interpreter.model_config();
+        assert_eq!((config.temperature, config.top_p, config.presence_penalty), (0.2, Some(0.9), Some(0.5)));
+        assert_eq!((config.frequency_penalty, config.stop.as_slice()), (None, ["END".to_string()].as_slice()));
+
+        // The provider gets them, and nothing for what was left unset
+        interpreter.evaluate(r#"llm.chat_completion("Is it flu?");"#.to_string()).await?;
+        let sent = &server.received()[0].body;
+        assert_eq!((sent["temperature"].as_f64(), sent["top_p"].as_f64()), (Some(0.2), Some(0.9)));
+        assert_eq!((sent["presence_penalty"].as_f64(), &sent["stop"]), (Some(0.5), &serde_json::json!(["END"])));
+        assert!(sent.get("frequency_penalty").is_none());
+
+        let err = interpreter.evaluate("llm.configure({ top_p: 2 });".to_string()).await.unwrap_err();
+        assert!(err.to_string().contains("top_p must be between 0 and 1"), "{}", err);
+        assert_eq!(interpreter.model_config().top_p, Some(0.9));
+        let source = r#"llm.bind_context("creative", { temperature: 3 });"#;
+        assert!(interpreter.evaluate(source.to_string()).await.is_err());
+        assert!(interpreter.evaluate("llm.configure({ top_k: 40 });".to_string()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rules_answer_without_a_model() -> Result<()> {
+        let rules = crate::llm::rules::RuleSet::new().with_keywords(&["rash"], "dermatology", 0.8)?;
+        let mut interpreter = Interpreter::new().with_rules(rules);
+        let source = r#"
+            llm.configure({ provider: "rules", model: "triage-rules" });
+            llm.rule("dose of ([a-z]+)", "Check the $1 label", 0.6);
+            llm.rule(["chest", "pain"], "urgent", 0.9);
+            let dose = llm.chat_completion("Usual dose of ibuprofen?");
+            let chest = llm.chat_completion("Chest pain since noon");
+            let rash = llm.chat_completion("A rash on the arm");
+            let unknown = llm.chat_completion("Knee pain");
+            [dose, conf_of(dose), chest, conf_of(chest), rash, unknown, conf_of(unknown)];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[Check the ibuprofen label, 0.6, urgent, 0.9, dermatology, nil, 0]");
+        assert!(interpreter.evaluate(r#"llm.rule("(unclosed", "x", 0.5);"#.to_string()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_escalation_stops_at_the_first_confident_tier() -> Result<()> {
+        let recording = r#"{"model":"gpt-4o","prompt":"Dose of ibuprofen?","response":"400 mg","confidence":0.85}"#;
+        let replay = Arc::new(crate::llm::replay::Replay::from_jsonl(recording)?);
+        let rules = crate::llm::rules::RuleSet::new()
+            .with_keywords(&["dose"], "Check the label", 0.5)?
+            .with_keywords(&["chest", "pain"], "urgent", 0.95)?;
+        let mut interpreter = Interpreter::new().with_replay(replay).with_rules(rules);
+        let source = r#"
+            let tiers = [
+                { provider: "rules", model: "triage-rules", min_confidence: 0.8 },
+                { model: "gpt-4o-mini", min_confidence: 0.7 },
+                { model: "gpt-4o" }
+            ];
+            let dose = llm.escalate("Dose of ibuprofen?", tiers);
+            let chest = llm.escalate("Chest pain since noon", tiers);
+            [dose.answer, conf_of(dose), chest.answer, len(chest.provenance), dose.provenance];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        let ValueKind::List(items) = &result.kind else { panic!("{}", result) };
+        assert_eq!(items[..4].iter().map(Value::to_string).collect::<Vec<_>>(), ["400 mg", "0.85", "urgent", "1"]);
+        let path = items[4].to_json()?;
+        let models: Vec<&str> = path.as_array().unwrap().iter().map(|step| step["model"].as_str().unwrap()).collect();
+        assert_eq!(models, ["triage-rules", "gpt-4o-mini", "gpt-4o"]);
+        assert_eq!(path[0]["confidence"], 0.5);
+        assert!(path[1]["error"].as_str().unwrap().contains("no recorded response"));
+
+        let err = interpreter.evaluate(r#"llm.escalate("Rash?", [{ model: "gpt-4o-mini" }]);"#.to_string()).await.unwrap_err();
+        assert!(err.to_string().contains("no recorded response"), "{}", err);
+        assert!(interpreter.evaluate(r#"llm.escalate("Rash?", [{ min_confidence: 2 }]);"#.to_string()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_responses_are_post_processed() -> Result<()> {
+        let recording = r#"{"model":"gpt-4","prompt":"Temperature?","response":"It is 39.5 degrees.","confidence":0.8}"#;
+        let replay = Arc::new(crate::llm::replay::Replay::from_jsonl(&[recording; 2].join("\n"))?);
+        let mut interpreter = Interpreter::new().with_replay(replay);
+        let source = r#"
+            let temperature = llm.chat_completion("Temperature?", { extract: "number" });
+            let symptoms = extract.list("fever; cough.");
+            let missing = extract.json("no JSON here");
+            [temperature, conf_of(temperature), symptoms, missing, conf_of(missing)];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[39.5, 0.72, [fever, cough], nil, 0]");
+        let source = r#"llm.chat_completion("Temperature?", { extract: "date" });"#;
+        let err = interpreter.evaluate(source.to_string()).await.unwrap_err();
+        assert!(err.to_string().contains("Unknown extraction 'date'"), "{}", err);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sleep_does_not_block_other_tasks() -> Result<()> {
+        // A current-thread runtime: a blocking sleep would serialize these
+        let started = std::time::Instant::now();
+        let mut first = Interpreter::new();
+        let mut second = Interpreter::new();
+        let (a, b) = tokio::join!(
+            first.evaluate("utils.sleep(0.1); 1;".to_string()),
+            second.evaluate("utils.sleep(0.1); 2;".to_string()),
+        );
+        assert_eq!(a?.kind, ValueKind::Number(1.0));
+        assert_eq!(b?.kind, ValueKind::Number(2.0));
+        assert!(started.elapsed() < Duration::from_millis(180));
+
+        assert!(first.evaluate("utils.sleep(-1);".to_string()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_call_typed_converts_host_structs() -> Result<()> {
+        #[derive(Serialize)]
+        struct Patient {
+            name: String,
+            temperature: f64,
+        }
+
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Triage {
+            urgent: bool,
+            note: String,
+        }
+
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .evaluate(
+                r#"
+                fn triage(patient) {
+                    return {"urgent": patient.temperature > 39, "note": "checked " + patient.name} ~> 0.8;
+                }
+                "#
+                .to_string(),
+            )
+            .await?;
+        let patient = Patient { name: "Ada".to_string(), temperature: 39.5 };
+        let (triage, confidence) = interpreter.call_typed::<_, Triage>("triage", &patient).await?;
+        assert_eq!(triage, Triage { urgent: true, note: "checked Ada".to_string() });
+        assert_eq!(confidence, 0.8);
+
+        let err = interpreter.call_typed::<_, Vec<f64>>("triage", &patient).await.unwrap_err();
+        assert!(err.to_string().contains("The result of triage does not match"), "{}", err);
+        assert!(interpreter.call_typed::<_, bool>("missing", &1).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_metadata_travels_with_values() -> Result<()> {
+        let recording = r#"{"model":"gpt-4","prompt":"Is it flu?","response":"yes","confidence":0.9}"#;
+        let replay = Arc::new(crate::llm::replay::Replay::from_jsonl(recording)?);
+        let mut interpreter = Interpreter::new().with_replay(replay);
+        let source = r#"
+            fn fetch(url) { return meta_set("page text", "source", url); }
+            let page = fetch("https://example.org/flu");
+            let copy = page;
+            let answer = llm.chat_completion("Is it flu?");
+            [meta_get(copy, "source"), meta_get(copy), meta_get(answer, "model"), type(meta_get(answer, "at")),
+             meta_get(1, "source")];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(
+            result.to_string(),
+            "[https://example.org/flu, {source: https://example.org/flu}, gpt-4, datetime, nil]"
+        );
+        let page = interpreter.evaluate("page;".to_string()).await?;
+        assert_eq!(page.metadata_json()?, Some(serde_json::json!({ "source": "https://example.org/flu" })));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_busy_loops_yield_to_other_tasks() -> Result<()> {
+        // A current-thread runtime: the timer below only fires if the loop yields
+        let mut interpreter = Interpreter::new().with_yield_interval(100);
+        let token = CancellationToken::new();
+        let handle = token.clone();
+        let (result, ()) = tokio::join!(
+            interpreter.evaluate_cancellable("let n = 0; while (true) { n = n + 1; }".to_string(), token),
+            async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                handle.cancel();
+            },
+        );
+        assert!(matches!(result, Err(PrismError::Cancelled(ref reason)) if reason.contains("loop back-edge")));
+
+        let mut interpreter = Interpreter::new().with_yield_interval(10);
+        let source = "fn inc(n) { return n + 1; } let n = 0; for (i in range(0, 50)) { n = inc(n); } n;";
+        assert_eq!(interpreter.evaluate(source.to_string()).await?.kind, ValueKind::Number(50.0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_all_runs_concurrently_and_combines_confidence() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            fn fever() { utils.sleep(0.1); return "fever" ~> 0.9; }
+            fn cough() { utils.sleep(0.1); return "cough" ~> 0.5; }
+            let found = async.all([fever, cough]);
+            [found, conf_of(found)];
+        "#;
+        let started = std::time::Instant::now();
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert!(started.elapsed() < Duration::from_millis(180));
+        assert_eq!(result.to_string(), "[[fever, cough], 0.45]");
+
+        let mut cautious = Interpreter::new().with_combine_strategy(CombineStrategy::Min);
+        let result = cautious.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[[fever, cough], 0.5]");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_map_and_race() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let in_flight = 0;
+            let peak = 0;
+            fn double(x) {
+                in_flight = in_flight + 1;
+                if (in_flight > peak) { peak = in_flight; }
+                utils.sleep(0.01);
+                in_flight = in_flight - 1;
+                return x * 2;
+            }
+            [async.map([1, 2, 3, 4, 5], double, { concurrency: 2 }), peak];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[[2, 4, 6, 8, 10], 2]");
+
+        let source = r#"
+            fn down() { assert(false, "service down"); }
+            fn slow() { utils.sleep(0.02); return "slow"; }
+            async.race([down, slow]);
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.kind, ValueKind::String("slow".to_string()));
+        assert!(interpreter.evaluate("async.race([down]);".to_string()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_for_in_iterates_the_protocol() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let seen = [];
+            let total = 0;
+            for (n in range(0, 10, 3)) { total = total + n; }
+            for (entry in { flu: 0.8 }) { seen = [entry.key, entry.value]; }
+            let count = 0;
+            fn step() {
+                if (count >= 3) { return { value: nil, done: true }; }
+                count = count + 1;
+                return { value: count, done: false };
+            }
+            let product = 1;
+            for (n in { next: step }) { product = product * (n + 1); }
+            fn first_over(limit, items) {
+                for (item in items) {
+                    if (item > limit) { return item; }
+                }
+                return nil;
+            }
+            let numbers = iter([1, 5, 9]);
+            [total, seen, product, first_over(4, [1, 5, 9]), numbers.next(), len(range(3)), type(range(3))];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[18, [flu, 0.8], 24, 5, {value: 1, done: false}, 3, range]");
+
+        let source = r#"
+            let queue = async.channel();
+            queue.send("a"); queue.send("b"); queue.close();
+            let joined = "";
+            for (item in queue) { joined = joined + item; }
+            joined;
+        "#;
+        assert_eq!(interpreter.evaluate(source.to_string()).await?.to_string(), "ab");
+        assert!(interpreter.evaluate("for (x in 3) { }".to_string()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_generators_are_lazy() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let produced = 0;
+            fn naturals() {
+                let n = 0;
+                while (true) {
+                    produced = produced + 1;
+                    yield n;
+                    n = n + 1;
+                }
+            }
+            fn first_square_over(limit) {
+                for (n in naturals()) {
+                    if (n * n > limit) { return n; }
+                }
+            }
+            fn chunks(items) {
+                for (item in items) {
+                    utils.sleep(0.001);
+                    yield item ~> 0.9;
+                }
+            }
+            let confidences = [];
+            for (chunk in chunks(["a", "b"])) { confidences = [chunk, conf_of(chunk)]; }
+            let pair = chunks([1]);
+            [first_square_over(20), produced, confidences, pair.next(), pair.next()];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(
+            result.to_string(),
+            "[5, 6, [b, 0.9], {value: 1, done: false}, {value: nil, done: true}]"
+        );
+
+        let source = "fn broken() { yield 1; assert(false, \"stream failed\"); } for (x in broken()) { }";
+        assert!(interpreter.evaluate(source.to_string()).await.is_err());
+        assert!(interpreter.evaluate("yield 1;".to_string()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pattern_match_confidence() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let exact = pattern.match({ symptom: "high fever", score: 0.8 }, { symptom: "*fever" });
+            let typo = pattern.match("fevr", "fever");
+            [exact, conf_of(exact), typo, conf_of(typo) < 0.5, pattern.captures("temp 39.5C", "/(\d+)\.(\d)/")];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[true, 1, false, true, [39.5, 39, 5]]");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_progress_reaches_sink_and_listeners() -> Result<()> {
+        struct Recorder(Arc<parking_lot::Mutex<Vec<crate::progress::Progress>>>);
+        impl ProgressSink for Recorder {
+            fn update(&self, progress: &crate::progress::Progress) {
+                self.0.lock().push(progress.clone());
+            }
+        }
+        let drawn = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let mut interpreter = Interpreter::new().with_progress(Arc::new(Recorder(Arc::clone(&drawn))));
+        let events = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        interpreter.on("progress", move |payload| seen.lock().push(payload.to_string()));
+
+        let source = r#"
+            progress.start(2);
+            for (row in ["a", "b"]) { progress.tick(row); }
+            progress.finish();
+        "#;
+        interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(drawn.lock().len(), 4);
+        assert_eq!(
+            events.lock().last().unwrap(),
+            "{current: 2, total: 2, message: b, done: true}"
+        );
+
+        assert!(Interpreter::new().evaluate("progress.tick();".to_string()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_channel_producer_consumer() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let chunks = async.channel(1);
+            fn producer() {
+                let i = 0;
+                while (i < 5) { chunks.send(i ~> 0.9); i = i + 1; }
+                chunks.close();
+                return "sent";
+            }
+            fn consumer() {
+                let total = 0;
+                let chunk = chunks.recv();
+                while (chunk != nil) { total = total + chunk; chunk = chunks.recv(); }
+                return total;
+            }
+            async.all([producer, consumer]);
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[sent, 10]");
+
+        let result = interpreter.evaluate("chunks.try_recv();".to_string()).await?;
+        assert_eq!(result.kind, ValueKind::Nil);
+        let err = interpreter.evaluate("chunks.send(1);".to_string()).await;
+        assert!(matches!(err.as_ref().map_err(PrismError::root), Err(PrismError::RuntimeError(ref msg)) if msg.contains("closed")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_waiting_on_a_channel_is_cancellable() {
+        let mut interpreter = Interpreter::new();
+        let source = "let idle = async.channel(); idle.recv();";
+        let result = interpreter.evaluate_with_timeout(source.to_string(), Duration::from_millis(30)).await;
+        assert!(matches!(result, Err(PrismError::Cancelled(ref reason)) if reason.contains("channel receive")));
+    }
+
+    #[tokio::test]
+    async fn test_events_between_host_and_script() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let seen = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        interpreter.on("diagnosis_complete", move |payload| log.lock().push(payload.to_string()));
+
+        let source = r#"
+            let alerts = 0;
+            fn on_alert(level) { alerts = alerts + level; }
+            events.on("alert", on_alert);
+            events.emit("diagnosis_complete", { diagnosis: "flu" });
+        "#;
+        let handled = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(handled.kind, ValueKind::Number(0.0));
+        assert_eq!(*seen.lock(), vec!["{diagnosis: flu}".to_string()]);
+
+        assert_eq!(interpreter.emit("alert", Value::new(ValueKind::Number(2.0))).await?, 1);
+        interpreter.emit("alert", Value::new(ValueKind::Number(3.0))).await?;
+        let alerts = interpreter.evaluate("alerts;".to_string()).await?;
+        assert_eq!(alerts.kind, ValueKind::Number(5.0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_requires_fs_capability() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("prism-interpreter-store-{}.json", std::process::id()));
+        let source = format!(
+            "let kb = store.open(\"{}\"); kb.set(\"flu\", 0.8 ~> 0.6); kb.get(\"flu\");",
+            path.display()
+        );
+
+        let denied = Interpreter::new().evaluate(source.clone()).await;
+        assert!(matches!(denied.as_ref().map_err(PrismError::root), Err(PrismError::PermissionDenied(_))));
+
+        let mut interpreter = Interpreter::new().with_capabilities(Capabilities::all());
+        let value = interpreter.evaluate(source).await?;
+        assert_eq!(value.confidence, 0.6);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_experiment_compares_variants() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            fn terse(row) { return row.answer ~> 0.8; }
+            fn wrong(row) { return "unsure" ~> 0.4; }
+            fn exact(output, row) {
+                if (output == row.answer) { return 1; }
+                return 0;
+            }
+            let report = experiment.run({
+                variants: { terse: terse, wrong: wrong },
+                dataset: [{ answer: "flu" }, { answer: "cold" }],
+                metric: exact
+            });
+            [report.best, report.variants.terse.mean, report.variants.wrong.mean_confidence];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[terse, 1, 0.4]");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_eval_run_scores_predictions() -> Result<()> {
+        let output = crate::output::CapturedOutput::new();
+        let mut interpreter = Interpreter::new().with_output(Arc::new(output.clone()));
+        let source = r#"
+            fn triage(case) {
+                if (case.temp > 39) { return "urgent" ~> 0.9; }
+                return "routine" ~> 0.6;
+            }
+            let report = eval.run(
+                [{ temp: 40, triage: "urgent" }, { temp: 37, triage: "routine" }, { temp: 38, triage: "urgent" }],
+                triage,
+                { label_key: "triage", print: true }
+            );
+            [report.accuracy, len(report.calibration), report.brier < 0.2];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[0.666666666666667, 2, true]");
+        assert!(output.contents().starts_with("3 examples, 0 errors\naccuracy 0.667"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_guard_wrap_scrubs_and_moderates() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            fn echo(text) { return "You said: " + text; }
+            let safe_echo = guard.wrap(echo);
+            safe_echo("reach me at ada@example.org");
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "You said: reach me at [EMAIL]");
+
+        let flagged = interpreter.evaluate("safe_echo(\"go shoot them\");".to_string()).await;
+        assert!(matches!(flagged.as_ref().map_err(PrismError::root), Err(PrismError::RuntimeError(message)) if message.contains("violence")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prompt_templates_are_audited() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("prism-interpreter-prompts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("triage.prompt"), "---\nversion: v2\nmodel: gpt-4\n---\nTriage: {symptoms}")?;
+
+        let mut interpreter = Interpreter::new().with_capabilities(Capabilities::all());
+        let source = format!("let library = prompts.load(\"{}\"); library.triage({{symptoms: \"fever\"}});", dir.display());
+        let prompt = interpreter.evaluate(source).await?;
+        assert_eq!(prompt.to_string(), "Triage: fever");
+        assert!(interpreter.audit_log().entries().iter().any(|entry| entry.event
+            == AuditEvent::Prompt {
+                name: "triage".to_string(),
+                version: Some("v2".to_string()),
+                model: Some("gpt-4".to_string()),
+                temperature: None,
+            }));
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
     }
 }
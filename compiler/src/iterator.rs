@@ -0,0 +1,153 @@
+//! The iteration protocol behind `for ... in` and `iter`.
+//!
+//! An iterator is a map with a `next` function returning `{value, done}`.
+//! Lists, ranges and plain maps are iterated directly (a map yields
+//! `{key, value}` entries); channels and user-defined iterators go through
+//! their `next`.
+
+use std::sync::Arc;
+use parking_lot::Mutex;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::value::{Value, ValueKind};
+
+pub enum Iteration {
+    Items(std::vec::IntoIter<Value>),
+    Range { start: f64, step: f64, index: usize, len: usize },
+    Protocol(Value),
+}
+
+impl Iteration {
+    pub fn of(iterable: &Value) -> Result<Self> {
+        match &iterable.kind {
+            ValueKind::List(items) => Ok(Iteration::Items(items.clone().into_iter())),
+            ValueKind::Range { start, end, step } => Ok(Iteration::Range {
+                start: *start,
+                step: *step,
+                index: 0,
+                len: range_len(*start, *end, *step),
+            }),
+            ValueKind::Map(entries) => match next_fn(entries) {
+                Some(next) => Ok(Iteration::Protocol(next.clone())),
+                None => Ok(Iteration::Items(entries.iter().map(|(key, value)| entry(key, value)).collect::<Vec<_>>().into_iter())),
+            },
+            _ => Err(PrismError::TypeError(format!("{} is not iterable", iterable))),
+        }
+    }
+
+    /// The next item of a list, map or range; `None` also for protocol
+    /// iterators, which need [`next`](Self::next).
+    fn next_sync(&mut self) -> Option<Value> {
+        match self {
+            Iteration::Items(items) => items.next(),
+            Iteration::Range { start, step, index, len } if *index < *len => {
+                let value = *start + *index as f64 * *step;
+                *index += 1;
+                Some(Value::new(ValueKind::Number(value)))
+            }
+            Iteration::Range { .. } | Iteration::Protocol(_) => None,
+        }
+    }
+
+    pub async fn next(&mut self, interpreter: &mut Interpreter) -> Result<Option<Value>> {
+        match self {
+            Iteration::Protocol(next) => {
+                let result = interpreter.call(next.clone(), Vec::new()).await?;
+                step_result(&result)
+            }
+            iteration => Ok(iteration.next_sync()),
+        }
+    }
+}
+
+/// Number of values `range(start, end, step)` produces.
+pub fn range_len(start: f64, end: f64, step: f64) -> usize {
+    let len = ((end - start) / step).ceil();
+    if len.is_finite() && len > 0.0 { len as usize } else { 0 }
+}
+
+/// `{value, done}` for the protocol's `next`.
+pub fn step(item: Option<Value>) -> Value {
+    let string = |text: &str| Value::new(ValueKind::String(text.to_string()));
+    let done = item.is_none();
+    Value::new(ValueKind::Map(vec![
+        (string("value"), item.unwrap_or_else(|| Value::new(ValueKind::Nil))),
+        (string("done"), Value::new(ValueKind::Boolean(done))),
+    ]))
+}
+
+/// The item in a `{value, done}` result, or `None` once done.
+fn step_result(result: &Value) -> Result<Option<Value>> {
+    let invalid = || PrismError::TypeError(format!("iterator next() must return {{value, done}}, got {}", result));
+    let ValueKind::Map(entries) = &result.kind else {
+        return Err(invalid());
+    };
+    let field = |name: &str| entries.iter().find(|(key, _)| key.to_string() == name).map(|(_, value)| value);
+    match field("done").map(|done| &done.kind) {
+        Some(ValueKind::Boolean(true)) => Ok(None),
+        Some(ValueKind::Boolean(false)) => Ok(Some(field("value").cloned().unwrap_or_else(|| Value::new(ValueKind::Nil)))),
+        _ => Err(invalid()),
+    }
+}
+
+/// An iterator over `iterable`; iterators are returned as they are.
+pub fn iterator(iterable: &Value) -> Result<Value> {
+    let iteration = match Iteration::of(iterable)? {
+        Iteration::Protocol(_) => return Ok(iterable.clone()),
+        iteration => Arc::new(Mutex::new(iteration)),
+    };
+    let next = Value::new(ValueKind::NativeFunction {
+        name: "next".to_string(),
+        arity: 0,
+        handler: Arc::new(move |_, _| Ok(step(iteration.lock().next_sync()))),
+    });
+    Ok(Value::new(ValueKind::Map(vec![(Value::new(ValueKind::String("next".to_string())), next)])))
+}
+
+fn next_fn(entries: &[(Value, Value)]) -> Option<&Value> {
+    entries.iter().find_map(|(key, value)| match (&key.kind, &value.kind) {
+        (
+            ValueKind::String(key),
+            ValueKind::Function { .. } | ValueKind::NativeFunction { .. } | ValueKind::AsyncNativeFunction { .. },
+        ) if key == "next" => Some(value),
+        _ => None,
+    })
+}
+
+fn entry(key: &Value, value: &Value) -> Value {
+    Value::new(ValueKind::Map(vec![
+        (Value::new(ValueKind::String("key".to_string())), key.clone()),
+        (Value::new(ValueKind::String("value".to_string())), value.clone()),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(iterable: Value) -> Vec<String> {
+        let mut iteration = Iteration::of(&iterable).unwrap();
+        std::iter::from_fn(|| iteration.next_sync()).map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn test_iterate_ranges_and_maps() {
+        assert_eq!(drain(Value::new(ValueKind::Range { start: 0.0, end: 1.0, step: 0.25 })), ["0", "0.25", "0.5", "0.75"]);
+        assert_eq!(drain(Value::new(ValueKind::Range { start: 3.0, end: 0.0, step: -1.0 })), ["3", "2", "1"]);
+        assert_eq!(range_len(0.0, 5.0, -1.0), 0);
+
+        let map = Value::new(ValueKind::Map(vec![(
+            Value::new(ValueKind::String("flu".to_string())),
+            Value::new(ValueKind::Number(0.8)),
+        )]));
+        assert_eq!(drain(map), ["{key: flu, value: 0.8}"]);
+    }
+
+    #[test]
+    fn test_step_results() -> Result<()> {
+        assert_eq!(step_result(&step(Some(Value::new(ValueKind::Number(1.0)))))?, Some(Value::new(ValueKind::Number(1.0))));
+        assert_eq!(step_result(&step(None))?, None);
+        assert!(step_result(&Value::new(ValueKind::Number(1.0))).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+//! Jupyter messages and their signed wire form.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value as Json};
+use sha2::Sha256;
+use crate::error::{PrismError, Result};
+use crate::secrets::same;
+
+/// The frames of one multipart message.
+pub type Frames = Vec<Vec<u8>>;
+
+/// The version of the messaging protocol the kernel speaks.
+pub const PROTOCOL_VERSION: &str = "5.3";
+
+/// Separates the routing identities from the message proper.
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    /// Routing prefixes, echoed on replies; for IOPub, the topic.
+    pub identities: Vec<Vec<u8>>,
+    pub header: Json,
+    pub parent_header: Json,
+    pub metadata: Json,
+    pub content: Json,
+    pub buffers: Vec<Vec<u8>>,
+}
+
+impl Message {
+    pub fn msg_type(&self) -> &str {
+        self.header["msg_type"].as_str().unwrap_or_default()
+    }
+
+    /// Parses `frames`, rejecting messages not signed with `key`. An empty
+    /// key turns signing off.
+    pub fn decode(mut frames: Frames, key: &[u8]) -> Result<Message> {
+        let delimiter = frames
+            .iter()
+            .position(|frame| frame == DELIMITER)
+            .ok_or_else(|| invalid("no <IDS|MSG> delimiter"))?;
+        let mut rest = frames.split_off(delimiter).into_iter().skip(1);
+        let (Some(signature), Some(header), Some(parent_header), Some(metadata), Some(content)) =
+            (rest.next(), rest.next(), rest.next(), rest.next(), rest.next())
+        else {
+            return Err(invalid("too few frames"));
+        };
+        if !key.is_empty() {
+            let expected = sign(key, &[&header, &parent_header, &metadata, &content]);
+            if !same(expected.as_bytes(), &signature) {
+                return Err(invalid("bad signature"));
+            }
+        }
+        Ok(Message {
+            identities: frames,
+            header: serde_json::from_slice(&header)?,
+            parent_header: serde_json::from_slice(&parent_header)?,
+            metadata: serde_json::from_slice(&metadata)?,
+            content: serde_json::from_slice(&content)?,
+            buffers: rest.collect(),
+        })
+    }
+
+    pub fn encode(&self, key: &[u8]) -> Frames {
+        let parts = [&self.header, &self.parent_header, &self.metadata, &self.content].map(|part| part.to_string());
+        let signature = if key.is_empty() {
+            String::new()
+        } else {
+            sign(key, &parts.each_ref().map(|part| part.as_bytes()))
+        };
+        let mut frames = self.identities.clone();
+        frames.push(DELIMITER.to_vec());
+        frames.push(signature.into_bytes());
+        frames.extend(parts.map(String::into_bytes));
+        frames.extend(self.buffers.iter().cloned());
+        frames
+    }
+}
+
+/// The hex HMAC-SHA256 of `parts` under `key`, as Jupyter signs messages.
+fn sign(key: &[u8], parts: &[&[u8]]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn invalid(reason: &str) -> PrismError {
+    PrismError::InvalidArgument(format!("Invalid Jupyter message: {}", reason))
+}
+
+/// Signs messages and gives them headers for one kernel session.
+#[derive(Debug)]
+pub struct Session {
+    pub id: String,
+    key: Vec<u8>,
+    sent: AtomicU64,
+}
+
+impl Session {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        let random = || RandomState::new().hash_one(std::process::id());
+        Session {
+            id: format!("{:016x}{:016x}", random(), random()),
+            key: key.into(),
+            sent: AtomicU64::new(0),
+        }
+    }
+
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// A message of `msg_type` in response to `parent`, routed back the way
+    /// `parent` came.
+    pub fn reply(&self, parent: &Message, msg_type: &str, content: Json) -> Message {
+        Message {
+            identities: parent.identities.clone(),
+            ..self.publish(&parent.header, msg_type, content)
+        }
+    }
+
+    /// A message for IOPub, with its type as the topic.
+    pub fn publish(&self, parent_header: &Json, msg_type: &str, content: Json) -> Message {
+        Message {
+            identities: vec![format!("kernel.{}.{}", self.id, msg_type).into_bytes()],
+            header: json!({
+                "msg_id": format!("{}_{}", self.id, self.sent.fetch_add(1, Ordering::Relaxed)),
+                "session": self.id,
+                "username": "kernel",
+                "date": chrono::Utc::now().to_rfc3339(),
+                "msg_type": msg_type,
+                "version": PROTOCOL_VERSION,
+            }),
+            parent_header: parent_header.clone(),
+            metadata: json!({}),
+            content,
+            buffers: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_round_trip_and_check_signatures() {
+        let session = Session::new("secret");
+        let request = Message {
+            identities: vec![b"client".to_vec()],
+            ..session.publish(&json!({}), "execute_request", json!({ "code": "1;" }))
+        };
+        let reply = session.reply(&request, "execute_reply", json!({ "status": "ok" }));
+        assert_eq!(reply.identities, request.identities);
+        assert_eq!(reply.parent_header, request.header);
+
+        let frames = reply.encode(session.key());
+        assert_eq!(Message::decode(frames.clone(), b"secret").unwrap(), reply);
+        let err = Message::decode(frames, b"other").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid argument: Invalid Jupyter message: bad signature");
+        assert!(Message::decode(vec![b"{}".to_vec()], b"").is_err());
+    }
+
+    #[test]
+    fn test_signatures_are_hex_hmac_sha256() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign(b"Jefe", &[b"what do ya ", b"want for nothing?"]),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
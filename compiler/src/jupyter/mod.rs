@@ -0,0 +1,707 @@
+//! `prism-kernel`: a Jupyter kernel, so notebooks can run Prism cells.
+//!
+//! ```text
+//! prism-kernel install    # registers the kernel with Jupyter
+//! jupyter lab             # then choose "Prism" for a new notebook
+//! ```
+//!
+//! Definitions persist from one cell to the next. A cell's value is shown
+//! with a badge for its confidence, colored by the `uncertain if` bands,
+//! and what it prints is streamed as it is written. Interrupting the kernel
+//! cancels the running cell.
+//!
+//! Jupyter talks to kernels over ZeroMQ, through libzmq (which the `zmq`
+//! crate builds from source). ZeroMQ sockets cannot be shared between
+//! threads, so each is served on a thread of its own.
+
+pub mod message;
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as Json};
+use tokio::sync::{mpsc, Notify};
+use crate::ast::{DEFAULT_HIGH_CONFIDENCE, DEFAULT_MEDIUM_CONFIDENCE};
+use crate::cancellation::CancellationToken;
+use crate::diagnostics::Diagnostic;
+use crate::docs;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::lexer::Lexer;
+use crate::output::OutputSink;
+use crate::token::TokenKind;
+use crate::value::{Value, ValueKind};
+use message::{Message, Session, PROTOCOL_VERSION};
+
+/// How long socket threads wait for messages before checking whether the
+/// kernel has stopped.
+const POLL_INTERVAL_MS: i64 = 100;
+
+/// How long messages not yet delivered are kept once the kernel stops.
+const LINGER_MS: i32 = 1000;
+
+/// The connection file Jupyter starts a kernel with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    pub transport: String,
+    pub ip: String,
+    pub key: String,
+    pub signature_scheme: String,
+    pub shell_port: u16,
+    pub iopub_port: u16,
+    pub stdin_port: u16,
+    pub control_port: u16,
+    pub hb_port: u16,
+}
+
+impl ConnectionInfo {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+/// A kernel whose sockets are bound, ready to [`run`](Kernel::run).
+pub struct Kernel {
+    info: ConnectionInfo,
+    context: zmq::Context,
+    shell: zmq::Socket,
+    control: zmq::Socket,
+    stdin: zmq::Socket,
+    heartbeat: zmq::Socket,
+    interpreter: Interpreter,
+    publisher: Publisher,
+}
+
+impl Kernel {
+    /// Binds the sockets `info` names; port 0 picks a free port, which
+    /// [`connection_info`](Kernel::connection_info) then reports.
+    pub async fn bind(mut info: ConnectionInfo, interpreter: Interpreter) -> Result<Kernel> {
+        if info.transport != "tcp" {
+            return Err(PrismError::InvalidArgument(format!("Unsupported transport {}", info.transport)));
+        }
+        if !info.key.is_empty() && info.signature_scheme != "hmac-sha256" {
+            return Err(PrismError::InvalidArgument(format!(
+                "Unsupported signature scheme {}",
+                info.signature_scheme
+            )));
+        }
+        let context = zmq::Context::new();
+        let ip = info.ip.clone();
+        let shell = bind(&context, zmq::ROUTER, &ip, &mut info.shell_port)?;
+        let control = bind(&context, zmq::ROUTER, &ip, &mut info.control_port)?;
+        let stdin = bind(&context, zmq::ROUTER, &ip, &mut info.stdin_port)?;
+        let iopub = bind(&context, zmq::PUB, &ip, &mut info.iopub_port)?;
+        let heartbeat = bind(&context, zmq::REP, &ip, &mut info.hb_port)?;
+
+        let publisher = Publisher {
+            session: Arc::new(Session::new(info.key.as_bytes())),
+            socket: Arc::new(Mutex::new(iopub)),
+            parent: Arc::new(Mutex::new(json!({}))),
+        };
+        let interpreter = interpreter.with_output(Arc::new(publisher.clone()));
+        Ok(Kernel { info, context, shell, control, stdin, heartbeat, interpreter, publisher })
+    }
+
+    pub fn connection_info(&self) -> &ConnectionInfo {
+        &self.info
+    }
+
+    /// Serves requests until a client asks the kernel to shut down.
+    pub async fn run(self) -> Result<()> {
+        let session = Arc::clone(&self.publisher.session);
+        let (shell_requests, mut shell) = mpsc::unbounded_channel();
+        let (control_requests, control) = mpsc::unbounded_channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let threads = [
+            router(&self.context, "shell", self.shell, Arc::clone(&session), Some(shell_requests), Arc::clone(&stop))?,
+            router(&self.context, "control", self.control, Arc::clone(&session), Some(control_requests), Arc::clone(&stop))?,
+            // Cells cannot ask for input, so nothing arrives on stdin.
+            router(&self.context, "stdin", self.stdin, Arc::clone(&session), None, Arc::clone(&stop))?,
+            spawn("heartbeat", {
+                let stop = Arc::clone(&stop);
+                move || echo(self.heartbeat, stop)
+            }),
+        ];
+
+        let interrupt = Arc::new(Mutex::new(CancellationToken::new()));
+        let shutdown = Arc::new(Notify::new());
+        let controller = tokio::spawn(serve_control(
+            control,
+            Arc::clone(&session),
+            Arc::clone(&interrupt),
+            Arc::clone(&shutdown),
+        ));
+        let mut cells = Cells {
+            interpreter: self.interpreter,
+            publisher: self.publisher,
+            interrupt,
+            execution_count: 0,
+        };
+        loop {
+            let request = tokio::select! {
+                request = shell.recv() => request,
+                _ = shutdown.notified() => None,
+            };
+            let Some(request) = request else { break };
+            if request.message.msg_type() == "shutdown_request" {
+                request.send(&session, "shutdown_reply", shutdown_reply(&request.message));
+                break;
+            }
+            cells.handle(&request).await;
+        }
+
+        controller.abort();
+        stop.store(true, Ordering::Relaxed);
+        let joined = tokio::task::spawn_blocking(move || threads.into_iter().for_each(|thread| {
+            let _ = thread.join();
+        }));
+        joined.await.map_err(|err| PrismError::RuntimeError(err.to_string()))
+    }
+}
+
+/// A request and the way its replies go back: an in-process socket to the
+/// thread serving the socket it came in on.
+struct Request {
+    message: Message,
+    replies: Arc<Mutex<zmq::Socket>>,
+}
+
+impl Request {
+    fn send(&self, session: &Session, msg_type: &str, content: Json) {
+        let reply = session.reply(&self.message, msg_type, content);
+        // The client may have gone; there is no one left to tell
+        let _ = self.replies.lock().send_multipart(reply.encode(session.key()), 0);
+    }
+}
+
+/// Sends IOPub messages to every subscriber. As the interpreter's output
+/// it streams what the current cell prints.
+#[derive(Clone)]
+struct Publisher {
+    session: Arc<Session>,
+    socket: Arc<Mutex<zmq::Socket>>,
+    parent: Arc<Mutex<Json>>,
+}
+
+impl Publisher {
+    fn send(&self, parent_header: &Json, msg_type: &str, content: Json) {
+        let message = self.session.publish(parent_header, msg_type, content);
+        // Without subscribers the message is dropped
+        let _ = self.socket.lock().send_multipart(message.encode(self.session.key()), 0);
+    }
+
+    fn status(&self, parent_header: &Json, state: &str) {
+        self.send(parent_header, "status", json!({ "execution_state": state }));
+    }
+}
+
+impl OutputSink for Publisher {
+    fn write(&self, text: &str) {
+        let parent = self.parent.lock().clone();
+        self.send(&parent, "stream", json!({ "name": "stdout", "text": text }));
+    }
+}
+
+/// Runs the shell requests, one at a time, against one interpreter.
+struct Cells {
+    interpreter: Interpreter,
+    publisher: Publisher,
+    interrupt: Arc<Mutex<CancellationToken>>,
+    execution_count: u64,
+}
+
+impl Cells {
+    async fn handle(&mut self, request: &Request) {
+        let parent = &request.message.header;
+        self.publisher.status(parent, "busy");
+        *self.publisher.parent.lock() = parent.clone();
+        let content = &request.message.content;
+        let reply = match request.message.msg_type() {
+            "kernel_info_request" => Some(("kernel_info_reply", kernel_info())),
+            "execute_request" => Some(("execute_reply", self.execute(content).await)),
+            "is_complete_request" => Some(("is_complete_reply", is_complete(str_field(content, "code")))),
+            "complete_request" => Some(("complete_reply", self.complete(content))),
+            "inspect_request" => Some(("inspect_reply", self.inspect(content))),
+            "comm_info_request" => Some(("comm_info_reply", json!({ "status": "ok", "comms": {} }))),
+            "history_request" => Some(("history_reply", json!({ "status": "ok", "history": [] }))),
+            other => {
+                log::warn!("Ignoring unsupported Jupyter request {}", other);
+                None
+            }
+        };
+        if let Some((msg_type, content)) = reply {
+            request.send(&self.publisher.session, msg_type, content);
+        }
+        self.publisher.status(parent, "idle");
+    }
+
+    async fn execute(&mut self, content: &Json) -> Json {
+        let code = str_field(content, "code");
+        let silent = content["silent"].as_bool().unwrap_or(false);
+        if content["store_history"].as_bool().unwrap_or(!silent) {
+            self.execution_count += 1;
+        }
+        let parent = self.publisher.parent.lock().clone();
+        if !silent {
+            self.publisher.send(&parent, "execute_input", json!({
+                "code": code,
+                "execution_count": self.execution_count,
+            }));
+        }
+
+        let token = CancellationToken::new();
+        *self.interrupt.lock() = token.clone();
+        let result = self.interpreter.evaluate_cancellable(code.to_string(), token).await;
+
+        let warnings: String = self
+            .interpreter
+            .diagnostics()
+            .iter()
+            .filter(|diagnostic| !diagnostic.is_error())
+            .map(|diagnostic| format!("{}\n", diagnostic))
+            .collect();
+        if !silent && !warnings.is_empty() {
+            self.publisher.send(&parent, "stream", json!({ "name": "stderr", "text": warnings }));
+        }
+        match result {
+            Ok(value) => {
+                if !silent && !matches!(value.kind, ValueKind::Nil) {
+                    self.publisher.send(&parent, "execute_result", json!({
+                        "execution_count": self.execution_count,
+                        "data": display_data(&value),
+                        "metadata": { "confidence": value.confidence },
+                    }));
+                }
+                json!({
+                    "status": "ok",
+                    "execution_count": self.execution_count,
+                    "user_expressions": {},
+                    "payload": [],
+                })
+            }
+            Err(err) => {
+                let diagnostic = Diagnostic::from_error(&err);
+                let error = json!({
+                    "ename": err.kind(),
+                    "evalue": &diagnostic.message,
+                    "traceback": diagnostic.to_string().lines().collect::<Vec<_>>(),
+                });
+                if !silent {
+                    self.publisher.send(&parent, "error", error.clone());
+                }
+                let mut reply = json!({ "status": "error", "execution_count": self.execution_count });
+                reply.as_object_mut().unwrap().extend(error.as_object().unwrap().clone());
+                reply
+            }
+        }
+    }
+
+    fn complete(&self, content: &Json) -> Json {
+        let code = str_field(content, "code");
+        let cursor = byte_offset(code, content["cursor_pos"].as_u64());
+        let (start, _) = word_at(code, cursor);
+        let prefix = &code[start..cursor];
+        let mut matches: Vec<String> = self
+            .interpreter
+            .global_values()
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        matches.sort();
+        matches.dedup();
+        json!({
+            "status": "ok",
+            "matches": matches,
+            "cursor_start": code[..start].chars().count(),
+            "cursor_end": code[..cursor].chars().count(),
+            "metadata": {},
+        })
+    }
+
+    /// What `:doc` would say about the name under the cursor, or its value.
+    fn inspect(&self, content: &Json) -> Json {
+        let code = str_field(content, "code");
+        let (start, end) = word_at(code, byte_offset(code, content["cursor_pos"].as_u64()));
+        let globals = self.interpreter.global_values();
+        let text = globals
+            .iter()
+            .find(|(name, _)| name == &code[start..end])
+            .map(|(_, value)| docs::describe(&globals, value).unwrap_or_else(|| plain(value)));
+        json!({
+            "status": "ok",
+            "found": text.is_some(),
+            "data": text.map_or(json!({}), |text| json!({ "text/plain": text })),
+            "metadata": {},
+        })
+    }
+}
+
+/// Answers the control channel, which works even while a cell runs.
+async fn serve_control(
+    mut requests: mpsc::UnboundedReceiver<Request>,
+    session: Arc<Session>,
+    interrupt: Arc<Mutex<CancellationToken>>,
+    shutdown: Arc<Notify>,
+) {
+    while let Some(request) = requests.recv().await {
+        match request.message.msg_type() {
+            "kernel_info_request" => request.send(&session, "kernel_info_reply", kernel_info()),
+            "interrupt_request" => {
+                interrupt.lock().cancel();
+                request.send(&session, "interrupt_reply", json!({ "status": "ok" }));
+            }
+            "shutdown_request" => {
+                interrupt.lock().cancel();
+                request.send(&session, "shutdown_reply", shutdown_reply(&request.message));
+                shutdown.notify_one();
+            }
+            other => log::warn!("Ignoring unsupported Jupyter control request {}", other),
+        }
+    }
+}
+
+fn kernel_info() -> Json {
+    json!({
+        "status": "ok",
+        "protocol_version": PROTOCOL_VERSION,
+        "implementation": "prism",
+        "implementation_version": env!("CARGO_PKG_VERSION"),
+        "language_info": {
+            "name": "prism",
+            "version": env!("CARGO_PKG_VERSION"),
+            "mimetype": "text/x-prism",
+            "file_extension": ".prism",
+        },
+        "banner": format!("Prism {}", env!("CARGO_PKG_VERSION")),
+        "help_links": [],
+    })
+}
+
+fn shutdown_reply(request: &Message) -> Json {
+    json!({ "status": "ok", "restart": request.content["restart"].as_bool().unwrap_or(false) })
+}
+
+/// `incomplete` while brackets or a string are still open, so consoles
+/// keep reading lines.
+fn is_complete(code: &str) -> Json {
+    let (tokens, skipped) = Lexer::new(code).scan_tokens_lossy();
+    let depth: i64 = tokens
+        .iter()
+        .map(|token| match token.kind {
+            TokenKind::LeftParen | TokenKind::LeftBrace | TokenKind::LeftBracket => 1,
+            TokenKind::RightParen | TokenKind::RightBrace | TokenKind::RightBracket => -1,
+            _ => 0,
+        })
+        .sum();
+    if depth > 0 || skipped.iter().any(|span| code[span.clone()].starts_with('"')) {
+        json!({ "status": "incomplete", "indent": "    " })
+    } else if crate::analysis::parse_program(code).is_ok() {
+        json!({ "status": "complete" })
+    } else {
+        json!({ "status": "invalid" })
+    }
+}
+
+/// The MIME bundle a value is shown with: plain text, and HTML with badges
+/// for its confidence and those of the items of a list or map.
+pub fn display_data(value: &Value) -> Json {
+    let mut html = format!("<span>{}</span>{}", escape(&value.to_string()), badge(value.confidence));
+    let items: Vec<(String, &Value)> = match &value.kind {
+        ValueKind::List(values) => values.iter().enumerate().map(|(i, value)| (i.to_string(), value)).collect(),
+        ValueKind::Map(entries) => entries.iter().map(|(key, value)| (key.to_string(), value)).collect(),
+        _ => Vec::new(),
+    };
+    if items.iter().any(|(_, item)| item.confidence < 1.0) {
+        html.push_str("<table>");
+        for (label, item) in items {
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&label),
+                escape(&item.to_string()),
+                badge(item.confidence)
+            ));
+        }
+        html.push_str("</table>");
+    }
+    json!({ "text/plain": plain(value), "text/html": html })
+}
+
+fn plain(value: &Value) -> String {
+    if value.confidence < 1.0 {
+        format!("{} (confidence {:.2})", value, value.confidence)
+    } else {
+        value.to_string()
+    }
+}
+
+/// A percentage colored by the `uncertain if` band it falls in; certain
+/// values go without.
+fn badge(confidence: f64) -> String {
+    if confidence >= 1.0 {
+        return String::new();
+    }
+    let color = if confidence >= DEFAULT_HIGH_CONFIDENCE {
+        "#2e7d32"
+    } else if confidence >= DEFAULT_MEDIUM_CONFIDENCE {
+        "#b26a00"
+    } else {
+        "#c62828"
+    };
+    format!(
+        " <span title=\"confidence {:.2}\" style=\"background: {}; color: white; border-radius: 0.75em; \
+         padding: 0 0.5em; font-size: 0.85em;\">{:.0}%</span>",
+        confidence,
+        color,
+        confidence * 100.0
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn str_field<'a>(content: &'a Json, name: &str) -> &'a str {
+    content[name].as_str().unwrap_or_default()
+}
+
+/// The byte offset of a cursor, which Jupyter counts in characters.
+fn byte_offset(code: &str, cursor: Option<u64>) -> usize {
+    let cursor = cursor.unwrap_or(u64::MAX);
+    code.char_indices().nth(cursor.try_into().unwrap_or(usize::MAX)).map_or(code.len(), |(i, _)| i)
+}
+
+/// The byte range of the identifier around `cursor`.
+fn word_at(code: &str, cursor: usize) -> (usize, usize) {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let start = code[..cursor].char_indices().rev().take_while(|(_, c)| is_word(*c)).last().map_or(cursor, |(i, _)| i);
+    let end = code[cursor..].find(|c| !is_word(c)).map_or(code.len(), |i| cursor + i);
+    (start, end)
+}
+
+/// Binds a socket of `socket_type` to `port` on `ip`, or to a free port,
+/// which `port` is then set to.
+fn bind(context: &zmq::Context, socket_type: zmq::SocketType, ip: &str, port: &mut u16) -> Result<zmq::Socket> {
+    let socket = context.socket(socket_type).map_err(socket_error)?;
+    socket.set_linger(LINGER_MS).map_err(socket_error)?;
+    let wanted = if *port == 0 { "*".to_string() } else { port.to_string() };
+    socket.bind(&format!("tcp://{}:{}", ip, wanted)).map_err(socket_error)?;
+    let endpoint = socket.get_last_endpoint().map_err(socket_error)?.unwrap_or_default();
+    if let Some(bound) = endpoint.rsplit(':').next().and_then(|bound| bound.parse().ok()) {
+        *port = bound;
+    }
+    Ok(socket)
+}
+
+fn socket_error(err: zmq::Error) -> PrismError {
+    PrismError::RuntimeError(format!("ZeroMQ: {}", err))
+}
+
+/// Runs `serve` on a thread of its own until the socket fails or the kernel
+/// stops.
+fn spawn(name: &'static str, serve: impl FnOnce() -> zmq::Result<()> + Send + 'static) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        if let Err(err) = serve() {
+            log::debug!("Jupyter {} socket closed: {}", name, err);
+        }
+    })
+}
+
+/// Serves a shell, control or stdin socket: requests are passed on with a
+/// way to reply, and replies, which may be sent from any thread, come back
+/// through an in-process socket.
+fn router(
+    context: &zmq::Context,
+    name: &'static str,
+    socket: zmq::Socket,
+    session: Arc<Session>,
+    requests: Option<mpsc::UnboundedSender<Request>>,
+    stop: Arc<AtomicBool>,
+) -> Result<thread::JoinHandle<()>> {
+    let endpoint = format!("inproc://{}-replies", name);
+    let outbox = context.socket(zmq::PULL).map_err(socket_error)?;
+    outbox.bind(&endpoint).map_err(socket_error)?;
+    let replies = context.socket(zmq::PUSH).map_err(socket_error)?;
+    replies.set_linger(LINGER_MS).map_err(socket_error)?;
+    replies.connect(&endpoint).map_err(socket_error)?;
+    let replies = Arc::new(Mutex::new(replies));
+    Ok(spawn(name, move || {
+        while !stop.load(Ordering::Relaxed) {
+            let mut ready = [socket.as_poll_item(zmq::POLLIN), outbox.as_poll_item(zmq::POLLIN)];
+            zmq::poll(&mut ready, POLL_INTERVAL_MS)?;
+            if ready[1].is_readable() {
+                socket.send_multipart(outbox.recv_multipart(0)?, 0)?;
+            }
+            if !ready[0].is_readable() {
+                continue;
+            }
+            let message = match Message::decode(socket.recv_multipart(0)?, session.key()) {
+                Ok(message) => message,
+                Err(err) => {
+                    log::warn!("{}", err);
+                    continue;
+                }
+            };
+            if let Some(requests) = &requests {
+                if requests.send(Request { message, replies: Arc::clone(&replies) }).is_err() {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }))
+}
+
+/// The heartbeat: every message is sent straight back.
+fn echo(socket: zmq::Socket, stop: Arc<AtomicBool>) -> zmq::Result<()> {
+    while !stop.load(Ordering::Relaxed) {
+        if socket.poll(zmq::POLLIN, POLL_INTERVAL_MS)? > 0 {
+            socket.send_multipart(socket.recv_multipart(0)?, 0)?;
+        }
+    }
+    Ok(())
+}
+
+/// The `kernel.json` that has Jupyter start `program` for Prism notebooks.
+pub fn kernel_spec(program: &Path) -> Json {
+    json!({
+        "argv": [program, "{connection_file}"],
+        "display_name": "Prism",
+        "language": "prism",
+        "interrupt_mode": "message",
+    })
+}
+
+/// Writes the kernel spec into `kernels_dir/prism` and returns that
+/// directory.
+pub fn install(kernels_dir: &Path, program: &Path) -> Result<PathBuf> {
+    let dir = kernels_dir.join("prism");
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join("kernel.json"), serde_json::to_string_pretty(&kernel_spec(program))?)?;
+    Ok(dir)
+}
+
+/// The user's Jupyter kernels directory: under `$JUPYTER_DATA_DIR`, or
+/// the platform's default data directory.
+pub fn user_kernels_dir() -> Option<PathBuf> {
+    let var = |name: &str| std::env::var_os(name).map(PathBuf::from);
+    let data = if let Some(dir) = var("JUPYTER_DATA_DIR") {
+        dir
+    } else if cfg!(windows) {
+        var("APPDATA")?.join("jupyter")
+    } else if cfg!(target_os = "macos") {
+        var("HOME")?.join("Library/Jupyter")
+    } else {
+        var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local/share")))?.join("jupyter")
+    };
+    Some(data.join("kernels"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A client socket. Calls on it block, so tests run on several threads.
+    fn connect(context: &zmq::Context, port: u16, socket_type: zmq::SocketType) -> zmq::Socket {
+        let socket = context.socket(socket_type).unwrap();
+        socket.set_rcvtimeo(10_000).unwrap();
+        socket.connect(&format!("tcp://127.0.0.1:{}", port)).unwrap();
+        socket
+    }
+
+    fn request(socket: &zmq::Socket, client: &Session, msg_type: &str, content: Json) -> Message {
+        let request = Message { identities: Vec::new(), ..client.publish(&json!({}), msg_type, content) };
+        socket.send_multipart(request.encode(client.key()), 0).unwrap();
+        let reply = Message::decode(socket.recv_multipart(0).unwrap(), client.key()).unwrap();
+        assert_eq!(reply.parent_header, request.header);
+        reply
+    }
+
+    /// The types and contents of what the kernel published for `reply`'s
+    /// request, up to its idle status.
+    fn published(iopub: &zmq::Socket, client: &Session, reply: &Message) -> Vec<(String, Json)> {
+        let mut messages = Vec::new();
+        loop {
+            let message = Message::decode(iopub.recv_multipart(0).unwrap(), client.key()).unwrap();
+            if message.parent_header["msg_id"] != reply.parent_header["msg_id"] {
+                continue;
+            }
+            let idle = message.content["execution_state"] == "idle";
+            messages.push((message.msg_type().to_string(), message.content));
+            if idle {
+                return messages;
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cells_share_state_and_show_confidence() {
+        let info = ConnectionInfo {
+            transport: "tcp".to_string(),
+            ip: "127.0.0.1".to_string(),
+            key: "secret".to_string(),
+            signature_scheme: "hmac-sha256".to_string(),
+            shell_port: 0,
+            iopub_port: 0,
+            stdin_port: 0,
+            control_port: 0,
+            hb_port: 0,
+        };
+        let kernel = Kernel::bind(info, Interpreter::new()).await.unwrap();
+        let info = kernel.connection_info().clone();
+        let running = tokio::spawn(kernel.run());
+        let client = Session::new("secret");
+        let context = zmq::Context::new();
+        let iopub = connect(&context, info.iopub_port, zmq::SUB);
+        iopub.set_subscribe(b"").unwrap();
+        let shell = connect(&context, info.shell_port, zmq::DEALER);
+
+        let heartbeat = connect(&context, info.hb_port, zmq::REQ);
+        heartbeat.send("ping", 0).unwrap();
+        assert_eq!(heartbeat.recv_bytes(0).unwrap(), b"ping");
+
+        let reply = request(&shell, &client, "kernel_info_request", json!({}));
+        assert_eq!(reply.content["language_info"]["name"], "prism");
+
+        let code = "let dose = 400 ~> 0.8; print(\"checking\");";
+        let reply = request(&shell, &client, "execute_request", json!({ "code": code }));
+        assert_eq!(reply.content["status"], "ok");
+        assert_eq!(reply.content["execution_count"], 1);
+        let types: Vec<String> = published(&iopub, &client, &reply).into_iter().map(|(t, _)| t).collect();
+        assert_eq!(types, ["status", "execute_input", "stream", "status"]);
+
+        let reply = request(&shell, &client, "execute_request", json!({ "code": "dose;" }));
+        let messages = published(&iopub, &client, &reply);
+        let (_, result) = messages.iter().find(|(t, _)| t == "execute_result").unwrap();
+        assert_eq!(result["execution_count"], 2);
+        assert_eq!(result["data"]["text/plain"], "400 (confidence 0.80)");
+        assert!(result["data"]["text/html"].as_str().unwrap().contains(">80%</span>"));
+
+        let reply = request(&shell, &client, "execute_request", json!({ "code": "missing;" }));
+        assert_eq!(reply.content["status"], "error");
+        assert!(published(&iopub, &client, &reply).iter().any(|(t, _)| t == "error"));
+
+        let reply = request(&shell, &client, "is_complete_request", json!({ "code": "fn f(x) {" }));
+        assert_eq!(reply.content["status"], "incomplete");
+        let reply = request(&shell, &client, "complete_request", json!({ "code": "dos", "cursor_pos": 3 }));
+        assert_eq!(reply.content["matches"], json!(["dose"]));
+
+        let control = connect(&context, info.control_port, zmq::DEALER);
+        let reply = request(&control, &client, "shutdown_request", json!({ "restart": false }));
+        assert_eq!(reply.content["status"], "ok");
+        running.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_display_badges_uncertain_items() {
+        let items = vec![Value::with_confidence(ValueKind::Number(1.0), 0.3), Value::new(ValueKind::Number(2.0))];
+        let data = display_data(&Value::new(ValueKind::List(items)));
+        assert_eq!(data["text/plain"], "[1, 2]");
+        let html = data["text/html"].as_str().unwrap();
+        assert!(html.starts_with("<span>[1, 2]</span><table><tr><td>0</td><td>1</td><td> <span"), "{}", html);
+        assert!(html.contains("#c62828") && html.contains(">30%</span>"), "{}", html);
+    }
+}
@@ -1,18 +1,22 @@
+use std::ops::Range;
+use std::sync::Arc;
 use crate::token::{Token, TokenKind};
 use crate::error::{PrismError, Result};
 
 pub struct Lexer {
-    source: String,
+    /// Shared with every token, whose lexemes are spans of it.
+    source: Arc<str>,
     tokens: Vec<Token>,
+    /// Byte offsets of the current token's start and of the next character.
     start: usize,
     current: usize,
     line: usize,
 }
 
 impl Lexer {
-    pub fn new(source: String) -> Self {
+    pub fn new(source: impl Into<Arc<str>>) -> Self {
         Self {
-            source,
+            source: source.into(),
             tokens: Vec::new(),
             start: 0,
             current: 0,
@@ -20,19 +24,43 @@ impl Lexer {
         }
     }
 
+    /// Numbers lines from `line` instead of 1, for source that is part of a
+    /// larger text.
+    pub fn starting_at_line(mut self, line: usize) -> Self {
+        self.line = line;
+        self
+    }
+
+    /// Reads the tokens, ending with [`TokenKind::EOF`]. The lexer is left
+    /// empty.
     pub fn scan_tokens(&mut self) -> Result<Vec<Token>> {
         while !self.is_at_end() {
             self.start = self.current;
             self.scan_token()?;
         }
 
-        self.tokens.push(Token::new(
-            TokenKind::EOF,
-            String::new(),
-            self.line,
-        ));
+        let end = self.source.len();
+        self.tokens.push(Token::in_source(TokenKind::EOF, &self.source, end..end, self.line));
 
-        Ok(self.tokens.clone())
+        Ok(std::mem::take(&mut self.tokens))
+    }
+
+    /// Reads what tokens it can, skipping past text that is not one, e.g.
+    /// for highlighting source that is still being typed. Also returns the
+    /// byte ranges that were skipped.
+    pub fn scan_tokens_lossy(&mut self) -> (Vec<Token>, Vec<Range<usize>>) {
+        let mut skipped = Vec::new();
+        while !self.is_at_end() {
+            self.start = self.current;
+            if self.scan_token().is_err() {
+                skipped.push(self.start..self.current);
+            }
+        }
+
+        let end = self.source.len();
+        self.tokens.push(Token::in_source(TokenKind::EOF, &self.source, end..end, self.line));
+
+        (std::mem::take(&mut self.tokens), skipped)
     }
 
     fn scan_token(&mut self) -> Result<()> {
@@ -42,9 +70,29 @@ impl Lexer {
             ')' => self.add_token(TokenKind::RightParen),
             '{' => self.add_token(TokenKind::LeftBrace),
             '}' => self.add_token(TokenKind::RightBrace),
+            '[' => self.add_token(TokenKind::LeftBracket),
+            ']' => self.add_token(TokenKind::RightBracket),
             ',' => self.add_token(TokenKind::Comma),
             '.' => self.add_token(TokenKind::Dot),
-            '-' => self.add_token(TokenKind::Minus),
+            ':' => self.add_token(TokenKind::Colon),
+            '|' => self.add_token(TokenKind::Pipe),
+            '@' => self.add_token(TokenKind::At),
+            '-' => {
+                let token = if self.match_char('>') {
+                    TokenKind::ThinArrow
+                } else {
+                    TokenKind::Minus
+                };
+                self.add_token(token);
+            }
+            '?' => {
+                let token = if self.match_char('.') {
+                    TokenKind::QuestionDot
+                } else {
+                    TokenKind::Question
+                };
+                self.add_token(token);
+            }
             '+' => self.add_token(TokenKind::Plus),
             ';' => self.add_token(TokenKind::Semicolon),
             '*' => self.add_token(TokenKind::Star),
@@ -120,34 +168,7 @@ impl Lexer {
         }
 
         let text = &self.source[self.start..self.current];
-        let token = match text {
-            "and" => TokenKind::And,
-            "class" => TokenKind::Class,
-            "else" => TokenKind::Else,
-            "false" => TokenKind::False,
-            "for" => TokenKind::For,
-            "fn" => TokenKind::Fun,
-            "if" => TokenKind::If,
-            "nil" => TokenKind::Nil,
-            "or" => TokenKind::Or,
-            "return" => TokenKind::Return,
-            "super" => TokenKind::Super,
-            "this" => TokenKind::This,
-            "true" => TokenKind::True,
-            "let" => TokenKind::Let,
-            "while" => TokenKind::While,
-            "break" => TokenKind::Break,
-            "continue" => TokenKind::Continue,
-            "import" => TokenKind::Import,
-            "export" => TokenKind::Export,
-            "from" => TokenKind::From,
-            "module" => TokenKind::Module,
-            "in" => TokenKind::In,
-            "context" => TokenKind::Context,
-            "as" => TokenKind::As,
-            "async" => TokenKind::Async,
-            _ => TokenKind::Identifier(text.to_string()),
-        };
+        let token = keyword(text).unwrap_or_else(|| TokenKind::Identifier(text.to_string()));
 
         self.add_token(token);
         Ok(())
@@ -166,6 +187,14 @@ impl Lexer {
             }
         }
 
+        // `19.99d` is a decimal literal; its digits are kept as written
+        if self.peek() == 'd' && !(self.peek_next().is_ascii_alphanumeric() || self.peek_next() == '_') {
+            let digits = self.source[self.start..self.current].to_string();
+            self.advance();
+            self.add_token(TokenKind::Decimal(digits));
+            return Ok(());
+        }
+
         let value = self.source[self.start..self.current]
             .parse::<f64>()
             .map_err(|_| {
@@ -201,31 +230,20 @@ impl Lexer {
     }
 
     fn match_char(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-        if self.source.chars().nth(self.current) != Some(expected) {
+        if self.peek() != expected || self.is_at_end() {
             return false;
         }
 
-        self.current += 1;
+        self.current += expected.len_utf8();
         true
     }
 
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            self.source.chars().nth(self.current).unwrap_or('\0')
-        }
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            '\0'
-        } else {
-            self.source.chars().nth(self.current + 1).unwrap_or('\0')
-        }
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
     fn is_at_end(&self) -> bool {
@@ -233,14 +251,49 @@ impl Lexer {
     }
 
     fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current).unwrap_or('\0');
-        self.current += 1;
+        let c = self.peek();
+        if !self.is_at_end() {
+            self.current += c.len_utf8();
+        }
         c
     }
 
     fn add_token(&mut self, kind: TokenKind) {
-        let text = self.source[self.start..self.current].to_string();
-        self.tokens.push(Token::new(kind, text, self.line));
+        self.tokens.push(Token::in_source(kind, &self.source, self.start..self.current, self.line));
+    }
+}
+
+/// The keyword spelled `text`, if it is one.
+pub fn keyword(text: &str) -> Option<TokenKind> {
+    match text {
+        "and" => Some(TokenKind::And),
+        "class" => Some(TokenKind::Class),
+        "else" => Some(TokenKind::Else),
+        "false" => Some(TokenKind::False),
+        "for" => Some(TokenKind::For),
+        "fn" => Some(TokenKind::Fun),
+        "if" => Some(TokenKind::If),
+        "nil" => Some(TokenKind::Nil),
+        "or" => Some(TokenKind::Or),
+        "return" => Some(TokenKind::Return),
+        "super" => Some(TokenKind::Super),
+        "this" => Some(TokenKind::This),
+        "true" => Some(TokenKind::True),
+        "let" => Some(TokenKind::Let),
+        "while" => Some(TokenKind::While),
+        "break" => Some(TokenKind::Break),
+        "continue" => Some(TokenKind::Continue),
+        "import" => Some(TokenKind::Import),
+        "export" => Some(TokenKind::Export),
+        "from" => Some(TokenKind::From),
+        "module" => Some(TokenKind::Module),
+        "in" => Some(TokenKind::In),
+        "context" => Some(TokenKind::Context),
+        "as" => Some(TokenKind::As),
+        "async" => Some(TokenKind::Async),
+        "match" => Some(TokenKind::Match),
+        "yield" => Some(TokenKind::Yield),
+        _ => None,
     }
 }
 
@@ -327,6 +380,21 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_scan_optional_types() -> Result<()> {
+        let source = "let x: string? = user?.name; fn f() -> number | nil {}".to_string();
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.scan_tokens()?;
+
+        assert_eq!(tokens[2].kind, TokenKind::Colon);
+        assert_eq!(tokens[4].kind, TokenKind::Question);
+        assert_eq!(tokens[7].kind, TokenKind::QuestionDot);
+        assert_eq!(tokens[14].kind, TokenKind::ThinArrow);
+        assert_eq!(tokens[16].kind, TokenKind::Pipe);
+
+        Ok(())
+    }
+
     #[test]
     fn test_scan_confidence() -> Result<()> {
         let source = "let x = 42 ~> 0.9;".to_string();
@@ -338,4 +406,31 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_scan_decimal_literals() -> Result<()> {
+        let tokens = Lexer::new("19.99d + 3d; 2do").scan_tokens()?;
+
+        assert_eq!(tokens[0].kind, TokenKind::Decimal("19.99".to_string()));
+        assert_eq!(tokens[0].lexeme(), "19.99d");
+        assert_eq!(tokens[2].kind, TokenKind::Decimal("3".to_string()));
+        assert_eq!(tokens[4].kind, TokenKind::Number(2.0));
+        assert_eq!(tokens[5].kind, TokenKind::Identifier("do".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lexemes_are_spans_of_non_ascii_source() -> Result<()> {
+        let source = "// température\nlet temp = \"39 °C\"; temp";
+        let tokens = Lexer::new(source).scan_tokens()?;
+
+        assert_eq!(tokens[1].kind, TokenKind::Identifier("temp".to_string()));
+        assert_eq!(tokens[3].kind, TokenKind::String("39 °C".to_string()));
+        assert_eq!(tokens[3].lexeme(), "\"39 °C\"");
+        assert_eq!(tokens[5].lexeme(), "temp");
+        assert_eq!(tokens[5].line, 2);
+
+        Ok(())
+    }
 }
@@ -3,7 +3,16 @@ use std::path::PathBuf;
 #[cfg(feature = "native")]
 use dotenv::dotenv;
 
+/// Loads `.env` files and the [configuration](config) for the CLI and
+/// hosts that want the user's settings.
 pub fn init() {
+    match config::Config::load() {
+        Ok(config) => {
+            config::set_global(config);
+        }
+        Err(err) => log::warn!("Ignoring configuration: {}", err),
+    }
+
     #[cfg(feature = "native")]
     {
         // Try to load .env from workspace root first
@@ -23,19 +32,67 @@ pub fn init() {
 
 pub mod token;
 pub mod lexer;
+pub mod highlight;
 pub mod parser;
+pub mod incremental;
 pub mod ast;
+pub mod checker;
+pub mod analysis;
+pub mod resolver;
+pub mod purity;
+pub mod cfg;
+pub mod config;
+pub mod diagnostics;
+pub mod source_map;
+pub mod docs;
+pub mod audit;
+pub mod capabilities;
+pub mod secrets;
+pub mod snapshot;
+pub mod cancellation;
+pub mod generator;
 pub mod interpreter;
+pub mod iterator;
+pub mod pool;
+pub mod run_report;
+pub mod batch;
 pub mod environment;
+pub mod events;
+pub mod telemetry;
 pub mod value;
 pub mod error;
 pub mod module;
+pub mod loader;
+pub mod precompile;
+pub mod input;
+pub mod metrics;
+pub mod output;
+pub mod progress;
 pub mod types;
 pub mod confidence;
 pub mod context;
 pub mod llm;
 pub mod stdlib;
 pub mod repl;
+#[cfg(feature = "native")]
+pub mod server;
+#[cfg(feature = "native")]
+pub mod mcp;
+#[cfg(feature = "jupyter")]
+pub mod jupyter;
+#[cfg(feature = "wasi")]
+pub mod wasi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
+pub use analysis::{analyze, parse_program, AnalysisReport, Program};
+pub use cancellation::CancellationToken;
+pub use capabilities::{Capabilities, Capability};
+pub use events::EventBus;
+pub use highlight::{highlight, TokenClass};
+pub use input::{InputSource, ScriptedInput};
 pub use interpreter::Interpreter;
+pub use output::{CapturedOutput, OutputSink};
+pub use pool::{InterpreterPool, Isolation};
 pub use repl::Repl;
+pub use secrets::Secrets;
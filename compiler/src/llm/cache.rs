@@ -0,0 +1,230 @@
+//! Caching LLM responses.
+//!
+//! An exact cache answers prompts sent to the same model before. A semantic
+//! cache also answers prompts whose embeddings are within a cosine
+//! similarity threshold of an answered one, e.g. the same validation
+//! question worded slightly differently; the cached answer's confidence is
+//! scaled by the similarity, so a near miss is never as sure as a hit. How
+//! well that works depends on the [`Embedder`], so the host chooses one.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use parking_lot::Mutex;
+use crate::value::Value;
+
+/// Turns text into a vector whose cosine similarity to others reflects how
+/// alike the texts are.
+pub trait Embedder: Send + Sync {
+    fn embed(&self, text: &str) -> Vec<f32>;
+}
+
+/// A local embedder hashing words and word pairs into a fixed number of
+/// dimensions. It needs no provider, and catches rewordings that keep most
+/// words, though not paraphrases; hosts can supply a model-backed
+/// [`Embedder`] instead.
+pub struct HashingEmbedder {
+    pub dimensions: usize,
+}
+
+impl Default for HashingEmbedder {
+    fn default() -> Self {
+        HashingEmbedder { dimensions: 512 }
+    }
+}
+
+impl Embedder for HashingEmbedder {
+    fn embed(&self, text: &str) -> Vec<f32> {
+        let words: Vec<String> = text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        let mut vector = vec![0.0f32; self.dimensions];
+        let pairs = words.windows(2).map(|pair| format!("{} {}", pair[0], pair[1]));
+        for feature in words.iter().cloned().chain(pairs) {
+            let mut hasher = DefaultHasher::new();
+            feature.hash(&mut hasher);
+            vector[(hasher.finish() % self.dimensions as u64) as usize] += 1.0;
+        }
+        vector
+    }
+}
+
+/// The cosine similarity of two vectors; 0 when either is all zeros.
+pub fn cosine(a: &[f32], b: &[f32]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| (*x as f64) * (*y as f64)).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| (*x as f64).powi(2)).sum::<f64>().sqrt();
+    match norm(a) * norm(b) {
+        0.0 => 0.0,
+        norms => dot / norms,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheMode {
+    Exact,
+    /// Prompts at least this similar to an answered one share its answer.
+    Semantic { threshold: f64 },
+}
+
+/// How many answers a cache keeps unless given another capacity.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+struct Entry {
+    id: u64,
+    model: String,
+    prompt: String,
+    embedding: Vec<f32>,
+    response: Value,
+}
+
+/// The answers, oldest first, with ids that count up from the front.
+#[derive(Default)]
+struct Entries {
+    order: VecDeque<Entry>,
+    /// The id of the latest answer to each model and prompt.
+    latest: HashMap<(String, String), u64>,
+    next_id: u64,
+}
+
+impl Entries {
+    fn get(&self, id: u64) -> Option<&Entry> {
+        let front = self.order.front()?.id;
+        self.order.get(id.checked_sub(front)? as usize)
+    }
+}
+
+/// Answers to LLM requests, up to a capacity past which the oldest are
+/// dropped.
+pub struct ResponseCache {
+    mode: CacheMode,
+    embedder: Option<Arc<dyn Embedder>>,
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl ResponseCache {
+    /// A cache answering prompts sent to the same model before.
+    pub fn exact() -> Self {
+        ResponseCache { mode: CacheMode::Exact, embedder: None, capacity: DEFAULT_CAPACITY, entries: Mutex::default() }
+    }
+
+    /// A cache also answering prompts whose embeddings by `embedder` are at
+    /// least `threshold` similar to an answered one's.
+    pub fn semantic(threshold: f64, embedder: Arc<dyn Embedder>) -> Self {
+        ResponseCache { mode: CacheMode::Semantic { threshold }, embedder: Some(embedder), ..Self::exact() }
+    }
+
+    /// Keeps at most `capacity` answers.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn mode(&self) -> CacheMode {
+        self.mode
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The answer to `prompt` for `model`, or to the most similar prompt
+    /// within the threshold with its confidence scaled by the similarity.
+    pub fn get(&self, model: &str, prompt: &str) -> Option<Value> {
+        let entries = self.entries.lock();
+        let exact = entries.latest.get(&(model.to_string(), prompt.to_string()));
+        if let Some(entry) = exact.and_then(|id| entries.get(*id)) {
+            return Some(entry.response.clone());
+        }
+        let (CacheMode::Semantic { threshold }, Some(embedder)) = (self.mode, &self.embedder) else {
+            return None;
+        };
+        let embedding = embedder.embed(prompt);
+        let (similarity, entry) = entries
+            .order
+            .iter()
+            .filter(|entry| entry.model == model)
+            .map(|entry| (cosine(&embedding, &entry.embedding), entry))
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))?;
+        if similarity < threshold {
+            return None;
+        }
+        let mut response = entry.response.clone();
+        response.confidence *= similarity;
+        Some(response)
+    }
+
+    pub fn insert(&self, model: &str, prompt: &str, response: &Value) {
+        if self.capacity == 0 {
+            return;
+        }
+        let embedding = self.embedder.as_ref().map(|embedder| embedder.embed(prompt)).unwrap_or_default();
+        let mut entries = self.entries.lock();
+        while entries.order.len() >= self.capacity {
+            let Some(oldest) = entries.order.pop_front() else { break };
+            let key = (oldest.model, oldest.prompt);
+            if entries.latest.get(&key) == Some(&oldest.id) {
+                entries.latest.remove(&key);
+            }
+        }
+        let id = entries.next_id;
+        entries.next_id += 1;
+        entries.latest.insert((model.to_string(), prompt.to_string()), id);
+        entries.order.push_back(Entry {
+            id,
+            model: model.to_string(),
+            prompt: prompt.to_string(),
+            embedding,
+            response: response.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::ValueKind;
+
+    #[test]
+    fn test_near_duplicates_share_answers_with_less_confidence() {
+        let answer = Value::with_confidence(ValueKind::String("valid".to_string()), 0.9);
+        let prompt = "Is this dosage of 500 mg amoxicillin twice daily valid for an adult patient?";
+        let reworded = "is this dosage of 500 mg amoxicillin, twice daily, valid for an adult patient";
+        let different = "Summarize the discharge notes for the cardiology ward";
+
+        let exact = ResponseCache::exact();
+        exact.insert("gpt-4", prompt, &answer);
+        assert_eq!(exact.get("gpt-4", prompt), Some(answer.clone()));
+        assert_eq!(exact.get("gpt-4o", prompt), None);
+        assert_eq!(exact.get("gpt-4", reworded), None);
+
+        let semantic = ResponseCache::semantic(0.95, Arc::new(HashingEmbedder::default()));
+        semantic.insert("gpt-4", prompt, &answer);
+        assert_eq!(semantic.get("gpt-4", reworded).map(|value| value.confidence), Some(0.9));
+        // Changing who the dose is for changes the question
+        assert_eq!(semantic.get("gpt-4", "Is this dosage of 500 mg amoxicillin twice daily valid for a child?"), None);
+        assert_eq!(semantic.get("gpt-4", different), None);
+    }
+
+    #[test]
+    fn test_the_oldest_answers_are_dropped_past_the_capacity() {
+        let cache = ResponseCache::exact().with_capacity(2);
+        let answer = |text: &str| Value::new(ValueKind::String(text.to_string()));
+        cache.insert("gpt-4", "a", &answer("1"));
+        cache.insert("gpt-4", "b", &answer("2"));
+        cache.insert("gpt-4", "a", &answer("3"));
+        assert_eq!(cache.get("gpt-4", "a"), Some(answer("3")));
+        cache.insert("gpt-4", "c", &answer("4"));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("gpt-4", "b"), None);
+        assert_eq!(cache.get("gpt-4", "a"), Some(answer("3")));
+        assert_eq!(cache.get("gpt-4", "c"), Some(answer("4")));
+    }
+}
@@ -0,0 +1,138 @@
+//! Calibrating model confidences against how often they turn out right.
+//!
+//! Each completion gets an id. When the script learns whether it was
+//! correct, `llm.feedback(id, was_correct)` updates the model's calibration
+//! table: the raw confidences from 0 to 1 fall into [`BINS`] bins, and each
+//! bin tracks the share of correct answers by exponential smoothing, so
+//! recent feedback counts most. Later completions whose bin has feedback
+//! report its accuracy instead of the raw confidence. A table kept in a
+//! [`Store`] survives between runs.
+
+use std::collections::HashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use crate::error::{PrismError, Result};
+use crate::stdlib::store::Store;
+use crate::value::Value;
+
+/// How many ranges of raw confidence are calibrated separately.
+pub const BINS: usize = 10;
+
+/// The weight of the newest feedback in a bin's smoothed accuracy.
+pub const SMOOTHING: f64 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Bin {
+    accuracy: f64,
+    feedback: u64,
+}
+
+type Table = [Bin; BINS];
+
+fn bin(raw: f64) -> usize {
+    ((raw.clamp(0.0, 1.0) * BINS as f64) as usize).min(BINS - 1)
+}
+
+#[derive(Default)]
+pub struct Calibration {
+    tables: Mutex<HashMap<String, Table>>,
+    /// The model and raw confidence of each completion awaiting feedback.
+    issued: Mutex<HashMap<String, (String, f64)>>,
+    next_id: Mutex<u64>,
+    store: Option<Store>,
+}
+
+impl Calibration {
+    /// A calibration kept in memory only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A calibration persisted in `store`, starting from the tables saved
+    /// there.
+    pub fn with_store(store: Store) -> Result<Self> {
+        let mut tables = HashMap::new();
+        for key in store.keys("calibration:") {
+            if let Some(value) = store.get(&key) {
+                let table = serde_json::from_value(value.to_json()?)?;
+                tables.insert(key["calibration:".len()..].to_string(), table);
+            }
+        }
+        Ok(Calibration { tables: Mutex::new(tables), store: Some(store), ..Self::default() })
+    }
+
+    /// `raw` mapped through `model`'s calibration curve.
+    pub fn calibrate(&self, model: &str, raw: f64) -> f64 {
+        match self.tables.lock().get(model).map(|table| table[bin(raw)]) {
+            Some(Bin { accuracy, feedback }) if feedback > 0 => accuracy,
+            _ => raw,
+        }
+    }
+
+    /// Remembers a completion by `model` with the `raw` confidence and
+    /// returns the id to give feedback on it with.
+    pub fn issue(&self, model: &str, raw: f64) -> String {
+        let id = {
+            let mut next = self.next_id.lock();
+            *next += 1;
+            format!("response-{}", next)
+        };
+        self.issued.lock().insert(id.clone(), (model.to_string(), raw));
+        id
+    }
+
+    /// Records whether the completion `id` was correct.
+    pub fn feedback(&self, id: &str, correct: bool) -> Result<()> {
+        let Some((model, raw)) = self.issued.lock().remove(id) else {
+            return Err(PrismError::InvalidArgument(format!("No completion awaits feedback as '{}'", id)));
+        };
+        let mut tables = self.tables.lock();
+        let table = tables.entry(model.clone()).or_insert_with(|| {
+            std::array::from_fn(|i| Bin { accuracy: (i as f64 + 0.5) / BINS as f64, feedback: 0 })
+        });
+        let bin = &mut table[bin(raw)];
+        let outcome = if correct { 1.0 } else { 0.0 };
+        bin.accuracy = match bin.feedback {
+            // The first feedback replaces the uninformed starting point
+            0 => outcome,
+            _ => SMOOTHING * outcome + (1.0 - SMOOTHING) * bin.accuracy,
+        };
+        bin.feedback += 1;
+        if let Some(store) = &self.store {
+            let table = Value::from_json(&serde_json::to_value(*table)?);
+            store.set(&format!("calibration:{}", model), &table)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feedback_moves_confidence_toward_accuracy() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("prism-calibration-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("calibration.json");
+        let _ = std::fs::remove_file(&path);
+
+        let calibration = Calibration::with_store(Store::open(&path)?)?;
+        assert_eq!(calibration.calibrate("gpt-4", 0.95), 0.95);
+        for correct in [false, true, false, false] {
+            let id = calibration.issue("gpt-4", 0.95);
+            calibration.feedback(&id, correct)?;
+        }
+        // Overconfident: 0.95 claimed, mostly wrong
+        let calibrated = calibration.calibrate("gpt-4", 0.92);
+        assert!((calibrated - 0.128).abs() < 1e-9, "{}", calibrated);
+        assert_eq!(calibration.calibrate("gpt-4", 0.5), 0.5);
+        assert_eq!(calibration.calibrate("gpt-4o", 0.95), 0.95);
+        assert!(calibration.feedback("response-1", true).is_err());
+
+        let reopened = Calibration::with_store(Store::open(&path)?)?;
+        assert_eq!(reopened.calibrate("gpt-4", 0.95), calibrated);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -1,125 +1,147 @@
 use serde::{Deserialize, Serialize};
 use crate::error::Result;
-use super::{CompletionRequest, CompletionResponse, TokenUsage};
+use super::keys::ApiKey;
+use super::{ChatMessage, CompletionRequest, CompletionResponse, ModelConfig, Role, TokenUsage};
+
+const DEFAULT_BASE_URL: &str = "https://generativelanguage.googleapis.com";
 
 #[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<Content>,
     contents: Vec<Content>,
     generation_config: GenerationConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Content {
+    #[serde(default)]
     role: String,
+    #[serde(default)]
     parts: Vec<Part>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Part {
+    #[serde(default)]
     text: String,
 }
 
 #[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
 struct GenerationConfig {
-    temperature: f64,
+    temperature: f32,
     max_output_tokens: usize,
-    top_p: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop_sequences: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiResponse {
+    #[serde(default)]
     candidates: Vec<Candidate>,
-    prompt_feedback: PromptFeedback,
+    #[serde(default)]
+    usage_metadata: Option<UsageMetadata>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct Candidate {
     content: Content,
-    finish_reason: String,
+    finish_reason: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-struct PromptFeedback {
-    token_count: TokenCount,
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct UsageMetadata {
+    prompt_token_count: usize,
+    candidates_token_count: usize,
+    total_token_count: usize,
 }
 
-#[derive(Debug, Deserialize)]
-struct TokenCount {
-    total_tokens: usize,
-    prompt_tokens: usize,
+/// Gemini's system instruction and contents for `chat` under the `system`
+/// instructions. Gemini has no system role in its contents, so system
+/// messages join the instruction, and it calls the assistant `model`.
+fn contents(mut system: Vec<String>, chat: &[ChatMessage]) -> (Option<Content>, Vec<Content>) {
+    let mut contents = Vec::new();
+    for message in chat {
+        let role = match message.role {
+            Role::System => {
+                system.push(message.content.clone());
+                continue;
+            }
+            Role::User => "user",
+            Role::Assistant => "model",
+        };
+        contents.push(Content { role: role.to_string(), parts: vec![Part { text: message.content.clone() }] });
+    }
+    let instruction = (!system.is_empty()).then(|| Content {
+        role: "system".to_string(),
+        parts: vec![Part { text: system.join("\n\n") }],
+    });
+    (instruction, contents)
 }
 
 pub(crate) async fn complete(
     client: &reqwest::Client,
-    api_key: &str,
-    request: CompletionRequest,
-    model_name: &str,
-    temperature: f64,
-    max_tokens: usize,
-    base_url: Option<String>,
+    api_key: &ApiKey,
+    request: &CompletionRequest,
+    config: &ModelConfig,
+    base_url: Option<&str>,
 ) -> Result<CompletionResponse> {
-    let contents = vec![
-        Content {
-            role: "user".to_string(),
-            parts: vec![Part {
-                text: format!(
-                    "Context: {}\n\nPrompt: {}",
-                    request.context.as_ref().map_or("None".to_string(), |ctx| ctx.to_string()),
-                    request.prompt.clone()
-                ),
-            }],
-        },
-    ];
+    let mut system: Vec<String> = request.system.iter().cloned().collect();
+    if let Some(context) = &request.context {
+        system.push(format!("Context: {}", context));
+    }
+    let (system_instruction, contents) = contents(system, &request.chat());
 
     let gemini_request = GeminiRequest {
+        system_instruction,
         contents,
         generation_config: GenerationConfig {
-            temperature,
-            max_output_tokens: max_tokens,
-            top_p: 1.0,
+            temperature: config.temperature,
+            max_output_tokens: config.max_tokens,
+            top_p: config.top_p,
+            presence_penalty: config.presence_penalty,
+            frequency_penalty: config.frequency_penalty,
+            stop_sequences: config.stop.clone(),
         },
     };
 
-    let base_url = base_url.unwrap_or_else(|| 
-        "https://generativelanguage.googleapis.com".to_string()
-    );
-
-    let response = client
-        .post(format!(
-            "{}/v1/models/{model_name}:generateContent?key={api_key}",
-            base_url
-        ))
-        .json(&gemini_request)
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<GeminiResponse>()
-        .await?;
-
-    let candidate = response.candidates.first().ok_or_else(|| {
-        std::io::Error::new(std::io::ErrorKind::Other, "No completion candidates returned")
-    })?;
+    let base_url = base_url.unwrap_or(DEFAULT_BASE_URL);
+    // The key goes in a header, so it never shows up in an error's URL
+    let post = client
+        .post(format!("{}/v1beta/models/{}:generateContent", base_url, config.model))
+        .timeout(config.timeout)
+        .header("x-goog-api-key", &api_key.key);
+    let response: GeminiResponse = super::send(post.json(&gemini_request)).await?;
+
+    let candidate = response.candidates.into_iter().next().ok_or_else(|| super::empty_response("candidates"))?;
 
     // Calculate confidence based on finish reason
-    let confidence = match candidate.finish_reason.as_str() {
-        "STOP" => 0.95, // Natural completion
-        "MAX_TOKENS" => 0.7, // Cut off by max tokens
+    let confidence = match candidate.finish_reason.as_deref() {
+        Some("STOP") => 0.95, // Natural completion
+        Some("MAX_TOKENS") => 0.7, // Cut off by max tokens
         _ => 0.5, // Other reasons
     };
 
-    let completion_tokens = response.prompt_feedback.token_count.total_tokens
-        - response.prompt_feedback.token_count.prompt_tokens;
-
+    let usage = response.usage_metadata.unwrap_or_default();
     Ok(CompletionResponse {
-        text: candidate.content.parts.first()
-            .map(|part| part.text.clone())
-            .unwrap_or_default(),
+        text: candidate.content.parts.into_iter().map(|part| part.text).collect(),
         confidence,
-        model: model_name.to_string(),
+        model: config.model.clone(),
         usage: TokenUsage {
-            prompt_tokens: response.prompt_feedback.token_count.prompt_tokens,
-            completion_tokens,
-            total_tokens: response.prompt_feedback.token_count.total_tokens,
+            prompt_tokens: usage.prompt_token_count,
+            completion_tokens: usage.candidates_token_count,
+            total_tokens: usage.total_token_count,
         },
     })
 }
@@ -127,7 +149,19 @@ pub(crate) async fn complete(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::llm::ModelConfig;
+
+    #[test]
+    fn test_contents_use_gemini_roles() {
+        let chat = [
+            ChatMessage::new(Role::System, "No jargon"),
+            ChatMessage::new(Role::User, "2+2?"),
+            ChatMessage::new(Role::Assistant, "4"),
+        ];
+        let (instruction, contents) = contents(vec!["Be brief".to_string()], &chat);
+        assert_eq!(instruction.unwrap().parts[0].text, "Be brief\n\nNo jargon");
+        let roles: Vec<&str> = contents.iter().map(|content| content.role.as_str()).collect();
+        assert_eq!(roles, ["user", "model"]);
+    }
 
     #[tokio::test]
     async fn test_gemini_completion() -> Result<()> {
@@ -138,22 +172,15 @@ mod tests {
         }
 
         let client = reqwest::Client::new();
-        let request = CompletionRequest {
-            prompt: "What is 2+2?".to_string(),
-            context: None,
-            config: Some(ModelConfig::default()),
+        let request = CompletionRequest::new("What is 2+2?").with_system("Answer with a number");
+        let config = ModelConfig {
+            model: "gemini-1.5-flash".to_string(),
+            provider: "google".to_string(),
+            max_tokens: 100,
+            ..ModelConfig::default()
         };
 
-        let response = complete(
-            &client,
-            &api_key.unwrap(),
-            request,
-            "gemini-pro",
-            0.7,
-            100,
-            None,
-        )
-        .await?;
+        let response = complete(&client, &ApiKey::new(api_key.unwrap()), &request, &config, None).await?;
 
         assert!(!response.text.is_empty());
         assert!(response.confidence > 0.0 && response.confidence <= 1.0);
@@ -161,4 +188,4 @@ mod tests {
 
         Ok(())
     }
-} 
\ No newline at end of file
+}
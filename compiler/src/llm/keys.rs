@@ -0,0 +1,189 @@
+//! Spreading a provider's requests over several API keys.
+//!
+//! A [`KeyRing`] hands out one of its keys per request: in turn with
+//! [`Rotation::RoundRobin`], or the first that works with
+//! [`Rotation::Failover`]. A key whose request failed sits out a cooldown
+//! before it is handed out again, and each key counts its requests,
+//! failures and tokens so teams can see where usage went. OpenAI keys may
+//! name the organization and project to bill.
+
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use crate::error::{PrismError, Result};
+
+/// How long a key that failed is left out when not configured.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub key: String,
+    /// Sent as `OpenAI-Organization`.
+    pub organization: Option<String>,
+    /// Sent as `OpenAI-Project`.
+    pub project: Option<String>,
+}
+
+impl ApiKey {
+    pub fn new(key: impl Into<String>) -> Self {
+        ApiKey { key: key.into(), organization: None, project: None }
+    }
+
+    pub fn with_organization(mut self, organization: impl Into<String>) -> Self {
+        self.organization = Some(organization.into());
+        self
+    }
+
+    pub fn with_project(mut self, project: impl Into<String>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    /// The headers OpenAI requests made with this key carry.
+    pub fn openai_headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![("Authorization", format!("Bearer {}", self.key))];
+        if let Some(organization) = &self.organization {
+            headers.push(("OpenAI-Organization", organization.clone()));
+        }
+        if let Some(project) = &self.project {
+            headers.push(("OpenAI-Project", project.clone()));
+        }
+        headers
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rotation {
+    /// Each request uses the next key.
+    #[default]
+    RoundRobin,
+    /// Requests use the first key until it fails, then the next.
+    Failover,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyUsage {
+    pub requests: u64,
+    pub failures: u64,
+    pub tokens: u64,
+}
+
+#[derive(Default)]
+struct State {
+    next: usize,
+    usage: Vec<KeyUsage>,
+    /// Until when each key that failed is left out.
+    cooling: Vec<Option<Instant>>,
+}
+
+pub struct KeyRing {
+    keys: Vec<ApiKey>,
+    rotation: Rotation,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+impl KeyRing {
+    pub fn new(keys: Vec<ApiKey>, rotation: Rotation) -> Self {
+        let state = State { next: 0, usage: vec![KeyUsage::default(); keys.len()], cooling: vec![None; keys.len()] };
+        KeyRing { keys, rotation, cooldown: DEFAULT_COOLDOWN, state: Mutex::new(state) }
+    }
+
+    /// Leaves keys that failed out for `cooldown`.
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    pub fn keys(&self) -> &[ApiKey] {
+        &self.keys
+    }
+
+    /// The index and key the next request should use.
+    pub fn next_key(&self) -> Result<(usize, &ApiKey)> {
+        if self.keys.is_empty() {
+            return Err(PrismError::RuntimeError("No API keys are configured".to_string()));
+        }
+        let mut state = self.state.lock();
+        let now = Instant::now();
+        let start = match self.rotation {
+            Rotation::RoundRobin => state.next,
+            Rotation::Failover => 0,
+        };
+        let available = (0..self.keys.len())
+            .map(|offset| (start + offset) % self.keys.len())
+            .find(|index| state.cooling[*index].is_none_or(|until| until <= now));
+        let Some(index) = available else {
+            return Err(PrismError::RuntimeError(format!(
+                "All {} API keys failed recently; retry after their cooldown",
+                self.keys.len()
+            )));
+        };
+        state.cooling[index] = None;
+        state.next = (index + 1) % self.keys.len();
+        state.usage[index].requests += 1;
+        Ok((index, &self.keys[index]))
+    }
+
+    /// Counts the `tokens` of a request that succeeded with key `index`.
+    pub fn record_success(&self, index: usize, tokens: u64) {
+        self.state.lock().usage[index].tokens += tokens;
+    }
+
+    /// Leaves key `index` out for the cooldown after a request with it failed.
+    pub fn record_failure(&self, index: usize) {
+        let mut state = self.state.lock();
+        state.usage[index].failures += 1;
+        state.cooling[index] = Some(Instant::now() + self.cooldown);
+    }
+
+    /// What each key has been used for, in the order of [`keys`](Self::keys).
+    pub fn usage(&self) -> Vec<KeyUsage> {
+        self.state.lock().usage.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<ApiKey> {
+        vec![ApiKey::new("sk-a"), ApiKey::new("sk-b").with_organization("org-research").with_project("proj-triage")]
+    }
+
+    #[test]
+    fn test_round_robin_skips_failed_keys() {
+        let ring = KeyRing::new(keys(), Rotation::RoundRobin);
+        let order: Vec<usize> = (0..3).map(|_| ring.next_key().unwrap().0).collect();
+        assert_eq!(order, [0, 1, 0]);
+
+        ring.record_failure(1);
+        ring.record_success(0, 120);
+        let order: Vec<usize> = (0..2).map(|_| ring.next_key().unwrap().0).collect();
+        assert_eq!(order, [0, 0]);
+        ring.record_failure(0);
+        assert!(ring.next_key().unwrap_err().to_string().contains("All 2 API keys failed"));
+        assert_eq!(ring.usage()[0], KeyUsage { requests: 4, failures: 1, tokens: 120 });
+        assert_eq!(ring.usage()[1], KeyUsage { requests: 1, failures: 1, tokens: 0 });
+    }
+
+    #[test]
+    fn test_failover_returns_to_the_first_key_after_its_cooldown() {
+        let ring = KeyRing::new(keys(), Rotation::Failover).with_cooldown(Duration::from_millis(20));
+        assert_eq!(ring.next_key().unwrap().0, 0);
+        assert_eq!(ring.next_key().unwrap().0, 0);
+        ring.record_failure(0);
+        assert_eq!(ring.next_key().unwrap().0, 1);
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(ring.next_key().unwrap().0, 0);
+
+        let headers = ring.keys()[1].openai_headers();
+        assert_eq!(
+            headers,
+            [
+                ("Authorization", "Bearer sk-b".to_string()),
+                ("OpenAI-Organization", "org-research".to_string()),
+                ("OpenAI-Project", "proj-triage".to_string()),
+            ]
+        );
+    }
+}
@@ -1,59 +1,348 @@
 use std::time::Duration;
 use crate::error::{Result, PrismError};
+use keys::{ApiKey, KeyRing, KeyUsage, Rotation};
+
+pub mod cache;
+pub mod calibration;
+#[cfg(feature = "native")]
+mod gemini;
+pub mod keys;
+#[cfg(feature = "native")]
+mod openai;
+pub mod queue;
+pub mod replay;
+pub mod rules;
+#[cfg(all(test, feature = "native"))]
+pub(crate) mod testing;
+pub mod tokenizer;
+
+/// USD per 1,000 input and output tokens for models with published prices.
+const PRICES: &[(&str, (f64, f64))] = &[
+    ("gpt-4", (0.03, 0.06)),
+    ("gpt-4-turbo", (0.01, 0.03)),
+    ("gpt-4o", (0.0025, 0.01)),
+    ("gpt-4o-mini", (0.00015, 0.0006)),
+    ("gpt-3.5-turbo", (0.0005, 0.0015)),
+    ("gemini-pro", (0.0005, 0.0015)),
+    ("gemini-1.5-pro", (0.00125, 0.005)),
+    ("gemini-1.5-flash", (0.000075, 0.0003)),
+];
+
+/// Tokens a request to each model may span, prompt and response together.
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-4", 8_192),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4o", 128_000),
+    ("gpt-4o-mini", 128_000),
+    ("gpt-3.5-turbo", 16_385),
+    ("gemini-pro", 32_760),
+    ("gemini-1.5-pro", 2_097_152),
+    ("gemini-1.5-flash", 1_048_576),
+];
+
+/// The context window assumed for models not in [`CONTEXT_WINDOWS`].
+pub const DEFAULT_CONTEXT_WINDOW: usize = 8_192;
+
+/// The entry of `table` for `model`. Dated snapshots such as
+/// `gpt-4o-2024-08-06` use the entry of their base model.
+fn lookup<'a, T>(table: &'a [(&str, T)], model: &str) -> Option<&'a T> {
+    table
+        .iter()
+        .filter(|(name, _)| model == *name || model.strip_prefix(name).is_some_and(|rest| rest.starts_with('-')))
+        .max_by_key(|(name, _)| name.len())
+        .map(|(_, entry)| entry)
+}
+
+/// Estimated cost in USD of a request to `model`; `None` for models
+/// without a known price.
+pub fn cost(model: &str, input_tokens: usize, output_tokens: usize) -> Option<f64> {
+    lookup(PRICES, model).map(|(input, output)| (input_tokens as f64 * input + output_tokens as f64 * output) / 1000.0)
+}
+
+/// How many tokens a request to `model` may span.
+pub fn context_window(model: &str) -> usize {
+    lookup(CONTEXT_WINDOWS, model).copied().unwrap_or(DEFAULT_CONTEXT_WINDOW)
+}
 
 pub enum LLMProvider {
     OpenAI(String),
     Google(String),
+    /// Answers from local rules, without a key or network.
+    Rules(rules::RuleSet),
 }
 
 #[derive(Clone)]
 pub struct ModelConfig {
     pub model: String,
+    /// Which API serves the model, e.g. `openai` or `google`.
+    pub provider: String,
     pub temperature: f32,
     pub max_tokens: usize,
     pub timeout: Duration,
     pub max_retries: usize,
+    /// Most USD the interpreter's LLM calls may cost in all; further
+    /// requests fail once it is spent.
+    pub budget: Option<f64>,
+    /// The model `llm.complete_checked` asks to verify answers; the
+    /// answering model itself when unset.
+    pub critic: Option<String>,
+    /// Nucleus sampling: only the most likely tokens making up this share
+    /// of probability are sampled. `None` leaves it to the provider, as
+    /// with the penalties.
+    pub top_p: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    /// Sequences that end a completion.
+    pub stop: Vec<String>,
+}
+
+/// What a provider accepts of the sampling parameters.
+struct SamplingLimits {
+    temperature: f32,
+    stop_sequences: usize,
+}
+
+fn sampling_limits(provider: &str) -> SamplingLimits {
+    match provider {
+        "openai" => SamplingLimits { temperature: 2.0, stop_sequences: 4 },
+        "google" => SamplingLimits { temperature: 2.0, stop_sequences: 5 },
+        // Local and unknown providers get the widest settings through
+        _ => SamplingLimits { temperature: f32::INFINITY, stop_sequences: usize::MAX },
+    }
 }
 
 impl Default for ModelConfig {
     fn default() -> Self {
         Self {
             model: "gpt-4".to_string(),
+            provider: "openai".to_string(),
             temperature: 0.7,
             max_tokens: 1000,
             timeout: Duration::from_secs(30),
             max_retries: 3,
+            budget: None,
+            critic: None,
+            top_p: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            stop: Vec::new(),
+        }
+    }
+}
+
+impl ModelConfig {
+    /// Checks the sampling parameters are ones the provider accepts.
+    pub fn validate(&self) -> Result<()> {
+        let limits = sampling_limits(&self.provider);
+        let invalid = |message: String| Err(PrismError::InvalidArgument(message));
+        if !(0.0..=limits.temperature).contains(&self.temperature) {
+            return invalid(format!(
+                "temperature must be between 0 and {} for {}, got {}",
+                limits.temperature, self.provider, self.temperature
+            ));
+        }
+        if let Some(top_p) = self.top_p {
+            if !(0.0..=1.0).contains(&top_p) {
+                return invalid(format!("top_p must be between 0 and 1, got {}", top_p));
+            }
+        }
+        for (name, penalty) in [("presence_penalty", self.presence_penalty), ("frequency_penalty", self.frequency_penalty)] {
+            if let Some(penalty) = penalty.filter(|penalty| !(-2.0..=2.0).contains(penalty)) {
+                return invalid(format!("{} must be between -2 and 2, got {}", name, penalty));
+            }
+        }
+        if self.stop.len() > limits.stop_sequences {
+            return invalid(format!(
+                "{} accepts at most {} stop sequences, got {}",
+                self.provider,
+                limits.stop_sequences,
+                self.stop.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Who a message of a chat is from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
         }
     }
 }
 
+impl std::str::FromStr for Role {
+    type Err = PrismError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "system" => Ok(Role::System),
+            "user" => Ok(Role::User),
+            "assistant" => Ok(Role::Assistant),
+            _ => Err(PrismError::InvalidArgument(format!(
+                "Unknown message role '{}'; expected system, user or assistant",
+                name
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        ChatMessage { role, content: content.into() }
+    }
+}
+
+/// A request for a completion: the `messages` of a chat so far, then
+/// `prompt` as the user's latest message unless it is empty, under the
+/// `system` instructions.
 pub struct CompletionRequest {
     pub prompt: String,
     pub context: Option<String>,
     pub config: Option<ModelConfig>,
+    pub system: Option<String>,
+    pub messages: Vec<ChatMessage>,
+}
+
+impl CompletionRequest {
+    pub fn new(prompt: impl Into<String>) -> Self {
+        CompletionRequest { prompt: prompt.into(), context: None, config: None, system: None, messages: Vec::new() }
+    }
+
+    pub fn with_system(mut self, system: impl Into<String>) -> Self {
+        self.system = Some(system.into());
+        self
+    }
+
+    pub fn with_messages(mut self, messages: Vec<ChatMessage>) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    /// The messages of the chat, ending with the prompt.
+    pub fn chat(&self) -> Vec<ChatMessage> {
+        let mut chat = self.messages.clone();
+        if !self.prompt.is_empty() {
+            chat.push(ChatMessage::new(Role::User, self.prompt.clone()));
+        }
+        chat
+    }
+
+    /// The request as one text, for what keys or counts prompts as text:
+    /// the prompt itself when it stands alone, and otherwise every message
+    /// on lines of `role: content`.
+    pub fn transcript(&self) -> String {
+        if self.system.is_none() && self.messages.is_empty() {
+            return self.prompt.clone();
+        }
+        let system = self.system.iter().map(|system| ChatMessage::new(Role::System, system.clone()));
+        system
+            .chain(self.chat())
+            .map(|message| format!("{}: {}\n", message.role.as_str(), message.content))
+            .collect()
+    }
+}
+
+/// Tokens a completion took, as the provider counted them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
 }
 
 pub struct CompletionResponse {
     pub text: String,
     pub confidence: f32,
     pub model: String,
+    pub usage: TokenUsage,
 }
 
 pub struct LLMClient {
     provider: LLMProvider,
     config: ModelConfig,
+    keys: KeyRing,
+    /// Where requests go instead of the provider's API, e.g. a proxy or an
+    /// OpenAI-compatible server.
+    base_url: Option<String>,
+    #[cfg(feature = "native")]
+    http: reqwest::Client,
+}
+
+impl LLMProvider {
+    fn key(&self) -> Option<&str> {
+        match self {
+            LLMProvider::OpenAI(key) | LLMProvider::Google(key) => Some(key),
+            LLMProvider::Rules(_) => None,
+        }
+    }
+
+    /// The provider as model settings name it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LLMProvider::OpenAI(_) => "openai",
+            LLMProvider::Google(_) => "google",
+            LLMProvider::Rules(_) => rules::PROVIDER,
+        }
+    }
 }
 
 impl LLMClient {
     pub fn new(provider: LLMProvider) -> Self {
+        Self::with_config(provider, ModelConfig::default())
+    }
+
+    pub fn with_config(provider: LLMProvider, config: ModelConfig) -> Self {
+        let keys = KeyRing::new(provider.key().map(ApiKey::new).into_iter().collect(), Rotation::default());
         Self {
             provider,
-            config: ModelConfig::default(),
+            config,
+            keys,
+            base_url: None,
+            #[cfg(feature = "native")]
+            http: reqwest::Client::new(),
         }
     }
 
-    pub fn with_config(provider: LLMProvider, config: ModelConfig) -> Self {
-        Self { provider, config }
+    /// Sends requests to `base_url` instead of the provider's API.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    pub fn base_url(&self) -> Option<&str> {
+        self.base_url.as_deref()
+    }
+
+    /// Spreads requests over `keys` instead of the provider's single key.
+    pub fn with_keys(mut self, keys: KeyRing) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    pub fn keys(&self) -> &KeyRing {
+        &self.keys
+    }
+
+    /// Requests, failures and tokens by key.
+    pub fn key_usage(&self) -> Vec<KeyUsage> {
+        self.keys.usage()
     }
 
     pub fn get_provider(&self) -> &LLMProvider {
@@ -64,8 +353,234 @@ impl LLMClient {
         &self.config
     }
 
-    pub async fn complete(&self, _request: CompletionRequest) -> Result<CompletionResponse> {
-        // For now, just return an error since we haven't implemented the actual API calls
-        Err(PrismError::RuntimeError("LLM API not implemented yet".to_string()))
+    /// Completes `request` with its own model settings, or the client's.
+    pub async fn complete(&self, request: CompletionRequest) -> Result<CompletionResponse> {
+        match &self.provider {
+            LLMProvider::Rules(rules) => {
+                let (text, confidence) = rules.answer(&request.transcript()).ok_or_else(|| {
+                    PrismError::RuntimeError(format!("No rule answers the prompt: {}", request.prompt))
+                })?;
+                let model = rules::PROVIDER.to_string();
+                Ok(CompletionResponse { text, confidence: confidence as f32, model, usage: TokenUsage::default() })
+            }
+            _ => self.request(request).await,
+        }
+    }
+
+    /// Sends `request` with the next key, moving on to the one after when
+    /// a key fails, up to the configured number of retries.
+    #[cfg(feature = "native")]
+    async fn request(&self, request: CompletionRequest) -> Result<CompletionResponse> {
+        let config = request.config.as_ref().unwrap_or(&self.config);
+        config.validate()?;
+        let base_url = self.base_url.as_deref();
+        let mut retries = config.max_retries;
+        let mut failure = None;
+        loop {
+            let (index, key) = match self.keys.next_key() {
+                Ok(next) => next,
+                // Every key failed recently; the last failure says why
+                Err(err) => return Err(failure.unwrap_or(err)),
+            };
+            let result = match &self.provider {
+                LLMProvider::Google(_) => gemini::complete(&self.http, key, &request, config, base_url).await,
+                _ => openai::complete(&self.http, key, &request, config, base_url).await,
+            };
+            match result {
+                Ok(response) => {
+                    self.keys.record_success(index, response.usage.total_tokens as u64);
+                    return Ok(response);
+                }
+                // The request itself is wrong, whatever the key
+                Err(err @ PrismError::InvalidArgument(_)) => return Err(err),
+                Err(err) => {
+                    self.keys.record_failure(index);
+                    if retries == 0 {
+                        return Err(err);
+                    }
+                    retries -= 1;
+                    failure = Some(err);
+                }
+            }
+        }
+    }
+
+    #[cfg(not(feature = "native"))]
+    async fn request(&self, _request: CompletionRequest) -> Result<CompletionResponse> {
+        Err(PrismError::RuntimeError(format!("Requests to {} need the native feature", self.provider.name())))
+    }
+}
+
+/// Sends a request to a provider and reads its JSON answer. A request
+/// the provider rejects as malformed fails with `InvalidArgument`; other
+/// failures, such as a refused key or a rate limit, may pass with another
+/// key or a later try.
+#[cfg(feature = "native")]
+async fn send<T: serde::de::DeserializeOwned>(request: reqwest::RequestBuilder) -> Result<T> {
+    use reqwest::StatusCode;
+
+    // The URL may carry a proxy's credentials
+    let failed = |err: reqwest::Error| PrismError::RuntimeError(format!("LLM request failed: {}", err.without_url()));
+    let response = request.send().await.map_err(failed)?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        let message = format!("LLM request failed with {}: {}", status, body.trim());
+        let key_or_load = [StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::TOO_MANY_REQUESTS].contains(&status);
+        return Err(match status.is_client_error() && !key_or_load {
+            true => PrismError::InvalidArgument(message),
+            false => PrismError::RuntimeError(message),
+        });
+    }
+    response.json().await.map_err(failed)
+}
+
+#[cfg(feature = "native")]
+fn empty_response(field: &str) -> PrismError {
+    PrismError::RuntimeError(format!("The provider answered with no {}", field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cost_uses_the_most_specific_price() {
+        assert_eq!(cost("gpt-4", 1000, 1000), Some(0.09));
+        assert_eq!(cost("gpt-4o-mini-2024-07-18", 1000, 0), Some(0.00015));
+        assert_eq!(cost("gpt-4o", 0, 1000), Some(0.01));
+        assert_eq!(cost("gpt-40", 1000, 0), None);
+        assert_eq!(cost("local-model", 1000, 0), None);
+    }
+
+    #[test]
+    fn test_transcripts_list_every_role() {
+        let alone = CompletionRequest::new("What is 2+2?");
+        assert_eq!(alone.transcript(), "What is 2+2?");
+
+        let chat = CompletionRequest::new("And 3+3?")
+            .with_system("Answer with a number")
+            .with_messages(vec![ChatMessage::new(Role::User, "2+2?"), ChatMessage::new(Role::Assistant, "4")]);
+        assert_eq!(chat.transcript(), "system: Answer with a number\nuser: 2+2?\nassistant: 4\nuser: And 3+3?\n");
+        assert_eq!(chat.chat().len(), 3);
+        assert!("critic".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn test_sampling_parameters_are_checked_per_provider() {
+        let config = ModelConfig { top_p: Some(0.9), frequency_penalty: Some(0.5), ..ModelConfig::default() };
+        assert!(config.validate().is_ok());
+        assert!(ModelConfig { top_p: Some(1.5), ..config.clone() }.validate().is_err());
+        assert!(ModelConfig { presence_penalty: Some(-3.0), ..config.clone() }.validate().is_err());
+        assert!(ModelConfig { temperature: 2.5, ..config.clone() }.validate().is_err());
+        assert!(ModelConfig { temperature: 2.5, provider: "local".to_string(), ..config.clone() }.validate().is_ok());
+
+        let stop: Vec<String> = ["\n\n", "END", "###", "Q:", "A:"].map(String::from).to_vec();
+        let err = ModelConfig { stop: stop.clone(), ..config.clone() }.validate().unwrap_err();
+        assert!(err.to_string().contains("at most 4 stop sequences"), "{}", err);
+        assert!(ModelConfig { stop, provider: "google".to_string(), ..config }.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rule_clients_answer_without_a_key() -> Result<()> {
+        let rules = rules::RuleSet::new().with_keywords(&["fever"], "Check for infection", 0.7)?;
+        let client = LLMClient::new(LLMProvider::Rules(rules));
+        assert!(client.keys().keys().is_empty());
+        let response = client.complete(CompletionRequest::new("Fever of 39")).await?;
+        assert_eq!((response.text.as_str(), response.confidence), ("Check for infection", 0.7));
+        assert!(client.complete(CompletionRequest::new("Rash")).await.is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn test_clients_send_chats_in_their_provider_format() -> Result<()> {
+        let server = testing::EchoServer::start();
+        let chat = || {
+            CompletionRequest::new("And 3+3?")
+                .with_system("Be brief")
+                .with_messages(vec![ChatMessage::new(Role::User, "2+2?"), ChatMessage::new(Role::Assistant, "4")])
+        };
+
+        let response = server.client().complete(chat()).await?;
+        assert_eq!(response.text, "LLM response to: system: Be brief\nuser: 2+2?\nassistant: 4\nuser: And 3+3?\n");
+        assert_eq!((response.confidence, response.usage.total_tokens), (0.95, 15));
+
+        let config = ModelConfig { model: "gemini-1.5-flash".to_string(), provider: "google".to_string(), ..ModelConfig::default() };
+        let gemini = LLMClient::with_config(LLMProvider::Google("g-key".to_string()), config).with_base_url(server.url());
+        let response = gemini.complete(chat()).await?;
+        assert_eq!(response.text, "LLM response to: system: Be brief\nuser: 2+2?\nmodel: 4\nuser: And 3+3?\n");
+
+        let received = server.received();
+        assert_eq!(received[0].headers["authorization"], "Bearer sk-test");
+        assert_eq!(received[1].path, "/v1beta/models/gemini-1.5-flash:generateContent");
+        assert_eq!(received[1].headers["x-goog-api-key"], "g-key");
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn test_requests_rotate_over_keys_and_skip_refused_ones() -> Result<()> {
+        let server = testing::EchoServer::start();
+        let keys = vec![
+            ApiKey::new("sk-a").with_organization("org-research").with_project("proj-triage"),
+            ApiKey::new("sk-revoked"),
+            ApiKey::new("sk-c"),
+        ];
+        let client = server.client().with_keys(KeyRing::new(keys, Rotation::RoundRobin));
+        for _ in 0..3 {
+            client.complete(CompletionRequest::new("Fever?")).await?;
+        }
+
+        // The refused key was retried with the next one and then left out
+        let sent: Vec<String> = server.received().iter().map(|request| request.headers["authorization"].clone()).collect();
+        assert_eq!(sent, ["Bearer sk-a", "Bearer sk-revoked", "Bearer sk-c", "Bearer sk-a"]);
+        assert_eq!(server.received()[0].headers["openai-organization"], "org-research");
+        assert_eq!(server.received()[0].headers["openai-project"], "proj-triage");
+        let usage = client.key_usage();
+        assert_eq!(usage[0], KeyUsage { requests: 2, failures: 0, tokens: 30 });
+        assert_eq!(usage[1], KeyUsage { requests: 1, failures: 1, tokens: 0 });
+        assert_eq!(usage[2], KeyUsage { requests: 1, failures: 0, tokens: 15 });
+
+        // With no key left the refusal itself is reported
+        let revoked = KeyRing::new(vec![ApiKey::new("sk-revoked")], Rotation::Failover);
+        let err = server.client().with_keys(revoked).complete(CompletionRequest::new("Fever?")).await.err().unwrap();
+        assert!(err.to_string().contains("401"), "{}", err);
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn test_sampling_parameters_reach_each_provider() -> Result<()> {
+        let server = testing::EchoServer::start();
+        let config = ModelConfig {
+            model: "gemini-1.5-pro".to_string(),
+            provider: "google".to_string(),
+            max_tokens: 200,
+            top_p: Some(0.8),
+            frequency_penalty: Some(0.3),
+            stop: vec!["END".to_string()],
+            ..ModelConfig::default()
+        };
+        let gemini = LLMClient::new(LLMProvider::Google("g-key".to_string())).with_base_url(server.url());
+        let request = CompletionRequest { config: Some(config.clone()), ..CompletionRequest::new("Dose?") };
+        gemini.complete(request).await?;
+        let generation = &server.received()[0].body["generationConfig"];
+        assert_eq!((generation["maxOutputTokens"].as_u64(), generation["topP"].as_f64()), (Some(200), Some(0.8)));
+        assert_eq!((generation["frequencyPenalty"].as_f64(), &generation["stopSequences"]), (Some(0.3), &serde_json::json!(["END"])));
+        assert!(generation.get("presencePenalty").is_none());
+
+        // Settings the provider rejects never leave the client
+        let request = CompletionRequest { config: Some(ModelConfig { top_p: Some(1.5), ..config }), ..CompletionRequest::new("Dose?") };
+        assert!(gemini.complete(request).await.is_err());
+        assert_eq!(server.received().len(), 1);
+        Ok(())
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_context_windows() {
+        assert_eq!(context_window("gpt-4"), 8_192);
+        assert_eq!(context_window("gpt-4o-2024-08-06"), 128_000);
+        assert_eq!(context_window("llama3"), DEFAULT_CONTEXT_WINDOW);
+    }
+}
@@ -1,34 +1,45 @@
 use serde::{Deserialize, Serialize};
 use crate::error::Result;
-use super::{CompletionRequest, CompletionResponse, TokenUsage};
+use super::keys::ApiKey;
+use super::{ChatMessage, CompletionRequest, CompletionResponse, ModelConfig, TokenUsage};
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com";
 
 #[derive(Debug, Serialize)]
 struct OpenAIRequest {
     model: String,
     messages: Vec<Message>,
-    temperature: f64,
+    temperature: f32,
     max_tokens: usize,
-    top_p: f64,
-    frequency_penalty: f64,
-    presence_penalty: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Message {
     role: String,
-    content: String,
+    // Null when the model only called tools
+    #[serde(default)]
+    content: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 struct OpenAIResponse {
     choices: Vec<Choice>,
-    usage: Usage,
+    #[serde(default)]
+    usage: Option<Usage>,
 }
 
 #[derive(Debug, Deserialize)]
 struct Choice {
     message: Message,
-    finish_reason: String,
+    finish_reason: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -38,80 +49,82 @@ struct Usage {
     total_tokens: usize,
 }
 
+/// The OpenAI chat messages for `chat` under the `system` instructions.
+fn messages(system: Option<String>, chat: &[ChatMessage]) -> Vec<Message> {
+    let system = system.map(|content| Message { role: "system".to_string(), content: Some(content) });
+    system
+        .into_iter()
+        .chain(chat.iter().map(|message| Message {
+            role: message.role.as_str().to_string(),
+            content: Some(message.content.clone()),
+        }))
+        .collect()
+}
+
 pub(crate) async fn complete(
     client: &reqwest::Client,
-    api_key: &str,
-    request: CompletionRequest,
-    model_name: &str,
-    temperature: f64,
-    max_tokens: usize,
-    base_url: Option<String>,
+    api_key: &ApiKey,
+    request: &CompletionRequest,
+    config: &ModelConfig,
+    base_url: Option<&str>,
 ) -> Result<CompletionResponse> {
-    let messages = vec![
-        Message {
-            role: "system".to_string(),
-            content: format!(
-                "You are an AI assistant with the following context: {}",
-                request.context.as_ref().map_or("None".to_string(), |ctx| ctx.to_string())
-            ),
-        },
-        Message {
-            role: "user".to_string(),
-            content: request.prompt.clone(),
-        },
-    ];
+    let system = match (&request.system, &request.context) {
+        (Some(system), Some(context)) => Some(format!("{}\n\nContext: {}", system, context)),
+        (Some(system), None) => Some(system.clone()),
+        (None, Some(context)) => Some(format!("You are an AI assistant with the following context: {}", context)),
+        (None, None) => None,
+    };
 
     let openai_request = OpenAIRequest {
-        model: model_name.to_string(),
-        messages,
-        temperature,
-        max_tokens,
-        top_p: 1.0,
-        frequency_penalty: 0.0,
-        presence_penalty: 0.0,
+        model: config.model.clone(),
+        messages: messages(system, &request.chat()),
+        temperature: config.temperature,
+        max_tokens: config.max_tokens,
+        top_p: config.top_p,
+        frequency_penalty: config.frequency_penalty,
+        presence_penalty: config.presence_penalty,
+        stop: config.stop.clone(),
     };
 
-    let base_url = base_url.unwrap_or_else(|| 
-        "https://api.openai.com".to_string()
-    );
-
-    let response = client
-        .post(format!("{}/v1/chat/completions", base_url))
-        .header("Authorization", format!("Bearer {}", api_key))
-        .json(&openai_request)
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<OpenAIResponse>()
-        .await?;
+    let base_url = base_url.unwrap_or(DEFAULT_BASE_URL);
+    let mut post = client.post(format!("{}/v1/chat/completions", base_url)).timeout(config.timeout);
+    for (name, value) in api_key.openai_headers() {
+        post = post.header(name, value);
+    }
+    let response: OpenAIResponse = super::send(post.json(&openai_request)).await?;
 
-    let choice = response.choices.first().ok_or_else(|| {
-        std::io::Error::new(std::io::ErrorKind::Other, "No completion choices returned")
-    })?;
+    let choice = response.choices.into_iter().next().ok_or_else(|| super::empty_response("choices"))?;
 
     // Calculate confidence based on finish reason
-    let confidence = match choice.finish_reason.as_str() {
-        "stop" => 0.95, // Natural completion
-        "length" => 0.7, // Cut off by max tokens
+    let confidence = match choice.finish_reason.as_deref() {
+        Some("stop") => 0.95, // Natural completion
+        Some("length") => 0.7, // Cut off by max tokens
         _ => 0.5, // Other reasons (content filter, etc.)
     };
 
     Ok(CompletionResponse {
-        text: choice.message.content.clone(),
+        text: choice.message.content.unwrap_or_default(),
         confidence,
-        model: model_name.to_string(),
-        usage: TokenUsage {
-            prompt_tokens: response.usage.prompt_tokens,
-            completion_tokens: response.usage.completion_tokens,
-            total_tokens: response.usage.total_tokens,
-        },
+        model: config.model.clone(),
+        usage: response.usage.map_or_else(TokenUsage::default, |usage| TokenUsage {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            total_tokens: usage.total_tokens,
+        }),
     })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::llm::ModelConfig;
+    use crate::llm::Role;
+
+    #[test]
+    fn test_messages_keep_their_roles() {
+        let chat = [ChatMessage::new(Role::User, "2+2?"), ChatMessage::new(Role::Assistant, "4")];
+        let roles: Vec<String> = messages(Some("Be brief".to_string()), &chat).into_iter().map(|m| m.role).collect();
+        assert_eq!(roles, ["system", "user", "assistant"]);
+    }
 
     #[tokio::test]
     async fn test_openai_completion() -> Result<()> {
@@ -122,22 +135,10 @@ mod tests {
         }
 
         let client = reqwest::Client::new();
-        let request = CompletionRequest {
-            prompt: "What is 2+2?".to_string(),
-            context: None,
-            config: Some(ModelConfig::default()),
-        };
-
-        let response = complete(
-            &client,
-            &api_key.unwrap(),
-            request,
-            "gpt-3.5-turbo",
-            0.7,
-            100,
-            None,
-        )
-        .await?;
+        let request = CompletionRequest::new("What is 2+2?").with_system("Answer with a number");
+        let config = ModelConfig { model: "gpt-3.5-turbo".to_string(), max_tokens: 100, ..ModelConfig::default() };
+
+        let response = complete(&client, &ApiKey::new(api_key.unwrap()), &request, &config, None).await?;
 
         assert!(!response.text.is_empty());
         assert!(response.confidence > 0.0 && response.confidence <= 1.0);
@@ -145,4 +146,4 @@ mod tests {
 
         Ok(())
     }
-} 
\ No newline at end of file
+}
@@ -0,0 +1,228 @@
+//! Pacing LLM requests to what providers allow.
+//!
+//! Concurrent tasks can send many requests at once and run into a
+//! provider's rate limits. Every request first waits in a [`RequestQueue`]
+//! until its provider has fewer than `max_in_flight` requests in flight and
+//! fewer than `requests_per_minute` started in the last minute. Interpreters
+//! share the process-wide [`global`] queue, configured by the `[providers]`
+//! table of the [configuration](crate::config):
+//!
+//! ```toml
+//! [providers.openai]
+//! max_in_flight = 4
+//! requests_per_minute = 60
+//! ```
+//!
+//! Providers without limits are not queued. How long requests wait is
+//! reported through [`Metrics`](crate::metrics::Metrics).
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+use std::sync::{Arc, OnceLock};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+
+/// The span `requests_per_minute` counts requests in.
+const PACING_WINDOW: Duration = Duration::from_secs(60);
+
+static GLOBAL: OnceLock<Arc<RequestQueue>> = OnceLock::new();
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProviderLimits {
+    pub max_in_flight: Option<usize>,
+    pub requests_per_minute: Option<usize>,
+}
+
+#[derive(Default)]
+struct Lane {
+    /// One permit per request allowed in flight, handed out in the order
+    /// requests asked for them.
+    slots: Option<Arc<Semaphore>>,
+    waiting: usize,
+    /// When the requests of the last [`PACING_WINDOW`] started, or are due
+    /// to start.
+    started: VecDeque<Instant>,
+}
+
+#[derive(Default)]
+pub struct RequestQueue {
+    limits: BTreeMap<String, ProviderLimits>,
+    lanes: Mutex<HashMap<String, Lane>>,
+}
+
+/// A request's place among those in flight; dropping it lets the next one
+/// start.
+pub struct Permit {
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+impl RequestQueue {
+    pub fn new(limits: BTreeMap<String, ProviderLimits>) -> Self {
+        RequestQueue { limits, lanes: Mutex::new(HashMap::new()) }
+    }
+
+    /// Waits for a request to `provider` to be allowed to start, giving up
+    /// when `interpreter`'s evaluation is cancelled.
+    pub async fn acquire(&self, provider: &str, interpreter: &mut Interpreter) -> Result<Permit> {
+        let queued = Instant::now();
+        let slot = match self.slots(provider) {
+            Some(slots) => match Arc::clone(&slots).try_acquire_owned() {
+                Ok(slot) => Some(slot),
+                Err(_) => Some(self.waiting_for(provider, next_slot(slots, interpreter)).await?),
+            },
+            None => None,
+        };
+        let start = self.reserve_start(provider);
+        let wait = start.saturating_duration_since(Instant::now());
+        if !wait.is_zero() {
+            if let Err(err) = self.waiting_for(provider, interpreter.sleep(wait)).await {
+                self.cancel_start(provider, start);
+                return Err(err);
+            }
+        }
+        interpreter.metrics().record_queue_wait(provider, queued.elapsed());
+        Ok(Permit { _slot: slot })
+    }
+
+    /// Counts a request as waiting while `wait` runs.
+    async fn waiting_for<T>(&self, provider: &str, wait: impl Future<Output = Result<T>>) -> Result<T> {
+        self.set_waiting(provider, 1);
+        let result = wait.await;
+        self.set_waiting(provider, -1);
+        result
+    }
+
+    /// The permits for `provider`'s requests in flight, if it limits them.
+    fn slots(&self, provider: &str) -> Option<Arc<Semaphore>> {
+        self.limits.get(provider)?.max_in_flight?;
+        self.lanes.lock().entry(provider.to_string()).or_insert_with(|| self.lane(provider)).slots.clone()
+    }
+
+    fn lane(&self, provider: &str) -> Lane {
+        let slots = self.limits.get(provider).and_then(|limits| limits.max_in_flight);
+        Lane { slots: slots.map(|max| Arc::new(Semaphore::new(max))), ..Lane::default() }
+    }
+
+    /// Takes the earliest start `provider`'s `requests_per_minute` leaves,
+    /// after those already taken.
+    fn reserve_start(&self, provider: &str) -> Instant {
+        let now = Instant::now();
+        let Some(max) = self.limits.get(provider).and_then(|limits| limits.requests_per_minute) else {
+            return now;
+        };
+        let mut lanes = self.lanes.lock();
+        let lane = lanes.entry(provider.to_string()).or_insert_with(|| self.lane(provider));
+        while lane.started.front().is_some_and(|started| now.saturating_duration_since(*started) >= PACING_WINDOW) {
+            lane.started.pop_front();
+        }
+        let start = match lane.started.len().checked_sub(max) {
+            // Starts are in order, so this one is due a window after the
+            // start `max` places back
+            Some(back) => lane.started[back] + PACING_WINDOW,
+            None => now,
+        };
+        lane.started.push_back(start);
+        start
+    }
+
+    /// Gives back a start a cancelled request reserved.
+    fn cancel_start(&self, provider: &str, start: Instant) {
+        if let Some(lane) = self.lanes.lock().get_mut(provider) {
+            if let Some(index) = lane.started.iter().position(|started| *started == start) {
+                lane.started.remove(index);
+            }
+        }
+    }
+
+    fn set_waiting(&self, provider: &str, change: isize) {
+        let mut lanes = self.lanes.lock();
+        let lane = lanes.entry(provider.to_string()).or_insert_with(|| self.lane(provider));
+        lane.waiting = lane.waiting.saturating_add_signed(change);
+        metrics::gauge!("prism_llm_queue_depth", "provider" => provider.to_string()).set(lane.waiting as f64);
+    }
+
+    /// Requests to `provider` waiting for their turn.
+    pub fn waiting(&self, provider: &str) -> usize {
+        self.lanes.lock().get(provider).map_or(0, |lane| lane.waiting)
+    }
+}
+
+/// Waits for the next of `slots` to come free, giving up when
+/// `interpreter`'s evaluation is cancelled.
+async fn next_slot(slots: Arc<Semaphore>, interpreter: &mut Interpreter) -> Result<OwnedSemaphorePermit> {
+    let mut slot = pin!(slots.acquire_owned());
+    let mut cancelled = pin!(async {
+        loop {
+            interpreter.sleep(PACING_WINDOW).await?;
+        }
+    });
+    poll_fn(|cx| match slot.as_mut().poll(cx) {
+        // The queue never closes its semaphores
+        Poll::Ready(slot) => Poll::Ready(slot.map_err(|err| PrismError::RuntimeError(err.to_string()))),
+        Poll::Pending => cancelled.as_mut().poll(cx),
+    })
+    .await
+}
+
+/// The queue every interpreter shares, with the configured limits.
+pub fn global() -> Arc<RequestQueue> {
+    Arc::clone(GLOBAL.get_or_init(|| Arc::new(RequestQueue::new(crate::config::global().providers.clone()))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(max_in_flight: Option<usize>, requests_per_minute: Option<usize>) -> Arc<RequestQueue> {
+        let limits = ProviderLimits { max_in_flight, requests_per_minute };
+        Arc::new(RequestQueue::new(BTreeMap::from([("openai".to_string(), limits)])))
+    }
+
+    #[test]
+    fn test_paced_requests_take_the_next_free_start() {
+        let paced = queue(None, Some(2));
+        let first = paced.reserve_start("openai");
+        let second = paced.reserve_start("openai");
+        assert!(second.duration_since(first) < Duration::from_secs(1));
+        assert_eq!(paced.reserve_start("openai"), first + PACING_WINDOW);
+        assert_eq!(paced.reserve_start("openai"), second + PACING_WINDOW);
+
+        // A start given back is free for the next request
+        paced.cancel_start("openai", second + PACING_WINDOW);
+        assert_eq!(paced.reserve_start("openai"), second + PACING_WINDOW);
+        assert!(paced.reserve_start("google").duration_since(first) < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_waiting_requests_start_in_turn() -> Result<()> {
+        let queue = queue(Some(1), None);
+        let first = queue.acquire("openai", &mut Interpreter::new()).await?;
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let mut waiters = Vec::new();
+        for name in ["second", "third"] {
+            let (queue, started) = (Arc::clone(&queue), Arc::clone(&started));
+            waiters.push(tokio::spawn(async move {
+                let _permit = queue.acquire("openai", &mut Interpreter::new()).await?;
+                started.lock().push(name);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                Ok::<_, PrismError>(())
+            }));
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(queue.waiting("openai"), 2);
+        drop(first);
+        for waiter in waiters {
+            waiter.await.unwrap()?;
+        }
+        assert_eq!(*started.lock(), ["second", "third"]);
+        assert_eq!(queue.waiting("openai"), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! Recording and replaying LLM exchanges.
+//!
+//! A [`Recorder`] appends every request and response of a run to a JSON
+//! Lines file; a [`Replay`] loaded from that file answers the same requests
+//! from the recording instead of the provider, so a past run can be
+//! reproduced exactly. Prompts and responses are stored with secrets
+//! redacted.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::path::Path;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use crate::error::{PrismError, Result};
+
+/// One LLM request and its response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    pub model: String,
+    pub prompt: String,
+    pub response: String,
+    pub confidence: f64,
+}
+
+/// Writes exchanges as JSON lines as they happen.
+pub struct Recorder {
+    sink: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Recorder {
+    pub fn new(sink: impl Write + Send + 'static) -> Self {
+        Self { sink: Mutex::new(Box::new(sink)) }
+    }
+
+    /// Records into a new file at `path`, replacing any old recording.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(std::fs::File::create(path)?))
+    }
+
+    pub fn record(&self, exchange: &Exchange) -> Result<()> {
+        let mut line = serde_json::to_string(exchange)?;
+        line.push('\n');
+        let mut sink = self.sink.lock();
+        sink.write_all(line.as_bytes())?;
+        Ok(sink.flush()?)
+    }
+}
+
+/// Serves recorded responses. A request asked several times gets its
+/// recorded responses in their original order.
+#[derive(Debug, Default)]
+pub struct Replay {
+    responses: Mutex<HashMap<(String, String), VecDeque<Exchange>>>,
+}
+
+impl Replay {
+    pub fn from_jsonl(text: &str) -> Result<Self> {
+        let mut responses: HashMap<(String, String), VecDeque<Exchange>> = HashMap::new();
+        for (index, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let exchange: Exchange = serde_json::from_str(line).map_err(|e| {
+                PrismError::InvalidArgument(format!("replay line {} is not an exchange: {}", index + 1, e))
+            })?;
+            responses.entry((exchange.model.clone(), exchange.prompt.clone())).or_default().push_back(exchange);
+        }
+        Ok(Self { responses: Mutex::new(responses) })
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_jsonl(&std::fs::read_to_string(path)?)
+    }
+
+    /// The next recorded response to `prompt` sent to `model`.
+    pub fn next(&self, model: &str, prompt: &str) -> Result<Exchange> {
+        self.responses
+            .lock()
+            .get_mut(&(model.to_string(), prompt.to_string()))
+            .and_then(VecDeque::pop_front)
+            .ok_or_else(|| PrismError::RuntimeError(format!(
+                "the replay has no recorded response for this {} prompt: {}",
+                model, prompt
+            )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_recorded_exchanges_replay_in_order() -> Result<()> {
+        let exchange = |response: &str| Exchange {
+            model: "gpt-4".to_string(),
+            prompt: "Diagnose".to_string(),
+            response: response.to_string(),
+            confidence: 0.8,
+        };
+        let buffer = Shared::default();
+        let recorder = Recorder::new(buffer.clone());
+        recorder.record(&exchange("flu"))?;
+        recorder.record(&exchange("cold"))?;
+
+        let replay = Replay::from_jsonl(&String::from_utf8(buffer.0.lock().clone()).unwrap())?;
+        assert_eq!(replay.next("gpt-4", "Diagnose")?, exchange("flu"));
+        assert_eq!(replay.next("gpt-4", "Diagnose")?.response, "cold");
+        assert!(replay.next("gpt-4", "Diagnose").is_err());
+        assert!(replay.next("gpt-4o", "Diagnose").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+//! A local, rule-based stand-in for a model.
+//!
+//! A [`RuleSet`] answers prompts from rules registered in advance: a regular
+//! expression or a set of keywords, the response to give (regex responses
+//! may refer to captures as `$1` or `$name`) and how confident it is. The
+//! first matching rule answers. Rules cost nothing and always answer the
+//! same, for unit tests, offline demos and pipelines that only ask a real
+//! model when no rule answers confidently enough.
+
+use regex::Regex;
+use crate::error::{PrismError, Result};
+
+/// The provider name that makes the interpreter answer from its rules.
+pub const PROVIDER: &str = "rules";
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Regex(Regex),
+    /// Every keyword must appear in the prompt, ignoring case.
+    Keywords(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    matcher: Matcher,
+    response: String,
+    confidence: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+fn checked(confidence: f64) -> Result<f64> {
+    if (0.0..=1.0).contains(&confidence) {
+        Ok(confidence)
+    } else {
+        Err(PrismError::InvalidArgument(format!("A rule's confidence must be between 0 and 1, got {}", confidence)))
+    }
+}
+
+impl RuleSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers prompts matching `pattern` with `response`.
+    pub fn with_regex(mut self, pattern: &str, response: &str, confidence: f64) -> Result<Self> {
+        self.add_regex(pattern, response, confidence)?;
+        Ok(self)
+    }
+
+    /// Answers prompts containing all `keywords` with `response`.
+    pub fn with_keywords(mut self, keywords: &[&str], response: &str, confidence: f64) -> Result<Self> {
+        self.add_keywords(keywords, response, confidence)?;
+        Ok(self)
+    }
+
+    pub fn add_regex(&mut self, pattern: &str, response: &str, confidence: f64) -> Result<()> {
+        let regex = Regex::new(pattern)
+            .map_err(|err| PrismError::InvalidArgument(format!("Invalid rule pattern '{}': {}", pattern, err)))?;
+        let confidence = checked(confidence)?;
+        self.rules.push(Rule { matcher: Matcher::Regex(regex), response: response.to_string(), confidence });
+        Ok(())
+    }
+
+    pub fn add_keywords(&mut self, keywords: &[&str], response: &str, confidence: f64) -> Result<()> {
+        if keywords.is_empty() {
+            return Err(PrismError::InvalidArgument("A keyword rule needs at least one keyword".to_string()));
+        }
+        let keywords = keywords.iter().map(|keyword| keyword.to_lowercase()).collect();
+        let confidence = checked(confidence)?;
+        self.rules.push(Rule { matcher: Matcher::Keywords(keywords), response: response.to_string(), confidence });
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The response and confidence of the first rule matching `prompt`.
+    pub fn answer(&self, prompt: &str) -> Option<(String, f64)> {
+        let lowercase = prompt.to_lowercase();
+        self.rules.iter().find_map(|rule| {
+            let response = match &rule.matcher {
+                Matcher::Regex(regex) => {
+                    let captures = regex.captures(prompt)?;
+                    let mut response = String::new();
+                    captures.expand(&rule.response, &mut response);
+                    response
+                }
+                Matcher::Keywords(keywords) => {
+                    if !keywords.iter().all(|keyword| lowercase.contains(keyword)) {
+                        return None;
+                    }
+                    rule.response.clone()
+                }
+            };
+            Some((response, rule.confidence))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_the_first_matching_rule_answers() -> Result<()> {
+        let rules = RuleSet::new()
+            .with_regex(r"(?i)dose of (?P<drug>\w+)", "Check the $drug label", 0.6)?
+            .with_keywords(&["chest", "pain"], "urgent", 0.9)?
+            .with_keywords(&["pain"], "routine", 0.5)?;
+        assert_eq!(rules.answer("Usual DOSE of ibuprofen?"), Some(("Check the ibuprofen label".to_string(), 0.6)));
+        assert_eq!(rules.answer("Chest pain since noon"), Some(("urgent".to_string(), 0.9)));
+        assert_eq!(rules.answer("Knee pain"), Some(("routine".to_string(), 0.5)));
+        assert_eq!(rules.answer("Rash on the arm"), None);
+
+        assert!(RuleSet::new().with_regex("(unclosed", "x", 0.5).is_err());
+        assert!(RuleSet::new().with_keywords(&["rash"], "x", 1.5).is_err());
+        assert!(RuleSet::new().with_keywords(&[], "x", 0.5).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! A local stand-in for the OpenAI and Gemini APIs that answers every
+//! completion with `LLM response to: ` and the chat it was sent, so tests
+//! go through the real request path without a key or network. Keys with
+//! `revoked` in them are refused.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use super::{LLMClient, LLMProvider};
+
+/// A request the server received.
+#[derive(Debug, Clone)]
+pub(crate) struct Received {
+    pub path: String,
+    /// Header names are lowercase.
+    pub headers: HashMap<String, String>,
+    pub body: Value,
+}
+
+pub(crate) struct EchoServer {
+    url: String,
+    received: Arc<Mutex<Vec<Received>>>,
+}
+
+impl EchoServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&received);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let log = Arc::clone(&log);
+                std::thread::spawn(move || answer(stream, &log));
+            }
+        });
+        EchoServer { url, received }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// An OpenAI client sending its requests here.
+    pub fn client(&self) -> LLMClient {
+        LLMClient::new(LLMProvider::OpenAI("sk-test".to_string())).with_base_url(self.url.clone())
+    }
+
+    pub fn received(&self) -> Vec<Received> {
+        self.received.lock().clone()
+    }
+}
+
+/// An OpenAI client for a server of its own.
+pub(crate) fn echo_client() -> LLMClient {
+    EchoServer::start().client()
+}
+
+fn answer(stream: TcpStream, log: &Mutex<Vec<Received>>) {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let path = line.split_whitespace().nth(1).unwrap_or_default().to_string();
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        let Some((name, value)) = line.trim_end().split_once(": ") else { break };
+        headers.insert(name.to_lowercase(), value.to_string());
+    }
+    let length = headers.get("content-length").and_then(|length| length.parse().ok()).unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    let key = headers.get("authorization").or(headers.get("x-goog-api-key")).cloned().unwrap_or_default();
+    let (status, response) = if key.contains("revoked") {
+        ("401 Unauthorized", json!({ "error": { "message": "Incorrect API key provided" } }))
+    } else if path.ends_with(":generateContent") {
+        ("200 OK", gemini(&body))
+    } else {
+        ("200 OK", openai(&body))
+    };
+    log.lock().push(Received { path, headers, body });
+
+    let response = response.to_string();
+    let mut stream = reader.into_inner();
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        response.len()
+    );
+    let _ = stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(response.as_bytes()));
+}
+
+/// The chat as one text: a lone user message as it is, and otherwise
+/// every message on lines of `role: content`.
+fn transcript(messages: Vec<(String, String)>) -> String {
+    match messages.as_slice() {
+        [(role, content)] if role == "user" => content.clone(),
+        _ => messages.iter().map(|(role, content)| format!("{}: {}\n", role, content)).collect(),
+    }
+}
+
+fn text(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
+
+fn openai(body: &Value) -> Value {
+    let messages = body["messages"].as_array().cloned().unwrap_or_default();
+    let chat = messages.iter().map(|message| (text(&message["role"]), text(&message["content"]))).collect();
+    json!({
+        "choices": [{
+            "message": { "role": "assistant", "content": format!("LLM response to: {}", transcript(chat)) },
+            "finish_reason": "stop",
+        }],
+        "usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 },
+    })
+}
+
+fn gemini(body: &Value) -> Value {
+    let parts = |content: &Value| content["parts"].as_array().into_iter().flatten().map(|part| text(&part["text"])).collect();
+    let mut chat: Vec<(String, String)> = Vec::new();
+    if !body["systemInstruction"].is_null() {
+        chat.push(("system".to_string(), parts(&body["systemInstruction"])));
+    }
+    for content in body["contents"].as_array().into_iter().flatten() {
+        chat.push((text(&content["role"]), parts(content)));
+    }
+    json!({
+        "candidates": [{
+            "content": { "role": "model", "parts": [{ "text": format!("LLM response to: {}", transcript(chat)) }] },
+            "finishReason": "STOP",
+        }],
+        "usageMetadata": { "promptTokenCount": 10, "candidatesTokenCount": 5, "totalTokenCount": 15 },
+    })
+}
@@ -0,0 +1,68 @@
+//! Token counting for prompts and chunking.
+//!
+//! Providers tokenize differently, so everything that budgets tokens goes
+//! through [`Tokenizer`]; [`ApproximateTokenizer`] is the built-in default.
+
+use std::ops::Range;
+
+pub trait Tokenizer: Send + Sync {
+    /// Byte ranges of the tokens in `text`, in order. Whitespace between
+    /// tokens belongs to no token.
+    fn tokens(&self, text: &str) -> Vec<Range<usize>>;
+
+    fn count(&self, text: &str) -> usize {
+        self.tokens(text).len()
+    }
+}
+
+/// Approximates BPE tokenizers: words split into pieces of at most
+/// [`Self::PIECE_CHARS`] characters, and each punctuation mark is a token.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApproximateTokenizer;
+
+impl ApproximateTokenizer {
+    pub const PIECE_CHARS: usize = 4;
+}
+
+impl Tokenizer for ApproximateTokenizer {
+    fn tokens(&self, text: &str) -> Vec<Range<usize>> {
+        let mut tokens = Vec::new();
+        let mut piece: Option<(usize, usize)> = None; // (start, chars)
+        for (index, ch) in text.char_indices() {
+            if ch.is_alphanumeric() {
+                match &mut piece {
+                    Some((_, chars)) if *chars < Self::PIECE_CHARS => *chars += 1,
+                    Some((start, _)) => {
+                        tokens.push(*start..index);
+                        piece = Some((index, 1));
+                    }
+                    None => piece = Some((index, 1)),
+                }
+                continue;
+            }
+            if let Some((start, _)) = piece.take() {
+                tokens.push(start..index);
+            }
+            if !ch.is_whitespace() {
+                tokens.push(index..index + ch.len_utf8());
+            }
+        }
+        if let Some((start, _)) = piece {
+            tokens.push(start..text.len());
+        }
+        tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_approximate_tokens() {
+        let text = "Patient has fever, ok.";
+        let tokens: Vec<&str> = ApproximateTokenizer.tokens(text).into_iter().map(|range| &text[range]).collect();
+        assert_eq!(tokens, ["Pati", "ent", "has", "feve", "r", ",", "ok", "."]);
+        assert_eq!(ApproximateTokenizer.count(""), 0);
+    }
+}
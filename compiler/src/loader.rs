@@ -0,0 +1,258 @@
+//! File modules: `import { triage, score } from "./triage.prism";` runs
+//! `triage.prism` once and binds the definitions it names.
+//!
+//! A module path is relative to the file that imports it, with `.prism`
+//! added when it has no extension; the name of a global module, such as
+//! `"fuzzy"`, imports from that module instead. Paths that do not start
+//! with `.` are also looked up in the configured module search paths when
+//! the importer's directory does not have them. A file module exports its
+//! top-level `let` and `fn` definitions, except those whose name starts
+//! with `_`.
+//!
+//! Before a program runs, the files it imports, directly or not, are read
+//! and parsed on parallel threads, or loaded from fresh
+//! [precompiled](crate::precompile) builds. They are then evaluated in
+//! dependency order: modules whose imports have all been evaluated run
+//! concurrently, each in its own global environment.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use crate::ast::Stmt;
+use crate::cfg::CfgFlags;
+use crate::diagnostics::Diagnostic;
+use crate::error::{PrismError, Result};
+
+/// A parsed and checked file module.
+#[derive(Debug)]
+pub struct FileModule {
+    pub path: PathBuf,
+    pub statements: Vec<Stmt>,
+    /// The files it imports.
+    pub imports: Vec<PathBuf>,
+}
+
+/// The file `spec` names when imported from `dir`, or `None` when it names
+/// a global module. Specs not starting with `.` are tried in `dir` and
+/// then in each of the `search` paths.
+pub fn module_path(spec: &str, dir: &Path, search: &[PathBuf], is_global: impl Fn(&str) -> bool) -> Option<PathBuf> {
+    if is_global(spec) {
+        return None;
+    }
+    let candidate = |dir: &Path| {
+        let mut path = dir.join(spec);
+        if path.extension().is_none() {
+            path.set_extension("prism");
+        }
+        path
+    };
+    let local = candidate(dir);
+    let path = if spec.starts_with('.') || local.exists() {
+        local
+    } else {
+        search.iter().map(|dir| candidate(dir)).find(|path| path.exists()).unwrap_or(local)
+    };
+    Some(std::fs::canonicalize(&path).unwrap_or(path))
+}
+
+/// The files `statements` import from `dir`, in order and without repeats.
+pub fn file_imports(statements: &[Stmt], dir: &Path, search: &[PathBuf], globals: &[String]) -> Vec<PathBuf> {
+    let mut specs = Vec::new();
+    statements.iter().for_each(|stmt| import_specs(stmt, &mut specs));
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for spec in specs {
+        if let Some(path) = module_path(spec, dir, search, |name| globals.iter().any(|global| global == name)) {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    paths
+}
+
+fn import_specs<'a>(stmt: &'a Stmt, specs: &mut Vec<&'a str>) {
+    match stmt {
+        Stmt::Import { module, .. } => specs.push(module),
+        Stmt::Block(statements) | Stmt::Module { body: statements, .. } => {
+            statements.iter().for_each(|stmt| import_specs(stmt, specs));
+        }
+        Stmt::If { then_branch, else_branch, .. } => {
+            import_specs(then_branch, specs);
+            if let Some(branch) = else_branch {
+                import_specs(branch, specs);
+            }
+        }
+        Stmt::UncertainIf { then_branch, medium_branch, low_branch, .. } => {
+            import_specs(then_branch, specs);
+            for branch in [medium_branch, low_branch].into_iter().flatten() {
+                import_specs(branch, specs);
+            }
+        }
+        Stmt::While { body, .. }
+        | Stmt::For { body, .. }
+        | Stmt::Function { body, .. }
+        | Stmt::Context { body, .. }
+        | Stmt::Export(_, body)
+        | Stmt::Cfg { body, .. } => import_specs(body, specs),
+        Stmt::Expression(_)
+        | Stmt::Let { .. }
+        | Stmt::Return(_)
+        | Stmt::Break
+        | Stmt::Continue
+        | Stmt::Yield(_)
+        | Stmt::ModuleAccess { .. } => {}
+    }
+}
+
+/// The names a file module exports.
+pub fn exported_names(statements: &[Stmt]) -> Vec<String> {
+    statements
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::Let { name, .. } | Stmt::Function { name, .. } if !name.starts_with('_') => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Reads, parses and checks the files `statements` import from `dir` that
+/// are not `loaded` yet, and the files those import in turn. Each round of
+/// newly found files is parsed in parallel.
+pub fn discover(
+    statements: &[Stmt],
+    dir: &Path,
+    search: &[PathBuf],
+    loaded: &HashSet<PathBuf>,
+    globals: &[String],
+    cfg: &CfgFlags,
+) -> Result<Vec<FileModule>> {
+    let mut found: HashMap<PathBuf, FileModule> = HashMap::new();
+    let mut frontier: Vec<PathBuf> = file_imports(statements, dir, search, globals)
+        .into_iter()
+        .filter(|path| !loaded.contains(path))
+        .collect();
+    // wasm builds cannot spawn threads
+    let threads = if cfg!(target_family = "wasm") {
+        1
+    } else {
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    };
+
+    while !frontier.is_empty() {
+        let per_thread = frontier.len().div_ceil(threads);
+        let parsed: Vec<Result<FileModule>> = if threads == 1 {
+            frontier.iter().map(|path| parse_module(path, search, globals, cfg)).collect()
+        } else {
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = frontier
+                    .chunks(per_thread)
+                    .map(|paths| {
+                        scope.spawn(move || paths.iter().map(|path| parse_module(path, search, globals, cfg)).collect::<Vec<_>>())
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .flat_map(|handle| handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                    .collect()
+            })
+        };
+
+        let mut next = Vec::new();
+        for module in parsed {
+            let module = module?;
+            for import in &module.imports {
+                let seen = loaded.contains(import) || found.contains_key(import) || frontier.contains(import);
+                if !seen && !next.contains(import) {
+                    next.push(import.clone());
+                }
+            }
+            found.insert(module.path.clone(), module);
+        }
+        frontier = next;
+    }
+    Ok(found.into_values().collect())
+}
+
+fn parse_module(path: &Path, search: &[PathBuf], globals: &[String], cfg: &CfgFlags) -> Result<FileModule> {
+    if !path.exists() && !crate::precompile::compiled_path(path).exists() {
+        return Err(PrismError::ModuleNotFound(path.display().to_string()));
+    }
+    let mut statements = crate::precompile::load(path)
+        .map_err(|err| err.map_message(|msg| format!("{}: {}", path.display(), msg)))?;
+    crate::cfg::apply(&mut statements, cfg);
+
+    let file = Some(path.display().to_string());
+    let in_file = |diagnostics: Vec<Diagnostic>| -> Option<String> {
+        let errors: Vec<String> = diagnostics
+            .into_iter()
+            .filter(Diagnostic::is_error)
+            .map(|d| d.in_file(file.clone()).to_string())
+            .collect();
+        (!errors.is_empty()).then(|| errors.join("\n"))
+    };
+    if let Some(errors) = in_file(crate::resolver::resolve(&mut statements, globals.iter().cloned())) {
+        return Err(PrismError::ResolveError(errors));
+    }
+    if let Some(errors) = in_file(crate::checker::check(&statements)) {
+        return Err(PrismError::TypeError(errors));
+    }
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let imports = file_imports(&statements, dir, search, globals);
+    Ok(FileModule { path: path.to_path_buf(), statements, imports })
+}
+
+/// Orders `modules` into rounds that only import modules of earlier rounds
+/// or ones `loaded` before; modules in the same round are independent.
+pub fn waves(modules: Vec<FileModule>, loaded: &HashSet<PathBuf>) -> Result<Vec<Vec<FileModule>>> {
+    let mut done: HashSet<PathBuf> = loaded.clone();
+    let mut remaining = modules;
+    let mut waves = Vec::new();
+    while !remaining.is_empty() {
+        let (mut ready, blocked): (Vec<FileModule>, Vec<FileModule>) = remaining
+            .into_iter()
+            .partition(|module| module.imports.iter().all(|import| done.contains(import)));
+        if ready.is_empty() {
+            let mut paths: Vec<String> = blocked.iter().map(|module| module.path.display().to_string()).collect();
+            paths.sort();
+            return Err(PrismError::RuntimeError(format!("Import cycle between {}", paths.join(", "))));
+        }
+        ready.sort_by(|a, b| a.path.cmp(&b.path));
+        done.extend(ready.iter().map(|module| module.path.clone()));
+        waves.push(ready);
+        remaining = blocked;
+    }
+    Ok(waves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(name: &str, imports: &[&str]) -> FileModule {
+        FileModule {
+            path: PathBuf::from(name),
+            statements: Vec::new(),
+            imports: imports.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    #[test]
+    fn test_waves_follow_the_dependency_graph() {
+        let modules = vec![
+            module("app", &["scores", "triage"]),
+            module("triage", &["units"]),
+            module("scores", &["units", "lib"]),
+            module("units", &[]),
+        ];
+        let loaded = HashSet::from([PathBuf::from("lib")]);
+        let names: Vec<Vec<String>> = waves(modules, &loaded)
+            .unwrap()
+            .iter()
+            .map(|wave| wave.iter().map(|module| module.path.display().to_string()).collect())
+            .collect();
+        assert_eq!(names, [vec!["units"], vec!["scores", "triage"], vec!["app"]]);
+
+        let err = waves(vec![module("a", &["b"]), module("b", &["a"]), module("c", &[])], &HashSet::new()).unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: Import cycle between a, b");
+    }
+}
@@ -3,11 +3,45 @@ use std::env;
 #[cfg(feature = "native")]
 use std::fs;
 #[cfg(feature = "native")]
+use std::sync::Arc;
+#[cfg(feature = "native")]
+use prism::capabilities::Capabilities;
+#[cfg(feature = "native")]
 use prism::interpreter::Interpreter;
 #[cfg(feature = "native")]
+use prism::llm::replay::{Recorder, Replay};
+#[cfg(feature = "native")]
+use prism::progress::TerminalProgress;
+#[cfg(feature = "native")]
 use prism::repl::Repl;
 #[cfg(feature = "native")]
-use prism::error::Result;
+use prism::run_report::RunReport;
+#[cfg(feature = "native")]
+use prism::snapshot::Snapshots;
+#[cfg(feature = "native")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "native")]
+use std::time::{Duration, Instant};
+#[cfg(feature = "native")]
+use prism::docs::generator;
+#[cfg(feature = "native")]
+use prism::precompile;
+#[cfg(feature = "native")]
+use prism::value::ValueKind;
+#[cfg(feature = "native")]
+use prism::capabilities::Capability;
+#[cfg(feature = "native")]
+use prism::pool::InterpreterPool;
+#[cfg(feature = "native")]
+use prism::server::{BearerToken, Server};
+#[cfg(feature = "native")]
+use prism::mcp::McpServer;
+#[cfg(feature = "native")]
+use prism::batch::{self, BatchFile};
+#[cfg(feature = "native")]
+use prism::error::{PrismError, Result};
+#[cfg(feature = "native")]
+use prism::diagnostics::Diagnostic;
 
 #[cfg(feature = "native")]
 #[tokio::main]
@@ -21,40 +55,527 @@ async fn main() -> Result<()> {
     }
 
     let args: Vec<String> = env::args().collect();
-    
-    match args.len() {
+
+    match args.as_slice() {
         // No arguments - start REPL
-        1 => {
+        [_] => {
             let mut repl = Repl::new()?;
             repl.run().await?;
         }
+        // `run <file> [--report <path>] [--record <path> | --replay <path>] [--dry-run] [--profile <name>]
+        //  [--model <model>] [--confidence <strategy>] [--allow <capabilities>] [-- args...]`
+        [_, command, options @ ..] if command == "run" => match RunOptions::parse(options) {
+            Some(options) => run(options).await?,
+            None => usage(),
+        },
+        // `test [--update-snapshots] [paths...]`
+        [_, command, options @ ..] if command == "test" => {
+            let update = options.iter().any(|option| option == "--update-snapshots");
+            let paths: Vec<&String> = options.iter().filter(|option| *option != "--update-snapshots").collect();
+            if paths.iter().any(|path| path.starts_with("--")) {
+                usage();
+            }
+            test(&paths, update).await?;
+        }
+        // `build <file> [-o <out>]`
+        [_, command, file] if command == "build" => build(file, None)?,
+        [_, command, file, flag, out] if command == "build" && flag == "-o" => build(file, Some(out))?,
+        // `doc [--html] [--out <path>] [paths...]`
+        [_, command, options @ ..] if command == "doc" => match DocOptions::parse(options) {
+            Some(options) => doc(options)?,
+            None => usage(),
+        },
+        // `batch <jobs.yaml> [--report <out.json>] [--junit <out.xml>]`
+        [_, command, options @ ..] if command == "batch" => match BatchOptions::parse(options) {
+            Some(options) => run_batch(options).await?,
+            None => usage(),
+        },
+        // `serve [--host <host>] [--port <port>] [--allow <capabilities>] [--token <token>] [--timeout <seconds>]`
+        [_, command, options @ ..] if command == "serve" => match ServeOptions::parse(options) {
+            Some(options) => serve(options).await?,
+            None => usage(),
+        },
+        // `mcp <file> [--allow <capabilities>] [--port <port>]`
+        [_, command, options @ ..] if command == "mcp" => match McpOptions::parse(options) {
+            Some(options) => mcp(options).await?,
+            None => usage(),
+        },
         // One argument - execute file
-        2 => {
-            let source = fs::read_to_string(&args[1]).unwrap_or_else(|err| {
-                eprintln!("Error reading file: {}", err);
-                std::process::exit(1);
-            });
+        [_, file] => run(RunOptions { file: file.clone(), ..RunOptions::default() }).await?,
+        _ => usage(),
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "native")]
+#[derive(Default)]
+struct RunOptions {
+    file: String,
+    report: Option<String>,
+    record: Option<String>,
+    replay: Option<String>,
+    /// A configured profile to start from.
+    profile: Option<String>,
+    /// Overrides the configured model.
+    model: Option<String>,
+    /// Overrides the configured confidence combination strategy.
+    confidence: Option<String>,
+    /// Overrides the configured capabilities.
+    capabilities: Option<Capabilities>,
+    /// Estimates LLM usage instead of calling providers.
+    dry_run: bool,
+    /// What follows `--`, for the script.
+    args: Vec<String>,
+}
 
-            let mut interpreter = Interpreter::new();
-            match interpreter.evaluate(source).await {
-                Ok(result) => println!("{:?}", result),
-                Err(err) => {
-                    eprintln!("Error: {}", err);
-                    std::process::exit(1);
+#[cfg(feature = "native")]
+impl RunOptions {
+    fn parse(args: &[String]) -> Option<Self> {
+        let mut options = RunOptions::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let slot = match arg.as_str() {
+                "--" => {
+                    options.args = args.cloned().collect();
+                    break;
+                }
+                "--report" => &mut options.report,
+                "--record" => &mut options.record,
+                "--replay" => &mut options.replay,
+                "--dry-run" => {
+                    options.dry_run = true;
+                    continue;
+                }
+                "--profile" => &mut options.profile,
+                "--model" => &mut options.model,
+                "--confidence" => &mut options.confidence,
+                "--allow" => {
+                    let granted = options.capabilities.unwrap_or_else(Capabilities::none);
+                    options.capabilities = Some(parse_capabilities(granted, args.next()?)?);
+                    continue;
                 }
+                flag if flag.starts_with("--") => return None,
+                file if options.file.is_empty() => {
+                    options.file = file.to_string();
+                    continue;
+                }
+                _ => return None,
+            };
+            *slot = Some(args.next()?.clone());
+        }
+        // A run either records its LLM exchanges or replays them, not both.
+        let exclusive = options.record.is_none() || options.replay.is_none();
+        (!options.file.is_empty() && exclusive).then_some(options)
+    }
+}
+
+/// Executes a file and its `main` function, optionally writing a
+/// [`RunReport`], recording or replaying its LLM exchanges, or only
+/// estimating their cost with `--dry-run`. Scripts get
+/// the configured capabilities, all by default. Exits with status 1 if it
+/// fails, or with the code `main` returns.
+#[cfg(feature = "native")]
+async fn run(options: RunOptions) -> Result<()> {
+    let program = match precompile::load(Path::new(&options.file)) {
+        Err(PrismError::IO(err)) => {
+            eprintln!("Error reading file: {}", err);
+            std::process::exit(1);
+        }
+        program => program,
+    };
+
+    let config = prism::config::global();
+    let mut interpreter = Interpreter::new()
+        .with_capabilities(options.capabilities.unwrap_or_else(|| config.granted()))
+        .with_progress(Arc::new(TerminalProgress))
+        .with_module_dir(Path::new(&options.file).parent().unwrap_or(Path::new(".")))
+        .with_args(options.args.clone())
+        .with_dry_run(options.dry_run);
+    if let Some(profile) = &options.profile {
+        interpreter.use_profile(profile)?;
+    }
+    if let Some(model) = &options.model {
+        let mut model_config = interpreter.model_config().clone();
+        model_config.model = model.clone();
+        interpreter = interpreter.with_model_config(model_config);
+    }
+    if let Some(strategy) = &options.confidence {
+        interpreter = interpreter.with_combine_strategy(strategy.parse()?);
+    }
+    if let Some(path) = &options.record {
+        interpreter = interpreter.with_recorder(Arc::new(Recorder::create(path)?));
+    }
+    if let Some(path) = &options.replay {
+        interpreter = interpreter.with_replay(Arc::new(Replay::open(path)?));
+    }
+    let started = Instant::now();
+    let mut result = match program {
+        Ok(program) => interpreter.evaluate_program(program).await,
+        Err(err) => Err(err),
+    };
+    let mut exit_code = None;
+    if result.is_ok() {
+        match interpreter.call_main().await {
+            Ok(Some(value)) => {
+                exit_code = Some(match value.kind {
+                    ValueKind::Number(code) => code as i32,
+                    ValueKind::Boolean(false) => 1,
+                    _ => 0,
+                });
+                result = Ok(value);
             }
+            Ok(None) => {}
+            Err(err) => result = Err(err),
         }
-        // Invalid usage
-        _ => {
-            eprintln!("Usage: prism [source_file]");
-            eprintln!("  Run without arguments to start REPL");
+    }
+
+    if options.dry_run {
+        let llm = RunReport::new(&interpreter, &result, started.elapsed()).llm;
+        eprintln!(
+            "Dry run: {} LLM calls, {} input and up to {} output tokens, estimated ${:.4}{}",
+            llm.calls.len(),
+            llm.input_tokens,
+            llm.output_tokens,
+            llm.cost,
+            match llm.unpriced_calls {
+                0 => String::new(),
+                unpriced => format!(" ({} calls to models without a known price)", unpriced),
+            }
+        );
+    }
+    if let Some(path) = &options.report {
+        let report = RunReport::new(&interpreter, &result, started.elapsed());
+        if let Err(err) = report.to_json().and_then(|json| Ok(fs::write(path, json)?)) {
+            eprintln!("Error writing report: {}", err);
+            std::process::exit(1);
+        }
+    }
+    match (result, exit_code) {
+        // A program with `main` prints what it means to
+        (Ok(_), Some(code)) => std::process::exit(code),
+        (Ok(result), None) => println!("{}", interpreter.secrets().redact(&format!("{:?}", result))),
+        (Err(err), _) => {
+            eprintln!("{}", Diagnostic::from_error(&err));
             std::process::exit(1);
         }
     }
+    Ok(())
+}
 
+/// Parses `file` and saves it precompiled, by default beside it as
+/// `.prismc`, for `run` and imports to load instead of the source.
+#[cfg(feature = "native")]
+fn build(file: &str, out: Option<&String>) -> Result<()> {
+    let source = fs::read_to_string(file)?;
+    let program = prism::parser::parse(&source).unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    });
+    let out = out.map_or_else(|| precompile::compiled_path(Path::new(file)), PathBuf::from);
+    fs::write(&out, precompile::encode(&program)?)?;
+    println!("Built {}", out.display());
     Ok(())
 }
 
+/// Runs every `*_test.prism` file under `paths` (default: the current
+/// directory) with snapshots in `__snapshots__/<name>/` beside it, and exits
+/// with status 1 if any fails.
+#[cfg(feature = "native")]
+async fn test(paths: &[&String], update: bool) -> Result<()> {
+    let mut files = Vec::new();
+    if paths.is_empty() {
+        find_tests(Path::new("."), &mut files)?;
+    }
+    for path in paths {
+        find_tests(Path::new(path), &mut files)?;
+    }
+    files.sort();
+
+    let mut failed = 0;
+    for file in &files {
+        let stem = file.file_stem().unwrap_or_default();
+        let module_dir = file.parent().unwrap_or(Path::new("."));
+        let dir = module_dir.join("__snapshots__").join(stem);
+        let mut interpreter = Interpreter::new()
+            .with_capabilities(prism::config::global().granted())
+            .with_module_dir(module_dir)
+            .with_snapshots(Snapshots::new(dir).updating(update));
+        // Output is shown only for failing tests
+        let output = interpreter.capture_output();
+        match interpreter.evaluate(fs::read_to_string(file)?).await {
+            Ok(_) => println!("ok   {}", file.display()),
+            Err(err) => {
+                failed += 1;
+                println!("FAIL {}\n{}{}", file.display(), output.contents(), err);
+            }
+        }
+    }
+    println!("{} passed, {} failed", files.len() - failed, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "native")]
+fn find_tests(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    find_files(path, &|name| name.ends_with("_test.prism"), files)
+}
+
+/// Files under `path` whose name `matches`, skipping hidden entries.
+#[cfg(feature = "native")]
+fn find_files(path: &Path, matches: &dyn Fn(&str) -> bool, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            let hidden = path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            if !hidden {
+                find_files(&path, matches, files)?;
+            }
+        }
+    } else if matches(&path.to_string_lossy()) {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "native")]
+#[derive(Default)]
+struct DocOptions {
+    html: bool,
+    out: Option<String>,
+    paths: Vec<String>,
+}
+
+#[cfg(feature = "native")]
+impl DocOptions {
+    fn parse(args: &[String]) -> Option<Self> {
+        let mut options = DocOptions::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--html" => options.html = true,
+                "--out" => options.out = Some(args.next()?.clone()),
+                flag if flag.starts_with("--") => return None,
+                path => options.paths.push(path.to_string()),
+            }
+        }
+        Some(options)
+    }
+}
+
+/// Documents every `.prism` file under the given paths (default: the
+/// current directory) except tests, as markdown or HTML, to stdout or a file.
+/// Modules are named by their path relative to the directory given.
+#[cfg(feature = "native")]
+fn doc(options: DocOptions) -> Result<()> {
+    let roots = if options.paths.is_empty() { vec![".".to_string()] } else { options.paths };
+    let mut modules = Vec::new();
+    for root in &roots {
+        let root = Path::new(root);
+        let mut files = Vec::new();
+        find_files(root, &|name| name.ends_with(".prism") && !name.ends_with("_test.prism"), &mut files)?;
+        files.sort();
+        for file in files {
+            let relative = file.strip_prefix(root).ok().filter(|path| !path.as_os_str().is_empty());
+            let name = relative.unwrap_or(&file).with_extension("").to_string_lossy().replace('\\', "/");
+            let module = generator::document(&name, &fs::read_to_string(&file)?).unwrap_or_else(|err| {
+                eprintln!("Error documenting {}: {}", file.display(), err);
+                std::process::exit(1);
+            });
+            modules.push(module);
+        }
+    }
+
+    let output = if options.html { generator::html(&modules) } else { generator::markdown(&modules) };
+    match &options.out {
+        Some(path) => fs::write(path, output)?,
+        None => print!("{}", output),
+    }
+    Ok(())
+}
+
+#[cfg(feature = "native")]
+#[derive(Default)]
+struct BatchOptions {
+    file: String,
+    report: Option<String>,
+    junit: Option<String>,
+}
+
+#[cfg(feature = "native")]
+impl BatchOptions {
+    fn parse(args: &[String]) -> Option<Self> {
+        let (file, args) = args.split_first()?;
+        let mut options = BatchOptions { file: file.clone(), ..BatchOptions::default() };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let slot = match arg.as_str() {
+                "--report" => &mut options.report,
+                "--junit" => &mut options.junit,
+                _ => return None,
+            };
+            *slot = Some(args.next()?.clone());
+        }
+        Some(options)
+    }
+}
+
+/// Runs the jobs of a batch file, writing the combined reports, and exits
+/// with status 1 if any job failed.
+#[cfg(feature = "native")]
+async fn run_batch(options: BatchOptions) -> Result<()> {
+    let path = Path::new(&options.file);
+    let jobs = BatchFile::load(path).unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    });
+    let report = batch::run(&jobs, path.parent().unwrap_or(Path::new("."))).await;
+    for job in &report.jobs {
+        match &job.failure {
+            None => println!("ok   {}", job.name),
+            Some(failure) => println!("FAIL {}\n{}", job.name, failure),
+        }
+    }
+    println!("{} passed, {} failed", report.passed, report.failed);
+    if let Some(out) = &options.report {
+        fs::write(out, report.to_json()?)?;
+    }
+    if let Some(out) = &options.junit {
+        fs::write(out, report.to_junit())?;
+    }
+    if report.failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "native")]
+struct ServeOptions {
+    host: String,
+    port: u16,
+    capabilities: Capabilities,
+    token: Option<String>,
+    /// How long evaluations and calls may run.
+    timeout: Duration,
+}
+
+#[cfg(feature = "native")]
+impl ServeOptions {
+    fn parse(args: &[String]) -> Option<Self> {
+        let mut options = ServeOptions {
+            host: "127.0.0.1".to_string(),
+            port: 9000,
+            capabilities: Capabilities::none(),
+            token: env::var("PRISM_SERVE_TOKEN").ok(),
+            timeout: prism::server::DEFAULT_TIMEOUT,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let value = args.next()?;
+            match arg.as_str() {
+                "--host" => options.host = value.clone(),
+                "--port" => options.port = value.parse().ok()?,
+                "--token" => options.token = Some(value.clone()),
+                "--allow" => options.capabilities = parse_capabilities(options.capabilities, value)?,
+                "--timeout" => options.timeout = Duration::try_from_secs_f64(value.parse().ok()?).ok()?,
+                _ => return None,
+            }
+        }
+        Some(options)
+    }
+}
+
+/// Serves evaluations over HTTP until killed. Sessions get only the
+/// capabilities allowed on the command line, and requests need the token
+/// when one is set.
+#[cfg(feature = "native")]
+async fn serve(options: ServeOptions) -> Result<()> {
+    let pool = InterpreterPool::new()?.with_capabilities(options.capabilities);
+    let mut server = Server::new(pool).with_timeout(options.timeout);
+    if let Some(token) = options.token {
+        server = server.with_authenticator(BearerToken(token));
+    }
+    let listener = tokio::net::TcpListener::bind((options.host.as_str(), options.port)).await?;
+    eprintln!("Serving on http://{}", listener.local_addr()?);
+    Arc::new(server).serve(listener).await
+}
+
+#[cfg(feature = "native")]
+struct McpOptions {
+    file: String,
+    capabilities: Capabilities,
+    port: Option<u16>,
+}
+
+#[cfg(feature = "native")]
+impl McpOptions {
+    fn parse(args: &[String]) -> Option<Self> {
+        let (file, args) = args.split_first()?;
+        let mut options = McpOptions { file: file.clone(), capabilities: Capabilities::none(), port: None };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let value = args.next()?;
+            match arg.as_str() {
+                "--port" => options.port = Some(value.parse().ok()?),
+                "--allow" => options.capabilities = parse_capabilities(options.capabilities, value)?,
+                _ => return None,
+            }
+        }
+        Some(options)
+    }
+}
+
+/// Serves the functions of a file as MCP tools over stdio, or over
+/// websockets on a port.
+#[cfg(feature = "native")]
+async fn mcp(options: McpOptions) -> Result<()> {
+    let interpreter = Interpreter::new().with_capabilities(options.capabilities);
+    let server = McpServer::load(Path::new(&options.file), interpreter).await.unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    });
+    match options.port {
+        None => server.serve_stdio(tokio::io::BufReader::new(tokio::io::stdin()), tokio::io::stdout()).await,
+        #[cfg(feature = "websocket")]
+        Some(port) => {
+            let listener = tokio::net::TcpListener::bind(("127.0.0.1", port)).await?;
+            eprintln!("Serving MCP on ws://{}", listener.local_addr()?);
+            Arc::new(server).serve_websocket(listener).await
+        }
+        #[cfg(not(feature = "websocket"))]
+        Some(_) => {
+            eprintln!("Error: serving MCP over websockets needs the `websocket` feature");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Grants the comma-separated capability names in `names`.
+#[cfg(feature = "native")]
+fn parse_capabilities(mut capabilities: Capabilities, names: &str) -> Option<Capabilities> {
+    for name in names.split(',') {
+        capabilities = capabilities.grant(name.trim().parse::<Capability>().ok()?);
+    }
+    Some(capabilities)
+}
+
+#[cfg(feature = "native")]
+fn usage() {
+    eprintln!("Usage: prism [source_file]");
+    eprintln!("       prism run <source_file> [--report <out.json>] [--record <run.jsonl> | --replay <run.jsonl>]");
+    eprintln!("                             [--dry-run] [--profile <name>] [--model <model>] [--confidence <product|min|mean>] [--allow <env,fs,...>] [-- args...]");
+    eprintln!("       prism build <source_file> [-o <out.prismc>]");
+    eprintln!("       prism test [--update-snapshots] [paths...]");
+    eprintln!("       prism batch <jobs.yaml> [--report <out.json>] [--junit <out.xml>]");
+    eprintln!("       prism doc [--html] [--out <path>] [paths...]");
+    eprintln!("       prism serve [--host <host>] [--port <port>] [--allow <env,fs,...>] [--token <token>] [--timeout <seconds>]");
+    eprintln!("       prism mcp <source_file> [--allow <env,fs,...>] [--port <port>]");
+    eprintln!("  Run without arguments to start REPL");
+    std::process::exit(1);
+}
+
 #[cfg(not(feature = "native"))]
 fn main() {
     panic!("Binary is only available with native feature enabled");
@@ -0,0 +1,324 @@
+//! `prism mcp`: serves the exported functions of a Prism file as Model
+//! Context Protocol tools, so IDE agents and LLM clients can call them.
+//!
+//! Each top-level function whose name does not start with `_` is a tool.
+//! Its `///` comment is the description, and its parameters, named in the
+//! order they are declared, make up the input schema. Type annotations
+//! become JSON schema types; `T?` parameters are optional and unannotated
+//! ones accept anything:
+//!
+//! ```prism
+//! /// Scores how urgent a support case is.
+//! fn triage(summary: string, age_days: number?) -> number { ... }
+//! ```
+//!
+//! Messages are JSON-RPC 2.0, one per line over stdio or one per message
+//! over a websocket (with the `websocket` feature). What a tool prints is
+//! returned with its result rather than written to stdout.
+
+use std::path::Path;
+use serde_json::{json, Value as Json};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+use crate::ast::{Stmt, Type};
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::output::CapturedOutput;
+use crate::server::{response, RpcError};
+use crate::value::Value;
+
+/// The protocol revision answered when the client does not ask for one.
+pub const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// A function offered as a tool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tool {
+    pub name: String,
+    pub description: String,
+    pub params: Vec<(String, Option<Type>)>,
+}
+
+impl Tool {
+    /// The JSON schema of the tool's arguments object.
+    pub fn input_schema(&self) -> Json {
+        let properties: serde_json::Map<String, Json> =
+            self.params.iter().map(|(name, ty)| (name.clone(), ty.as_ref().map_or(json!({}), schema))).collect();
+        let required: Vec<&str> = self
+            .params
+            .iter()
+            .filter(|(_, ty)| !matches!(ty, Some(Type::Union(members)) if members.contains(&Type::Nil)))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        json!({ "type": "object", "properties": properties, "required": required })
+    }
+}
+
+fn schema(ty: &Type) -> Json {
+    match ty {
+        Type::Nil => json!({ "type": "null" }),
+        Type::Boolean => json!({ "type": "boolean" }),
+        Type::Number => json!({ "type": "number" }),
+        Type::String => json!({ "type": "string" }),
+        Type::List => json!({ "type": "array" }),
+        Type::Map => json!({ "type": "object" }),
+        Type::Union(members) => json!({ "anyOf": members.iter().map(schema).collect::<Vec<_>>() }),
+        // Functions cannot be passed as JSON and named types are not checked
+        Type::Any | Type::Function | Type::Named(_) => json!({}),
+    }
+}
+
+/// The tools `program`, parsed from `source`, offers.
+pub fn tools(program: &[Stmt], source: &str) -> Result<Vec<Tool>> {
+    let docs = crate::docs::generator::document("tools", source)?;
+    Ok(program
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::Function { name, params, param_types, .. } if !name.starts_with('_') => Some(Tool {
+                name: name.clone(),
+                description: docs
+                    .functions
+                    .iter()
+                    .find(|function| function.name == *name)
+                    .map(|function| function.doc.clone())
+                    .unwrap_or_default(),
+                params: params.iter().cloned().zip(param_types.iter().cloned()).collect(),
+            }),
+            _ => None,
+        })
+        .collect())
+}
+
+/// A loaded file and the interpreter its tools run in.
+pub struct McpServer {
+    interpreter: Mutex<Interpreter>,
+    output: CapturedOutput,
+    tools: Vec<Tool>,
+}
+
+impl McpServer {
+    /// Runs `path` in `interpreter` to define its functions.
+    pub async fn load(path: &Path, interpreter: Interpreter) -> Result<Self> {
+        let source = std::fs::read_to_string(path)?;
+        let program = crate::parser::parse(&source)?;
+        let tools = tools(&program, &source)?;
+        let mut interpreter = interpreter.with_module_dir(path.parent().unwrap_or(Path::new(".")));
+        let output = interpreter.capture_output();
+        interpreter.evaluate_program(program).await?;
+        output.take();
+        Ok(Self { interpreter: Mutex::new(interpreter), output, tools })
+    }
+
+    pub fn tools(&self) -> &[Tool] {
+        &self.tools
+    }
+
+    /// Answers one JSON-RPC message; notifications get no answer.
+    pub async fn handle(&self, message: &str) -> Option<Json> {
+        let message: Json = match serde_json::from_str(message) {
+            Ok(message) => message,
+            Err(err) => return Some(response(Json::Null, Err(RpcError::new(RpcError::PARSE_ERROR, err.to_string())))),
+        };
+        let id = message.get("id").cloned()?;
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        let result = match message.get("method").and_then(Json::as_str) {
+            Some("initialize") => Ok(json!({
+                "protocolVersion": params.get("protocolVersion").and_then(Json::as_str).unwrap_or(PROTOCOL_VERSION),
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "prism", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            Some("ping") => Ok(json!({})),
+            Some("tools/list") => {
+                let tools: Vec<Json> = self
+                    .tools
+                    .iter()
+                    .map(|tool| json!({ "name": tool.name, "description": tool.description, "inputSchema": tool.input_schema() }))
+                    .collect();
+                Ok(json!({ "tools": tools }))
+            }
+            Some("tools/call") => self.call(&params).await,
+            Some(method) => Err(RpcError::new(RpcError::METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+            None => Err(RpcError::new(RpcError::INVALID_REQUEST, "Expected a method")),
+        };
+        Some(response(id, result))
+    }
+
+    async fn call(&self, params: &Json) -> std::result::Result<Json, RpcError> {
+        let name = params.get("name").and_then(Json::as_str).unwrap_or_default();
+        let tool = self
+            .tools
+            .iter()
+            .find(|tool| tool.name == name)
+            .ok_or_else(|| RpcError::new(RpcError::INVALID_PARAMS, format!("Unknown tool '{}'", name)))?;
+        let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+        let required = tool.input_schema()["required"].clone();
+        let mut args = Vec::new();
+        for (param, _) in &tool.params {
+            match arguments.get(param) {
+                Some(arg) => args.push(Value::from_json(arg)),
+                None if required.as_array().is_some_and(|names| names.contains(&json!(param))) => {
+                    return Err(RpcError::new(RpcError::INVALID_PARAMS, format!("Missing argument '{}'", param)));
+                }
+                None => args.push(Value::from_json(&Json::Null)),
+            }
+        }
+
+        let mut interpreter = self.interpreter.lock().await;
+        let callee = interpreter
+            .global_values()
+            .into_iter()
+            .find(|(global, _)| global == name)
+            .map(|(_, value)| value)
+            .ok_or_else(|| RpcError::from(PrismError::UndefinedVariable(name.to_string())))?;
+        let result = interpreter.call(callee, args).await;
+        let mut content = Vec::new();
+        let printed = self.output.take();
+        if !printed.is_empty() {
+            content.push(json!({ "type": "text", "text": printed }));
+        }
+        // A failing tool is a result the model can read, not a protocol error
+        Ok(match result {
+            Ok(value) => {
+                let json = value.to_json().unwrap_or_else(|_| Json::String(value.to_string()));
+                let text = json.as_str().map_or_else(|| json.to_string(), str::to_string);
+                content.insert(0, json!({ "type": "text", "text": text }));
+                let mut structured = json!({ "value": json, "confidence": value.confidence });
+                if let Ok(Some(metadata)) = value.metadata_json() {
+                    structured["metadata"] = metadata;
+                }
+                json!({ "content": content, "structuredContent": structured, "isError": false })
+            }
+            Err(err) => {
+                content.insert(0, json!({ "type": "text", "text": err.to_string() }));
+                json!({ "content": content, "isError": true })
+            }
+        })
+    }
+
+    /// Serves newline-delimited messages from `input` until it ends.
+    pub async fn serve_stdio(&self, input: impl AsyncBufRead + Unpin, mut output: impl AsyncWrite + Unpin) -> Result<()> {
+        let mut lines = input.lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(answer) = self.handle(&line).await {
+                output.write_all(format!("{}\n", answer).as_bytes()).await?;
+                output.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Serves websocket clients from `listener` until it fails.
+    #[cfg(feature = "websocket")]
+    pub async fn serve_websocket(self: std::sync::Arc<Self>, listener: tokio::net::TcpListener) -> Result<()> {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                let Ok(mut socket) = tokio_tungstenite::accept_async(stream).await else { return };
+                while let Some(Ok(message)) = socket.next().await {
+                    let Message::Text(text) = message else {
+                        if message.is_close() {
+                            break;
+                        }
+                        continue;
+                    };
+                    if let Some(answer) = server.handle(&text).await {
+                        if socket.send(Message::Text(answer.to_string())).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"
+/// Scores how urgent a case is.
+fn triage(summary: string, age_days: number?) -> number {
+    print(summary);
+    if (age_days == nil) { return 1; }
+    return age_days * 2;
+}
+
+fn _helper(x) { return x; }
+
+fn echo(x) { return x ~> 0.7; }
+"#;
+
+    async fn server(dir: &Path) -> McpServer {
+        let path = dir.join("tools.prism");
+        std::fs::write(&path, SOURCE).unwrap();
+        McpServer::load(&path, Interpreter::new()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_exported_functions_are_tools() {
+        let dir = std::env::temp_dir().join(format!("prism-mcp-list-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let server = server(&dir).await;
+        let answer = server.handle(r#"{"jsonrpc": "2.0", "id": 1, "method": "tools/list"}"#).await.unwrap();
+        assert_eq!(
+            answer["result"]["tools"],
+            json!([
+                {
+                    "name": "triage",
+                    "description": "Scores how urgent a case is.",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": { "summary": { "type": "string" }, "age_days": { "anyOf": [{ "type": "number" }, { "type": "null" }] } },
+                        "required": ["summary"],
+                    },
+                },
+                {
+                    "name": "echo",
+                    "description": "",
+                    "inputSchema": { "type": "object", "properties": { "x": {} }, "required": ["x"] },
+                },
+            ])
+        );
+        assert!(server.handle(r#"{"jsonrpc": "2.0", "method": "notifications/initialized"}"#).await.is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tools_are_called_over_stdio() {
+        let dir = std::env::temp_dir().join(format!("prism-mcp-call-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let server = server(&dir).await;
+        let input = [
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"protocolVersion": "2025-03-26"}}"#,
+            r#"{"jsonrpc": "2.0", "method": "notifications/initialized"}"#,
+            r#"{"jsonrpc": "2.0", "id": 2, "method": "tools/call", "params": {"name": "triage", "arguments": {"summary": "down", "age_days": 3}}}"#,
+            r#"{"jsonrpc": "2.0", "id": 3, "method": "tools/call", "params": {"name": "echo", "arguments": {}}}"#,
+            r#"{"jsonrpc": "2.0", "id": 4, "method": "tools/call", "params": {"name": "_helper", "arguments": {"x": 1}}}"#,
+        ]
+        .join("\n");
+        let mut output = Vec::new();
+        server.serve_stdio(input.as_bytes(), &mut output).await.unwrap();
+        let answers: Vec<Json> = String::from_utf8(output).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+        assert_eq!(answers.len(), 4);
+        assert_eq!(answers[0]["result"]["protocolVersion"], "2025-03-26");
+        assert_eq!(
+            answers[1]["result"],
+            json!({
+                "content": [{ "type": "text", "text": "6" }, { "type": "text", "text": "down\n" }],
+                "structuredContent": { "value": 6, "confidence": 1.0 },
+                "isError": false,
+            })
+        );
+        assert_eq!(answers[2]["error"]["message"], "Missing argument 'x'");
+        assert_eq!(answers[3]["error"]["message"], "Unknown tool '_helper'");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use parking_lot::Mutex;
+use serde::Serialize;
+use crate::error::PrismError;
+
+/// Runtime counters of an interpreter and its forks.
+///
+/// Every count is also reported through the [`metrics`] facade, so a host
+/// that installs a recorder (e.g. a Prometheus exporter) sees them without
+/// further wiring. Statement and call counts are hot, so they reach the
+/// facade in one batch at the end of each evaluation; the rest as they
+/// happen. [`Interpreter::metrics_snapshot`](crate::Interpreter::metrics_snapshot)
+/// reads the totals directly.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    statements: AtomicU64,
+    calls: AtomicU64,
+    /// Statement and call totals already handed to the facade.
+    published: Mutex<(u64, u64)>,
+    llm: Mutex<Latency>,
+    queue_waits: Mutex<Latency>,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    errors: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+/// Totals since the interpreter was created.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MetricsSnapshot {
+    pub statements: u64,
+    pub calls: u64,
+    pub llm_requests: Latency,
+    /// Time LLM requests spent in the request queue before starting.
+    pub llm_queue_waits: Latency,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Failed evaluations by error kind, e.g. `runtime_error`.
+    pub errors: BTreeMap<String, u64>,
+}
+
+impl MetricsSnapshot {
+    /// Share of cache lookups that hit; 0.0 before the first lookup.
+    pub fn cache_hit_rate(&self) -> f64 {
+        match self.cache_hits + self.cache_misses {
+            0 => 0.0,
+            lookups => self.cache_hits as f64 / lookups as f64,
+        }
+    }
+}
+
+/// Count and duration summary of timed operations.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Latency {
+    pub count: u64,
+    pub total_seconds: f64,
+    pub max_seconds: f64,
+}
+
+impl Latency {
+    pub fn mean_seconds(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.total_seconds / self.count as f64 }
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record_statement(&self) {
+        self.counters.statements.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_call(&self) {
+        self.counters.calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records one LLM request; natives that call a provider time it here.
+    pub fn record_llm_request(&self, model: &str, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        {
+            let mut llm = self.counters.llm.lock();
+            llm.count += 1;
+            llm.total_seconds += seconds;
+            llm.max_seconds = llm.max_seconds.max(seconds);
+        }
+        metrics::histogram!("prism_llm_request_duration_seconds", "model" => model.to_string()).record(seconds);
+    }
+
+    /// Records how long an LLM request waited for its provider's limits.
+    pub fn record_queue_wait(&self, provider: &str, waited: Duration) {
+        let seconds = waited.as_secs_f64();
+        {
+            let mut waits = self.counters.queue_waits.lock();
+            waits.count += 1;
+            waits.total_seconds += seconds;
+            waits.max_seconds = waits.max_seconds.max(seconds);
+        }
+        metrics::histogram!("prism_llm_queue_wait_seconds", "provider" => provider.to_string()).record(seconds);
+    }
+
+    pub fn record_cache_lookup(&self, hit: bool) {
+        if hit {
+            self.counters.cache_hits.fetch_add(1, Ordering::Relaxed);
+            metrics::counter!("prism_cache_hits_total").increment(1);
+        } else {
+            self.counters.cache_misses.fetch_add(1, Ordering::Relaxed);
+            metrics::counter!("prism_cache_misses_total").increment(1);
+        }
+    }
+
+    pub(crate) fn record_error(&self, err: &PrismError) {
+        *self.counters.errors.lock().entry(err.kind()).or_default() += 1;
+        metrics::counter!("prism_errors_total", "kind" => err.kind()).increment(1);
+    }
+
+    /// Hands the statements and calls counted since the last call to the facade.
+    pub(crate) fn publish(&self) {
+        let statements = self.counters.statements.load(Ordering::Relaxed);
+        let calls = self.counters.calls.load(Ordering::Relaxed);
+        let mut published = self.counters.published.lock();
+        metrics::counter!("prism_statements_total").increment(statements - published.0);
+        metrics::counter!("prism_calls_total").increment(calls - published.1);
+        *published = (statements, calls);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            statements: self.counters.statements.load(Ordering::Relaxed),
+            calls: self.counters.calls.load(Ordering::Relaxed),
+            llm_requests: self.counters.llm.lock().clone(),
+            llm_queue_waits: self.counters.queue_waits.lock().clone(),
+            cache_hits: self.counters.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.counters.cache_misses.load(Ordering::Relaxed),
+            errors: self.counters.errors.lock().iter().map(|(kind, n)| (kind.to_string(), *n)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clones_share_counters() {
+        let metrics = Metrics::new();
+        let fork = metrics.clone();
+        fork.record_call();
+        metrics.record_cache_lookup(true);
+        fork.record_cache_lookup(false);
+        fork.record_cache_lookup(true);
+        metrics.record_llm_request("gpt-4", Duration::from_millis(300));
+        metrics.record_llm_request("gpt-4", Duration::from_millis(100));
+        metrics.record_error(&PrismError::RuntimeError("boom".to_string()));
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.calls, 1);
+        assert!((snapshot.cache_hit_rate() - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(snapshot.llm_requests.count, 2);
+        assert!((snapshot.llm_requests.mean_seconds() - 0.2).abs() < 1e-9);
+        assert_eq!(snapshot.errors["runtime_error"], 1);
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 use parking_lot::RwLock;
+use crate::docs::FunctionDoc;
 use crate::error::{PrismError, Result};
 use crate::value::Value;
 
@@ -8,6 +9,7 @@ use crate::value::Value;
 pub struct Module {
     pub name: String,
     exports: HashMap<String, Value>,
+    docs: HashMap<String, FunctionDoc>,
 }
 
 impl Module {
@@ -15,6 +17,7 @@ impl Module {
         Self {
             name,
             exports: HashMap::new(),
+            docs: HashMap::new(),
         }
     }
 
@@ -23,6 +26,20 @@ impl Module {
         Ok(())
     }
 
+    /// Exports a function along with its documentation.
+    pub fn export_documented(&mut self, name: &str, value: Value, doc: FunctionDoc) -> Result<()> {
+        self.docs.insert(name.to_string(), doc);
+        self.export(name.to_string(), value)
+    }
+
+    pub fn doc(&self, name: &str) -> Option<&FunctionDoc> {
+        self.docs.get(name)
+    }
+
+    pub fn exports(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.exports.iter()
+    }
+
     pub fn get_export(&self, name: &str) -> Result<Value> {
         self.exports
             .get(name)
@@ -36,6 +53,12 @@ pub struct ModuleRegistry {
     modules: HashMap<String, Arc<RwLock<Module>>>,
 }
 
+impl Default for ModuleRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ModuleRegistry {
     pub fn new() -> Self {
         Self {
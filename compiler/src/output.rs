@@ -0,0 +1,52 @@
+use std::io::Write;
+use std::sync::Arc;
+use parking_lot::Mutex;
+
+/// Where `print` and the other output builtins write.
+///
+/// Native builds default to [`Stdout`]; browser builds and test harnesses
+/// install their own sink to capture what a script prints.
+pub trait OutputSink: Send + Sync {
+    fn write(&self, text: &str);
+}
+
+/// Writes to the process's standard output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stdout;
+
+impl OutputSink for Stdout {
+    fn write(&self, text: &str) {
+        let mut stdout = std::io::stdout().lock();
+        // Output is best effort; a closed pipe must not fail the script
+        let _ = stdout.write_all(text.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+/// Collects output in memory. Clones share the buffer, so keep one to read
+/// what the interpreter wrote.
+#[derive(Debug, Clone, Default)]
+pub struct CapturedOutput {
+    buffer: Arc<Mutex<String>>,
+}
+
+impl CapturedOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contents(&self) -> String {
+        self.buffer.lock().clone()
+    }
+
+    /// Returns the output so far and empties the buffer.
+    pub fn take(&self) -> String {
+        std::mem::take(&mut *self.buffer.lock())
+    }
+}
+
+impl OutputSink for CapturedOutput {
+    fn write(&self, text: &str) {
+        self.buffer.lock().push_str(text);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::ast::{Expr, Stmt};
+use crate::ast::{CfgPredicate, Expr, MatchArm, Pattern, Stmt, Type, DEFAULT_HIGH_CONFIDENCE, DEFAULT_MEDIUM_CONFIDENCE};
 use crate::error::{PrismError, Result};
 use crate::token::{Token, TokenKind};
 use crate::lexer::Lexer;
@@ -26,7 +26,9 @@ impl Parser {
     }
 
     fn declaration(&mut self) -> Result<Stmt> {
-        if self.match_token(&[TokenKind::Import]) {
+        if self.match_token(&[TokenKind::At]) {
+            self.cfg_declaration()
+        } else if self.match_token(&[TokenKind::Import]) {
             self.import_declaration()
         } else if self.match_token(&[TokenKind::Let]) {
             self.let_declaration()
@@ -79,8 +81,64 @@ impl Parser {
         })
     }
 
+    /// `@cfg(predicate)` followed by the statement it applies to.
+    fn cfg_declaration(&mut self) -> Result<Stmt> {
+        if !self.check_identifier("cfg") {
+            return Err(PrismError::ParseError(format!(
+                "Expected 'cfg' after '@' at line {}.",
+                self.peek().line
+            )));
+        }
+        self.advance();
+        self.consume(TokenKind::LeftParen, "Expected '(' after '@cfg'.")?;
+        let predicate = self.cfg_predicate()?;
+        self.consume(TokenKind::RightParen, "Expected ')' after cfg predicate.")?;
+        let body = Box::new(self.declaration()?);
+        Ok(Stmt::Cfg { predicate, body })
+    }
+
+    fn cfg_predicate(&mut self) -> Result<CfgPredicate> {
+        if let TokenKind::String(flag) = &self.peek().kind {
+            let flag = flag.clone();
+            self.advance();
+            return Ok(CfgPredicate::Flag(flag));
+        }
+        let name = self.consume_identifier("Expected a flag, `key = \"value\"`, not(..), all(..) or any(..).")?;
+        if self.match_token(&[TokenKind::Equal]) {
+            let value = self.consume_string("Expected a string after '='.")?;
+            return Ok(CfgPredicate::Value(name, value));
+        }
+        self.consume(TokenKind::LeftParen, "Expected '=' or '(' in cfg predicate.")?;
+        let mut items = Vec::new();
+        if !self.check(&TokenKind::RightParen) {
+            loop {
+                items.push(self.cfg_predicate()?);
+                if !self.match_token(&[TokenKind::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenKind::RightParen, "Expected ')' after cfg predicates.")?;
+        match name.as_str() {
+            "not" if items.len() == 1 => Ok(CfgPredicate::Not(Box::new(items.remove(0)))),
+            "all" => Ok(CfgPredicate::All(items)),
+            "any" => Ok(CfgPredicate::Any(items)),
+            _ => Err(PrismError::ParseError(format!(
+                "Unknown cfg predicate '{}' at line {}; expected not(x), all(..) or any(..).",
+                name,
+                self.previous().line
+            ))),
+        }
+    }
+
     fn let_declaration(&mut self) -> Result<Stmt> {
         let name = self.consume_identifier("Expected variable name.")?;
+
+        let type_annotation = if self.match_token(&[TokenKind::Colon]) {
+            Some(self.type_annotation()?)
+        } else {
+            None
+        };
         
         let initializer = if self.match_token(&[TokenKind::Equal]) {
             Some(Box::new(self.expression()?))
@@ -89,7 +147,29 @@ impl Parser {
         };
 
         self.consume(TokenKind::Semicolon, "Expected ';' after variable declaration.")?;
-        Ok(Stmt::Let(name, initializer))
+        Ok(Stmt::Let { name, type_annotation, initializer })
+    }
+
+    fn type_annotation(&mut self) -> Result<Type> {
+        let mut members = vec![self.type_atom()?];
+        while self.match_token(&[TokenKind::Pipe]) {
+            members.push(self.type_atom()?);
+        }
+        Ok(Type::union(members))
+    }
+
+    fn type_atom(&mut self) -> Result<Type> {
+        let ty = if self.match_token(&[TokenKind::Nil]) {
+            Type::Nil
+        } else {
+            Type::from_name(&self.consume_identifier("Expected type name.")?)
+        };
+
+        if self.match_token(&[TokenKind::Question]) {
+            Ok(Type::optional(ty))
+        } else {
+            Ok(ty)
+        }
     }
 
     fn function_declaration(&mut self) -> Result<Stmt> {
@@ -97,9 +177,15 @@ impl Parser {
         self.consume(TokenKind::LeftParen, "Expected '(' after function name.")?;
         
         let mut params = Vec::new();
+        let mut param_types = Vec::new();
         if !self.check(&TokenKind::RightParen) {
             loop {
                 params.push(self.consume_identifier("Expected parameter name.")?);
+                param_types.push(if self.match_token(&[TokenKind::Colon]) {
+                    Some(self.type_annotation()?)
+                } else {
+                    None
+                });
                 if !self.match_token(&[TokenKind::Comma]) {
                     break;
                 }
@@ -107,6 +193,12 @@ impl Parser {
         }
         
         self.consume(TokenKind::RightParen, "Expected ')' after parameters.")?;
+
+        let return_type = if self.match_token(&[TokenKind::ThinArrow]) {
+            Some(self.type_annotation()?)
+        } else {
+            None
+        };
         
         let is_async = self.match_token(&[TokenKind::Async]);
         let confidence = if self.match_token(&[TokenKind::Confidence]) {
@@ -115,22 +207,88 @@ impl Parser {
             None
         };
         
-        self.consume(TokenKind::LeftBrace, "Expected '{' before function body.")?;
+        if !self.check(&TokenKind::LeftBrace) {
+            return Err(PrismError::ParseError("Expected '{' before function body.".to_string()));
+        }
         let body = Box::new(self.block()?);
         
-        Ok(Stmt::Function { name, params, body, is_async, confidence })
+        Ok(Stmt::Function { name, params, param_types, return_type, body, is_async, confidence, captures: None })
     }
 
     fn statement(&mut self) -> Result<Stmt> {
         if self.match_token(&[TokenKind::If]) {
             self.if_statement()
-        } else if self.match_token(&[TokenKind::LeftBrace]) {
+        } else if self.check_identifier("uncertain") && self.check_next(&TokenKind::If) {
+            self.advance();
+            self.advance();
+            self.uncertain_if_statement()
+        } else if self.check(&TokenKind::In) && self.check_next(&TokenKind::Context) {
+            self.advance();
+            self.advance();
+            self.context_statement()
+        } else if self.match_token(&[TokenKind::While]) {
+            self.while_statement()
+        } else if self.match_token(&[TokenKind::For]) {
+            self.for_statement()
+        } else if self.match_token(&[TokenKind::Return]) {
+            self.return_statement()
+        } else if self.match_token(&[TokenKind::Break]) {
+            self.consume(TokenKind::Semicolon, "Expected ';' after 'break'.")?;
+            Ok(Stmt::Break)
+        } else if self.match_token(&[TokenKind::Continue]) {
+            self.consume(TokenKind::Semicolon, "Expected ';' after 'continue'.")?;
+            Ok(Stmt::Continue)
+        } else if self.match_token(&[TokenKind::Yield]) {
+            let value = Box::new(self.expression()?);
+            self.consume(TokenKind::Semicolon, "Expected ';' after yield value.")?;
+            Ok(Stmt::Yield(value))
+        } else if self.check(&TokenKind::LeftBrace) {
             self.block()
         } else {
             self.expression_statement()
         }
     }
 
+    fn return_statement(&mut self) -> Result<Stmt> {
+        let value = if self.check(&TokenKind::Semicolon) {
+            None
+        } else {
+            Some(Box::new(self.expression()?))
+        };
+        self.consume(TokenKind::Semicolon, "Expected ';' after return value.")?;
+        Ok(Stmt::Return(value))
+    }
+
+    /// `in context Triage { ... }`, or with a quoted name for names that
+    /// are not identifiers.
+    fn context_statement(&mut self) -> Result<Stmt> {
+        let name = if self.check(&TokenKind::String(String::new())) {
+            self.consume_string("Expected context name after 'in context'.")?
+        } else {
+            self.consume_identifier("Expected context name after 'in context'.")?
+        };
+        let body = Box::new(self.block()?);
+        Ok(Stmt::Context { name, body })
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt> {
+        self.consume(TokenKind::LeftParen, "Expected '(' after 'while'.")?;
+        let condition = Box::new(self.expression()?);
+        self.consume(TokenKind::RightParen, "Expected ')' after while condition.")?;
+        let body = Box::new(self.block()?);
+        Ok(Stmt::While { condition, body })
+    }
+
+    fn for_statement(&mut self) -> Result<Stmt> {
+        self.consume(TokenKind::LeftParen, "Expected '(' after 'for'.")?;
+        let name = self.consume_identifier("Expected loop variable name.")?;
+        self.consume(TokenKind::In, "Expected 'in' after loop variable.")?;
+        let iterable = Box::new(self.expression()?);
+        self.consume(TokenKind::RightParen, "Expected ')' after for clause.")?;
+        let body = Box::new(self.block()?);
+        Ok(Stmt::For { name, iterable, body })
+    }
+
     fn if_statement(&mut self) -> Result<Stmt> {
         self.consume(TokenKind::LeftParen, "Expected '(' after 'if'.")?;
         let condition = Box::new(self.expression()?);
@@ -154,6 +312,65 @@ impl Parser {
         })
     }
 
+    fn uncertain_if_statement(&mut self) -> Result<Stmt> {
+        self.consume(TokenKind::LeftParen, "Expected '(' after 'uncertain if'.")?;
+        // `uncertain if (x ~> 0.9)` sets the threshold for the first branch.
+        let (condition, high_threshold) = match self.expression()? {
+            Expr::Confidence { expr, confidence } => (expr, confidence),
+            expr => (Box::new(expr), DEFAULT_HIGH_CONFIDENCE),
+        };
+        self.consume(TokenKind::RightParen, "Expected ')' after uncertain if condition.")?;
+        let then_branch = Box::new(self.block()?);
+
+        let mut medium_threshold = DEFAULT_MEDIUM_CONFIDENCE;
+        let medium_branch = if self.match_branch_keyword("medium") {
+            if self.match_token(&[TokenKind::LeftParen]) {
+                // Either `medium (~> 0.5)` or, repeating the condition, `medium (x ~> 0.5)`.
+                medium_threshold = if self.match_token(&[TokenKind::Confidence]) {
+                    self.confidence_value()?
+                } else {
+                    match self.expression()? {
+                        Expr::Confidence { confidence, .. } => confidence,
+                        _ => return Err(PrismError::ParseError(
+                            "Expected '~>' threshold for medium branch.".to_string(),
+                        )),
+                    }
+                };
+                self.consume(TokenKind::RightParen, "Expected ')' after medium threshold.")?;
+            }
+            Some(Box::new(self.block()?))
+        } else {
+            None
+        };
+
+        let low_branch = if self.match_branch_keyword("low") {
+            Some(Box::new(self.block()?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::UncertainIf {
+            condition,
+            high_threshold,
+            medium_threshold,
+            then_branch,
+            medium_branch,
+            low_branch,
+        })
+    }
+
+    /// `medium` and `low` are only keywords directly in front of their branch.
+    fn match_branch_keyword(&mut self, keyword: &str) -> bool {
+        if self.check_identifier(keyword)
+            && (self.check_next(&TokenKind::LeftBrace) || self.check_next(&TokenKind::LeftParen))
+        {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
     fn block(&mut self) -> Result<Stmt> {
         self.consume(TokenKind::LeftBrace, "Expected '{' before block.")?;
         let mut statements = Vec::new();
@@ -177,16 +394,17 @@ impl Parser {
     }
 
     fn assignment(&mut self) -> Result<Expr> {
-        let expr = self.equality()?;
+        let expr = self.confidence()?;
 
         if self.match_token(&[TokenKind::Equal]) {
             let equals = self.previous().clone();
             let value = self.assignment()?;
 
-            if let Expr::Variable(name) = expr {
+            if let Expr::Variable { name, .. } = expr {
                 return Ok(Expr::Assign {
                     name,
                     value: Box::new(value),
+                    slot: None,
                 });
             }
 
@@ -199,6 +417,64 @@ impl Parser {
         Ok(expr)
     }
 
+    fn confidence(&mut self) -> Result<Expr> {
+        let expr = self.or()?;
+
+        if self.match_token(&[TokenKind::Confidence]) {
+            let confidence = self.confidence_value()?;
+            return Ok(Expr::Confidence {
+                expr: Box::new(expr),
+                confidence,
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn confidence_value(&mut self) -> Result<f64> {
+        let line = self.peek().line;
+        let value = self.consume_number("Expected confidence value after '~>'.")?;
+        if !(0.0..=1.0).contains(&value) {
+            return Err(PrismError::ParseError(format!(
+                "Confidence must be between 0 and 1, got {} at line {}",
+                value, line
+            )));
+        }
+        Ok(value)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+
+        while self.match_token(&[TokenKind::Or]) {
+            let operator = self.previous().clone();
+            let right = self.and()?;
+            expr = Expr::Logical {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.equality()?;
+
+        while self.match_token(&[TokenKind::And]) {
+            let operator = self.previous().clone();
+            let right = self.equality()?;
+            expr = Expr::Logical {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(expr)
+    }
+
     fn equality(&mut self) -> Result<Expr> {
         let mut expr = self.comparison()?;
 
@@ -277,10 +553,46 @@ impl Parser {
                 right: Box::new(right),
             })
         } else {
-            self.primary()
+            self.call()
         }
     }
 
+    fn call(&mut self) -> Result<Expr> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.match_token(&[TokenKind::LeftParen]) {
+                let mut arguments = Vec::new();
+                if !self.check(&TokenKind::RightParen) {
+                    loop {
+                        arguments.push(self.expression()?);
+                        if !self.match_token(&[TokenKind::Comma]) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(TokenKind::RightParen, "Expected ')' after arguments.")?;
+                expr = Expr::Call {
+                    callee: Box::new(expr),
+                    arguments,
+                    tail: false,
+                };
+            } else if self.match_token(&[TokenKind::Dot, TokenKind::QuestionDot]) {
+                let optional = self.previous().kind == TokenKind::QuestionDot;
+                let name = self.consume_property_name()?;
+                expr = Expr::Get {
+                    object: Box::new(expr),
+                    name,
+                    optional,
+                };
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
     fn primary(&mut self) -> Result<Expr> {
         if self.match_token(&[TokenKind::False]) {
             Ok(Expr::Literal(Value::new(ValueKind::Boolean(false))))
@@ -295,15 +607,33 @@ impl Parser {
             } else {
                 unreachable!()
             }
+        } else if let TokenKind::Decimal(digits) = &self.peek().kind {
+            let digits = digits.clone();
+            self.advance();
+            decimal_literal(&digits)
         } else if self.match_token(&[TokenKind::String(String::new())]) {
             if let TokenKind::String(ref s) = self.previous().kind {
                 Ok(Expr::Literal(Value::new(ValueKind::String(s.clone()))))
             } else {
                 unreachable!()
             }
+        } else if self.check(&TokenKind::Async) && self.check_next(&TokenKind::Dot) {
+            // `async` is a keyword after parameter lists but also names the
+            // stdlib concurrency module
+            let line = self.advance().line;
+            Ok(Expr::Variable {
+                name: "async".to_string(),
+                line,
+                slot: None,
+            })
         } else if self.match_token(&[TokenKind::Identifier(String::new())]) {
-            if let TokenKind::Identifier(ref name) = self.previous().kind {
-                Ok(Expr::Variable(name.clone()))
+            let token = self.previous();
+            if let TokenKind::Identifier(ref name) = token.kind {
+                Ok(Expr::Variable {
+                    name: name.clone(),
+                    line: token.line,
+                    slot: None,
+                })
             } else {
                 unreachable!()
             }
@@ -311,6 +641,35 @@ impl Parser {
             let expr = self.expression()?;
             self.consume(TokenKind::RightParen, "Expected ')' after expression.")?;
             Ok(Expr::Grouping(Box::new(expr)))
+        } else if self.match_token(&[TokenKind::Match]) {
+            self.match_expression()
+        } else if self.match_token(&[TokenKind::LeftBracket]) {
+            let mut items = Vec::new();
+            while !self.check(&TokenKind::RightBracket) {
+                items.push(self.expression()?);
+                if !self.match_token(&[TokenKind::Comma]) {
+                    break;
+                }
+            }
+            self.consume(TokenKind::RightBracket, "Expected ']' after list elements.")?;
+            Ok(Expr::List(items))
+        } else if self.match_token(&[TokenKind::LeftBrace]) {
+            let mut entries = Vec::new();
+            while !self.check(&TokenKind::RightBrace) {
+                let key = if matches!(self.peek().kind, TokenKind::String(_)) {
+                    self.consume_string("Expected map key.")?
+                } else {
+                    self.consume_identifier("Expected map key.")?
+                };
+                self.consume(TokenKind::Colon, "Expected ':' after map key.")?;
+                let value = self.expression()?;
+                entries.push((Expr::Literal(Value::new(ValueKind::String(key))), value));
+                if !self.match_token(&[TokenKind::Comma]) {
+                    break;
+                }
+            }
+            self.consume(TokenKind::RightBrace, "Expected '}' after map entries.")?;
+            Ok(Expr::Map(entries))
         } else {
             Err(PrismError::ParseError(format!(
                 "Expected expression at line {}",
@@ -319,6 +678,68 @@ impl Parser {
         }
     }
 
+    fn match_expression(&mut self) -> Result<Expr> {
+        let subject = Box::new(self.expression()?);
+        self.consume(TokenKind::LeftBrace, "Expected '{' after match subject.")?;
+
+        let mut arms = Vec::new();
+        while !self.check(&TokenKind::RightBrace) && !self.is_at_end() {
+            let pattern = self.pattern()?;
+            let guard = if self.match_token(&[TokenKind::If]) {
+                Some(self.expression()?)
+            } else {
+                None
+            };
+            self.consume(TokenKind::Arrow, "Expected '=>' after match pattern.")?;
+            let body = self.expression()?;
+            arms.push(MatchArm { pattern, guard, body });
+
+            if !self.match_token(&[TokenKind::Comma]) {
+                break;
+            }
+        }
+
+        self.consume(TokenKind::RightBrace, "Expected '}' after match arms.")?;
+        Ok(Expr::Match { subject, arms })
+    }
+
+    fn pattern(&mut self) -> Result<Pattern> {
+        if self.match_token(&[TokenKind::Confidence]) {
+            return Ok(Pattern::Confidence(self.confidence_value()?));
+        }
+        if self.match_token(&[TokenKind::Minus]) {
+            let n = self.consume_number("Expected number after '-' in pattern.")?;
+            return Ok(Pattern::Literal(Value::new(ValueKind::Number(-n))));
+        }
+        if let TokenKind::Identifier(name) = &self.peek().kind {
+            let pattern = if name == "_" {
+                Pattern::Wildcard
+            } else {
+                Pattern::Binding(name.clone())
+            };
+            self.advance();
+            return Ok(pattern);
+        }
+        match self.primary()? {
+            Expr::Literal(value) => Ok(Pattern::Literal(value)),
+            _ => Err(PrismError::ParseError(format!(
+                "Expected pattern at line {}",
+                self.previous().line
+            ))),
+        }
+    }
+
+    fn check_identifier(&self, name: &str) -> bool {
+        matches!(&self.peek().kind, TokenKind::Identifier(ident) if ident == name)
+    }
+
+    fn check_next(&self, kind: &TokenKind) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => std::mem::discriminant(&token.kind) == std::mem::discriminant(kind),
+            None => false,
+        }
+    }
+
     fn match_token(&mut self, kinds: &[TokenKind]) -> bool {
         for kind in kinds {
             if self.check(kind) {
@@ -353,6 +774,11 @@ impl Parser {
         self.peek().kind == TokenKind::EOF
     }
 
+    /// The line of the token the parser is at, e.g. where a parse failed.
+    pub fn line(&self) -> usize {
+        self.peek().line
+    }
+
     fn peek(&self) -> &Token {
         &self.tokens[self.current]
     }
@@ -369,6 +795,20 @@ impl Parser {
         }
     }
 
+    /// A name after `.`; keywords are allowed there, as in `pattern.match`.
+    fn consume_property_name(&mut self) -> Result<String> {
+        let token = self.peek();
+        let is_keyword = token.kind != TokenKind::EOF
+            && !token.lexeme().is_empty()
+            && token.lexeme().chars().all(|c| c.is_ascii_alphabetic());
+        if is_keyword && !matches!(token.kind, TokenKind::Identifier(_)) {
+            let name = token.lexeme().to_string();
+            self.advance();
+            return Ok(name);
+        }
+        self.consume_identifier("Expected property name after '.'.")
+    }
+
     fn consume_identifier(&mut self, message: &str) -> Result<String> {
         if let TokenKind::Identifier(ref name) = self.peek().kind {
             let name = name.clone();
@@ -381,7 +821,6 @@ impl Parser {
 
     fn consume_number(&mut self, message: &str) -> Result<f64> {
         if let TokenKind::Number(n) = self.peek().kind {
-            let n = n;
             self.advance();
             Ok(n)
         } else {
@@ -390,10 +829,7 @@ impl Parser {
     }
 
     fn check_number(&self) -> bool {
-        match &self.peek().kind {
-            TokenKind::Number(_) => true,
-            _ => false,
-        }
+        matches!(self.peek().kind, TokenKind::Number(_))
     }
 
     fn consume_string(&mut self, message: &str) -> Result<String> {
@@ -407,8 +843,22 @@ impl Parser {
     }
 }
 
+#[cfg(feature = "decimal")]
+fn decimal_literal(digits: &str) -> Result<Expr> {
+    let decimal = crate::stdlib::decimal::parse(digits)?;
+    Ok(Expr::Literal(Value::new(ValueKind::Decimal(decimal))))
+}
+
+#[cfg(not(feature = "decimal"))]
+fn decimal_literal(digits: &str) -> Result<Expr> {
+    Err(PrismError::ParseError(format!(
+        "The decimal literal {}d needs Prism built with the `decimal` feature.",
+        digits
+    )))
+}
+
 pub fn parse(source: &str) -> Result<Vec<Stmt>> {
-    let mut lexer = Lexer::new(source.to_string());
+    let mut lexer = Lexer::new(source);
     let tokens = lexer.scan_tokens()?;
     let mut parser = Parser::new(tokens);
     parser.parse()
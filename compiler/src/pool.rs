@@ -0,0 +1,281 @@
+use std::sync::{Arc, OnceLock};
+use crate::capabilities::Capabilities;
+use crate::environment::Environment;
+use crate::error::Result;
+use crate::interpreter::Interpreter;
+use crate::input::InputSource;
+use crate::llm::replay::{Recorder, Replay};
+use crate::output::OutputSink;
+use crate::progress::ProgressSink;
+use crate::value::Value;
+
+/// How the interpreters of a pool see each other's globals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Isolation {
+    /// One global environment for every interpreter: what one evaluation
+    /// defines, the next sees, until the pool is dropped.
+    Shared,
+    /// Each interpreter starts from the pool's globals and keeps what it
+    /// defines to itself.
+    #[default]
+    CopyOnWrite,
+    /// As [`CopyOnWrite`](Self::CopyOnWrite), and scripts cannot redefine
+    /// or assign the modules, prelude functions and host globals.
+    Frozen,
+}
+
+/// Creates isolated interpreters that share read-only setup.
+///
+/// The standard library modules are built once and handed to every
+/// interpreter as `Arc`s, so a server can start one interpreter per request
+/// cheaply. Globals defined by a script stay in that script's interpreter
+/// unless the pool's [`Isolation`] is `Shared`.
+/// The pool is cheap to clone and can be shared across tasks.
+#[derive(Clone)]
+pub struct InterpreterPool {
+    globals: Arc<Vec<(String, Value)>>,
+    prelude: Option<Arc<Vec<(String, Value)>>>,
+    isolation: Isolation,
+    /// The environment `Shared` interpreters run in, made on first use.
+    shared: Arc<OnceLock<Arc<Environment>>>,
+    max_call_depth: Option<usize>,
+    max_loop_iterations: Option<usize>,
+    yield_interval: Option<usize>,
+    capabilities: Capabilities,
+    output: Option<Arc<dyn OutputSink>>,
+    input: Option<Arc<dyn InputSource>>,
+    progress: Option<Arc<dyn ProgressSink>>,
+    recorder: Option<Arc<Recorder>>,
+    replay: Option<Arc<Replay>>,
+}
+
+impl InterpreterPool {
+    pub fn new() -> Result<Self> {
+        let modules = crate::stdlib::init_stdlib()?;
+        let prelude = crate::stdlib::prelude_of(&modules)?
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        let globals = modules
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        Ok(Self {
+            globals: Arc::new(globals),
+            prelude: Some(Arc::new(prelude)),
+            isolation: Isolation::default(),
+            shared: Arc::new(OnceLock::new()),
+            max_call_depth: None,
+            max_loop_iterations: None,
+            yield_interval: None,
+            capabilities: Capabilities::none(),
+            output: None,
+            input: None,
+            progress: None,
+            recorder: None,
+            replay: None,
+        })
+    }
+
+    /// Leaves the prelude out; see [`Interpreter::without_prelude`].
+    pub fn without_prelude(mut self) -> Self {
+        self.prelude = None;
+        self.shared = Arc::new(OnceLock::new());
+        self
+    }
+
+    /// Adds a host global, e.g. a native function, to every interpreter.
+    pub fn with_global(mut self, name: impl Into<String>, value: Value) -> Self {
+        Arc::make_mut(&mut self.globals).push((name.into(), value));
+        self.shared = Arc::new(OnceLock::new());
+        self
+    }
+
+    /// How interpreters share globals; copy-on-write by default.
+    pub fn with_isolation(mut self, isolation: Isolation) -> Self {
+        self.isolation = isolation;
+        self
+    }
+
+    pub fn with_max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = Some(depth);
+        self
+    }
+
+    /// See [`Interpreter::with_max_loop_iterations`].
+    pub fn with_max_loop_iterations(mut self, limit: usize) -> Self {
+        self.max_loop_iterations = Some(limit);
+        self
+    }
+
+    /// How often each interpreter yields to the runtime; see
+    /// [`Interpreter::with_yield_interval`].
+    pub fn with_yield_interval(mut self, interval: usize) -> Self {
+        self.yield_interval = Some(interval);
+        self
+    }
+
+    /// Capabilities granted to every interpreter; none by default.
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Output sink shared by every interpreter; stdout by default.
+    pub fn with_output(mut self, output: Arc<dyn OutputSink>) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    /// Input source shared by every interpreter; stdin by default.
+    pub fn with_input(mut self, input: Arc<dyn InputSource>) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    /// Progress display shared by every interpreter; none by default.
+    pub fn with_progress(mut self, progress: Arc<dyn ProgressSink>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Recorder shared by every interpreter; their exchanges interleave.
+    pub fn with_recorder(mut self, recorder: Arc<Recorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Recording every interpreter answers LLM requests from.
+    pub fn with_replay(mut self, replay: Arc<Replay>) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    /// A fresh interpreter with the shared globals defined.
+    pub fn interpreter(&self) -> Interpreter {
+        let mut interpreter = match self.isolation {
+            Isolation::Shared => {
+                let globals = self.shared.get_or_init(|| Arc::new(self.environment()));
+                Interpreter::with_shared_globals(Arc::clone(globals))
+            }
+            Isolation::CopyOnWrite => Interpreter::with_global_environment(self.environment()),
+            Isolation::Frozen => Interpreter::with_global_environment(self.environment().freeze()),
+        }
+        .with_capabilities(self.capabilities.clone());
+        if let Some(depth) = self.max_call_depth {
+            interpreter = interpreter.with_max_call_depth(depth);
+        }
+        if let Some(limit) = self.max_loop_iterations {
+            interpreter = interpreter.with_max_loop_iterations(limit);
+        }
+        if let Some(interval) = self.yield_interval {
+            interpreter = interpreter.with_yield_interval(interval);
+        }
+        if let Some(output) = &self.output {
+            interpreter = interpreter.with_output(Arc::clone(output));
+        }
+        if let Some(input) = &self.input {
+            interpreter = interpreter.with_input(Arc::clone(input));
+        }
+        if let Some(progress) = &self.progress {
+            interpreter = interpreter.with_progress(Arc::clone(progress));
+        }
+        if let Some(recorder) = &self.recorder {
+            interpreter = interpreter.with_recorder(Arc::clone(recorder));
+        }
+        if let Some(replay) = &self.replay {
+            interpreter = interpreter.with_replay(Arc::clone(replay));
+        }
+        interpreter
+    }
+
+    fn environment(&self) -> Environment {
+        match &self.prelude {
+            // Host globals come last so they can replace prelude functions
+            Some(prelude) => Environment::with_values(prelude.iter().chain(self.globals.iter()).cloned()),
+            None => Environment::with_values(self.globals.iter().cloned()),
+        }
+    }
+
+    /// Evaluates `source` in a fresh interpreter.
+    pub async fn evaluate(&self, source: String) -> Result<Value> {
+        self.interpreter().evaluate(source).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::ValueKind;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_evaluations_are_isolated() {
+        let pool = InterpreterPool::new().unwrap();
+
+        let tasks: Vec<_> = (0..300)
+            .map(|i| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    let source = format!(
+                        "let id = {}; fn twice(n) {{ return n * 2; }} let i = 0; while (i < 50) {{ i = i + 1; }} twice(id) + i;",
+                        i
+                    );
+                    (i, pool.evaluate(source).await)
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            let (i, result) = task.await.unwrap();
+            assert_eq!(result.unwrap().kind, ValueKind::Number((i * 2 + 50) as f64));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shared_modules_and_host_globals() -> Result<()> {
+        let pool = InterpreterPool::new()?.with_global("limit", Value::new(ValueKind::Number(3.0)));
+
+        let mut first = pool.interpreter();
+        let result = first.evaluate("let mine = limit + 1; core.type(mine);".to_string()).await?;
+        assert_eq!(result.kind, ValueKind::String("number".to_string()));
+
+        let mut second = pool.interpreter();
+        assert!(second.evaluate("mine;".to_string()).await.is_err());
+
+        let first_core = first.evaluate("core;".to_string()).await?;
+        let second_core = second.evaluate("core;".to_string()).await?;
+        match (first_core.kind, second_core.kind) {
+            (ValueKind::Module(a), ValueKind::Module(b)) => assert!(Arc::ptr_eq(&a, &b)),
+            other => panic!("expected modules, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_isolation_levels() -> Result<()> {
+        let shared = InterpreterPool::new()?.with_isolation(Isolation::Shared);
+        shared.evaluate("let visits = 1;".to_string()).await?;
+        let result = shared.evaluate("visits = visits + 1; visits;".to_string()).await?;
+        assert_eq!(result.kind, ValueKind::Number(2.0));
+        // Interpreters going away leave the shared globals in place
+        assert_eq!(shared.clone().evaluate("visits;".to_string()).await?.kind, ValueKind::Number(2.0));
+
+        let frozen = InterpreterPool::new()?
+            .with_global("limit", Value::new(ValueKind::Number(3.0)))
+            .with_isolation(Isolation::Frozen);
+        let err = frozen.evaluate("fn len(x) { return 0; }".to_string()).await.unwrap_err();
+        assert!(err.to_string().contains("len is built in and cannot be redefined"), "{}", err);
+        assert!(frozen.evaluate("limit = 4;".to_string()).await.is_err());
+        let result = frozen.evaluate("let mine = 1; mine = limit + len([1, 2]); mine;".to_string()).await?;
+        assert_eq!(result.kind, ValueKind::Number(5.0));
+        assert!(frozen.evaluate("mine;".to_string()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pool_without_prelude() {
+        let pool = InterpreterPool::new().unwrap();
+        assert!(pool.evaluate("range(3);".to_string()).await.is_ok());
+        assert!(pool.without_prelude().evaluate("range(3);".to_string()).await.is_err());
+    }
+}
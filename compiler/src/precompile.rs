@@ -0,0 +1,824 @@
+//! Precompiled programs: `prism build triage.prism` saves the parsed program
+//! as `triage.prismc`, a compact binary form that running or importing the
+//! file loads instead of lexing and parsing the source again.
+//!
+//! A precompiled file is used while it is at least as new as its source
+//! and was written by the same version of Prism; otherwise the source is
+//! parsed as usual. `@cfg` annotations are kept, so one build serves every
+//! set of flags, and so are line numbers, so errors still point at the
+//! source.
+
+use std::path::{Path, PathBuf};
+use crate::ast::{CfgPredicate, Expr, MatchArm, Pattern, Slot, Stmt, Type};
+use crate::error::{PrismError, Result};
+use crate::lexer::Lexer;
+use crate::token::{Token, TokenKind};
+use crate::value::{Value, ValueKind};
+
+pub const EXTENSION: &str = "prismc";
+
+const MAGIC: &[u8] = b"PRISMC";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Where `source` is precompiled to unless told otherwise.
+pub fn compiled_path(source: &Path) -> PathBuf {
+    source.with_extension(EXTENSION)
+}
+
+/// The program in `path`. A `.prismc` file is decoded; a source file is
+/// parsed unless its precompiled twin is fresh, or it only exists
+/// precompiled.
+pub fn load(path: &Path) -> Result<Vec<Stmt>> {
+    if path.extension().is_some_and(|extension| extension == EXTENSION) {
+        return decode(&std::fs::read(path)?);
+    }
+    let compiled = compiled_path(path);
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    match (modified(&compiled), modified(path)) {
+        (Some(_), None) => decode(&std::fs::read(&compiled)?),
+        (Some(built), Some(edited)) if built >= edited => {
+            // One from another version of Prism is as good as stale
+            match std::fs::read(&compiled).map_err(PrismError::from).and_then(|bytes| decode(&bytes)) {
+                Ok(program) => Ok(program),
+                Err(_) => crate::parser::parse(&std::fs::read_to_string(path)?),
+            }
+        }
+        _ => crate::parser::parse(&std::fs::read_to_string(path)?),
+    }
+}
+
+/// The binary form of `program`. Literals must be plain data: programs
+/// built in code may hold functions, which cannot be saved.
+pub fn encode(program: &[Stmt]) -> Result<Vec<u8>> {
+    let mut writer = Writer { bytes: MAGIC.to_vec() };
+    writer.string(VERSION);
+    writer.stmts(program)?;
+    Ok(writer.bytes)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Vec<Stmt>> {
+    let bytes = bytes.strip_prefix(MAGIC).ok_or_else(|| invalid("not a precompiled Prism program"))?;
+    let mut reader = Reader { bytes };
+    let version = reader.string()?;
+    if version != VERSION {
+        return Err(invalid(&format!("built by Prism {}, this is {}", version, VERSION)));
+    }
+    let program = reader.stmts()?;
+    if !reader.bytes.is_empty() {
+        return Err(invalid("trailing bytes"));
+    }
+    Ok(program)
+}
+
+fn invalid(reason: &str) -> PrismError {
+    PrismError::ParseError(format!("Invalid precompiled program: {}", reason))
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn byte(&mut self, byte: u8) {
+        self.bytes.push(byte);
+    }
+
+    /// LEB128: seven bits a byte, low bits first.
+    fn uint(&mut self, mut n: usize) {
+        while n >= 0x80 {
+            self.byte((n as u8 & 0x7f) | 0x80);
+            n >>= 7;
+        }
+        self.byte(n as u8);
+    }
+
+    fn number(&mut self, n: f64) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn flag(&mut self, flag: bool) {
+        self.byte(flag as u8);
+    }
+
+    fn string(&mut self, text: &str) {
+        self.uint(text.len());
+        self.bytes.extend_from_slice(text.as_bytes());
+    }
+
+    fn strings(&mut self, items: &[String]) {
+        self.uint(items.len());
+        items.iter().for_each(|item| self.string(item));
+    }
+
+    fn option<T>(&mut self, value: Option<T>, write: impl FnOnce(&mut Self, T) -> Result<()>) -> Result<()> {
+        self.flag(value.is_some());
+        value.map_or(Ok(()), |value| write(self, value))
+    }
+
+    fn stmts(&mut self, statements: &[Stmt]) -> Result<()> {
+        self.uint(statements.len());
+        statements.iter().try_for_each(|stmt| self.stmt(stmt))
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) -> Result<()> {
+        self.uint(exprs.len());
+        exprs.iter().try_for_each(|expr| self.expr(expr))
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<()> {
+        match stmt {
+            Stmt::Expression(expr) => {
+                self.byte(0);
+                self.expr(expr)
+            }
+            Stmt::Let { name, type_annotation, initializer } => {
+                self.byte(1);
+                self.string(name);
+                self.option(type_annotation.as_ref(), |w, ty| {
+                    w.ty(ty);
+                    Ok(())
+                })?;
+                self.option(initializer.as_deref(), Self::expr)
+            }
+            Stmt::Block(statements) => {
+                self.byte(2);
+                self.stmts(statements)
+            }
+            Stmt::If { condition, then_branch, else_branch } => {
+                self.byte(3);
+                self.expr(condition)?;
+                self.stmt(then_branch)?;
+                self.option(else_branch.as_deref(), Self::stmt)
+            }
+            Stmt::UncertainIf { condition, high_threshold, medium_threshold, then_branch, medium_branch, low_branch } => {
+                self.byte(4);
+                self.expr(condition)?;
+                self.number(*high_threshold);
+                self.number(*medium_threshold);
+                self.stmt(then_branch)?;
+                self.option(medium_branch.as_deref(), Self::stmt)?;
+                self.option(low_branch.as_deref(), Self::stmt)
+            }
+            Stmt::While { condition, body } => {
+                self.byte(5);
+                self.expr(condition)?;
+                self.stmt(body)
+            }
+            Stmt::For { name, iterable, body } => {
+                self.byte(6);
+                self.string(name);
+                self.expr(iterable)?;
+                self.stmt(body)
+            }
+            Stmt::Function { name, params, param_types, return_type, body, is_async, confidence, captures } => {
+                self.byte(7);
+                self.string(name);
+                self.strings(params);
+                self.uint(param_types.len());
+                for param_type in param_types {
+                    self.option(param_type.as_ref(), |w, ty| {
+                        w.ty(ty);
+                        Ok(())
+                    })?;
+                }
+                self.option(return_type.as_ref(), |w, ty| {
+                    w.ty(ty);
+                    Ok(())
+                })?;
+                self.stmt(body)?;
+                self.flag(*is_async);
+                self.option(*confidence, |w, confidence| {
+                    w.number(confidence);
+                    Ok(())
+                })?;
+                self.option(captures.as_ref(), |w, captures| {
+                    w.uint(captures.len());
+                    captures.iter().for_each(|&index| w.uint(index));
+                    Ok(())
+                })
+            }
+            Stmt::Return(value) => {
+                self.byte(8);
+                self.option(value.as_deref(), Self::expr)
+            }
+            Stmt::Yield(value) => {
+                self.byte(9);
+                self.expr(value)
+            }
+            Stmt::Context { name, body } => {
+                self.byte(10);
+                self.string(name);
+                self.stmt(body)
+            }
+            Stmt::Import { module, imports, confidence } => {
+                self.byte(11);
+                self.string(module);
+                self.uint(imports.len());
+                for (name, alias) in imports {
+                    self.string(name);
+                    self.option(alias.as_deref(), |w, alias| {
+                        w.string(alias);
+                        Ok(())
+                    })?;
+                }
+                self.option(*confidence, |w, confidence| {
+                    w.number(confidence);
+                    Ok(())
+                })
+            }
+            Stmt::Export(name, body) => {
+                self.byte(12);
+                self.string(name);
+                self.stmt(body)
+            }
+            Stmt::Module { name, body, confidence } => {
+                self.byte(13);
+                self.string(name);
+                self.stmts(body)?;
+                self.option(*confidence, |w, confidence| {
+                    w.number(confidence);
+                    Ok(())
+                })
+            }
+            Stmt::ModuleAccess { module_name, name } => {
+                self.byte(14);
+                self.string(module_name);
+                self.string(name);
+                Ok(())
+            }
+            Stmt::Cfg { predicate, body } => {
+                self.byte(15);
+                self.predicate(predicate);
+                self.stmt(body)
+            }
+            Stmt::Break => {
+                self.byte(16);
+                Ok(())
+            }
+            Stmt::Continue => {
+                self.byte(17);
+                Ok(())
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<()> {
+        match expr {
+            Expr::Literal(value) => {
+                self.byte(0);
+                self.value(value)
+            }
+            Expr::Variable { name, line, slot } => {
+                self.byte(1);
+                self.string(name);
+                self.uint(*line);
+                self.slot(*slot);
+                Ok(())
+            }
+            Expr::Assign { name, value, slot } => {
+                self.byte(2);
+                self.string(name);
+                self.expr(value)?;
+                self.slot(*slot);
+                Ok(())
+            }
+            Expr::Binary { left, operator, right } | Expr::Logical { left, operator, right } => {
+                self.byte(if matches!(expr, Expr::Binary { .. }) { 3 } else { 4 });
+                self.expr(left)?;
+                self.token(operator);
+                self.expr(right)
+            }
+            Expr::Unary { operator, right } => {
+                self.byte(5);
+                self.token(operator);
+                self.expr(right)
+            }
+            Expr::Call { callee, arguments, tail } => {
+                self.byte(6);
+                self.expr(callee)?;
+                self.exprs(arguments)?;
+                self.flag(*tail);
+                Ok(())
+            }
+            Expr::Get { object, name, optional } => {
+                self.byte(7);
+                self.expr(object)?;
+                self.string(name);
+                self.flag(*optional);
+                Ok(())
+            }
+            Expr::Confidence { expr, confidence } => {
+                self.byte(8);
+                self.expr(expr)?;
+                self.number(*confidence);
+                Ok(())
+            }
+            Expr::ConfidenceCombine { left, right } => {
+                self.byte(9);
+                self.expr(left)?;
+                self.expr(right)
+            }
+            Expr::InContext { context, body } => {
+                self.byte(10);
+                self.string(context);
+                self.expr(body)
+            }
+            Expr::Grouping(inner) => {
+                self.byte(11);
+                self.expr(inner)
+            }
+            Expr::List(items) => {
+                self.byte(12);
+                self.exprs(items)
+            }
+            Expr::Map(entries) => {
+                self.byte(13);
+                self.uint(entries.len());
+                entries.iter().try_for_each(|(key, value)| {
+                    self.expr(key)?;
+                    self.expr(value)
+                })
+            }
+            Expr::ModuleAccess { module, name } => {
+                self.byte(14);
+                self.string(module);
+                self.string(name);
+                Ok(())
+            }
+            Expr::Match { subject, arms } => {
+                self.byte(15);
+                self.expr(subject)?;
+                self.uint(arms.len());
+                for arm in arms {
+                    self.pattern(&arm.pattern)?;
+                    self.option(arm.guard.as_ref(), Self::expr)?;
+                    self.expr(&arm.body)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn value(&mut self, value: &Value) -> Result<()> {
+        match &value.kind {
+            ValueKind::Nil => self.byte(0),
+            ValueKind::Boolean(b) => {
+                self.byte(1);
+                self.flag(*b);
+            }
+            ValueKind::Number(n) => {
+                self.byte(2);
+                self.number(*n);
+            }
+            ValueKind::String(s) => {
+                self.byte(3);
+                self.string(s);
+            }
+            ValueKind::List(items) => {
+                self.byte(4);
+                self.uint(items.len());
+                items.iter().try_for_each(|item| self.value(item))?;
+            }
+            ValueKind::Map(entries) => {
+                self.byte(5);
+                self.uint(entries.len());
+                entries.iter().try_for_each(|(key, value)| {
+                    self.value(key)?;
+                    self.value(value)
+                })?;
+            }
+            ValueKind::Range { start, end, step } => {
+                self.byte(6);
+                self.number(*start);
+                self.number(*end);
+                self.number(*step);
+            }
+            ValueKind::DateTime(_) | ValueKind::Duration(_) => {
+                self.byte(if matches!(value.kind, ValueKind::DateTime(_)) { 8 } else { 9 });
+                self.string(&value.to_string());
+            }
+            #[cfg(feature = "decimal")]
+            ValueKind::Decimal(decimal) => {
+                self.byte(7);
+                self.string(&decimal.to_string());
+            }
+            _ => {
+                return Err(PrismError::InvalidOperation(format!("Cannot precompile the literal {}", value)));
+            }
+        }
+        self.number(value.confidence);
+        self.option(value.context.as_deref(), |w, context| {
+            w.string(context);
+            Ok(())
+        })
+    }
+
+    /// Tokens are saved as their lexeme and read back by lexing it.
+    fn token(&mut self, token: &Token) {
+        self.string(token.lexeme());
+        self.uint(token.line);
+    }
+
+    fn slot(&mut self, slot: Option<Slot>) {
+        self.flag(slot.is_some());
+        match slot {
+            Some(Slot::Local(index)) => {
+                self.byte(0);
+                self.uint(index);
+            }
+            Some(Slot::Captured { depth, index }) => {
+                self.byte(1);
+                self.uint(depth);
+                self.uint(index);
+            }
+            None => {}
+        }
+    }
+
+    fn ty(&mut self, ty: &Type) {
+        match ty {
+            Type::Any => self.byte(0),
+            Type::Nil => self.byte(1),
+            Type::Boolean => self.byte(2),
+            Type::Number => self.byte(3),
+            Type::String => self.byte(4),
+            Type::List => self.byte(5),
+            Type::Map => self.byte(6),
+            Type::Function => self.byte(7),
+            Type::Named(name) => {
+                self.byte(8);
+                self.string(name);
+            }
+            Type::Union(members) => {
+                self.byte(9);
+                self.uint(members.len());
+                members.iter().for_each(|member| self.ty(member));
+            }
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> Result<()> {
+        match pattern {
+            Pattern::Literal(value) => {
+                self.byte(0);
+                return self.value(value);
+            }
+            Pattern::Wildcard => self.byte(1),
+            Pattern::Binding(name) => {
+                self.byte(2);
+                self.string(name);
+            }
+            Pattern::Confidence(threshold) => {
+                self.byte(3);
+                self.number(*threshold);
+            }
+        }
+        Ok(())
+    }
+
+    fn predicate(&mut self, predicate: &CfgPredicate) {
+        match predicate {
+            CfgPredicate::Flag(flag) => {
+                self.byte(0);
+                self.string(flag);
+            }
+            CfgPredicate::Value(key, value) => {
+                self.byte(1);
+                self.string(key);
+                self.string(value);
+            }
+            CfgPredicate::Not(inner) => {
+                self.byte(2);
+                self.predicate(inner);
+            }
+            CfgPredicate::All(items) | CfgPredicate::Any(items) => {
+                self.byte(if matches!(predicate, CfgPredicate::All(_)) { 3 } else { 4 });
+                self.uint(items.len());
+                items.iter().for_each(|item| self.predicate(item));
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8> {
+        let (&byte, rest) = self.bytes.split_first().ok_or_else(|| invalid("unexpected end"))?;
+        self.bytes = rest;
+        Ok(byte)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        if self.bytes.len() < len {
+            return Err(invalid("unexpected end"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn uint(&mut self) -> Result<usize> {
+        let mut n = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.byte()?;
+            n |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(invalid("number too large"))
+    }
+
+    fn number(&mut self) -> Result<f64> {
+        let bytes = self.take(8)?.try_into().expect("eight bytes");
+        Ok(f64::from_le_bytes(bytes))
+    }
+
+    fn flag(&mut self) -> Result<bool> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid("bad flag")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.uint()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("bad string"))
+    }
+
+    fn strings(&mut self) -> Result<Vec<String>> {
+        let len = self.uint()?;
+        (0..len).map(|_| self.string()).collect()
+    }
+
+    fn option<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T>) -> Result<Option<T>> {
+        if self.flag()? {
+            read(self).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn stmts(&mut self) -> Result<Vec<Stmt>> {
+        let len = self.uint()?;
+        (0..len).map(|_| self.stmt()).collect()
+    }
+
+    fn exprs(&mut self) -> Result<Vec<Expr>> {
+        let len = self.uint()?;
+        (0..len).map(|_| self.expr()).collect()
+    }
+
+    fn boxed_stmt(&mut self) -> Result<Box<Stmt>> {
+        self.stmt().map(Box::new)
+    }
+
+    fn boxed_expr(&mut self) -> Result<Box<Expr>> {
+        self.expr().map(Box::new)
+    }
+
+    fn stmt(&mut self) -> Result<Stmt> {
+        Ok(match self.byte()? {
+            0 => Stmt::Expression(self.boxed_expr()?),
+            1 => Stmt::Let {
+                name: self.string()?,
+                type_annotation: self.option(Self::ty)?,
+                initializer: self.option(Self::boxed_expr)?,
+            },
+            2 => Stmt::Block(self.stmts()?),
+            3 => Stmt::If {
+                condition: self.boxed_expr()?,
+                then_branch: self.boxed_stmt()?,
+                else_branch: self.option(Self::boxed_stmt)?,
+            },
+            4 => Stmt::UncertainIf {
+                condition: self.boxed_expr()?,
+                high_threshold: self.number()?,
+                medium_threshold: self.number()?,
+                then_branch: self.boxed_stmt()?,
+                medium_branch: self.option(Self::boxed_stmt)?,
+                low_branch: self.option(Self::boxed_stmt)?,
+            },
+            5 => Stmt::While { condition: self.boxed_expr()?, body: self.boxed_stmt()? },
+            6 => Stmt::For { name: self.string()?, iterable: self.boxed_expr()?, body: self.boxed_stmt()? },
+            7 => Stmt::Function {
+                name: self.string()?,
+                params: self.strings()?,
+                param_types: {
+                    let len = self.uint()?;
+                    (0..len).map(|_| self.option(Self::ty)).collect::<Result<_>>()?
+                },
+                return_type: self.option(Self::ty)?,
+                body: self.boxed_stmt()?,
+                is_async: self.flag()?,
+                confidence: self.option(Self::number)?,
+                captures: self.option(|r| {
+                    let len = r.uint()?;
+                    (0..len).map(|_| r.uint()).collect()
+                })?,
+            },
+            8 => Stmt::Return(self.option(Self::boxed_expr)?),
+            9 => Stmt::Yield(self.boxed_expr()?),
+            10 => Stmt::Context { name: self.string()?, body: self.boxed_stmt()? },
+            11 => Stmt::Import {
+                module: self.string()?,
+                imports: {
+                    let len = self.uint()?;
+                    (0..len).map(|_| Ok((self.string()?, self.option(Self::string)?))).collect::<Result<_>>()?
+                },
+                confidence: self.option(Self::number)?,
+            },
+            12 => Stmt::Export(self.string()?, self.boxed_stmt()?),
+            13 => Stmt::Module { name: self.string()?, body: self.stmts()?, confidence: self.option(Self::number)? },
+            14 => Stmt::ModuleAccess { module_name: self.string()?, name: self.string()? },
+            15 => Stmt::Cfg { predicate: self.predicate()?, body: self.boxed_stmt()? },
+            16 => Stmt::Break,
+            17 => Stmt::Continue,
+            tag => return Err(invalid(&format!("unknown statement {}", tag))),
+        })
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        Ok(match self.byte()? {
+            0 => Expr::Literal(self.value()?),
+            1 => Expr::Variable { name: self.string()?, line: self.uint()?, slot: self.slot()? },
+            2 => Expr::Assign { name: self.string()?, value: self.boxed_expr()?, slot: self.slot()? },
+            3 => Expr::Binary { left: self.boxed_expr()?, operator: self.token()?, right: self.boxed_expr()? },
+            4 => Expr::Logical { left: self.boxed_expr()?, operator: self.token()?, right: self.boxed_expr()? },
+            5 => Expr::Unary { operator: self.token()?, right: self.boxed_expr()? },
+            6 => Expr::Call { callee: self.boxed_expr()?, arguments: self.exprs()?, tail: self.flag()? },
+            7 => Expr::Get { object: self.boxed_expr()?, name: self.string()?, optional: self.flag()? },
+            8 => Expr::Confidence { expr: self.boxed_expr()?, confidence: self.number()? },
+            9 => Expr::ConfidenceCombine { left: self.boxed_expr()?, right: self.boxed_expr()? },
+            10 => Expr::InContext { context: self.string()?, body: self.boxed_expr()? },
+            11 => Expr::Grouping(self.boxed_expr()?),
+            12 => Expr::List(self.exprs()?),
+            13 => {
+                let len = self.uint()?;
+                Expr::Map((0..len).map(|_| Ok((self.expr()?, self.expr()?))).collect::<Result<_>>()?)
+            }
+            14 => Expr::ModuleAccess { module: self.string()?, name: self.string()? },
+            15 => {
+                let subject = self.boxed_expr()?;
+                let len = self.uint()?;
+                let arms = (0..len)
+                    .map(|_| Ok(MatchArm { pattern: self.pattern()?, guard: self.option(Self::expr)?, body: self.expr()? }))
+                    .collect::<Result<_>>()?;
+                Expr::Match { subject, arms }
+            }
+            tag => return Err(invalid(&format!("unknown expression {}", tag))),
+        })
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        let kind = match self.byte()? {
+            0 => ValueKind::Nil,
+            1 => ValueKind::Boolean(self.flag()?),
+            2 => ValueKind::Number(self.number()?),
+            3 => ValueKind::String(self.string()?),
+            4 => {
+                let len = self.uint()?;
+                ValueKind::List((0..len).map(|_| self.value()).collect::<Result<_>>()?)
+            }
+            5 => {
+                let len = self.uint()?;
+                ValueKind::Map((0..len).map(|_| Ok((self.value()?, self.value()?))).collect::<Result<_>>()?)
+            }
+            6 => ValueKind::Range { start: self.number()?, end: self.number()?, step: self.number()? },
+            8 => ValueKind::DateTime(crate::stdlib::time::parse(&self.string()?, None, None)?),
+            9 => ValueKind::Duration(crate::stdlib::time::parse_duration(&self.string()?)?),
+            #[cfg(feature = "decimal")]
+            7 => ValueKind::Decimal(crate::stdlib::decimal::parse(&self.string()?)?),
+            tag => return Err(invalid(&format!("unknown value {}", tag))),
+        };
+        let mut value = Value::with_confidence(kind, self.number()?);
+        value.context = self.option(Self::string)?;
+        Ok(value)
+    }
+
+    fn token(&mut self) -> Result<Token> {
+        let lexeme = self.string()?;
+        let line = self.uint()?;
+        let kind = Lexer::new(lexeme.as_str())
+            .scan_tokens()?
+            .into_iter()
+            .next()
+            .map(|token| token.kind)
+            .filter(|kind| *kind != TokenKind::EOF)
+            .ok_or_else(|| invalid("empty token"))?;
+        Ok(Token::new(kind, &lexeme, line))
+    }
+
+    fn slot(&mut self) -> Result<Option<Slot>> {
+        self.option(|r| match r.byte()? {
+            0 => Ok(Slot::Local(r.uint()?)),
+            1 => Ok(Slot::Captured { depth: r.uint()?, index: r.uint()? }),
+            tag => Err(invalid(&format!("unknown slot {}", tag))),
+        })
+    }
+
+    fn ty(&mut self) -> Result<Type> {
+        Ok(match self.byte()? {
+            0 => Type::Any,
+            1 => Type::Nil,
+            2 => Type::Boolean,
+            3 => Type::Number,
+            4 => Type::String,
+            5 => Type::List,
+            6 => Type::Map,
+            7 => Type::Function,
+            8 => Type::Named(self.string()?),
+            9 => {
+                let len = self.uint()?;
+                Type::Union((0..len).map(|_| self.ty()).collect::<Result<_>>()?)
+            }
+            tag => return Err(invalid(&format!("unknown type {}", tag))),
+        })
+    }
+
+    fn pattern(&mut self) -> Result<Pattern> {
+        Ok(match self.byte()? {
+            0 => Pattern::Literal(self.value()?),
+            1 => Pattern::Wildcard,
+            2 => Pattern::Binding(self.string()?),
+            3 => Pattern::Confidence(self.number()?),
+            tag => return Err(invalid(&format!("unknown pattern {}", tag))),
+        })
+    }
+
+    fn predicate(&mut self) -> Result<CfgPredicate> {
+        Ok(match self.byte()? {
+            0 => CfgPredicate::Flag(self.string()?),
+            1 => CfgPredicate::Value(self.string()?, self.string()?),
+            2 => CfgPredicate::Not(Box::new(self.predicate()?)),
+            tag @ (3 | 4) => {
+                let len = self.uint()?;
+                let items = (0..len).map(|_| self.predicate()).collect::<Result<_>>()?;
+                if tag == 3 { CfgPredicate::All(items) } else { CfgPredicate::Any(items) }
+            }
+            tag => return Err(invalid(&format!("unknown cfg predicate {}", tag))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    const SOURCE: &str = r#"
+        import { ratio as similarity } from "fuzzy";
+        @cfg(all("native", not(feature = "demo")))
+        fn triage(case: map, temp: number | nil) -> string ~> 0.8 {
+            let label: string? = match temp {
+                nil => "unknown",
+                t if t > 39 => "urgent",
+                ~> 0.5 => "likely",
+                _ => "routine",
+            };
+            uncertain if (case.score ~> 0.7) { return label; } medium { return "check"; } low { return nil; }
+        }
+        let xs = [1, -2.5, "text", true, { a: nil }];
+        for (x in xs) { while (!false and x != x) { x = x + 1; continue; } break; }
+        print(xs?.name);
+        fn later() async { yield triage(xs, 1) ~> 0.9; }
+    "#;
+
+    #[test]
+    fn test_programs_round_trip() {
+        let program = parse(SOURCE).unwrap();
+        let bytes = encode(&program).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), program);
+
+        assert!(decode(b"fn main() {}").unwrap_err().to_string().contains("not a precompiled Prism program"));
+        let err = decode(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert!(err.to_string().contains("unexpected end"), "{}", err);
+        let mut other = MAGIC.to_vec();
+        other.extend_from_slice(b"\x050.0.1\x00");
+        assert!(decode(&other).unwrap_err().to_string().contains("built by Prism 0.0.1"));
+    }
+
+    #[test]
+    fn test_load_prefers_a_fresh_build() {
+        let dir = std::env::temp_dir().join(format!("prism-precompile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("app.prism");
+        std::fs::write(&source, "let x = 1;").unwrap();
+        std::fs::write(compiled_path(&source), encode(&parse("let x = 2;").unwrap()).unwrap()).unwrap();
+        assert_eq!(load(&source).unwrap(), parse("let x = 2;").unwrap());
+
+        // Editing the source makes the build stale
+        let later = std::fs::metadata(compiled_path(&source)).unwrap().modified().unwrap() + std::time::Duration::from_secs(5);
+        std::fs::File::options().write(true).open(&source).unwrap().set_modified(later).unwrap();
+        assert_eq!(load(&source).unwrap(), parse("let x = 1;").unwrap());
+
+        std::fs::remove_file(&source).unwrap();
+        assert_eq!(load(&source).unwrap(), parse("let x = 2;").unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
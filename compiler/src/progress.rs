@@ -0,0 +1,122 @@
+use std::sync::Arc;
+use parking_lot::Mutex;
+
+/// State of the bar a script drives with `progress.start` and `progress.tick`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    pub current: u64,
+    pub total: u64,
+    pub message: Option<String>,
+    pub finished: bool,
+}
+
+/// Shows progress to a person. The CLI renders a bar on stderr; embedders
+/// that want their own display listen for `progress` events instead.
+pub trait ProgressSink: Send + Sync {
+    fn update(&self, progress: &Progress);
+}
+
+/// Draws a single-line bar on stderr when it is a terminal.
+#[cfg(feature = "native")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TerminalProgress;
+
+#[cfg(feature = "native")]
+impl ProgressSink for TerminalProgress {
+    fn update(&self, progress: &Progress) {
+        use std::io::{IsTerminal, Write};
+
+        let mut stderr = std::io::stderr().lock();
+        if !stderr.is_terminal() {
+            return;
+        }
+        let line = render(progress, 30);
+        let end = if progress.finished { "\n" } else { "" };
+        // Drawing is best effort, like script output
+        let _ = write!(stderr, "\r{}\x1b[K{}", line, end);
+        let _ = stderr.flush();
+    }
+}
+
+/// `[=======>      ] 7/20 message` with a bar `width` cells wide.
+pub fn render(progress: &Progress, width: usize) -> String {
+    let ratio = if progress.total == 0 { 1.0 } else { (progress.current as f64 / progress.total as f64).min(1.0) };
+    let filled = (ratio * width as f64).round() as usize;
+    let head = if filled < width && !progress.finished { ">" } else { "" };
+    let bar = format!("{}{}", "=".repeat(filled.saturating_sub(head.len())), head);
+    let mut line = format!("[{:<width$}] {}/{}", bar, progress.current, progress.total, width = width);
+    if let Some(message) = &progress.message {
+        line.push(' ');
+        line.push_str(message);
+    }
+    line
+}
+
+/// The current bar of an evaluation. Clones share it, so ticks from
+/// concurrent tasks advance the same bar.
+#[derive(Clone, Default)]
+pub struct ProgressTracker {
+    state: Arc<Mutex<Option<Progress>>>,
+    sink: Option<Arc<dyn ProgressSink>>,
+}
+
+impl ProgressTracker {
+    pub fn new(sink: Option<Arc<dyn ProgressSink>>) -> Self {
+        Self { state: Arc::default(), sink }
+    }
+
+    /// Replaces any running bar with a new one at zero.
+    pub fn start(&self, total: u64) -> Progress {
+        let progress = Progress { current: 0, total, message: None, finished: false };
+        self.update(progress)
+    }
+
+    /// Advances the running bar by one; `None` when no bar is running.
+    pub fn tick(&self, message: Option<String>) -> Option<Progress> {
+        let mut progress = self.state.lock().clone().filter(|progress| !progress.finished)?;
+        progress.current += 1;
+        progress.message = message.or(progress.message);
+        Some(self.update(progress))
+    }
+
+    /// Completes the running bar; `None` when no bar is running.
+    pub fn finish(&self) -> Option<Progress> {
+        let mut progress = self.state.lock().clone().filter(|progress| !progress.finished)?;
+        progress.finished = true;
+        Some(self.update(progress))
+    }
+
+    fn update(&self, progress: Progress) -> Progress {
+        *self.state.lock() = Some(progress.clone());
+        if let Some(sink) = &self.sink {
+            sink.update(&progress);
+        }
+        progress
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut progress = Progress { current: 1, total: 4, message: Some("row 1".to_string()), finished: false };
+        assert_eq!(render(&progress, 8), "[=>      ] 1/4 row 1");
+        progress.current = 4;
+        progress.finished = true;
+        progress.message = None;
+        assert_eq!(render(&progress, 8), "[========] 4/4");
+    }
+
+    #[test]
+    fn test_tracker_needs_a_running_bar() {
+        let tracker = ProgressTracker::new(None);
+        assert!(tracker.tick(None).is_none());
+        tracker.start(2);
+        assert_eq!(tracker.tick(Some("a".to_string())).unwrap().current, 1);
+        assert_eq!(tracker.tick(None).unwrap().message.as_deref(), Some("a"));
+        assert!(tracker.finish().unwrap().finished);
+        assert!(tracker.finish().is_none());
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use crate::ast::{Expr, Stmt};
+
+/// Whether `expr` can be evaluated without suspending.
+///
+/// Only calls can suspend — a native may be waiting on an LLM request — so
+/// call-free code runs on the interpreter's synchronous path and skips the
+/// boxed future that every async evaluation step needs.
+pub fn is_sync_expr(expr: &Expr) -> bool {
+    sync_expr(expr, &mut ())
+}
+
+/// Whether `stmt` can be executed without suspending; see [`is_sync_expr`].
+pub fn is_sync_stmt(stmt: &Stmt) -> bool {
+    sync_stmt(stmt, &mut ())
+}
+
+/// Which nodes of a tree are sync, worked out in one pass so the interpreter
+/// looks them up instead of walking each subtree again every time it runs.
+///
+/// Nodes are keyed by address, so the tree must stay where it is while the
+/// table is in use. Bodies of the functions declared in it are left out:
+/// each function value carries a table for its own body.
+#[derive(Debug, Default)]
+pub struct SyncNodes {
+    stmts: HashMap<usize, bool>,
+    exprs: HashMap<usize, bool>,
+}
+
+impl SyncNodes {
+    pub fn of(statements: &[Stmt]) -> Self {
+        let mut nodes = SyncNodes::default();
+        for stmt in statements {
+            sync_stmt(stmt, &mut nodes);
+        }
+        nodes
+    }
+
+    /// Whether `stmt` is sync, or `None` if it is not part of the tree.
+    pub fn stmt(&self, stmt: &Stmt) -> Option<bool> {
+        self.stmts.get(&(stmt as *const Stmt as usize)).copied()
+    }
+
+    /// Whether `expr` is sync, or `None` if it is not part of the tree.
+    pub fn expr(&self, expr: &Expr) -> Option<bool> {
+        self.exprs.get(&(expr as *const Expr as usize)).copied()
+    }
+}
+
+trait Record {
+    fn stmt(&mut self, stmt: &Stmt, sync: bool);
+    fn expr(&mut self, expr: &Expr, sync: bool);
+}
+
+impl Record for () {
+    fn stmt(&mut self, _: &Stmt, _: bool) {}
+    fn expr(&mut self, _: &Expr, _: bool) {}
+}
+
+impl Record for SyncNodes {
+    fn stmt(&mut self, stmt: &Stmt, sync: bool) {
+        self.stmts.insert(stmt as *const Stmt as usize, sync);
+    }
+
+    fn expr(&mut self, expr: &Expr, sync: bool) {
+        self.exprs.insert(expr as *const Expr as usize, sync);
+    }
+}
+
+// Children are visited even once the answer is known, so that a recording
+// pass sees every node; `&` rather than `&&` keeps that from short-circuiting.
+fn sync_expr<R: Record>(expr: &Expr, record: &mut R) -> bool {
+    let all = |exprs: &mut dyn Iterator<Item = &Expr>, record: &mut R| {
+        exprs.fold(true, |sync, expr| sync_expr(expr, record) & sync)
+    };
+    let sync = match expr {
+        Expr::Call { callee, arguments, .. } => {
+            sync_expr(callee, record);
+            all(&mut arguments.iter(), record);
+            false
+        }
+        Expr::Literal(_) | Expr::Variable { .. } | Expr::ModuleAccess { .. } => true,
+        Expr::Assign { value, .. } => sync_expr(value, record),
+        Expr::Binary { left, right, .. }
+        | Expr::Logical { left, right, .. }
+        | Expr::ConfidenceCombine { left, right } => sync_expr(left, record) & sync_expr(right, record),
+        Expr::Unary { right, .. } => sync_expr(right, record),
+        Expr::Get { object, .. } => sync_expr(object, record),
+        Expr::Confidence { expr, .. } | Expr::Grouping(expr) => sync_expr(expr, record),
+        Expr::InContext { body, .. } => sync_expr(body, record),
+        Expr::List(items) => all(&mut items.iter(), record),
+        Expr::Map(entries) => all(&mut entries.iter().flat_map(|(key, value)| [key, value]), record),
+        Expr::Match { subject, arms } => {
+            let arms = &mut arms.iter().flat_map(|arm| arm.guard.iter().chain([&arm.body]));
+            sync_expr(subject, record) & all(arms, record)
+        }
+    };
+    record.expr(expr, sync);
+    sync
+}
+
+fn sync_stmt<R: Record>(stmt: &Stmt, record: &mut R) -> bool {
+    let optional = |stmt: &Option<Box<Stmt>>, record: &mut R| stmt.as_deref().is_none_or(|stmt| sync_stmt(stmt, record));
+    let sync = match stmt {
+        Stmt::Expression(expr) => sync_expr(expr, record),
+        Stmt::Let { initializer, .. } => initializer.as_deref().is_none_or(|expr| sync_expr(expr, record)),
+        Stmt::Block(statements) => statements.iter().fold(true, |sync, stmt| sync_stmt(stmt, record) & sync),
+        Stmt::If { condition, then_branch, else_branch } => {
+            sync_expr(condition, record) & sync_stmt(then_branch, record) & optional(else_branch, record)
+        }
+        Stmt::UncertainIf { condition, then_branch, medium_branch, low_branch, .. } => {
+            sync_expr(condition, record)
+                & sync_stmt(then_branch, record)
+                & optional(medium_branch, record)
+                & optional(low_branch, record)
+        }
+        // Declaring a function only captures its body; calling it is async.
+        Stmt::Function { .. } => true,
+        Stmt::Return(value) => value.as_deref().is_none_or(|expr| sync_expr(expr, record)),
+        Stmt::Break | Stmt::Continue => true,
+        Stmt::Cfg { body, .. } => sync_stmt(body, record),
+        Stmt::While { condition, body } => {
+            sync_expr(condition, record);
+            sync_stmt(body, record);
+            false
+        }
+        Stmt::For { iterable, body, .. } => {
+            sync_expr(iterable, record);
+            sync_stmt(body, record);
+            false
+        }
+        Stmt::Context { body, .. } => {
+            sync_stmt(body, record);
+            false
+        }
+        Stmt::Yield(value) => {
+            sync_expr(value, record);
+            false
+        }
+        Stmt::Export(_, body) => {
+            sync_stmt(body, record);
+            false
+        }
+        Stmt::Module { body, .. } => {
+            body.iter().for_each(|stmt| {
+                sync_stmt(stmt, record);
+            });
+            false
+        }
+        Stmt::Import { .. } | Stmt::ModuleAccess { .. } => false,
+    };
+    record.stmt(stmt, sync);
+    sync
+}
+
+/// Whether a function with this body is a generator: it yields outside of
+/// the functions nested in it.
+pub fn yields(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Yield(_) => true,
+        Stmt::Block(statements) => statements.iter().any(yields),
+        Stmt::If { then_branch, else_branch, .. } => {
+            yields(then_branch) || else_branch.as_deref().is_some_and(yields)
+        }
+        Stmt::UncertainIf { then_branch, medium_branch, low_branch, .. } => {
+            yields(then_branch)
+                || medium_branch.as_deref().is_some_and(yields)
+                || low_branch.as_deref().is_some_and(yields)
+        }
+        Stmt::While { body, .. }
+        | Stmt::For { body, .. }
+        | Stmt::Context { body, .. }
+        | Stmt::Cfg { body, .. } => yields(body),
+        Stmt::Expression(_)
+        | Stmt::Let { .. }
+        | Stmt::Function { .. }
+        | Stmt::Return(_)
+        | Stmt::Break
+        | Stmt::Continue
+        | Stmt::Import { .. }
+        | Stmt::Export(..)
+        | Stmt::Module { .. }
+        | Stmt::ModuleAccess { .. } => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn first(source: &str) -> Stmt {
+        parse(source).unwrap().remove(0)
+    }
+
+    #[test]
+    fn test_call_free_code_is_sync() {
+        assert!(is_sync_stmt(&first("let x = [1, 2 + 3, { a: 4 }];")));
+        assert!(is_sync_stmt(&first("if (true) { let y = 1; } else { 2; }")));
+        assert!(is_sync_stmt(&first("fn f() { return g(); }")));
+    }
+
+    #[test]
+    fn test_generators_yield_outside_nested_functions() {
+        let body = |source: &str| match first(source) {
+            Stmt::Function { body, .. } => body,
+            _ => unreachable!(),
+        };
+        assert!(yields(&body("fn gen() { for (x in xs) { if (x) { yield x; } } }")));
+        assert!(!yields(&body("fn outer() { fn inner() { yield 1; } return inner; }")));
+    }
+
+    #[test]
+    fn test_sync_nodes_cover_the_tree_but_not_function_bodies() {
+        let program = parse("while (more()) { let y = 2; g(y); } fn f() { let z = 1; }").unwrap();
+        let nodes = SyncNodes::of(&program);
+        assert_eq!(nodes.stmt(&program[0]), Some(false));
+        let Stmt::While { condition, body } = &program[0] else { unreachable!() };
+        assert_eq!(nodes.expr(condition), Some(false));
+        let Stmt::Block(statements) = &**body else { unreachable!() };
+        assert_eq!(nodes.stmt(&statements[0]), Some(true));
+        assert_eq!(nodes.stmt(&statements[1]), Some(false));
+        let Stmt::Function { body, .. } = &program[1] else { unreachable!() };
+        assert_eq!(nodes.stmt(&program[1]), Some(true));
+        assert_eq!(nodes.stmt(body), None);
+    }
+
+    #[test]
+    fn test_calls_are_async() {
+        assert!(!is_sync_stmt(&first("let x = 1 + f(2);")));
+        assert!(!is_sync_stmt(&first("if (true) { f(); }")));
+        assert!(!is_sync_stmt(&first("match 1 { n if check(n) => 1, _ => 0 };")));
+    }
+}
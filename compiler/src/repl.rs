@@ -1,27 +1,96 @@
 #[cfg(feature = "native")]
-use rustyline::DefaultEditor;
+use std::borrow::Cow;
+#[cfg(feature = "native")]
+use colored::Colorize;
+#[cfg(feature = "native")]
+use rustyline::{Editor, Helper, completion::Completer, highlight::Highlighter, hint::Hinter, validate::Validator};
 #[cfg(feature = "native")]
 use rustyline::error::ReadlineError;
 #[cfg(feature = "native")]
+use rustyline::history::DefaultHistory;
+#[cfg(feature = "native")]
+use crate::highlight::{highlight, TokenClass};
+#[cfg(feature = "native")]
+use crate::docs;
+#[cfg(feature = "native")]
 use crate::interpreter::Interpreter;
+#[cfg(feature = "native")]
+use crate::progress::TerminalProgress;
+#[cfg(feature = "native")]
+use std::sync::Arc;
 use crate::error::{Result, PrismError};
 #[cfg(feature = "native")]
 use crate::value::Value;
+#[cfg(feature = "native")]
+use crate::diagnostics::Diagnostic;
 
 #[cfg(feature = "native")]
 pub struct Repl {
     interpreter: Interpreter,
-    editor: DefaultEditor,
+    editor: Editor<PrismHelper, DefaultHistory>,
+}
+
+/// Colors input as it is typed.
+#[cfg(feature = "native")]
+struct PrismHelper;
+
+#[cfg(feature = "native")]
+impl Highlighter for PrismHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        let mut painted = String::with_capacity(line.len());
+        let mut end = 0;
+        for (span, class) in highlight(line) {
+            painted.push_str(&line[end..span.start]);
+            let text = &line[span.clone()];
+            let text = match class {
+                TokenClass::Keyword => text.magenta(),
+                TokenClass::Confidence => text.yellow().bold(),
+                TokenClass::Context => text.cyan(),
+                TokenClass::String => text.green(),
+                TokenClass::Number => text.yellow(),
+                TokenClass::Operator => text.normal(),
+                TokenClass::Comment => text.dimmed(),
+                TokenClass::Invalid => text.red(),
+            };
+            painted.push_str(&text.to_string());
+            end = span.end;
+        }
+        painted.push_str(&line[end..]);
+        Cow::Owned(painted)
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        true
+    }
+}
+
+#[cfg(feature = "native")]
+impl Completer for PrismHelper {
+    type Candidate = String;
+}
+
+#[cfg(feature = "native")]
+impl Hinter for PrismHelper {
+    type Hint = String;
 }
 
+#[cfg(feature = "native")]
+impl Validator for PrismHelper {}
+
+#[cfg(feature = "native")]
+impl Helper for PrismHelper {}
+
 #[cfg(feature = "native")]
 impl Repl {
     pub fn new() -> Result<Self> {
-        let mut editor = DefaultEditor::new().map_err(|e| PrismError::RuntimeError(e.to_string()))?;
+        let mut editor = Editor::new().map_err(|e| PrismError::RuntimeError(e.to_string()))?;
+        editor.set_helper(Some(PrismHelper));
         editor.load_history("history.txt").ok(); // Don't fail if no history
 
         Ok(Self {
-            interpreter: Interpreter::new(),
+            interpreter: Interpreter::new()
+                .with_capabilities(crate::config::global().granted())
+                .with_progress(Arc::new(TerminalProgress)),
             editor,
         })
     }
@@ -38,10 +107,11 @@ impl Repl {
                     match line.trim() {
                         "exit" | "quit" => break,
                         "help" => self.print_help(),
+                        input if input.starts_with(":doc ") => self.print_doc(input[":doc ".len()..].trim()).await,
                         input => {
                             match self.eval(input).await {
-                                Ok(value) => println!("{:?}", value),
-                                Err(e) => eprintln!("Error: {}", e),
+                                Ok(value) => println!("{}", self.interpreter.secrets().redact(&format!("{:?}", value))),
+                                Err(e) => eprintln!("{}", Diagnostic::from_error(&e)),
                             }
                         }
                     }
@@ -69,11 +139,24 @@ impl Repl {
         self.interpreter.evaluate(input.to_string()).await
     }
 
+    /// `:doc name` shows how to call a function and what it does.
+    async fn print_doc(&mut self, name: &str) {
+        let value = match self.eval(name).await {
+            Ok(value) => value,
+            Err(e) => return eprintln!("Error: {}", e),
+        };
+        match docs::describe(&self.interpreter.global_values(), &value) {
+            Some(description) => println!("{}", description),
+            None => println!("{} is not a function", name),
+        }
+    }
+
     fn print_help(&self) {
         println!("Available commands:");
         println!("  help     - Show this help message");
         println!("  exit     - Exit the REPL");
         println!("  quit     - Exit the REPL");
+        println!("  :doc f   - Show the signature and documentation of f");
         println!("\nExample expressions:");
         println!("  42                     - Number literal");
         println!("  \"Hello\"                - String literal");
@@ -0,0 +1,481 @@
+use std::collections::{HashMap, HashSet};
+use crate::ast::{Expr, Pattern, Slot, Stmt};
+use crate::diagnostics::Diagnostic;
+
+/// Binds every variable reference to the scope that declares it.
+///
+/// Locals get a [`Slot`] so the interpreter can index straight into the
+/// frame of the running call, or into the scopes a closure captured;
+/// globals stay name-based because the REPL, imports and natives add them
+/// at runtime. Each function records which locals of its defining frame it
+/// closes over. `globals` are the names already defined
+/// before this program runs. Undefined names, duplicate declarations in a
+/// local scope and locals read in their own initializer are reported as errors.
+/// `return f(...)` inside a function is marked as a tail call.
+pub fn resolve(statements: &mut [Stmt], globals: impl IntoIterator<Item = String>) -> Vec<Diagnostic> {
+    let mut resolver = Resolver::new(globals.into_iter().collect());
+    resolver.resolve_program(statements);
+    resolver.diagnostics
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    // name -> (position in the scope, initializer finished)
+    names: HashMap<String, (usize, bool)>,
+    /// Where the scope's locals start in the frame.
+    start: usize,
+}
+
+impl Scope {
+    fn declare(&mut self, name: &str) -> Option<usize> {
+        if self.names.contains_key(name) {
+            return None;
+        }
+        let index = self.names.len();
+        self.names.insert(name.to_string(), (index, false));
+        Some(index)
+    }
+}
+
+/// A function being resolved, or the top-level code.
+#[derive(Debug, Default)]
+struct Function {
+    /// Where the function's scopes start in `Resolver::scopes`; the first
+    /// holds its parameters.
+    base: usize,
+    /// The locals of the defining frame it closes over, once it uses any
+    /// local of an enclosing function.
+    captures: Option<Vec<usize>>,
+}
+
+struct Resolver {
+    /// The scopes around the code being resolved, across functions.
+    scopes: Vec<Scope>,
+    /// The functions around the code being resolved, top-level code first.
+    functions: Vec<Function>,
+    /// Globals defined so far by top-level code.
+    globals: HashSet<String>,
+    /// Every global the program declares; function bodies may refer to
+    /// globals declared after them since they only run once called.
+    hoisted: HashSet<String>,
+    /// Loops around the code being resolved, within the innermost function.
+    loop_depth: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolver {
+    fn new(globals: HashSet<String>) -> Self {
+        Self {
+            scopes: Vec::new(),
+            functions: vec![Function::default()],
+            globals,
+            hoisted: HashSet::new(),
+            loop_depth: 0,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn resolve_program(&mut self, statements: &mut [Stmt]) {
+        for stmt in statements.iter() {
+            self.hoisted.extend(declared_names(stmt));
+        }
+        for stmt in statements.iter_mut() {
+            self.resolve_stmt(stmt);
+        }
+    }
+
+    fn resolve_statements(&mut self, statements: &mut [Stmt]) {
+        for stmt in statements {
+            self.resolve_stmt(stmt);
+        }
+    }
+
+    fn resolve_stmt(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Expression(expr) => self.resolve_expr(expr),
+            Stmt::Let { name, initializer, .. } => {
+                if self.scopes.is_empty() {
+                    // A global only exists once its initializer has run.
+                    if let Some(init) = initializer {
+                        self.resolve_expr(init);
+                    }
+                    self.declare(name);
+                } else {
+                    self.declare(name);
+                    if let Some(init) = initializer {
+                        self.resolve_expr(init);
+                    }
+                    self.define(name);
+                }
+            }
+            Stmt::Block(statements) => {
+                self.begin_scope();
+                self.resolve_statements(statements);
+                self.scopes.pop();
+            }
+            Stmt::If { condition, then_branch, else_branch } => {
+                self.resolve_expr(condition);
+                self.resolve_stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_stmt(else_branch);
+                }
+            }
+            Stmt::UncertainIf { condition, then_branch, medium_branch, low_branch, .. } => {
+                self.resolve_expr(condition);
+                self.resolve_stmt(then_branch);
+                for branch in [medium_branch, low_branch].into_iter().flatten() {
+                    self.resolve_stmt(branch);
+                }
+            }
+            Stmt::While { condition, body } => {
+                self.resolve_expr(condition);
+                self.loop_depth += 1;
+                self.resolve_stmt(body);
+                self.loop_depth -= 1;
+            }
+            Stmt::For { name, iterable, body } => {
+                self.resolve_expr(iterable);
+                // The loop variable gets a scope of its own around the body,
+                // matching the fresh scope each iteration runs in.
+                self.begin_scope();
+                self.declare(name);
+                self.define(name);
+                self.loop_depth += 1;
+                self.resolve_stmt(body);
+                self.loop_depth -= 1;
+                self.scopes.pop();
+            }
+            Stmt::Function { name, params, body, captures, .. } => {
+                // Declared before the body so the function can call itself.
+                self.declare(name);
+                self.define(name);
+
+                self.functions.push(Function { base: self.scopes.len(), captures: None });
+                // `break` cannot leave a loop from inside a function called in it.
+                let loop_depth = std::mem::take(&mut self.loop_depth);
+                self.begin_scope();
+                for param in params.iter() {
+                    self.declare(param);
+                    self.define(param);
+                }
+                self.resolve_stmt(body);
+                self.scopes.pop();
+                self.loop_depth = loop_depth;
+                *captures = self.functions.pop().and_then(|function| function.captures);
+            }
+            Stmt::Return(value) => {
+                if !self.in_function() {
+                    self.error("Cannot return from top-level code".to_string(), None);
+                }
+                if let Some(value) = value {
+                    self.resolve_expr(value);
+                    // Nothing is left to do in this frame after the call, so
+                    // the interpreter can reuse it instead of nesting a new one.
+                    if let Expr::Call { tail, .. } = value.as_mut() {
+                        *tail = self.in_function();
+                    }
+                }
+            }
+            Stmt::Break | Stmt::Continue => {
+                if self.loop_depth == 0 {
+                    let keyword = if matches!(stmt, Stmt::Break) { "break" } else { "continue" };
+                    self.error(format!("Cannot {} outside of a loop", keyword), None);
+                }
+            }
+            Stmt::Yield(value) => {
+                if !self.in_function() {
+                    self.error("Cannot yield from top-level code".to_string(), None);
+                }
+                self.resolve_expr(value);
+            }
+            Stmt::Context { body, .. } => self.resolve_stmt(body),
+            Stmt::Export(_, stmt) | Stmt::Cfg { body: stmt, .. } => self.resolve_stmt(stmt),
+            Stmt::Import { imports, .. } => {
+                for (name, alias) in imports.iter() {
+                    let name = alias.as_ref().unwrap_or(name);
+                    self.declare(name);
+                    self.define(name);
+                }
+            }
+            Stmt::Module { body, .. } => {
+                // A module body is its own top level: its names are globals
+                // of the module, not locals of the enclosing scope.
+                let mut module = Resolver::new(self.visible_globals());
+                module.resolve_program(body);
+                self.diagnostics.append(&mut module.diagnostics);
+            }
+            Stmt::ModuleAccess { .. } => {}
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Variable { name, line, slot } => {
+                if let Some(scope) = self.scopes.last() {
+                    if let Some((_, false)) = scope.names.get(name.as_str()) {
+                        self.error(
+                            format!("Cannot read local variable `{}` in its own initializer", name),
+                            Some(*line),
+                        );
+                    }
+                }
+                *slot = self.lookup(name, Some(*line));
+            }
+            Expr::Assign { name, value, slot } => {
+                self.resolve_expr(value);
+                *slot = self.lookup(name, None);
+            }
+            Expr::Binary { left, right, .. }
+            | Expr::Logical { left, right, .. }
+            | Expr::ConfidenceCombine { left, right } => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            Expr::Unary { right, .. } => self.resolve_expr(right),
+            Expr::Call { callee, arguments, .. } => {
+                self.resolve_expr(callee);
+                for argument in arguments {
+                    self.resolve_expr(argument);
+                }
+            }
+            Expr::Get { object, .. } => self.resolve_expr(object),
+            Expr::Confidence { expr, .. } | Expr::Grouping(expr) => self.resolve_expr(expr),
+            Expr::InContext { body, .. } => self.resolve_expr(body),
+            Expr::List(items) => {
+                for item in items {
+                    self.resolve_expr(item);
+                }
+            }
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            }
+            Expr::Match { subject, arms } => {
+                self.resolve_expr(subject);
+                for arm in arms {
+                    // Only binding arms get a scope, matching the interpreter.
+                    let binding = match &arm.pattern {
+                        Pattern::Binding(name) => Some(name.clone()),
+                        _ => None,
+                    };
+                    if let Some(name) = &binding {
+                        self.begin_scope();
+                        self.declare(name);
+                        self.define(name);
+                    }
+                    if let Some(guard) = &mut arm.guard {
+                        self.resolve_expr(guard);
+                    }
+                    self.resolve_expr(&mut arm.body);
+                    if binding.is_some() {
+                        self.scopes.pop();
+                    }
+                }
+            }
+            Expr::Literal(_) | Expr::ModuleAccess { .. } => {}
+        }
+    }
+
+    fn in_function(&self) -> bool {
+        self.functions.len() > 1
+    }
+
+    /// Opens a scope whose locals follow those of the scopes around it in
+    /// the same frame.
+    fn begin_scope(&mut self) {
+        let base = self.functions.last().map_or(0, |function| function.base);
+        let start = match self.scopes.last() {
+            Some(scope) if self.scopes.len() > base => scope.start + scope.names.len(),
+            _ => 0,
+        };
+        self.scopes.push(Scope { names: HashMap::new(), start });
+    }
+
+    fn declare(&mut self, name: &str) {
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                if scope.declare(name).is_none() {
+                    self.error(format!("`{}` is already declared in this scope", name), None);
+                }
+            }
+            // Globals may be redeclared, e.g. `let n = n + 1;` at the top level.
+            None => {
+                self.globals.insert(name.to_string());
+            }
+        }
+    }
+
+    fn define(&mut self, name: &str) {
+        if let Some(entry) = self.scopes.last_mut().and_then(|scope| scope.names.get_mut(name)) {
+            entry.1 = true;
+        }
+    }
+
+    fn lookup(&mut self, name: &str, line: Option<usize>) -> Option<Slot> {
+        for (position, scope) in self.scopes.iter().enumerate().rev() {
+            let Some(&(index, _)) = scope.names.get(name) else { continue };
+            let current = self.functions.len() - 1;
+            let owner = self.functions.iter().rposition(|function| function.base <= position).unwrap_or(0);
+            if owner == current {
+                return Some(Slot::Local(scope.start + index));
+            }
+            // Every function from the one defined in the owner's frame
+            // inwards closes over the local; the outermost of them captures it
+            for function in &mut self.functions[owner + 1..] {
+                function.captures.get_or_insert_with(Vec::new);
+            }
+            let captures = self.functions[owner + 1].captures.get_or_insert_with(Vec::new);
+            if !captures.contains(&(scope.start + index)) {
+                captures.push(scope.start + index);
+            }
+            // Counted from the scope the running function was defined in
+            let defined_in = self.functions[current].base - 1;
+            return Some(Slot::Captured { depth: defined_in - position, index });
+        }
+
+        let known = self.globals.contains(name)
+            || (self.in_function() && self.hoisted.contains(name));
+        if !known {
+            self.error(format!("Undefined variable `{}`", name), line);
+        }
+        None
+    }
+
+    fn visible_globals(&self) -> HashSet<String> {
+        self.globals.union(&self.hoisted).cloned().collect()
+    }
+
+    fn error(&mut self, message: String, line: Option<usize>) {
+        self.diagnostics.push(Diagnostic::error(message).at_line(line));
+    }
+}
+
+/// Names a top-level statement adds to the global scope.
+fn declared_names(stmt: &Stmt) -> Vec<String> {
+    match stmt {
+        Stmt::Let { name, .. } | Stmt::Function { name, .. } => vec![name.clone()],
+        Stmt::Import { imports, .. } => imports
+            .iter()
+            .map(|(name, alias)| alias.clone().unwrap_or_else(|| name.clone()))
+            .collect(),
+        Stmt::Export(_, stmt) | Stmt::Cfg { body: stmt, .. } => declared_names(stmt),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn resolved(source: &str) -> (Vec<Stmt>, Vec<Diagnostic>) {
+        let mut statements = parse(source).unwrap();
+        let diagnostics = resolve(&mut statements, Vec::new());
+        (statements, diagnostics)
+    }
+
+    fn messages(source: &str) -> Vec<String> {
+        resolved(source).1.into_iter().map(|d| d.message).collect()
+    }
+
+    #[test]
+    fn test_undefined_variable_is_reported() {
+        let found = messages("let total = 1; totl + 1;");
+        assert_eq!(found, vec!["Undefined variable `totl`".to_string()]);
+        assert_eq!(messages("missing = 2;").len(), 1);
+    }
+
+    #[test]
+    fn test_globals_used_before_declaration() {
+        assert_eq!(messages("x + 1; let x = 1;").len(), 1);
+        assert_eq!(messages("let y = y;").len(), 1);
+        // A function body only runs once called, so later globals are fine.
+        assert!(messages("fn f() { return later; } let later = 1;").is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_declarations() {
+        assert_eq!(messages("{ let a = 1; let a = 2; }").len(), 1);
+        assert!(messages("let a = 1; let a = a + 1;").is_empty());
+        assert_eq!(messages("fn f(a, a) { }").len(), 1);
+        // Shadowing in a nested scope is allowed.
+        assert!(messages("let a = 1; { let a = 2; a; }").is_empty());
+    }
+
+    #[test]
+    fn test_own_initializer() {
+        let found = messages("let a = 1; { let a = a + 1; }");
+        assert_eq!(found, vec!["Cannot read local variable `a` in its own initializer".to_string()]);
+    }
+
+    #[test]
+    fn test_locals_get_slots() {
+        let (statements, diagnostics) = resolved("let g = 1; { let a = 1; let b = 2; { b + g; } }");
+        assert!(diagnostics.is_empty());
+
+        let Stmt::Block(outer) = &statements[1] else { panic!("expected block") };
+        let Stmt::Block(inner) = &outer[2] else { panic!("expected block") };
+        let Stmt::Expression(expr) = &inner[0] else { panic!("expected expression") };
+        let Expr::Binary { left, right, .. } = expr.as_ref() else { panic!("expected binary") };
+        assert!(matches!(left.as_ref(), Expr::Variable { slot: Some(Slot::Local(1)), .. }));
+        assert!(matches!(right.as_ref(), Expr::Variable { slot: None, .. }));
+    }
+
+    #[test]
+    fn test_functions_record_what_they_capture() {
+        let source = "fn outer(a) { let b = a; fn inner() { return b; } fn pure(c) { return c; } return inner; }";
+        let (statements, diagnostics) = resolved(source);
+        assert!(diagnostics.is_empty());
+
+        let Stmt::Function { body, captures: None, .. } = &statements[0] else { panic!("expected function") };
+        let Stmt::Block(body) = body.as_ref() else { panic!("expected block") };
+        let Stmt::Function { body: inner, captures: Some(captures), .. } = &body[1] else { panic!("expected closure") };
+        assert_eq!(captures, &vec![1]);
+        assert!(matches!(&body[2], Stmt::Function { captures: None, .. }));
+
+        let Stmt::Block(inner) = inner.as_ref() else { panic!("expected block") };
+        let Stmt::Return(Some(value)) = &inner[0] else { panic!("expected return") };
+        assert!(matches!(value.as_ref(), Expr::Variable { slot: Some(Slot::Captured { depth: 0, index: 0 }), .. }));
+    }
+
+    #[test]
+    fn test_known_globals_and_match_bindings() {
+        let mut statements = parse("print(1); match 2 { n if n > 1 => n, _ => 0 };").unwrap();
+        let diagnostics = resolve(&mut statements, vec!["print".to_string()]);
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_tail_calls_are_marked() {
+        let (statements, diagnostics) = resolved("fn f(n) { if (n > 0) { return f(n - 1); } return 1 + f(0); }");
+        assert!(diagnostics.is_empty());
+
+        let mut tails = Vec::new();
+        fn collect(stmt: &Stmt, tails: &mut Vec<bool>) {
+            match stmt {
+                Stmt::Function { body, .. } => collect(body, tails),
+                Stmt::Block(statements) => statements.iter().for_each(|s| collect(s, tails)),
+                Stmt::If { then_branch, .. } => collect(then_branch, tails),
+                Stmt::Return(Some(value)) => tails.push(matches!(value.as_ref(), Expr::Call { tail: true, .. })),
+                _ => {}
+            }
+        }
+        collect(&statements[0], &mut tails);
+        assert_eq!(tails, vec![true, false]);
+    }
+
+    #[test]
+    fn test_top_level_return() {
+        assert_eq!(messages("return 1;").len(), 1);
+    }
+
+    #[test]
+    fn test_break_and_continue_need_a_loop() {
+        assert!(messages("while (true) { if (true) { break; } continue; }").is_empty());
+        assert_eq!(messages("break;"), ["Cannot break outside of a loop"]);
+        // A function called in a loop cannot leave it.
+        assert_eq!(messages("for (x in [1]) { fn f() { continue; } }"), ["Cannot continue outside of a loop"]);
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use serde::Serialize;
+use crate::audit::{AuditEvent, AuditLog};
+use crate::error::Result;
+use crate::interpreter::Interpreter;
+use crate::secrets::Secrets;
+use crate::value::Value;
+
+/// Machine-readable summary of one evaluation, written by
+/// `prism run --report` so CI can gate on confidence or cost.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunReport {
+    pub success: bool,
+    /// The final value as JSON, or its display form when it is not data.
+    pub value: Option<serde_json::Value>,
+    pub confidence: Option<f64>,
+    pub error: Option<String>,
+    pub duration_ms: f64,
+    /// Whether LLM calls were only estimated, making `llm` a worst case.
+    pub dry_run: bool,
+    pub llm: LlmSummary,
+    pub warnings: Vec<String>,
+    pub audit: AuditSummary,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LlmSummary {
+    pub calls: Vec<LlmCall>,
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+    /// Estimated USD cost of the calls with a known price.
+    pub cost: f64,
+    /// Calls to models without a known price, left out of `cost`.
+    pub unpriced_calls: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LlmCall {
+    pub model: String,
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+    pub cost: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AuditSummary {
+    pub events: usize,
+    /// How often each native function was called.
+    pub native_calls: BTreeMap<String, usize>,
+    pub prompts: usize,
+    pub cancelled: Option<String>,
+}
+
+impl RunReport {
+    /// Reports `result`, which `interpreter` just produced in `duration`.
+    pub fn new(interpreter: &Interpreter, result: &Result<Value>, duration: Duration) -> Self {
+        let (value, confidence, error) = match result {
+            Ok(value) => {
+                let json = value.to_json().unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+                (Some(redact(interpreter.secrets(), json)), Some(value.confidence), None)
+            }
+            Err(err) => (None, None, Some(interpreter.secrets().redact(&err.to_string()).into_owned())),
+        };
+        RunReport {
+            success: result.is_ok(),
+            value,
+            confidence,
+            error,
+            duration_ms: duration.as_secs_f64() * 1000.0,
+            dry_run: interpreter.dry_run(),
+            llm: LlmSummary::of(interpreter.audit_log()),
+            warnings: interpreter
+                .diagnostics()
+                .iter()
+                .filter(|diagnostic| !diagnostic.is_error())
+                .map(|diagnostic| interpreter.secrets().redact(&diagnostic.to_string()).into_owned())
+                .collect(),
+            audit: AuditSummary::of(interpreter.audit_log()),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// `json` with secrets masked in its strings and keys, since reports are
+/// kept as CI artifacts.
+fn redact(secrets: &Secrets, json: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value as Json;
+    match json {
+        Json::String(text) => Json::String(secrets.redact(&text).into_owned()),
+        Json::Array(items) => Json::Array(items.into_iter().map(|item| redact(secrets, item)).collect()),
+        Json::Object(entries) => Json::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| (secrets.redact(&key).into_owned(), redact(secrets, value)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+impl LlmSummary {
+    fn of(audit: &AuditLog) -> Self {
+        let mut summary = LlmSummary::default();
+        for (model, input_tokens, output_tokens) in audit.llm_requests() {
+            let cost = crate::llm::cost(model, input_tokens, output_tokens);
+            summary.input_tokens += input_tokens;
+            summary.output_tokens += output_tokens;
+            match cost {
+                Some(cost) => summary.cost += cost,
+                None => summary.unpriced_calls += 1,
+            }
+            summary.calls.push(LlmCall { model: model.to_string(), input_tokens, output_tokens, cost });
+        }
+        summary
+    }
+}
+
+impl AuditSummary {
+    fn of(audit: &AuditLog) -> Self {
+        let mut summary = AuditSummary { events: audit.len(), ..AuditSummary::default() };
+        for entry in audit.entries() {
+            match &entry.event {
+                AuditEvent::NativeCall { name } => *summary.native_calls.entry(name.clone()).or_default() += 1,
+                AuditEvent::Prompt { .. } => summary.prompts += 1,
+                AuditEvent::Cancelled { reason } => summary.cancelled = Some(reason.clone()),
+                AuditEvent::LlmRequest { .. } | AuditEvent::Conversation { .. } => {}
+            }
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_report_of_a_run() {
+        let mut interpreter = Interpreter::new().with_llm_client(crate::llm::testing::echo_client());
+        let result = interpreter.evaluate(r#"let answer = llm.chat_completion("Is it flu?"); 0.7 ~> 0.9;"#.to_string()).await;
+        let report = RunReport::new(&interpreter, &result, Duration::from_millis(5));
+        assert!(report.success);
+        assert_eq!(report.value, Some(serde_json::json!(0.7)));
+        assert_eq!(report.confidence, Some(0.9));
+        assert_eq!(report.duration_ms, 5.0);
+        assert_eq!(report.llm.calls.len(), 1);
+        assert!(report.llm.cost > 0.0);
+        assert_eq!(report.audit.native_calls["chat_completion"], 1);
+
+        let result = interpreter.evaluate("missing();".to_string()).await;
+        let report = RunReport::new(&interpreter, &result, Duration::ZERO);
+        assert!(!report.success && report.value.is_none());
+        assert!(report.error.unwrap().contains("missing"));
+    }
+
+    #[tokio::test]
+    async fn test_secrets_are_masked_in_the_value() {
+        let mut interpreter = Interpreter::new();
+        interpreter.secrets().register("sk-report-123");
+        let result = interpreter.evaluate(r#"let k = "sk-report-123"; let m = {"key": k}; [k, m];"#.to_string()).await;
+        let report = RunReport::new(&interpreter, &result, Duration::ZERO);
+        let json = report.to_json().unwrap();
+        assert!(!json.contains("sk-report-123"), "{}", json);
+        assert_eq!(report.value, Some(serde_json::json!(["[REDACTED]", { "key": "[REDACTED]" }])));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_estimates_the_worst_case() {
+        let mut interpreter = Interpreter::new().with_dry_run(true);
+        let source = r#"let answer = llm.chat_completion("Is it flu?"); [answer, conf_of(answer)];"#;
+        let result = interpreter.evaluate(source.to_string()).await;
+        assert_eq!(result.as_ref().unwrap().to_string(), "[[dry run: gpt-4], 0.1]");
+
+        let report = RunReport::new(&interpreter, &result, Duration::ZERO);
+        assert!(report.dry_run);
+        assert_eq!(report.llm.output_tokens, 1000);
+        assert!(report.llm.cost > 0.06);
+    }
+}
@@ -0,0 +1,89 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+use parking_lot::RwLock;
+
+/// Placeholder that replaces secret values in anything shown to the user.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Values that must never appear in output, errors, logs or the audit log.
+///
+/// Clones share one registry, so a secret registered by a native (such as
+/// `env.get("OPENAI_API_KEY")`) is masked everywhere from then on.
+#[derive(Debug, Clone, Default)]
+pub struct Secrets {
+    values: Arc<RwLock<Vec<String>>>,
+}
+
+impl Secrets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `secret` for redaction. Empty values are ignored since they
+    /// would match everywhere.
+    pub fn register(&self, secret: impl Into<String>) {
+        let secret = secret.into();
+        if secret.is_empty() {
+            return;
+        }
+        let mut values = self.values.write();
+        if !values.contains(&secret) {
+            values.push(secret);
+            // Longest first, so a secret containing another is masked whole.
+            values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.read().is_empty()
+    }
+
+    /// `text` with every registered secret replaced by [`REDACTED`].
+    pub fn redact<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        let values = self.values.read();
+        let mut redacted = Cow::Borrowed(text);
+        for secret in values.iter() {
+            if redacted.contains(secret.as_str()) {
+                redacted = Cow::Owned(redacted.replace(secret.as_str(), REDACTED));
+            }
+        }
+        redacted
+    }
+}
+
+/// Whether an environment variable name suggests its value is a credential.
+pub fn looks_secret(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    ["KEY", "TOKEN", "SECRET", "PASSWORD", "CREDENTIAL"]
+        .iter()
+        .any(|marker| name.contains(marker))
+}
+
+/// Compares a credential with the expected one in time independent of
+/// where they differ.
+pub fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        let secrets = Secrets::new();
+        assert!(matches!(secrets.redact("nothing to hide"), Cow::Borrowed(_)));
+
+        secrets.register("sk-123");
+        secrets.register("sk-123456");
+        secrets.register("");
+        assert_eq!(secrets.redact("key=sk-123456, old=sk-123"), "key=[REDACTED], old=[REDACTED]");
+    }
+
+    #[test]
+    fn test_looks_secret() {
+        assert!(looks_secret("OPENAI_API_KEY"));
+        assert!(looks_secret("github_token"));
+        assert!(!looks_secret("HOME"));
+    }
+}
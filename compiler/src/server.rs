@@ -0,0 +1,499 @@
+//! `prism serve`: a long-running daemon that lets applications in any
+//! language use Prism as a sidecar.
+//!
+//! Requests are JSON-RPC 2.0 messages posted to `/rpc`, or, for simple
+//! HTTP clients, the params of one method posted to `/<method>`:
+//!
+//! ```text
+//! POST /rpc       {"jsonrpc": "2.0", "id": 1, "method": "evaluate", "params": {"source": "1 + 1;"}}
+//! POST /evaluate  {"source": "1 + 1;"}
+//! ```
+//!
+//! | method           | params                        | result                                  |
+//! |------------------|-------------------------------|-----------------------------------------|
+//! | `session.create` |                               | `{"session"}`                           |
+//! | `session.close`  | `session`                     | `{"closed"}`                            |
+//! | `evaluate`       | `source`, `session`?          | `{"value", "confidence", "output"}`     |
+//! | `call`           | `name`, `args`?, `session`?   | `{"value", "confidence", "output"}`     |
+//! | `snapshot`       | `session`?                    | `{"globals"}`: what the session defined |
+//! | `metrics`        | `session`?                    | the session's [`MetricsSnapshot`]       |
+//!
+//! Results whose value carries metadata also have a `"metadata"` object.
+//!
+//! Each session has its own interpreter, so definitions persist between
+//! its requests but not across sessions, unless the pool's
+//! [`Isolation`](crate::pool::Isolation) is `Shared`; requests without a
+//! `session` use a shared default one. An [`Authenticator`] decides which
+//! requests are let in. Evaluations and calls give up after the server's
+//! [timeout](Server::with_timeout).
+//!
+//! [`MetricsSnapshot`]: crate::metrics::MetricsSnapshot
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use parking_lot::Mutex;
+use serde_json::{json, Value as Json};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::output::CapturedOutput;
+use crate::pool::InterpreterPool;
+use crate::secrets;
+use crate::value::Value;
+
+/// The largest request body accepted.
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+/// The longest request line or header line accepted.
+const MAX_LINE: u64 = 8 * 1024;
+
+/// The most header lines accepted.
+const MAX_HEADERS: usize = 100;
+
+/// How long an evaluation or call may run unless the server is configured
+/// otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+const DEFAULT_SESSION: &str = "default";
+
+/// Decides whether a request may proceed, from its HTTP headers with
+/// lowercase names.
+pub trait Authenticator: Send + Sync {
+    fn authenticate(&self, headers: &HashMap<String, String>) -> bool;
+}
+
+/// Lets in requests with the header `Authorization: Bearer <token>`.
+pub struct BearerToken(pub String);
+
+impl Authenticator for BearerToken {
+    fn authenticate(&self, headers: &HashMap<String, String>) -> bool {
+        headers
+            .get("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| secrets::same(token.as_bytes(), self.0.as_bytes()))
+    }
+}
+
+/// A failed request, as a JSON-RPC error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Json>,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    /// The script failed; `data.kind` is the [`PrismError::kind`].
+    pub const SCRIPT_ERROR: i64 = -32000;
+
+    pub(crate) fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), data: None }
+    }
+
+    pub(crate) fn to_json(&self) -> Json {
+        let mut error = json!({ "code": self.code, "message": self.message });
+        if let Some(data) = &self.data {
+            error["data"] = data.clone();
+        }
+        error
+    }
+}
+
+impl From<PrismError> for RpcError {
+    fn from(err: PrismError) -> Self {
+        Self { code: Self::SCRIPT_ERROR, message: err.to_string(), data: Some(json!({ "kind": err.kind() })) }
+    }
+}
+
+struct Session {
+    interpreter: Interpreter,
+    output: CapturedOutput,
+    /// Globals the interpreter started with, left out of snapshots.
+    builtins: HashSet<String>,
+}
+
+/// Sessions and the requests that use them, independent of the transport.
+pub struct Server {
+    pool: InterpreterPool,
+    sessions: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Session>>>>,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    timeout: Duration,
+    next_session: AtomicU64,
+}
+
+impl Server {
+    /// Sessions get interpreters from `pool`, with its capabilities.
+    pub fn new(pool: InterpreterPool) -> Self {
+        Self {
+            pool,
+            sessions: Mutex::new(HashMap::new()),
+            authenticator: None,
+            timeout: DEFAULT_TIMEOUT,
+            next_session: AtomicU64::new(1),
+        }
+    }
+
+    /// How long an evaluation or call may run before it is cancelled;
+    /// [`DEFAULT_TIMEOUT`] otherwise.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_authenticator(mut self, authenticator: impl Authenticator + 'static) -> Self {
+        self.authenticator = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Runs `method` with `params`.
+    pub async fn handle(&self, method: &str, params: &Json) -> std::result::Result<Json, RpcError> {
+        let session_id = match params.get("session") {
+            None | Some(Json::Null) => DEFAULT_SESSION,
+            Some(Json::String(id)) => id.as_str(),
+            Some(_) => return Err(RpcError::new(RpcError::INVALID_PARAMS, "'session' must be a string")),
+        };
+        match method {
+            "session.create" => {
+                let id = format!("s{}", self.next_session.fetch_add(1, Ordering::Relaxed));
+                self.sessions.lock().insert(id.clone(), self.new_session());
+                Ok(json!({ "session": id }))
+            }
+            "session.close" => Ok(json!({ "closed": self.sessions.lock().remove(session_id).is_some() })),
+            "evaluate" => {
+                let source = string_param(params, "source")?;
+                let session = self.session(session_id)?;
+                let mut session = session.lock().await;
+                let result = session.interpreter.evaluate_with_timeout(source.to_string(), self.timeout).await;
+                session.result(result)
+            }
+            "call" => {
+                let name = string_param(params, "name")?;
+                let args = match params.get("args") {
+                    None | Some(Json::Null) => Vec::new(),
+                    Some(Json::Array(args)) => args.iter().map(Value::from_json).collect(),
+                    Some(_) => return Err(RpcError::new(RpcError::INVALID_PARAMS, "'args' must be an array")),
+                };
+                let session = self.session(session_id)?;
+                let mut session = session.lock().await;
+                let callee = session
+                    .interpreter
+                    .global_values()
+                    .into_iter()
+                    .find(|(global, _)| global == name)
+                    .map(|(_, value)| value)
+                    .ok_or_else(|| RpcError::from(PrismError::UndefinedVariable(name.to_string())))?;
+                let timeout = self.timeout;
+                let result = session
+                    .interpreter
+                    .with_time_limit(timeout, |interpreter| Box::pin(interpreter.call(callee, args)))
+                    .await
+                    .and_then(|value| {
+                        value.ok_or_else(|| PrismError::Cancelled(format!("{} timed out after {:?}", name, timeout)))
+                    });
+                session.result(result)
+            }
+            "snapshot" => {
+                let session = self.session(session_id)?;
+                let session = session.lock().await;
+                let globals: serde_json::Map<String, Json> = session
+                    .interpreter
+                    .global_values()
+                    .into_iter()
+                    .filter(|(name, _)| !session.builtins.contains(name))
+                    .filter_map(|(name, value)| Some((name, value.to_json().ok()?)))
+                    .collect();
+                Ok(json!({ "globals": globals }))
+            }
+            "metrics" => {
+                let session = self.session(session_id)?;
+                let snapshot = session.lock().await.interpreter.metrics_snapshot();
+                serde_json::to_value(snapshot).map_err(|err| RpcError::from(PrismError::from(err)))
+            }
+            _ => Err(RpcError::new(RpcError::METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+        }
+    }
+
+    /// Answers a JSON-RPC request; notifications, which have no `id`, get
+    /// no answer.
+    pub async fn rpc(&self, request: &str) -> Option<Json> {
+        let request: Json = match serde_json::from_str(request) {
+            Ok(request) => request,
+            Err(err) => return Some(response(Json::Null, Err(RpcError::new(RpcError::PARSE_ERROR, err.to_string())))),
+        };
+        let id = request.get("id").cloned();
+        let method = request.get("method").and_then(Json::as_str);
+        let result = match method {
+            Some(method) if request.get("jsonrpc") == Some(&json!("2.0")) => {
+                self.handle(method, request.get("params").unwrap_or(&Json::Null)).await
+            }
+            _ => Err(RpcError::new(RpcError::INVALID_REQUEST, "Expected a JSON-RPC 2.0 request")),
+        };
+        id.map(|id| response(id, result))
+    }
+
+    /// Serves HTTP requests from `listener` until it fails.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(err) = server.connection(stream).await {
+                    log::debug!("connection failed: {}", err);
+                }
+            });
+        }
+    }
+
+    async fn connection(&self, stream: TcpStream) -> Result<()> {
+        let mut stream = BufReader::new(stream);
+        let (status, body) = match read_head(&mut stream).await? {
+            None => (431, json!({ "error": { "code": RpcError::INVALID_REQUEST, "message": "Request head too large" } })),
+            Some((_, _, headers)) if self.authenticator.as_ref().is_some_and(|auth| !auth.authenticate(&headers)) => {
+                (401, json!({ "error": { "code": RpcError::INVALID_REQUEST, "message": "Unauthorized" } }))
+            }
+            Some((method, _, _)) if method != "POST" => {
+                (405, json!({ "error": { "code": RpcError::INVALID_REQUEST, "message": "Use POST" } }))
+            }
+            Some((_, path, headers)) => {
+                let length: usize = headers.get("content-length").and_then(|length| length.parse().ok()).unwrap_or(0);
+                if length > MAX_BODY {
+                    (413, json!({ "error": { "code": RpcError::INVALID_REQUEST, "message": "Request too large" } }))
+                } else {
+                    let mut body = vec![0; length];
+                    stream.read_exact(&mut body).await?;
+                    let body = String::from_utf8_lossy(&body);
+                    self.route(&path, &body).await
+                }
+            }
+        };
+
+        let body = if body.is_null() { String::new() } else { body.to_string() };
+        let reason = match status {
+            200 => "OK",
+            204 => "No Content",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            _ => "Request Header Fields Too Large",
+        };
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            reason,
+            body.len()
+        );
+        let stream = stream.get_mut();
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body.as_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    async fn route(&self, path: &str, body: &str) -> (u16, Json) {
+        match path.trim_start_matches('/') {
+            "rpc" => match self.rpc(body).await {
+                Some(response) => (200, response),
+                None => (204, Json::Null),
+            },
+            method => {
+                let params = if body.trim().is_empty() { Ok(json!({})) } else { serde_json::from_str(body) };
+                let result = match params {
+                    Ok(params) => self.handle(method, &params).await,
+                    Err(err) => Err(RpcError::new(RpcError::PARSE_ERROR, err.to_string())),
+                };
+                match result {
+                    Ok(result) => (200, result),
+                    Err(err) if err.code == RpcError::METHOD_NOT_FOUND => (404, json!({ "error": err.to_json() })),
+                    Err(err) => (400, json!({ "error": err.to_json() })),
+                }
+            }
+        }
+    }
+
+    fn new_session(&self) -> Arc<tokio::sync::Mutex<Session>> {
+        let mut interpreter = self.pool.interpreter();
+        let output = interpreter.capture_output();
+        let builtins = interpreter.global_values().into_iter().map(|(name, _)| name).collect();
+        Arc::new(tokio::sync::Mutex::new(Session { interpreter, output, builtins }))
+    }
+
+    fn session(&self, id: &str) -> std::result::Result<Arc<tokio::sync::Mutex<Session>>, RpcError> {
+        let mut sessions = self.sessions.lock();
+        if id == DEFAULT_SESSION {
+            return Ok(sessions.entry(id.to_string()).or_insert_with(|| self.new_session()).clone());
+        }
+        sessions
+            .get(id)
+            .cloned()
+            .ok_or_else(|| RpcError::new(RpcError::INVALID_PARAMS, format!("Unknown session '{}'", id)))
+    }
+}
+
+impl Session {
+    /// The result of an evaluation or call, with what it printed.
+    fn result(&mut self, result: Result<Value>) -> std::result::Result<Json, RpcError> {
+        let output = self.output.take();
+        let value = result.map_err(|err| {
+            let mut err = RpcError::from(err);
+            if let Some(data) = &mut err.data {
+                data["output"] = json!(output);
+            }
+            err
+        })?;
+        let json = value.to_json().unwrap_or_else(|_| Json::String(value.to_string()));
+        let mut result = json!({ "value": json, "confidence": value.confidence, "output": output });
+        if let Ok(Some(metadata)) = value.metadata_json() {
+            result["metadata"] = metadata;
+        }
+        Ok(result)
+    }
+}
+
+/// The method, path and headers with lowercase names of a request, or
+/// `None` when a line is longer than [`MAX_LINE`] or there are more than
+/// [`MAX_HEADERS`] headers.
+async fn read_head(stream: &mut BufReader<TcpStream>) -> Result<Option<(String, String, HashMap<String, String>)>> {
+    let mut line = String::new();
+    if !read_line(stream, &mut line).await? {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default().to_string(), parts.next().unwrap_or_default().to_string());
+
+    let mut headers = HashMap::new();
+    for _ in 0..=MAX_HEADERS {
+        line.clear();
+        if !read_line(stream, &mut line).await? {
+            return Ok(None);
+        }
+        if line.trim().is_empty() {
+            return Ok(Some((method, path, headers)));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    Ok(None)
+}
+
+/// Reads a line into `line`, or at the end of the stream nothing; `false`
+/// if the line is too long.
+async fn read_line(stream: &mut BufReader<TcpStream>, line: &mut String) -> Result<bool> {
+    let read = (&mut *stream).take(MAX_LINE).read_line(line).await?;
+    Ok((read as u64) < MAX_LINE || line.ends_with('\n'))
+}
+
+fn string_param<'a>(params: &'a Json, name: &str) -> std::result::Result<&'a str, RpcError> {
+    params
+        .get(name)
+        .and_then(Json::as_str)
+        .ok_or_else(|| RpcError::new(RpcError::INVALID_PARAMS, format!("Expected a string '{}'", name)))
+}
+
+pub(crate) fn response(id: Json, result: std::result::Result<Json, RpcError>) -> Json {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({ "jsonrpc": "2.0", "id": id, "error": err.to_json() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capabilities::Capabilities;
+
+    fn server() -> Server {
+        Server::new(InterpreterPool::new().unwrap().with_capabilities(Capabilities::none()))
+    }
+
+    #[tokio::test]
+    async fn test_sessions_keep_their_definitions() {
+        let server = server();
+        let session = server.handle("session.create", &json!({})).await.unwrap()["session"].clone();
+        let defined = json!({ "session": session, "source": "let limit = 3 ~> 0.8; fn twice(x) { print(x); return x * 2; }" });
+        server.handle("evaluate", &defined).await.unwrap();
+
+        let called = server.handle("call", &json!({ "session": session, "name": "twice", "args": [21] })).await;
+        assert_eq!(called.unwrap(), json!({ "value": 42, "confidence": 1.0, "output": "21\n" }));
+        let snapshot = server.handle("snapshot", &json!({ "session": session })).await.unwrap();
+        assert_eq!(snapshot, json!({ "globals": { "limit": 3 } }));
+
+        // Other sessions do not see them
+        let err = server.handle("evaluate", &json!({ "source": "limit;" })).await.unwrap_err();
+        assert_eq!(err.code, RpcError::SCRIPT_ERROR);
+        assert_eq!(server.handle("session.close", &json!({ "session": session })).await.unwrap(), json!({ "closed": true }));
+        assert_eq!(server.handle("snapshot", &json!({ "session": session })).await.unwrap_err().code, RpcError::INVALID_PARAMS);
+    }
+
+    #[tokio::test]
+    async fn test_json_rpc_messages() {
+        let server = server();
+        let answer = server.rpc(r#"{"jsonrpc": "2.0", "id": 7, "method": "evaluate", "params": {"source": "1 + 1;"}}"#).await;
+        assert_eq!(answer.unwrap(), json!({ "jsonrpc": "2.0", "id": 7, "result": { "value": 2, "confidence": 1.0, "output": "" } }));
+        let tagged = server.handle("evaluate", &json!({ "source": r#"meta_set(2, "source", "sensor");"# })).await;
+        assert_eq!(tagged.unwrap()["metadata"], json!({ "source": "sensor" }));
+        let answer = server.rpc(r#"{"jsonrpc": "2.0", "id": 8, "method": "fly"}"#).await.unwrap();
+        assert_eq!(answer["error"]["code"], RpcError::METHOD_NOT_FOUND);
+        assert_eq!(server.rpc("{").await.unwrap()["error"]["code"], RpcError::PARSE_ERROR);
+        assert!(server.rpc(r#"{"jsonrpc": "2.0", "method": "metrics"}"#).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_http_requests_are_authenticated() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Arc::new(server().with_authenticator(BearerToken("secret".to_string())));
+        tokio::spawn(server.serve(listener));
+
+        let post = |path: &str, token: &str, body: &str| {
+            format!(
+                "POST {} HTTP/1.1\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\n\r\n{}",
+                path,
+                token,
+                body.len(),
+                body
+            )
+        };
+        let send = |request: String| async move {
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = send(post("/evaluate", "secret", r#"{"source": "\"ok\" ~> 0.7;"}"#)).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(r#"{"confidence":0.7,"output":"","value":"ok"}"#));
+        let response = send(post("/evaluate", "guess", r#"{"source": "1;"}"#)).await;
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized"));
+        let response = send(post("/evaluate", &"x".repeat(MAX_LINE as usize), "{}")).await;
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large"));
+        let padding = "X-Padding: 1\r\n".repeat(MAX_HEADERS + 1);
+        let response = send(format!("POST /evaluate HTTP/1.1\r\n{}\r\n", padding)).await;
+        assert!(response.starts_with("HTTP/1.1 431"));
+    }
+
+    #[tokio::test]
+    async fn test_evaluations_and_calls_time_out() {
+        let server = server().with_timeout(Duration::from_millis(50));
+        let spin = json!({ "source": "fn spin() { return spin(); } spin();" });
+        let err = server.handle("evaluate", &spin).await.unwrap_err();
+        assert_eq!(err.data.unwrap()["kind"], "cancelled");
+        let err = server.handle("call", &json!({ "name": "spin" })).await.unwrap_err();
+        assert_eq!(err.data.unwrap()["kind"], "cancelled");
+
+        // The session is still usable afterwards
+        let answer = server.handle("evaluate", &json!({ "source": "1 + 1;" })).await;
+        assert_eq!(answer.unwrap()["value"], 2);
+    }
+}
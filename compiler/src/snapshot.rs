@@ -0,0 +1,111 @@
+//! Golden-file snapshots for `snapshot(name, value)`.
+//!
+//! Each snapshot is a pretty-printed JSON file holding the value and its
+//! confidence. The first run writes it; later runs compare against it and
+//! fail with a line diff when the value changed, unless snapshots are being
+//! updated (`prism test --update-snapshots`).
+
+use std::path::{Path, PathBuf};
+use similar::TextDiff;
+use crate::error::{PrismError, Result};
+use crate::value::Value;
+
+/// Where a script's snapshots live and whether to overwrite them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshots {
+    dir: PathBuf,
+    update: bool,
+}
+
+/// What [`Snapshots::check`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotOutcome {
+    Matched,
+    Created,
+    Updated,
+}
+
+impl Snapshots {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into(), update: false }
+    }
+
+    /// Overwrites snapshots that differ instead of failing.
+    pub fn updating(mut self, update: bool) -> Self {
+        self.update = update;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Compares `value` with the snapshot called `name`, writing it when it
+    /// is new or being updated.
+    pub fn check(&self, name: &str, value: &Value) -> Result<SnapshotOutcome> {
+        let valid = !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+            && !name.starts_with('.');
+        if !valid {
+            return Err(PrismError::InvalidArgument(format!(
+                "snapshot name '{}' may only use letters, digits, '_', '-' and '.'",
+                name
+            )));
+        }
+        let path = self.dir.join(format!("{}.snap.json", name));
+        let actual = serialize(value)?;
+        let expected = match std::fs::read_to_string(&path) {
+            Ok(expected) => Some(expected),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        match expected {
+            Some(expected) if expected == actual => Ok(SnapshotOutcome::Matched),
+            Some(expected) if !self.update => {
+                let diff = TextDiff::from_lines(&expected, &actual)
+                    .unified_diff()
+                    .header("snapshot", "actual")
+                    .to_string();
+                Err(PrismError::RuntimeError(format!(
+                    "snapshot '{}' changed; rerun with --update-snapshots to accept:\n{}",
+                    name, diff
+                )))
+            }
+            expected => {
+                std::fs::create_dir_all(&self.dir)?;
+                std::fs::write(&path, actual)?;
+                Ok(if expected.is_some() { SnapshotOutcome::Updated } else { SnapshotOutcome::Created })
+            }
+        }
+    }
+}
+
+fn serialize(value: &Value) -> Result<String> {
+    let data = value.to_json().unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+    let snapshot = serde_json::json!({ "value": data, "confidence": value.confidence });
+    Ok(serde_json::to_string_pretty(&snapshot)? + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::ValueKind;
+
+    #[test]
+    fn test_snapshots_are_created_compared_and_updated() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("prism-snapshots-{}", std::process::id()));
+        let snapshots = Snapshots::new(&dir);
+        let flu = Value::with_confidence(ValueKind::String("flu".to_string()), 0.8);
+        let cold = Value::with_confidence(ValueKind::String("cold".to_string()), 0.8);
+
+        assert_eq!(snapshots.check("diagnosis", &flu)?, SnapshotOutcome::Created);
+        assert_eq!(snapshots.check("diagnosis", &flu)?, SnapshotOutcome::Matched);
+        let err = snapshots.check("diagnosis", &cold).unwrap_err().to_string();
+        assert!(err.contains("-  \"value\": \"flu\"") && err.contains("+  \"value\": \"cold\""));
+        assert_eq!(snapshots.clone().updating(true).check("diagnosis", &cold)?, SnapshotOutcome::Updated);
+        assert!(snapshots.check("../escape", &flu).is_err());
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//! Source maps: where the lines of a program that was generated or
+//! transformed before it ran came from, so errors point at the code the
+//! user wrote rather than the code that was executed.
+//!
+//! A map holds the original position of some generated lines; lines
+//! between two mapped ones are assumed to follow the earlier one, so a
+//! program made by concatenating files needs one entry per file:
+//!
+//! ```
+//! use prism::source_map::{Origin, SourceMap};
+//!
+//! let map = SourceMap::new()
+//!     .with_mapping(1, Origin::in_file("helpers.prism", 1))
+//!     .with_mapping(41, Origin::in_file("triage.prism", 1));
+//! assert_eq!(map.original(45).unwrap().to_string(), "line 5 of triage.prism");
+//! ```
+//!
+//! Printing a syntax tree with [`to_source_with_map`](crate::ast::to_source_with_map)
+//! produces the map back to the lines recorded in the tree.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::diagnostics::Diagnostic;
+
+/// A position in the original source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    pub file: Option<String>,
+    pub line: usize,
+}
+
+impl Origin {
+    pub fn line(line: usize) -> Self {
+        Origin { file: None, line }
+    }
+
+    pub fn in_file(file: impl Into<String>, line: usize) -> Self {
+        Origin { file: Some(file.into()), line }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "line {} of {}", self.line, file),
+            None => write!(f, "line {}", self.line),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    /// Generated line to where it came from.
+    mappings: BTreeMap<usize, Origin>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_mapping(mut self, generated: usize, origin: Origin) -> Self {
+        self.add(generated, origin);
+        self
+    }
+
+    pub fn add(&mut self, generated: usize, origin: Origin) {
+        self.mappings.insert(generated, origin);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// Where generated `line` came from, or `None` before the first mapping.
+    pub fn original(&self, line: usize) -> Option<Origin> {
+        let (mapped, origin) = self.mappings.range(..=line).next_back()?;
+        Some(Origin { file: origin.file.clone(), line: origin.line + (line - mapped) })
+    }
+
+    /// Moves a diagnostic to the original line it is about.
+    pub fn remap(&self, diagnostic: Diagnostic) -> Diagnostic {
+        match diagnostic.line.and_then(|line| self.original(line)) {
+            Some(origin) => diagnostic.at_line(Some(origin.line)).in_file(origin.file),
+            None => diagnostic,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_follow_the_nearest_mapping() {
+        let map = SourceMap::new().with_mapping(3, Origin::line(10)).with_mapping(8, Origin::in_file("lib.prism", 1));
+        assert_eq!(map.original(2), None);
+        assert_eq!(map.original(3), Some(Origin::line(10)));
+        assert_eq!(map.original(5), Some(Origin::line(12)));
+        assert_eq!(map.original(9), Some(Origin::in_file("lib.prism", 2)));
+
+        let diagnostic = map.remap(Diagnostic::error("undefined variable 'x'").at_line(Some(9)));
+        assert_eq!(diagnostic.to_string(), "error at line 2 of lib.prism: undefined variable 'x'");
+    }
+}
@@ -0,0 +1,162 @@
+//! Conversions behind `len`, `str`, `num` and `bool`.
+//!
+//! A successful conversion keeps the input's confidence. A failed one is an
+//! error unless the caller opts in by passing a fallback as the last
+//! argument — `num(input, 0)` — in which case the fallback is returned with
+//! [`FAILED_CONVERSION_CONFIDENCE`], so uncertain code can branch on it.
+
+use crate::error::{PrismError, Result};
+use crate::iterator::range_len;
+use crate::value::{Value, ValueKind};
+
+/// Confidence of the fallback returned when a lenient conversion fails.
+pub const FAILED_CONVERSION_CONFIDENCE: f64 = 0.0;
+
+pub fn len(args: &[Value]) -> Result<Value> {
+    convert("len", args, |arg| {
+        let len = match &arg.kind {
+            ValueKind::String(s) => s.chars().count(),
+            ValueKind::List(items) => items.len(),
+            ValueKind::Map(entries) => entries.len(),
+            ValueKind::Range { start, end, step } => range_len(*start, *end, *step),
+            #[cfg(feature = "tensor")]
+            ValueKind::Tensor(t) => t.shape().first().copied().unwrap_or(0),
+            _ => return Err(format!("{} has no length", arg)),
+        };
+        Ok(ValueKind::Number(len as f64))
+    })
+}
+
+pub fn to_str(args: &[Value]) -> Result<Value> {
+    convert("str", args, |arg| Ok(ValueKind::String(arg.to_string())))
+}
+
+/// A decimal with more digits than a number holds loses confidence as it
+/// converts; see the `decimal` module.
+pub fn to_num(args: &[Value]) -> Result<Value> {
+    #[cfg(feature = "decimal")]
+    if let Some(Value { kind: ValueKind::Decimal(decimal), confidence, .. }) = args.first() {
+        let (n, factor) = crate::stdlib::decimal::to_number(decimal);
+        return Ok(Value::with_confidence(ValueKind::Number(n), confidence * factor));
+    }
+    convert("num", args, |arg| match &arg.kind {
+        ValueKind::Number(n) => Ok(ValueKind::Number(*n)),
+        ValueKind::Boolean(b) => Ok(ValueKind::Number(if *b { 1.0 } else { 0.0 })),
+        ValueKind::String(s) => parse_number(s)
+            .map(ValueKind::Number)
+            .ok_or_else(|| format!("cannot convert \"{}\" to a number", s)),
+        _ => Err(format!("cannot convert {} to a number", arg)),
+    })
+}
+
+/// Booleans and `"true"`/`"false"`/`"yes"`/`"no"` convert exactly. A number in
+/// `0..=1` is read as a probability: it becomes the more likely boolean, with
+/// that likelihood folded into the result's confidence.
+pub fn to_bool(args: &[Value]) -> Result<Value> {
+    let Some(arg) = args.first() else {
+        return Err(missing_argument("bool"));
+    };
+    if let ValueKind::Number(p) = arg.kind {
+        if (0.0..=1.0).contains(&p) {
+            let (value, likelihood) = if p >= 0.5 { (true, p) } else { (false, 1.0 - p) };
+            return Ok(Value::with_confidence(ValueKind::Boolean(value), arg.confidence * likelihood));
+        }
+    }
+    convert("bool", args, |arg| match &arg.kind {
+        ValueKind::Boolean(b) => Ok(ValueKind::Boolean(*b)),
+        ValueKind::String(s) => match s.trim().to_ascii_lowercase().as_str() {
+            "true" | "yes" => Ok(ValueKind::Boolean(true)),
+            "false" | "no" => Ok(ValueKind::Boolean(false)),
+            _ => Err(format!("cannot convert \"{}\" to a boolean", s)),
+        },
+        _ => Err(format!("cannot convert {} to a boolean", arg)),
+    })
+}
+
+/// Applies `conversion` to the first argument, falling back to the optional
+/// second argument on failure.
+fn convert(
+    name: &str,
+    args: &[Value],
+    conversion: impl FnOnce(&Value) -> std::result::Result<ValueKind, String>,
+) -> Result<Value> {
+    let (arg, fallback) = match args {
+        [arg] => (arg, None),
+        [arg, fallback] => (arg, Some(fallback)),
+        [] => return Err(missing_argument(name)),
+        _ => return Err(PrismError::InvalidArgument(format!(
+            "{} expects a value and an optional fallback",
+            name
+        ))),
+    };
+    match (conversion(arg), fallback) {
+        (Ok(kind), _) => Ok(Value::with_confidence(kind, arg.confidence)),
+        (Err(_), Some(fallback)) => Ok(Value::with_confidence(
+            fallback.kind.clone(),
+            FAILED_CONVERSION_CONFIDENCE,
+        )),
+        (Err(message), None) => Err(PrismError::InvalidArgument(format!("{}: {}", name, message))),
+    }
+}
+
+fn missing_argument(name: &str) -> PrismError {
+    PrismError::InvalidArgument(format!("{} expects a value", name))
+}
+
+/// Decimal numbers, optionally as a percentage (`"80%"` is 0.8).
+fn parse_number(text: &str) -> Option<f64> {
+    let text = text.trim();
+    match text.strip_suffix('%') {
+        Some(percent) => percent.trim_end().parse::<f64>().ok().map(|n| n / 100.0),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::new(ValueKind::String(s.to_string()))
+    }
+
+    fn number(n: f64) -> Value {
+        Value::new(ValueKind::Number(n))
+    }
+
+    #[test]
+    fn test_conversions_keep_confidence() {
+        let uncertain = Value::with_confidence(ValueKind::String("0.8".to_string()), 0.6);
+        let converted = to_num(&[uncertain]).unwrap();
+        assert_eq!(converted.kind, ValueKind::Number(0.8));
+        assert_eq!(converted.confidence, 0.6);
+
+        assert_eq!(to_num(&[string(" 80% ")]).unwrap().kind, ValueKind::Number(0.8));
+        assert_eq!(to_str(&[number(42.0)]).unwrap().kind, ValueKind::String("42".to_string()));
+        assert_eq!(len(&[string("héllo")]).unwrap().kind, ValueKind::Number(5.0));
+        assert_eq!(to_bool(&[string("Yes")]).unwrap().kind, ValueKind::Boolean(true));
+    }
+
+    #[test]
+    fn test_probabilities_become_uncertain_booleans() {
+        let likely = to_bool(&[number(0.9)]).unwrap();
+        assert_eq!(likely.kind, ValueKind::Boolean(true));
+        assert!((likely.confidence - 0.9).abs() < 1e-9);
+
+        let unlikely = to_bool(&[number(0.2)]).unwrap();
+        assert_eq!(unlikely.kind, ValueKind::Boolean(false));
+        assert!((unlikely.confidence - 0.8).abs() < 1e-9);
+
+        assert!(to_bool(&[number(3.0)]).is_err());
+    }
+
+    #[test]
+    fn test_failed_conversions_error_or_fall_back() {
+        assert!(matches!(to_num(&[string("high")]), Err(PrismError::InvalidArgument(_))));
+        assert!(len(&[number(1.0)]).is_err());
+
+        let fallback = to_num(&[string("high"), number(0.0)]).unwrap();
+        assert_eq!(fallback.kind, ValueKind::Number(0.0));
+        assert_eq!(fallback.confidence, FAILED_CONVERSION_CONFIDENCE);
+    }
+}
@@ -0,0 +1,86 @@
+//! `printf`-style templates: each `{}` takes the next argument, `{:.N}`
+//! prints a number with `N` decimals and `{{`/`}}` are literal braces.
+
+use crate::error::{PrismError, Result};
+use crate::value::{Value, ValueKind};
+
+pub fn format(template: &str, args: &[Value]) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut args = args.iter();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                output.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                output.push('}');
+            }
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err(invalid(format!("unclosed placeholder in \"{}\"", template))),
+                    }
+                }
+                let Some(arg) = args.next() else {
+                    return Err(invalid(format!("not enough arguments for \"{}\"", template)));
+                };
+                output.push_str(&placeholder(&spec, arg)?);
+            }
+            '}' => return Err(invalid(format!("unmatched '}}' in \"{}\"", template))),
+            c => output.push(c),
+        }
+    }
+
+    if args.next().is_some() {
+        return Err(invalid(format!("too many arguments for \"{}\"", template)));
+    }
+    Ok(output)
+}
+
+fn placeholder(spec: &str, arg: &Value) -> Result<String> {
+    if spec.is_empty() {
+        return Ok(arg.to_string());
+    }
+    let precision = spec
+        .strip_prefix(":.")
+        .and_then(|digits| digits.parse::<usize>().ok())
+        .ok_or_else(|| invalid(format!("unsupported placeholder {{{}}}", spec)))?;
+    match arg.kind {
+        ValueKind::Number(n) => Ok(format!("{:.*}", precision, n)),
+        _ => Err(invalid(format!("{{{}}} expects a number, got {}", spec, arg))),
+    }
+}
+
+fn invalid(message: String) -> PrismError {
+    PrismError::InvalidArgument(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(n: f64) -> Value {
+        Value::new(ValueKind::Number(n))
+    }
+
+    #[test]
+    fn test_format_placeholders() {
+        let text = format("score: {} ({:.1}%) {{raw}}", &[number(0.8), number(80.0)]).unwrap();
+        assert_eq!(text, "score: 0.8 (80.0%) {raw}");
+    }
+
+    #[test]
+    fn test_format_argument_mismatch() {
+        assert!(format("{} {}", &[number(1.0)]).is_err());
+        assert!(format("{}", &[number(1.0), number(2.0)]).is_err());
+        assert!(format("{:x}", &[number(1.0)]).is_err());
+        assert!(format("{", &[]).is_err());
+    }
+}
@@ -2,30 +2,56 @@
 
 use std::sync::Arc;
 use parking_lot::RwLock;
-use crate::error::Result;
+use crate::docs::{self, FunctionDoc};
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::iterator;
 use crate::module::Module;
 use crate::value::{Value, ValueKind};
 
+pub mod convert;
+pub mod format;
+pub mod query;
+
 pub fn init_core_module() -> Result<Arc<RwLock<Module>>> {
     let module = Arc::new(RwLock::new(Module::new("core".to_string())));
 
-    // print function
+    // print function: the arguments' display forms separated by spaces, then a newline
     let print_fn = Value::new(ValueKind::NativeFunction {
         name: "print".to_string(),
         arity: 1,
-        handler: Arc::new(|args| {
-            if let Some(arg) = args.first() {
-                println!("{:?}", arg);
-            }
+        handler: Arc::new(print_line),
+    });
+
+    // println is print under the name many users reach for first
+    let println_fn = Value::new(ValueKind::NativeFunction {
+        name: "println".to_string(),
+        arity: 1,
+        handler: Arc::new(print_line),
+    });
+
+    // printf function: formats like `format` and writes without a newline
+    let printf_fn = Value::new(ValueKind::NativeFunction {
+        name: "printf".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            interpreter.write_output(&format_args(&args)?);
             Ok(Value::new(ValueKind::Nil))
         }),
     });
 
+    // format function: `format("score: {} ({:.1}%)", x, p)`
+    let format_fn = Value::new(ValueKind::NativeFunction {
+        name: "format".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| Ok(Value::new(ValueKind::String(format_args(&args)?)))),
+    });
+
     // type function
     let type_fn = Value::new(ValueKind::NativeFunction {
         name: "type".to_string(),
         arity: 1,
-        handler: Arc::new(|args| {
+        handler: Arc::new(|_, args| {
             if let Some(arg) = args.first() {
                 let type_str = match &arg.kind {
                     ValueKind::Nil => "nil",
@@ -33,10 +59,17 @@ pub fn init_core_module() -> Result<Arc<RwLock<Module>>> {
                     ValueKind::Number(_) => "number",
                     ValueKind::String(_) => "string",
                     ValueKind::Function { .. } => "function",
-                    ValueKind::NativeFunction { .. } => "native_function",
+                    ValueKind::NativeFunction { .. } | ValueKind::AsyncNativeFunction { .. } => "native_function",
                     ValueKind::Module(_) => "module",
                     ValueKind::List(_) => "list",
                     ValueKind::Map(_) => "map",
+                    ValueKind::Range { .. } => "range",
+                    ValueKind::DateTime(_) => "datetime",
+                    ValueKind::Duration(_) => "duration",
+                    #[cfg(feature = "decimal")]
+                    ValueKind::Decimal(_) => "decimal",
+                    #[cfg(feature = "tensor")]
+                    ValueKind::Tensor(_) => "tensor",
                 };
                 Ok(Value::new(ValueKind::String(type_str.to_string())))
             } else {
@@ -49,7 +82,7 @@ pub fn init_core_module() -> Result<Arc<RwLock<Module>>> {
     let assert_fn = Value::new(ValueKind::NativeFunction {
         name: "assert".to_string(),
         arity: 2,
-        handler: Arc::new(|args| {
+        handler: Arc::new(|_, args| {
             if args.len() != 2 {
                 return Ok(Value::new(ValueKind::Nil));
             }
@@ -64,18 +97,335 @@ pub fn init_core_module() -> Result<Arc<RwLock<Module>>> {
                         ValueKind::String(s) => s.clone(),
                         _ => "Assertion failed".to_string(),
                     };
-                    Err(crate::error::PrismError::RuntimeError(message))
+                    Err(PrismError::RuntimeError(message))
+                }
+            }
+        }),
+    });
+
+    // len function
+    let len_fn = Value::new(ValueKind::NativeFunction {
+        name: "len".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| convert::len(&args)),
+    });
+
+    // conf_of function
+    let conf_of_fn = Value::new(ValueKind::NativeFunction {
+        name: "conf_of".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| match args.first() {
+            Some(arg) => Ok(Value::new(ValueKind::Number(arg.confidence))),
+            None => Err(PrismError::InvalidArgument("conf_of expects a value".to_string())),
+        }),
+    });
+
+    // range function: range(end), range(start, end) or range(start, end, step);
+    // the numbers are produced lazily as the range is iterated
+    let range_fn = Value::new(ValueKind::NativeFunction {
+        name: "range".to_string(),
+        arity: 3,
+        handler: Arc::new(|_, args| {
+            let mut bounds = Vec::with_capacity(args.len());
+            for arg in &args {
+                match arg.kind {
+                    ValueKind::Number(n) => bounds.push(n),
+                    _ => return Err(PrismError::InvalidArgument("range expects numbers".to_string())),
                 }
             }
+            let (start, end, step) = match bounds[..] {
+                [end] => (0.0, end, 1.0),
+                [start, end] => (start, end, 1.0),
+                [start, end, step] => (start, end, step),
+                _ => return Err(PrismError::InvalidArgument(
+                    "range expects between 1 and 3 arguments".to_string(),
+                )),
+            };
+            if step == 0.0 {
+                return Err(PrismError::InvalidArgument("range step must not be zero".to_string()));
+            }
+            Ok(Value::new(ValueKind::Range { start, end, step }))
+        }),
+    });
+
+    // iter function: an iterator (a map with `next()` returning
+    // {value, done}) over a list, map or range; iterators are returned as is
+    let iter_fn = Value::new(ValueKind::NativeFunction {
+        name: "iter".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| match args.first() {
+            Some(iterable) => iterator::iterator(iterable),
+            None => Err(PrismError::InvalidArgument("iter expects a list, map or range".to_string())),
+        }),
+    });
+
+    // Conversions; each takes an optional fallback, see `convert`
+    let str_fn = Value::new(ValueKind::NativeFunction {
+        name: "str".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| convert::to_str(&args)),
+    });
+
+    let num_fn = Value::new(ValueKind::NativeFunction {
+        name: "num".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| convert::to_num(&args)),
+    });
+
+    let bool_fn = Value::new(ValueKind::NativeFunction {
+        name: "bool".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| convert::to_bool(&args)),
+    });
+
+    // Navigating nested values, see `query`
+    let query_fn = Value::new(ValueKind::NativeFunction {
+        name: "query".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| query::query_fn(&args)),
+    });
+
+    let get_in_fn = Value::new(ValueKind::NativeFunction {
+        name: "get_in".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| query::get_in_fn(&args)),
+    });
+
+    let set_in_fn = Value::new(ValueKind::NativeFunction {
+        name: "set_in".to_string(),
+        arity: 3,
+        handler: Arc::new(|_, args| query::set_in_fn(&args)),
+    });
+
+    // Metadata: meta_get(value, key?) reads it, meta_set(value, key, meta)
+    // returns a copy with the entry set
+    let meta_get_fn = Value::new(ValueKind::NativeFunction {
+        name: "meta_get".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| match args.as_slice() {
+            [value] => Ok(Value::new(ValueKind::Map(
+                value
+                    .metadata
+                    .iter()
+                    .flat_map(|metadata| metadata.iter())
+                    .map(|(key, meta)| (Value::new(ValueKind::String(key.clone())), meta.clone()))
+                    .collect(),
+            ))),
+            [value, Value { kind: ValueKind::String(key), .. }] => {
+                Ok(value.meta(key).cloned().unwrap_or_else(|| Value::new(ValueKind::Nil)))
+            }
+            _ => Err(PrismError::InvalidArgument("meta_get expects a value and optionally a key".to_string())),
+        }),
+    });
+
+    let meta_set_fn = Value::new(ValueKind::NativeFunction {
+        name: "meta_set".to_string(),
+        arity: 3,
+        handler: Arc::new(|_, args| match args.as_slice() {
+            [value, Value { kind: ValueKind::String(key), .. }, meta] => Ok(value.clone().with_meta(key.clone(), meta.clone())),
+            _ => Err(PrismError::InvalidArgument("meta_set expects a value, a key and the metadata".to_string())),
+        }),
+    });
+
+    // snapshot function: snapshot(name, value) compares value with a golden
+    // file; the host enables it, see `snapshot`
+    let snapshot_fn = Value::new(ValueKind::NativeFunction {
+        name: "snapshot".to_string(),
+        arity: 2,
+        handler: Arc::new(|interpreter, args| {
+            let (name, value) = match args.as_slice() {
+                [Value { kind: ValueKind::String(name), .. }, value] => (name, value),
+                _ => return Err(PrismError::InvalidArgument("snapshot expects a name and a value".to_string())),
+            };
+            let snapshots = interpreter.snapshots().ok_or_else(|| {
+                PrismError::RuntimeError("snapshots are not enabled; run the script with prism test".to_string())
+            })?;
+            snapshots.check(name, value)?;
+            Ok(value.clone())
+        }),
+    });
+
+    // doc function: doc(fn) is the function's documentation, or nil
+    let doc_fn = Value::new(ValueKind::NativeFunction {
+        name: "doc".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            let function = function_arg(&args, "doc")?;
+            let doc = docs::find(&interpreter.global_values(), function).and_then(|found| found.doc);
+            Ok(match doc {
+                Some(doc) => Value::new(ValueKind::String(doc.summary)),
+                None => Value::new(ValueKind::Nil),
+            })
+        }),
+    });
+
+    // signature function: signature(fn) is e.g. "printf(template, values...)"
+    let signature_fn = Value::new(ValueKind::NativeFunction {
+        name: "signature".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            let function = function_arg(&args, "signature")?;
+            let signature = docs::signature(&interpreter.global_values(), function)
+                .expect("functions always have a signature");
+            Ok(Value::new(ValueKind::String(signature)))
+        }),
+    });
+
+    // members function: members(module) lists the exported names, sorted
+    let members_fn = Value::new(ValueKind::NativeFunction {
+        name: "members".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| match args.first().map(|arg| &arg.kind) {
+            Some(ValueKind::Module(module)) => {
+                let module = module.read();
+                let mut names: Vec<&String> = module.exports().map(|(name, _)| name).collect();
+                names.sort();
+                Ok(string_list(names))
+            }
+            _ => Err(PrismError::InvalidArgument("members expects a module".to_string())),
+        }),
+    });
+
+    // globals function: the names of every global, sorted
+    let globals_fn = Value::new(ValueKind::NativeFunction {
+        name: "globals".to_string(),
+        arity: 0,
+        handler: Arc::new(|interpreter, _| {
+            let globals = interpreter.global_values();
+            Ok(string_list(globals.iter().map(|(name, _)| name)))
         }),
     });
 
     {
         let mut module_guard = module.write();
-        module_guard.export("print".to_string(), print_fn)?;
-        module_guard.export("type".to_string(), type_fn)?;
-        module_guard.export("assert".to_string(), assert_fn)?;
+        module_guard.export_documented("print", print_fn, FunctionDoc::new(
+            &["values..."],
+            "Writes the values separated by spaces and ends the line.",
+        ))?;
+        module_guard.export_documented("println", println_fn, FunctionDoc::new(
+            &["values..."],
+            "The same as print.",
+        ))?;
+        module_guard.export_documented("printf", printf_fn, FunctionDoc::new(
+            &["template", "values..."],
+            "Writes the template without a newline, replacing each {} with the next value and {:.N} with a number rounded to N decimals.",
+        ).with_example("printf(\"{} has {:.1} C\", name, temp);"))?;
+        module_guard.export_documented("format", format_fn, FunctionDoc::new(
+            &["template", "values..."],
+            "The text printf would write, as a string.",
+        ))?;
+        module_guard.export_documented("type", type_fn, FunctionDoc::new(
+            &["value"],
+            "The value's type name, e.g. \"number\" or \"list\".",
+        ))?;
+        module_guard.export_documented("assert", assert_fn, FunctionDoc::new(
+            &["condition", "message"],
+            "Fails with the message unless the condition is true.",
+        ))?;
+        module_guard.export_documented("len", len_fn, FunctionDoc::new(
+            &["value"],
+            "The number of items in a list, map or range, or of characters in a string.",
+        ))?;
+        module_guard.export_documented("conf_of", conf_of_fn, FunctionDoc::new(
+            &["value"],
+            "The value's confidence, from 0 to 1.",
+        ))?;
+        module_guard.export_documented("range", range_fn, FunctionDoc::new(
+            &["start", "end", "step"],
+            "The numbers from start (default 0) up to but excluding end, produced lazily.",
+        )
+        .with_param("step", "1 by default; negative to count down")
+        .with_example("for (i in range(0, 10, 2)) { print(i); }"))?;
+        module_guard.export_documented("iter", iter_fn, FunctionDoc::new(
+            &["collection"],
+            "An iterator over a list, map or range.",
+        ))?;
+        module_guard.export_documented("str", str_fn, FunctionDoc::new(
+            &["value", "fallback"],
+            "The value as a string, keeping its confidence.",
+        ))?;
+        module_guard.export_documented("num", num_fn, FunctionDoc::new(
+            &["value", "fallback"],
+            "The value as a number; \"80%\" is 0.8. Without a fallback a failed conversion is an error.",
+        )
+        .with_param("fallback", "returned with confidence 0 when the value is not a number")
+        .with_example("let dose = num(input, 0);"))?;
+        module_guard.export_documented("bool", bool_fn, FunctionDoc::new(
+            &["value", "fallback"],
+            "The value as a boolean; a number from 0 to 1 is read as a probability.",
+        ))?;
+        module_guard.export_documented("query", query_fn, FunctionDoc::new(
+            &["value", "query"],
+            "The list of outputs of a jq-like query: .field, [n], [], | and select(condition).",
+        ).with_example("let names = query(response, \".results[] | select(.confidence > 0.8) | .name\");"))?;
+        module_guard.export_documented("get_in", get_in_fn, FunctionDoc::new(
+            &["value", "path", "default"],
+            "The value at a path of map keys and list indices, or the default (nil) when it is missing.",
+        ).with_example("let name = get_in(response, [\"results\", 0, \"name\"], \"unknown\");"))?;
+        module_guard.export_documented("set_in", set_in_fn, FunctionDoc::new(
+            &["value", "path", "new"],
+            "A copy of the value with the new value at the path; missing map keys are added.",
+        ).with_example("let response = set_in(response, [\"results\", 0, \"checked\"], true);"))?;
+        module_guard.export_documented("meta_get", meta_get_fn, FunctionDoc::new(
+            &["value", "key"],
+            "The value's metadata entry, or nil; without a key, all of its metadata as a map.",
+        ).with_example("let model = meta_get(answer, \"model\");"))?;
+        module_guard.export_documented("meta_set", meta_set_fn, FunctionDoc::new(
+            &["value", "key", "meta"],
+            "A copy of the value with the metadata entry set; assignments and returns keep metadata.",
+        ).with_example("let page = meta_set(page, \"source\", url);"))?;
+        module_guard.export_documented("snapshot", snapshot_fn, FunctionDoc::new(
+            &["name", "value"],
+            "Compares the value with its saved snapshot under prism test and returns it.",
+        ).with_example("snapshot(\"triage\", triage(patient));"))?;
+        module_guard.export_documented("now", crate::stdlib::time::now_fn(), FunctionDoc::new(
+            &["zone"],
+            "The current datetime, in UTC unless a zone such as \"local\" or \"+02:00\" is given.",
+        ).with_example("let elapsed = now() - started;"))?;
+        module_guard.export_documented("doc", doc_fn, FunctionDoc::new(
+            &["function"],
+            "The function's documentation, or nil.",
+        ))?;
+        module_guard.export_documented("signature", signature_fn, FunctionDoc::new(
+            &["function"],
+            "How to call the function, e.g. \"printf(template, values...)\".",
+        ))?;
+        module_guard.export_documented("members", members_fn, FunctionDoc::new(
+            &["module"],
+            "The names a module exports, sorted.",
+        ))?;
+        module_guard.export_documented("globals", globals_fn, FunctionDoc::new(
+            &[],
+            "The names of all globals, sorted: modules, prelude functions and top-level definitions.",
+        ))?;
     }
 
     Ok(module)
 }
+
+fn function_arg<'a>(args: &'a [Value], name: &str) -> Result<&'a Value> {
+    match args.first() {
+        Some(function @ Value {
+            kind: ValueKind::Function { .. } | ValueKind::NativeFunction { .. } | ValueKind::AsyncNativeFunction { .. },
+            ..
+        }) => Ok(function),
+        _ => Err(PrismError::InvalidArgument(format!("{} expects a function", name))),
+    }
+}
+
+fn string_list<'a>(names: impl IntoIterator<Item = &'a String>) -> Value {
+    Value::new(ValueKind::List(names.into_iter().map(|name| Value::new(ValueKind::String(name.clone()))).collect()))
+}
+
+fn print_line(interpreter: &Interpreter, args: Vec<Value>) -> Result<Value> {
+    let line: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    interpreter.write_output(&format!("{}\n", line.join(" ")));
+    Ok(Value::new(ValueKind::Nil))
+}
+
+fn format_args(args: &[Value]) -> Result<String> {
+    match args.split_first() {
+        Some((Value { kind: ValueKind::String(template), .. }, rest)) => format::format(template, rest),
+        _ => Err(PrismError::InvalidArgument("expected a format string".to_string())),
+    }
+}
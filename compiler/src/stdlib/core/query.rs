@@ -0,0 +1,502 @@
+//! Navigating nested values: `get_in`, `set_in` and jq-like `query`.
+//!
+//! A path for `get_in`/`set_in` is a list of map keys and list indices, e.g.
+//! `["results", 0, "name"]`; negative indices count from the end.
+//!
+//! A query is a pipeline of stages separated by `|`, each applied to every
+//! output of the one before: `.` is the input, `.name` a map field, `[n]` a
+//! list item, `[]` every item of a list or value of a map, and
+//! `select(condition)` keeps inputs for which a comparison holds, e.g.
+//! `.results[] | select(.confidence > 0.8 and .kind == "drug") | .name`.
+//! Missing fields and items are nil. A query returns the list of outputs.
+
+use crate::error::{PrismError, Result};
+use crate::value::{Value, ValueKind};
+
+fn invalid(message: impl Into<String>) -> PrismError {
+    PrismError::InvalidArgument(message.into())
+}
+
+fn nil() -> Value {
+    Value::new(ValueKind::Nil)
+}
+
+/// Where a negative `index` into `len` items lands.
+fn position(index: i64, len: usize) -> Option<usize> {
+    let position = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&position).then_some(position as usize)
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Result<Option<&'a Value>> {
+    match &value.kind {
+        ValueKind::Map(entries) => Ok(entries.iter().find(|(key, _)| key.to_string() == name).map(|(_, value)| value)),
+        ValueKind::Nil => Ok(None),
+        _ => Err(PrismError::RuntimeError(format!("Cannot read field \"{}\" of {}", name, value))),
+    }
+}
+
+fn item(value: &Value, index: i64) -> Result<Option<&Value>> {
+    match &value.kind {
+        ValueKind::List(items) => Ok(position(index, items.len()).map(|position| &items[position])),
+        ValueKind::Nil => Ok(None),
+        _ => Err(PrismError::RuntimeError(format!("Cannot read item {} of {}", index, value))),
+    }
+}
+
+/// One step of a path: a key or an index.
+fn step<'a>(value: &'a Value, key: &Value) -> Result<Option<&'a Value>> {
+    match &key.kind {
+        ValueKind::String(name) => field(value, name),
+        ValueKind::Number(index) if index.fract() == 0.0 => item(value, *index as i64),
+        _ => Err(invalid(format!("{} is not a map key or list index", key))),
+    }
+}
+
+/// The value at `path` inside `value`, if there is one.
+pub fn get_in(value: &Value, path: &[Value]) -> Result<Option<Value>> {
+    let mut current = value;
+    for key in path {
+        match step(current, key)? {
+            Some(next) => current = next,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(current.clone()))
+}
+
+/// A copy of `value` with `new` at `path`; missing map keys are added,
+/// nil along the way becomes a map.
+pub fn set_in(value: &Value, path: &[Value], new: Value) -> Result<Value> {
+    let Some((key, rest)) = path.split_first() else {
+        return Ok(new);
+    };
+    let mut updated = value.clone();
+    match (&mut updated.kind, &key.kind) {
+        (ValueKind::Nil, ValueKind::String(name)) => {
+            let inner = set_in(&nil(), rest, new)?;
+            updated.kind = ValueKind::Map(vec![(Value::new(ValueKind::String(name.clone())), inner)]);
+        }
+        (ValueKind::Map(entries), ValueKind::String(name)) => {
+            match entries.iter_mut().find(|(key, _)| key.to_string() == *name) {
+                Some((_, slot)) => *slot = set_in(slot, rest, new)?,
+                None => {
+                    let inner = set_in(&nil(), rest, new)?;
+                    entries.push((Value::new(ValueKind::String(name.clone())), inner));
+                }
+            }
+        }
+        (ValueKind::List(items), ValueKind::Number(index)) if index.fract() == 0.0 => {
+            let len = items.len();
+            let Some(position) = position(*index as i64, len) else {
+                return Err(PrismError::RuntimeError(format!("Index {} is out of range for {} items", index, len)));
+            };
+            items[position] = set_in(&items[position], rest, new)?;
+        }
+        _ => return Err(PrismError::RuntimeError(format!("Cannot set {} in {}", key, value))),
+    }
+    Ok(updated)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Dot,
+    Name(String),
+    Literal(ValueKind),
+    Open,
+    Close,
+    OpenParen,
+    CloseParen,
+    Pipe,
+    Compare(&'static str),
+    And,
+    Or,
+    Select,
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = query.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '.' => Token::Dot,
+            '[' => Token::Open,
+            ']' => Token::Close,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            '|' => Token::Pipe,
+            '=' | '!' | '<' | '>' => {
+                let equals = chars.next_if(|(_, next)| *next == '=').is_some();
+                Token::Compare(match (c, equals) {
+                    ('=', true) => "==",
+                    ('!', true) => "!=",
+                    ('<', true) => "<=",
+                    ('>', true) => ">=",
+                    ('<', false) => "<",
+                    ('>', false) => ">",
+                    _ => return Err(invalid(format!("Unexpected '{}' in query \"{}\"", c, query))),
+                })
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => text.extend(chars.next().map(|(_, c)| c)),
+                        Some((_, c)) => text.push(c),
+                        None => return Err(invalid(format!("Unterminated string in query \"{}\"", query))),
+                    }
+                }
+                Token::Literal(ValueKind::String(text))
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut end = start + c.len_utf8();
+                while let Some((index, _)) = chars.next_if(|(_, next)| next.is_ascii_digit() || *next == '.') {
+                    end = index + 1;
+                }
+                let number = query[start..end]
+                    .parse()
+                    .map_err(|_| invalid(format!("Invalid number '{}' in query \"{}\"", &query[start..end], query)))?;
+                Token::Literal(ValueKind::Number(number))
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some((index, next)) = chars.next_if(|(_, next)| next.is_alphanumeric() || *next == '_') {
+                    end = index + next.len_utf8();
+                }
+                match &query[start..end] {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "select" => Token::Select,
+                    "true" => Token::Literal(ValueKind::Boolean(true)),
+                    "false" => Token::Literal(ValueKind::Boolean(false)),
+                    "null" | "nil" => Token::Literal(ValueKind::Nil),
+                    name => Token::Name(name.to_string()),
+                }
+            }
+            c => return Err(invalid(format!("Unexpected '{}' in query \"{}\"", c, query))),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Field(String),
+    Item(i64),
+    Each,
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+    Truthy(Vec<Step>),
+    Compare(Vec<Step>, &'static str, ValueKind),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+#[derive(Debug, Clone)]
+enum Stage {
+    Path(Vec<Step>),
+    Select(Condition),
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    query: &'a str,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn error(&self, expected: &str) -> PrismError {
+        invalid(format!("Expected {} in query \"{}\"", expected, self.query))
+    }
+
+    fn expect(&mut self, token: Token, expected: &str) -> Result<()> {
+        if self.next() == Some(token) { Ok(()) } else { Err(self.error(expected)) }
+    }
+
+    fn pipeline(&mut self) -> Result<Vec<Stage>> {
+        let mut stages = vec![self.stage()?];
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            stages.push(self.stage()?);
+        }
+        if self.peek().is_some() {
+            return Err(self.error("'|' or the end"));
+        }
+        Ok(stages)
+    }
+
+    fn stage(&mut self) -> Result<Stage> {
+        if self.peek() == Some(&Token::Select) {
+            self.next();
+            self.expect(Token::OpenParen, "'(' after select")?;
+            let condition = self.or()?;
+            self.expect(Token::CloseParen, "')' after the condition")?;
+            return Ok(Stage::Select(condition));
+        }
+        Ok(Stage::Path(self.path()?))
+    }
+
+    /// `.`, then any of `name`, `.name`, `[n]` and `[]`.
+    fn path(&mut self) -> Result<Vec<Step>> {
+        self.expect(Token::Dot, "a path starting with '.'")?;
+        let mut steps = Vec::new();
+        if let Some(Token::Name(name) | Token::Literal(ValueKind::String(name))) = self.peek().cloned() {
+            self.next();
+            steps.push(Step::Field(name));
+        }
+        loop {
+            match self.peek() {
+                Some(Token::Dot) => {
+                    self.next();
+                    match self.next() {
+                        Some(Token::Name(name) | Token::Literal(ValueKind::String(name))) => steps.push(Step::Field(name)),
+                        _ => return Err(self.error("a field name after '.'")),
+                    }
+                }
+                Some(Token::Open) => {
+                    self.next();
+                    match self.next() {
+                        Some(Token::Close) => steps.push(Step::Each),
+                        Some(Token::Literal(ValueKind::Number(n))) if n.fract() == 0.0 => {
+                            self.expect(Token::Close, "']'")?;
+                            steps.push(Step::Item(n as i64));
+                        }
+                        Some(Token::Literal(ValueKind::String(name))) => {
+                            self.expect(Token::Close, "']'")?;
+                            steps.push(Step::Field(name));
+                        }
+                        _ => return Err(self.error("an index, a quoted key or ']'")),
+                    }
+                }
+                _ => return Ok(steps),
+            }
+        }
+    }
+
+    fn or(&mut self) -> Result<Condition> {
+        let mut condition = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> Result<Condition> {
+        let mut condition = self.comparison()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            condition = Condition::And(Box::new(condition), Box::new(self.comparison()?));
+        }
+        Ok(condition)
+    }
+
+    fn comparison(&mut self) -> Result<Condition> {
+        if self.peek() == Some(&Token::OpenParen) {
+            self.next();
+            let condition = self.or()?;
+            self.expect(Token::CloseParen, "')'")?;
+            return Ok(condition);
+        }
+        let path = self.path()?;
+        let Some(Token::Compare(operator)) = self.peek().cloned() else {
+            return Ok(Condition::Truthy(path));
+        };
+        self.next();
+        match self.next() {
+            Some(Token::Literal(literal)) => Ok(Condition::Compare(path, operator, literal)),
+            _ => Err(self.error("a literal after the comparison")),
+        }
+    }
+}
+
+/// Every value `steps` lead to from `value`.
+fn walk(value: &Value, steps: &[Step]) -> Result<Vec<Value>> {
+    let Some((first, rest)) = steps.split_first() else {
+        return Ok(vec![value.clone()]);
+    };
+    let next: Vec<Value> = match first {
+        Step::Field(name) => vec![field(value, name)?.cloned().unwrap_or_else(nil)],
+        Step::Item(index) => vec![item(value, *index)?.cloned().unwrap_or_else(nil)],
+        Step::Each => match &value.kind {
+            ValueKind::List(items) => items.clone(),
+            ValueKind::Map(entries) => entries.iter().map(|(_, value)| value.clone()).collect(),
+            _ => return Err(PrismError::RuntimeError(format!("Cannot iterate over {}", value))),
+        },
+    };
+    let mut outputs = Vec::new();
+    for value in &next {
+        outputs.extend(walk(value, rest)?);
+    }
+    Ok(outputs)
+}
+
+fn compare(left: &ValueKind, operator: &str, right: &ValueKind) -> bool {
+    let ordering = match (left, right) {
+        (ValueKind::Number(l), ValueKind::Number(r)) => l.partial_cmp(r),
+        (ValueKind::String(l), ValueKind::String(r)) => Some(l.cmp(r)),
+        _ => None,
+    };
+    match operator {
+        "==" => left == right,
+        "!=" => left != right,
+        "<" => ordering.is_some_and(|o| o.is_lt()),
+        "<=" => ordering.is_some_and(|o| o.is_le()),
+        ">" => ordering.is_some_and(|o| o.is_gt()),
+        _ => ordering.is_some_and(|o| o.is_ge()),
+    }
+}
+
+fn holds(value: &Value, condition: &Condition) -> Result<bool> {
+    Ok(match condition {
+        Condition::Truthy(path) => walk(value, path)?
+            .iter()
+            .any(|found| !matches!(found.kind, ValueKind::Nil | ValueKind::Boolean(false))),
+        Condition::Compare(path, operator, literal) => {
+            walk(value, path)?.iter().any(|found| compare(&found.kind, operator, literal))
+        }
+        Condition::And(left, right) => holds(value, left)? && holds(value, right)?,
+        Condition::Or(left, right) => holds(value, left)? || holds(value, right)?,
+    })
+}
+
+/// The outputs of `query` run on `value`.
+pub fn query(value: &Value, query: &str) -> Result<Vec<Value>> {
+    let tokens = tokenize(query)?;
+    let stages = Parser { tokens, position: 0, query }.pipeline()?;
+    let mut stream = vec![value.clone()];
+    for stage in &stages {
+        let mut next = Vec::new();
+        for value in &stream {
+            match stage {
+                Stage::Path(steps) => next.extend(walk(value, steps)?),
+                Stage::Select(condition) => {
+                    if holds(value, condition)? {
+                        next.push(value.clone());
+                    }
+                }
+            }
+        }
+        stream = next;
+    }
+    Ok(stream)
+}
+
+fn path_arg<'a>(name: &str, path: Option<&'a Value>) -> Result<&'a [Value]> {
+    match path.map(|path| &path.kind) {
+        Some(ValueKind::List(keys)) => Ok(keys),
+        _ => Err(invalid(format!("{} expects a value and a path such as [\"results\", 0, \"name\"]", name))),
+    }
+}
+
+/// `query(value, query)`: the list of the query's outputs.
+pub fn query_fn(args: &[Value]) -> Result<Value> {
+    let (value, text) = match args {
+        [value, Value { kind: ValueKind::String(text), .. }] => (value, text),
+        _ => return Err(invalid("query expects a value and a query such as \".results[] | .name\"")),
+    };
+    Ok(Value::new(ValueKind::List(query(value, text)?)))
+}
+
+/// `get_in(value, path, default?)`: the value at the path, else the default
+/// or nil.
+pub fn get_in_fn(args: &[Value]) -> Result<Value> {
+    let path = path_arg("get_in", args.get(1))?;
+    Ok(get_in(&args[0], path)?.or_else(|| args.get(2).cloned()).unwrap_or_else(nil))
+}
+
+/// `set_in(value, path, new)`: a copy of the value with `new` at the path.
+pub fn set_in_fn(args: &[Value]) -> Result<Value> {
+    let path = path_arg("set_in", args.get(1))?;
+    let Some(new) = args.get(2) else {
+        return Err(invalid("set_in expects a value, a path and the new value"));
+    };
+    set_in(&args[0], path, new.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    fn results() -> Value {
+        Value::from_json(&serde_json::json!({
+            "results": [
+                { "name": "ibuprofen", "confidence": 0.9, "kind": "drug" },
+                { "name": "rest", "confidence": 0.95, "kind": "advice" },
+                { "name": "aspirin", "confidence": 0.6, "kind": "drug" }
+            ]
+        }))
+    }
+
+    fn shown(values: Vec<Value>) -> Vec<String> {
+        values.iter().map(Value::to_string).collect()
+    }
+
+    #[test]
+    fn test_queries_filter_and_project() -> Result<()> {
+        let value = results();
+        assert_eq!(shown(query(&value, ".results[] | select(.confidence > 0.8) | .name")?), ["ibuprofen", "rest"]);
+        assert_eq!(
+            shown(query(&value, r#".results[] | select(.kind == "drug" and (.confidence >= 0.9 or .name == "aspirin")) | .name"#)?),
+            ["ibuprofen", "aspirin"]
+        );
+        assert_eq!(shown(query(&value, ".results[-1].name")?), ["aspirin"]);
+        assert_eq!(shown(query(&value, ".results[5].name")?), ["nil"]);
+        assert_eq!(shown(query(&value, r#".["results"][0] | .missing"#)?), ["nil"]);
+        assert_eq!(query(&value, ".")?, vec![value.clone()]);
+        assert!(query(&value, ".results[0].name.first").is_err());
+        assert!(query(&value, "results").is_err());
+        assert!(query(&value, ".results[] | select(.confidence >)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_paths_read_and_update_copies() -> Result<()> {
+        let value = results();
+        let path = |keys: serde_json::Value| match Value::from_json(&keys).kind {
+            ValueKind::List(keys) => keys,
+            _ => unreachable!(),
+        };
+        let name = get_in(&value, &path(serde_json::json!(["results", 1, "name"])))?;
+        assert_eq!(name.map(|name| name.to_string()).as_deref(), Some("rest"));
+        assert_eq!(get_in(&value, &path(serde_json::json!(["results", 7, "name"])))?, None);
+
+        let updated = set_in(&value, &path(serde_json::json!(["results", -1, "dose", "mg"])), Value::new(ValueKind::Number(300.0)))?;
+        assert_eq!(shown(query(&updated, ".results[2].dose.mg")?), ["300"]);
+        assert_eq!(shown(query(&value, ".results[2].dose")?), ["nil"]);
+        assert!(set_in(&value, &path(serde_json::json!(["results", 3])), nil()).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_and_paths_navigate_nested_values() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let response = {"results": [{"name": "ibuprofen", "confidence": 0.9}, {"name": "rest", "confidence": 0.4}]};
+            let checked = set_in(response, ["results", 0, "checked"], true);
+            [query(response, ".results[] | select(.confidence > 0.8) | .name"), get_in(response, ["results", -1, "name"]),
+             get_in(response, ["results", 5, "name"], "unknown"), get_in(checked, ["results", 0, "checked"]),
+             get_in(response, ["results", 0, "checked"])];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[[ibuprofen], rest, unknown, true, nil]");
+        let err = interpreter.evaluate(r#"query([1], ".[0] |");"#.to_string()).await.unwrap_err();
+        assert!(err.to_string().contains("Expected a path starting with '.'"), "{}", err);
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+//! `db.open(path)`: SQLite databases, behind the `sqlite` cargo feature.
+//!
+//! The handle is a map of functions: `query(sql, params)` returns the rows as
+//! a list of maps keyed by column name, and `execute(sql, params)` returns
+//! the number of changed rows. Parameters are a list bound to `?` in order.
+
+use std::sync::Arc;
+use parking_lot::{Mutex, RwLock};
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::Connection;
+use crate::capabilities::Capability;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{NativeHandler, Value, ValueKind};
+
+pub fn init_db_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("db".to_string())));
+
+    // open function: opens or creates the database file; ":memory:" is a
+    // private in-memory database
+    let open_fn = Value::new(ValueKind::NativeFunction {
+        name: "open".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            interpreter.capabilities().require(Capability::Fs, "db.open")?;
+            let path = match args.first().map(|arg| &arg.kind) {
+                Some(ValueKind::String(path)) => path,
+                _ => return Err(PrismError::InvalidArgument("db.open expects a path".to_string())),
+            };
+            let connection = Connection::open(path).map_err(sql_error)?;
+            Ok(handles(Arc::new(Mutex::new(connection))))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("open".to_string(), open_fn)?;
+    }
+
+    Ok(module)
+}
+
+fn handles(connection: Arc<Mutex<Connection>>) -> Value {
+    let function = |name: &str, handler: NativeHandler| {
+        (
+            Value::new(ValueKind::String(name.to_string())),
+            Value::new(ValueKind::NativeFunction { name: name.to_string(), arity: 2, handler }),
+        )
+    };
+
+    let queries = Arc::clone(&connection);
+    Value::new(ValueKind::Map(vec![
+        function("query", Arc::new(move |_, args| {
+            let (sql, params) = statement(&args, "query")?;
+            Ok(Value::new(ValueKind::List(query(&queries.lock(), sql, &params)?)))
+        })),
+        function("execute", Arc::new(move |_, args| {
+            let (sql, params) = statement(&args, "execute")?;
+            let changed = connection
+                .lock()
+                .execute(sql, rusqlite::params_from_iter(params))
+                .map_err(sql_error)?;
+            Ok(Value::new(ValueKind::Number(changed as f64)))
+        })),
+    ]))
+}
+
+fn query(connection: &Connection, sql: &str, params: &[SqlValue]) -> Result<Vec<Value>> {
+    let mut statement = connection.prepare(sql).map_err(sql_error)?;
+    let columns: Vec<String> = statement.column_names().into_iter().map(String::from).collect();
+    let mut rows = statement.query(rusqlite::params_from_iter(params)).map_err(sql_error)?;
+
+    let mut results = Vec::new();
+    while let Some(row) = rows.next().map_err(sql_error)? {
+        let mut fields = Vec::with_capacity(columns.len());
+        for (index, column) in columns.iter().enumerate() {
+            let value = from_sql(row.get_ref(index).map_err(sql_error)?);
+            fields.push((Value::new(ValueKind::String(column.clone())), value));
+        }
+        results.push(Value::new(ValueKind::Map(fields)));
+    }
+    Ok(results)
+}
+
+/// The SQL text and bound parameters of a `query` or `execute` call.
+fn statement<'a>(args: &'a [Value], function: &str) -> Result<(&'a str, Vec<SqlValue>)> {
+    let sql = match args.first().map(|arg| &arg.kind) {
+        Some(ValueKind::String(sql)) => sql,
+        _ => return Err(PrismError::InvalidArgument(format!("{} expects an SQL string", function))),
+    };
+    let params = match args.get(1).map(|arg| &arg.kind) {
+        None | Some(ValueKind::Nil) => Vec::new(),
+        Some(ValueKind::List(items)) => items.iter().map(to_sql).collect::<Result<_>>()?,
+        Some(_) => return Err(PrismError::InvalidArgument(format!("{} parameters must be a list", function))),
+    };
+    Ok((sql, params))
+}
+
+fn to_sql(value: &Value) -> Result<SqlValue> {
+    Ok(match &value.kind {
+        ValueKind::Nil => SqlValue::Null,
+        ValueKind::Boolean(b) => SqlValue::Integer(*b as i64),
+        ValueKind::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => SqlValue::Integer(*n as i64),
+        ValueKind::Number(n) => SqlValue::Real(*n),
+        ValueKind::String(s) => SqlValue::Text(s.clone()),
+        _ => return Err(PrismError::InvalidArgument(format!("{} cannot be stored in SQLite", value))),
+    })
+}
+
+fn from_sql(value: ValueRef<'_>) -> Value {
+    Value::new(match value {
+        ValueRef::Null => ValueKind::Nil,
+        ValueRef::Integer(i) => ValueKind::Number(i as f64),
+        ValueRef::Real(r) => ValueKind::Number(r),
+        ValueRef::Text(text) => ValueKind::String(String::from_utf8_lossy(text).into_owned()),
+        ValueRef::Blob(bytes) => ValueKind::List(
+            bytes.iter().map(|byte| Value::new(ValueKind::Number(*byte as f64))).collect(),
+        ),
+    })
+}
+
+fn sql_error(err: rusqlite::Error) -> PrismError {
+    PrismError::RuntimeError(format!("SQLite: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_returns_maps() -> Result<()> {
+        let connection = Connection::open_in_memory().map_err(sql_error)?;
+        connection
+            .execute_batch(
+                "CREATE TABLE cases (diagnosis TEXT, score REAL, confirmed INTEGER);
+                 INSERT INTO cases VALUES ('flu', 0.8, 1), ('cold', 0.3, NULL);",
+            )
+            .map_err(sql_error)?;
+
+        let params = [to_sql(&Value::new(ValueKind::Number(0.5)))?];
+        let rows = query(&connection, "SELECT diagnosis, score, confirmed FROM cases WHERE score > ?", &params)?;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].to_string(), "{diagnosis: flu, score: 0.8, confirmed: 1}");
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+//! Exact decimal arithmetic, behind the `decimal` cargo feature.
+//!
+//! A decimal literal ends in `d`, e.g. `19.99d`, and is held as an
+//! arbitrary precision decimal, so `0.1d + 0.2d == 0.3d` and sums of money
+//! do not drift. Decimals keep the scale they were written with (`1.50d`
+//! shows as `1.50`). Adding, subtracting and multiplying is exact; dividing
+//! rounds to [`DIVISION_PLACES`] places half to even, and `decimal.div`
+//! takes the places and [rounding mode](Rounding) explicitly.
+//!
+//! Mixing a decimal and a number makes a decimal: the number converts as the
+//! shortest decimal that reads back as it, so `0.1` becomes `0.1d`. Going the
+//! other way, with `decimal.to_number` or `num`, can lose digits; when it
+//! does, the number's confidence is scaled by [`LOSSY_CONVERSION_CONFIDENCE`].
+
+use std::str::FromStr;
+use std::sync::Arc;
+use bigdecimal::{BigDecimal, RoundingMode, ToPrimitive, Zero};
+use parking_lot::RwLock;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::token::TokenKind;
+use crate::value::{Value, ValueKind};
+
+/// Decimal places a quotient is rounded to.
+pub const DIVISION_PLACES: i64 = 28;
+
+/// How confident a number is, relative to the decimal it came from, when
+/// the decimal has more digits than an `f64` holds.
+pub const LOSSY_CONVERSION_CONFIDENCE: f64 = 0.95;
+
+/// How a decimal is rounded to fewer places.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    /// To the nearest, ties to the even neighbour: banker's rounding.
+    #[default]
+    HalfEven,
+    /// To the nearest, ties away from zero.
+    HalfUp,
+    /// To the nearest, ties towards zero.
+    HalfDown,
+    /// Away from zero.
+    Up,
+    /// Towards zero: truncation.
+    Down,
+    Ceiling,
+    Floor,
+}
+
+impl Rounding {
+    fn mode(self) -> RoundingMode {
+        match self {
+            Rounding::HalfEven => RoundingMode::HalfEven,
+            Rounding::HalfUp => RoundingMode::HalfUp,
+            Rounding::HalfDown => RoundingMode::HalfDown,
+            Rounding::Up => RoundingMode::Up,
+            Rounding::Down => RoundingMode::Down,
+            Rounding::Ceiling => RoundingMode::Ceiling,
+            Rounding::Floor => RoundingMode::Floor,
+        }
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = PrismError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Ok(match name {
+            "half_even" => Rounding::HalfEven,
+            "half_up" => Rounding::HalfUp,
+            "half_down" => Rounding::HalfDown,
+            "up" => Rounding::Up,
+            "down" => Rounding::Down,
+            "ceiling" => Rounding::Ceiling,
+            "floor" => Rounding::Floor,
+            _ => {
+                return Err(PrismError::InvalidArgument(format!(
+                    "Unknown rounding mode '{}'; expected half_even, half_up, half_down, up, down, ceiling or floor",
+                    name
+                )))
+            }
+        })
+    }
+}
+
+/// Reads a decimal as written, e.g. `"19.99"` or `"-1.5e3"`.
+pub fn parse(text: &str) -> Result<BigDecimal> {
+    BigDecimal::from_str(text.trim())
+        .map_err(|_| PrismError::InvalidArgument(format!("cannot convert \"{}\" to a decimal", text)))
+}
+
+/// The shortest decimal that reads back as `n`.
+pub fn from_number(n: f64) -> Result<BigDecimal> {
+    if !n.is_finite() {
+        return Err(PrismError::InvalidArgument(format!("{} has no decimal form", n)));
+    }
+    parse(&n.to_string())
+}
+
+/// The number nearest `decimal`, and how confident it is relative to it:
+/// 1 when it converts back to the same decimal.
+pub fn to_number(decimal: &BigDecimal) -> (f64, f64) {
+    let n = decimal.to_f64().unwrap_or(f64::NAN);
+    let exact = from_number(n).is_ok_and(|back| &back == decimal);
+    (n, if exact { 1.0 } else { LOSSY_CONVERSION_CONFIDENCE })
+}
+
+/// `decimal` rounded to `places` after the point.
+pub fn round(decimal: &BigDecimal, places: i64, rounding: Rounding) -> BigDecimal {
+    decimal.with_scale_round(places, rounding.mode())
+}
+
+/// `left / right` rounded to `places`, without trailing zeros.
+pub fn divide(left: &BigDecimal, right: &BigDecimal, places: i64, rounding: Rounding) -> Result<BigDecimal> {
+    if right.is_zero() {
+        return Err(PrismError::RuntimeError("Division by zero".to_string()));
+    }
+    let quotient = round(&(left / right), places, rounding).normalized();
+    // Whole quotients keep their zeros: 100, not 1E+2
+    Ok(if quotient.fractional_digit_count() < 0 { quotient.with_scale(0) } else { quotient })
+}
+
+/// The result of a binary operator on two decimals.
+pub fn binary(operator: &TokenKind, left: &BigDecimal, right: &BigDecimal) -> Result<ValueKind> {
+    Ok(match operator {
+        TokenKind::Plus => ValueKind::Decimal(left + right),
+        TokenKind::Minus => ValueKind::Decimal(left - right),
+        TokenKind::Star => ValueKind::Decimal(left * right),
+        TokenKind::Slash => ValueKind::Decimal(divide(left, right, DIVISION_PLACES, Rounding::HalfEven)?),
+        TokenKind::Greater => ValueKind::Boolean(left > right),
+        TokenKind::GreaterEqual => ValueKind::Boolean(left >= right),
+        TokenKind::Less => ValueKind::Boolean(left < right),
+        TokenKind::LessEqual => ValueKind::Boolean(left <= right),
+        TokenKind::EqualEqual => ValueKind::Boolean(left == right),
+        TokenKind::BangEqual => ValueKind::Boolean(left != right),
+        _ => return Err(PrismError::RuntimeError("Invalid operator for decimals".to_string())),
+    })
+}
+
+/// A decimal, number or numeric string argument as a decimal.
+fn decimal_arg(name: &str, arg: Option<&Value>) -> Result<BigDecimal> {
+    match arg.map(|arg| &arg.kind) {
+        Some(ValueKind::Decimal(decimal)) => Ok(decimal.clone()),
+        Some(ValueKind::Number(n)) => from_number(*n),
+        Some(ValueKind::String(text)) => parse(text),
+        _ => Err(PrismError::InvalidArgument(format!("decimal.{} expects a decimal", name))),
+    }
+}
+
+fn places_arg(name: &str, arg: Option<&Value>) -> Result<i64> {
+    match arg.map(|arg| &arg.kind) {
+        Some(ValueKind::Number(n)) if n.fract() == 0.0 && *n >= 0.0 => Ok(*n as i64),
+        _ => Err(PrismError::InvalidArgument(format!("decimal.{} expects a whole number of places", name))),
+    }
+}
+
+fn rounding_arg(name: &str, arg: Option<&Value>) -> Result<Rounding> {
+    match arg.map(|arg| &arg.kind) {
+        None | Some(ValueKind::Nil) => Ok(Rounding::default()),
+        Some(ValueKind::String(mode)) => mode.parse(),
+        Some(_) => Err(PrismError::InvalidArgument(format!("decimal.{} expects a rounding mode name", name))),
+    }
+}
+
+/// The confidence of the first argument, which results keep.
+fn confidence(args: &[Value]) -> f64 {
+    args.first().map_or(1.0, |arg| arg.confidence)
+}
+
+fn native(name: &str, handler: impl Fn(&[Value]) -> Result<Value> + Send + Sync + 'static) -> Value {
+    Value::new(ValueKind::NativeFunction {
+        name: name.to_string(),
+        arity: 1,
+        handler: Arc::new(move |_, args| handler(&args)),
+    })
+}
+
+pub fn init_decimal_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("decimal".to_string())));
+    {
+        let mut module = module.write();
+        module.export(
+            "of".to_string(),
+            native("of", |args| {
+                let decimal = decimal_arg("of", args.first())?;
+                Ok(Value::with_confidence(ValueKind::Decimal(decimal), confidence(args)))
+            }),
+        )?;
+        module.export(
+            "to_number".to_string(),
+            native("to_number", |args| {
+                let (n, factor) = to_number(&decimal_arg("to_number", args.first())?);
+                Ok(Value::with_confidence(ValueKind::Number(n), confidence(args) * factor))
+            }),
+        )?;
+        module.export(
+            "round".to_string(),
+            native("round", |args| {
+                let decimal = decimal_arg("round", args.first())?;
+                let places = places_arg("round", args.get(1))?;
+                let rounding = rounding_arg("round", args.get(2))?;
+                Ok(Value::with_confidence(ValueKind::Decimal(round(&decimal, places, rounding)), confidence(args)))
+            }),
+        )?;
+        module.export(
+            "div".to_string(),
+            native("div", |args| {
+                let left = decimal_arg("div", args.first())?;
+                let right = decimal_arg("div", args.get(1))?;
+                let places = places_arg("div", args.get(2))?;
+                let rounding = rounding_arg("div", args.get(3))?;
+                let quotient = divide(&left, &right, places, rounding)?;
+                Ok(Value::with_confidence(ValueKind::Decimal(quotient), confidence(args)))
+            }),
+        )?;
+    }
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    fn decimal(text: &str) -> BigDecimal {
+        parse(text).unwrap()
+    }
+
+    #[test]
+    fn test_rounding_modes() -> Result<()> {
+        let cases = [
+            ("half_even", ["2", "-2", "3"]),
+            ("half_up", ["3", "-3", "3"]),
+            ("half_down", ["2", "-2", "3"]),
+            ("up", ["3", "-3", "3"]),
+            ("down", ["2", "-2", "2"]),
+            ("ceiling", ["3", "-2", "3"]),
+            ("floor", ["2", "-3", "2"]),
+        ];
+        for (mode, expected) in cases {
+            let rounding: Rounding = mode.parse()?;
+            let rounded = ["2.5", "-2.5", "2.51"].map(|text| round(&decimal(text), 0, rounding).to_string());
+            assert_eq!(rounded, expected, "{}", mode);
+        }
+        assert!("bankers".parse::<Rounding>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_division_rounds_and_conversions_report_loss() -> Result<()> {
+        assert_eq!(divide(&decimal("1"), &decimal("3"), 4, Rounding::HalfEven)?.to_string(), "0.3333");
+        assert_eq!(divide(&decimal("2"), &decimal("3"), 2, Rounding::Down)?.to_string(), "0.66");
+        assert_eq!(divide(&decimal("300"), &decimal("3"), DIVISION_PLACES, Rounding::HalfEven)?.to_string(), "100");
+        assert!(divide(&decimal("1"), &decimal("0"), 2, Rounding::HalfEven).is_err());
+
+        assert_eq!(from_number(0.1)?, decimal("0.1"));
+        assert!(from_number(f64::NAN).is_err());
+        assert_eq!(to_number(&decimal("19.99")), (19.99, 1.0));
+        assert_eq!(to_number(&decimal("0.1234567890123456789")).1, LOSSY_CONVERSION_CONFIDENCE);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decimal_arithmetic_is_exact() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let total = 0.1d + 0.2d;
+            let price = 19.99d * 3 - 0.97d;
+            let share = decimal.div(10d, 3d, 2, "down");
+            [total == 0.3d, str(price), type(price), str(1d / 8), str(share), str(-2.50d), 0.1 + 0.2 == 0.3];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[true, 59.00, decimal, 0.125, 3.33, -2.50, false]");
+
+        let lossy = interpreter.evaluate("num(decimal.of(\"0.12345678901234567890123\") ~> 0.8);".to_string()).await?;
+        assert!((lossy.confidence - 0.8 * LOSSY_CONVERSION_CONFIDENCE).abs() < 1e-9);
+        let exact = interpreter.evaluate("decimal.to_number(12.5d);".to_string()).await?;
+        assert_eq!((exact.kind, exact.confidence), (ValueKind::Number(12.5), 1.0));
+        assert!(interpreter.evaluate("1d / 0;".to_string()).await.is_err());
+        Ok(())
+    }
+}
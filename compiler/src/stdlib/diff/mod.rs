@@ -0,0 +1,135 @@
+//! The `diff` module: comparing texts, e.g. a prompt's output before and
+//! after a change, or several sampled completions with each other.
+//!
+//! `diff.lines` and `diff.words` return the changed hunks only. A hunk is a
+//! map `{op, old_start, old, new_start, new}` where `op` is `"insert"`,
+//! `"delete"` or `"replace"`, the starts are 1-based positions and `old`/`new`
+//! list the lines or words involved.
+
+use std::sync::Arc;
+use parking_lot::RwLock;
+use similar::{capture_diff_slices, Algorithm, DiffTag, TextDiff};
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{Value, ValueKind};
+
+pub fn init_diff_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("diff".to_string())));
+
+    // lines function
+    let lines_fn = Value::new(ValueKind::NativeFunction {
+        name: "lines".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let (old, new) = texts(&args, "diff.lines")?;
+            Ok(hunks(&old.lines().collect::<Vec<_>>(), &new.lines().collect::<Vec<_>>()))
+        }),
+    });
+
+    // words function
+    let words_fn = Value::new(ValueKind::NativeFunction {
+        name: "words".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let (old, new) = texts(&args, "diff.words")?;
+            Ok(hunks(
+                &old.split_whitespace().collect::<Vec<_>>(),
+                &new.split_whitespace().collect::<Vec<_>>(),
+            ))
+        }),
+    });
+
+    // similarity function: 1.0 for identical texts down to 0.0 for texts
+    // without a character in common
+    let similarity_fn = Value::new(ValueKind::NativeFunction {
+        name: "similarity".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let (old, new) = texts(&args, "diff.similarity")?;
+            Ok(Value::new(ValueKind::Number(similarity(old, new))))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("lines".to_string(), lines_fn)?;
+        module.export("words".to_string(), words_fn)?;
+        module.export("similarity".to_string(), similarity_fn)?;
+    }
+
+    Ok(module)
+}
+
+fn hunks(old: &[&str], new: &[&str]) -> Value {
+    let string = |text: &str| Value::new(ValueKind::String(text.to_string()));
+    let items = |items: &[&str]| Value::new(ValueKind::List(items.iter().map(|item| string(item)).collect()));
+
+    let hunks = capture_diff_slices(Algorithm::Myers, old, new)
+        .into_iter()
+        .filter_map(|op| {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            let op = match tag {
+                DiffTag::Equal => return None,
+                DiffTag::Insert => "insert",
+                DiffTag::Delete => "delete",
+                DiffTag::Replace => "replace",
+            };
+            Some(Value::new(ValueKind::Map(vec![
+                (string("op"), string(op)),
+                (string("old_start"), Value::new(ValueKind::Number((old_range.start + 1) as f64))),
+                (string("old"), items(&old[old_range])),
+                (string("new_start"), Value::new(ValueKind::Number((new_range.start + 1) as f64))),
+                (string("new"), items(&new[new_range])),
+            ])))
+        })
+        .collect();
+    Value::new(ValueKind::List(hunks))
+}
+
+/// Character-level similarity from 0.0 (nothing in common) to 1.0 (equal).
+pub fn similarity(old: &str, new: &str) -> f64 {
+    let total = old.chars().count() + new.chars().count();
+    if total == 0 {
+        return 1.0;
+    }
+    let matched: usize = TextDiff::from_chars(old, new)
+        .ops()
+        .iter()
+        .filter(|op| op.tag() == DiffTag::Equal)
+        .map(|op| op.old_range().len())
+        .sum();
+    2.0 * matched as f64 / total as f64
+}
+
+fn texts<'a>(args: &'a [Value], name: &str) -> Result<(&'a str, &'a str)> {
+    match (args.first().map(|arg| &arg.kind), args.get(1).map(|arg| &arg.kind)) {
+        (Some(ValueKind::String(old)), Some(ValueKind::String(new))) => Ok((old, new)),
+        _ => Err(PrismError::InvalidArgument(format!("{} expects two strings", name))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_hunks() {
+        let hunks = hunks(
+            &"the patient has a mild fever".split_whitespace().collect::<Vec<_>>(),
+            &"the patient has a high fever today".split_whitespace().collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            hunks.to_string(),
+            "[{op: replace, old_start: 5, old: [mild], new_start: 5, new: [high]}, \
+             {op: insert, old_start: 7, old: [], new_start: 7, new: [today]}]"
+        );
+    }
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity("flu", "flu"), 1.0);
+        assert_eq!(similarity("abc", "xyz"), 0.0);
+        assert_eq!(similarity("", ""), 1.0);
+        assert!((similarity("fever", "fevers") - 10.0 / 11.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+use parking_lot::RwLock;
+use crate::capabilities::Capability;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::secrets::looks_secret;
+use crate::value::{Value, ValueKind};
+
+pub fn init_env_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("env".to_string())));
+
+    // get function: the variable's value, or nil when it is unset. The host's
+    // `.env` file is already part of the process environment via `prism::init`,
+    // and hosts may set more with `Interpreter::with_env_vars`.
+    let get_fn = Value::new(ValueKind::NativeFunction {
+        name: "get".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            interpreter.capabilities().require(Capability::Env, "env.get")?;
+            let name = variable_name(&args)?;
+            match interpreter.env_var(name) {
+                Some(value) => {
+                    // Credentials must not leak into output or the audit log
+                    if looks_secret(name) {
+                        interpreter.secrets().register(value.clone());
+                    }
+                    Ok(Value::new(ValueKind::String(value)))
+                }
+                None => Ok(Value::new(ValueKind::Nil)),
+            }
+        }),
+    });
+
+    // has function
+    let has_fn = Value::new(ValueKind::NativeFunction {
+        name: "has".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            interpreter.capabilities().require(Capability::Env, "env.has")?;
+            let name = variable_name(&args)?;
+            Ok(Value::new(ValueKind::Boolean(interpreter.env_var(name).is_some())))
+        }),
+    });
+
+    // args function: the script's command-line arguments as strings. They
+    // are given by whoever runs the script, so no capability is needed.
+    let args_fn = Value::new(ValueKind::NativeFunction {
+        name: "args".to_string(),
+        arity: 0,
+        handler: Arc::new(|interpreter, _| {
+            let args = interpreter.args().iter().map(|arg| Value::new(ValueKind::String(arg.clone()))).collect();
+            Ok(Value::new(ValueKind::List(args)))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("get".to_string(), get_fn)?;
+        module.export("has".to_string(), has_fn)?;
+        module.export("args".to_string(), args_fn)?;
+    }
+
+    Ok(module)
+}
+
+fn variable_name(args: &[Value]) -> Result<&str> {
+    match args.first().map(|arg| &arg.kind) {
+        Some(ValueKind::String(name)) => Ok(name),
+        _ => Err(PrismError::InvalidArgument(
+            "expected the name of an environment variable".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,301 @@
+//! The `eval` module. `eval.sandbox` runs generated Prism code with
+//! restricted access (see [`sandbox`]); `eval.run` measures how well a
+//! prediction function does on labeled examples and whether its
+//! confidences can be trusted.
+//!
+//! A prediction is correct when it equals the example's label. Besides
+//! accuracy the report has the Brier score (mean squared gap between
+//! confidence and correctness, lower is better) and a calibration table
+//! grouping predictions by confidence; in a calibrated model each group's
+//! accuracy matches its mean confidence. `ece` is the expected calibration
+//! error, the count-weighted mean of those gaps.
+
+use std::sync::Arc;
+use parking_lot::RwLock;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::module::Module;
+use crate::stdlib::tasks::{concurrency, drive, Task};
+use crate::value::{Value, ValueKind};
+
+pub mod sandbox;
+
+const DEFAULT_LABEL_KEY: &str = "label";
+const DEFAULT_BINS: usize = 10;
+
+pub fn init_eval_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("eval".to_string())));
+
+    // run function: run(dataset, predict, {label_key: "label", bins: 10,
+    // concurrency: 4, print: false}) calls predict(example) on every example
+    let run_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "run".to_string(),
+        arity: 3,
+        handler: Arc::new(|interpreter, args| Box::pin(async move {
+            let dataset = match args.first().map(|arg| &arg.kind) {
+                Some(ValueKind::List(examples)) => examples.clone(),
+                _ => return Err(invalid("dataset must be a list of examples")),
+            };
+            let predict = match args.get(1) {
+                Some(predict @ Value {
+                    kind: ValueKind::Function { .. } | ValueKind::NativeFunction { .. } | ValueKind::AsyncNativeFunction { .. },
+                    ..
+                }) => predict.clone(),
+                _ => return Err(invalid("predict must be a function")),
+            };
+            let options = args.get(2);
+            let label_key = match option(options, "label_key") {
+                None => DEFAULT_LABEL_KEY.to_string(),
+                Some(ValueKind::String(key)) => key.clone(),
+                Some(_) => return Err(invalid("label_key must be a string")),
+            };
+            let bins = match option(options, "bins") {
+                None => DEFAULT_BINS,
+                Some(ValueKind::Number(n)) if *n >= 1.0 && n.fract() == 0.0 => *n as usize,
+                Some(_) => return Err(invalid("bins must be a positive whole number")),
+            };
+            let print = matches!(option(options, "print"), Some(ValueKind::Boolean(true)));
+            let limit = concurrency(options)?;
+
+            let mut labels = Vec::with_capacity(dataset.len());
+            for example in &dataset {
+                let label = match &example.kind {
+                    ValueKind::Map(entries) => entries.iter().find(|(key, _)| key.to_string() == label_key),
+                    _ => None,
+                };
+                match label {
+                    Some((_, label)) => labels.push(label.clone()),
+                    None => return Err(invalid(&format!("example {} has no '{}'", example, label_key))),
+                }
+            }
+
+            let tasks = dataset.iter().map(|example| prediction(interpreter, predict.clone(), example.clone())).collect();
+            let mut outcomes: Vec<Option<Outcome>> = vec![None; dataset.len()];
+            let mut cancelled = None;
+            drive(tasks, limit, |index, fork, result| {
+                interpreter.join(fork);
+                match result {
+                    Err(err @ PrismError::Cancelled(_)) => {
+                        cancelled = Some(err);
+                        true
+                    }
+                    Ok(prediction) => {
+                        outcomes[index] = Some(Outcome::Predicted {
+                            correct: prediction.kind == labels[index].kind,
+                            confidence: prediction.confidence.clamp(0.0, 1.0),
+                        });
+                        false
+                    }
+                    Err(err) => {
+                        outcomes[index] = Some(Outcome::Failed(err.to_string()));
+                        false
+                    }
+                }
+            })
+            .await;
+            if let Some(err) = cancelled {
+                return Err(err);
+            }
+
+            let outcomes: Vec<Outcome> = outcomes.into_iter().map(|outcome| outcome.expect("every example ran")).collect();
+            let evaluation = Evaluation::of(&outcomes, bins);
+            if print {
+                interpreter.write_output(&evaluation.render());
+            }
+            Ok(evaluation.report())
+        })),
+    });
+
+    // sandbox function: sandbox(code, {capabilities: [], timeout: 5}) runs
+    // code in a fresh interpreter and reports {ok, value, error, output,
+    // diagnostics}
+    let sandbox_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "sandbox".to_string(),
+        arity: 2,
+        handler: Arc::new(|interpreter, args| Box::pin(async move {
+            let source = match args.first().map(|arg| &arg.kind) {
+                Some(ValueKind::String(source)) => source.clone(),
+                _ => return Err(PrismError::InvalidArgument("eval.sandbox expects code as a string".to_string())),
+            };
+            let limits = sandbox::Limits::from_options(args.get(1), interpreter.capabilities())?;
+            sandbox::run(interpreter, source, limits).await
+        })),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("sandbox".to_string(), sandbox_fn)?;
+        module.export("run".to_string(), run_fn)?;
+    }
+
+    Ok(module)
+}
+
+#[derive(Debug, Clone)]
+enum Outcome {
+    Predicted { correct: bool, confidence: f64 },
+    Failed(String),
+}
+
+fn prediction(interpreter: &Interpreter, predict: Value, example: Value) -> Task {
+    let mut fork = interpreter.fork();
+    Box::pin(async move {
+        let result = fork.call(predict, vec![example]).await;
+        (fork, result)
+    })
+}
+
+/// Predictions whose confidence falls in `[low, high)`; the last bin
+/// includes 1.0.
+#[derive(Debug, Clone, PartialEq)]
+struct Bin {
+    low: f64,
+    high: f64,
+    count: usize,
+    confidence: f64,
+    accuracy: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Evaluation {
+    examples: usize,
+    errors: Vec<String>,
+    accuracy: f64,
+    brier: f64,
+    ece: f64,
+    bins: Vec<Bin>,
+}
+
+impl Evaluation {
+    fn of(outcomes: &[Outcome], bins: usize) -> Self {
+        let mut sums = vec![(0usize, 0.0, 0usize); bins]; // (count, confidence, correct)
+        let (mut scored, mut correct, mut squared) = (0usize, 0usize, 0.0);
+        let mut errors = Vec::new();
+        for outcome in outcomes {
+            match outcome {
+                Outcome::Predicted { correct: hit, confidence } => {
+                    let truth = if *hit { 1.0 } else { 0.0 };
+                    scored += 1;
+                    correct += usize::from(*hit);
+                    squared += (confidence - truth).powi(2);
+                    let bin = &mut sums[((confidence * bins as f64) as usize).min(bins - 1)];
+                    bin.0 += 1;
+                    bin.1 += confidence;
+                    bin.2 += usize::from(*hit);
+                }
+                Outcome::Failed(err) => errors.push(err.clone()),
+            }
+        }
+        let ratio = |a: f64, b: usize| if b == 0 { 0.0 } else { a / b as f64 };
+        let bins: Vec<Bin> = sums
+            .iter()
+            .enumerate()
+            .filter(|(_, (count, _, _))| *count > 0)
+            .map(|(index, (count, confidence, hits))| Bin {
+                low: index as f64 / bins as f64,
+                high: (index + 1) as f64 / bins as f64,
+                count: *count,
+                confidence: confidence / *count as f64,
+                accuracy: *hits as f64 / *count as f64,
+            })
+            .collect();
+        let ece = bins.iter().map(|bin| bin.count as f64 * (bin.accuracy - bin.confidence).abs()).sum::<f64>();
+        Self {
+            examples: outcomes.len(),
+            errors,
+            accuracy: ratio(correct as f64, scored),
+            brier: ratio(squared, scored),
+            ece: ratio(ece, scored),
+            bins,
+        }
+    }
+
+    /// `{examples, errors, accuracy, brier, ece, calibration: [{low, high,
+    /// count, confidence, accuracy}]}`
+    fn report(&self) -> Value {
+        let string = |text: &str| Value::new(ValueKind::String(text.to_string()));
+        let number = |n: f64| Value::new(ValueKind::Number(n));
+        let map = |entries: Vec<(&str, Value)>| {
+            Value::new(ValueKind::Map(entries.into_iter().map(|(key, value)| (string(key), value)).collect()))
+        };
+        let calibration = self
+            .bins
+            .iter()
+            .map(|bin| map(vec![
+                ("low", number(bin.low)),
+                ("high", number(bin.high)),
+                ("count", number(bin.count as f64)),
+                ("confidence", number(bin.confidence)),
+                ("accuracy", number(bin.accuracy)),
+            ]))
+            .collect();
+        map(vec![
+            ("examples", number(self.examples as f64)),
+            ("errors", Value::new(ValueKind::List(self.errors.iter().map(|err| string(err)).collect()))),
+            ("accuracy", number(self.accuracy)),
+            ("brier", number(self.brier)),
+            ("ece", number(self.ece)),
+            ("calibration", Value::new(ValueKind::List(calibration))),
+        ])
+    }
+
+    fn render(&self) -> String {
+        let mut text = format!(
+            "{} examples, {} errors\naccuracy {:.3}  brier {:.3}  ece {:.3}\nconfidence  count  mean conf  accuracy\n",
+            self.examples,
+            self.errors.len(),
+            self.accuracy,
+            self.brier,
+            self.ece
+        );
+        for bin in &self.bins {
+            text.push_str(&format!(
+                "{:.2}-{:.2}  {:>5}  {:>9.3}  {:>8.3}\n",
+                bin.low, bin.high, bin.count, bin.confidence, bin.accuracy
+            ));
+        }
+        text
+    }
+}
+
+fn option<'a>(options: Option<&'a Value>, name: &str) -> Option<&'a ValueKind> {
+    match options.map(|options| &options.kind) {
+        Some(ValueKind::Map(entries)) => entries.iter().find(|(key, _)| key.to_string() == name).map(|(_, value)| &value.kind),
+        _ => None,
+    }
+}
+
+fn invalid(message: &str) -> PrismError {
+    PrismError::InvalidArgument(format!("eval.run: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accuracy_brier_and_calibration() {
+        let predicted = |correct, confidence| Outcome::Predicted { correct, confidence };
+        let evaluation = Evaluation::of(
+            &[
+                predicted(true, 0.9),
+                predicted(true, 0.95),
+                predicted(false, 0.9),
+                predicted(true, 0.3),
+                Outcome::Failed("timeout".to_string()),
+            ],
+            10,
+        );
+        assert_eq!(evaluation.examples, 5);
+        assert_eq!(evaluation.errors, ["timeout"]);
+        assert_eq!(evaluation.accuracy, 0.75);
+        assert!((evaluation.brier - (0.01 + 0.0025 + 0.81 + 0.49) / 4.0).abs() < 1e-9);
+
+        let bins: Vec<(f64, usize)> = evaluation.bins.iter().map(|bin| (bin.low, bin.count)).collect();
+        assert_eq!(bins, [(0.3, 1), (0.9, 3)]);
+        let top = &evaluation.bins[1];
+        assert!((top.accuracy - 2.0 / 3.0).abs() < 1e-9);
+        // (3 * |0.667 - 0.917| + 1 * |1 - 0.3|) / 4
+        assert!((evaluation.ece - (3.0 * 0.25 + 0.7) / 4.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,112 @@
+//! `eval.sandbox`: runs Prism source, typically written by a model, in a
+//! fresh interpreter that shares nothing with the calling program.
+//!
+//! The child gets the standard library and prelude but none of the caller's
+//! variables, only the capabilities asked for (never more than the caller
+//! has), a time limit and a shallow call depth. What it prints is captured
+//! rather than shown, and what it did is added to the caller's audit log.
+
+use std::sync::Arc;
+use std::time::Duration;
+use crate::capabilities::{Capabilities, Capability};
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::output::CapturedOutput;
+use crate::value::{Value, ValueKind};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const MAX_CALL_DEPTH: usize = 64;
+
+/// What the sandboxed code may do.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub capabilities: Capabilities,
+    pub timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits { capabilities: Capabilities::none(), timeout: DEFAULT_TIMEOUT }
+    }
+}
+
+impl Limits {
+    /// Reads `{capabilities: ["net"], timeout: secs}`. Asking for a
+    /// capability the caller lacks is an error rather than a silent downgrade.
+    pub fn from_options(options: Option<&Value>, granted: &Capabilities) -> Result<Self> {
+        let entries = match options.map(|options| &options.kind) {
+            None | Some(ValueKind::Nil) => return Ok(Limits::default()),
+            Some(ValueKind::Map(entries)) => entries,
+            Some(_) => return Err(invalid("options must be a map")),
+        };
+        let option = |name: &str| entries.iter().find(|(key, _)| key.to_string() == name).map(|(_, value)| &value.kind);
+
+        let mut capabilities = Capabilities::none();
+        match option("capabilities") {
+            None => {}
+            Some(ValueKind::List(names)) => {
+                for name in names {
+                    let capability: Capability = name.to_string().parse()?;
+                    granted.require(capability, "eval.sandbox")?;
+                    capabilities = capabilities.grant(capability);
+                }
+            }
+            Some(_) => return Err(invalid("capabilities must be a list of names")),
+        }
+        let timeout = match option("timeout") {
+            None => DEFAULT_TIMEOUT,
+            Some(ValueKind::Number(secs)) => Duration::try_from_secs_f64(*secs)
+                .map_err(|_| invalid(&format!("{} is not a valid number of seconds", secs)))?,
+            Some(_) => return Err(invalid("timeout must be a number of seconds")),
+        };
+        Ok(Limits { capabilities, timeout })
+    }
+}
+
+/// Runs `source` under `limits`. Failures of the code itself (parse, type
+/// and runtime errors, running out of time) are part of the report; only
+/// cancellation of the caller fails the call.
+///
+/// The report is `{ok, value, error, output, diagnostics}`; `value` is
+/// `nil ~> 0` when the code failed.
+pub async fn run(interpreter: &mut Interpreter, source: String, limits: Limits) -> Result<Value> {
+    let output = CapturedOutput::new();
+    let mut child = Interpreter::new()
+        .with_capabilities(limits.capabilities)
+        .with_cfg(interpreter.cfg().clone())
+        .with_max_call_depth(MAX_CALL_DEPTH)
+        .with_output(Arc::new(output.clone()));
+    let token = interpreter.cancellation().limited_to(limits.timeout);
+    let result = child.evaluate_cancellable(source, token).await;
+
+    let secrets = interpreter.secrets().clone();
+    let diagnostics: Vec<Value> = child.diagnostics().iter().map(|d| string(&secrets.redact(&d.to_string()))).collect();
+    interpreter.join(child);
+    if interpreter.cancellation().is_cancelled() {
+        interpreter.check_cancelled("eval.sandbox")?;
+    }
+
+    let (ok, value, error) = match result {
+        Ok(value) => (true, value, Value::new(ValueKind::Nil)),
+        Err(err) => (
+            false,
+            Value::with_confidence(ValueKind::Nil, 0.0),
+            string(&secrets.redact(&err.to_string())),
+        ),
+    };
+    Ok(Value::new(ValueKind::Map(vec![
+        (string("ok"), Value::new(ValueKind::Boolean(ok))),
+        (string("value"), value),
+        (string("error"), error),
+        (string("output"), string(&secrets.redact(&output.contents()))),
+        (string("diagnostics"), Value::new(ValueKind::List(diagnostics))),
+    ])))
+}
+
+fn string(text: &str) -> Value {
+    Value::new(ValueKind::String(text.to_string()))
+}
+
+fn invalid(message: &str) -> PrismError {
+    PrismError::InvalidArgument(format!("eval.sandbox: {}", message))
+}
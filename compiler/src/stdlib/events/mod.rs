@@ -0,0 +1,66 @@
+use std::sync::Arc;
+use parking_lot::RwLock;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{Value, ValueKind};
+
+pub fn init_events_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("events".to_string())));
+
+    // on function: on(name, handler) calls handler(payload) for every event
+    // called `name`, whether a script or the host emitted it
+    let on_fn = Value::new(ValueKind::NativeFunction {
+        name: "on".to_string(),
+        arity: 2,
+        handler: Arc::new(|interpreter, args| {
+            let name = event_name(&args, "events.on")?;
+            match args.get(1) {
+                Some(handler @ Value {
+                    kind: ValueKind::Function { .. } | ValueKind::NativeFunction { .. } | ValueKind::AsyncNativeFunction { .. },
+                    ..
+                }) => interpreter.events().subscribe(name, handler.clone()),
+                _ => return Err(PrismError::InvalidArgument("events.on expects a handler function".to_string())),
+            }
+            Ok(Value::new(ValueKind::Nil))
+        }),
+    });
+
+    // emit function: emit(name, payload) runs the handlers in subscription
+    // order and returns how many ran
+    let emit_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "emit".to_string(),
+        arity: 2,
+        handler: Arc::new(|interpreter, args| Box::pin(async move {
+            let name = event_name(&args, "events.emit")?.to_string();
+            let payload = args.get(1).cloned().unwrap_or_else(|| Value::new(ValueKind::Nil));
+            let handled = interpreter.emit(&name, payload).await?;
+            Ok(Value::new(ValueKind::Number(handled as f64)))
+        })),
+    });
+
+    // off function: off(name) removes every handler of `name`
+    let off_fn = Value::new(ValueKind::NativeFunction {
+        name: "off".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            interpreter.events().clear(event_name(&args, "events.off")?);
+            Ok(Value::new(ValueKind::Nil))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("on".to_string(), on_fn)?;
+        module.export("emit".to_string(), emit_fn)?;
+        module.export("off".to_string(), off_fn)?;
+    }
+
+    Ok(module)
+}
+
+fn event_name<'a>(args: &'a [Value], function: &str) -> Result<&'a str> {
+    match args.first().map(|arg| &arg.kind) {
+        Some(ValueKind::String(name)) => Ok(name),
+        _ => Err(PrismError::InvalidArgument(format!("{} expects an event name", function))),
+    }
+}
@@ -0,0 +1,231 @@
+//! `experiment.run`: compares prompt variants over a dataset.
+//!
+//! A variant is any function from a row to an output, typically a prompt
+//! template fed to the LLM; tests pass plain functions instead. The metric
+//! scores each output against its row. Runs use the `async` module's
+//! scheduler, so variants wait on the model concurrently.
+
+use std::sync::Arc;
+use parking_lot::RwLock;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::module::Module;
+use crate::stdlib::tasks::{concurrency, drive, Task};
+use crate::value::{Value, ValueKind};
+
+pub fn init_experiment_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("experiment".to_string())));
+
+    // run function: run({variants: {name: fn}, dataset: rows, metric: fn,
+    // concurrency: 4}) calls every variant on every row, scores each output
+    // with metric(output, row) and reports per-variant statistics
+    let run_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "run".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| Box::pin(async move {
+            let config = args.first().cloned().unwrap_or_else(|| Value::new(ValueKind::Nil));
+            let variants = match field(&config, "variants") {
+                Some(ValueKind::Map(variants)) if !variants.is_empty() => variants.clone(),
+                _ => return Err(invalid("variants must be a non-empty map of functions")),
+            };
+            let dataset = match field(&config, "dataset") {
+                Some(ValueKind::List(rows)) => rows.clone(),
+                _ => return Err(invalid("dataset must be a list of rows")),
+            };
+            let metric = match field(&config, "metric") {
+                Some(kind @ (ValueKind::Function { .. } | ValueKind::NativeFunction { .. } | ValueKind::AsyncNativeFunction { .. })) => {
+                    Value::new(kind.clone())
+                }
+                _ => return Err(invalid("metric must be a function")),
+            };
+            let limit = concurrency(Some(&config))?;
+
+            let mut tasks = Vec::with_capacity(variants.len() * dataset.len());
+            for (_, variant) in &variants {
+                for row in &dataset {
+                    tasks.push(trial(interpreter, variant.clone(), metric.clone(), row.clone()));
+                }
+            }
+
+            let mut trials: Vec<Option<Trial>> = vec![None; tasks.len()];
+            let mut cancelled = None;
+            drive(tasks, limit, |index, fork, result| {
+                interpreter.join(fork);
+                match result {
+                    Err(err @ PrismError::Cancelled(_)) => {
+                        cancelled = Some(err);
+                        true
+                    }
+                    result => {
+                        trials[index] = Some(Trial::from(result));
+                        false
+                    }
+                }
+            })
+            .await;
+            if let Some(err) = cancelled {
+                return Err(err);
+            }
+
+            let trials: Vec<Trial> = trials.into_iter().map(|trial| trial.expect("every trial finished")).collect();
+            let reports: Vec<(String, Summary)> = variants
+                .iter()
+                .zip(trials.chunks(dataset.len().max(1)))
+                .map(|((name, _), trials)| (name.to_string(), Summary::of(trials)))
+                .collect();
+            Ok(report(&reports, dataset.len()))
+        })),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("run".to_string(), run_fn)?;
+    }
+
+    Ok(module)
+}
+
+/// One variant on one row: its output's confidence and the metric's score,
+/// or why either call failed.
+#[derive(Debug, Clone)]
+enum Trial {
+    Scored { score: f64, confidence: f64 },
+    Failed(String),
+}
+
+impl From<Result<Value>> for Trial {
+    fn from(result: Result<Value>) -> Self {
+        match result.map(|value| value.kind) {
+            Ok(ValueKind::List(pair)) => match pair.as_slice() {
+                [output, Value { kind: ValueKind::Number(score), .. }] => Trial::Scored {
+                    score: *score,
+                    confidence: output.confidence,
+                },
+                [_, score] => Trial::Failed(format!("metric returned {}, not a number", score)),
+                _ => unreachable!("trials return [output, score]"),
+            },
+            Ok(_) => unreachable!("trials return [output, score]"),
+            Err(err) => Trial::Failed(err.to_string()),
+        }
+    }
+}
+
+fn trial(interpreter: &Interpreter, variant: Value, metric: Value, row: Value) -> Task {
+    let mut fork = interpreter.fork();
+    Box::pin(async move {
+        let result = match fork.call(variant, vec![row.clone()]).await {
+            Ok(output) => fork
+                .call(metric, vec![output.clone(), row])
+                .await
+                .map(|score| Value::new(ValueKind::List(vec![output, score]))),
+            Err(err) => Err(err),
+        };
+        (fork, result)
+    })
+}
+
+/// Statistics of one variant's trials.
+#[derive(Debug, Clone, PartialEq)]
+struct Summary {
+    runs: usize,
+    errors: Vec<String>,
+    mean: f64,
+    min: f64,
+    max: f64,
+    stddev: f64,
+    mean_confidence: f64,
+}
+
+impl Summary {
+    fn of(trials: &[Trial]) -> Self {
+        let mut scores = Vec::new();
+        let mut confidences = Vec::new();
+        let mut errors = Vec::new();
+        for trial in trials {
+            match trial {
+                Trial::Scored { score, confidence } => {
+                    scores.push(*score);
+                    confidences.push(*confidence);
+                }
+                Trial::Failed(err) => errors.push(err.clone()),
+            }
+        }
+        let mean = |values: &[f64]| if values.is_empty() { 0.0 } else { values.iter().sum::<f64>() / values.len() as f64 };
+        let average = mean(&scores);
+        let variance = mean(&scores.iter().map(|score| (score - average).powi(2)).collect::<Vec<_>>());
+        Self {
+            runs: trials.len(),
+            errors,
+            mean: average,
+            min: scores.iter().copied().reduce(f64::min).unwrap_or(0.0),
+            max: scores.iter().copied().reduce(f64::max).unwrap_or(0.0),
+            stddev: variance.sqrt(),
+            mean_confidence: mean(&confidences),
+        }
+    }
+}
+
+/// `{rows, best, variants: {name: {runs, errors, mean, min, max, stddev,
+/// mean_confidence}}}`, where `best` is the variant with the highest mean.
+fn report(summaries: &[(String, Summary)], rows: usize) -> Value {
+    let string = |text: &str| Value::new(ValueKind::String(text.to_string()));
+    let number = |n: f64| Value::new(ValueKind::Number(n));
+    let best = summaries
+        .iter()
+        .fold(None::<&(String, Summary)>, |best, candidate| match best {
+            Some(best) if best.1.mean >= candidate.1.mean => Some(best),
+            _ => Some(candidate),
+        })
+        .map(|(name, _)| string(name))
+        .unwrap_or_else(|| Value::new(ValueKind::Nil));
+    let variants = summaries
+        .iter()
+        .map(|(name, summary)| {
+            let errors = summary.errors.iter().map(|err| string(err)).collect();
+            let stats = vec![
+                (string("runs"), number(summary.runs as f64)),
+                (string("errors"), Value::new(ValueKind::List(errors))),
+                (string("mean"), number(summary.mean)),
+                (string("min"), number(summary.min)),
+                (string("max"), number(summary.max)),
+                (string("stddev"), number(summary.stddev)),
+                (string("mean_confidence"), number(summary.mean_confidence)),
+            ];
+            (string(name), Value::new(ValueKind::Map(stats)))
+        })
+        .collect();
+    Value::new(ValueKind::Map(vec![
+        (string("rows"), number(rows as f64)),
+        (string("best"), best),
+        (string("variants"), Value::new(ValueKind::Map(variants))),
+    ]))
+}
+
+fn field<'a>(config: &'a Value, name: &str) -> Option<&'a ValueKind> {
+    match &config.kind {
+        ValueKind::Map(entries) => entries.iter().find(|(key, _)| key.to_string() == name).map(|(_, value)| &value.kind),
+        _ => None,
+    }
+}
+
+fn invalid(message: &str) -> PrismError {
+    PrismError::InvalidArgument(format!("experiment.run: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_statistics() {
+        let summary = Summary::of(&[
+            Trial::Scored { score: 1.0, confidence: 0.9 },
+            Trial::Scored { score: 0.0, confidence: 0.5 },
+            Trial::Failed("timeout".to_string()),
+        ]);
+        assert_eq!(summary.runs, 3);
+        assert_eq!(summary.errors, ["timeout"]);
+        assert_eq!((summary.mean, summary.min, summary.max, summary.stddev), (0.5, 0.0, 1.0, 0.5));
+        assert!((summary.mean_confidence - 0.7).abs() < 1e-9);
+    }
+}
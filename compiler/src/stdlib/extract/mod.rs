@@ -0,0 +1,267 @@
+//! The `extract` module: pulls structured values out of LLM responses.
+//!
+//! Models wrap answers in prose, fence JSON in Markdown and format numbers
+//! and lists however they like. `extract.number`, `extract.list` and
+//! `extract.json` accept the clean form as is, and otherwise fall back to
+//! heuristics: the first number in a sentence, bullet lines or comma
+//! separated items, the JSON blob inside a code fence or prose, trailing
+//! commas and single quotes repaired. The more repair was needed, the less
+//! confident the result; what cannot be extracted is nil with confidence 0.
+//!
+//! The same extraction backs the `extract` option of
+//! `llm.chat_completion`.
+
+use std::sync::{Arc, LazyLock};
+use parking_lot::RwLock;
+use regex::Regex;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{Value, ValueKind};
+
+/// How confident a value found inside surrounding text is, relative to the
+/// text it came from.
+pub const FOUND_CONFIDENCE: f64 = 0.9;
+
+/// How confident a value is that needed guessing: one of several numbers,
+/// or JSON that had to be repaired.
+pub const REPAIRED_CONFIDENCE: f64 = 0.7;
+
+static NUMBER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"[-+]?(?:\d{1,3}(?:,\d{3})+|\d+)(?:[.,]\d+)?(?:[eE][-+]?\d+)?%?").expect("valid number pattern")
+});
+
+static BULLET: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(?:[-*•+]|\d+[.)])\s+(.*)$").expect("valid bullet pattern"));
+
+static FENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)```[a-zA-Z]*\s*\n(.*?)```").expect("valid fence pattern"));
+
+static TRAILING_COMMA: LazyLock<Regex> = LazyLock::new(|| Regex::new(r",\s*([}\]])").expect("valid comma pattern"));
+
+/// Reads one number as written, e.g. `1,234.5`, `39,5` or `75%` (0.75).
+fn parse_number(text: &str) -> Option<f64> {
+    let (text, scale) = match text.strip_suffix('%') {
+        Some(text) => (text, 0.01),
+        None => (text, 1.0),
+    };
+    // A lone comma before one or two digits is a decimal comma
+    let decimal_comma = text.matches(',').count() == 1
+        && !text.contains('.')
+        && text.rsplit(',').next().is_some_and(|digits| digits.len() != 3);
+    let normalized = if decimal_comma { text.replace(',', ".") } else { text.replace(',', "") };
+    normalized.parse::<f64>().ok().map(|n| n * scale)
+}
+
+/// The number in `text` and how confident it is relative to `text`.
+pub fn number(text: &str) -> Option<(f64, f64)> {
+    let trimmed = text.trim();
+    if let Some(n) = parse_number(trimmed) {
+        return Some((n, 1.0));
+    }
+    let mut found = NUMBER.find_iter(trimmed).filter_map(|found| parse_number(found.as_str()));
+    let first = found.next()?;
+    let confidence = if found.next().is_some() { REPAIRED_CONFIDENCE } else { FOUND_CONFIDENCE };
+    Some((first, confidence))
+}
+
+/// The items listed in `text` and how confident they are relative to it.
+pub fn list(text: &str) -> Option<(Vec<Value>, f64)> {
+    if let Some((Value { kind: ValueKind::List(items), .. }, confidence)) = json(text) {
+        return Some((items, confidence));
+    }
+    let bullets: Vec<Value> = text
+        .lines()
+        .filter_map(|line| BULLET.captures(line))
+        .map(|captures| Value::new(ValueKind::String(captures[1].trim().to_string())))
+        .collect();
+    if !bullets.is_empty() {
+        return Some((bullets, FOUND_CONFIDENCE));
+    }
+    let separator = if text.contains(';') { ';' } else if text.contains(',') { ',' } else { '\n' };
+    let items: Vec<Value> = text
+        .split(separator)
+        .map(|item| item.trim().trim_end_matches('.').trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| Value::new(ValueKind::String(item.to_string())))
+        .collect();
+    match items.len() {
+        0 => None,
+        1 => Some((items, REPAIRED_CONFIDENCE)),
+        _ => Some((items, FOUND_CONFIDENCE)),
+    }
+}
+
+/// The first balanced `{...}` or `[...]` in `text`, minding strings.
+fn blob(text: &str) -> Option<&str> {
+    let start = text.find(['{', '['])?;
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (offset, c) in text[start..].char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '{' | '[') => depth += 1,
+            (None, '}' | ']') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&text[start..start + offset + 1]);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `text` with trailing commas dropped and single-quoted strings
+/// double-quoted.
+fn repair(text: &str) -> String {
+    let mut repaired = String::with_capacity(text.len());
+    let mut quote = None;
+    let mut escaped = false;
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(open), _) if escaped => {
+                escaped = false;
+                // `\'` needs no escape in JSON
+                if !(open == '\'' && c == '\'') {
+                    repaired.push('\\');
+                }
+                repaired.push(c);
+            }
+            (Some(_), '\\') => escaped = true,
+            (Some('\''), '\'') | (None, '\'') => {
+                quote = if quote.is_some() { None } else { Some('\'') };
+                repaired.push('"');
+            }
+            (Some('\''), '"') => repaired.push_str("\\\""),
+            (Some('"'), '"') => {
+                quote = None;
+                repaired.push(c);
+            }
+            (None, '"') => {
+                quote = Some('"');
+                repaired.push(c);
+            }
+            _ => repaired.push(c),
+        }
+    }
+    TRAILING_COMMA.replace_all(&repaired, "$1").into_owned()
+}
+
+/// The JSON value in `text` and how confident it is relative to `text`.
+pub fn json(text: &str) -> Option<(Value, f64)> {
+    let parse = |text: &str| serde_json::from_str::<serde_json::Value>(text).ok();
+    if let Some(json) = parse(text.trim()) {
+        return Some((Value::from_json(&json), 1.0));
+    }
+    let fenced = FENCE.captures(text).map(|captures| captures.get(1).expect("fence body").as_str());
+    let candidate = fenced.and_then(|body| blob(body).or(Some(body.trim()))).or_else(|| blob(text))?;
+    if let Some(json) = parse(candidate) {
+        return Some((Value::from_json(&json), FOUND_CONFIDENCE));
+    }
+    parse(&repair(candidate)).map(|json| (Value::from_json(&json), REPAIRED_CONFIDENCE))
+}
+
+/// Applies an extraction to `value`, scaling its confidence: nil with
+/// confidence 0 when nothing could be extracted.
+pub fn apply(extraction: &str, value: &Value) -> Result<Value> {
+    let text = match &value.kind {
+        ValueKind::String(text) => text.clone(),
+        kind => Value::new(kind.clone()).to_string(),
+    };
+    let extracted = match extraction {
+        "number" => number(&text).map(|(n, confidence)| (Value::new(ValueKind::Number(n)), confidence)),
+        "list" => list(&text).map(|(items, confidence)| (Value::new(ValueKind::List(items)), confidence)),
+        "json" => json(&text),
+        _ => {
+            return Err(PrismError::InvalidArgument(format!(
+                "Unknown extraction '{}'; expected number, list or json",
+                extraction
+            )))
+        }
+    };
+    Ok(match extracted {
+        Some((mut result, confidence)) => {
+            result.confidence = value.confidence * confidence;
+            result.context = value.context.clone();
+            result
+        }
+        None => Value::with_confidence(ValueKind::Nil, 0.0),
+    })
+}
+
+fn extractor(name: &'static str) -> Value {
+    Value::new(ValueKind::NativeFunction {
+        name: name.to_string(),
+        arity: 1,
+        handler: Arc::new(move |_, args| match args.first() {
+            Some(value) => apply(name, value),
+            None => Err(PrismError::InvalidArgument(format!("extract.{} expects text", name))),
+        }),
+    })
+}
+
+pub fn init_extract_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("extract".to_string())));
+    {
+        let mut module = module.write();
+        module.export("number".to_string(), extractor("number"))?;
+        module.export("list".to_string(), extractor("list"))?;
+        module.export("json".to_string(), extractor("json"))?;
+    }
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers_are_found_in_prose() {
+        assert_eq!(number(" 39.5 "), Some((39.5, 1.0)));
+        assert_eq!(number("1,234.5"), Some((1234.5, 1.0)));
+        assert_eq!(number("39,5"), Some((39.5, 1.0)));
+        assert_eq!(number("75%"), Some((0.75, 1.0)));
+        assert_eq!(number("The temperature is 39.5°C."), Some((39.5, FOUND_CONFIDENCE)));
+        assert_eq!(number("Between 4 and 6 hours"), Some((4.0, REPAIRED_CONFIDENCE)));
+        assert_eq!(number("Dose: -2.5e1 mg"), Some((-25.0, FOUND_CONFIDENCE)));
+        assert_eq!(number("unknown"), None);
+    }
+
+    #[test]
+    fn test_lists_come_from_json_bullets_or_separators() {
+        let strings = |(items, confidence): (Vec<Value>, f64)| {
+            (items.iter().map(Value::to_string).collect::<Vec<_>>(), confidence)
+        };
+        assert_eq!(strings(list(r#"["fever", "cough"]"#).unwrap()), (vec!["fever".into(), "cough".into()], 1.0));
+        let bullets = "Symptoms:\n- fever\n* dry cough\n2) sore throat\n";
+        assert_eq!(
+            strings(list(bullets).unwrap()),
+            (vec!["fever".into(), "dry cough".into(), "sore throat".into()], FOUND_CONFIDENCE)
+        );
+        assert_eq!(strings(list("fever, cough.").unwrap()), (vec!["fever".into(), "cough".into()], FOUND_CONFIDENCE));
+        assert_eq!(strings(list("fever").unwrap()), (vec!["fever".into()], REPAIRED_CONFIDENCE));
+        assert!(list("  ").is_none());
+    }
+
+    #[test]
+    fn test_json_is_unfenced_and_repaired() {
+        let found = |text: &str| json(text).map(|(value, confidence)| (value.to_json().unwrap(), confidence));
+        assert_eq!(found(r#"{"dose": 500}"#), Some((serde_json::json!({ "dose": 500 }), 1.0)));
+        let fenced = "Here you go:\n```json\n{\"dose\": 500, \"unit\": \"mg\"}\n```\nAnything else?";
+        assert_eq!(found(fenced), Some((serde_json::json!({ "dose": 500, "unit": "mg" }), FOUND_CONFIDENCE)));
+        let prose = r#"The answer is {"note": "use } carefully", "ok": true} as requested."#;
+        assert_eq!(found(prose), Some((serde_json::json!({ "note": "use } carefully", "ok": true }), FOUND_CONFIDENCE)));
+        let sloppy = "{'drugs': ['ibuprofen', 'it\\'s \"fine\"'],}";
+        assert_eq!(
+            found(sloppy),
+            Some((serde_json::json!({ "drugs": ["ibuprofen", "it's \"fine\""] }), REPAIRED_CONFIDENCE))
+        );
+        assert_eq!(found("no JSON here"), None);
+    }
+}
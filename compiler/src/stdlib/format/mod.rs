@@ -0,0 +1,341 @@
+//! The `format` module: locale-aware numbers, percentages, money and dates
+//! for human-readable reports.
+//!
+//! Locales are glibc names such as `"en_US"` or `"de_DE"` (`"de-DE"` works
+//! too) and default to `en_US`. Timestamps are Unix seconds or RFC 3339
+//! strings, rendered in UTC, or datetime values, rendered in their zone.
+
+use std::sync::Arc;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Locale, TimeZone, Utc};
+use parking_lot::RwLock;
+use pure_rust_locales::locale_match;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{NumberFormat, Value, ValueKind};
+
+const DEFAULT_DATETIME: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Currencies that can be shown with a symbol outside their home locale:
+/// code, symbol and minor digits.
+const CURRENCIES: &[(&str, &str, usize)] = &[
+    ("USD", "$", 2),
+    ("EUR", "€", 2),
+    ("GBP", "£", 2),
+    ("JPY", "¥", 0),
+    ("CNY", "¥", 2),
+    ("INR", "₹", 2),
+    ("CHF", "CHF", 2),
+];
+
+/// Separators and currency conventions of a locale.
+#[derive(Debug, Clone, Copy)]
+pub struct Conventions {
+    pub decimal_point: &'static str,
+    pub thousands_sep: &'static str,
+    pub grouping: &'static [i64],
+    pub mon_decimal_point: &'static str,
+    pub mon_thousands_sep: &'static str,
+    pub mon_grouping: &'static [i64],
+    pub currency_code: &'static str,
+    pub currency_symbol: &'static str,
+    pub frac_digits: i64,
+    pub symbol_precedes: bool,
+    pub symbol_spaced: bool,
+}
+
+impl Conventions {
+    pub fn of(locale: Locale) -> Self {
+        Conventions {
+            decimal_point: locale_match!(locale => LC_NUMERIC::DECIMAL_POINT),
+            thousands_sep: locale_match!(locale => LC_NUMERIC::THOUSANDS_SEP),
+            grouping: locale_match!(locale => LC_NUMERIC::GROUPING),
+            mon_decimal_point: locale_match!(locale => LC_MONETARY::MON_DECIMAL_POINT),
+            mon_thousands_sep: locale_match!(locale => LC_MONETARY::MON_THOUSANDS_SEP),
+            mon_grouping: locale_match!(locale => LC_MONETARY::MON_GROUPING),
+            currency_code: locale_match!(locale => LC_MONETARY::INT_CURR_SYMBOL).trim(),
+            currency_symbol: locale_match!(locale => LC_MONETARY::CURRENCY_SYMBOL),
+            frac_digits: locale_match!(locale => LC_MONETARY::FRAC_DIGITS),
+            symbol_precedes: locale_match!(locale => LC_MONETARY::P_CS_PRECEDES) == 1,
+            symbol_spaced: locale_match!(locale => LC_MONETARY::P_SEP_BY_SPACE) == 1,
+        }
+    }
+}
+
+pub fn init_format_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("format".to_string())));
+
+    // number function: number(n, { decimals, thousands, locale }); thousands
+    // is false to turn grouping off or a string to replace the separator
+    let number_fn = Value::new(ValueKind::NativeFunction {
+        name: "number".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let n = number_arg(&args, "format.number")?;
+            let conventions = Conventions::of(locale(option(&args, 1, "locale")?)?);
+            let decimals = decimals(option(&args, 1, "decimals")?)?;
+            let separator = match option(&args, 1, "thousands")?.map(|value| &value.kind) {
+                None | Some(ValueKind::Boolean(true)) => conventions.thousands_sep,
+                Some(ValueKind::Boolean(false)) => "",
+                Some(ValueKind::String(separator)) => separator.as_str(),
+                Some(_) => return Err(PrismError::InvalidArgument(
+                    "option thousands must be a boolean or a string".to_string(),
+                )),
+            };
+            Ok(string(format_number(n, decimals, conventions.decimal_point, separator, conventions.grouping)))
+        }),
+    });
+
+    // percent function: percent(0.873, { decimals = 0, locale }) is "87%"
+    let percent_fn = Value::new(ValueKind::NativeFunction {
+        name: "percent".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let n = number_arg(&args, "format.percent")?;
+            let conventions = Conventions::of(locale(option(&args, 1, "locale")?)?);
+            let decimals = decimals(option(&args, 1, "decimals")?)?.unwrap_or(0);
+            let text = format_number(
+                n * 100.0,
+                Some(decimals),
+                conventions.decimal_point,
+                conventions.thousands_sep,
+                conventions.grouping,
+            );
+            Ok(string(format!("{}%", text)))
+        }),
+    });
+
+    // currency function: currency(amount, { currency, decimals, locale });
+    // the currency defaults to the locale's own
+    let currency_fn = Value::new(ValueKind::NativeFunction {
+        name: "currency".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let amount = number_arg(&args, "format.currency")?;
+            let conventions = Conventions::of(locale(option(&args, 1, "locale")?)?);
+            let code = match option(&args, 1, "currency")?.map(|value| &value.kind) {
+                None => None,
+                Some(ValueKind::String(code)) => Some(code.to_uppercase()),
+                Some(_) => return Err(PrismError::InvalidArgument("option currency must be a string".to_string())),
+            };
+            let decimals = decimals(option(&args, 1, "decimals")?)?;
+            Ok(string(format_currency(amount, code.as_deref(), decimals, &conventions)))
+        }),
+    });
+
+    // datetime function: datetime(ts, pattern, locale) with strftime
+    // patterns; month and day names follow the locale
+    let datetime_fn = Value::new(ValueKind::NativeFunction {
+        name: "datetime".to_string(),
+        arity: 3,
+        handler: Arc::new(|_, args| {
+            let timestamp = match args.first().map(|arg| &arg.kind) {
+                Some(ValueKind::Number(seconds)) => {
+                    let whole = seconds.floor();
+                    DateTime::from_timestamp(whole as i64, ((seconds - whole) * 1e9) as u32)
+                        .ok_or_else(|| PrismError::InvalidArgument(format!("timestamp {} is out of range", seconds)))?
+                        .fixed_offset()
+                }
+                Some(ValueKind::String(text)) => DateTime::parse_from_rfc3339(text)
+                    .map_err(|e| PrismError::InvalidArgument(format!("invalid timestamp '{}': {}", text, e)))?
+                    .with_timezone(&Utc)
+                    .fixed_offset(),
+                // A datetime value is shown in its own zone
+                Some(ValueKind::DateTime(datetime)) => *datetime,
+                _ => return Err(PrismError::InvalidArgument(
+                    "format.datetime expects a datetime, Unix seconds or an RFC 3339 string".to_string(),
+                )),
+            };
+            let pattern = match args.get(1).map(|arg| &arg.kind) {
+                None | Some(ValueKind::Nil) => DEFAULT_DATETIME,
+                Some(ValueKind::String(pattern)) => pattern.as_str(),
+                Some(_) => return Err(PrismError::InvalidArgument("format.datetime pattern must be a string".to_string())),
+            };
+            Ok(string(format_datetime(&timestamp, pattern, locale(args.get(2))?)?))
+        }),
+    });
+
+    // precision function: precision(n, digits, { scientific }) rounds to
+    // significant digits, as printing does with fifteen
+    let precision_fn = Value::new(ValueKind::NativeFunction {
+        name: "precision".to_string(),
+        arity: 3,
+        handler: Arc::new(|_, args| {
+            let n = number_arg(&args, "format.precision")?;
+            let precision = match args.get(1).map(|arg| &arg.kind) {
+                Some(ValueKind::Number(digits)) if *digits >= 1.0 && digits.fract() == 0.0 => *digits as usize,
+                _ => return Err(PrismError::InvalidArgument(
+                    "format.precision expects a whole number of digits from 1".to_string(),
+                )),
+            };
+            let scientific = match option(&args, 2, "scientific")?.map(|value| &value.kind) {
+                None => false,
+                Some(ValueKind::Boolean(scientific)) => *scientific,
+                Some(_) => return Err(PrismError::InvalidArgument("option scientific must be a boolean".to_string())),
+            };
+            Ok(string(NumberFormat { precision, scientific }.format(n)))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("number".to_string(), number_fn)?;
+        module.export("percent".to_string(), percent_fn)?;
+        module.export("currency".to_string(), currency_fn)?;
+        module.export("datetime".to_string(), datetime_fn)?;
+        module.export("precision".to_string(), precision_fn)?;
+    }
+
+    Ok(module)
+}
+
+/// Formats `n` with `decimals` fixed digits (or as few as needed), grouping
+/// the integer digits by `grouping` as in `LC_NUMERIC`: the last size
+/// repeats, and -1 stops grouping.
+pub fn format_number(n: f64, decimals: Option<usize>, point: &str, separator: &str, grouping: &[i64]) -> String {
+    if !n.is_finite() {
+        return n.to_string();
+    }
+    let digits = match decimals {
+        Some(decimals) => format!("{:.*}", decimals, n.abs()),
+        None => n.abs().to_string(),
+    };
+    let (integer, fraction) = match digits.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (digits.as_str(), None),
+    };
+    let negative = n.is_sign_negative() && digits.bytes().any(|b| matches!(b, b'1'..=b'9'));
+
+    let mut groups = Vec::new();
+    let mut rest = integer;
+    let mut sizes = grouping.iter().copied();
+    let mut size = sizes.next().unwrap_or(-1);
+    while size > 0 && rest.len() > size as usize && !separator.is_empty() {
+        let (head, tail) = rest.split_at(rest.len() - size as usize);
+        groups.push(tail);
+        rest = head;
+        size = sizes.next().unwrap_or(size);
+    }
+    groups.push(rest);
+    groups.reverse();
+
+    let mut text = String::new();
+    if negative {
+        text.push('-');
+    }
+    text.push_str(&groups.join(separator));
+    if let Some(fraction) = fraction {
+        text.push_str(point);
+        text.push_str(fraction);
+    }
+    text
+}
+
+/// Formats an amount with the locale's monetary separators and symbol
+/// placement. A foreign `code` is shown by its symbol when known.
+pub fn format_currency(amount: f64, code: Option<&str>, decimals: Option<usize>, conventions: &Conventions) -> String {
+    let (symbol, digits) = match code {
+        None => (conventions.currency_symbol, conventions.frac_digits.max(0) as usize),
+        Some(code) if code == conventions.currency_code => {
+            (conventions.currency_symbol, conventions.frac_digits.max(0) as usize)
+        }
+        Some(code) => match CURRENCIES.iter().find(|(known, _, _)| *known == code) {
+            Some((_, symbol, digits)) => (*symbol, *digits),
+            None => (code, 2),
+        },
+    };
+    let number = format_number(
+        amount.abs(),
+        Some(decimals.unwrap_or(digits)),
+        conventions.mon_decimal_point,
+        conventions.mon_thousands_sep,
+        conventions.mon_grouping,
+    );
+    let space = if conventions.symbol_spaced { " " } else { "" };
+    let sign = if amount < 0.0 { "-" } else { "" };
+    if conventions.symbol_precedes {
+        format!("{}{}{}{}", sign, symbol, space, number)
+    } else {
+        format!("{}{}{}{}", sign, number, space, symbol)
+    }
+}
+
+pub fn format_datetime<Tz: TimeZone>(timestamp: &DateTime<Tz>, pattern: &str, locale: Locale) -> Result<String>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let items = StrftimeItems::new_with_locale(pattern, locale).collect::<Vec<_>>();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return Err(PrismError::InvalidArgument(format!("invalid datetime pattern '{}'", pattern)));
+    }
+    Ok(timestamp.format_localized_with_items(items.into_iter(), locale).to_string())
+}
+
+fn locale(value: Option<&Value>) -> Result<Locale> {
+    match value.map(|value| &value.kind) {
+        None | Some(ValueKind::Nil) => Ok(Locale::en_US),
+        Some(ValueKind::String(name)) => Locale::try_from(name.replace('-', "_").as_str())
+            .map_err(|_| PrismError::InvalidArgument(format!("unknown locale '{}'", name))),
+        Some(_) => Err(PrismError::InvalidArgument("locale must be a string".to_string())),
+    }
+}
+
+fn decimals(value: Option<&Value>) -> Result<Option<usize>> {
+    match value.map(|value| &value.kind) {
+        None => Ok(None),
+        Some(ValueKind::Number(n)) if *n >= 0.0 && n.fract() == 0.0 => Ok(Some(*n as usize)),
+        Some(_) => Err(PrismError::InvalidArgument("option decimals must be a whole number".to_string())),
+    }
+}
+
+fn number_arg(args: &[Value], name: &str) -> Result<f64> {
+    match args.first().map(|arg| &arg.kind) {
+        Some(ValueKind::Number(n)) => Ok(*n),
+        _ => Err(PrismError::InvalidArgument(format!("{} expects a number", name))),
+    }
+}
+
+fn option<'a>(args: &'a [Value], index: usize, name: &str) -> Result<Option<&'a Value>> {
+    match args.get(index).map(|arg| &arg.kind) {
+        None | Some(ValueKind::Nil) => Ok(None),
+        Some(ValueKind::Map(entries)) => {
+            Ok(entries.iter().find(|(key, _)| key.to_string() == name).map(|(_, value)| value))
+        }
+        Some(_) => Err(PrismError::InvalidArgument("expected an options map".to_string())),
+    }
+}
+
+fn string(text: String) -> Value {
+    Value::new(ValueKind::String(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers_follow_the_locale() {
+        let us = Conventions::of(Locale::en_US);
+        let de = Conventions::of(Locale::de_DE);
+        let india = Conventions::of(Locale::en_IN);
+        assert_eq!(format_number(1234567.891, Some(2), us.decimal_point, us.thousands_sep, us.grouping), "1,234,567.89");
+        assert_eq!(format_number(-1234.5, None, de.decimal_point, de.thousands_sep, de.grouping), "-1.234,5");
+        assert_eq!(format_number(12345678.0, Some(0), ".", ",", india.grouping), "1,23,45,678");
+        assert_eq!(format_number(-0.001, Some(2), ".", ",", us.grouping), "0.00");
+        assert_eq!(format_number(999.0, None, ".", ",", us.grouping), "999");
+    }
+
+    #[test]
+    fn test_currency_and_dates() {
+        let us = Conventions::of(Locale::en_US);
+        let de = Conventions::of(Locale::de_DE);
+        assert_eq!(format_currency(1234.5, None, None, &us), "$1,234.50");
+        assert_eq!(format_currency(1234.5, None, None, &de), "1.234,50 €");
+        assert_eq!(format_currency(-5.0, Some("JPY"), None, &us), "-¥5");
+
+        let timestamp = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        assert_eq!(format_datetime(&timestamp, "%d %B %Y", Locale::de_DE).unwrap(), "14 November 2023");
+        assert_eq!(format_datetime(&timestamp, "%A", Locale::fr_FR).unwrap(), "mardi");
+        assert!(format_datetime(&timestamp, "%Q", Locale::en_US).is_err());
+    }
+}
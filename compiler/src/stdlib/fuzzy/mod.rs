@@ -0,0 +1,132 @@
+//! The `fuzzy` module: cheap string similarity, e.g. to shortlist
+//! candidates before asking the LLM which one is meant.
+//!
+//! Scores run from 0.0 to 1.0 so they can be used as confidences directly.
+//! The `method` option picks `"levenshtein"` (normalized edit distance, the
+//! default) or `"jaro_winkler"`, which favours shared prefixes and suits
+//! short names.
+
+use std::sync::Arc;
+use parking_lot::RwLock;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{Value, ValueKind};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    Levenshtein,
+    JaroWinkler,
+}
+
+impl Method {
+    pub fn score(self, a: &str, b: &str) -> f64 {
+        match self {
+            Method::Levenshtein => strsim::normalized_levenshtein(a, b),
+            Method::JaroWinkler => strsim::jaro_winkler(a, b),
+        }
+    }
+}
+
+pub fn init_fuzzy_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("fuzzy".to_string())));
+
+    // ratio function: ratio(a, b, { method })
+    let ratio_fn = Value::new(ValueKind::NativeFunction {
+        name: "ratio".to_string(),
+        arity: 3,
+        handler: Arc::new(|_, args| {
+            let (a, b) = match (args.first().map(|arg| &arg.kind), args.get(1).map(|arg| &arg.kind)) {
+                (Some(ValueKind::String(a)), Some(ValueKind::String(b))) => (a, b),
+                _ => return Err(PrismError::InvalidArgument("fuzzy.ratio expects two strings".to_string())),
+            };
+            let method = method(args.get(2))?;
+            Ok(Value::new(ValueKind::Number(method.score(a, b))))
+        }),
+    });
+
+    // best_match function: best_match(query, candidates, { method }) is the
+    // closest candidate with its score as confidence, or nil ~> 0.0 when
+    // there are no candidates
+    let best_match_fn = Value::new(ValueKind::NativeFunction {
+        name: "best_match".to_string(),
+        arity: 3,
+        handler: Arc::new(|_, args| {
+            let (query, candidates) = match (args.first().map(|arg| &arg.kind), args.get(1).map(|arg| &arg.kind)) {
+                (Some(ValueKind::String(query)), Some(ValueKind::List(candidates))) => (query, candidates),
+                _ => return Err(PrismError::InvalidArgument(
+                    "fuzzy.best_match expects a string and a list of strings".to_string(),
+                )),
+            };
+            let method = method(args.get(2))?;
+            let candidates = candidates
+                .iter()
+                .map(|candidate| match &candidate.kind {
+                    ValueKind::String(candidate) => Ok(candidate.as_str()),
+                    _ => Err(PrismError::InvalidArgument(format!("fuzzy.best_match: {} is not a string", candidate))),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(match best_match(query, &candidates, method) {
+                Some((candidate, score)) => Value::with_confidence(ValueKind::String(candidate.to_string()), score),
+                None => Value::with_confidence(ValueKind::Nil, 0.0),
+            })
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("ratio".to_string(), ratio_fn)?;
+        module.export("best_match".to_string(), best_match_fn)?;
+    }
+
+    Ok(module)
+}
+
+/// The highest-scoring candidate; the first one wins ties.
+pub fn best_match<'a>(query: &str, candidates: &[&'a str], method: Method) -> Option<(&'a str, f64)> {
+    candidates
+        .iter()
+        .map(|candidate| (*candidate, method.score(query, candidate)))
+        .fold(None, |best, (candidate, score)| match best {
+            Some((_, best_score)) if best_score >= score => best,
+            _ => Some((candidate, score)),
+        })
+}
+
+fn method(options: Option<&Value>) -> Result<Method> {
+    let entries = match options.map(|options| &options.kind) {
+        None | Some(ValueKind::Nil) => return Ok(Method::Levenshtein),
+        Some(ValueKind::Map(entries)) => entries,
+        Some(_) => return Err(PrismError::InvalidArgument("fuzzy options must be a map".to_string())),
+    };
+    match entries.iter().find(|(key, _)| key.to_string() == "method").map(|(_, value)| &value.kind) {
+        None => Ok(Method::Levenshtein),
+        Some(ValueKind::String(name)) if name == "levenshtein" => Ok(Method::Levenshtein),
+        Some(ValueKind::String(name)) if name == "jaro_winkler" => Ok(Method::JaroWinkler),
+        Some(other) => Err(PrismError::InvalidArgument(format!(
+            "unknown fuzzy method {}; use \"levenshtein\" or \"jaro_winkler\"",
+            Value::new(other.clone())
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scores_are_normalized() {
+        assert_eq!(Method::Levenshtein.score("flu", "flu"), 1.0);
+        assert_eq!(Method::Levenshtein.score("abc", "xyz"), 0.0);
+        assert!((Method::Levenshtein.score("fever", "fevr") - 0.8).abs() < 1e-9);
+        assert!(Method::JaroWinkler.score("amoxicillin", "amoxicilin") > Method::Levenshtein.score("amoxicillin", "amoxicilin"));
+    }
+
+    #[test]
+    fn test_best_match() {
+        let candidates = ["ibuprofen", "paracetamol", "aspirin"];
+        let (best, score) = best_match("paracetmol", &candidates, Method::Levenshtein).unwrap();
+        assert_eq!(best, "paracetamol");
+        assert!(score > 0.9);
+        assert!(best_match("x", &[], Method::JaroWinkler).is_none());
+    }
+}
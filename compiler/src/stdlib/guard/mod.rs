@@ -0,0 +1,246 @@
+//! The `guard` module: policy checks on text going to and coming from the
+//! LLM.
+//!
+//! Moderation uses a local rule engine: whole-word phrase lists per
+//! category. It catches the obvious cases only, so a clean verdict is never
+//! fully confident. `guard.wrap(fn)` applies both checks around a call, the
+//! way a middleware would around a completion.
+
+use std::sync::{Arc, LazyLock};
+use parking_lot::RwLock;
+use regex::Regex;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{Value, ValueKind};
+
+/// Confidence of a verdict that found nothing; phrase lists miss paraphrases.
+pub const CLEAN_CONFIDENCE: f64 = 0.7;
+
+/// Category scores at or above this flag the text unless told otherwise.
+pub const DEFAULT_THRESHOLD: f64 = 0.5;
+
+const CATEGORIES: &[(&str, &[&str])] = &[
+    ("violence", &["kill", "murder", "shoot", "stab", "bomb", "behead", "massacre"]),
+    ("self_harm", &["suicide", "kill myself", "end my life", "self harm", "self-harm", "cut myself"]),
+    ("harassment", &["idiot", "moron", "loser", "shut up", "worthless"]),
+    ("weapons", &["explosive", "detonator", "nerve agent", "untraceable gun"]),
+];
+
+/// Patterns masked by `guard.pii_scrub`, in the order they are applied.
+static PII: LazyLock<Vec<(Regex, &str)>> = LazyLock::new(|| {
+    [
+        (r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}", "[EMAIL]"),
+        (r"\b\d{3}-\d{2}-\d{4}\b", "[SSN]"),
+        (r"\b(?:\d[ -]?){12,18}\d\b", "[CARD]"),
+        (r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{3}\)|\b\d{3})[ .-]?\d{3}[ .-]?\d{4}\b", "[PHONE]"),
+        (r"\b(?:\d{1,3}\.){3}\d{1,3}\b", "[IP]"),
+    ]
+    .into_iter()
+    .map(|(pattern, mask)| (Regex::new(pattern).expect("valid PII pattern"), mask))
+    .collect()
+});
+
+pub fn init_guard_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("guard".to_string())));
+
+    // moderate function: moderate(text, { threshold: 0.5 }) is
+    // {flagged, categories, scores}
+    let moderate_fn = Value::new(ValueKind::NativeFunction {
+        name: "moderate".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let text = string(args.first(), "guard.moderate")?;
+            let threshold = threshold(args.get(1))?;
+            Ok(moderate(text, threshold).to_value())
+        }),
+    });
+
+    // pii_scrub function: masks emails, phone numbers, SSNs, card numbers and
+    // IP addresses
+    let pii_scrub_fn = Value::new(ValueKind::NativeFunction {
+        name: "pii_scrub".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let text = string(args.first(), "guard.pii_scrub")?;
+            let confidence = args.first().map_or(1.0, |arg| arg.confidence);
+            Ok(Value::with_confidence(ValueKind::String(pii_scrub(text)), confidence))
+        }),
+    });
+
+    // wrap function: wrap(fn, { threshold }) returns a function that scrubs
+    // PII from its string arguments, calls fn and fails if fn's output is
+    // flagged by moderation
+    let wrap_fn = Value::new(ValueKind::NativeFunction {
+        name: "wrap".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let callee = match args.first() {
+                Some(callee @ Value {
+                    kind: ValueKind::Function { .. } | ValueKind::NativeFunction { .. } | ValueKind::AsyncNativeFunction { .. },
+                    ..
+                }) => callee.clone(),
+                _ => return Err(PrismError::InvalidArgument("guard.wrap expects a function".to_string())),
+            };
+            let threshold = threshold(args.get(1))?;
+            Ok(Value::new(ValueKind::AsyncNativeFunction {
+                name: "guarded".to_string(),
+                arity: 1,
+                handler: Arc::new(move |interpreter, args| {
+                    let callee = callee.clone();
+                    Box::pin(async move {
+                        let args = args
+                            .into_iter()
+                            .map(|arg| match &arg.kind {
+                                ValueKind::String(text) => Value {
+                                    kind: ValueKind::String(pii_scrub(text)),
+                                    ..arg
+                                },
+                                _ => arg,
+                            })
+                            .collect();
+                        let output = interpreter.call(callee, args).await?;
+                        if let ValueKind::String(text) = &output.kind {
+                            let verdict = moderate(text, threshold);
+                            if verdict.flagged {
+                                return Err(PrismError::RuntimeError(format!(
+                                    "guard: output flagged for {}",
+                                    verdict.categories().join(", ")
+                                )));
+                            }
+                        }
+                        Ok(output)
+                    })
+                }),
+            }))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("moderate".to_string(), moderate_fn)?;
+        module.export("pii_scrub".to_string(), pii_scrub_fn)?;
+        module.export("wrap".to_string(), wrap_fn)?;
+    }
+
+    Ok(module)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict {
+    pub flagged: bool,
+    /// Every category with its score: 1 - 0.5^n for n matched phrases.
+    pub scores: Vec<(&'static str, f64)>,
+    threshold: f64,
+}
+
+impl Verdict {
+    /// The categories at or above the threshold.
+    pub fn categories(&self) -> Vec<&'static str> {
+        self.scores
+            .iter()
+            .filter(|(_, score)| *score >= self.threshold)
+            .map(|(category, _)| *category)
+            .collect()
+    }
+
+    /// The top score when flagged, [`CLEAN_CONFIDENCE`] otherwise.
+    pub fn confidence(&self) -> f64 {
+        if self.flagged {
+            self.scores.iter().map(|(_, score)| *score).fold(0.0, f64::max)
+        } else {
+            CLEAN_CONFIDENCE
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        let string = |text: &str| Value::new(ValueKind::String(text.to_string()));
+        let categories = self.categories().into_iter().map(string).collect();
+        let scores = self
+            .scores
+            .iter()
+            .map(|(category, score)| (string(category), Value::new(ValueKind::Number(*score))))
+            .collect();
+        Value::with_confidence(
+            ValueKind::Map(vec![
+                (string("flagged"), Value::with_confidence(ValueKind::Boolean(self.flagged), self.confidence())),
+                (string("categories"), Value::new(ValueKind::List(categories))),
+                (string("scores"), Value::new(ValueKind::Map(scores))),
+            ]),
+            self.confidence(),
+        )
+    }
+}
+
+pub fn moderate(text: &str, threshold: f64) -> Verdict {
+    let words: Vec<String> = text
+        .split(|c: char| !(c.is_alphanumeric() || c == '-' || c == '\''))
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let scores: Vec<(&'static str, f64)> = CATEGORIES
+        .iter()
+        .map(|(category, phrases)| {
+            let hits: usize = phrases
+                .iter()
+                .map(|phrase| {
+                    let phrase: Vec<&str> = phrase.split(' ').collect();
+                    words.windows(phrase.len()).filter(|window| *window == phrase.as_slice()).count()
+                })
+                .sum();
+            (*category, 1.0 - 0.5f64.powi(hits as i32))
+        })
+        .collect();
+    let flagged = scores.iter().any(|(_, score)| *score >= threshold);
+    Verdict { flagged, scores, threshold }
+}
+
+pub fn pii_scrub(text: &str) -> String {
+    PII.iter()
+        .fold(text.to_string(), |text, (pattern, mask)| pattern.replace_all(&text, *mask).into_owned())
+}
+
+fn string<'a>(arg: Option<&'a Value>, name: &str) -> Result<&'a str> {
+    match arg.map(|arg| &arg.kind) {
+        Some(ValueKind::String(text)) => Ok(text),
+        _ => Err(PrismError::InvalidArgument(format!("{} expects a string", name))),
+    }
+}
+
+fn threshold(options: Option<&Value>) -> Result<f64> {
+    let entries = match options.map(|options| &options.kind) {
+        None | Some(ValueKind::Nil) => return Ok(DEFAULT_THRESHOLD),
+        Some(ValueKind::Map(entries)) => entries,
+        Some(_) => return Err(PrismError::InvalidArgument("guard options must be a map".to_string())),
+    };
+    match entries.iter().find(|(key, _)| key.to_string() == "threshold") {
+        None => Ok(DEFAULT_THRESHOLD),
+        Some((_, Value { kind: ValueKind::Number(n), .. })) if (0.0..=1.0).contains(n) => Ok(*n),
+        Some((_, value)) => Err(PrismError::InvalidArgument(format!("threshold must be between 0 and 1, got {}", value))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pii_scrub() {
+        assert_eq!(
+            pii_scrub("Mail jane.doe@example.org or call (555) 123-4567, SSN 123-45-6789, card 4111 1111 1111 1111, host 10.0.0.12"),
+            "Mail [EMAIL] or call [PHONE], SSN [SSN], card [CARD], host [IP]"
+        );
+        assert_eq!(pii_scrub("Dose 500 mg twice daily"), "Dose 500 mg twice daily");
+    }
+
+    #[test]
+    fn test_moderate() {
+        let verdict = moderate("I want to kill myself", DEFAULT_THRESHOLD);
+        assert!(verdict.flagged);
+        assert_eq!(verdict.categories(), ["violence", "self_harm"]);
+
+        let clean = moderate("The patient killed time reading", DEFAULT_THRESHOLD);
+        assert!(!clean.flagged);
+        assert_eq!(clean.confidence(), CLEAN_CONFIDENCE);
+        assert!(!moderate("you idiot", 0.7).flagged);
+    }
+}
@@ -0,0 +1,163 @@
+//! `html.parse`, `html.select` and `html.text`: HTML scraping, behind the
+//! `html` cargo feature.
+//!
+//! Documents and nodes are plain maps so they can be stored, printed and
+//! passed between tasks: a document is `{tag: "#document", html}` and a node
+//! is `{tag, attributes, html}` holding its outer HTML. Selecting from either
+//! re-parses that HTML.
+
+use std::sync::Arc;
+use parking_lot::RwLock;
+use scraper::{ElementRef, Html, Node, Selector};
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{Value, ValueKind};
+
+const DOCUMENT_TAG: &str = "#document";
+
+/// Elements whose contents are never text a reader would see.
+const HIDDEN_TAGS: &[&str] = &["script", "style", "noscript", "template", "head"];
+
+pub fn init_html_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("html".to_string())));
+
+    // parse function: keeps the source so later selects can re-parse it
+    let parse_fn = Value::new(ValueKind::NativeFunction {
+        name: "parse".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let text = match args.first().map(|arg| &arg.kind) {
+                Some(ValueKind::String(text)) => text,
+                _ => return Err(PrismError::InvalidArgument("html.parse expects a string".to_string())),
+            };
+            Ok(map(vec![
+                ("tag", string(DOCUMENT_TAG)),
+                ("html", string(text)),
+            ]))
+        }),
+    });
+
+    // select function: every element matching a CSS selector, in document order
+    let select_fn = Value::new(ValueKind::NativeFunction {
+        name: "select".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let tree = tree(args.first(), "select")?;
+            let selector = match args.get(1).map(|arg| &arg.kind) {
+                Some(ValueKind::String(selector)) => Selector::parse(selector)
+                    .map_err(|err| PrismError::InvalidArgument(format!("html.select: invalid selector '{}': {}", selector, err)))?,
+                _ => return Err(PrismError::InvalidArgument("html.select expects a CSS selector".to_string())),
+            };
+            Ok(Value::new(ValueKind::List(tree.select(&selector).map(node).collect())))
+        }),
+    });
+
+    // text function: the visible text with whitespace collapsed
+    let text_fn = Value::new(ValueKind::NativeFunction {
+        name: "text".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let tree = tree(args.first(), "text")?;
+            let mut words = Vec::new();
+            collect_text(tree.root_element(), &mut words);
+            Ok(Value::new(ValueKind::String(words.join(" "))))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("parse".to_string(), parse_fn)?;
+        module.export("select".to_string(), select_fn)?;
+        module.export("text".to_string(), text_fn)?;
+    }
+
+    Ok(module)
+}
+
+/// Re-parses a document, a node or a raw HTML string.
+fn tree(value: Option<&Value>, operation: &str) -> Result<Html> {
+    let invalid = || PrismError::InvalidArgument(format!("html.{} expects a document, node or string", operation));
+    match value.map(|value| &value.kind) {
+        Some(ValueKind::String(text)) => Ok(Html::parse_document(text)),
+        Some(ValueKind::Map(entries)) => {
+            let field = |name: &str| entries.iter().find_map(|(key, value)| match (&key.kind, &value.kind) {
+                (ValueKind::String(key), ValueKind::String(value)) if key == name => Some(value.as_str()),
+                _ => None,
+            });
+            let html = field("html").ok_or_else(invalid)?;
+            if field("tag") == Some(DOCUMENT_TAG) {
+                Ok(Html::parse_document(html))
+            } else {
+                Ok(Html::parse_fragment(html))
+            }
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn node(element: ElementRef) -> Value {
+    let attributes = element
+        .value()
+        .attrs()
+        .map(|(name, value)| (string(name), string(value)))
+        .collect();
+    map(vec![
+        ("tag", string(element.value().name())),
+        ("attributes", Value::new(ValueKind::Map(attributes))),
+        ("html", string(&element.html())),
+    ])
+}
+
+fn collect_text(element: ElementRef, words: &mut Vec<String>) {
+    if HIDDEN_TAGS.contains(&element.value().name()) {
+        return;
+    }
+    for child in element.children() {
+        match child.value() {
+            Node::Text(text) => words.extend(text.split_whitespace().map(str::to_string)),
+            Node::Element(_) => {
+                if let Some(child) = ElementRef::wrap(child) {
+                    collect_text(child, words);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn string(text: &str) -> Value {
+    Value::new(ValueKind::String(text.to_string()))
+}
+
+fn map(entries: Vec<(&str, Value)>) -> Value {
+    Value::new(ValueKind::Map(entries.into_iter().map(|(key, value)| (string(key), value)).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = "<html><head><title>Notes</title><style>p { color: red }</style></head>
+        <body><p class=\"lead\">Fever   and <b>cough</b></p><script>track()</script>
+        <a href=\"/a\">First</a><a href=\"/b\">Second</a></body></html>";
+
+    #[test]
+    fn test_select_and_text() -> Result<()> {
+        let document = map(vec![("tag", string(DOCUMENT_TAG)), ("html", string(PAGE))]);
+        let tree = tree(Some(&document), "select")?;
+
+        let links: Vec<Value> = tree.select(&Selector::parse("a").unwrap()).map(node).collect();
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[1].to_string(), "{tag: a, attributes: {href: /b}, html: <a href=\"/b\">Second</a>}");
+
+        let mut words = Vec::new();
+        collect_text(tree.root_element(), &mut words);
+        assert_eq!(words.join(" "), "Fever and cough First Second");
+
+        let lead = tree.select(&Selector::parse("p.lead").unwrap()).map(node).next().unwrap();
+        let mut words = Vec::new();
+        collect_text(self::tree(Some(&lead), "text")?.root_element(), &mut words);
+        assert_eq!(words.join(" "), "Fever and cough");
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::Arc;
+use parking_lot::RwLock;
+use crate::capabilities::Capability;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{Value, ValueKind};
+
+pub fn init_io_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("io".to_string())));
+
+    // input function: shows the optional prompt and reads one line, or nil
+    // once the input is exhausted
+    let input_fn = Value::new(ValueKind::NativeFunction {
+        name: "input".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            interpreter.capabilities().require(Capability::Stdin, "io.input")?;
+            match args.first().map(|arg| &arg.kind) {
+                Some(ValueKind::String(prompt)) => interpreter.write_output(prompt),
+                Some(_) => return Err(PrismError::InvalidArgument("io.input expects a string prompt".to_string())),
+                None => {}
+            }
+            Ok(match interpreter.input().read_line()? {
+                Some(line) => Value::new(ValueKind::String(line)),
+                None => Value::new(ValueKind::Nil),
+            })
+        }),
+    });
+
+    // read_stdin function: everything piped into the script
+    let read_stdin_fn = Value::new(ValueKind::NativeFunction {
+        name: "read_stdin".to_string(),
+        arity: 0,
+        handler: Arc::new(|interpreter, _| {
+            interpreter.capabilities().require(Capability::Stdin, "io.read_stdin")?;
+            Ok(Value::new(ValueKind::String(interpreter.input().read_to_end()?)))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("input".to_string(), input_fn)?;
+        module.export("read_stdin".to_string(), read_stdin_fn)?;
+    }
+
+    Ok(module)
+}
@@ -0,0 +1,118 @@
+//! `llm.complete_batch(prompts, options?)`: many completions at once.
+//!
+//! Each prompt is completed in its own [fork](Interpreter::fork) with at
+//! most `concurrency` requests in flight (4 by default), on top of the
+//! provider limits of the [request queue](crate::llm::queue). No provider
+//! here offers a batch endpoint yet, so every batch fans out this way.
+//!
+//! A failed prompt does not fail the batch. The result is a map
+//! `{ responses, failures }`: `responses` is aligned to `prompts`, with nil
+//! where a prompt failed, and `failures` lists `{ index, prompt, error }`
+//! for each of those.
+
+use std::sync::Arc;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::stdlib::tasks::{drive, Task, DEFAULT_CONCURRENCY};
+use crate::value::{Value, ValueKind};
+
+fn string(text: &str) -> Value {
+    Value::new(ValueKind::String(text.to_string()))
+}
+
+fn options(arg: Option<&Value>) -> Result<usize> {
+    let mut concurrency = DEFAULT_CONCURRENCY;
+    match arg.map(|arg| &arg.kind) {
+        None | Some(ValueKind::Nil) => {}
+        Some(ValueKind::Map(options)) => {
+            for (key, value) in options {
+                match (key.to_string().as_str(), &value.kind) {
+                    ("concurrency", ValueKind::Number(n)) if *n >= 1.0 => concurrency = *n as usize,
+                    (key, _) => {
+                        return Err(PrismError::InvalidArgument(format!(
+                            "llm.complete_batch: invalid option '{}'",
+                            key
+                        )))
+                    }
+                }
+            }
+        }
+        Some(_) => return Err(PrismError::InvalidArgument(
+            "llm.complete_batch expects a map of options".to_string(),
+        )),
+    }
+    Ok(concurrency)
+}
+
+async fn complete_batch(interpreter: &mut Interpreter, prompts: Vec<Value>, concurrency: usize) -> Result<Value> {
+    let tasks: Vec<Task> = prompts
+        .iter()
+        .cloned()
+        .map(|prompt| {
+            let mut fork = interpreter.fork();
+            Box::pin(async move {
+                let result = match &prompt.kind {
+                    ValueKind::String(text) => super::complete(&mut fork, text, prompt.context.as_deref()).await,
+                    _ => Err(PrismError::InvalidArgument(format!("{} is not a prompt", prompt))),
+                };
+                (fork, result)
+            }) as Task
+        })
+        .collect();
+
+    let mut responses = vec![Value::new(ValueKind::Nil); prompts.len()];
+    let mut errors: Vec<(usize, PrismError)> = Vec::new();
+    let mut cancelled = None;
+    drive(tasks, concurrency, |index, fork, result| {
+        interpreter.join(fork);
+        match result {
+            Ok(response) => responses[index] = response,
+            // Cancellation stops the whole batch, not just one prompt
+            Err(err @ PrismError::Cancelled(_)) => {
+                cancelled = Some(err);
+                return true;
+            }
+            Err(err) => errors.push((index, err)),
+        }
+        false
+    })
+    .await;
+    if let Some(err) = cancelled {
+        return Err(err);
+    }
+
+    errors.sort_by_key(|(index, _)| *index);
+    let failures = errors
+        .into_iter()
+        .map(|(index, err)| {
+            Value::new(ValueKind::Map(vec![
+                (string("index"), Value::new(ValueKind::Number(index as f64))),
+                (string("prompt"), prompts[index].clone()),
+                (string("error"), string(&err.to_string())),
+            ]))
+        })
+        .collect();
+    Ok(Value::new(ValueKind::Map(vec![
+        (string("responses"), Value::new(ValueKind::List(responses))),
+        (string("failures"), Value::new(ValueKind::List(failures))),
+    ])))
+}
+
+/// The `llm.complete_batch` native.
+pub fn complete_batch_fn() -> Value {
+    Value::new(ValueKind::AsyncNativeFunction {
+        name: "complete_batch".to_string(),
+        arity: 2,
+        handler: Arc::new(|interpreter, args| {
+            Box::pin(async move {
+                let Some(ValueKind::List(prompts)) = args.first().map(|arg| arg.kind.clone()) else {
+                    return Err(PrismError::InvalidArgument(
+                        "llm.complete_batch expects a list of prompts".to_string(),
+                    ));
+                };
+                let concurrency = options(args.get(1))?;
+                complete_batch(interpreter, prompts, concurrency).await
+            })
+        }),
+    })
+}
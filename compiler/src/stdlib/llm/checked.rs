@@ -0,0 +1,120 @@
+//! `llm.complete_checked(prompt, options?)`: an answer verified by a second
+//! model.
+//!
+//! The active model answers `prompt`, then the critic model (the `critic`
+//! option, else the configured `critic`, else the same model) is asked
+//! whether the answer is correct. A critic answering "yes" supports the
+//! answer with its own confidence, one answering "no" with the rest of it,
+//! and any other reply is taken as undecided (0.5). The two confidences
+//! combine by the interpreter's [`CombineStrategy`](crate::confidence::CombineStrategy).
+//!
+//! The result is a map `{ answer, verdict, provenance: { model, critic } }`
+//! naming both models; it and its `answer` carry the combined confidence.
+
+use std::sync::Arc;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::value::{Value, ValueKind};
+
+/// How much an undecided critic supports the answer.
+const UNDECIDED: f64 = 0.5;
+
+/// The request asking the critic to verify `answer` to `question`.
+pub fn critique_prompt(question: &str, answer: &str) -> String {
+    format!(
+        "Is the answer to this question correct? Reply \"yes\" or \"no\" first, then explain.\n\nQuestion: {}\n\nAnswer: {}",
+        question, answer
+    )
+}
+
+/// How strongly the critic's `verdict` supports the answer.
+fn support(verdict: &Value) -> f64 {
+    let text = verdict.to_string().to_lowercase();
+    let first = text.split(|c: char| !c.is_alphanumeric()).find(|word| !word.is_empty());
+    match first {
+        Some("yes") => verdict.confidence,
+        Some("no") => 1.0 - verdict.confidence,
+        _ => UNDECIDED,
+    }
+}
+
+async fn complete_checked(interpreter: &mut Interpreter, prompt: &Value, critic: Option<String>) -> Result<Value> {
+    let ValueKind::String(question) = &prompt.kind else {
+        return Err(PrismError::InvalidArgument("llm.complete_checked expects a prompt".to_string()));
+    };
+    let config = interpreter.active_model_config();
+    let mut critic_config = config.clone();
+    critic_config.model = critic.or_else(|| config.critic.clone()).unwrap_or_else(|| config.model.clone());
+
+    let mut answer = super::complete_with(interpreter, config.clone(), question, prompt.context.as_deref()).await?;
+    let request = critique_prompt(question, &answer.to_string());
+    let verdict = super::complete_with(interpreter, critic_config.clone(), &request, None).await?;
+    let confidence = interpreter.combine_strategy().combine([answer.confidence, support(&verdict)]);
+    answer.confidence = confidence;
+
+    let string = |text: &str| Value::new(ValueKind::String(text.to_string()));
+    let provenance = Value::new(ValueKind::Map(vec![
+        (string("model"), string(&config.model)),
+        (string("critic"), string(&critic_config.model)),
+    ]));
+    let context = answer.context.clone();
+    let mut result = Value::with_confidence(
+        ValueKind::Map(vec![
+            (string("answer"), answer),
+            (string("verdict"), verdict),
+            (string("provenance"), provenance),
+        ]),
+        confidence,
+    );
+    result.context = context;
+    Ok(result)
+}
+
+/// The `llm.complete_checked` native.
+pub fn complete_checked_fn() -> Value {
+    Value::new(ValueKind::AsyncNativeFunction {
+        name: "complete_checked".to_string(),
+        arity: 2,
+        handler: Arc::new(|interpreter, args| {
+            Box::pin(async move {
+                let prompt = args.first().cloned().unwrap_or_else(|| Value::new(ValueKind::Nil));
+                let critic = match args.get(1).map(|arg| &arg.kind) {
+                    None | Some(ValueKind::Nil) => None,
+                    Some(ValueKind::Map(options)) => {
+                        let mut critic = None;
+                        for (key, value) in options {
+                            match (key.to_string().as_str(), &value.kind) {
+                                ("critic", ValueKind::String(model)) => critic = Some(model.clone()),
+                                (key, _) => {
+                                    return Err(PrismError::InvalidArgument(format!(
+                                        "llm.complete_checked: invalid option '{}'",
+                                        key
+                                    )))
+                                }
+                            }
+                        }
+                        critic
+                    }
+                    Some(_) => return Err(PrismError::InvalidArgument(
+                        "llm.complete_checked expects a map of options".to_string(),
+                    )),
+                };
+                complete_checked(interpreter, &prompt, critic).await
+            })
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verdicts_support_or_undermine_the_answer() {
+        let verdict = |text: &str, confidence| Value::with_confidence(ValueKind::String(text.to_string()), confidence);
+        assert_eq!(support(&verdict("Yes, 500 mg is a standard adult dose.", 0.9)), 0.9);
+        assert!((support(&verdict("NO - the dose is too high", 0.9)) - 0.1).abs() < 1e-9);
+        assert_eq!(support(&verdict("Nobody can tell", 0.9)), UNDECIDED);
+        assert_eq!(support(&verdict("", 0.9)), UNDECIDED);
+    }
+}
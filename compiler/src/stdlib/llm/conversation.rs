@@ -0,0 +1,418 @@
+//! `llm.conversation(options?)`: a multi-turn chat that stays within the
+//! model's context window.
+//!
+//! The conversation is a map of functions sharing one history: `say(text)`
+//! sends the history and `text` to the model and returns its answer,
+//! `history()` lists the turns as `{ role, content }` maps carrying their
+//! confidence, and `tokens()` counts the tokens the history takes up. The
+//! `system` option gives the model instructions for the whole chat.
+//!
+//! `export()` returns the session as a plain map for auditing or storage,
+//! e.g. in a snapshot: the settings and every message with its role,
+//! content, confidence, token count and the model that wrote it, and the
+//! models and tokens overall. `import(data)` resumes an exported session in
+//! place of the current one, recounting its tokens. Both are recorded in
+//! the audit log.
+//!
+//! Before a request would not fit, all but the latest `keep` turns (2 by
+//! default) are replaced by a summary the model writes when given the
+//! `summarizer` prompt. A summary is less confident than what it replaces,
+//! and an answer no more confident than the least confident turn it was
+//! given. The window is `window` tokens if given, and otherwise the model's
+//! context window less its `max_tokens` for the answer.
+
+use std::sync::Arc;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use crate::audit::AuditEvent;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::llm::{context_window, ChatMessage, CompletionRequest, Role};
+use crate::llm::tokenizer::{ApproximateTokenizer, Tokenizer};
+use crate::value::{Value, ValueKind};
+
+/// The prompt a summary is requested with when none is configured.
+pub const DEFAULT_SUMMARIZER: &str =
+    "Summarize this conversation, keeping every fact and decision needed to continue it:";
+
+/// How much a summary's confidence is reduced below that of its turns.
+pub const SUMMARY_CONFIDENCE: f64 = 0.8;
+
+/// Latest turns that are never summarized, when not configured.
+const DEFAULT_KEEP: usize = 2;
+
+/// Identifies exported conversations and the version of their layout.
+pub const EXPORT_FORMAT: &str = "prism.conversation/1";
+
+#[derive(Debug, Clone, PartialEq)]
+struct Turn {
+    role: &'static str,
+    content: String,
+    confidence: f64,
+    /// The model that wrote an answer or summary.
+    model: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExportedMessage {
+    role: String,
+    content: String,
+    confidence: f64,
+    #[serde(default)]
+    tokens: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Export {
+    format: String,
+    #[serde(default)]
+    system: Option<String>,
+    #[serde(default)]
+    window: Option<usize>,
+    keep: usize,
+    summarizer: String,
+    messages: Vec<ExportedMessage>,
+    #[serde(default)]
+    models: Vec<String>,
+    #[serde(default)]
+    tokens: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Conversation {
+    turns: Vec<Turn>,
+    window: Option<usize>,
+    summarizer: String,
+    keep: usize,
+    system: Option<String>,
+}
+
+fn render(turns: &[Turn]) -> String {
+    turns.iter().map(|turn| format!("{}: {}\n", turn.role, turn.content)).collect()
+}
+
+impl Conversation {
+    /// The request the turns make up; a summary instructs the model as
+    /// system messages do.
+    fn request(&self) -> CompletionRequest {
+        let messages = self
+            .turns
+            .iter()
+            .map(|turn| match turn.role {
+                "summary" => ChatMessage::new(Role::System, format!("Summary of the conversation so far: {}", turn.content)),
+                "assistant" => ChatMessage::new(Role::Assistant, turn.content.clone()),
+                _ => ChatMessage::new(Role::User, turn.content.clone()),
+            })
+            .collect();
+        let request = CompletionRequest::new("").with_messages(messages);
+        match &self.system {
+            Some(system) => request.with_system(system.clone()),
+            None => request,
+        }
+    }
+
+    fn tokens(&self) -> usize {
+        ApproximateTokenizer.count(&self.request().transcript())
+    }
+
+    /// The request for a summary of the turns that have to go for the rest
+    /// to fit in `limit` tokens, if any have to.
+    fn summary_prompt(&self, limit: usize) -> Option<String> {
+        if self.tokens() <= limit || self.turns.len() <= self.keep {
+            return None;
+        }
+        let older = &self.turns[..self.turns.len() - self.keep];
+        Some(format!("{}\n\n{}", self.summarizer, render(older)))
+    }
+
+    /// Replaces the turns [`summary_prompt`](Self::summary_prompt) asked
+    /// about with `summary`, written by `model`.
+    fn summarize(&mut self, summary: &Value, model: Option<&str>) {
+        let older: Vec<Turn> = self.turns.drain(..self.turns.len() - self.keep).collect();
+        let confidence = older.iter().map(|turn| turn.confidence).fold(summary.confidence, f64::min);
+        let summary = Turn {
+            role: "summary",
+            content: summary.to_string(),
+            confidence: confidence * SUMMARY_CONFIDENCE,
+            model: model.map(str::to_string),
+        };
+        self.turns.insert(0, summary);
+    }
+
+    /// The request for the model's next answer.
+    fn prompt(&self, limit: usize) -> Result<CompletionRequest> {
+        let tokens = self.tokens();
+        if tokens > limit {
+            return Err(PrismError::RuntimeError(format!(
+                "The conversation takes {} tokens even when summarized, more than the {} its window allows",
+                tokens, limit
+            )));
+        }
+        Ok(self.request())
+    }
+
+    fn confidence(&self) -> f64 {
+        self.turns.iter().map(|turn| turn.confidence).fold(1.0, f64::min)
+    }
+
+    fn export(&self) -> Export {
+        let messages: Vec<ExportedMessage> = self
+            .turns
+            .iter()
+            .map(|turn| ExportedMessage {
+                role: turn.role.to_string(),
+                content: turn.content.clone(),
+                confidence: turn.confidence,
+                tokens: ApproximateTokenizer.count(&turn.content),
+                model: turn.model.clone(),
+            })
+            .collect();
+        let mut models: Vec<String> = Vec::new();
+        for model in self.turns.iter().filter_map(|turn| turn.model.as_ref()) {
+            if !models.contains(model) {
+                models.push(model.clone());
+            }
+        }
+        Export {
+            format: EXPORT_FORMAT.to_string(),
+            system: self.system.clone(),
+            window: self.window,
+            keep: self.keep,
+            summarizer: self.summarizer.clone(),
+            tokens: self.tokens(),
+            messages,
+            models,
+        }
+    }
+
+    fn import(data: Export) -> Result<Self> {
+        if data.format != EXPORT_FORMAT {
+            return Err(PrismError::InvalidArgument(format!(
+                "Not an exported conversation: format '{}', expected '{}'",
+                data.format, EXPORT_FORMAT
+            )));
+        }
+        let turns = data
+            .messages
+            .into_iter()
+            .map(|message| {
+                let role = match message.role.as_str() {
+                    "user" => "user",
+                    "assistant" => "assistant",
+                    "summary" => "summary",
+                    role => return Err(PrismError::InvalidArgument(format!("Unknown conversation role '{}'", role))),
+                };
+                Ok(Turn { role, content: message.content, confidence: message.confidence, model: message.model })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Conversation {
+            turns,
+            window: data.window,
+            summarizer: data.summarizer,
+            keep: data.keep,
+            system: data.system,
+        })
+    }
+}
+
+async fn say(state: &Mutex<Conversation>, interpreter: &mut Interpreter, message: Value) -> Result<Value> {
+    let content = match message.kind {
+        ValueKind::String(text) => text,
+        kind => Value::new(kind).to_string(),
+    };
+    // Requests run without the lock; concurrent turns would race anyway
+    let mut conversation = state.lock().clone();
+    conversation.turns.push(Turn { role: "user", content, confidence: message.confidence, model: None });
+
+    let config = interpreter.active_model_config();
+    let limit = conversation
+        .window
+        .unwrap_or_else(|| context_window(&config.model).saturating_sub(config.max_tokens));
+    if let Some(request) = conversation.summary_prompt(limit) {
+        let summary = super::complete(interpreter, &request, None).await?;
+        conversation.summarize(&summary, Some(&config.model));
+    }
+    let request = conversation.prompt(limit)?;
+    let mut answer = super::complete_chat(interpreter, request).await?;
+    answer.confidence = answer.confidence.min(conversation.confidence());
+    conversation.turns.push(Turn {
+        role: "assistant",
+        content: answer.to_string(),
+        confidence: answer.confidence,
+        model: Some(config.model.clone()),
+    });
+    *state.lock() = conversation;
+    Ok(answer)
+}
+
+/// The `llm.conversation` native.
+pub fn conversation_fn() -> Value {
+    Value::new(ValueKind::NativeFunction {
+        name: "conversation".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let mut conversation = Conversation {
+                turns: Vec::new(),
+                window: None,
+                summarizer: DEFAULT_SUMMARIZER.to_string(),
+                keep: DEFAULT_KEEP,
+                system: None,
+            };
+            match args.first().map(|arg| &arg.kind) {
+                None | Some(ValueKind::Nil) => {}
+                Some(ValueKind::Map(options)) => {
+                    for (key, value) in options {
+                        match (key.to_string().as_str(), &value.kind) {
+                            ("window", ValueKind::Number(n)) if *n >= 1.0 => conversation.window = Some(*n as usize),
+                            ("keep", ValueKind::Number(n)) if *n >= 0.0 => conversation.keep = *n as usize,
+                            ("summarizer", ValueKind::String(prompt)) => conversation.summarizer = prompt.clone(),
+                            ("system", ValueKind::String(system)) => conversation.system = Some(system.clone()),
+                            (key, _) => {
+                                return Err(PrismError::InvalidArgument(format!(
+                                    "llm.conversation: invalid option '{}'",
+                                    key
+                                )))
+                            }
+                        }
+                    }
+                }
+                Some(_) => return Err(PrismError::InvalidArgument(
+                    "llm.conversation expects a map of options".to_string(),
+                )),
+            }
+            Ok(handles(Arc::new(Mutex::new(conversation))))
+        }),
+    })
+}
+
+fn handles(state: Arc<Mutex<Conversation>>) -> Value {
+    let entry = |name: &str, value: Value| (Value::new(ValueKind::String(name.to_string())), value);
+    let string = |text: &str| Value::new(ValueKind::String(text.to_string()));
+
+    let speaker = Arc::clone(&state);
+    let say_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "say".to_string(),
+        arity: 1,
+        handler: Arc::new(move |interpreter, args| {
+            let state = Arc::clone(&speaker);
+            Box::pin(async move {
+                let message = args.into_iter().next().unwrap_or_else(|| Value::new(ValueKind::Nil));
+                say(&state, interpreter, message).await
+            })
+        }),
+    });
+
+    let reader = Arc::clone(&state);
+    let history_fn = Value::new(ValueKind::NativeFunction {
+        name: "history".to_string(),
+        arity: 0,
+        handler: Arc::new(move |_, _| {
+            let turns = reader
+                .lock()
+                .turns
+                .iter()
+                .map(|turn| {
+                    let entries = vec![(string("role"), string(turn.role)), (string("content"), string(&turn.content))];
+                    Value::with_confidence(ValueKind::Map(entries), turn.confidence)
+                })
+                .collect();
+            Ok(Value::new(ValueKind::List(turns)))
+        }),
+    });
+
+    let counter = Arc::clone(&state);
+    let tokens_fn = Value::new(ValueKind::NativeFunction {
+        name: "tokens".to_string(),
+        arity: 0,
+        handler: Arc::new(move |_, _| Ok(Value::new(ValueKind::Number(counter.lock().tokens() as f64)))),
+    });
+
+    let exporter = Arc::clone(&state);
+    let export_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "export".to_string(),
+        arity: 0,
+        handler: Arc::new(move |interpreter, _| {
+            let export = exporter.lock().export();
+            Box::pin(async move {
+                interpreter.record(AuditEvent::Conversation {
+                    action: "export".to_string(),
+                    messages: export.messages.len(),
+                    tokens: export.tokens,
+                });
+                Ok(Value::from_json(&serde_json::to_value(export)?))
+            })
+        }),
+    });
+
+    let import_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "import".to_string(),
+        arity: 1,
+        handler: Arc::new(move |interpreter, args| {
+            let state = Arc::clone(&state);
+            Box::pin(async move {
+                let data = args.first().map(Value::to_json).transpose()?.unwrap_or_default();
+                let data: Export = serde_json::from_value(data)
+                    .map_err(|err| PrismError::InvalidArgument(format!("Not an exported conversation: {}", err)))?;
+                let conversation = Conversation::import(data)?;
+                interpreter.record(AuditEvent::Conversation {
+                    action: "import".to_string(),
+                    messages: conversation.turns.len(),
+                    tokens: conversation.tokens(),
+                });
+                *state.lock() = conversation;
+                Ok(Value::new(ValueKind::Nil))
+            })
+        }),
+    });
+
+    Value::new(ValueKind::Map(vec![
+        entry("say", say_fn),
+        entry("history", history_fn),
+        entry("tokens", tokens_fn),
+        entry("export", export_fn),
+        entry("import", import_fn),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn(role: &'static str, content: &str, confidence: f64) -> Turn {
+        Turn { role, content: content.to_string(), confidence, model: None }
+    }
+
+    #[test]
+    fn test_older_turns_are_summarized_to_fit() {
+        let mut conversation = Conversation {
+            turns: vec![
+                turn("user", "The patient has had a fever of 39 degrees for three days", 1.0),
+                turn("assistant", "Any cough or shortness of breath?", 0.9),
+                turn("user", "A dry cough", 1.0),
+            ],
+            window: None,
+            summarizer: "Summarize:".to_string(),
+            keep: 1,
+            system: Some("You are a triage nurse".to_string()),
+        };
+        let tokens = conversation.tokens();
+        assert_eq!(conversation.summary_prompt(tokens), None);
+
+        let request = conversation.summary_prompt(tokens - 1).unwrap();
+        assert!(request.starts_with("Summarize:\n\nuser: The patient"));
+        assert!(!request.contains("dry cough"));
+
+        conversation.summarize(&Value::new(ValueKind::String("Fever, 3 days".to_string())), None);
+        assert_eq!(conversation.turns, [turn("summary", "Fever, 3 days", 0.9 * SUMMARY_CONFIDENCE), turn("user", "A dry cough", 1.0)]);
+        assert_eq!(
+            conversation.prompt(tokens).unwrap().transcript(),
+            "system: You are a triage nurse\nsystem: Summary of the conversation so far: Fever, 3 days\nuser: A dry cough\n"
+        );
+        assert!(conversation.confidence() < 0.9);
+        assert!(conversation.prompt(2).is_err());
+    }
+}
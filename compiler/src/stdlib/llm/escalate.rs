@@ -0,0 +1,107 @@
+//! `llm.escalate(prompt, tiers)`: asks ever more capable (and costly)
+//! models until one is confident enough.
+//!
+//! Each tier is a map of model settings, as `llm.configure` takes, with a
+//! `min_confidence` (0 if left out), e.g.
+//! `[{ provider: "rules", min_confidence: 0.8 }, { model: "gpt-4o-mini",
+//! min_confidence: 0.7 }, { model: "gpt-4o" }]`. The first response that
+//! meets its tier's threshold is the answer; when none does, the last
+//! tier's is. A tier that fails moves on to the next, unless it was the
+//! last.
+//!
+//! The result is a map `{ answer, provenance }`, as confident as its
+//! answer, where `provenance` lists the tiers asked in order as
+//! `{ provider, model, confidence }` maps, with `error` for those that
+//! failed.
+
+use std::sync::Arc;
+use crate::config::Profile;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::value::{Value, ValueKind};
+
+struct Tier {
+    settings: Profile,
+    min_confidence: f64,
+}
+
+fn string(text: &str) -> Value {
+    Value::new(ValueKind::String(text.to_string()))
+}
+
+fn tiers(arg: Option<&Value>) -> Result<Vec<Tier>> {
+    let Some(ValueKind::List(tiers)) = arg.map(|arg| &arg.kind) else {
+        return Err(PrismError::InvalidArgument("llm.escalate expects a list of tiers".to_string()));
+    };
+    if tiers.is_empty() {
+        return Err(PrismError::InvalidArgument("llm.escalate needs at least one tier".to_string()));
+    }
+    tiers
+        .iter()
+        .map(|tier| {
+            let ValueKind::Map(entries) = &tier.kind else {
+                return Err(PrismError::InvalidArgument(format!("{} is not a tier of model settings", tier)));
+            };
+            let (threshold, settings): (Vec<_>, Vec<_>) =
+                entries.iter().cloned().partition(|(key, _)| key.to_string() == "min_confidence");
+            let min_confidence = match threshold.first().map(|(_, value)| &value.kind) {
+                None => 0.0,
+                Some(ValueKind::Number(n)) if (0.0..=1.0).contains(n) => *n,
+                Some(_) => return Err(PrismError::InvalidArgument(
+                    "min_confidence must be a number between 0 and 1".to_string(),
+                )),
+            };
+            let settings = super::model_settings(Some(&Value::new(ValueKind::Map(settings))))?;
+            Ok(Tier { settings, min_confidence })
+        })
+        .collect()
+}
+
+async fn escalate(interpreter: &mut Interpreter, prompt: &Value, tiers: Vec<Tier>) -> Result<Value> {
+    let ValueKind::String(text) = &prompt.kind else {
+        return Err(PrismError::InvalidArgument("llm.escalate expects a prompt".to_string()));
+    };
+    let base = interpreter.active_model_config();
+    let last = tiers.len() - 1;
+    let mut provenance = Vec::new();
+    for (index, tier) in tiers.into_iter().enumerate() {
+        let config = tier.settings.apply(base.clone());
+        let mut step = vec![(string("provider"), string(&config.provider)), (string("model"), string(&config.model))];
+        match super::complete_with(interpreter, config, text, prompt.context.as_deref()).await {
+            Ok(answer) => {
+                step.push((string("confidence"), Value::new(ValueKind::Number(answer.confidence))));
+                provenance.push(Value::new(ValueKind::Map(step)));
+                if answer.confidence >= tier.min_confidence || index == last {
+                    let confidence = answer.confidence;
+                    let result = vec![
+                        (string("answer"), answer),
+                        (string("provenance"), Value::new(ValueKind::List(provenance))),
+                    ];
+                    return Ok(Value::with_confidence(ValueKind::Map(result), confidence));
+                }
+            }
+            Err(err @ PrismError::Cancelled(_)) => return Err(err),
+            Err(err) if index == last => return Err(err),
+            Err(err) => {
+                step.push((string("error"), string(&err.to_string())));
+                provenance.push(Value::new(ValueKind::Map(step)));
+            }
+        }
+    }
+    unreachable!("the last tier always returns")
+}
+
+/// The `llm.escalate` native.
+pub fn escalate_fn() -> Value {
+    Value::new(ValueKind::AsyncNativeFunction {
+        name: "escalate".to_string(),
+        arity: 2,
+        handler: Arc::new(|interpreter, args| {
+            Box::pin(async move {
+                let prompt = args.first().cloned().unwrap_or_else(|| Value::new(ValueKind::Nil));
+                let tiers = tiers(args.get(1))?;
+                escalate(interpreter, &prompt, tiers).await
+            })
+        }),
+    })
+}
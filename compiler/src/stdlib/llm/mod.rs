@@ -1,28 +1,405 @@
+use std::borrow::Cow;
 use std::sync::Arc;
+use std::time::Instant;
 use parking_lot::RwLock;
-use crate::error::Result;
+use crate::audit::AuditEvent;
+use crate::config::Profile;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::llm::tokenizer::{ApproximateTokenizer, Tokenizer};
+use crate::capabilities::Capability;
+use crate::llm::cache::ResponseCache;
+use crate::llm::calibration::Calibration;
+use crate::llm::rules;
+use crate::llm::{ChatMessage, CompletionRequest, ModelConfig, Role};
+use crate::stdlib::store::Store;
+use crate::llm::replay::Exchange;
+use crate::stdlib::extract;
+use crate::telemetry;
 use crate::module::Module;
 use crate::value::{Value, ValueKind};
 
+pub mod batch;
+pub mod checked;
+pub mod conversation;
+pub mod escalate;
+
+/// How similar prompts must be to share answers in a semantic cache when
+/// `llm.cache` is not given a threshold.
+const DEFAULT_SIMILARITY: f64 = 0.9;
+
+/// How confident the placeholder answers of a dry run are.
+const DRY_RUN_CONFIDENCE: f64 = 0.1;
+
+/// Sends `text` to the model active in the interpreter's current context
+/// and audits the request; `context` is the context of the prompt itself.
+pub(crate) async fn complete(interpreter: &mut Interpreter, text: &str, context: Option<&str>) -> Result<Value> {
+    let config = interpreter.active_model_config();
+    complete_with(interpreter, config, text, context).await
+}
+
+/// [`complete`] with the model `config` describes.
+pub(crate) async fn complete_with(
+    interpreter: &mut Interpreter,
+    config: ModelConfig,
+    text: &str,
+    context: Option<&str>,
+) -> Result<Value> {
+    let request = CompletionRequest { context: context.map(str::to_string), ..CompletionRequest::new(text) };
+    send(interpreter, config, request).await
+}
+
+/// Sends the chat `request` makes up, keeping the roles of its messages,
+/// to the model active in the interpreter's current context.
+pub(crate) async fn complete_chat(interpreter: &mut Interpreter, request: CompletionRequest) -> Result<Value> {
+    let config = interpreter.active_model_config();
+    send(interpreter, config, request).await
+}
+
+/// `request` with the secrets the interpreter knows of masked, as
+/// providers, recordings and caches get to see it.
+fn redacted(interpreter: &Interpreter, request: CompletionRequest) -> CompletionRequest {
+    let secrets = interpreter.secrets();
+    let redact = |text: String| secrets.redact(&text).into_owned();
+    CompletionRequest {
+        prompt: redact(request.prompt),
+        context: request.context.map(redact),
+        config: request.config,
+        system: request.system.map(redact),
+        messages: request
+            .messages
+            .into_iter()
+            .map(|message| ChatMessage { content: redact(message.content), ..message })
+            .collect(),
+    }
+}
+
+/// Audits `request` and answers it from the replay, the rules or the
+/// provider `config` names.
+async fn send(interpreter: &mut Interpreter, config: ModelConfig, request: CompletionRequest) -> Result<Value> {
+    config.validate()?;
+    let model = config.model.clone();
+    let text = request.transcript();
+    if interpreter.dry_run() {
+        let input_tokens = ApproximateTokenizer.count(&text);
+        let output_tokens = config.max_tokens;
+        interpreter.record(AuditEvent::LlmRequest { model: model.clone(), input_tokens, output_tokens });
+        let placeholder = ValueKind::String(format!("[dry run: {}]", model));
+        return Ok(Value::with_confidence(placeholder, DRY_RUN_CONFIDENCE));
+    }
+    let request = redacted(interpreter, request);
+    let prompt = request.transcript();
+    if let Some(cache) = interpreter.llm_cache() {
+        let cached = cache.get(&model, &prompt);
+        interpreter.metrics().record_cache_lookup(cached.is_some());
+        if let Some(response) = cached {
+            return Ok(response);
+        }
+    }
+    if let Some(budget) = config.budget {
+        let spent: f64 = interpreter
+            .audit_log()
+            .llm_requests()
+            .filter_map(|(model, input, output)| crate::llm::cost(model, input, output))
+            .sum();
+        if spent >= budget {
+            return Err(PrismError::RuntimeError(format!(
+                "Over budget: LLM calls cost ${:.4} of the ${:.4} allowed",
+                spent, budget
+            )));
+        }
+    }
+    let queue = Arc::clone(interpreter.request_queue());
+    let _permit = queue.acquire(&config.provider, interpreter).await?;
+    let span = telemetry::span("prism.llm.request");
+    span.set("gen_ai.request.model", model.clone());
+    if let Some(context) = &request.context {
+        span.set("prism.context", context.clone());
+    }
+    let started = Instant::now();
+    let mut response = match interpreter.replay() {
+        // Rules are never recorded, so they answer replays too
+        _ if config.provider == rules::PROVIDER => match interpreter.rules().read().answer(&prompt) {
+            Some((text, confidence)) => Value::with_confidence(ValueKind::String(text), confidence),
+            None => Value::with_confidence(ValueKind::Nil, 0.0),
+        },
+        Some(replay) => {
+            let exchange = replay.next(&model, &prompt)?;
+            Value::with_confidence(ValueKind::String(exchange.response), exchange.confidence)
+        }
+        None => {
+            let client = interpreter.llm_client(&config.provider)?;
+            let response = client.complete(CompletionRequest { config: Some(config), ..request }).await?;
+            Value::with_confidence(ValueKind::String(response.text), response.confidence as f64)
+        }
+    };
+    // A model may repeat a secret it learned elsewhere; keep it out of
+    // the script, the recording and the cache
+    if let ValueKind::String(text) = &mut response.kind {
+        if let Cow::Owned(redacted) = interpreter.secrets().redact(text) {
+            *text = redacted;
+        }
+    }
+    if response.context.is_none() {
+        response.context = interpreter.contexts().last().cloned();
+    }
+    response.set_meta("model", Value::new(ValueKind::String(model.clone())));
+    response.set_meta("at", Value::new(ValueKind::DateTime(chrono::Utc::now().fixed_offset())));
+    interpreter.metrics().record_llm_request(&model, started.elapsed());
+    if let Some(recorder) = interpreter.recorder() {
+        recorder.record(&Exchange {
+            model: model.clone(),
+            prompt: prompt.clone(),
+            response: response.to_string(),
+            confidence: response.confidence,
+        })?;
+    }
+    // Recordings keep the raw confidence, so replays are calibrated afresh
+    let calibration = Arc::clone(interpreter.calibration());
+    let id = calibration.issue(&model, response.confidence);
+    response.confidence = calibration.calibrate(&model, response.confidence);
+    interpreter.set_last_response_id(id);
+    if let Some(cache) = interpreter.llm_cache() {
+        cache.insert(&model, &prompt, &response);
+    }
+
+    let input_tokens = ApproximateTokenizer.count(&text);
+    let output_tokens = ApproximateTokenizer.count(&response.to_string());
+    span.set("gen_ai.usage.input_tokens", input_tokens as i64);
+    span.set("gen_ai.usage.output_tokens", output_tokens as i64);
+    span.set("prism.confidence", response.confidence);
+    interpreter.record(AuditEvent::LlmRequest { model, input_tokens, output_tokens });
+    Ok(response)
+}
+
+/// The model settings a script gives as a map, e.g. `{ model: "gpt-4o" }`.
+fn model_settings(settings: Option<&Value>) -> Result<Profile> {
+    let Some(settings @ Value { kind: ValueKind::Map(_), .. }) = settings else {
+        return Err(PrismError::InvalidArgument("expected a map of model settings".to_string()));
+    };
+    serde_json::from_value(settings.to_json()?)
+        .map_err(|err| PrismError::InvalidArgument(format!("Invalid model settings: {}", err)))
+}
+
+/// The chat `messages` lists as `{ role, content }` maps.
+fn chat_messages(messages: &[Value]) -> Result<Vec<ChatMessage>> {
+    messages
+        .iter()
+        .map(|message| {
+            let ValueKind::Map(entries) = &message.kind else {
+                return Err(PrismError::InvalidArgument(format!("{} is not a {{ role, content }} message", message)));
+            };
+            let field = |name: &str| entries.iter().find(|(key, _)| key.to_string() == name).map(|(_, value)| value.to_string());
+            match (field("role"), field("content")) {
+                (Some(role), Some(content)) => Ok(ChatMessage::new(role.parse::<Role>()?, content)),
+                _ => Err(PrismError::InvalidArgument(format!("{} is not a {{ role, content }} message", message))),
+            }
+        })
+        .collect()
+}
+
 pub fn init_llm_module() -> Result<Arc<RwLock<Module>>> {
     let module = Arc::new(RwLock::new(Module::new("llm".to_string())));
 
-    // chat_completion function
-    let chat_completion_fn = Value::new(ValueKind::NativeFunction {
+    // chat_completion function: a prompt, or a list of `{ role, content }`
+    // messages, with `{ system: "..." }` instructions and `extract: "number"`
+    // (or "list" or "json") to post-process the response as options
+    let chat_completion_fn = Value::new(ValueKind::AsyncNativeFunction {
         name: "chat_completion".to_string(),
+        arity: 2,
+        handler: Arc::new(|interpreter, args| {
+            Box::pin(async move {
+                let Some(arg) = args.first() else {
+                    return Ok(Value::new(ValueKind::Nil));
+                };
+                let request = match &arg.kind {
+                    ValueKind::String(text) => CompletionRequest::new(text.clone()),
+                    ValueKind::List(messages) => CompletionRequest::new("").with_messages(chat_messages(messages)?),
+                    _ => return Ok(Value::new(ValueKind::Nil)),
+                };
+                let options = match args.get(1).map(|options| &options.kind) {
+                    None | Some(ValueKind::Nil) => &[][..],
+                    Some(ValueKind::Map(options)) => options.as_slice(),
+                    Some(_) => return Err(PrismError::InvalidArgument(
+                        "llm.chat_completion expects a map of options".to_string(),
+                    )),
+                };
+                let option = |name: &str| options.iter().find(|(key, _)| key.to_string() == name).map(|(_, value)| value.to_string());
+                let request = match option("system") {
+                    Some(system) => request.with_system(system),
+                    None => request,
+                };
+                let request = CompletionRequest { context: arg.context.clone(), ..request };
+                let response = complete_chat(interpreter, request).await?;
+                match option("extract") {
+                    Some(extraction) => extract::apply(&extraction, &response),
+                    None => Ok(response),
+                }
+            })
+        }),
+    });
+
+    // use_profile function: switches later LLM calls to a configured profile
+    let use_profile_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "use_profile".to_string(),
         arity: 1,
-        handler: Arc::new(|args| {
-            if let Some(arg) = args.first() {
-                match &arg.kind {
-                    ValueKind::String(text) => {
-                        // TODO: Implement actual LLM chat completion
-                        Ok(Value::new(ValueKind::String(format!("LLM response to: {}", text))))
-                    }
-                    _ => Ok(Value::new(ValueKind::Nil)),
+        handler: Arc::new(|interpreter, args| {
+            Box::pin(async move {
+                match args.first().map(|arg| &arg.kind) {
+                    Some(ValueKind::String(name)) => interpreter.use_profile(name)?,
+                    _ => return Err(PrismError::InvalidArgument("expected the name of a profile".to_string())),
                 }
-            } else {
                 Ok(Value::new(ValueKind::Nil))
+            })
+        }),
+    });
+
+    // configure function: `llm.configure({ temperature: 0.2, top_p: 0.9,
+    // stop: ["\n\n"] })` changes the model settings of later LLM calls
+    let configure_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "configure".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            Box::pin(async move {
+                let settings = model_settings(args.first())?;
+                interpreter.set_model_config(settings.apply(interpreter.model_config().clone()))?;
+                Ok(Value::new(ValueKind::Nil))
+            })
+        }),
+    });
+
+    // rule function: `llm.rule("dose of ([a-z]+)", "Check the $1 label", 0.6)`
+    // or `llm.rule(["chest", "pain"], "urgent", 0.9)` answers matching
+    // prompts while the provider is "rules"
+    let rule_fn = Value::new(ValueKind::NativeFunction {
+        name: "rule".to_string(),
+        arity: 3,
+        handler: Arc::new(|interpreter, args| {
+            let kinds: Vec<&ValueKind> = args.iter().map(|arg| &arg.kind).collect();
+            let mut rules = interpreter.rules().write();
+            match kinds.as_slice() {
+                [ValueKind::String(pattern), ValueKind::String(response), ValueKind::Number(confidence)] => {
+                    rules.add_regex(pattern, response, *confidence)?
+                }
+                [ValueKind::List(keywords), ValueKind::String(response), ValueKind::Number(confidence)] => {
+                    let keywords: Vec<String> = keywords.iter().map(Value::to_string).collect();
+                    let keywords: Vec<&str> = keywords.iter().map(String::as_str).collect();
+                    rules.add_keywords(&keywords, response, *confidence)?
+                }
+                _ => return Err(PrismError::InvalidArgument(
+                    "llm.rule expects a pattern or keywords, a response and a confidence".to_string(),
+                )),
             }
+            Ok(Value::new(ValueKind::Nil))
+        }),
+    });
+
+    // cache function: `llm.cache({ semantic: true, threshold: 0.9, capacity: 100 })`
+    // answers repeated or similar prompts from earlier responses,
+    // `llm.cache(false)` stops caching
+    let cache_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "cache".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            Box::pin(async move {
+                let cache = match args.first().map(|arg| &arg.kind) {
+                    Some(ValueKind::Boolean(false)) => {
+                        interpreter.set_llm_cache(None);
+                        return Ok(Value::new(ValueKind::Nil));
+                    }
+                    None | Some(ValueKind::Nil) | Some(ValueKind::Boolean(true)) => ResponseCache::exact(),
+                    Some(ValueKind::Map(_)) => {
+                        let options = args[0].to_json()?;
+                        let cache = match options.get("semantic").and_then(serde_json::Value::as_bool) {
+                            Some(true) => {
+                                let embedder = interpreter.embedder().cloned().ok_or_else(|| {
+                                    PrismError::InvalidArgument(
+                                        "Semantic caching needs an embedder, which the host provides".to_string(),
+                                    )
+                                })?;
+                                let threshold = options.get("threshold").and_then(serde_json::Value::as_f64);
+                                ResponseCache::semantic(threshold.unwrap_or(DEFAULT_SIMILARITY), embedder)
+                            }
+                            _ => ResponseCache::exact(),
+                        };
+                        match options.get("capacity").and_then(serde_json::Value::as_u64) {
+                            Some(capacity) => cache.with_capacity(capacity as usize),
+                            None => cache,
+                        }
+                    }
+                    Some(_) => return Err(PrismError::InvalidArgument(
+                        "llm.cache expects a map of options or a boolean".to_string(),
+                    )),
+                };
+                interpreter.set_llm_cache(Some(Arc::new(cache)));
+                Ok(Value::new(ValueKind::Nil))
+            })
+        }),
+    });
+
+    // feedback function: `llm.feedback(id, was_correct)` on the completion
+    // `llm.last_response_id()` named, to calibrate later confidences
+    let feedback_fn = Value::new(ValueKind::NativeFunction {
+        name: "feedback".to_string(),
+        arity: 2,
+        handler: Arc::new(|interpreter, args| {
+            match (args.first().map(|arg| &arg.kind), args.get(1).map(|arg| &arg.kind)) {
+                (Some(ValueKind::String(id)), Some(ValueKind::Boolean(correct))) => {
+                    interpreter.calibration().feedback(id, *correct)?;
+                    Ok(Value::new(ValueKind::Nil))
+                }
+                _ => Err(PrismError::InvalidArgument(
+                    "llm.feedback expects a response id and whether it was correct".to_string(),
+                )),
+            }
+        }),
+    });
+
+    // last_response_id function: the id of this interpreter's latest completion
+    let last_response_id_fn = Value::new(ValueKind::NativeFunction {
+        name: "last_response_id".to_string(),
+        arity: 0,
+        handler: Arc::new(|interpreter, _| {
+            Ok(match interpreter.last_response_id() {
+                Some(id) => Value::new(ValueKind::String(id.to_string())),
+                None => Value::new(ValueKind::Nil),
+            })
+        }),
+    });
+
+    // calibration function: `llm.calibration(path)` keeps the calibration
+    // tables in a store file, starting from those saved there
+    let calibration_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "calibration".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            Box::pin(async move {
+                interpreter.capabilities().require(Capability::Fs, "llm.calibration")?;
+                let Some(ValueKind::String(path)) = args.first().map(|arg| &arg.kind) else {
+                    return Err(PrismError::InvalidArgument("llm.calibration expects a path".to_string()));
+                };
+                let calibration = Calibration::with_store(Store::open(path)?)?;
+                interpreter.set_calibration(Arc::new(calibration));
+                Ok(Value::new(ValueKind::Nil))
+            })
+        }),
+    });
+
+    // bind_context function: model settings for LLM calls inside a context,
+    // e.g. `llm.bind_context("final diagnosis", { model: "gpt-4o" })`
+    let bind_context_fn = Value::new(ValueKind::NativeFunction {
+        name: "bind_context".to_string(),
+        arity: 2,
+        handler: Arc::new(|interpreter, args| {
+            let Some(ValueKind::String(name)) = args.first().map(|arg| &arg.kind) else {
+                return Err(PrismError::InvalidArgument(
+                    "expected a context name and a map of model settings".to_string(),
+                ));
+            };
+            let settings = model_settings(args.get(1))?;
+            settings.apply(interpreter.model_config().clone()).validate()?;
+            interpreter.bind_context(name, settings);
+            Ok(Value::new(ValueKind::Nil))
         }),
     });
 
@@ -30,7 +407,7 @@ pub fn init_llm_module() -> Result<Arc<RwLock<Module>>> {
     let embedding_fn = Value::new(ValueKind::NativeFunction {
         name: "embedding".to_string(),
         arity: 1,
-        handler: Arc::new(|args| {
+        handler: Arc::new(|_, args| {
             if let Some(arg) = args.first() {
                 match &arg.kind {
                     ValueKind::String(_text) => {
@@ -52,6 +429,18 @@ pub fn init_llm_module() -> Result<Arc<RwLock<Module>>> {
     {
         let mut module_guard = module.write();
         module_guard.export("chat_completion".to_string(), chat_completion_fn)?;
+        module_guard.export("complete_batch".to_string(), batch::complete_batch_fn())?;
+        module_guard.export("complete_checked".to_string(), checked::complete_checked_fn())?;
+        module_guard.export("conversation".to_string(), conversation::conversation_fn())?;
+        module_guard.export("escalate".to_string(), escalate::escalate_fn())?;
+        module_guard.export("cache".to_string(), cache_fn)?;
+        module_guard.export("configure".to_string(), configure_fn)?;
+        module_guard.export("rule".to_string(), rule_fn)?;
+        module_guard.export("use_profile".to_string(), use_profile_fn)?;
+        module_guard.export("feedback".to_string(), feedback_fn)?;
+        module_guard.export("last_response_id".to_string(), last_response_id_fn)?;
+        module_guard.export("calibration".to_string(), calibration_fn)?;
+        module_guard.export("bind_context".to_string(), bind_context_fn)?;
         module_guard.export("embedding".to_string(), embedding_fn)?;
     }
 
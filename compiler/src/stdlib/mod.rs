@@ -5,18 +5,97 @@ use crate::value::{Value, ValueKind};
 use crate::module::Module;
 
 pub mod core;
+#[cfg(feature = "sqlite")]
+pub mod db;
+#[cfg(feature = "decimal")]
+pub mod decimal;
+pub mod diff;
+pub mod env;
+pub mod eval;
+pub mod events;
+pub mod experiment;
+pub mod extract;
+pub mod format;
+pub mod fuzzy;
+pub mod guard;
+#[cfg(feature = "html")]
+pub mod html;
+pub mod io;
 pub mod llm;
 pub mod medical;
+pub mod pattern;
+pub mod progress;
+pub mod prompts;
+pub mod report;
+pub mod schema;
+pub mod store;
+pub mod tasks;
+#[cfg(feature = "tensor")]
+pub mod tensor;
+pub mod text;
+pub mod time;
+pub mod toml;
 pub mod utils;
+#[cfg(feature = "websocket")]
+pub mod ws;
+pub mod yaml;
+
+/// `core` functions every program can call without the `core.` prefix.
+pub const PRELUDE: &[&str] = &[
+    "print", "println", "printf", "type", "assert", "len", "conf_of", "range", "iter", "str", "num", "bool", "snapshot",
+    "query", "get_in", "set_in", "meta_get", "meta_set", "now", "doc", "signature", "members", "globals",
+];
+
+/// The [`PRELUDE`] functions as globals.
+pub fn init_prelude() -> Result<Vec<(&'static str, Value)>> {
+    prelude_from(&core::init_core_module()?)
+}
+
+/// The [`PRELUDE`] functions taken from the `core` module among `modules`,
+/// so they are the same functions as `core.print` and share its docs.
+pub fn prelude_of(modules: &[(&'static str, Value)]) -> Result<Vec<(&'static str, Value)>> {
+    match modules.iter().find(|(name, _)| *name == "core").map(|(_, value)| &value.kind) {
+        Some(ValueKind::Module(core_module)) => prelude_from(core_module),
+        _ => init_prelude(),
+    }
+}
+
+fn prelude_from(core_module: &Arc<RwLock<Module>>) -> Result<Vec<(&'static str, Value)>> {
+    let core_module = core_module.read();
+    PRELUDE
+        .iter()
+        .map(|&name| Ok((name, core_module.get_export(name)?)))
+        .collect()
+}
 
 pub fn init_stdlib() -> Result<Vec<(&'static str, Value)>> {
     let mut modules = Vec::new();
     
     // Initialize each module and convert to Value
     let core_module = core::init_core_module()?;
+    let diff_module = diff::init_diff_module()?;
+    let env_module = env::init_env_module()?;
+    let eval_module = eval::init_eval_module()?;
+    let events_module = events::init_events_module()?;
+    let experiment_module = experiment::init_experiment_module()?;
+    let extract_module = extract::init_extract_module()?;
+    let format_module = format::init_format_module()?;
+    let fuzzy_module = fuzzy::init_fuzzy_module()?;
+    let guard_module = guard::init_guard_module()?;
+    let io_module = io::init_io_module()?;
     let llm_module = llm::init_llm_module()?;
     let medical_module = medical::init_medical_module()?;
+    let pattern_module = pattern::init_pattern_module()?;
+    let progress_module = progress::init_progress_module()?;
+    let prompts_module = prompts::init_prompts_module()?;
+    let report_module = report::init_report_module()?;
+    let schema_module = schema::init_schema_module()?;
+    let store_module = store::init_store_module()?;
+    let tasks_module = tasks::init_tasks_module()?;
+    let text_module = text::init_text_module()?;
+    let toml_module = toml::init_toml_module()?;
     let utils_module = utils::init_utils_module()?;
+    let yaml_module = yaml::init_yaml_module()?;
 
     // Convert each module to a Value with the correct RwLock type
     let convert_module = |m: Arc<RwLock<Module>>| -> Value {
@@ -24,9 +103,40 @@ pub fn init_stdlib() -> Result<Vec<(&'static str, Value)>> {
     };
 
     modules.push(("core", convert_module(core_module)));
+    #[cfg(feature = "decimal")]
+    modules.push(("decimal", convert_module(decimal::init_decimal_module()?)));
+    modules.push(("diff", convert_module(diff_module)));
+    modules.push(("env", convert_module(env_module)));
+    modules.push(("eval", convert_module(eval_module)));
+    modules.push(("events", convert_module(events_module)));
+    modules.push(("experiment", convert_module(experiment_module)));
+    modules.push(("extract", convert_module(extract_module)));
+    modules.push(("format", convert_module(format_module)));
+    modules.push(("fuzzy", convert_module(fuzzy_module)));
+    modules.push(("guard", convert_module(guard_module)));
+    #[cfg(feature = "html")]
+    modules.push(("html", convert_module(html::init_html_module()?)));
+    modules.push(("io", convert_module(io_module)));
     modules.push(("llm", convert_module(llm_module)));
     modules.push(("medical", convert_module(medical_module)));
+    modules.push(("pattern", convert_module(pattern_module)));
+    modules.push(("progress", convert_module(progress_module)));
+    modules.push(("prompts", convert_module(prompts_module)));
+    modules.push(("report", convert_module(report_module)));
+    #[cfg(feature = "sqlite")]
+    modules.push(("db", convert_module(db::init_db_module()?)));
+    modules.push(("schema", convert_module(schema_module)));
+    modules.push(("store", convert_module(store_module)));
+    modules.push(("async", convert_module(tasks_module)));
+    #[cfg(feature = "tensor")]
+    modules.push(("tensor", convert_module(tensor::init_tensor_module()?)));
+    modules.push(("text", convert_module(text_module)));
+    modules.push(("time", convert_module(time::init_time_module()?)));
+    modules.push(("toml", convert_module(toml_module)));
     modules.push(("utils", convert_module(utils_module)));
+    modules.push(("yaml", convert_module(yaml_module)));
+    #[cfg(feature = "websocket")]
+    modules.push(("ws", convert_module(ws::init_ws_module()?)));
     
     Ok(modules)
 }
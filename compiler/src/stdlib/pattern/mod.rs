@@ -0,0 +1,196 @@
+//! The `pattern` module: matching values against patterns.
+//!
+//! A string pattern is a regex when written between slashes (`"/fe+ver/i"`,
+//! unanchored, `i` for case-insensitive), a glob when it contains `*` or `?`
+//! (matching the whole text) and an exact string otherwise. A map pattern
+//! matches a map that has each of its keys with a matching value, extra keys
+//! allowed; a list pattern matches a list of the same length item by item.
+//! Anything else must be equal.
+//!
+//! `pattern.match` returns `true` when the value matches. Otherwise it
+//! returns `false` with a confidence lowered by how similar the value was, so
+//! a near miss such as a typo is an unconfident `false`.
+
+use std::sync::Arc;
+use parking_lot::RwLock;
+use regex::{Regex, RegexBuilder};
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::stdlib::diff::similarity;
+use crate::value::{Value, ValueKind};
+
+pub fn init_pattern_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("pattern".to_string())));
+
+    // match function: match(value, pattern)
+    let match_fn = Value::new(ValueKind::NativeFunction {
+        name: "match".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let (value, pattern) = match args.as_slice() {
+                [value, pattern, ..] => (value, pattern),
+                _ => return Err(PrismError::InvalidArgument("pattern.match expects a value and a pattern".to_string())),
+            };
+            let closeness = score(value, pattern)?;
+            let matched = closeness == 1.0;
+            let confidence = if matched { 1.0 } else { 1.0 - closeness };
+            Ok(Value::with_confidence(ValueKind::Boolean(matched), confidence))
+        }),
+    });
+
+    // captures function: captures(text, regex) lists the whole match and its
+    // groups, or is nil when the regex does not match
+    let captures_fn = Value::new(ValueKind::NativeFunction {
+        name: "captures".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let (text, pattern) = match (args.first().map(|arg| &arg.kind), args.get(1).map(|arg| &arg.kind)) {
+                (Some(ValueKind::String(text)), Some(ValueKind::String(pattern))) => (text, pattern),
+                _ => return Err(PrismError::InvalidArgument("pattern.captures expects a text and a regex".to_string())),
+            };
+            let regex = match regex(pattern) {
+                Some(regex) => regex?,
+                None => Regex::new(pattern).map_err(invalid_regex)?,
+            };
+            Ok(match regex.captures(text) {
+                Some(captures) => Value::new(ValueKind::List(
+                    captures
+                        .iter()
+                        .map(|group| match group {
+                            Some(group) => Value::new(ValueKind::String(group.as_str().to_string())),
+                            None => Value::new(ValueKind::Nil),
+                        })
+                        .collect(),
+                )),
+                None => Value::new(ValueKind::Nil),
+            })
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("match".to_string(), match_fn)?;
+        module.export("captures".to_string(), captures_fn)?;
+    }
+
+    Ok(module)
+}
+
+/// How closely `value` matches `pattern`: 1.0 for a match, otherwise the
+/// similarity of the parts that could be compared.
+fn score(value: &Value, pattern: &Value) -> Result<f64> {
+    match (&value.kind, &pattern.kind) {
+        (_, ValueKind::String(pattern)) if regex(pattern).is_some() => {
+            let regex = regex(pattern).expect("checked above")?;
+            let matched = matches!(&value.kind, ValueKind::String(text) if regex.is_match(text));
+            Ok(if matched { 1.0 } else { 0.0 })
+        }
+        (ValueKind::String(text), ValueKind::String(pattern)) if pattern.contains(['*', '?']) => {
+            if glob(pattern)?.is_match(text) {
+                Ok(1.0)
+            } else {
+                Ok(similarity(text, &pattern.replace(['*', '?'], "")).min(NEAR_MISS))
+            }
+        }
+        (ValueKind::String(text), ValueKind::String(pattern)) => {
+            Ok(if text == pattern { 1.0 } else { similarity(text, pattern).min(NEAR_MISS) })
+        }
+        (ValueKind::Map(entries), ValueKind::Map(fields)) => {
+            let scores = fields
+                .iter()
+                .map(|(key, field)| match entries.iter().find(|(name, _)| name == key) {
+                    Some((_, entry)) => score(entry, field),
+                    None => Ok(0.0),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(mean(&scores))
+        }
+        (ValueKind::List(items), ValueKind::List(patterns)) if items.len() == patterns.len() => {
+            let scores = items
+                .iter()
+                .zip(patterns)
+                .map(|(item, pattern)| score(item, pattern))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(mean(&scores))
+        }
+        _ => Ok(if value.kind == pattern.kind { 1.0 } else { 0.0 }),
+    }
+}
+
+/// Highest score of a value that does not match, so only matches score 1.0.
+const NEAR_MISS: f64 = 0.99;
+
+fn mean(scores: &[f64]) -> f64 {
+    if scores.is_empty() {
+        1.0
+    } else {
+        let mean = scores.iter().sum::<f64>() / scores.len() as f64;
+        if scores.iter().all(|&score| score == 1.0) { 1.0 } else { mean.min(NEAR_MISS) }
+    }
+}
+
+/// The regex in a `/.../` or `/.../i` pattern, `None` for other strings.
+fn regex(pattern: &str) -> Option<Result<Regex>> {
+    let body = pattern.strip_prefix('/')?;
+    let (body, insensitive) = match body.strip_suffix("/i") {
+        Some(body) => (body, true),
+        None => (body.strip_suffix('/')?, false),
+    };
+    Some(RegexBuilder::new(body).case_insensitive(insensitive).build().map_err(invalid_regex))
+}
+
+fn glob(pattern: &str) -> Result<Regex> {
+    let mut source = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => source.push_str(".*"),
+            '?' => source.push('.'),
+            c => source.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    source.push('$');
+    RegexBuilder::new(&source).dot_matches_new_line(true).build().map_err(invalid_regex)
+}
+
+fn invalid_regex(err: regex::Error) -> PrismError {
+    PrismError::InvalidArgument(format!("invalid pattern: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(text: &str) -> Value {
+        Value::new(ValueKind::String(text.to_string()))
+    }
+
+    #[test]
+    fn test_string_patterns() -> Result<()> {
+        assert_eq!(score(&string("high fever"), &string("*fever"))?, 1.0);
+        assert_eq!(score(&string("fever"), &string("fe?er"))?, 1.0);
+        assert_eq!(score(&string("Fever 39C"), &string("/^fever \\d+/i"))?, 1.0);
+        assert_eq!(score(&string("cough"), &string("/^fever/"))?, 0.0);
+        let typo = score(&string("fevr"), &string("fever"))?;
+        assert!(typo > 0.8 && typo < 1.0);
+        assert!(regex("/(/").is_some_and(|regex| regex.is_err()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_structural_patterns() -> Result<()> {
+        let value = Value::new(ValueKind::Map(vec![
+            (string("symptom"), string("fever")),
+            (string("tags"), Value::new(ValueKind::List(vec![string("acute"), string("viral")]))),
+        ]));
+        let pattern = |symptom: &str| {
+            Value::new(ValueKind::Map(vec![
+                (string("symptom"), string(symptom)),
+                (string("tags"), Value::new(ValueKind::List(vec![string("a*"), string("*")]))),
+            ]))
+        };
+        assert_eq!(score(&value, &pattern("fev*"))?, 1.0);
+        assert_eq!(score(&value, &pattern("cough"))?, 0.5);
+        assert_eq!(score(&string("fever"), &pattern("fever"))?, 0.0);
+        Ok(())
+    }
+}
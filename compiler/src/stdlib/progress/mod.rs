@@ -0,0 +1,98 @@
+//! The `progress` module: feedback for long-running scripts.
+//!
+//! `progress.start(total)` begins a bar, `progress.tick(message)` advances
+//! it by one and `progress.finish()` completes it. Each step is drawn by the
+//! interpreter's [`ProgressSink`](crate::progress::ProgressSink), if any, and
+//! sent to host listeners as a `progress` event with `{current, total,
+//! message, done}`; with neither, the calls do nothing.
+
+use std::sync::Arc;
+use parking_lot::RwLock;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::module::Module;
+use crate::progress::Progress;
+use crate::value::{Value, ValueKind};
+
+pub fn init_progress_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("progress".to_string())));
+
+    // start function: start(total)
+    let start_fn = Value::new(ValueKind::NativeFunction {
+        name: "start".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            let total = match args.first().map(|arg| &arg.kind) {
+                Some(ValueKind::Number(total)) if *total >= 0.0 && total.fract() == 0.0 => *total as u64,
+                _ => return Err(PrismError::InvalidArgument(
+                    "progress.start expects a whole number of steps".to_string(),
+                )),
+            };
+            let progress = interpreter.progress().start(total);
+            Ok(notify(interpreter, &progress))
+        }),
+    });
+
+    // tick function: tick(message?)
+    let tick_fn = Value::new(ValueKind::NativeFunction {
+        name: "tick".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            let message = match args.first().map(|arg| &arg.kind) {
+                None | Some(ValueKind::Nil) => None,
+                Some(ValueKind::String(message)) => Some(message.clone()),
+                Some(_) => Some(args[0].to_string()),
+            };
+            let progress = interpreter.progress().tick(message).ok_or_else(not_started)?;
+            Ok(notify(interpreter, &progress))
+        }),
+    });
+
+    // finish function: finish()
+    let finish_fn = Value::new(ValueKind::NativeFunction {
+        name: "finish".to_string(),
+        arity: 0,
+        handler: Arc::new(|interpreter, _| {
+            let progress = interpreter.progress().finish().ok_or_else(not_started)?;
+            Ok(notify(interpreter, &progress))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("start".to_string(), start_fn)?;
+        module.export("tick".to_string(), tick_fn)?;
+        module.export("finish".to_string(), finish_fn)?;
+    }
+
+    Ok(module)
+}
+
+/// Sends the step to host listeners and returns its payload.
+fn notify(interpreter: &Interpreter, progress: &Progress) -> Value {
+    let message = match &progress.message {
+        Some(message) => ValueKind::String(message.clone()),
+        None => ValueKind::Nil,
+    };
+    let payload = map(vec![
+        ("current", Value::new(ValueKind::Number(progress.current as f64))),
+        ("total", Value::new(ValueKind::Number(progress.total as f64))),
+        ("message", Value::new(message)),
+        ("done", Value::new(ValueKind::Boolean(progress.finished))),
+    ]);
+    interpreter.events().notify_listeners("progress", &payload);
+    payload
+}
+
+fn not_started() -> PrismError {
+    PrismError::RuntimeError("no progress bar is running; call progress.start first".to_string())
+}
+
+fn map(entries: Vec<(&str, Value)>) -> Value {
+    Value::new(ValueKind::Map(
+        entries
+            .into_iter()
+            .map(|(key, value)| (Value::new(ValueKind::String(key.to_string())), value))
+            .collect(),
+    ))
+}
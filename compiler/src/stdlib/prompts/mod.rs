@@ -0,0 +1,197 @@
+//! `prompts.load(dir)`: a versioned library of prompt templates.
+//!
+//! Every file in the directory is a template named after the file stem. An
+//! optional YAML front matter between `---` lines sets `model`,
+//! `temperature` and `version`; the rest is the prompt, where `{name}` is
+//! replaced by the `name` entry of the map the template is called with and
+//! `{{`/`}}` are literal braces. Each call records the template's name and
+//! version in the audit log, so a run shows which prompt revisions it used.
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use parking_lot::RwLock;
+use serde::Deserialize;
+use crate::audit::AuditEvent;
+use crate::capabilities::Capability;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{Value, ValueKind};
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FrontMatter {
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    pub version: Option<serde_yaml::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub name: String,
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    pub version: Option<String>,
+    pub text: String,
+}
+
+impl Template {
+    pub fn parse(name: &str, source: &str) -> Result<Self> {
+        let (front_matter, text) = match source.strip_prefix("---\n") {
+            Some(rest) => {
+                let end = rest
+                    .find("\n---\n")
+                    .ok_or_else(|| PrismError::ParseError(format!("prompt {}: unclosed front matter", name)))?;
+                let front_matter: FrontMatter = serde_yaml::from_str(&rest[..end])
+                    .map_err(|err| PrismError::ParseError(format!("prompt {}: {}", name, err)))?;
+                (front_matter, &rest[end + "\n---\n".len()..])
+            }
+            None => (FrontMatter::default(), source),
+        };
+        let version = front_matter.version.map(|version| match version {
+            serde_yaml::Value::String(version) => version,
+            version => serde_yaml::to_string(&version).unwrap_or_default().trim().to_string(),
+        });
+        Ok(Self {
+            name: name.to_string(),
+            model: front_matter.model,
+            temperature: front_matter.temperature,
+            version,
+            text: text.to_string(),
+        })
+    }
+
+    /// The prompt with each `{name}` replaced by `vars[name]`.
+    pub fn render(&self, vars: &[(Value, Value)]) -> Result<String> {
+        let invalid = |message: String| PrismError::InvalidArgument(format!("prompt {}: {}", self.name, message));
+        let mut output = String::with_capacity(self.text.len());
+        let mut rest = self.text.as_str();
+        while let Some(index) = rest.find(['{', '}']) {
+            output.push_str(&rest[..index]);
+            let brace = &rest[index..index + 1];
+            rest = &rest[index + 1..];
+            if let Some(after) = rest.strip_prefix(brace) {
+                output.push_str(brace);
+                rest = after;
+                continue;
+            }
+            if brace == "}" {
+                return Err(invalid("unmatched '}'".to_string()));
+            }
+            let end = rest.find('}').ok_or_else(|| invalid("unclosed placeholder".to_string()))?;
+            let name = rest[..end].trim();
+            let value = vars
+                .iter()
+                .find(|(key, _)| key.to_string() == name)
+                .map(|(_, value)| value)
+                .ok_or_else(|| invalid(format!("missing variable '{}'", name)))?;
+            output.push_str(&value.to_string());
+            rest = &rest[end + 1..];
+        }
+        output.push_str(rest);
+        Ok(output)
+    }
+}
+
+pub fn init_prompts_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("prompts".to_string())));
+
+    // load function: a map from template name to template
+    let load_fn = Value::new(ValueKind::NativeFunction {
+        name: "load".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            interpreter.capabilities().require(Capability::Fs, "prompts.load")?;
+            let dir = match args.first().map(|arg| &arg.kind) {
+                Some(ValueKind::String(dir)) => dir,
+                _ => return Err(PrismError::InvalidArgument("prompts.load expects a directory".to_string())),
+            };
+            let templates = load(Path::new(dir))?
+                .into_iter()
+                .map(|template| (Value::new(ValueKind::String(template.name.clone())), callable(template)))
+                .collect();
+            Ok(Value::new(ValueKind::Map(templates)))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("load".to_string(), load_fn)?;
+    }
+
+    Ok(module)
+}
+
+/// The templates in `dir`, sorted by name; hidden files and subdirectories
+/// are skipped.
+pub fn load(dir: &Path) -> Result<Vec<Template>> {
+    let mut templates = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if !path.is_file() || name.starts_with('.') {
+            continue;
+        }
+        templates.push(Template::parse(name, &fs::read_to_string(&path)?)?);
+    }
+    templates.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(templates)
+}
+
+fn callable(template: Template) -> Value {
+    let template = Arc::new(template);
+    Value::new(ValueKind::AsyncNativeFunction {
+        name: template.name.clone(),
+        arity: 1,
+        handler: Arc::new(move |interpreter, args| {
+            let template = Arc::clone(&template);
+            Box::pin(async move {
+                let prompt = match args.first().map(|arg| &arg.kind) {
+                    None | Some(ValueKind::Nil) => template.render(&[])?,
+                    Some(ValueKind::Map(vars)) => template.render(vars)?,
+                    Some(_) => {
+                        return Err(PrismError::InvalidArgument(format!(
+                            "prompt {} expects a map of variables",
+                            template.name
+                        )))
+                    }
+                };
+                interpreter.record(AuditEvent::Prompt {
+                    name: template.name.clone(),
+                    version: template.version.clone(),
+                    model: template.model.clone(),
+                    temperature: template.temperature,
+                });
+                Ok(Value::new(ValueKind::String(prompt)))
+            })
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_render() -> Result<()> {
+        let template = Template::parse(
+            "triage",
+            "---\nmodel: gpt-4\ntemperature: 0.2\nversion: 3\n---\nTriage {symptoms} for {{json}} output.",
+        )?;
+        assert_eq!(template.model.as_deref(), Some("gpt-4"));
+        assert_eq!(template.temperature, Some(0.2));
+        assert_eq!(template.version.as_deref(), Some("3"));
+
+        let vars = [(Value::new(ValueKind::String("symptoms".to_string())), Value::new(ValueKind::String("fever".to_string())))];
+        assert_eq!(template.render(&vars)?, "Triage fever for {json} output.");
+        assert!(template.render(&[]).is_err());
+
+        let plain = Template::parse("plain", "No front matter")?;
+        assert_eq!(plain.version, None);
+        assert_eq!(plain.render(&[])?, "No front matter");
+        assert!(Template::parse("bad", "---\nseed: 1\n---\nx").is_err());
+        Ok(())
+    }
+}
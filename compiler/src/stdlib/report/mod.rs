@@ -0,0 +1,275 @@
+//! Renders script results for people: aligned tables for lists of maps and
+//! markdown for embedding results in documents.
+
+use std::sync::Arc;
+use parking_lot::RwLock;
+use crate::ast::{DEFAULT_HIGH_CONFIDENCE, DEFAULT_MEDIUM_CONFIDENCE};
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{Value, ValueKind};
+
+const CONFIDENCE_HEADER: &str = "confidence";
+
+pub fn init_report_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("report".to_string())));
+
+    // table function: table(rows, { format: "ascii" | "markdown", confidence, color })
+    let table_fn = Value::new(ValueKind::NativeFunction {
+        name: "table".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let Some(Value { kind: ValueKind::List(rows), .. }) = args.first() else {
+                return Err(PrismError::InvalidArgument("report.table expects a list of maps".to_string()));
+            };
+            let options = TableOptions::from_value(args.get(1), rows)?;
+            Ok(Value::new(ValueKind::String(table(rows, &options)?)))
+        }),
+    });
+
+    // markdown function
+    let markdown_fn = Value::new(ValueKind::NativeFunction {
+        name: "markdown".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| match args.first() {
+            Some(value) => Ok(Value::new(ValueKind::String(markdown(value)?))),
+            None => Err(PrismError::InvalidArgument("report.markdown expects a value".to_string())),
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("table".to_string(), table_fn)?;
+        module.export("markdown".to_string(), markdown_fn)?;
+    }
+
+    Ok(module)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    Markdown,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TableOptions {
+    format: Format,
+    /// Adds a column with each row's confidence.
+    confidence: bool,
+    /// Colors the confidence column by band with ANSI escapes.
+    color: bool,
+}
+
+impl TableOptions {
+    /// Reads the options map. The confidence column is shown by default when
+    /// any row is uncertain.
+    fn from_value(options: Option<&Value>, rows: &[Value]) -> Result<Self> {
+        let mut table_options = TableOptions {
+            format: Format::Ascii,
+            confidence: rows.iter().any(|row| row.confidence < 1.0),
+            color: false,
+        };
+        let entries = match options.map(|options| &options.kind) {
+            None | Some(ValueKind::Nil) => return Ok(table_options),
+            Some(ValueKind::Map(entries)) => entries,
+            Some(_) => return Err(PrismError::InvalidArgument("report.table options must be a map".to_string())),
+        };
+        for (key, value) in entries {
+            match (key.to_string().as_str(), &value.kind) {
+                ("format", ValueKind::String(format)) if format == "ascii" => table_options.format = Format::Ascii,
+                ("format", ValueKind::String(format)) if format == "markdown" => table_options.format = Format::Markdown,
+                ("confidence", ValueKind::Boolean(show)) => table_options.confidence = *show,
+                ("color", ValueKind::Boolean(color)) => table_options.color = *color,
+                (option, _) => {
+                    return Err(PrismError::InvalidArgument(format!(
+                        "invalid report.table option {}: {}",
+                        option, value
+                    )))
+                }
+            }
+        }
+        Ok(table_options)
+    }
+}
+
+fn table(rows: &[Value], options: &TableOptions) -> Result<String> {
+    // Columns in the order their keys first appear
+    let mut headers: Vec<String> = Vec::new();
+    for row in rows {
+        let ValueKind::Map(entries) = &row.kind else {
+            return Err(PrismError::InvalidArgument(format!("report.table rows must be maps, got {}", row)));
+        };
+        for (key, _) in entries {
+            let key = key.to_string();
+            if !headers.contains(&key) {
+                headers.push(key);
+            }
+        }
+    }
+
+    let mut cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            headers
+                .iter()
+                .map(|header| field(row, header).map(ToString::to_string).unwrap_or_default())
+                .collect()
+        })
+        .collect();
+    if options.confidence {
+        headers.push(CONFIDENCE_HEADER.to_string());
+        for (row, cells) in rows.iter().zip(&mut cells) {
+            cells.push(format!("{:.2}", row.confidence));
+        }
+    }
+
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(column, header)| {
+            cells
+                .iter()
+                .map(|row| row[column].chars().count())
+                .chain(std::iter::once(header.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let mut lines = Vec::with_capacity(rows.len() + 4);
+    let separator = |corner: &str| {
+        let dashes: Vec<String> = widths.iter().map(|width| "-".repeat(width + 2)).collect();
+        format!("{}{}{}", corner, dashes.join(corner), corner)
+    };
+    if options.format == Format::Ascii {
+        lines.push(separator("+"));
+    }
+    lines.push(render_row(&headers, &widths, None));
+    lines.push(separator(if options.format == Format::Ascii { "+" } else { "|" }));
+    for (row, cells) in rows.iter().zip(&cells) {
+        let color = (options.confidence && options.color).then(|| band_color(row.confidence));
+        lines.push(render_row(cells, &widths, color));
+    }
+    if options.format == Format::Ascii {
+        lines.push(separator("+"));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// One `| a | b |` line; `color` applies to the last (confidence) cell.
+fn render_row(cells: &[String], widths: &[usize], color: Option<&str>) -> String {
+    let last = cells.len().saturating_sub(1);
+    let cells: Vec<String> = cells
+        .iter()
+        .zip(widths)
+        .enumerate()
+        .map(|(column, (cell, width))| {
+            // Pad before coloring so escape codes do not count towards the width
+            let padded = format!("{:<width$}", cell, width = width);
+            match color {
+                Some(color) if column == last => format!("{}{}\x1b[0m", color, padded),
+                _ => padded,
+            }
+        })
+        .collect();
+    format!("| {} |", cells.join(" | "))
+}
+
+/// ANSI color for a confidence, following the `uncertain if` bands.
+fn band_color(confidence: f64) -> &'static str {
+    if confidence >= DEFAULT_HIGH_CONFIDENCE {
+        "\x1b[32m"
+    } else if confidence >= DEFAULT_MEDIUM_CONFIDENCE {
+        "\x1b[33m"
+    } else {
+        "\x1b[31m"
+    }
+}
+
+fn field<'v>(row: &'v Value, name: &str) -> Option<&'v Value> {
+    match &row.kind {
+        ValueKind::Map(entries) => entries
+            .iter()
+            .find(|(key, _)| key.to_string() == name)
+            .map(|(_, value)| value),
+        _ => None,
+    }
+}
+
+/// Lists of maps become tables, other lists and maps bullet lists, and
+/// uncertain values are annotated with their confidence.
+fn markdown(value: &Value) -> Result<String> {
+    match &value.kind {
+        ValueKind::List(rows)
+            if !rows.is_empty() && rows.iter().all(|row| matches!(row.kind, ValueKind::Map(_))) =>
+        {
+            let options = TableOptions::from_value(None, rows)?;
+            table(rows, &TableOptions { format: Format::Markdown, ..options })
+        }
+        ValueKind::List(items) => Ok(items
+            .iter()
+            .map(|item| format!("- {}", inline(item)))
+            .collect::<Vec<_>>()
+            .join("\n")),
+        ValueKind::Map(entries) => Ok(entries
+            .iter()
+            .map(|(key, value)| format!("- **{}**: {}", key, inline(value)))
+            .collect::<Vec<_>>()
+            .join("\n")),
+        _ => Ok(inline(value)),
+    }
+}
+
+fn inline(value: &Value) -> String {
+    if value.confidence < 1.0 {
+        format!("{} _(confidence {:.2})_", value, value.confidence)
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(name: &str, score: f64, confidence: f64) -> Value {
+        Value::with_confidence(
+            ValueKind::Map(vec![
+                (Value::new(ValueKind::String("diagnosis".to_string())), Value::new(ValueKind::String(name.to_string()))),
+                (Value::new(ValueKind::String("score".to_string())), Value::new(ValueKind::Number(score))),
+            ]),
+            confidence,
+        )
+    }
+
+    #[test]
+    fn test_ascii_table_with_confidence_column() {
+        let rows = [row("flu", 0.8, 0.9), row("strep", 0.35, 0.4)];
+        let options = TableOptions::from_value(None, &rows).unwrap();
+        let expected = "\
++-----------+-------+------------+
+| diagnosis | score | confidence |
++-----------+-------+------------+
+| flu       | 0.8   | 0.90       |
+| strep     | 0.35  | 0.40       |
++-----------+-------+------------+";
+        assert_eq!(table(&rows, &options).unwrap(), expected);
+
+        let colored = table(&rows, &TableOptions { color: true, ..options }).unwrap();
+        assert!(colored.contains("\x1b[32m0.90      \x1b[0m"));
+        assert!(colored.contains("\x1b[31m0.40      \x1b[0m"));
+    }
+
+    #[test]
+    fn test_markdown() {
+        let rows = Value::new(ValueKind::List(vec![row("flu", 0.8, 1.0)]));
+        assert_eq!(
+            markdown(&rows).unwrap(),
+            "| diagnosis | score |\n|-----------|-------|\n| flu       | 0.8   |"
+        );
+
+        let uncertain = Value::with_confidence(ValueKind::String("viral".to_string()), 0.75);
+        let list = Value::new(ValueKind::List(vec![uncertain]));
+        assert_eq!(markdown(&list).unwrap(), "- viral _(confidence 0.75)_");
+    }
+}
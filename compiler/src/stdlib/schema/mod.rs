@@ -0,0 +1,311 @@
+//! The `schema` module: data contracts written in Prism.
+//!
+//! Schemas are a subset of JSON Schema (`type`, `properties`, `required`,
+//! `additionalProperties`, `items`, `enum`, `minimum`, `maximum`,
+//! `minLength`, `maxLength`, `minItems`, `maxItems`, `description`) with a
+//! shorthand: a type name stands for `{type: name}`, a one-item list for an
+//! array of that item, and a map without `type` for an object whose
+//! properties are all required.
+//!
+//! Validation coerces values that are close enough, such as `"42"` for a
+//! number, and lowers the confidence of the coerced part and of the whole
+//! value by [`COERCION_PENALTY`] per coercion; an invalid value comes back
+//! with confidence 0.0. [`validate`] is public so
+//! host code checking structured LLM output can share it.
+
+use std::sync::Arc;
+use parking_lot::RwLock;
+use serde_json::{json, Map, Value as Json};
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{Value, ValueKind};
+
+/// Factor applied to a value's confidence for each coercion it needed.
+pub const COERCION_PENALTY: f64 = 0.9;
+
+const TYPES: &[&str] = &["any", "array", "boolean", "integer", "null", "number", "object", "string"];
+
+const KEYWORDS: &[&str] = &[
+    "type", "properties", "required", "additionalProperties", "items", "enum", "minimum", "maximum",
+    "minLength", "maxLength", "minItems", "maxItems", "description",
+];
+
+/// The outcome of [`validate`].
+#[derive(Debug, Clone)]
+pub struct Validation {
+    /// One message per violation, prefixed with the path (`$.items[0].name`).
+    pub errors: Vec<String>,
+    /// The value after coercion.
+    pub value: Value,
+}
+
+impl Validation {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+pub fn init_schema_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("schema".to_string())));
+
+    // define function: checks a schema and expands the shorthand
+    let define_fn = Value::new(ValueKind::NativeFunction {
+        name: "define".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let spec = args.first().ok_or_else(|| PrismError::InvalidArgument("schema.define expects a schema".to_string()))?;
+            Ok(Value::from_json(&define(&spec.to_json()?)?))
+        }),
+    });
+
+    // validate function: validate(value, schema) is {valid, errors, value}
+    let validate_fn = Value::new(ValueKind::NativeFunction {
+        name: "validate".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let (value, spec) = match args.as_slice() {
+                [value, spec, ..] => (value, spec),
+                _ => return Err(PrismError::InvalidArgument("schema.validate expects a value and a schema".to_string())),
+            };
+            let validation = validate(value, &define(&spec.to_json()?)?);
+            let string = |text: &str| Value::new(ValueKind::String(text.to_string()));
+            Ok(Value::new(ValueKind::Map(vec![
+                (string("valid"), Value::new(ValueKind::Boolean(validation.is_valid()))),
+                (string("errors"), Value::new(ValueKind::List(validation.errors.iter().map(|err| string(err)).collect()))),
+                (string("value"), validation.value),
+            ])))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("define".to_string(), define_fn)?;
+        module.export("validate".to_string(), validate_fn)?;
+    }
+
+    Ok(module)
+}
+
+/// Expands the shorthand and rejects unknown keywords and types.
+pub fn define(spec: &Json) -> Result<Json> {
+    let invalid = |message: String| PrismError::InvalidArgument(format!("schema.define: {}", message));
+    match spec {
+        Json::String(name) if TYPES.contains(&name.as_str()) => Ok(json!({ "type": name })),
+        Json::String(name) => Err(invalid(format!("unknown type '{}'", name))),
+        Json::Array(items) if items.len() == 1 => Ok(json!({ "type": "array", "items": define(&items[0])? })),
+        Json::Object(fields) if !fields.contains_key("type") => {
+            let properties = fields
+                .iter()
+                .map(|(name, spec)| Ok((name.clone(), define(spec)?)))
+                .collect::<Result<Map<_, _>>>()?;
+            let required: Vec<&String> = fields.keys().collect();
+            Ok(json!({ "type": "object", "properties": properties, "required": required }))
+        }
+        Json::Object(fields) => {
+            let mut schema = Map::new();
+            for (keyword, value) in fields {
+                let value = match keyword.as_str() {
+                    "type" => match value.as_str() {
+                        Some(name) if TYPES.contains(&name) => value.clone(),
+                        _ => return Err(invalid(format!("unknown type {}", value))),
+                    },
+                    "properties" => match value {
+                        Json::Object(properties) => Json::Object(
+                            properties
+                                .iter()
+                                .map(|(name, spec)| Ok((name.clone(), define(spec)?)))
+                                .collect::<Result<_>>()?,
+                        ),
+                        _ => return Err(invalid("properties must be a map".to_string())),
+                    },
+                    "items" => define(value)?,
+                    "required" | "enum" if !value.is_array() => {
+                        return Err(invalid(format!("{} must be a list", keyword)))
+                    }
+                    "additionalProperties" if !value.is_boolean() => {
+                        return Err(invalid("additionalProperties must be a bool".to_string()))
+                    }
+                    "minimum" | "maximum" | "minLength" | "maxLength" | "minItems" | "maxItems" if !value.is_number() => {
+                        return Err(invalid(format!("{} must be a number", keyword)))
+                    }
+                    keyword if KEYWORDS.contains(&keyword) => value.clone(),
+                    keyword => return Err(invalid(format!("unknown keyword '{}'", keyword))),
+                };
+                schema.insert(keyword.clone(), value);
+            }
+            Ok(Json::Object(schema))
+        }
+        _ => Err(invalid(format!("{} is not a schema", spec))),
+    }
+}
+
+/// Checks `value` against a schema returned by [`define`].
+pub fn validate(value: &Value, schema: &Json) -> Validation {
+    let mut errors = Vec::new();
+    let mut coercions = 0;
+    let mut checked = check(value, schema, "$", &mut errors, &mut coercions);
+    checked.confidence = if errors.is_empty() {
+        value.confidence * COERCION_PENALTY.powi(coercions)
+    } else {
+        0.0
+    };
+    Validation { errors, value: checked }
+}
+
+fn check(value: &Value, schema: &Json, path: &str, errors: &mut Vec<String>, coercions: &mut i32) -> Value {
+    let keyword = |name: &str| schema.get(name);
+    let number = |name: &str| keyword(name).and_then(Json::as_f64);
+    let mut value = match keyword("type").and_then(Json::as_str) {
+        Some(expected) => match coerce(value, expected) {
+            Some((coerced, converted)) => {
+                *coercions += i32::from(converted);
+                coerced
+            }
+            None => {
+                errors.push(format!("{}: expected {}, got {}", path, expected, type_name(value)));
+                return value.clone();
+            }
+        },
+        None => value.clone(),
+    };
+
+    if let Some(Json::Array(allowed)) = keyword("enum") {
+        if !value.to_json().is_ok_and(|json| allowed.contains(&json)) {
+            errors.push(format!("{}: {} is not one of {}", path, value, Json::Array(allowed.clone())));
+        }
+    }
+
+    let mut bounds = |actual: f64, what: &str, min: &str, max: &str| {
+        if let Some(min) = number(min).filter(|&min| actual < min) {
+            errors.push(format!("{}: {} {} is below the minimum of {}", path, what, actual, min));
+        }
+        if let Some(max) = number(max).filter(|&max| actual > max) {
+            errors.push(format!("{}: {} {} is above the maximum of {}", path, what, actual, max));
+        }
+    };
+    match &mut value.kind {
+        ValueKind::Number(n) => bounds(*n, "value", "minimum", "maximum"),
+        ValueKind::String(s) => bounds(s.chars().count() as f64, "length", "minLength", "maxLength"),
+        ValueKind::List(items) => {
+            bounds(items.len() as f64, "length", "minItems", "maxItems");
+            if let Some(item_schema) = keyword("items") {
+                for (index, item) in items.iter_mut().enumerate() {
+                    *item = check(item, item_schema, &format!("{}[{}]", path, index), errors, coercions);
+                }
+            }
+        }
+        ValueKind::Map(entries) => {
+            let properties = keyword("properties").and_then(Json::as_object);
+            if let Some(Json::Array(required)) = keyword("required") {
+                for name in required.iter().filter_map(Json::as_str) {
+                    if !entries.iter().any(|(key, _)| key.to_string() == name) {
+                        errors.push(format!("{}: missing required property '{}'", path, name));
+                    }
+                }
+            }
+            let closed = keyword("additionalProperties") == Some(&Json::Bool(false));
+            for (key, entry) in entries.iter_mut() {
+                let name = key.to_string();
+                match properties.and_then(|properties| properties.get(&name)) {
+                    Some(property) => *entry = check(entry, property, &format!("{}.{}", path, name), errors, coercions),
+                    None if closed => errors.push(format!("{}: unexpected property '{}'", path, name)),
+                    None => {}
+                }
+            }
+        }
+        _ => {}
+    }
+    value
+}
+
+/// `value` as the given type and whether it had to be converted (with a
+/// confidence penalty), or `None` when it cannot be.
+fn coerce(value: &Value, expected: &str) -> Option<(Value, bool)> {
+    let converted = |kind: ValueKind| {
+        let confidence = value.confidence * COERCION_PENALTY;
+        Some((Value { kind, confidence, context: value.context.clone(), metadata: value.metadata.clone() }, true))
+    };
+    match (expected, &value.kind) {
+        ("any", _)
+        | ("null", ValueKind::Nil)
+        | ("boolean", ValueKind::Boolean(_))
+        | ("number", ValueKind::Number(_))
+        | ("string", ValueKind::String(_))
+        | ("array", ValueKind::List(_))
+        | ("object", ValueKind::Map(_)) => Some((value.clone(), false)),
+        ("integer", ValueKind::Number(n)) if n.fract() == 0.0 => Some((value.clone(), false)),
+        ("number", ValueKind::String(s)) => s.trim().parse().ok().map(|n| converted(ValueKind::Number(n)))?,
+        ("integer", ValueKind::String(s)) => s.trim().parse::<i64>().ok().map(|n| converted(ValueKind::Number(n as f64)))?,
+        ("boolean", ValueKind::String(s)) => match s.trim().to_lowercase().as_str() {
+            "true" => converted(ValueKind::Boolean(true)),
+            "false" => converted(ValueKind::Boolean(false)),
+            _ => None,
+        },
+        ("string", ValueKind::Number(_) | ValueKind::Boolean(_)) => converted(ValueKind::String(value.to_string())),
+        _ => None,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value.kind {
+        ValueKind::Nil => "null",
+        ValueKind::Boolean(_) => "boolean",
+        ValueKind::Number(_) => "number",
+        ValueKind::String(_) => "string",
+        ValueKind::List(_) => "array",
+        ValueKind::Map(_) => "object",
+        _ => "function",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patient() -> Json {
+        define(&json!({
+            "name": "string",
+            "age": { "type": "integer", "minimum": 0 },
+            "symptoms": ["string"],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_define_expands_shorthand() -> Result<()> {
+        assert_eq!(patient()["properties"]["symptoms"], json!({ "type": "array", "items": { "type": "string" } }));
+        assert_eq!(patient()["required"], json!(["age", "name", "symptoms"]));
+        assert!(define(&json!({ "type": "object", "pattern": "x" })).is_err());
+        assert!(define(&json!("decimal")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_coerces_with_penalty() {
+        let value = Value::from_json(&json!({ "name": "Ada", "age": "42", "symptoms": ["fever"] }));
+        let validation = validate(&value, &patient());
+        assert!(validation.is_valid(), "{:?}", validation.errors);
+        assert_eq!(validation.value.to_string(), "{age: 42, name: Ada, symptoms: [fever]}");
+        let age = match &validation.value.kind {
+            ValueKind::Map(entries) => entries[0].1.confidence,
+            _ => unreachable!(),
+        };
+        assert_eq!(age, COERCION_PENALTY);
+        assert_eq!(validation.value.confidence, COERCION_PENALTY);
+    }
+
+    #[test]
+    fn test_validate_reports_paths() {
+        let value = Value::from_json(&json!({ "age": -1, "symptoms": ["fever", 3, null] }));
+        let validation = validate(&value, &patient());
+        assert_eq!(
+            validation.errors,
+            [
+                "$: missing required property 'name'",
+                "$.age: value -1 is below the minimum of 0",
+                "$.symptoms[2]: expected string, got null",
+            ]
+        );
+        assert_eq!(validation.value.confidence, 0.0);
+    }
+}
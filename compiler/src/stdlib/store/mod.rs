@@ -0,0 +1,194 @@
+//! `store.open(path)`: a persistent key-value store for facts and learned
+//! confidences that should survive between runs.
+//!
+//! A store is one JSON file. Every `set` and `delete` rewrites it through a
+//! temporary file and a rename, so a crash leaves either the old or the new
+//! contents, never a torn file. Values keep their confidence.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use crate::capabilities::Capability;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{NativeHandler, Value, ValueKind};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    value: serde_json::Value,
+    confidence: f64,
+}
+
+#[derive(Debug)]
+pub struct Store {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<String, Entry>>,
+}
+
+impl Store {
+    /// Opens the store at `path`, starting empty if the file does not exist.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let entries = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.entries.lock().get(key).map(|entry| {
+            let mut value = Value::from_json(&entry.value);
+            value.confidence = entry.confidence;
+            value
+        })
+    }
+
+    pub fn set(&self, key: &str, value: &Value) -> Result<()> {
+        let entry = Entry {
+            value: value.to_json()?,
+            confidence: value.confidence,
+        };
+        let mut entries = self.entries.lock();
+        entries.insert(key.to_string(), entry);
+        self.save(&entries)
+    }
+
+    /// Removes `key`; returns whether it was present.
+    pub fn delete(&self, key: &str) -> Result<bool> {
+        let mut entries = self.entries.lock();
+        let removed = entries.remove(key).is_some();
+        if removed {
+            self.save(&entries)?;
+        }
+        Ok(removed)
+    }
+
+    /// Keys starting with `prefix`, in sorted order.
+    pub fn keys(&self, prefix: &str) -> Vec<String> {
+        self.entries
+            .lock()
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    fn save(&self, entries: &BTreeMap<String, Entry>) -> Result<()> {
+        let temporary = temporary_path(&self.path);
+        fs::write(&temporary, serde_json::to_vec_pretty(entries)?)?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
+
+/// A sibling of `path`, so the rename stays on one file system.
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+pub fn init_store_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("store".to_string())));
+
+    // open function: a map of get/set/delete/keys functions bound to the file
+    let open_fn = Value::new(ValueKind::NativeFunction {
+        name: "open".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| {
+            interpreter.capabilities().require(Capability::Fs, "store.open")?;
+            let path = string(args.first(), "store.open expects a path")?;
+            Ok(handles(Arc::new(Store::open(path)?)))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("open".to_string(), open_fn)?;
+    }
+
+    Ok(module)
+}
+
+fn handles(store: Arc<Store>) -> Value {
+    let function = |name: &str, arity: usize, handler: NativeHandler| {
+        (
+            Value::new(ValueKind::String(name.to_string())),
+            Value::new(ValueKind::NativeFunction { name: name.to_string(), arity, handler }),
+        )
+    };
+
+    let get_store = Arc::clone(&store);
+    let set_store = Arc::clone(&store);
+    let delete_store = Arc::clone(&store);
+    Value::new(ValueKind::Map(vec![
+        function("get", 1, Arc::new(move |_, args| {
+            let key = string(args.first(), "get expects a key")?;
+            Ok(get_store.get(key).unwrap_or_else(|| Value::new(ValueKind::Nil)))
+        })),
+        function("set", 2, Arc::new(move |_, args| {
+            let key = string(args.first(), "set expects a key")?;
+            let value = args.get(1).cloned().unwrap_or_else(|| Value::new(ValueKind::Nil));
+            set_store.set(key, &value)?;
+            Ok(value)
+        })),
+        function("delete", 1, Arc::new(move |_, args| {
+            let key = string(args.first(), "delete expects a key")?;
+            Ok(Value::new(ValueKind::Boolean(delete_store.delete(key)?)))
+        })),
+        function("keys", 1, Arc::new(move |_, args| {
+            let prefix = match args.first() {
+                None => "",
+                arg => string(arg, "keys expects a string prefix")?,
+            };
+            let keys = store.keys(prefix).into_iter().map(|key| Value::new(ValueKind::String(key))).collect();
+            Ok(Value::new(ValueKind::List(keys)))
+        })),
+    ]))
+}
+
+fn string<'a>(arg: Option<&'a Value>, message: &str) -> Result<&'a str> {
+    match arg.map(|arg| &arg.kind) {
+        Some(ValueKind::String(s)) => Ok(s),
+        _ => Err(PrismError::InvalidArgument(message.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_persists_values_and_confidence() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("prism-store-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("facts.json");
+        let _ = fs::remove_file(&path);
+
+        let store = Store::open(&path)?;
+        store.set("symptom:fever", &Value::with_confidence(ValueKind::Number(0.8), 0.7))?;
+        store.set("symptom:cough", &Value::new(ValueKind::Boolean(true)))?;
+        store.set("patient", &Value::new(ValueKind::String("Ada".to_string())))?;
+        assert!(store.delete("patient")?);
+        assert!(!store.delete("patient")?);
+
+        let reopened = Store::open(&path)?;
+        assert_eq!(reopened.keys("symptom:"), vec!["symptom:cough", "symptom:fever"]);
+        let fever = reopened.get("symptom:fever").unwrap();
+        assert_eq!(fever.kind, ValueKind::Number(0.8));
+        assert_eq!(fever.confidence, 0.7);
+        assert!(reopened.get("patient").is_none());
+        assert!(!temporary_path(&path).exists());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
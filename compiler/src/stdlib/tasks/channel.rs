@@ -0,0 +1,215 @@
+//! `async.channel(capacity?)`: a queue connecting concurrent tasks.
+//!
+//! The channel is a map of functions sharing one queue:
+//! `send(value)` waits while a bounded channel is full and fails once the
+//! channel is closed, `recv()` waits for the next value and is `nil` once
+//! the channel is closed and drained, `try_recv()` is the next value or `nil`
+//! without waiting, and `close()` lets receivers finish. `next()` makes the
+//! channel an iterator, so `for (value in channel)` receives until it is
+//! closed and drained.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::task::Waker;
+use std::time::Duration;
+use parking_lot::Mutex;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::iterator;
+use crate::value::{Value, ValueKind};
+
+/// How long a waiting task sleeps before it checks for cancellation again.
+const WAIT_SLICE: Duration = Duration::from_millis(10);
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<Value>,
+    capacity: Option<usize>,
+    closed: bool,
+    /// Bumped on every change so waiting tasks can tell they were notified.
+    version: u64,
+    /// Tasks waiting for the queue to change.
+    waiting: Vec<Waker>,
+}
+
+#[derive(Default)]
+struct Channel {
+    state: Mutex<State>,
+}
+
+impl Channel {
+    fn notify(state: &mut State) {
+        state.version += 1;
+        for waker in state.waiting.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Waits until the queue changes from the `seen` version, or at most one
+    /// slice so the caller can check for cancellation.
+    async fn changed(&self, seen: u64) {
+        #[cfg(feature = "native")]
+        {
+            use std::future::{poll_fn, Future};
+            use std::pin::pin;
+            use std::task::Poll;
+
+            let mut timer = pin!(tokio::time::sleep(WAIT_SLICE));
+            poll_fn(|cx| {
+                let mut state = self.state.lock();
+                if state.version != seen || timer.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(());
+                }
+                state.waiting.push(cx.waker().clone());
+                Poll::Pending
+            })
+            .await
+        }
+        #[cfg(not(feature = "native"))]
+        {
+            let _ = seen;
+            std::thread::sleep(WAIT_SLICE);
+        }
+    }
+
+    async fn send(&self, interpreter: &mut Interpreter, value: Value) -> Result<()> {
+        loop {
+            let seen = {
+                let mut state = self.state.lock();
+                if state.closed {
+                    return Err(PrismError::RuntimeError("send on a closed channel".to_string()));
+                }
+                if state.capacity.is_none_or(|capacity| state.queue.len() < capacity) {
+                    state.queue.push_back(value);
+                    Channel::notify(&mut state);
+                    return Ok(());
+                }
+                state.version
+            };
+            self.changed(seen).await;
+            interpreter.check_cancelled("channel send")?;
+        }
+    }
+
+    /// The next value, or `None` once the channel is closed and drained.
+    async fn recv(&self, interpreter: &mut Interpreter) -> Result<Option<Value>> {
+        loop {
+            let seen = {
+                let mut state = self.state.lock();
+                if let Some(value) = state.queue.pop_front() {
+                    Channel::notify(&mut state);
+                    return Ok(Some(value));
+                }
+                if state.closed {
+                    return Ok(None);
+                }
+                state.version
+            };
+            self.changed(seen).await;
+            interpreter.check_cancelled("channel receive")?;
+        }
+    }
+
+    fn try_recv(&self) -> Value {
+        let mut state = self.state.lock();
+        match state.queue.pop_front() {
+            Some(value) => {
+                Channel::notify(&mut state);
+                value
+            }
+            None => Value::new(ValueKind::Nil),
+        }
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock();
+        state.closed = true;
+        Channel::notify(&mut state);
+    }
+}
+
+/// The `async.channel` native.
+pub fn channel_fn() -> Value {
+    Value::new(ValueKind::NativeFunction {
+        name: "channel".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let capacity = match args.first().map(|arg| &arg.kind) {
+                None | Some(ValueKind::Nil) => None,
+                Some(ValueKind::Number(n)) if *n >= 1.0 => Some(*n as usize),
+                Some(_) => return Err(PrismError::InvalidArgument(
+                    "async.channel expects a positive capacity".to_string(),
+                )),
+            };
+            let channel = Arc::new(Channel {
+                state: Mutex::new(State { capacity, ..State::default() }),
+            });
+            Ok(handles(channel))
+        }),
+    })
+}
+
+fn handles(channel: Arc<Channel>) -> Value {
+    let entry = |name: &str, value: Value| (Value::new(ValueKind::String(name.to_string())), value);
+
+    let sender = Arc::clone(&channel);
+    let send = Value::new(ValueKind::AsyncNativeFunction {
+        name: "send".to_string(),
+        arity: 1,
+        handler: Arc::new(move |interpreter, args| {
+            let channel = Arc::clone(&sender);
+            Box::pin(async move {
+                let value = args.into_iter().next().unwrap_or_else(|| Value::new(ValueKind::Nil));
+                channel.send(interpreter, value).await?;
+                Ok(Value::new(ValueKind::Nil))
+            })
+        }),
+    });
+
+    let receiver = Arc::clone(&channel);
+    let recv = Value::new(ValueKind::AsyncNativeFunction {
+        name: "recv".to_string(),
+        arity: 0,
+        handler: Arc::new(move |interpreter, _| {
+            let channel = Arc::clone(&receiver);
+            Box::pin(async move {
+                let value = channel.recv(interpreter).await?;
+                Ok(value.unwrap_or_else(|| Value::new(ValueKind::Nil)))
+            })
+        }),
+    });
+
+    let receiver = Arc::clone(&channel);
+    let next = Value::new(ValueKind::AsyncNativeFunction {
+        name: "next".to_string(),
+        arity: 0,
+        handler: Arc::new(move |interpreter, _| {
+            let channel = Arc::clone(&receiver);
+            Box::pin(async move { Ok(iterator::step(channel.recv(interpreter).await?)) })
+        }),
+    });
+
+    let receiver = Arc::clone(&channel);
+    let try_recv = Value::new(ValueKind::NativeFunction {
+        name: "try_recv".to_string(),
+        arity: 0,
+        handler: Arc::new(move |_, _| Ok(receiver.try_recv())),
+    });
+
+    let close = Value::new(ValueKind::NativeFunction {
+        name: "close".to_string(),
+        arity: 0,
+        handler: Arc::new(move |_, _| {
+            channel.close();
+            Ok(Value::new(ValueKind::Nil))
+        }),
+    });
+
+    Value::new(ValueKind::Map(vec![
+        entry("send", send),
+        entry("recv", recv),
+        entry("try_recv", try_recv),
+        entry("next", next),
+        entry("close", close),
+    ]))
+}
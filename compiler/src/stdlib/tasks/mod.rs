@@ -0,0 +1,209 @@
+//! The `async` module: runs Prism functions concurrently.
+//!
+//! Each task runs in a [fork](Interpreter::fork) of the calling interpreter
+//! and is polled on the caller's task, so tasks interleave wherever they
+//! wait (sleeps, LLM and HTTP calls) without needing a multi-threaded
+//! runtime. A list of results is as confident as its items combined with
+//! the interpreter's [strategy](crate::confidence::CombineStrategy), by
+//! default their product.
+
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use parking_lot::RwLock;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::module::Module;
+use crate::value::{Value, ValueKind};
+
+/// Tasks `async.map` runs at once unless told otherwise.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+pub mod channel;
+
+pub(crate) type Task = Pin<Box<dyn Future<Output = (Interpreter, Result<Value>)> + Send>>;
+
+pub fn init_tasks_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("async".to_string())));
+
+    // all function: all([f, g]) calls every function concurrently and returns
+    // their results in order; the first error fails the whole call
+    let all_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "all".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| Box::pin(async move {
+            let functions = list(args.first(), "async.all expects a list of functions")?;
+            let tasks = functions
+                .iter()
+                .map(|callee| task(interpreter, callee.clone(), Vec::new()))
+                .collect::<Result<Vec<_>>>()?;
+            let limit = tasks.len();
+            collect(interpreter, tasks, limit).await
+        })),
+    });
+
+    // race function: race([f, g]) is the first result to succeed; it only
+    // fails when every function failed, with the last error
+    let race_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "race".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| Box::pin(async move {
+            let functions = list(args.first(), "async.race expects a list of functions")?;
+            if functions.is_empty() {
+                return Err(PrismError::InvalidArgument("async.race needs at least one function".to_string()));
+            }
+            let tasks = functions
+                .iter()
+                .map(|callee| task(interpreter, callee.clone(), Vec::new()))
+                .collect::<Result<Vec<_>>>()?;
+            let limit = tasks.len();
+
+            let mut outcome = None;
+            drive(tasks, limit, |_, fork, result| {
+                interpreter.join(fork);
+                let done = result.is_ok() || matches!(result, Err(PrismError::Cancelled(_)));
+                outcome = Some(result);
+                done
+            })
+            .await;
+            outcome.expect("at least one task ran")
+        })),
+    });
+
+    // map function: map(list, fn, { concurrency: 4 }) calls fn on every item
+    // with at most `concurrency` calls in flight and returns the results in order
+    let map_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "map".to_string(),
+        arity: 3,
+        handler: Arc::new(|interpreter, args| Box::pin(async move {
+            let items = list(args.first(), "async.map expects a list")?;
+            let callee = args.get(1).cloned().unwrap_or_else(|| Value::new(ValueKind::Nil));
+            let concurrency = concurrency(args.get(2))?;
+            let tasks = items
+                .into_iter()
+                .map(|item| task(interpreter, callee.clone(), vec![item]))
+                .collect::<Result<Vec<_>>>()?;
+            collect(interpreter, tasks, concurrency).await
+        })),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("all".to_string(), all_fn)?;
+        module.export("race".to_string(), race_fn)?;
+        module.export("map".to_string(), map_fn)?;
+        module.export("channel".to_string(), channel::channel_fn())?;
+    }
+
+    Ok(module)
+}
+
+/// A call of `callee` in its own fork of `interpreter`.
+fn task(interpreter: &Interpreter, callee: Value, args: Vec<Value>) -> Result<Task> {
+    if !matches!(
+        callee.kind,
+        ValueKind::Function { .. } | ValueKind::NativeFunction { .. } | ValueKind::AsyncNativeFunction { .. }
+    ) {
+        return Err(PrismError::InvalidArgument(format!("{} is not a function", callee)));
+    }
+    let mut fork = interpreter.fork();
+    Ok(Box::pin(async move {
+        let result = fork.call(callee, args).await;
+        (fork, result)
+    }))
+}
+
+/// Runs `tasks` and lists their results in order, failing on the first error.
+async fn collect(interpreter: &mut Interpreter, tasks: Vec<Task>, limit: usize) -> Result<Value> {
+    let mut results: Vec<Option<Value>> = vec![None; tasks.len()];
+    let mut error = None;
+    drive(tasks, limit, |index, fork, result| {
+        interpreter.join(fork);
+        match result {
+            Ok(value) => {
+                results[index] = Some(value);
+                false
+            }
+            Err(err) => {
+                error = Some(err);
+                true
+            }
+        }
+    })
+    .await;
+    if let Some(err) = error {
+        return Err(err);
+    }
+
+    let results: Vec<Value> = results.into_iter().map(|value| value.expect("every task finished")).collect();
+    let confidence = interpreter.combine_strategy().combine(results.iter().map(|value| value.confidence));
+    Ok(Value::with_confidence(ValueKind::List(results), confidence))
+}
+
+/// Polls `tasks` with at most `limit` in flight, passing each outcome with
+/// the task's index to `settle` as it finishes. Stops early, dropping the
+/// unfinished tasks, once `settle` returns true.
+pub(crate) async fn drive(
+    tasks: Vec<Task>,
+    limit: usize,
+    mut settle: impl FnMut(usize, Interpreter, Result<Value>) -> bool,
+) {
+    let limit = limit.max(1);
+    let mut pending = tasks.into_iter().enumerate();
+    let mut running: Vec<(usize, Task)> = Vec::with_capacity(limit);
+    poll_fn(|cx| loop {
+        while running.len() < limit {
+            match pending.next() {
+                Some(task) => running.push(task),
+                None => break,
+            }
+        }
+        if running.is_empty() {
+            return Poll::Ready(());
+        }
+
+        let mut progressed = false;
+        let mut i = 0;
+        while i < running.len() {
+            match running[i].1.as_mut().poll(cx) {
+                Poll::Ready((fork, result)) => {
+                    let (index, _) = running.swap_remove(i);
+                    if settle(index, fork, result) {
+                        return Poll::Ready(());
+                    }
+                    progressed = true;
+                }
+                Poll::Pending => i += 1,
+            }
+        }
+        // Finished tasks free slots for pending ones, so go round again
+        if !progressed {
+            return Poll::Pending;
+        }
+    })
+    .await
+}
+
+fn list(arg: Option<&Value>, message: &str) -> Result<Vec<Value>> {
+    match arg.map(|arg| &arg.kind) {
+        Some(ValueKind::List(items)) => Ok(items.clone()),
+        _ => Err(PrismError::InvalidArgument(message.to_string())),
+    }
+}
+
+pub(crate) fn concurrency(options: Option<&Value>) -> Result<usize> {
+    let entries = match options.map(|options| &options.kind) {
+        None | Some(ValueKind::Nil) => return Ok(DEFAULT_CONCURRENCY),
+        Some(ValueKind::Map(entries)) => entries,
+        Some(_) => return Err(PrismError::InvalidArgument("async.map options must be a map".to_string())),
+    };
+    match entries.iter().find(|(key, _)| key.to_string() == "concurrency") {
+        None => Ok(DEFAULT_CONCURRENCY),
+        Some((_, Value { kind: ValueKind::Number(n), .. })) if *n >= 1.0 => Ok(*n as usize),
+        Some((_, value)) => Err(PrismError::InvalidArgument(format!(
+            "concurrency must be a positive number, got {}",
+            value
+        ))),
+    }
+}
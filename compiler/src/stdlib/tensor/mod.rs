@@ -0,0 +1,330 @@
+//! Numeric vectors and matrices, behind the `tensor` cargo feature.
+//!
+//! A tensor holds its numbers unboxed in one `ndarray` array, so comparing a
+//! query embedding with thousands of stored ones is a matrix product rather
+//! than a loop over lists of values. Tensors are vectors (one dimension) or
+//! matrices (two, one embedding per row); `tensor.of` converts a list of
+//! numbers or a list of equally long lists.
+//!
+//! `+`, `-`, `*` and `/` work elementwise on tensors of the same shape, and
+//! scale a tensor by a number. Tensors are shared when copied, so passing
+//! a large matrix around costs nothing.
+
+use std::sync::Arc;
+use ndarray::{Array1, Array2, ArrayD, Axis, Ix1, Ix2, IxDyn};
+use parking_lot::RwLock;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::token::TokenKind;
+use crate::value::{Value, ValueKind};
+
+pub type Tensor = Arc<ArrayD<f64>>;
+
+fn tensor(array: ArrayD<f64>) -> ValueKind {
+    ValueKind::Tensor(Arc::new(array))
+}
+
+fn invalid(message: impl Into<String>) -> PrismError {
+    PrismError::InvalidArgument(message.into())
+}
+
+fn number(value: &Value) -> Result<f64> {
+    match value.kind {
+        ValueKind::Number(n) => Ok(n),
+        _ => Err(invalid(format!("{} is not a number", value))),
+    }
+}
+
+/// A vector from a list of numbers, or a matrix from a list of equally long
+/// lists of numbers or vectors.
+pub fn from_list(items: &[Value]) -> Result<ArrayD<f64>> {
+    let rows = items
+        .iter()
+        .map(|item| match &item.kind {
+            ValueKind::List(row) => row.iter().map(number).collect::<Result<Vec<_>>>().map(Some),
+            ValueKind::Tensor(row) if row.ndim() == 1 => Ok(Some(row.iter().copied().collect())),
+            _ => Ok(None),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if rows.iter().all(Option::is_none) {
+        let data = items.iter().map(number).collect::<Result<Vec<_>>>()?;
+        return Ok(ArrayD::from_shape_vec(IxDyn(&[data.len()]), data).expect("vector shape"));
+    }
+    let rows: Vec<Vec<f64>> = rows
+        .into_iter()
+        .map(|row| row.ok_or_else(|| invalid("a matrix needs every row to be a list of numbers")))
+        .collect::<Result<_>>()?;
+    let width = rows.first().map_or(0, Vec::len);
+    if rows.iter().any(|row| row.len() != width) {
+        return Err(invalid("every row of a matrix must have the same length"));
+    }
+    let height = rows.len();
+    Ok(ArrayD::from_shape_vec(IxDyn(&[height, width]), rows.concat()).expect("matrix shape"))
+}
+
+/// The tensor as nested lists of numbers.
+pub fn to_list(array: &ArrayD<f64>) -> Value {
+    match array.ndim() {
+        0 => Value::new(ValueKind::Number(array.iter().next().copied().unwrap_or(0.0))),
+        1 => Value::new(ValueKind::List(array.iter().map(|n| Value::new(ValueKind::Number(*n))).collect())),
+        _ => Value::new(ValueKind::List(array.outer_iter().map(|row| to_list(&row.to_owned())).collect())),
+    }
+}
+
+fn vector(array: &ArrayD<f64>) -> Option<ndarray::ArrayView1<'_, f64>> {
+    array.view().into_dimensionality::<Ix1>().ok()
+}
+
+fn matrix(array: &ArrayD<f64>) -> Option<ndarray::ArrayView2<'_, f64>> {
+    array.view().into_dimensionality::<Ix2>().ok()
+}
+
+fn mismatch(a: &ArrayD<f64>, b: &ArrayD<f64>) -> PrismError {
+    PrismError::RuntimeError(format!("Tensor shapes {:?} and {:?} do not match", a.shape(), b.shape()))
+}
+
+/// The dot product: a number for two vectors, a vector for a matrix and a
+/// vector, a matrix for two matrices.
+pub fn dot(a: &ArrayD<f64>, b: &ArrayD<f64>) -> Result<ValueKind> {
+    let compatible = match (a.ndim(), b.ndim()) {
+        (1, _) | (2, _) => a.shape()[a.ndim() - 1] == b.shape()[0],
+        _ => false,
+    };
+    if !compatible {
+        return Err(mismatch(a, b));
+    }
+    Ok(match (vector(a), matrix(a), vector(b), matrix(b)) {
+        (Some(a), _, Some(b), _) => ValueKind::Number(a.dot(&b)),
+        (Some(a), _, _, Some(b)) => tensor(a.dot(&b).into_dyn()),
+        (_, Some(a), Some(b), _) => tensor(a.dot(&b).into_dyn()),
+        (_, Some(a), _, Some(b)) => tensor(a.dot(&b).into_dyn()),
+        _ => return Err(mismatch(a, b)),
+    })
+}
+
+/// The Euclidean length of a vector.
+pub fn norm(a: &ArrayD<f64>) -> f64 {
+    a.iter().map(|n| n * n).sum::<f64>().sqrt()
+}
+
+/// A vector scaled to length 1, or a matrix with every row scaled so; all
+/// zero vectors stay zero.
+pub fn normalize(a: &ArrayD<f64>) -> ArrayD<f64> {
+    let scale = |row: ndarray::ArrayView1<f64>| {
+        let length = row.dot(&row).sqrt();
+        if length == 0.0 { row.to_owned() } else { &row / length }
+    };
+    match (vector(a), matrix(a)) {
+        (Some(v), _) => scale(v).into_dyn(),
+        (_, Some(m)) => {
+            let mut rows = Array2::zeros(m.raw_dim());
+            for (mut target, row) in rows.axis_iter_mut(Axis(0)).zip(m.axis_iter(Axis(0))) {
+                target.assign(&scale(row));
+            }
+            rows.into_dyn()
+        }
+        _ => a.clone(),
+    }
+}
+
+/// The cosine similarity of two vectors, or of each row of a matrix with a
+/// vector as a vector of similarities.
+pub fn cosine(a: &ArrayD<f64>, b: &ArrayD<f64>) -> Result<ValueKind> {
+    if b.ndim() != 1 {
+        return Err(invalid("tensor.cosine compares with a vector"));
+    }
+    dot(&normalize(a), &normalize(b))
+}
+
+/// The indices of the `k` largest entries of a vector, largest first.
+pub fn top(a: &ArrayD<f64>, k: usize) -> Result<Vec<usize>> {
+    let v = vector(a).ok_or_else(|| invalid("tensor.top expects a vector"))?;
+    let mut indices: Vec<usize> = (0..v.len()).collect();
+    indices.sort_by(|&i, &j| v[j].total_cmp(&v[i]));
+    indices.truncate(k);
+    Ok(indices)
+}
+
+/// The result of a binary operator with a tensor operand.
+pub fn binary(operator: &TokenKind, left: &ValueKind, right: &ValueKind) -> Result<ValueKind> {
+    let elementwise = |a: &ArrayD<f64>, b: &ArrayD<f64>| -> Result<ArrayD<f64>> {
+        if a.shape() != b.shape() {
+            return Err(mismatch(a, b));
+        }
+        Ok(match operator {
+            TokenKind::Plus => a + b,
+            TokenKind::Minus => a - b,
+            TokenKind::Star => a * b,
+            TokenKind::Slash => a / b,
+            _ => return Err(PrismError::RuntimeError("Invalid operator for tensors".to_string())),
+        })
+    };
+    Ok(match (left, operator, right) {
+        (ValueKind::Tensor(a), TokenKind::EqualEqual, ValueKind::Tensor(b)) => ValueKind::Boolean(a == b),
+        (ValueKind::Tensor(a), TokenKind::BangEqual, ValueKind::Tensor(b)) => ValueKind::Boolean(a != b),
+        (ValueKind::Tensor(a), _, ValueKind::Tensor(b)) => tensor(elementwise(a, b)?),
+        (ValueKind::Tensor(a), TokenKind::Star, ValueKind::Number(n))
+        | (ValueKind::Number(n), TokenKind::Star, ValueKind::Tensor(a)) => tensor(a.as_ref() * *n),
+        (ValueKind::Tensor(a), TokenKind::Slash, ValueKind::Number(n)) => tensor(a.as_ref() / *n),
+        (ValueKind::Tensor(a), TokenKind::Plus, ValueKind::Number(n)) => tensor(a.as_ref() + *n),
+        (ValueKind::Tensor(a), TokenKind::Minus, ValueKind::Number(n)) => tensor(a.as_ref() - *n),
+        (_, TokenKind::EqualEqual, _) => ValueKind::Boolean(false),
+        (_, TokenKind::BangEqual, _) => ValueKind::Boolean(true),
+        _ => {
+            return Err(PrismError::RuntimeError(format!(
+                "Invalid operation between {:?} and {:?}",
+                left, right
+            )))
+        }
+    })
+}
+
+fn tensor_arg(name: &str, arg: Option<&Value>) -> Result<Tensor> {
+    match arg.map(|arg| &arg.kind) {
+        Some(ValueKind::Tensor(array)) => Ok(Arc::clone(array)),
+        Some(ValueKind::List(items)) => Ok(Arc::new(from_list(items)?)),
+        _ => Err(invalid(format!("tensor.{} expects a tensor or a list of numbers", name))),
+    }
+}
+
+fn native(name: &'static str, handler: impl Fn(&[Value]) -> Result<ValueKind> + Send + Sync + 'static) -> Value {
+    Value::new(ValueKind::NativeFunction {
+        name: name.to_string(),
+        arity: 1,
+        handler: Arc::new(move |_, args| {
+            let confidence = args.iter().map(|arg| arg.confidence).fold(1.0, f64::min);
+            Ok(Value::with_confidence(handler(&args)?, confidence))
+        }),
+    })
+}
+
+fn size(name: &str, arg: Option<&Value>) -> Result<usize> {
+    match arg.map(|arg| &arg.kind) {
+        Some(ValueKind::Number(n)) if *n >= 0.0 && n.fract() == 0.0 => Ok(*n as usize),
+        _ => Err(invalid(format!("tensor.{} expects a whole number", name))),
+    }
+}
+
+pub fn init_tensor_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("tensor".to_string())));
+    {
+        let mut module = module.write();
+        module.export("of".to_string(), native("of", |args| Ok(ValueKind::Tensor(tensor_arg("of", args.first())?))))?;
+        module.export(
+            "zeros".to_string(),
+            native("zeros", |args| {
+                let rows = size("zeros", args.first())?;
+                Ok(tensor(match args.get(1) {
+                    Some(cols) => Array2::zeros((rows, size("zeros", Some(cols))?)).into_dyn(),
+                    None => Array1::zeros(rows).into_dyn(),
+                }))
+            }),
+        )?;
+        module.export(
+            "to_list".to_string(),
+            native("to_list", |args| {
+                let array = tensor_arg("to_list", args.first())?;
+                Ok(to_list(&array).kind)
+            }),
+        )?;
+        module.export(
+            "shape".to_string(),
+            native("shape", |args| {
+                let array = tensor_arg("shape", args.first())?;
+                Ok(ValueKind::List(array.shape().iter().map(|n| Value::new(ValueKind::Number(*n as f64))).collect()))
+            }),
+        )?;
+        module.export(
+            "dot".to_string(),
+            native("dot", |args| {
+                let (a, b) = (tensor_arg("dot", args.first())?, tensor_arg("dot", args.get(1))?);
+                dot(&a, &b)
+            }),
+        )?;
+        module.export(
+            "norm".to_string(),
+            native("norm", |args| {
+                let array = tensor_arg("norm", args.first())?;
+                Ok(ValueKind::Number(norm(&array)))
+            }),
+        )?;
+        module.export(
+            "normalize".to_string(),
+            native("normalize", |args| {
+                let array = tensor_arg("normalize", args.first())?;
+                Ok(tensor(normalize(&array)))
+            }),
+        )?;
+        module.export(
+            "cosine".to_string(),
+            native("cosine", |args| {
+                let (a, b) = (tensor_arg("cosine", args.first())?, tensor_arg("cosine", args.get(1))?);
+                cosine(&a, &b)
+            }),
+        )?;
+        module.export(
+            "transpose".to_string(),
+            native("transpose", |args| Ok(tensor(tensor_arg("transpose", args.first())?.t().to_owned()))),
+        )?;
+        module.export(
+            "top".to_string(),
+            native("top", |args| {
+                let array = tensor_arg("top", args.first())?;
+                let indices = top(&array, size("top", args.get(1))?)?;
+                Ok(ValueKind::List(indices.into_iter().map(|i| Value::new(ValueKind::Number(i as f64))).collect()))
+            }),
+        )?;
+    }
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    fn numbers(values: &[f64]) -> Vec<Value> {
+        values.iter().map(|n| Value::new(ValueKind::Number(*n))).collect()
+    }
+
+    #[test]
+    fn test_similarity_of_rows_to_a_query() -> Result<()> {
+        let rows = vec![
+            Value::new(ValueKind::List(numbers(&[1.0, 0.0]))),
+            Value::new(ValueKind::List(numbers(&[0.0, 2.0]))),
+            Value::new(ValueKind::List(numbers(&[3.0, 3.0]))),
+        ];
+        let embeddings = from_list(&rows)?;
+        assert_eq!(embeddings.shape(), [3, 2]);
+        let query = from_list(&numbers(&[0.0, 1.0]))?;
+
+        let ValueKind::Tensor(scores) = cosine(&embeddings, &query)? else { panic!("expected a tensor") };
+        let expected = [0.0, 1.0, std::f64::consts::FRAC_1_SQRT_2];
+        assert!(scores.iter().zip(expected).all(|(score, expected)| (score - expected).abs() < 1e-12));
+        assert_eq!(top(&scores, 2)?, [1, 2]);
+        assert_eq!(dot(&query, &query)?, ValueKind::Number(1.0));
+        assert_eq!(norm(&from_list(&numbers(&[3.0, 4.0]))?), 5.0);
+
+        assert!(dot(&embeddings, &from_list(&numbers(&[1.0, 2.0, 3.0]))?).is_err());
+        let ragged = vec![Value::new(ValueKind::List(numbers(&[1.0]))), Value::new(ValueKind::List(numbers(&[1.0, 2.0])))];
+        assert!(from_list(&ragged).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tensors_compare_embeddings() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let docs = tensor.of([[1, 0], [0, 2], [3, 3]]);
+            let query = tensor.of([0, 1]);
+            let scores = tensor.cosine(docs, query);
+            let v = tensor.of([3, 4]);
+            [tensor.top(scores, 1), tensor.shape(docs), tensor.norm(v), str(v * 2 - tensor.of([1, 1])),
+             tensor.dot(v, v), type(v), len(docs), tensor.to_list(tensor.transpose(docs))];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[[1], [3, 2], 5, tensor([5, 7]), 25, tensor, 3, [[1, 0, 3], [0, 2, 3]]]");
+        assert!(interpreter.evaluate("tensor.of([1, 2]) + tensor.of([1, 2, 3]);".to_string()).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+//! The `text` module: splitting documents for embedding and prompts.
+//!
+//! Token budgets are measured with the [`ApproximateTokenizer`] until
+//! providers supply their own [`Tokenizer`].
+
+use std::sync::Arc;
+use parking_lot::RwLock;
+use crate::error::{PrismError, Result};
+use crate::llm::tokenizer::{ApproximateTokenizer, Tokenizer};
+use crate::module::Module;
+use crate::value::{Value, ValueKind};
+
+/// Tokens per chunk when `text.chunk` is not given `max_tokens`.
+pub const DEFAULT_CHUNK_TOKENS: usize = 256;
+
+pub fn init_text_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("text".to_string())));
+
+    // chunk function: chunk(text, { max_tokens: 256, overlap: 0 }) splits text
+    // into pieces of at most max_tokens tokens; consecutive chunks share
+    // `overlap` tokens
+    let chunk_fn = Value::new(ValueKind::NativeFunction {
+        name: "chunk".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let text = string(args.first(), "text.chunk")?;
+            let max_tokens = option(&args, "max_tokens", DEFAULT_CHUNK_TOKENS as f64)?;
+            let overlap = option(&args, "overlap", 0.0)?;
+            if max_tokens < 1.0 || overlap < 0.0 || overlap >= max_tokens {
+                return Err(PrismError::InvalidArgument(
+                    "text.chunk needs max_tokens of at least 1 and an overlap below it".to_string(),
+                ));
+            }
+            let chunks = chunk(&ApproximateTokenizer, text, max_tokens as usize, overlap as usize);
+            Ok(Value::new(ValueKind::List(chunks.into_iter().map(to_value).collect())))
+        }),
+    });
+
+    // sentences function
+    let sentences_fn = Value::new(ValueKind::NativeFunction {
+        name: "sentences".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let text = string(args.first(), "text.sentences")?;
+            Ok(Value::new(ValueKind::List(sentences(text).into_iter().map(to_value).collect())))
+        }),
+    });
+
+    // word_count function
+    let word_count_fn = Value::new(ValueKind::NativeFunction {
+        name: "word_count".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let text = string(args.first(), "text.word_count")?;
+            Ok(Value::new(ValueKind::Number(text.split_whitespace().count() as f64)))
+        }),
+    });
+
+    // token_count function
+    let token_count_fn = Value::new(ValueKind::NativeFunction {
+        name: "token_count".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let text = string(args.first(), "text.token_count")?;
+            Ok(Value::new(ValueKind::Number(ApproximateTokenizer.count(text) as f64)))
+        }),
+    });
+
+    // dedent function: removes the indentation shared by all non-blank lines,
+    // for multi-line string literals indented along with the code
+    let dedent_fn = Value::new(ValueKind::NativeFunction {
+        name: "dedent".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let text = string(args.first(), "text.dedent")?;
+            Ok(Value::new(ValueKind::String(dedent(text))))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("chunk".to_string(), chunk_fn)?;
+        module.export("sentences".to_string(), sentences_fn)?;
+        module.export("word_count".to_string(), word_count_fn)?;
+        module.export("token_count".to_string(), token_count_fn)?;
+        module.export("dedent".to_string(), dedent_fn)?;
+    }
+
+    Ok(module)
+}
+
+fn chunk<'a>(tokenizer: &dyn Tokenizer, text: &'a str, max_tokens: usize, overlap: usize) -> Vec<&'a str> {
+    let tokens = tokenizer.tokens(text);
+    let mut chunks = Vec::new();
+    let mut first = 0;
+    while first < tokens.len() {
+        let last = (first + max_tokens).min(tokens.len()) - 1;
+        chunks.push(&text[tokens[first].start..tokens[last].end]);
+        if last + 1 == tokens.len() {
+            break;
+        }
+        first += max_tokens - overlap;
+    }
+    chunks
+}
+
+/// Splits after `.`, `!` or `?` followed by whitespace, and at blank lines.
+fn sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, ch)) = chars.next() {
+        let next = chars.peek().map(|&(_, next)| next);
+        let end = match ch {
+            '.' | '!' | '?' if next.is_none_or(char::is_whitespace) => index + 1,
+            '\n' if next == Some('\n') => index,
+            _ => continue,
+        };
+        sentences.push(text[start..end].trim());
+        start = end;
+    }
+    sentences.push(text[start..].trim());
+    sentences.retain(|sentence| !sentence.is_empty());
+    sentences
+}
+
+fn dedent(text: &str) -> String {
+    let indent = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    text.split('\n')
+        .map(|line| if line.trim().is_empty() { line.trim_start() } else { &line[indent..] })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn string<'a>(arg: Option<&'a Value>, name: &str) -> Result<&'a str> {
+    match arg.map(|arg| &arg.kind) {
+        Some(ValueKind::String(text)) => Ok(text),
+        _ => Err(PrismError::InvalidArgument(format!("{} expects a string", name))),
+    }
+}
+
+fn to_value(text: &str) -> Value {
+    Value::new(ValueKind::String(text.to_string()))
+}
+
+/// A number from the options map in the second argument, or `default`.
+fn option(args: &[Value], name: &str, default: f64) -> Result<f64> {
+    let entries = match args.get(1).map(|arg| &arg.kind) {
+        None | Some(ValueKind::Nil) => return Ok(default),
+        Some(ValueKind::Map(entries)) => entries,
+        Some(_) => return Err(PrismError::InvalidArgument("expected an options map".to_string())),
+    };
+    match entries.iter().find(|(key, _)| key.to_string() == name) {
+        None => Ok(default),
+        Some((_, Value { kind: ValueKind::Number(n), .. })) => Ok(*n),
+        Some((_, value)) => Err(PrismError::InvalidArgument(format!("option {} must be a number, got {}", name, value))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_with_overlap() {
+        let text = "one two four five six nine ten";
+        assert_eq!(chunk(&ApproximateTokenizer, text, 3, 1), ["one two four", "four five six", "six nine ten"]);
+        assert_eq!(chunk(&ApproximateTokenizer, text, 10, 0), [text]);
+        assert!(chunk(&ApproximateTokenizer, "  ", 3, 0).is_empty());
+    }
+
+    #[test]
+    fn test_sentences_and_dedent() {
+        assert_eq!(
+            sentences("Fever is 3.5 degrees up. Is it flu?\n\nHeading\nNo!"),
+            ["Fever is 3.5 degrees up.", "Is it flu?", "Heading\nNo!"]
+        );
+        assert_eq!(dedent("\n    first\n      nested\n\n    last"), "\nfirst\n  nested\n\nlast");
+    }
+}
@@ -0,0 +1,346 @@
+//! The `time` module: points in time and durations as values.
+//!
+//! A datetime is an instant together with the UTC offset it is shown in,
+//! e.g. `2026-10-16T09:30:00+02:00`; a duration is a signed span such as
+//! `1h 30m`. Zones are written `"UTC"` (or `"Z"`), `"local"` for the
+//! host's offset at that instant, or an offset like `"+02:00"`.
+//!
+//! Subtracting datetimes gives a duration (`now() - started`), a datetime
+//! plus or minus a duration is a datetime, durations add up and scale by
+//! numbers, and datetimes and durations compare with each other.
+
+use std::sync::Arc;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, SecondsFormat, TimeDelta, TimeZone, Utc};
+use parking_lot::RwLock;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::token::TokenKind;
+use crate::value::{NumberFormat, Value, ValueKind};
+
+/// Fallback patterns `time.parse` tries, after RFC 3339, when given none.
+const NAIVE_PATTERNS: &[&str] = &["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S%.f"];
+
+/// The offset `zone` stands for at the instant `at`.
+pub fn zone(name: &str, at: DateTime<Utc>) -> Result<FixedOffset> {
+    match name {
+        "UTC" | "utc" | "Z" => Ok(FixedOffset::east_opt(0).expect("zero offset")),
+        "local" => Ok(at.with_timezone(&Local).fixed_offset().timezone()),
+        offset => offset
+            .parse::<FixedOffset>()
+            .map_err(|_| PrismError::InvalidArgument(format!("Unknown zone '{}'; expected UTC, local or an offset like +02:00", name))),
+    }
+}
+
+/// The current time, in `zone` or UTC.
+pub fn now(zone_name: Option<&str>) -> Result<DateTime<FixedOffset>> {
+    let now = Utc::now();
+    Ok(now.with_timezone(&zone(zone_name.unwrap_or("UTC"), now)?))
+}
+
+/// Reads `text` as RFC 3339, or with `pattern` (strftime syntax). Text
+/// without an offset is taken to be in `zone`, UTC by default.
+pub fn parse(text: &str, pattern: Option<&str>, zone_name: Option<&str>) -> Result<DateTime<FixedOffset>> {
+    let text = text.trim();
+    let invalid = || PrismError::InvalidArgument(format!("cannot read \"{}\" as a datetime", text));
+    let with_offset = match pattern {
+        Some(pattern) => DateTime::parse_from_str(text, pattern).ok(),
+        None => DateTime::parse_from_rfc3339(text).ok(),
+    };
+    if let Some(datetime) = with_offset {
+        return Ok(datetime);
+    }
+    let patterns = pattern.map_or(NAIVE_PATTERNS.to_vec(), |pattern| vec![pattern]);
+    let naive = patterns
+        .iter()
+        .find_map(|pattern| NaiveDateTime::parse_from_str(text, pattern).ok())
+        .or_else(|| {
+            let date = NaiveDate::parse_from_str(text, pattern.unwrap_or("%Y-%m-%d")).ok()?;
+            date.and_hms_opt(0, 0, 0)
+        })
+        .ok_or_else(invalid)?;
+    let offset = zone(zone_name.unwrap_or("UTC"), naive.and_utc())?;
+    // Local offsets are looked up at the instant read as UTC; close enough
+    // outside the hour around a daylight saving change
+    offset.from_local_datetime(&naive).single().ok_or_else(invalid)
+}
+
+/// The instant `seconds` after the Unix epoch, in `zone` or UTC.
+pub fn from_timestamp(seconds: f64, zone_name: Option<&str>) -> Result<DateTime<FixedOffset>> {
+    let whole = seconds.floor();
+    let utc = DateTime::from_timestamp(whole as i64, ((seconds - whole) * 1e9) as u32)
+        .ok_or_else(|| PrismError::InvalidArgument(format!("timestamp {} is out of range", seconds)))?;
+    Ok(utc.with_timezone(&zone(zone_name.unwrap_or("UTC"), utc)?))
+}
+
+/// Seconds since the Unix epoch.
+pub fn timestamp(datetime: &DateTime<FixedOffset>) -> f64 {
+    datetime.timestamp() as f64 + f64::from(datetime.timestamp_subsec_nanos()) / 1e9
+}
+
+/// How a datetime is written: RFC 3339, with `Z` for UTC.
+pub fn format_datetime(datetime: &DateTime<FixedOffset>) -> String {
+    datetime.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+/// The length of `duration` in seconds.
+pub fn seconds(duration: &TimeDelta) -> f64 {
+    duration.num_seconds() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+/// A duration `seconds` long, to the nanosecond.
+pub fn from_seconds(seconds: f64) -> Result<TimeDelta> {
+    let nanos = (seconds * 1e9).round();
+    if !nanos.is_finite() || nanos.abs() >= i64::MAX as f64 {
+        return Err(PrismError::InvalidArgument(format!("{} seconds is out of range for a duration", seconds)));
+    }
+    Ok(TimeDelta::nanoseconds(nanos as i64))
+}
+
+/// How a duration is written: days, hours, minutes and seconds, e.g.
+/// `1d 2h 30m`, `1.5s` or `-45m`.
+pub fn format_duration(duration: &TimeDelta) -> String {
+    if duration.is_zero() {
+        return "0s".to_string();
+    }
+    let sign = if *duration < TimeDelta::zero() { "-" } else { "" };
+    let magnitude = duration.abs();
+    let total = magnitude.num_seconds();
+    let mut parts = Vec::new();
+    for (amount, unit) in [(total / 86_400, "d"), (total / 3_600 % 24, "h"), (total / 60 % 60, "m")] {
+        if amount != 0 {
+            parts.push(format!("{}{}", amount, unit));
+        }
+    }
+    let nanos = magnitude.subsec_nanos();
+    if total % 60 != 0 || nanos != 0 {
+        let seconds = (total % 60) as f64 + f64::from(nanos) / 1e9;
+        parts.push(format!("{}s", NumberFormat::default().format(seconds)));
+    }
+    format!("{}{}", sign, parts.join(" "))
+}
+
+/// Reads a duration written as [`format_duration`] writes it; `ms` also
+/// counts as a unit.
+pub fn parse_duration(text: &str) -> Result<TimeDelta> {
+    let invalid = || PrismError::InvalidArgument(format!("cannot read \"{}\" as a duration; expected e.g. 1h 30m", text));
+    let trimmed = text.trim();
+    let (negative, rest) = match trimmed.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed),
+    };
+    let mut total = 0.0;
+    let mut rest = rest.trim_start();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let number_end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).ok_or_else(invalid)?;
+        let amount: f64 = rest[..number_end].parse().map_err(|_| invalid())?;
+        let unit_end = rest[number_end..].find(|c: char| !c.is_ascii_alphabetic()).map_or(rest.len(), |end| number_end + end);
+        let scale = match &rest[number_end..unit_end] {
+            "d" => 86_400.0,
+            "h" => 3_600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return Err(invalid()),
+        };
+        total += amount * scale;
+        rest = rest[unit_end..].trim_start();
+    }
+    from_seconds(if negative { -total } else { total })
+}
+
+/// The result of a binary operator with a datetime or duration operand.
+pub fn binary(operator: &TokenKind, left: &ValueKind, right: &ValueKind) -> Result<ValueKind> {
+    use ValueKind::{DateTime as At, Duration as Span, Number};
+    let out_of_range = || PrismError::RuntimeError("datetime out of range".to_string());
+    Ok(match (left, operator, right) {
+        (At(l), TokenKind::Minus, At(r)) => Span(l.signed_duration_since(r)),
+        (At(l), TokenKind::Plus, Span(r)) | (Span(r), TokenKind::Plus, At(l)) => {
+            At(l.checked_add_signed(*r).ok_or_else(out_of_range)?)
+        }
+        (At(l), TokenKind::Minus, Span(r)) => At(l.checked_sub_signed(*r).ok_or_else(out_of_range)?),
+        (Span(l), TokenKind::Plus, Span(r)) => Span(l.checked_add(r).ok_or_else(out_of_range)?),
+        (Span(l), TokenKind::Minus, Span(r)) => Span(l.checked_sub(r).ok_or_else(out_of_range)?),
+        (Span(l), TokenKind::Star, Number(r)) | (Number(r), TokenKind::Star, Span(l)) => Span(from_seconds(seconds(l) * r)?),
+        (Span(_), TokenKind::Slash, Number(r)) if *r == 0.0 => {
+            return Err(PrismError::RuntimeError("Division by zero".to_string()))
+        }
+        (Span(l), TokenKind::Slash, Number(r)) => Span(from_seconds(seconds(l) / r)?),
+        (Span(l), TokenKind::Slash, Span(r)) => Number(seconds(l) / seconds(r)),
+        (At(l), _, At(r)) => compare(operator, l.cmp(r))?,
+        (Span(l), _, Span(r)) => compare(operator, l.cmp(r))?,
+        (_, TokenKind::EqualEqual, _) => ValueKind::Boolean(left == right),
+        (_, TokenKind::BangEqual, _) => ValueKind::Boolean(left != right),
+        _ => {
+            return Err(PrismError::RuntimeError(format!(
+                "Invalid operation between {:?} and {:?}",
+                left, right
+            )))
+        }
+    })
+}
+
+fn compare(operator: &TokenKind, ordering: std::cmp::Ordering) -> Result<ValueKind> {
+    Ok(ValueKind::Boolean(match operator {
+        TokenKind::Greater => ordering.is_gt(),
+        TokenKind::GreaterEqual => ordering.is_ge(),
+        TokenKind::Less => ordering.is_lt(),
+        TokenKind::LessEqual => ordering.is_le(),
+        TokenKind::EqualEqual => ordering.is_eq(),
+        TokenKind::BangEqual => ordering.is_ne(),
+        _ => return Err(PrismError::RuntimeError("Invalid operator for times".to_string())),
+    }))
+}
+
+fn datetime_arg(name: &str, arg: Option<&Value>) -> Result<DateTime<FixedOffset>> {
+    match arg.map(|arg| &arg.kind) {
+        Some(ValueKind::DateTime(datetime)) => Ok(*datetime),
+        Some(ValueKind::String(text)) => parse(text, None, None),
+        _ => Err(PrismError::InvalidArgument(format!("time.{} expects a datetime", name))),
+    }
+}
+
+fn optional_string<'a>(name: &str, arg: Option<&'a Value>, what: &str) -> Result<Option<&'a str>> {
+    match arg.map(|arg| &arg.kind) {
+        None | Some(ValueKind::Nil) => Ok(None),
+        Some(ValueKind::String(text)) => Ok(Some(text)),
+        Some(_) => Err(PrismError::InvalidArgument(format!("time.{} expects {} as a string", name, what))),
+    }
+}
+
+fn native(name: &str, handler: impl Fn(&[Value]) -> Result<ValueKind> + Send + Sync + 'static) -> Value {
+    Value::new(ValueKind::NativeFunction {
+        name: name.to_string(),
+        arity: 1,
+        handler: Arc::new(move |_, args| {
+            let confidence = args.first().map_or(1.0, |arg| arg.confidence);
+            Ok(Value::with_confidence(handler(&args)?, confidence))
+        }),
+    })
+}
+
+/// The `now(zone?)` native, also in the prelude.
+pub fn now_fn() -> Value {
+    Value::new(ValueKind::NativeFunction {
+        name: "now".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| Ok(Value::new(ValueKind::DateTime(now(optional_string("now", args.first(), "a zone")?)?)))),
+    })
+}
+
+pub fn init_time_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("time".to_string())));
+    {
+        let mut module = module.write();
+        module.export("now".to_string(), now_fn())?;
+        module.export(
+            "parse".to_string(),
+            native("parse", |args| {
+                let Some(ValueKind::String(text)) = args.first().map(|arg| &arg.kind) else {
+                    return Err(PrismError::InvalidArgument("time.parse expects text".to_string()));
+                };
+                let pattern = optional_string("parse", args.get(1), "a pattern")?;
+                let zone = optional_string("parse", args.get(2), "a zone")?;
+                Ok(ValueKind::DateTime(parse(text, pattern, zone)?))
+            }),
+        )?;
+        module.export(
+            "from_timestamp".to_string(),
+            native("from_timestamp", |args| {
+                let Some(ValueKind::Number(seconds)) = args.first().map(|arg| &arg.kind) else {
+                    return Err(PrismError::InvalidArgument("time.from_timestamp expects Unix seconds".to_string()));
+                };
+                let zone = optional_string("from_timestamp", args.get(1), "a zone")?;
+                Ok(ValueKind::DateTime(from_timestamp(*seconds, zone)?))
+            }),
+        )?;
+        module.export(
+            "timestamp".to_string(),
+            native("timestamp", |args| Ok(ValueKind::Number(timestamp(&datetime_arg("timestamp", args.first())?)))),
+        )?;
+        module.export(
+            "in_zone".to_string(),
+            native("in_zone", |args| {
+                let datetime = datetime_arg("in_zone", args.first())?;
+                let Some(name) = optional_string("in_zone", args.get(1), "a zone")? else {
+                    return Err(PrismError::InvalidArgument("time.in_zone expects a zone".to_string()));
+                };
+                Ok(ValueKind::DateTime(datetime.with_timezone(&zone(name, datetime.to_utc())?)))
+            }),
+        )?;
+        module.export(
+            "duration".to_string(),
+            native("duration", |args| {
+                Ok(ValueKind::Duration(match args.first().map(|arg| &arg.kind) {
+                    Some(ValueKind::Number(seconds)) => from_seconds(*seconds)?,
+                    Some(ValueKind::String(text)) => parse_duration(text)?,
+                    Some(ValueKind::Duration(duration)) => *duration,
+                    _ => return Err(PrismError::InvalidArgument(
+                        "time.duration expects seconds or text such as \"1h 30m\"".to_string(),
+                    )),
+                }))
+            }),
+        )?;
+        module.export(
+            "seconds".to_string(),
+            native("seconds", |args| match args.first().map(|arg| &arg.kind) {
+                Some(ValueKind::Duration(duration)) => Ok(ValueKind::Number(seconds(duration))),
+                _ => Err(PrismError::InvalidArgument("time.seconds expects a duration".to_string())),
+            }),
+        )?;
+    }
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn test_datetimes_parse_in_zones() -> Result<()> {
+        let at = parse("2026-10-16T09:30:00+02:00", None, None)?;
+        assert_eq!(format_datetime(&at), "2026-10-16T09:30:00+02:00");
+        assert_eq!(format_datetime(&at.with_timezone(&zone("UTC", at.to_utc())?)), "2026-10-16T07:30:00Z");
+        assert_eq!(format_datetime(&parse("2026-10-16 09:30", None, Some("-05:00"))?), "2026-10-16T09:30:00-05:00");
+        assert_eq!(format_datetime(&parse("16/10/2026", Some("%d/%m/%Y"), None)?), "2026-10-16T00:00:00Z");
+        assert_eq!(timestamp(&from_timestamp(1_700_000_000.5, None)?), 1_700_000_000.5);
+        assert!(parse("yesterday", None, None).is_err());
+        assert!(zone("Mars/Olympus", Utc::now()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_durations_round_trip_through_text() -> Result<()> {
+        for text in ["0s", "1h 30m", "1d 2h 3m 4.5s", "-45m", "0.25s", "2d"] {
+            assert_eq!(format_duration(&parse_duration(text)?), text);
+        }
+        assert_eq!(format_duration(&parse_duration("90m 250ms")?), "1h 30m 0.25s");
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("5 parsecs").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_datetimes_and_durations_do_arithmetic() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let started = time.parse("2026-10-16T09:00:00+02:00");
+            let ended = started + time.duration("1h 30m");
+            let taken = ended - started;
+            [str(ended), str(taken), ended > started, type(taken), str(time.in_zone(ended, "UTC")),
+             time.seconds(taken / 2), str(taken * 2 - time.duration(600)), now() - started > taken];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(
+            result.to_string(),
+            "[2026-10-16T10:30:00+02:00, 1h 30m, true, duration, 2026-10-16T08:30:00Z, 2700, 2h 50m, true]"
+        );
+        let json = interpreter.evaluate(r#"[time.from_timestamp(0), -time.duration(90)];"#.to_string()).await?;
+        assert_eq!(json.to_json()?, serde_json::json!(["1970-01-01T00:00:00Z", "-1m 30s"]));
+        assert!(interpreter.evaluate("now() + 1;".to_string()).await.is_err());
+        Ok(())
+    }
+}
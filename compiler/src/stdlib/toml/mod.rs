@@ -0,0 +1,81 @@
+use std::sync::Arc;
+use parking_lot::RwLock;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{Value, ValueKind};
+
+pub fn init_toml_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("toml".to_string())));
+
+    // parse function: tables become maps and arrays lists; dates are strings
+    let parse_fn = Value::new(ValueKind::NativeFunction {
+        name: "parse".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let text = match args.first().map(|arg| &arg.kind) {
+                Some(ValueKind::String(text)) => text,
+                _ => return Err(PrismError::InvalidArgument("toml.parse expects a string".to_string())),
+            };
+            let table: ::toml::Table = text
+                .parse()
+                .map_err(|err| PrismError::ParseError(format!("TOML: {}", err)))?;
+            Ok(Value::from_json(&to_json(::toml::Value::Table(table))))
+        }),
+    });
+
+    // stringify function: the value must be a map, since a TOML document is a table
+    let stringify_fn = Value::new(ValueKind::NativeFunction {
+        name: "stringify".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let value = args.first().cloned().unwrap_or_else(|| Value::new(ValueKind::Nil));
+            let text = ::toml::to_string(&value.to_json()?)
+                .map_err(|err| PrismError::InvalidOperation(format!("TOML: {}", err)))?;
+            Ok(Value::new(ValueKind::String(text)))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("parse".to_string(), parse_fn)?;
+        module.export("stringify".to_string(), stringify_fn)?;
+    }
+
+    Ok(module)
+}
+
+/// TOML has datetimes, which JSON and Prism lack; they become strings.
+fn to_json(value: ::toml::Value) -> serde_json::Value {
+    match value {
+        ::toml::Value::String(s) => serde_json::Value::String(s),
+        ::toml::Value::Integer(i) => serde_json::Value::from(i),
+        ::toml::Value::Float(f) => serde_json::Value::from(f),
+        ::toml::Value::Boolean(b) => serde_json::Value::Bool(b),
+        ::toml::Value::Datetime(datetime) => serde_json::Value::String(datetime.to_string()),
+        ::toml::Value::Array(items) => serde_json::Value::Array(items.into_iter().map(to_json).collect()),
+        ::toml::Value::Table(table) => serde_json::Value::Object(
+            table.into_iter().map(|(key, value)| (key, to_json(value))).collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Result;
+    use crate::interpreter::Interpreter;
+
+    #[tokio::test]
+    async fn test_toml_round_trip() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let config = toml.parse("[model]
+name = 'gpt'
+temperature = 0.2");
+            [config.model.name, config.model.temperature, toml.stringify({ retries: 3 })];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[gpt, 0.2, retries = 3\n]");
+        assert!(interpreter.evaluate("toml.stringify([1]);".to_string()).await.is_err());
+        Ok(())
+    }
+}
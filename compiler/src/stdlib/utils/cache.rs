@@ -0,0 +1,186 @@
+//! `utils.memo(fn)` and `utils.cache(fn, {ttl})`: memoized functions.
+//!
+//! The result is a map of functions sharing one cache: `call(args...)`
+//! returns the cached result for equal arguments (same values and
+//! confidences) or calls `fn`, `stats()` is `{hits, misses, size}`,
+//! `invalidate(args...)` forgets one entry and `clear()` all of them.
+//! Results of `cache` expire `ttl` seconds after they were computed. Only
+//! pure functions should be memoized: a cached call does not run `fn`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use crate::error::{PrismError, Result};
+use crate::value::{Value, ValueKind};
+use super::{callable, seconds};
+
+#[derive(Default)]
+struct Cache {
+    ttl: Option<Duration>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<String, (Instant, Value)>,
+    hits: usize,
+    misses: usize,
+}
+
+impl Cache {
+    fn get(&self, key: &str) -> Option<Value> {
+        let mut state = self.state.lock();
+        let fresh = match state.entries.get(key) {
+            Some((stored, _)) => self.ttl.is_none_or(|ttl| stored.elapsed() < ttl),
+            None => false,
+        };
+        if !fresh {
+            state.entries.remove(key);
+            state.misses += 1;
+            return None;
+        }
+        state.hits += 1;
+        state.entries.get(key).map(|(_, value)| value.clone())
+    }
+
+    fn insert(&self, key: String, value: Value) {
+        self.state.lock().entries.insert(key, (Instant::now(), value));
+    }
+}
+
+/// Arguments as a cache key; only data can be compared this way.
+fn key(args: &[Value]) -> Result<String> {
+    let parts = args
+        .iter()
+        .map(|arg| Ok(serde_json::json!([arg.to_json()?, arg.confidence])))
+        .collect::<Result<Vec<_>>>()
+        .map_err(|_| PrismError::InvalidArgument("memoized functions only take data arguments".to_string()))?;
+    Ok(serde_json::Value::Array(parts).to_string())
+}
+
+pub fn memo_fn() -> Value {
+    Value::new(ValueKind::NativeFunction {
+        name: "memo".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let callee = callable(&args, "utils.memo")?;
+            Ok(handles(callee, Arc::new(Cache::default())))
+        }),
+    })
+}
+
+pub fn cache_fn() -> Value {
+    Value::new(ValueKind::NativeFunction {
+        name: "cache".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let callee = callable(&args, "utils.cache")?;
+            let ttl = match args.get(1).map(|arg| &arg.kind) {
+                None | Some(ValueKind::Nil) => None,
+                Some(ValueKind::Map(entries)) => match entries.iter().find(|(key, _)| key.to_string() == "ttl") {
+                    None => None,
+                    Some((_, Value { kind: ValueKind::Number(secs), .. })) => Some(seconds(*secs)?),
+                    Some((_, value)) => {
+                        return Err(PrismError::InvalidArgument(format!("option ttl must be a number, got {}", value)))
+                    }
+                },
+                Some(_) => return Err(PrismError::InvalidArgument("expected an options map".to_string())),
+            };
+            Ok(handles(callee, Arc::new(Cache { ttl, ..Cache::default() })))
+        }),
+    })
+}
+
+fn handles(callee: Value, cache: Arc<Cache>) -> Value {
+    let entry = |name: &str, value: Value| (Value::new(ValueKind::String(name.to_string())), value);
+    let number = |n: usize| Value::new(ValueKind::Number(n as f64));
+
+    let calls = Arc::clone(&cache);
+    let call = Value::new(ValueKind::AsyncNativeFunction {
+        name: "call".to_string(),
+        arity: 0,
+        handler: Arc::new(move |interpreter, args| {
+            let cache = Arc::clone(&calls);
+            let callee = callee.clone();
+            Box::pin(async move {
+                let key = key(&args)?;
+                let cached = cache.get(&key);
+                interpreter.metrics().record_cache_lookup(cached.is_some());
+                if let Some(value) = cached {
+                    return Ok(value);
+                }
+                let value = interpreter.call(callee, args).await?;
+                cache.insert(key, value.clone());
+                Ok(value)
+            })
+        }),
+    });
+
+    let counted = Arc::clone(&cache);
+    let stats = Value::new(ValueKind::NativeFunction {
+        name: "stats".to_string(),
+        arity: 0,
+        handler: Arc::new(move |_, _| {
+            let state = counted.state.lock();
+            Ok(Value::new(ValueKind::Map(vec![
+                entry("hits", number(state.hits)),
+                entry("misses", number(state.misses)),
+                entry("size", number(state.entries.len())),
+            ])))
+        }),
+    });
+
+    let invalidated = Arc::clone(&cache);
+    let invalidate = Value::new(ValueKind::NativeFunction {
+        name: "invalidate".to_string(),
+        arity: 0,
+        handler: Arc::new(move |_, args| {
+            let removed = invalidated.state.lock().entries.remove(&key(&args)?).is_some();
+            Ok(Value::new(ValueKind::Boolean(removed)))
+        }),
+    });
+
+    let clear = Value::new(ValueKind::NativeFunction {
+        name: "clear".to_string(),
+        arity: 0,
+        handler: Arc::new(move |_, _| {
+            cache.state.lock().entries.clear();
+            Ok(Value::new(ValueKind::Nil))
+        }),
+    });
+
+    Value::new(ValueKind::Map(vec![
+        entry("call", call),
+        entry("stats", stats),
+        entry("invalidate", invalidate),
+        entry("clear", clear),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expired_entries_are_misses() {
+        let cache = Cache { ttl: Some(Duration::ZERO), ..Cache::default() };
+        cache.insert("k".to_string(), Value::new(ValueKind::Nil));
+        assert!(cache.get("k").is_none());
+
+        let cache = Cache::default();
+        cache.insert("k".to_string(), Value::new(ValueKind::Number(1.0)));
+        assert!(cache.get("k").is_some());
+        let state = cache.state.lock();
+        assert_eq!((state.hits, state.misses, state.entries.len()), (1, 0, 1));
+    }
+
+    #[test]
+    fn test_key_includes_confidence() -> Result<()> {
+        let certain = Value::new(ValueKind::String("flu".to_string()));
+        let unsure = Value::with_confidence(ValueKind::String("flu".to_string()), 0.5);
+        assert_ne!(key(&[certain.clone(), certain.clone()])?, key(&[certain.clone(), unsure])?);
+        assert_eq!(key(&[certain.clone(), certain.clone()])?, key(&[certain.clone(), certain])?);
+        Ok(())
+    }
+}
@@ -1,37 +1,223 @@
 use std::sync::Arc;
-use parking_lot::RwLock;
-use std::time::Duration;
-use crate::error::Result;
+use std::time::{Duration, Instant};
+use parking_lot::{Mutex, RwLock};
+use crate::docs::FunctionDoc;
+use crate::error::{PrismError, Result};
 use crate::module::Module;
 use crate::value::{Value, ValueKind};
 
+mod cache;
+
 pub fn init_utils_module() -> Result<Arc<RwLock<Module>>> {
     let module = Arc::new(RwLock::new(Module::new("utils".to_string())));
 
-    // sleep function
-    let sleep_fn = Value::new(ValueKind::NativeFunction {
+    // sleep function: waits without blocking other tasks on the runtime
+    let sleep_fn = Value::new(ValueKind::AsyncNativeFunction {
         name: "sleep".to_string(),
         arity: 1,
-        handler: Arc::new(|args| {
-            if let Some(arg) = args.first() {
-                match &arg.kind {
-                    ValueKind::Number(seconds) => {
-                        let duration = Duration::from_secs_f64(*seconds);
-                        std::thread::sleep(duration);
-                        Ok(Value::new(ValueKind::Nil))
+        handler: Arc::new(|interpreter, args| Box::pin(async move {
+            let duration = seconds(number(args.first(), "utils.sleep")?)?;
+            interpreter.sleep(duration).await?;
+            Ok(Value::new(ValueKind::Nil))
+        })),
+    });
+
+    // retry function: retry(fn, { attempts: 3, backoff: 0.5 }) calls `fn` until
+    // it succeeds, waiting `backoff` seconds after the first failure and twice
+    // as long after each further one
+    let retry_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "retry".to_string(),
+        arity: 2,
+        handler: Arc::new(|interpreter, args| Box::pin(async move {
+            let callee = callable(&args, "utils.retry")?;
+            let attempts = option(&args, "attempts", 3.0)?;
+            let mut backoff = option(&args, "backoff", 0.0)?;
+            if attempts < 1.0 || backoff < 0.0 {
+                return Err(PrismError::InvalidArgument(
+                    "utils.retry needs at least one attempt and a non-negative backoff".to_string(),
+                ));
+            }
+
+            let attempts = attempts as usize;
+            for attempt in 1..=attempts {
+                match interpreter.call(callee.clone(), Vec::new()).await {
+                    // Cancellation is the host's decision, not a flaky failure
+                    Err(err @ PrismError::Cancelled(_)) => return Err(err),
+                    Err(err) if attempt == attempts => {
+                        return Err(PrismError::RuntimeError(format!(
+                            "utils.retry gave up after {} attempts: {}",
+                            attempts, err
+                        )))
+                    }
+                    Err(err) => {
+                        log::debug!("utils.retry attempt {} failed: {}", attempt, err);
+                        interpreter.sleep(seconds(backoff)?).await?;
+                        backoff *= 2.0;
                     }
-                    _ => Ok(Value::new(ValueKind::Nil)),
+                    Ok(value) => return Ok(value),
                 }
-            } else {
-                Ok(Value::new(ValueKind::Nil))
             }
+            unreachable!("the last attempt returns")
+        })),
+    });
+
+    // timeout function: timeout(fn, secs) is fn's result, or nil when it did
+    // not finish in time. Like host cancellation, the limit is checked at
+    // calls, loop iterations and sleeps.
+    let timeout_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "timeout".to_string(),
+        arity: 2,
+        handler: Arc::new(|interpreter, args| Box::pin(async move {
+            let callee = callable(&args, "utils.timeout")?;
+            let limit = seconds(number(args.get(1), "utils.timeout")?)?;
+            let result = interpreter
+                .with_time_limit(limit, move |interpreter| Box::pin(interpreter.call(callee, Vec::new())))
+                .await?;
+            Ok(result.unwrap_or_else(|| Value::with_confidence(ValueKind::Nil, 0.0)))
+        })),
+    });
+
+    // throttle function: throttle(fn, secs) returns a function that calls fn
+    // at most once every `secs` seconds; calls in between return fn's last
+    // result again
+    let throttle_fn = Value::new(ValueKind::NativeFunction {
+        name: "throttle".to_string(),
+        arity: 2,
+        handler: Arc::new(|_, args| {
+            let callee = callable(&args, "utils.throttle")?;
+            let window = seconds(number(args.get(1), "utils.throttle")?)?;
+            let state: Arc<Mutex<(Option<Instant>, Value)>> =
+                Arc::new(Mutex::new((None, Value::new(ValueKind::Nil))));
+            Ok(Value::new(ValueKind::AsyncNativeFunction {
+                name: "throttled".to_string(),
+                arity: 0,
+                handler: Arc::new(move |interpreter, args| {
+                    let callee = callee.clone();
+                    let state = Arc::clone(&state);
+                    Box::pin(async move {
+                        let now = Instant::now();
+                        {
+                            let mut state = state.lock();
+                            if state.0.is_some_and(|last| now.duration_since(last) < window) {
+                                return Ok(state.1.clone());
+                            }
+                            state.0 = Some(now);
+                        }
+                        let value = interpreter.call(callee, args).await?;
+                        state.lock().1 = value.clone();
+                        Ok(value)
+                    })
+                }),
+            }))
         }),
     });
 
+    // interval function: interval(fn, secs, times) calls fn every `secs`
+    // seconds until it returns false, it ran `times` times (if given) or the
+    // evaluation is cancelled; returns how often it ran
+    let interval_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "interval".to_string(),
+        arity: 3,
+        handler: Arc::new(|interpreter, args| Box::pin(async move {
+            let callee = callable(&args, "utils.interval")?;
+            let period = seconds(number(args.get(1), "utils.interval")?)?;
+            let times = match args.get(2).map(|arg| &arg.kind) {
+                None | Some(ValueKind::Nil) => None,
+                Some(ValueKind::Number(times)) if *times >= 0.0 => Some(*times as usize),
+                Some(_) => return Err(PrismError::InvalidArgument(
+                    "utils.interval expects a non-negative number of times".to_string(),
+                )),
+            };
+
+            let mut runs = 0;
+            while times.is_none_or(|times| runs < times) {
+                let started = Instant::now();
+                let value = interpreter.call(callee.clone(), Vec::new()).await?;
+                runs += 1;
+                if matches!(value.kind, ValueKind::Boolean(false)) || times == Some(runs) {
+                    break;
+                }
+                interpreter.sleep(period.saturating_sub(started.elapsed())).await?;
+            }
+            Ok(Value::new(ValueKind::Number(runs as f64)))
+        })),
+    });
+
     {
         let mut module = module.write();
-        module.export("sleep".to_string(), sleep_fn)?;
+        module.export_documented("sleep", sleep_fn, FunctionDoc::new(
+            &["secs"],
+            "Waits without blocking other tasks.",
+        ).with_example("utils.sleep(0.5);"))?;
+        module.export_documented("retry", retry_fn, FunctionDoc::new(
+            &["fn", "options"],
+            "Calls fn until it succeeds and returns its result.",
+        )
+        .with_param("options", "{attempts: 3, backoff: 0}; the wait after a failure starts at backoff seconds and doubles")
+        .with_example("let reply = utils.retry(ask, { attempts: 5, backoff: 0.5 });"))?;
+        module.export_documented("timeout", timeout_fn, FunctionDoc::new(
+            &["fn", "secs"],
+            "fn's result, or nil ~> 0 when it did not finish in time.",
+        ).with_example("let reply = utils.timeout(ask, 10);"))?;
+        module.export_documented("throttle", throttle_fn, FunctionDoc::new(
+            &["fn", "secs"],
+            "A function that calls fn at most once every secs seconds and otherwise repeats fn's last result.",
+        ).with_example("let refresh = utils.throttle(reload, 2);"))?;
+        module.export_documented("interval", interval_fn, FunctionDoc::new(
+            &["fn", "secs", "times"],
+            "Calls fn every secs seconds and returns how often it ran.",
+        )
+        .with_param("fn", "returning false stops the interval")
+        .with_param("times", "the most runs, or nil for no limit")
+        .with_example("utils.interval(poll, 60, 10);"))?;
+        module.export_documented("memo", cache::memo_fn(), FunctionDoc::new(
+            &["fn"],
+            "Memoizes a pure function: {call, stats, invalidate, clear}.",
+        ).with_example("let lookup = utils.memo(slow_lookup).call;"))?;
+        module.export_documented("cache", cache::cache_fn(), FunctionDoc::new(
+            &["fn", "options"],
+            "Like memo, but results expire.",
+        )
+        .with_param("options", "{ttl: secs}, how long a result is reused")
+        .with_example("let rates = utils.cache(fetch_rates, { ttl: 60 }).call;"))?;
     }
 
     Ok(module)
 }
+
+fn callable(args: &[Value], name: &str) -> Result<Value> {
+    match args.first() {
+        Some(callee @ Value {
+            kind: ValueKind::Function { .. } | ValueKind::NativeFunction { .. } | ValueKind::AsyncNativeFunction { .. },
+            ..
+        }) => Ok(callee.clone()),
+        _ => Err(PrismError::InvalidArgument(format!("{} expects a function", name))),
+    }
+}
+
+fn number(arg: Option<&Value>, name: &str) -> Result<f64> {
+    match arg.map(|arg| &arg.kind) {
+        Some(ValueKind::Number(n)) => Ok(*n),
+        _ => Err(PrismError::InvalidArgument(format!("{} expects a number of seconds", name))),
+    }
+}
+
+fn seconds(secs: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(secs)
+        .map_err(|_| PrismError::InvalidArgument(format!("{} is not a valid number of seconds", secs)))
+}
+
+/// A number from the options map in the second argument, or `default`.
+fn option(args: &[Value], name: &str, default: f64) -> Result<f64> {
+    let entries = match args.get(1).map(|arg| &arg.kind) {
+        None | Some(ValueKind::Nil) => return Ok(default),
+        Some(ValueKind::Map(entries)) => entries,
+        Some(_) => return Err(PrismError::InvalidArgument("expected an options map".to_string())),
+    };
+    match entries.iter().find(|(key, _)| key.to_string() == name) {
+        None => Ok(default),
+        Some((_, Value { kind: ValueKind::Number(n), .. })) => Ok(*n),
+        Some((_, value)) => Err(PrismError::InvalidArgument(format!("option {} must be a number, got {}", name, value))),
+    }
+}
+
@@ -0,0 +1,176 @@
+//! `ws.connect(url)`: websocket client, behind the `websocket` cargo feature.
+//!
+//! The connection is a map of functions: `send(message)` sends text (other
+//! values are sent as their JSON), `recv()` waits for the next message and is
+//! `nil` once the server closed the connection, and `close()` closes it.
+//! Connecting requires the `net` capability.
+
+use std::sync::Arc;
+use std::time::Duration;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use parking_lot::RwLock;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use crate::capabilities::Capability;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+use crate::module::Module;
+use crate::value::{AsyncNativeHandler, Value, ValueKind};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// How long `recv` waits before it checks for cancellation again.
+const WAIT_SLICE: Duration = Duration::from_millis(10);
+
+struct Connection {
+    sink: Mutex<SplitSink<Socket, Message>>,
+    stream: Mutex<SplitStream<Socket>>,
+}
+
+impl Connection {
+    async fn send(&self, message: Message) -> Result<()> {
+        self.sink.lock().await.send(message).await.map_err(ws_error)
+    }
+
+    async fn recv(&self, interpreter: &mut Interpreter) -> Result<Value> {
+        let mut stream = self.stream.lock().await;
+        loop {
+            // `next` is cancel-safe, so giving up after a slice loses nothing
+            let message = match tokio::time::timeout(WAIT_SLICE, stream.next()).await {
+                Ok(message) => message,
+                Err(_) => {
+                    interpreter.check_cancelled("websocket receive")?;
+                    continue;
+                }
+            };
+            return match message {
+                None | Some(Ok(Message::Close(_))) => Ok(Value::new(ValueKind::Nil)),
+                Some(Ok(Message::Text(text))) => Ok(Value::new(ValueKind::String(text))),
+                Some(Ok(Message::Binary(bytes))) => Ok(Value::new(ValueKind::List(
+                    bytes.iter().map(|byte| Value::new(ValueKind::Number(*byte as f64))).collect(),
+                ))),
+                // Pings are answered by the library; frames are internal
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => continue,
+                Some(Err(err)) => Err(ws_error(err)),
+            };
+        }
+    }
+}
+
+pub fn init_ws_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("ws".to_string())));
+
+    // connect function
+    let connect_fn = Value::new(ValueKind::AsyncNativeFunction {
+        name: "connect".to_string(),
+        arity: 1,
+        handler: Arc::new(|interpreter, args| Box::pin(async move {
+            interpreter.capabilities().require(Capability::Net, "ws.connect")?;
+            let url = match args.first().map(|arg| &arg.kind) {
+                Some(ValueKind::String(url)) => url.clone(),
+                _ => return Err(PrismError::InvalidArgument("ws.connect expects a URL".to_string())),
+            };
+            // wss:// needs a process-wide TLS provider; installing it twice is harmless
+            let _ = rustls::crypto::ring::default_provider().install_default();
+            let (socket, _) = tokio_tungstenite::connect_async(url.as_str()).await.map_err(ws_error)?;
+            let (sink, stream) = socket.split();
+            Ok(handles(Arc::new(Connection {
+                sink: Mutex::new(sink),
+                stream: Mutex::new(stream),
+            })))
+        })),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("connect".to_string(), connect_fn)?;
+    }
+
+    Ok(module)
+}
+
+fn handles(connection: Arc<Connection>) -> Value {
+    let function = |name: &str, arity: usize, handler: AsyncNativeHandler| {
+        (
+            Value::new(ValueKind::String(name.to_string())),
+            Value::new(ValueKind::AsyncNativeFunction { name: name.to_string(), arity, handler }),
+        )
+    };
+
+    let sender = Arc::clone(&connection);
+    let receiver = Arc::clone(&connection);
+    Value::new(ValueKind::Map(vec![
+        function("send", 1, Arc::new(move |_, args| {
+            let connection = Arc::clone(&sender);
+            Box::pin(async move {
+                let message = match args.first() {
+                    Some(Value { kind: ValueKind::String(text), .. }) => Message::text(text.clone()),
+                    Some(value) => Message::text(value.to_json()?.to_string()),
+                    None => return Err(PrismError::InvalidArgument("send expects a message".to_string())),
+                };
+                connection.send(message).await?;
+                Ok(Value::new(ValueKind::Nil))
+            })
+        })),
+        function("recv", 0, Arc::new(move |interpreter, _| {
+            let connection = Arc::clone(&receiver);
+            Box::pin(async move { connection.recv(interpreter).await })
+        })),
+        function("close", 0, Arc::new(move |_, _| {
+            let connection = Arc::clone(&connection);
+            Box::pin(async move {
+                connection.sink.lock().await.close().await.map_err(ws_error)?;
+                Ok(Value::new(ValueKind::Nil))
+            })
+        })),
+    ]))
+}
+
+fn ws_error(err: tokio_tungstenite::tungstenite::Error) -> PrismError {
+    PrismError::RuntimeError(format!("websocket: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capabilities::Capabilities;
+    use crate::error::Result;
+    use crate::interpreter::Interpreter;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_echo_round_trip() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            while let Some(Ok(message)) = socket.next().await {
+                if message.is_close() {
+                    break;
+                }
+                socket.send(message).await.unwrap();
+            }
+        });
+
+        let mut interpreter = Interpreter::new().with_capabilities(Capabilities::all());
+        let source = format!(
+            r#"
+            let feed = ws.connect("ws://{}");
+            feed.send("hello");
+            feed.send({{ score: 0.8 }});
+            let replies = [feed.recv(), feed.recv()];
+            feed.close();
+            replies;
+            "#,
+            address
+        );
+        let result = interpreter.evaluate(source).await?;
+        assert_eq!(result.to_string(), r#"[hello, {"score":0.8}]"#);
+        server.await.unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+use parking_lot::RwLock;
+use crate::error::{PrismError, Result};
+use crate::module::Module;
+use crate::value::{Value, ValueKind};
+
+pub fn init_yaml_module() -> Result<Arc<RwLock<Module>>> {
+    let module = Arc::new(RwLock::new(Module::new("yaml".to_string())));
+
+    // parse function: mappings become maps and sequences lists
+    let parse_fn = Value::new(ValueKind::NativeFunction {
+        name: "parse".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let text = match args.first().map(|arg| &arg.kind) {
+                Some(ValueKind::String(text)) => text,
+                _ => return Err(PrismError::InvalidArgument("yaml.parse expects a string".to_string())),
+            };
+            let json: serde_json::Value = serde_yaml::from_str(text)
+                .map_err(|err| PrismError::ParseError(format!("YAML: {}", err)))?;
+            Ok(Value::from_json(&json))
+        }),
+    });
+
+    // stringify function
+    let stringify_fn = Value::new(ValueKind::NativeFunction {
+        name: "stringify".to_string(),
+        arity: 1,
+        handler: Arc::new(|_, args| {
+            let value = args.first().cloned().unwrap_or_else(|| Value::new(ValueKind::Nil));
+            let text = serde_yaml::to_string(&value.to_json()?)
+                .map_err(|err| PrismError::InvalidOperation(format!("YAML: {}", err)))?;
+            Ok(Value::new(ValueKind::String(text)))
+        }),
+    });
+
+    {
+        let mut module = module.write();
+        module.export("parse".to_string(), parse_fn)?;
+        module.export("stringify".to_string(), stringify_fn)?;
+    }
+
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{PrismError, Result};
+    use crate::interpreter::Interpreter;
+
+    #[tokio::test]
+    async fn test_yaml_round_trip() -> Result<()> {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            let pipeline = yaml.parse("name: triage
+stages:
+  - classify
+  - verify
+threshold: 0.8");
+            [pipeline.stages, pipeline.threshold];
+        "#;
+        let result = interpreter.evaluate(source.to_string()).await?;
+        assert_eq!(result.to_string(), "[[classify, verify], 0.8]");
+
+        let yaml = interpreter.evaluate("yaml.stringify({ stages: [\"a\"] });".to_string()).await?;
+        assert_eq!(yaml.to_string(), "stages:\n- a\n");
+        assert!(matches!(
+            interpreter.evaluate("yaml.parse(\"a: [\");".to_string()).await.as_ref().map_err(PrismError::root),
+            Err(PrismError::ParseError(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+//! OpenTelemetry spans for evaluations and LLM requests.
+//!
+//! With the `otel` feature, [`Interpreter::evaluate`](crate::Interpreter::evaluate)
+//! opens a `prism.evaluate` span with `prism.parse`, `prism.check` and
+//! `prism.execute` children, and each LLM request a `prism.llm.request`
+//! span. Spans go to the tracer provider the host installed with
+//! `opentelemetry::global`, under the host's current span. Without the
+//! feature every function here does nothing, so call sites need no `cfg`.
+
+use std::future::Future;
+use crate::error::PrismError;
+
+/// Instrumentation scope of Prism's spans.
+pub const TRACER: &str = "prism";
+
+/// An open span; it ends when dropped.
+pub struct Span {
+    #[cfg(feature = "otel")]
+    context: opentelemetry::Context,
+}
+
+/// Starts a span under the current one.
+pub fn span(name: &'static str) -> Span {
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::trace::{TraceContextExt, Tracer};
+        let span = opentelemetry::global::tracer(TRACER).start(name);
+        Span { context: opentelemetry::Context::current_with_span(span) }
+    }
+    #[cfg(not(feature = "otel"))]
+    {
+        let _ = name;
+        Span {}
+    }
+}
+
+impl Span {
+    #[cfg(feature = "otel")]
+    pub fn set(&self, key: &'static str, value: impl Into<opentelemetry::Value>) {
+        use opentelemetry::trace::TraceContextExt;
+        self.context.span().set_attribute(opentelemetry::KeyValue::new(key, value));
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub fn set<V>(&self, _key: &'static str, _value: V) {}
+
+    /// Marks the span failed. Only the error kind is recorded, as messages
+    /// can quote script data.
+    pub fn fail(&self, err: &PrismError) {
+        #[cfg(feature = "otel")]
+        {
+            use opentelemetry::trace::{Status, TraceContextExt};
+            self.context.span().set_status(Status::error(err.kind()));
+        }
+        self.set("error.type", err.kind());
+    }
+
+    /// Ends the span before it goes out of scope.
+    pub fn end(self) {}
+
+    /// Runs `future` with this span as the current one, so spans and trace
+    /// headers created inside it are its children.
+    pub async fn run<F: Future>(&self, future: F) -> F::Output {
+        #[cfg(feature = "otel")]
+        {
+            use opentelemetry::context::FutureExt;
+            future.with_context(self.context.clone()).await
+        }
+        #[cfg(not(feature = "otel"))]
+        future.await
+    }
+}
+
+#[cfg(feature = "otel")]
+impl Drop for Span {
+    fn drop(&mut self) {
+        use opentelemetry::trace::TraceContextExt;
+        self.context.span().end();
+    }
+}
+
+/// Headers that carry the current trace into an outgoing HTTP request, e.g.
+/// `traceparent`, in the format of the host's global propagator. Providers
+/// add these to their requests; empty without the `otel` feature.
+pub fn trace_headers() -> Vec<(String, String)> {
+    #[cfg(feature = "otel")]
+    {
+        let mut headers = std::collections::HashMap::new();
+        opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&opentelemetry::Context::current(), &mut headers)
+        });
+        let mut headers: Vec<(String, String)> = headers.into_iter().collect();
+        headers.sort();
+        headers
+    }
+    #[cfg(not(feature = "otel"))]
+    Vec::new()
+}
+
+#[cfg(all(test, feature = "otel"))]
+mod tests {
+    use super::*;
+    use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+    use opentelemetry::trace::{SpanContext, TraceContextExt, TraceFlags, TraceState};
+    use opentelemetry::{Context, SpanId, TraceId};
+
+    /// Writes a W3C `traceparent`, like the SDK's propagator.
+    #[derive(Debug)]
+    struct TraceParent;
+
+    impl TextMapPropagator for TraceParent {
+        fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+            let span = cx.span();
+            let context = span.span_context();
+            if context.is_valid() {
+                let flags = context.trace_flags().to_u8();
+                injector.set(
+                    "traceparent",
+                    format!("00-{}-{}-{:02x}", context.trace_id(), context.span_id(), flags),
+                );
+            }
+        }
+
+        fn extract_with_context(&self, cx: &Context, _extractor: &dyn Extractor) -> Context {
+            cx.clone()
+        }
+
+        fn fields(&self) -> opentelemetry::propagation::text_map_propagator::FieldIter<'_> {
+            opentelemetry::propagation::text_map_propagator::FieldIter::new(&[])
+        }
+    }
+
+    #[test]
+    fn test_trace_headers_follow_the_current_span() {
+        opentelemetry::global::set_text_map_propagator(TraceParent);
+        assert!(trace_headers().is_empty());
+
+        let remote = SpanContext::new(
+            TraceId::from(0x4bf92f3577b34da6a3ce929d0e0e4736),
+            SpanId::from(0x00f067aa0ba902b7),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let _guard = Context::current().with_remote_span_context(remote).attach();
+        assert_eq!(
+            trace_headers(),
+            [("traceparent".to_string(), "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string())]
+        );
+    }
+}
@@ -1,25 +1,33 @@
 use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenKind {
     // Single-character tokens
     LeftParen, RightParen,
     LeftBrace, RightBrace,
+    LeftBracket, RightBracket,
     Comma, Dot, Minus, Plus,
     Semicolon, Slash, Star,
+    Colon, Pipe, Question, At,
 
     // One or two character tokens
     Bang, BangEqual,
     Equal, EqualEqual,
     Greater, GreaterEqual,
     Less, LessEqual,
-    Arrow,      // =>
-    Confidence, // ~>
+    Arrow,       // =>
+    ThinArrow,   // ->
+    Confidence,  // ~>
+    QuestionDot, // ?.
 
     // Literals
     Identifier(String),
     String(String),
     Number(f64),
+    /// The digits of a decimal literal such as `19.99d`.
+    Decimal(String),
 
     // Keywords
     And, Class, Else, False,
@@ -28,29 +36,62 @@ pub enum TokenKind {
     Let, While, Break, Continue,
     Import, Export, From, Module,
     In, Context, As, Async,
+    Match, Yield,
 
     EOF,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// A token and where it was read. The lexeme is a span of the shared
+/// source text rather than a copy of it, so lexing does not allocate per
+/// token.
+#[derive(Debug, Clone)]
 pub struct Token {
     pub kind: TokenKind,
-    pub lexeme: String,
+    source: Arc<str>,
+    span: Range<usize>,
     pub line: usize,
 }
 
 impl Token {
-    pub fn new(kind: TokenKind, lexeme: String, line: usize) -> Self {
+    /// A token that is not part of a source text, e.g. one built in code.
+    pub fn new(kind: TokenKind, lexeme: &str, line: usize) -> Self {
+        Self {
+            kind,
+            source: Arc::from(lexeme),
+            span: 0..lexeme.len(),
+            line,
+        }
+    }
+
+    /// The token covering `span` of `source`.
+    pub fn in_source(kind: TokenKind, source: &Arc<str>, span: Range<usize>, line: usize) -> Self {
         Self {
             kind,
-            lexeme,
+            source: Arc::clone(source),
+            span,
             line,
         }
     }
+
+    pub fn lexeme(&self) -> &str {
+        &self.source[self.span.clone()]
+    }
+
+    /// The byte range of the lexeme in its source.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+}
+
+/// Tokens are equal when they read the same, wherever they came from.
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.lexeme() == other.lexeme() && self.line == other.line
+    }
 }
 
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?} {}", self.kind, self.lexeme)
+        write!(f, "{:?} {}", self.kind, self.lexeme())
     }
 } 
\ No newline at end of file
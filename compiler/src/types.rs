@@ -7,6 +7,9 @@ use serde::{Serialize, Deserialize};
 
 use crate::interpreter::Interpreter;
 
+pub type NativeFn = Arc<dyn Fn(&mut Interpreter, Vec<Value>) -> Result<Value, Box<dyn Error>> + Send + Sync>;
+pub type AsyncNativeFn = Arc<dyn Fn(Vec<Value>) -> Pin<Box<dyn Future<Output = Result<Value, Box<dyn Error>>> + Send>> + Send + Sync>;
+
 #[derive(Clone)]
 pub enum Value {
     Void,
@@ -15,8 +18,8 @@ pub enum Value {
     Boolean(bool),
     Object(Vec<(String, Value)>),
     Array(Vec<Value>),
-    NativeFunction(Arc<dyn Fn(&mut Interpreter, Vec<Value>) -> Result<Value, Box<dyn Error>> + Send + Sync>),
-    AsyncFn(Arc<dyn Fn(Vec<Value>) -> Pin<Box<dyn Future<Output = Result<Value, Box<dyn Error>>> + Send>> + Send + Sync>),
+    NativeFunction(NativeFn),
+    AsyncFn(AsyncNativeFn),
     Tensor(Vec<f64>, Vec<usize>),
 }
 
@@ -108,7 +111,7 @@ impl Value {
     }
 
     pub fn with_confidence(&self, confidence: f64) -> Result<Value, Box<dyn Error>> {
-        if confidence < 0.0 || confidence > 1.0 {
+        if !(0.0..=1.0).contains(&confidence) {
             return Err("Confidence must be between 0 and 1".into());
         }
 
@@ -123,45 +126,45 @@ impl Value {
                 Ok(Value::Object(new_fields))
             }
             _ => {
-                let mut fields = Vec::new();
-                fields.push(("value".to_string(), self.clone()));
-                fields.push(("confidence".to_string(), Value::Float(confidence)));
-                Ok(Value::Object(fields))
+                Ok(Value::Object(vec![
+                    ("value".to_string(), self.clone()),
+                    ("confidence".to_string(), Value::Float(confidence)),
+                ]))
             }
         }
     }
+}
 
-    pub fn to_string(&self) -> String {
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Value::Void => "void".to_string(),
-            Value::Float(n) => n.to_string(),
-            Value::String(s) => s.clone(),
-            Value::Boolean(b) => b.to_string(),
+            Value::Void => write!(f, "void"),
+            Value::Float(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Boolean(b) => write!(f, "{}", b),
             Value::Object(fields) => {
-                let mut result = String::from("{");
+                write!(f, "{{")?;
                 for (i, (name, value)) in fields.iter().enumerate() {
                     if i > 0 {
-                        result.push_str(", ");
+                        write!(f, ", ")?;
                     }
-                    result.push_str(&format!("{}: {}", name, value.to_string()));
+                    write!(f, "{}: {}", name, value)?;
                 }
-                result.push('}');
-                result
+                write!(f, "}}")
             }
             Value::Array(values) => {
-                let mut result = String::from("[");
+                write!(f, "[")?;
                 for (i, value) in values.iter().enumerate() {
                     if i > 0 {
-                        result.push_str(", ");
+                        write!(f, ", ")?;
                     }
-                    result.push_str(&value.to_string());
+                    write!(f, "{}", value)?;
                 }
-                result.push(']');
-                result
+                write!(f, "]")
             }
-            Value::NativeFunction(_) => "[native function]".to_string(),
-            Value::AsyncFn(_) => "[async function]".to_string(),
-            Value::Tensor(values, shape) => format!("Tensor({:?}, {:?})", values, shape),
+            Value::NativeFunction(_) => write!(f, "[native function]"),
+            Value::AsyncFn(_) => write!(f, "[async function]"),
+            Value::Tensor(values, shape) => write!(f, "Tensor({:?}, {:?})", values, shape),
         }
     }
 }
@@ -1,8 +1,91 @@
+use std::collections::BTreeMap;
 use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
 use parking_lot::RwLock;
+use crate::ast::Stmt;
+use crate::environment::Closure;
 use crate::module::Module;
-use crate::error::Result;
+use crate::purity::SyncNodes;
+use crate::error::{PrismError, Result};
+use crate::interpreter::Interpreter;
+
+/// Largest magnitude up to which every whole `f64` is exact.
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+/// How numbers are written out. Values are rounded to `precision`
+/// significant digits and trailing zeros are trimmed, so `0.1 + 0.2` shows
+/// as `0.3` and whole numbers have no fraction. Magnitudes from 1e21 up or
+/// below 1e-7 use scientific notation, as does every number when
+/// `scientific` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberFormat {
+    pub precision: usize,
+    pub scientific: bool,
+}
+
+impl Default for NumberFormat {
+    /// Fifteen digits, the most an `f64` always holds exactly.
+    fn default() -> Self {
+        NumberFormat { precision: 15, scientific: false }
+    }
+}
+
+impl NumberFormat {
+    pub fn format(&self, n: f64) -> String {
+        if !n.is_finite() {
+            return n.to_string();
+        }
+        if n == 0.0 {
+            return "0".to_string();
+        }
+        let precision = self.precision.max(1);
+        let rounded = format!("{:.*e}", precision - 1, n);
+        let (mantissa, exponent) = rounded.split_once('e').expect("exponent notation");
+        let exponent: i32 = exponent.parse().expect("integer exponent");
+        if self.scientific || !(-7..21).contains(&exponent) {
+            return format!("{}e{}", trim_zeros(mantissa), exponent);
+        }
+        // Place the point in the rounded digits rather than formatting `n`
+        // again, which would show digits past the precision
+        let (sign, mantissa) = match mantissa.strip_prefix('-') {
+            Some(mantissa) => ("-", mantissa),
+            None => ("", mantissa),
+        };
+        let digits = mantissa.replace('.', "");
+        let text = if exponent >= 0 {
+            let point = exponent as usize + 1;
+            if digits.len() > point {
+                format!("{}.{}", &digits[..point], &digits[point..])
+            } else {
+                format!("{}{}", digits, "0".repeat(point - digits.len()))
+            }
+        } else {
+            format!("0.{}{}", "0".repeat((-exponent - 1) as usize), digits)
+        };
+        format!("{}{}", sign, trim_zeros(&text))
+    }
+}
+
+fn trim_zeros(digits: &str) -> &str {
+    if digits.contains('.') {
+        digits.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        digits
+    }
+}
+
+/// Body of a native function. It gets the calling interpreter so it can
+/// consult the granted capabilities and registered secrets.
+pub type NativeHandler = Arc<dyn Fn(&Interpreter, Vec<Value>) -> Result<Value> + Send + Sync>;
+
+/// Future returned by an [`AsyncNativeHandler`].
+pub type NativeFuture<'a> = Pin<Box<dyn Future<Output = Result<Value>> + Send + 'a>>;
+
+/// Body of a native function that needs to wait, e.g. on a timer, or to call
+/// back into script functions through [`Interpreter::call`].
+pub type AsyncNativeHandler = Arc<dyn for<'a> Fn(&'a mut Interpreter, Vec<Value>) -> NativeFuture<'a> + Send + Sync>;
 
 #[derive(Clone)]
 pub enum ValueKind {
@@ -13,16 +96,38 @@ pub enum ValueKind {
     Function {
         name: String,
         params: Vec<String>,
-        body: Arc<dyn Fn(Vec<Value>) -> Result<Value> + Send + Sync>,
+        body: Arc<Stmt>,
+        /// Which nodes of `body` run synchronously.
+        sync: Arc<SyncNodes>,
+        /// Whether `body` yields, making calls start a generator.
+        generator: bool,
+        closure: Closure,
     },
     NativeFunction {
         name: String,
         arity: usize,
-        handler: Arc<dyn Fn(Vec<Value>) -> Result<Value> + Send + Sync>,
+        handler: NativeHandler,
+    },
+    AsyncNativeFunction {
+        name: String,
+        arity: usize,
+        handler: AsyncNativeHandler,
     },
     Module(Arc<RwLock<Module>>),
     List(Vec<Value>),
     Map(Vec<(Value, Value)>),
+    /// `range(start, end, step)`: numbers produced as they are iterated.
+    Range { start: f64, end: f64, step: f64 },
+    /// A point in time shown at a UTC offset; see [`crate::stdlib::time`].
+    DateTime(chrono::DateTime<chrono::FixedOffset>),
+    /// A signed span of time.
+    Duration(chrono::TimeDelta),
+    /// An exact decimal such as `19.99d`; see [`crate::stdlib::decimal`].
+    #[cfg(feature = "decimal")]
+    Decimal(bigdecimal::BigDecimal),
+    /// A numeric vector or matrix; see [`crate::stdlib::tensor`].
+    #[cfg(feature = "tensor")]
+    Tensor(crate::stdlib::tensor::Tensor),
 }
 
 impl fmt::Debug for ValueKind {
@@ -34,6 +139,7 @@ impl fmt::Debug for ValueKind {
             ValueKind::String(s) => write!(f, "String({})", s),
             ValueKind::Function { name, .. } => write!(f, "Function({})", name),
             ValueKind::NativeFunction { name, .. } => write!(f, "NativeFunction({})", name),
+            ValueKind::AsyncNativeFunction { name, .. } => write!(f, "AsyncNativeFunction({})", name),
             ValueKind::Module(m) => {
                 let module = m.read();
                 write!(f, "Module({})", module.name)
@@ -46,6 +152,13 @@ impl fmt::Debug for ValueKind {
                 }
                 map.finish()
             }
+            ValueKind::Range { start, end, step } => write!(f, "Range({}, {}, {})", start, end, step),
+            ValueKind::DateTime(at) => write!(f, "DateTime({})", crate::stdlib::time::format_datetime(at)),
+            ValueKind::Duration(span) => write!(f, "Duration({})", crate::stdlib::time::format_duration(span)),
+            #[cfg(feature = "decimal")]
+            ValueKind::Decimal(d) => write!(f, "Decimal({})", d),
+            #[cfg(feature = "tensor")]
+            ValueKind::Tensor(t) => write!(f, "Tensor({:?})", t.shape()),
         }
     }
 }
@@ -59,8 +172,9 @@ impl PartialEq for ValueKind {
             (ValueKind::String(a), ValueKind::String(b)) => a == b,
             (ValueKind::Function { name: n1, .. }, ValueKind::Function { name: n2, .. }) => n1 == n2,
             (ValueKind::NativeFunction { name: n1, .. }, ValueKind::NativeFunction { name: n2, .. }) => n1 == n2,
+            (ValueKind::AsyncNativeFunction { name: n1, .. }, ValueKind::AsyncNativeFunction { name: n2, .. }) => n1 == n2,
             (ValueKind::Module(m1), ValueKind::Module(m2)) => {
-                Arc::ptr_eq(&m1, &m2) || {
+                Arc::ptr_eq(m1, m2) || {
                     let m1 = m1.read();
                     let m2 = m2.read();
                     m1.name == m2.name
@@ -68,16 +182,32 @@ impl PartialEq for ValueKind {
             }
             (ValueKind::List(a), ValueKind::List(b)) => a == b,
             (ValueKind::Map(a), ValueKind::Map(b)) => a == b,
+            (
+                ValueKind::Range { start: s1, end: e1, step: t1 },
+                ValueKind::Range { start: s2, end: e2, step: t2 },
+            ) => s1 == s2 && e1 == e2 && t1 == t2,
+            (ValueKind::DateTime(a), ValueKind::DateTime(b)) => a == b,
+            (ValueKind::Duration(a), ValueKind::Duration(b)) => a == b,
+            #[cfg(feature = "decimal")]
+            (ValueKind::Decimal(a), ValueKind::Decimal(b)) => a == b,
+            #[cfg(feature = "tensor")]
+            (ValueKind::Tensor(a), ValueKind::Tensor(b)) => a == b,
             _ => false,
         }
     }
 }
 
+/// What a value carries besides its confidence and context, such as the
+/// model that produced it or its source URL.
+pub type Metadata = BTreeMap<String, Value>;
+
 #[derive(Debug, Clone)]
 pub struct Value {
     pub kind: ValueKind,
     pub confidence: f64,
     pub context: Option<String>,
+    /// Shared until a copy changes it, so values stay cheap to clone.
+    pub metadata: Option<Arc<Metadata>>,
 }
 
 impl Value {
@@ -86,6 +216,7 @@ impl Value {
             kind,
             confidence: 1.0,
             context: None,
+            metadata: None,
         }
     }
 
@@ -94,6 +225,7 @@ impl Value {
             kind,
             confidence,
             context: None,
+            metadata: None,
         }
     }
 
@@ -102,6 +234,7 @@ impl Value {
             kind,
             confidence: 1.0,
             context: Some(context),
+            metadata: None,
         }
     }
 
@@ -110,6 +243,7 @@ impl Value {
             kind,
             confidence,
             context: Some(context),
+            metadata: None,
         }
     }
 
@@ -128,6 +262,93 @@ impl Value {
     pub fn set_context(&mut self, context: String) {
         self.context = Some(context);
     }
+
+    /// The metadata entry `key`, if the value has one.
+    pub fn meta(&self, key: &str) -> Option<&Value> {
+        self.metadata.as_ref()?.get(key)
+    }
+
+    pub fn set_meta(&mut self, key: impl Into<String>, value: Value) {
+        Arc::make_mut(self.metadata.get_or_insert_with(Default::default)).insert(key.into(), value);
+    }
+
+    /// The value with the metadata entry `key` set to `value`.
+    pub fn with_meta(mut self, key: impl Into<String>, value: Value) -> Self {
+        self.set_meta(key, value);
+        self
+    }
+
+    /// The metadata as a JSON object, or `None` when there is none.
+    pub fn metadata_json(&self) -> Result<Option<serde_json::Value>> {
+        let Some(metadata) = self.metadata.as_ref().filter(|metadata| !metadata.is_empty()) else {
+            return Ok(None);
+        };
+        let fields = metadata
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), value.to_json()?)))
+            .collect::<Result<_>>()?;
+        Ok(Some(serde_json::Value::Object(fields)))
+    }
+
+    /// The value as JSON, without its confidence, context and metadata. Map
+    /// keys are rendered as strings; functions and modules have no JSON form.
+    pub fn to_json(&self) -> Result<serde_json::Value> {
+        Ok(match &self.kind {
+            ValueKind::Nil => serde_json::Value::Null,
+            ValueKind::Boolean(b) => serde_json::Value::Bool(*b),
+            // Whole numbers are written as integers, as most formats expect
+            ValueKind::Number(n) if n.fract() == 0.0 && n.abs() < MAX_SAFE_INTEGER => {
+                serde_json::Value::from(*n as i64)
+            }
+            ValueKind::Number(n) => serde_json::Number::from_f64(*n)
+                .map(serde_json::Value::Number)
+                .ok_or_else(|| PrismError::InvalidOperation(format!("{} has no JSON form", n)))?,
+            ValueKind::String(s) => serde_json::Value::String(s.clone()),
+            ValueKind::List(items) => serde_json::Value::Array(
+                items.iter().map(Value::to_json).collect::<Result<_>>()?,
+            ),
+            ValueKind::Map(entries) => serde_json::Value::Object(
+                entries
+                    .iter()
+                    .map(|(key, value)| Ok((key.to_string(), value.to_json()?)))
+                    .collect::<Result<_>>()?,
+            ),
+            ValueKind::Range { start, end, step } => serde_json::Value::Array(
+                (0..crate::iterator::range_len(*start, *end, *step))
+                    .map(|index| Value::new(ValueKind::Number(start + index as f64 * step)).to_json())
+                    .collect::<Result<_>>()?,
+            ),
+            ValueKind::DateTime(_) | ValueKind::Duration(_) => serde_json::Value::String(self.to_string()),
+            // A string, as a JSON number would be read back as a float
+            #[cfg(feature = "decimal")]
+            ValueKind::Decimal(d) => serde_json::Value::String(d.to_plain_string()),
+            #[cfg(feature = "tensor")]
+            ValueKind::Tensor(t) => crate::stdlib::tensor::to_list(t).to_json()?,
+            ValueKind::Function { .. }
+            | ValueKind::NativeFunction { .. }
+            | ValueKind::AsyncNativeFunction { .. }
+            | ValueKind::Module(_) => {
+                return Err(PrismError::InvalidOperation(format!("{} has no JSON form", self)))
+            }
+        })
+    }
+
+    /// A fully confident value from JSON; objects become maps with string keys.
+    pub fn from_json(json: &serde_json::Value) -> Value {
+        Value::new(match json {
+            serde_json::Value::Null => ValueKind::Nil,
+            serde_json::Value::Bool(b) => ValueKind::Boolean(*b),
+            serde_json::Value::Number(n) => ValueKind::Number(n.as_f64().unwrap_or(f64::NAN)),
+            serde_json::Value::String(s) => ValueKind::String(s.clone()),
+            serde_json::Value::Array(items) => ValueKind::List(items.iter().map(Value::from_json).collect()),
+            serde_json::Value::Object(fields) => ValueKind::Map(
+                fields
+                    .iter()
+                    .map(|(key, value)| (Value::new(ValueKind::String(key.clone())), Value::from_json(value)))
+                    .collect(),
+            ),
+        })
+    }
 }
 
 impl fmt::Display for Value {
@@ -135,10 +356,12 @@ impl fmt::Display for Value {
         match &self.kind {
             ValueKind::Nil => write!(f, "nil"),
             ValueKind::Boolean(b) => write!(f, "{}", b),
-            ValueKind::Number(n) => write!(f, "{}", n),
+            ValueKind::Number(n) => write!(f, "{}", NumberFormat::default().format(*n)),
             ValueKind::String(s) => write!(f, "{}", s),
             ValueKind::Function { name, .. } => write!(f, "<fn {}>", name),
-            ValueKind::NativeFunction { name, .. } => write!(f, "<native fn {}>", name),
+            ValueKind::NativeFunction { name, .. } | ValueKind::AsyncNativeFunction { name, .. } => {
+                write!(f, "<native fn {}>", name)
+            }
             ValueKind::Module(m) => {
                 let module = m.read();
                 write!(f, "<module {}>", module.name)
@@ -163,6 +386,14 @@ impl fmt::Display for Value {
                 }
                 write!(f, "}}")
             }
+            ValueKind::Range { start, end, step } if *step == 1.0 => write!(f, "range({}, {})", start, end),
+            ValueKind::Range { start, end, step } => write!(f, "range({}, {}, {})", start, end, step),
+            ValueKind::DateTime(at) => write!(f, "{}", crate::stdlib::time::format_datetime(at)),
+            ValueKind::Duration(span) => write!(f, "{}", crate::stdlib::time::format_duration(span)),
+            #[cfg(feature = "decimal")]
+            ValueKind::Decimal(d) => write!(f, "{}", d.to_plain_string()),
+            #[cfg(feature = "tensor")]
+            ValueKind::Tensor(t) => write!(f, "tensor({})", crate::stdlib::tensor::to_list(t)),
         }
     }
 }
@@ -177,4 +408,57 @@ impl PartialEq for Value {
         }
         self.kind == other.kind
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip() {
+        let json = serde_json::json!({ "name": "flu", "scores": [0.8, 1], "confirmed": null });
+        let value = Value::from_json(&json);
+        assert_eq!(value.to_string(), "{confirmed: nil, name: flu, scores: [0.8, 1]}");
+        assert_eq!(value.to_json().unwrap(), json);
+
+        let module = Value::new(ValueKind::Module(Arc::new(RwLock::new(Module::new("core".to_string())))));
+        assert!(module.to_json().is_err());
+    }
+
+    #[test]
+    fn test_metadata_is_copied_on_write() -> Result<()> {
+        let source = Value::new(ValueKind::String("https://example.org".to_string()));
+        let page = Value::new(ValueKind::String("text".to_string())).with_meta("source", source.clone());
+        let mut copy = page.clone();
+        copy.set_meta("model", Value::new(ValueKind::String("gpt-4o".to_string())));
+        assert_eq!(page.meta("source"), Some(&source));
+        assert_eq!(page.meta("model"), None);
+        assert_eq!(
+            copy.metadata_json()?,
+            Some(serde_json::json!({ "model": "gpt-4o", "source": "https://example.org" }))
+        );
+        assert_eq!(Value::new(ValueKind::Nil).metadata_json()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_numbers_display_without_float_artifacts() {
+        let display = |n: f64| Value::new(ValueKind::Number(n)).to_string();
+        assert_eq!(display(3.0), "3");
+        assert_eq!(display(0.1 + 0.2), "0.3");
+        assert_eq!(display(5.000000000000001), "5");
+        assert_eq!(display(-2.5), "-2.5");
+        assert_eq!(display(-0.0), "0");
+        assert_eq!(display(1e21), "1e21");
+        assert_eq!(display(0.00000001234), "1.234e-8");
+        assert_eq!(display(123456789.0), "123456789");
+
+        let short = NumberFormat { precision: 3, scientific: false };
+        assert_eq!(short.format(98.64), "98.6");
+        assert_eq!(short.format(1234.5), "1230");
+        assert_eq!(short.format(-0.0012345), "-0.00123");
+        let scientific = NumberFormat { precision: 3, scientific: true };
+        assert_eq!(scientific.format(1234.5), "1.23e3");
+        assert_eq!(scientific.format(f64::NAN), "NaN");
+    }
+}
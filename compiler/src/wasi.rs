@@ -0,0 +1,70 @@
+//! Running scripts under WASI, e.g. as plugins of a Wasmtime-based host.
+//!
+//! Build the `prism-wasi` command with
+//!
+//! ```text
+//! cargo build --release --target wasm32-wasip1 --no-default-features --features wasi --bin prism-wasi
+//! wasmtime run --dir . --env OPENAI_API_KEY target/wasm32-wasip1/release/prism-wasi.wasm triage.prism
+//! ```
+//!
+//! Files, environment variables and clocks go through WASI, so a script
+//! sees only the directories and variables the host passes in. There are
+//! no sockets: the `net` capability is not granted and `@cfg("wasi")`
+//! selects code for this target.
+
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+use crate::capabilities::{Capabilities, Capability};
+use crate::interpreter::Interpreter;
+
+/// An interpreter with what a WASI host can sandbox granted: files, the
+/// environment and stdin.
+pub fn interpreter() -> Interpreter {
+    Interpreter::new().with_capabilities(
+        Capabilities::none()
+            .grant(Capability::Fs)
+            .grant(Capability::Env)
+            .grant(Capability::Stdin),
+    )
+}
+
+/// Runs `future` to completion on the current thread. Builds without tokio
+/// wait by blocking, so futures are rarely pending for long; a pending one
+/// is polled again when woken or after a millisecond.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(std::thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        std::thread::park_timeout(Duration::from_millis(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::ValueKind;
+
+    #[test]
+    fn test_scripts_run_without_a_runtime() {
+        let mut interpreter = interpreter();
+        assert!(!interpreter.capabilities().allows(Capability::Net));
+        let source = "fn fib(n) { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); } fib(10);";
+        let result = block_on(interpreter.evaluate(source.to_string())).unwrap();
+        assert_eq!(result.kind, ValueKind::Number(55.0));
+    }
+}
@@ -1,82 +1,158 @@
-use wasm_bindgen::prelude::*;
-use serde::{Serialize, Deserialize};
-use crate::{Interpreter, Value, RuntimeError};
+//! Bindings for running Prism in the browser, as used by the playground in
+//! `examples/playground`.
+//!
+//! ```text
+//! wasm-pack build compiler --target web --no-default-features --features wasm
+//! ```
+//!
+//! Results cross into JavaScript as JSON text. Scripts run without
+//! capabilities, and whatever they print is returned with the result
+//! rather than written to the console.
 
-#[derive(Serialize, Deserialize)]
-struct PrismValue {
-    value: Value,
-    confidence: f64,
-    context: Option<String>,
-}
+use std::sync::Arc;
+use serde_json::{json, Value as Json};
+use wasm_bindgen::prelude::*;
+use crate::interpreter::Interpreter;
+use crate::output::CapturedOutput;
+use crate::value::{Value, ValueKind};
+use crate::wasi::block_on;
 
+/// An interpreter whose globals persist from one run to the next.
 #[wasm_bindgen]
 pub struct PrismRuntime {
     interpreter: Interpreter,
+    output: CapturedOutput,
 }
 
 #[wasm_bindgen]
 impl PrismRuntime {
     #[wasm_bindgen(constructor)]
-    pub fn new() -> Self {
-        console_error_panic_hook::set_once();
-        Self {
-            interpreter: Interpreter::new()
-        }
+    pub fn new() -> PrismRuntime {
+        let output = CapturedOutput::new();
+        let interpreter = Interpreter::new().with_output(Arc::new(output.clone()));
+        PrismRuntime { interpreter, output }
     }
 
-    #[wasm_bindgen]
-    pub async fn eval(&mut self, code: &str) -> Result<JsValue, JsError> {
-        let result = self.interpreter.eval_async(code).await
-            .map_err(|e| JsError::new(&e.to_string()))?;
-        
-        let prism_value = PrismValue {
-            confidence: result.get_confidence().unwrap_or(1.0),
-            context: result.get_context().map(|s| s.to_string()),
-            value: result,
+    /// Runs `source` and returns `{"ok", "value", "confidence", "context",
+    /// "items", "output", "diagnostics"}`, or `{"ok": false, "error",
+    /// "output", "diagnostics"}` when it fails. `items` are the entries of a
+    /// list or map result with their own confidences.
+    pub fn run(&mut self, source: &str) -> String {
+        let result = block_on(self.interpreter.evaluate(source.to_string()));
+        let output = self.output.take();
+        let diagnostics: Vec<String> = self.interpreter.diagnostics().iter().map(ToString::to_string).collect();
+        let json = match result {
+            Ok(value) => json!({
+                "ok": true,
+                "value": value.to_json().unwrap_or_else(|_| Json::String(value.to_string())),
+                "confidence": value.confidence,
+                "context": value.context,
+                "items": items(&value),
+                "output": output,
+                "diagnostics": diagnostics,
+            }),
+            Err(err) => json!({ "ok": false, "error": err.to_string(), "output": output, "diagnostics": diagnostics }),
         };
-
-        serde_wasm_bindgen::to_value(&prism_value)
-            .map_err(|e| JsError::new(&e.to_string()))
+        json.to_string()
     }
 
-    #[wasm_bindgen]
-    pub fn get_confidence(&self, value: &JsValue) -> Result<f64, JsError> {
-        let prism_value: PrismValue = serde_wasm_bindgen::from_value(value.clone())
-            .map_err(|e| JsError::new(&e.to_string()))?;
-        Ok(prism_value.confidence)
+    /// Forgets everything earlier runs defined.
+    pub fn reset(&mut self) {
+        *self = PrismRuntime::new();
     }
+}
 
-    #[wasm_bindgen]
-    pub fn get_context(&self, value: &JsValue) -> Result<Option<String>, JsError> {
-        let prism_value: PrismValue = serde_wasm_bindgen::from_value(value.clone())
-            .map_err(|e| JsError::new(&e.to_string()))?;
-        Ok(prism_value.context)
+impl Default for PrismRuntime {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-// Helper functions for TypeScript
+/// Classifies `source` for highlighting as `[[start, end, class], ...]`,
+/// with offsets in UTF-16 code units as JavaScript strings count them; see
+/// [`crate::highlight`].
 #[wasm_bindgen]
-pub fn create_value_with_confidence(value: JsValue, confidence: f64) -> Result<JsValue, JsError> {
-    let prism_value = PrismValue {
-        value: serde_wasm_bindgen::from_value(value)
-            .map_err(|e| JsError::new(&e.to_string()))?,
-        confidence,
-        context: None,
-    };
-
-    serde_wasm_bindgen::to_value(&prism_value)
-        .map_err(|e| JsError::new(&e.to_string()))
+pub fn highlight(source: &str) -> String {
+    let spans: Vec<Json> = crate::highlight::highlight(source)
+        .into_iter()
+        .map(|(span, class)| json!([utf16_offset(source, span.start), utf16_offset(source, span.end), class.name()]))
+        .collect();
+    Json::Array(spans).to_string()
 }
 
+/// Checks `source` without running it and returns the problems found as
+/// `[{"severity", "message", "line"}, ...]`.
 #[wasm_bindgen]
-pub fn create_value_in_context(value: JsValue, context: String) -> Result<JsValue, JsError> {
-    let prism_value = PrismValue {
-        value: serde_wasm_bindgen::from_value(value)
-            .map_err(|e| JsError::new(&e.to_string()))?,
-        confidence: 1.0,
-        context: Some(context),
+pub fn check(source: &str) -> String {
+    let diagnostics = match crate::analysis::parse_program(source) {
+        Ok(program) => crate::analysis::analyze(&program).diagnostics,
+        Err(diagnostics) => diagnostics,
+    };
+    let diagnostics: Vec<Json> = diagnostics
+        .iter()
+        .map(|d| json!({
+            "severity": if d.is_error() { "error" } else { "warning" },
+            "message": d.message,
+            "line": d.line,
+        }))
+        .collect();
+    Json::Array(diagnostics).to_string()
+}
+
+fn items(value: &Value) -> Json {
+    let item = |label: String, value: &Value| {
+        json!({ "label": label, "value": value.to_string(), "confidence": value.confidence })
     };
+    match &value.kind {
+        ValueKind::List(values) => values.iter().enumerate().map(|(i, v)| item(i.to_string(), v)).collect(),
+        ValueKind::Map(entries) => entries.iter().map(|(key, v)| item(key.to_string(), v)).collect(),
+        _ => Json::Array(Vec::new()),
+    }
+}
+
+fn utf16_offset(source: &str, byte: usize) -> usize {
+    source[..byte].encode_utf16().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs_report_results_and_output() {
+        let mut runtime = PrismRuntime::new();
+        let result: Json = serde_json::from_str(&runtime.run("print(\"hi\"); let xs = [1 ~> 0.5, 2]; xs;")).unwrap();
+        assert_eq!(result["ok"], true);
+        assert_eq!(result["value"], json!([1, 2]));
+        assert_eq!(result["output"], "hi\n");
+        assert_eq!(result["items"][0], json!({ "label": "0", "value": "1", "confidence": 0.5 }));
+
+        let result: Json = serde_json::from_str(&runtime.run("missing;")).unwrap();
+        assert_eq!(result["ok"], false);
+        runtime.reset();
+        let result: Json = serde_json::from_str(&runtime.run("xs;")).unwrap();
+        assert_eq!(result["ok"], false);
+    }
+
+    #[test]
+    fn test_highlight_offsets_count_utf16_units() {
+        let spans: Json = serde_json::from_str(&highlight("\"\u{1F48A}\" ~> 0.9")).unwrap();
+        assert_eq!(spans, json!([[0, 4, "string"], [5, 7, "confidence"], [8, 11, "number"]]));
+        let problems: Json = serde_json::from_str(&check("let x: number = \"a\";")).unwrap();
+        assert_eq!(problems[0]["severity"], "error");
+    }
 
-    serde_wasm_bindgen::to_value(&prism_value)
-        .map_err(|e| JsError::new(&e.to_string()))
-} 
\ No newline at end of file
+    #[test]
+    fn test_playground_samples_run() {
+        let samples = [
+            include_str!("../../examples/playground/samples/medical_diagnosis.prism"),
+            include_str!("../../examples/playground/samples/uncertain_if.prism"),
+            include_str!("../../examples/playground/samples/pattern_matching.prism"),
+            include_str!("../../examples/playground/samples/generators.prism"),
+        ];
+        for sample in samples {
+            let result: Json = serde_json::from_str(&PrismRuntime::new().run(sample)).unwrap();
+            assert_eq!(result["ok"], true, "{}", result);
+        }
+    }
+}
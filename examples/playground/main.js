@@ -0,0 +1,124 @@
+// The playground runs Prism in the page through the wasm bindings in
+// compiler/src/wasm.rs; see README.md for building them into ./pkg.
+import init, { PrismRuntime, highlight, check } from "./pkg/prism.js";
+
+const SAMPLES = [
+  { title: "Medical diagnosis", file: "samples/medical_diagnosis.prism" },
+  { title: "Uncertain if", file: "samples/uncertain_if.prism" },
+  { title: "Pattern matching", file: "samples/pattern_matching.prism" },
+  { title: "Generators and loops", file: "samples/generators.prism" },
+];
+
+const source = document.getElementById("source");
+const highlighted = document.getElementById("highlighted");
+const gallery = document.getElementById("gallery");
+const result = document.getElementById("result");
+const output = document.getElementById("output");
+const problems = document.getElementById("problems");
+
+let runtime;
+
+function escape(text) {
+  return text.replace(/[&<>]/g, (c) => ({ "&": "&amp;", "<": "&lt;", ">": "&gt;" })[c]);
+}
+
+// Offsets from `highlight` are UTF-16 code units, as string indices are.
+function render() {
+  const text = source.value;
+  let html = "";
+  let end = 0;
+  for (const [start, stop, kind] of JSON.parse(highlight(text))) {
+    html += escape(text.slice(end, start));
+    html += `<span class="tok-${kind}">${escape(text.slice(start, stop))}</span>`;
+    end = stop;
+  }
+  // A trailing newline needs a character after it to take up a line.
+  highlighted.innerHTML = html + escape(text.slice(end)) + "\n";
+}
+
+function showProblems(list) {
+  problems.replaceChildren(...list.map((problem) => {
+    const item = document.createElement("li");
+    item.className = problem.severity;
+    item.textContent = problem.line ? `line ${problem.line}: ${problem.message}` : problem.message;
+    return item;
+  }));
+}
+
+// Hue runs from red at confidence 0 to green at 1.
+function confidenceRow(label, confidence) {
+  const row = document.createElement("div");
+  row.className = "confidence";
+  row.innerHTML = `
+    <span class="label"></span>
+    <span class="bar"><span class="fill"></span></span>
+    <span class="percent">${Math.round(confidence * 100)}%</span>`;
+  row.querySelector(".label").textContent = label;
+  row.querySelector(".label").title = label;
+  const fill = row.querySelector(".fill");
+  fill.style.width = `${confidence * 100}%`;
+  fill.style.background = `hsl(${confidence * 120}, 70%, 55%)`;
+  return row;
+}
+
+function run() {
+  const outcome = JSON.parse(runtime.run(source.value));
+  output.textContent = outcome.output;
+  result.replaceChildren();
+  if (!outcome.ok) {
+    const error = document.createElement("div");
+    error.className = "error";
+    error.textContent = outcome.error;
+    result.append(error);
+    return;
+  }
+  const value = document.createElement("div");
+  value.className = "value";
+  value.textContent = JSON.stringify(outcome.value, null, 2);
+  result.append(value, confidenceRow(outcome.context ? `result in ${outcome.context}` : "result", outcome.confidence));
+  for (const item of outcome.items) {
+    result.append(confidenceRow(`${item.label}: ${item.value}`, item.confidence));
+  }
+}
+
+let pending;
+function edited() {
+  render();
+  clearTimeout(pending);
+  pending = setTimeout(() => showProblems(JSON.parse(check(source.value))), 300);
+}
+
+async function load(file) {
+  source.value = await (await fetch(file)).text();
+  edited();
+}
+
+await init();
+runtime = new PrismRuntime();
+
+for (const sample of SAMPLES) {
+  gallery.append(new Option(sample.title, sample.file));
+}
+gallery.addEventListener("change", () => load(gallery.value));
+source.addEventListener("input", edited);
+source.addEventListener("scroll", () => {
+  highlighted.scrollTop = source.scrollTop;
+});
+source.addEventListener("keydown", (event) => {
+  if (event.key === "Enter" && (event.ctrlKey || event.metaKey)) {
+    event.preventDefault();
+    run();
+  } else if (event.key === "Tab") {
+    event.preventDefault();
+    source.setRangeText("    ", source.selectionStart, source.selectionEnd, "end");
+    edited();
+  }
+});
+document.getElementById("run").addEventListener("click", run);
+document.getElementById("reset").addEventListener("click", () => {
+  runtime.reset();
+  output.textContent = "";
+  result.replaceChildren();
+});
+
+await load(SAMPLES[0].file);
@@ -0,0 +1,87 @@
+//! Node.js native addon for the Prism runtime, built with napi-rs.
+//!
+//! It exposes the same `PrismRuntime` surface as the wasm build, for
+//! server-side hosts that want native speed and networking:
+//!
+//! ```js
+//! const { PrismRuntime } = require("@prism-lang/node");
+//!
+//! const runtime = new PrismRuntime(["net"]);
+//! const result = await runtime.eval('let score = 0.8 ~> 0.9; score;');
+//! // { value: 0.8, confidence: 0.9, context: null }
+//! ```
+//!
+//! Values cross into JavaScript in their JSON form, wrapped with their
+//! confidence and context.
+
+use std::sync::Arc;
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use prism::capabilities::Capability;
+use prism::interpreter::Interpreter;
+use prism::value::{Value, ValueKind};
+use serde_json::json;
+use tokio::sync::Mutex;
+
+#[napi]
+pub struct PrismRuntime {
+    interpreter: Arc<Mutex<Interpreter>>,
+}
+
+#[napi]
+impl PrismRuntime {
+    /// `capabilities` names what scripts may use: "env", "stdin", "fs" or
+    /// "net". Nothing is granted by default.
+    #[napi(constructor)]
+    pub fn new(capabilities: Option<Vec<String>>) -> Result<Self> {
+        let mut granted = Interpreter::new().capabilities().clone();
+        for name in capabilities.unwrap_or_default() {
+            let capability = name
+                .parse::<Capability>()
+                .map_err(|_| Error::new(Status::InvalidArg, format!("Unknown capability '{}'", name)))?;
+            granted = granted.grant(capability);
+        }
+        let interpreter = Interpreter::new().with_capabilities(granted);
+        Ok(Self { interpreter: Arc::new(Mutex::new(interpreter)) })
+    }
+
+    /// Evaluates `code` and resolves to the value of its last statement.
+    #[napi]
+    pub async fn eval(&self, code: String) -> Result<serde_json::Value> {
+        let result = self.interpreter.lock().await.evaluate(code).await;
+        let value = result.map_err(|err| Error::from_reason(err.to_string()))?;
+        wrap(&value)
+    }
+
+    #[napi]
+    pub fn get_confidence(&self, value: serde_json::Value) -> f64 {
+        value.get("confidence").and_then(serde_json::Value::as_f64).unwrap_or(1.0)
+    }
+
+    #[napi]
+    pub fn get_context(&self, value: serde_json::Value) -> Option<String> {
+        value.get("context").and_then(serde_json::Value::as_str).map(str::to_string)
+    }
+}
+
+#[napi]
+pub fn create_value_with_confidence(value: serde_json::Value, confidence: f64) -> Result<serde_json::Value> {
+    wrap(&Value::with_confidence(Value::from_json(&value).kind, confidence))
+}
+
+#[napi]
+pub fn create_value_in_context(value: serde_json::Value, context: String) -> Result<serde_json::Value> {
+    wrap(&Value::with_confidence_and_context(Value::from_json(&value).kind, 1.0, context))
+}
+
+fn wrap(value: &Value) -> Result<serde_json::Value> {
+    let json = match &value.kind {
+        // Definitions evaluate to the function, which stays in the runtime
+        ValueKind::Function { .. }
+        | ValueKind::NativeFunction { .. }
+        | ValueKind::AsyncNativeFunction { .. }
+        | ValueKind::Module(_) => serde_json::Value::Null,
+        _ => value.to_json().map_err(|err| Error::from_reason(err.to_string()))?,
+    };
+    Ok(json!({ "value": json, "confidence": value.confidence, "context": value.context }))
+}
@@ -0,0 +1,30 @@
+// Run after `npm run build`.
+import assert from "node:assert/strict";
+import { createRequire } from "node:module";
+import test from "node:test";
+
+const require = createRequire(import.meta.url);
+const { PrismRuntime, createValueWithConfidence, createValueInContext } = require(
+  process.env.PRISM_ADDON ?? "../prism.node",
+);
+
+test("eval resolves to the value, confidence and context", async () => {
+  const runtime = new PrismRuntime();
+  await runtime.eval("fn label(x) { return { x: x, tags: [1, 2.5, nil] }; }");
+  const result = await runtime.eval('let result = label("a") ~> 0.9; result;');
+  assert.deepEqual(result, { value: { x: "a", tags: [1, 2.5, null] }, confidence: 0.9, context: null });
+  assert.equal(runtime.getConfidence(result), 0.9);
+  assert.equal(runtime.getContext(result), null);
+});
+
+test("errors reject and capabilities must be granted", async () => {
+  await assert.rejects(new PrismRuntime().eval("missing;"));
+  await assert.rejects(new PrismRuntime().eval('env.get("HOME");'));
+  await new PrismRuntime(["env"]).eval('env.get("HOME");');
+  assert.throws(() => new PrismRuntime(["everything"]));
+});
+
+test("values can be created with confidence or context", () => {
+  assert.deepEqual(createValueWithConfidence([1, 2], 0.5), { value: [1, 2], confidence: 0.5, context: null });
+  assert.equal(createValueInContext("x", "triage").context, "triage");
+});
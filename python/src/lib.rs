@@ -0,0 +1,222 @@
+//! Python bindings for the Prism runtime, built as the `prism` module with
+//! maturin:
+//!
+//! ```python
+//! import prism
+//!
+//! interpreter = prism.PrismInterpreter()
+//! interpreter.eval('fn triage(score) { return score; }')
+//! result = interpreter.call("triage", prism.Value(21, confidence=0.8))
+//! result.value, result.confidence  # (21, 0.8)
+//! result = await interpreter.eval_async('triage(1);')
+//! ```
+//!
+//! `eval` and `call` return a [`Value`](PyValue) holding the result as a
+//! Python object and its confidence. Nested values convert to plain Python
+//! objects, except uncertain ones, which stay `Value`s. Arguments may be
+//! plain objects, which are certain, or `Value`s. Functions and modules
+//! convert to `None`.
+
+use std::sync::Arc;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyTypeError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple};
+use prism::capabilities::Capability;
+use prism::interpreter::Interpreter;
+use prism::value::{Value, ValueKind};
+use tokio::sync::Mutex;
+
+create_exception!(prism, PrismError, PyException, "An error raised by a Prism program.");
+
+/// A Prism value and its confidence.
+#[pyclass(name = "Value", module = "prism", frozen)]
+pub struct PyValue {
+    #[pyo3(get)]
+    value: PyObject,
+    #[pyo3(get)]
+    confidence: f64,
+}
+
+#[pymethods]
+impl PyValue {
+    #[new]
+    #[pyo3(signature = (value, confidence = 1.0))]
+    fn new(value: PyObject, confidence: f64) -> Self {
+        Self { value, confidence }
+    }
+
+    fn __repr__(&self, py: Python) -> PyResult<String> {
+        Ok(format!("Value({}, confidence={})", self.value.bind(py).repr()?, self.confidence))
+    }
+}
+
+/// An interpreter with the standard library and prelude. Evaluations share
+/// its global environment and run one at a time.
+#[pyclass(name = "PrismInterpreter", module = "prism")]
+pub struct PyInterpreter {
+    interpreter: Arc<Mutex<Interpreter>>,
+}
+
+#[pymethods]
+impl PyInterpreter {
+    /// `capabilities` names what scripts may use: "env", "stdin", "fs" or
+    /// "net". Nothing is granted by default.
+    #[new]
+    #[pyo3(signature = (capabilities = Vec::new()))]
+    fn new(capabilities: Vec<String>) -> PyResult<Self> {
+        let mut granted = Interpreter::new().capabilities().clone();
+        for name in capabilities {
+            let capability = name
+                .parse::<Capability>()
+                .map_err(|_| PyTypeError::new_err(format!("Unknown capability '{}'", name)))?;
+            granted = granted.grant(capability);
+        }
+        let interpreter = Interpreter::new().with_capabilities(granted);
+        Ok(Self { interpreter: Arc::new(Mutex::new(interpreter)) })
+    }
+
+    /// Evaluates `source` and returns the value of its last statement.
+    fn eval(&self, py: Python, source: String) -> PyResult<PyValue> {
+        let interpreter = self.interpreter.clone();
+        let value = py.allow_threads(|| {
+            runtime().block_on(async move { interpreter.lock().await.evaluate(source).await })
+        });
+        to_result(py, value)
+    }
+
+    /// Calls the global function `name` with `args`.
+    #[pyo3(signature = (name, *args))]
+    fn call(&self, py: Python, name: String, args: &Bound<PyTuple>) -> PyResult<PyValue> {
+        let args = args.iter().map(|arg| from_python(&arg)).collect::<PyResult<Vec<_>>>()?;
+        let interpreter = self.interpreter.clone();
+        let value = py.allow_threads(|| runtime().block_on(call(interpreter, name, args)));
+        to_result(py, value)
+    }
+
+    /// `eval` as an awaitable for asyncio.
+    fn eval_async<'py>(&self, py: Python<'py>, source: String) -> PyResult<Bound<'py, PyAny>> {
+        let interpreter = self.interpreter.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let value = interpreter.lock().await.evaluate(source).await;
+            Python::with_gil(|py| to_result(py, value))
+        })
+    }
+
+    /// `call` as an awaitable for asyncio.
+    #[pyo3(signature = (name, *args))]
+    fn call_async<'py>(&self, py: Python<'py>, name: String, args: &Bound<PyTuple>) -> PyResult<Bound<'py, PyAny>> {
+        let args = args.iter().map(|arg| from_python(&arg)).collect::<PyResult<Vec<_>>>()?;
+        let interpreter = self.interpreter.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let value = call(interpreter, name, args).await;
+            Python::with_gil(|py| to_result(py, value))
+        })
+    }
+}
+
+fn runtime() -> &'static tokio::runtime::Runtime {
+    pyo3_async_runtimes::tokio::get_runtime()
+}
+
+async fn call(interpreter: Arc<Mutex<Interpreter>>, name: String, args: Vec<Value>) -> prism::error::Result<Value> {
+    let mut interpreter = interpreter.lock().await;
+    let callee = interpreter
+        .global_values()
+        .into_iter()
+        .find(|(global, _)| *global == name)
+        .map(|(_, value)| value)
+        .ok_or(prism::error::PrismError::UndefinedVariable(name))?;
+    interpreter.call(callee, args).await
+}
+
+fn to_result(py: Python, value: prism::error::Result<Value>) -> PyResult<PyValue> {
+    let value = value.map_err(|err| PrismError::new_err(err.to_string()))?;
+    Ok(PyValue { value: plain(py, &value)?, confidence: value.confidence })
+}
+
+/// `value` as a Python object, or a `Value` if it is uncertain.
+fn to_python(py: Python, value: &Value) -> PyResult<PyObject> {
+    let object = plain(py, value)?;
+    if value.confidence < 1.0 {
+        return Ok(Py::new(py, PyValue { value: object, confidence: value.confidence })?.into_any());
+    }
+    Ok(object)
+}
+
+fn plain(py: Python, value: &Value) -> PyResult<PyObject> {
+    Ok(match &value.kind {
+        // Functions and modules are called by name through `call`
+        ValueKind::Nil
+        | ValueKind::Function { .. }
+        | ValueKind::NativeFunction { .. }
+        | ValueKind::AsyncNativeFunction { .. }
+        | ValueKind::Module(_) => py.None(),
+        ValueKind::Boolean(b) => b.into_pyobject(py)?.to_owned().into_any().unbind(),
+        // Whole numbers become ints, as Python code expects
+        ValueKind::Number(n) if n.fract() == 0.0 && n.abs() < 9_007_199_254_740_992.0 => {
+            (*n as i64).into_pyobject(py)?.into_any().unbind()
+        }
+        ValueKind::Number(n) => n.into_pyobject(py)?.into_any().unbind(),
+        ValueKind::String(s) => s.into_pyobject(py)?.into_any().unbind(),
+        // RFC 3339 and `1h 30m` text, as in JSON
+        ValueKind::DateTime(_) | ValueKind::Duration(_) => value.to_string().into_pyobject(py)?.into_any().unbind(),
+        ValueKind::List(items) => {
+            let items = items.iter().map(|item| to_python(py, item)).collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, items)?.into_any().unbind()
+        }
+        ValueKind::Map(entries) => {
+            let dict = PyDict::new(py);
+            for (key, value) in entries {
+                dict.set_item(plain(py, key)?, to_python(py, value)?)?;
+            }
+            dict.into_any().unbind()
+        }
+        ValueKind::Range { start, end, step } => {
+            let items = (0..prism::iterator::range_len(*start, *end, *step))
+                .map(|index| plain(py, &Value::new(ValueKind::Number(start + index as f64 * step))))
+                .collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, items)?.into_any().unbind()
+        }
+    })
+}
+
+fn from_python(object: &Bound<PyAny>) -> PyResult<Value> {
+    if let Ok(value) = object.downcast::<PyValue>() {
+        let value = value.get();
+        let inner = from_python(value.value.bind(object.py()))?;
+        return Ok(Value::with_confidence(inner.kind, value.confidence));
+    }
+    let kind = if object.is_none() {
+        ValueKind::Nil
+    } else if let Ok(b) = object.downcast::<PyBool>() {
+        ValueKind::Boolean(b.is_true())
+    } else if object.is_instance_of::<PyInt>() || object.is_instance_of::<PyFloat>() {
+        ValueKind::Number(object.extract()?)
+    } else if let Ok(s) = object.downcast::<PyString>() {
+        ValueKind::String(s.to_str()?.to_string())
+    } else if let Ok(dict) = object.downcast::<PyDict>() {
+        let entries = dict
+            .iter()
+            .map(|(key, value)| Ok((from_python(&key)?, from_python(&value)?)))
+            .collect::<PyResult<_>>()?;
+        ValueKind::Map(entries)
+    } else if object.is_instance_of::<PyList>() || object.is_instance_of::<PyTuple>() {
+        ValueKind::List(object.try_iter()?.map(|item| from_python(&item?)).collect::<PyResult<_>>()?)
+    } else {
+        return Err(PyTypeError::new_err(format!(
+            "{} has no Prism form",
+            object.get_type().name()?
+        )));
+    };
+    Ok(Value::new(kind))
+}
+
+#[pymodule]
+#[pyo3(name = "prism")]
+fn prism_python(module: &Bound<PyModule>) -> PyResult<()> {
+    module.add_class::<PyInterpreter>()?;
+    module.add_class::<PyValue>()?;
+    module.add("PrismError", module.py().get_type::<PrismError>())?;
+    Ok(())
+}
@@ -0,0 +1,45 @@
+"""Tests for the Python bindings; run after `maturin develop`."""
+
+import asyncio
+import unittest
+
+import prism
+
+
+class PrismInterpreterTest(unittest.TestCase):
+    def test_eval_converts_values(self):
+        interpreter = prism.PrismInterpreter()
+        result = interpreter.eval('let result = { name: "triage", scores: [1, 2.5, nil] } ~> 0.9; result;')
+        self.assertEqual(result.value, {"name": "triage", "scores": [1, 2.5, None]})
+        self.assertEqual(result.confidence, 0.9)
+
+    def test_call_passes_confidence(self):
+        interpreter = prism.PrismInterpreter()
+        interpreter.eval('fn label(x) { return [x, "checked"]; }')
+        result = interpreter.call("label", prism.Value(21, confidence=0.8))
+        self.assertEqual(result.confidence, 1.0)
+        self.assertEqual(result.value[1], "checked")
+        self.assertEqual((result.value[0].value, result.value[0].confidence), (21, 0.8))
+
+    def test_errors_raise(self):
+        interpreter = prism.PrismInterpreter()
+        with self.assertRaises(prism.PrismError):
+            interpreter.call("missing")
+        with self.assertRaises(prism.PrismError):
+            interpreter.eval('env.get("HOME");')
+        granted = prism.PrismInterpreter(capabilities=["env"])
+        granted.eval('env.get("HOME");')
+
+    def test_async(self):
+        interpreter = prism.PrismInterpreter()
+        interpreter.eval("fn inc(x) { return x + 1; }")
+
+        async def run():
+            return await asyncio.gather(interpreter.eval_async("inc(1);"), interpreter.call_async("inc", 2))
+
+        first, second = asyncio.run(run())
+        self.assertEqual((first.value, second.value), (2, 3))
+
+
+if __name__ == "__main__":
+    unittest.main()